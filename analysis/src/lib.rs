@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::fmt;
+use ast::visit::{self, Visitor};
+use ast::{Expression, Identifier, Program, Statement};
+use optimizer::Pass;
+use token::{Span, Symbol, TokenType};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Level {
+    // Fails when run
+    Error,
+    // Runs, but probably not as intended
+    Warning,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Error => write!(f, "error"),
+            Level::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Finding {
+    pub lint: &'static str,
+    pub level: Level,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} at {} [{}]", self.level, self.message, self.span, self.lint)
+    }
+}
+
+// Checks a program without running it. Findings come in source order.
+pub fn analyze(program: &Program) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    let mut resolver = Resolver { globals: globals(program), scopes: vec![HashMap::new()], findings: &mut findings };
+    resolver.visit_program(program);
+    Conditions { findings: &mut findings }.visit_program(program);
+
+    // The optimizer already knows what can never run
+    let mut copy = Program { statements: program.statements.clone() };
+    let mut warnings = Vec::new();
+    optimizer::UnreachableCode.run(&mut copy, &mut warnings);
+    findings.extend(warnings.into_iter().map(|warning| Finding {
+        lint: warning.lint,
+        level: Level::Warning,
+        message: warning.message,
+        span: warning.span,
+    }));
+
+    findings.sort_by_key(|finding| (finding.span.line, finding.span.column));
+    findings
+}
+
+fn is_builtin(name: Symbol) -> bool {
+    eval::builtins::BUILTINS.iter().any(|(builtin, _)| *builtin == name.as_str())
+}
+
+// Every binding made outside of functions, which functions may use
+// whenever they are called, even if defined further down
+fn globals(program: &Program) -> HashMap<Symbol, Span> {
+    let mut declarations = Declarations::default();
+    for statement in &program.statements {
+        declarations.visit_statement(statement);
+    }
+    declarations.0.into_iter().map(|(ident, _)| (ident.value, ident.token.span())).collect()
+}
+
+// The names a function body binds, not counting those of functions inside
+// it, and whether each is a loop variable
+#[derive(Default)]
+struct Declarations(Vec<(Identifier, bool)>);
+
+impl Visitor for Declarations {
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::LetStatement(ls) => self.0.push((ls.name.clone(), false)),
+            Statement::ForStatement(fs) => {
+                if let Statement::LetStatement(ls) = &*fs.init {
+                    self.0.push((ls.name.clone(), true));
+                }
+                self.visit_block_statement(&fs.body);
+                return;
+            },
+            Statement::ForInStatement(fs) => self.0.push((fs.variable.clone(), true)),
+            Statement::ImportStatement(is) => self.0.push((is.name.clone(), false)),
+            Statement::StructStatement(ss) => self.0.push((ss.name.clone(), false)),
+            _ => {},
+        }
+        visit::walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, _expression: &Expression) {}
+}
+
+// Finds names that are used but never bound, and bindings that hide
+// another of the same name. There is one scope per function body, as loops
+// share the scope they are in. The top level is read in order, since its
+// statements run as they are reached, but a function may use any of its own
+// bindings before the let, as a loop might run it again.
+struct Resolver<'a> {
+    globals: HashMap<Symbol, Span>,
+    scopes: Vec<HashMap<Symbol, Span>>,
+    findings: &'a mut Vec<Finding>,
+}
+
+impl Resolver<'_> {
+    fn in_function(&self) -> bool {
+        self.scopes.len() > 1
+    }
+
+    fn lookup(&self, name: Symbol) -> Option<Span> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(&name).copied())
+            .or_else(|| if self.in_function() { self.globals.get(&name).copied() } else { None })
+    }
+
+    fn declare(&mut self, ident: &Identifier, loop_variable: bool) {
+        let span = ident.token.span();
+        let scope = self.scopes.last().unwrap();
+        // Loops at the top level commonly reuse their variable's name, and
+        // modules export names like reverse on purpose
+        let message = match scope.get(&ident.value) {
+            Some(_) if loop_variable => None,
+            Some(_) => Some(format!("{} is already defined", ident.value)),
+            None => match self.lookup(ident.value) {
+                Some(_) => Some(format!("{} shadows an outer binding", ident.value)),
+                None if self.in_function() && is_builtin(ident.value) => Some(format!("{} shadows a builtin", ident.value)),
+                None => None,
+            },
+        };
+        if let Some(message) = message {
+            self.findings.push(Finding { lint: "shadowing", level: Level::Warning, message, span });
+        }
+        self.scopes.last_mut().unwrap().insert(ident.value, span);
+    }
+
+    fn check_defined(&mut self, ident: &Identifier) {
+        if self.lookup(ident.value).is_none() && !is_builtin(ident.value) {
+            self.findings.push(Finding {
+                lint: "undefined_identifier",
+                level: Level::Error,
+                message: format!("identifier not found: {}", ident.value),
+                span: ident.token.span(),
+            });
+        }
+    }
+}
+
+impl Visitor for Resolver<'_> {
+    fn visit_statement(&mut self, statement: &Statement) {
+        // Function bodies declare everything up front
+        if self.in_function() {
+            return visit::walk_statement(self, statement);
+        }
+        match statement {
+            // A function may call itself, so its name is bound before the body
+            Statement::LetStatement(ls) if matches!(ls.value, Expression::FunctionLiteral(_)) => {
+                self.declare(&ls.name, false);
+                self.visit_expression(&ls.value);
+            },
+            Statement::LetStatement(ls) => {
+                self.visit_expression(&ls.value);
+                self.declare(&ls.name, false);
+            },
+            Statement::ForStatement(fs) => {
+                match &*fs.init {
+                    Statement::LetStatement(ls) => {
+                        self.visit_expression(&ls.value);
+                        self.declare(&ls.name, true);
+                    },
+                    init => self.visit_statement(init),
+                }
+                self.visit_expression(&fs.condition);
+                self.visit_expression(&fs.update);
+                self.visit_block_statement(&fs.body);
+            },
+            Statement::ForInStatement(fs) => {
+                self.visit_expression(&fs.iterable);
+                self.declare(&fs.variable, true);
+                self.visit_block_statement(&fs.body);
+            },
+            Statement::ImportStatement(is) => self.declare(&is.name, false),
+            Statement::StructStatement(ss) => self.declare(&ss.name, false),
+            _ => visit::walk_statement(self, statement),
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Identifier(ident) => self.check_defined(ident),
+            Expression::StructLiteral(literal) => {
+                self.check_defined(&literal.name);
+                for (_, value) in &literal.fields {
+                    self.visit_expression(value);
+                }
+            },
+            Expression::FunctionLiteral(func) => {
+                self.scopes.push(HashMap::new());
+                for parameter in &func.parameters {
+                    self.scopes.last_mut().unwrap().insert(parameter.value, parameter.token.span());
+                }
+                let mut declarations = Declarations::default();
+                declarations.visit_block_statement(&func.body);
+                for (ident, loop_variable) in &declarations.0 {
+                    self.declare(ident, *loop_variable);
+                }
+                self.visit_block_statement(&func.body);
+                self.scopes.pop();
+            },
+            // Like a loop variable, the parameter is bound in the scope the
+            // try is in
+            Expression::Try(t) => {
+                self.visit_block_statement(&t.body);
+                self.declare(&t.parameter, true);
+                self.visit_block_statement(&t.handler);
+            },
+            _ => visit::walk_expression(self, expression),
+        }
+    }
+}
+
+// Conditions that are literals, or assignments where a comparison was
+// probably meant
+struct Conditions<'a> {
+    findings: &'a mut Vec<Finding>,
+}
+
+impl Conditions<'_> {
+    fn check(&mut self, condition: &Expression) {
+        let truthy = match condition {
+            Expression::BooleanLiteral(token) => Some(token.ttype == TokenType::True),
+            Expression::NullLiteral(_) => Some(false),
+            Expression::IntegerLiteral(_)
+            | Expression::FloatLiteral(_)
+            | Expression::BigIntegerLiteral(_)
+            | Expression::StringLiteral(_)
+            | Expression::InterpolatedString(_)
+            | Expression::CharLiteral(_)
+            | Expression::ArrayLiteral(_)
+            | Expression::RangeLiteral(_)
+            | Expression::HashLiteral(_)
+            | Expression::StructLiteral(_)
+            | Expression::FunctionLiteral(_) => Some(true),
+            _ => None,
+        };
+        if let Some(truthy) = truthy {
+            self.findings.push(Finding {
+                lint: "constant_condition",
+                level: Level::Warning,
+                message: format!("condition is always {}", truthy),
+                span: condition.span(),
+            });
+        }
+
+        if let Expression::Assignment(assignment) = condition {
+            if assignment.token.ttype == TokenType::Assign {
+                self.findings.push(Finding {
+                    lint: "assignment_in_condition",
+                    level: Level::Warning,
+                    message: "assignment used as a condition, did you mean ==?".to_string(),
+                    span: condition.span(),
+                });
+            }
+        }
+    }
+}
+
+impl Visitor for Conditions<'_> {
+    fn visit_statement(&mut self, statement: &Statement) {
+        // An endless `for (;;)` loop is spelled with a true condition, so
+        // only assignments are suspicious there
+        if let Statement::ForStatement(fs) = statement {
+            if matches!(fs.condition, Expression::Assignment(_)) {
+                self.check(&fs.condition);
+            }
+        }
+        visit::walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let Expression::Ternary(ternary) = expression {
+            self.check(&ternary.condition);
+        }
+        visit::walk_expression(self, expression);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lexer::Lexer;
+    use parser::Parser;
+    use super::*;
+
+    fn findings(input: &str) -> Vec<String> {
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        analyze(&program).iter().map(|finding| finding.to_string()).collect()
+    }
+
+    #[test]
+    fn test_undefined_identifiers() {
+        let tests = vec![
+            ("let x = 1; x + len([])", vec![]),
+            ("y", vec!["error: identifier not found: y at line 1, col 1 [undefined_identifier]"]),
+            ("puts(x); let x = 1;", vec!["error: identifier not found: x at line 1, col 6 [undefined_identifier]"]),
+            ("x = 1;", vec!["error: identifier not found: x at line 1, col 1 [undefined_identifier]"]),
+            // Functions see globals bound after them, and themselves
+            ("let f = fn() { g() }; let g = fn() { f() };", vec![]),
+            ("let f = fn(n) { n == 0 ? 0 : f(n - 1) };", vec![]),
+            ("let f = fn(a) { fn(b) { a + b + c } };", vec!["error: identifier not found: c at line 1, col 33 [undefined_identifier]"]),
+            ("for (i in [1]) { let latest = i; } latest", vec![]),
+            ("for (let i = 0; i < 3; i++) { puts(i); }", vec![]),
+            ("let f = fn() { P { x: 1 } }; struct P { x }", vec![]),
+            ("Q { x: y }", vec![
+                "error: identifier not found: Q at line 1, col 1 [undefined_identifier]",
+                "error: identifier not found: y at line 1, col 8 [undefined_identifier]",
+            ]),
+            ("import \"lib/io\"; io.puts(1)", vec![]),
+            ("let f = fn() { for (i in [1, 2]) { puts(x); let x = i; } };", vec![]),
+            ("try { [][1] } catch (e) { puts(e) } try { 1 } catch (e) { e }", vec![]),
+            ("try { e } catch (e) { 0 }", vec!["error: identifier not found: e at line 1, col 7 [undefined_identifier]"]),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(findings(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_shadowing() {
+        let tests = vec![
+            ("let x = 1; let x = 2;", vec!["warning: x is already defined at line 1, col 16 [shadowing]"]),
+            ("let x = 1; let f = fn() { let x = 2; x };", vec!["warning: x shadows an outer binding at line 1, col 31 [shadowing]"]),
+            ("let f = fn(x) { let x = 2; x };", vec!["warning: x is already defined at line 1, col 21 [shadowing]"]),
+            ("let f = fn() { let len = 1; len };", vec!["warning: len shadows a builtin at line 1, col 20 [shadowing]"]),
+            ("let len = 1;", vec![]),
+            // Parameters and reused loop variables are not reported
+            ("let x = 1; let f = fn(x) { x };", vec![]),
+            ("for (i in [1]) {} for (i in [2]) {}", vec![]),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(findings(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_conditions() {
+        let tests = vec![
+            ("let x = 1; true ? x : 2", vec!["warning: condition is always true at line 1, col 12 [constant_condition]"]),
+            ("null ? 1 : 2", vec!["warning: condition is always false at line 1, col 1 [constant_condition]"]),
+            ("\"s\" ? 1 : 2", vec!["warning: condition is always true at line 1, col 1 [constant_condition]"]),
+            ("let x = 1; x ? 1 : 2", vec![]),
+            ("let x = 1; (x = 2) ? 1 : 2", vec!["warning: assignment used as a condition, did you mean ==? at line 1, col 15 [assignment_in_condition]"]),
+            ("let x = 1; (x += 2) ? 1 : 2", vec![]),
+            ("for (let i = 0; i = 3; i++) {}", vec!["warning: assignment used as a condition, did you mean ==? at line 1, col 19 [assignment_in_condition]"]),
+            ("for (let i = 0; true; i++) { return i; }", vec![]),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(findings(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_analyze() {
+        let input = "let f = fn() {\n  return y;\n  let len = 1;\n};";
+        assert_eq!(findings(input), vec![
+            "error: identifier not found: y at line 2, col 10 [undefined_identifier]",
+            "warning: unreachable statement at line 3, col 3 [unreachable_code]",
+            "warning: len shadows a builtin at line 3, col 7 [shadowing]",
+        ]);
+    }
+}
@@ -0,0 +1,179 @@
+use std::fmt::Write;
+use crate::visit::{self, Visitor};
+use crate::{BlockStatement, Expression, Identifier, Program, Statement};
+
+impl Program {
+    // The tree as a Graphviz graph, one box per node labelled with its
+    // operator, literal or kind, and an edge to each child in order
+    pub fn to_dot(&self) -> String {
+        let mut graph = Graph { out: String::from("digraph ast {\n    node [shape=box];\n"), parents: Vec::new(), next: 0 };
+        graph.visit_program(self);
+        graph.out.push_str("}\n");
+        graph.out
+    }
+}
+
+struct Graph {
+    out: String,
+    // Nodes whose children are being visited, innermost last
+    parents: Vec<usize>,
+    next: usize,
+}
+
+impl Graph {
+    fn node(&mut self, label: &str) -> usize {
+        let id = self.next;
+        self.next += 1;
+        let label = label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        writeln!(self.out, "    n{} [label=\"{}\"];", id, label).unwrap();
+        if let Some(parent) = self.parents.last() {
+            writeln!(self.out, "    n{} -> n{};", parent, id).unwrap();
+        }
+        id
+    }
+
+    // Adds a node and visits whatever walk visits beneath it
+    fn within(&mut self, label: &str, walk: impl FnOnce(&mut Graph)) {
+        let id = self.node(label);
+        self.parents.push(id);
+        walk(self);
+        self.parents.pop();
+    }
+}
+
+impl Visitor for Graph {
+    fn visit_program(&mut self, program: &Program) {
+        self.within("program", |graph| visit::walk_program(graph, program));
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        let label = match statement {
+            // The expression stands for its statement
+            Statement::ExpressionStatement(_) | Statement::BlockStatement(_) => return visit::walk_statement(self, statement),
+            Statement::LetStatement(_) => "let".to_string(),
+            Statement::ReturnStatement(_) => "return".to_string(),
+            Statement::ThrowStatement(_) => "throw".to_string(),
+            Statement::ForStatement(_) => "for".to_string(),
+            Statement::ForInStatement(_) => "for in".to_string(),
+            Statement::ImportStatement(is) => format!("import {}", is.path.literal),
+            Statement::StructStatement(ss) => {
+                let fields: Vec<String> = ss.fields.iter().map(|field| field.value.to_string()).collect();
+                format!("struct {{ {} }}", fields.join(", "))
+            },
+        };
+        self.within(&label, |graph| visit::walk_statement(graph, statement));
+    }
+
+    fn visit_block_statement(&mut self, block: &BlockStatement) {
+        self.within("block", |graph| visit::walk_block_statement(graph, block));
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        let label = match expression {
+            // Visited as identifiers
+            Expression::Identifier(_) => return visit::walk_expression(self, expression),
+            Expression::Block(_) => return visit::walk_expression(self, expression),
+            Expression::IntegerLiteral(il) => il.value.to_string(),
+            Expression::FloatLiteral(fl) => fl.token.literal.clone(),
+            Expression::CharLiteral(c) => c.token.literal.clone(),
+            Expression::BigIntegerLiteral(token)
+            | Expression::StringLiteral(token)
+            | Expression::BooleanLiteral(token)
+            | Expression::NullLiteral(token) => token.literal.clone(),
+            Expression::InterpolatedString(_) => "interpolate".to_string(),
+            Expression::Prefix(prefix) => prefix.operator.literal.clone(),
+            Expression::Infix(infix) => infix.operator.literal.clone(),
+            Expression::Postfix(postfix) => postfix.operator.literal.clone(),
+            Expression::Propagate(_) => "?".to_string(),
+            Expression::Ternary(_) => "? :".to_string(),
+            Expression::Assignment(assignment) => assignment.token.literal.clone(),
+            Expression::ArrayLiteral(_) => "[ ]".to_string(),
+            Expression::RangeLiteral(range) => range.token.literal.clone(),
+            Expression::HashLiteral(_) => "{ }".to_string(),
+            Expression::FunctionLiteral(_) => "fn".to_string(),
+            Expression::Call(_) => "call".to_string(),
+            Expression::Member(member) => format!(".{}", member.property.value),
+            Expression::MethodCall(call) => format!(".{}()", call.method.value),
+            Expression::Index(_) => "index".to_string(),
+            Expression::Import(import) => format!("import {}", import.path.literal),
+            Expression::StructLiteral(_) => "instance".to_string(),
+            // Arms are labelled with their patterns, which are not visited
+            Expression::Match(m) => {
+                return self.within("match", |graph| {
+                    graph.visit_expression(&m.subject);
+                    for arm in &m.arms {
+                        graph.within(&format!("{} =>", arm.pattern), |graph| graph.visit_expression(&arm.body));
+                    }
+                });
+            },
+            Expression::Try(t) => {
+                return self.within("try", |graph| {
+                    graph.visit_block_statement(&t.body);
+                    graph.within("catch", |graph| {
+                        graph.visit_identifier(&t.parameter);
+                        graph.visit_block_statement(&t.handler);
+                    });
+                });
+            },
+        };
+        self.within(&label, |graph| visit::walk_expression(graph, expression));
+    }
+
+    fn visit_identifier(&mut self, identifier: &Identifier) {
+        self.node(identifier.value.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use token::{Symbol, Token, TokenType};
+    use crate::{Identifier, Infix, IntegerLiteral, LetStatement, Program, Prefix, Statement, Expression};
+
+    fn int(value: i64) -> Expression {
+        Expression::IntegerLiteral(IntegerLiteral { token: Token::new(TokenType::Integer, value.to_string()), value })
+    }
+
+    fn infix(operator: &str, left: Expression, right: Expression) -> Expression {
+        Expression::Infix(Infix {
+            left: Box::new(left),
+            operator: Token::new(TokenType::Plus, operator.to_string()),
+            right: Box::new(right),
+        })
+    }
+
+    #[test]
+    fn test_to_dot() {
+        // let x = 1 + -2 * "a";
+        let negative = Expression::Prefix(Prefix { operator: Token::new(TokenType::Minus, "-".to_string()), right: Box::new(int(2)) });
+        let program = Program {
+            statements: vec![Statement::LetStatement(LetStatement {
+                token: Token::new(TokenType::Let, "let".to_string()),
+                name: Identifier { token: Token::new(TokenType::Identifier, "x".to_string()), value: Symbol::intern("x") },
+                annotation: None,
+                value: infix("+", int(1), infix("*", negative, Expression::StringLiteral(Token::new(TokenType::String, "\"a\"".to_string())))),
+                docs: None,
+            })],
+        };
+        assert_eq!(program.to_dot(), r#"digraph ast {
+    node [shape=box];
+    n0 [label="program"];
+    n1 [label="let"];
+    n0 -> n1;
+    n2 [label="x"];
+    n1 -> n2;
+    n3 [label="+"];
+    n1 -> n3;
+    n4 [label="1"];
+    n3 -> n4;
+    n5 [label="*"];
+    n3 -> n5;
+    n6 [label="-"];
+    n5 -> n6;
+    n7 [label="2"];
+    n6 -> n7;
+    n8 [label="\"a\""];
+    n5 -> n8;
+}
+"#);
+    }
+}
@@ -1,172 +1,905 @@
+mod dot;
+pub mod pretty;
+mod sexpr;
+pub mod visit;
+
+use std::fmt;
 #[allow(unused_imports)]
-use token::{Token, TokenType};
+use token::{Span, Symbol, Token, TokenType};
+
+pub use pretty::{pretty, pretty_with, Indent};
+pub use visit::{Visitor, VisitorMut};
 
 #[allow(dead_code)]
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Program {
     pub statements: Vec<Statement>,
 }
-impl Program {
-    pub fn string(&self) -> String {
-        let mut s = String::new();
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for statement in &self.statements {
-            s.push_str(&statement.string());
+            write!(f, "{}", statement)?;
         }
-        s
+        Ok(())
+    }
+}
+impl Program {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
+    pub fn string(&self) -> String {
+        self.to_string()
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Statement {
     LetStatement(LetStatement),
     ReturnStatement(ReturnStatement),
     ExpressionStatement(ExpressionStatement),
+    ForStatement(ForStatement),
+    ForInStatement(ForInStatement),
+    ImportStatement(ImportStatement),
+    StructStatement(StructStatement),
+    ThrowStatement(ThrowStatement),
+    // { ... } on its own, with a scope of its own
+    BlockStatement(BlockStatement),
 }
 
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Statement::LetStatement(ls) => write!(f, "{}", ls),
+            Statement::ReturnStatement(rs) => write!(f, "{}", rs),
+            Statement::ExpressionStatement(es) => write!(f, "{}", es),
+            Statement::ForStatement(fs) => write!(f, "{}", fs),
+            Statement::ForInStatement(fs) => write!(f, "{}", fs),
+            Statement::ImportStatement(is) => write!(f, "{}", is),
+            Statement::StructStatement(ss) => write!(f, "{}", ss),
+            Statement::ThrowStatement(ts) => write!(f, "{}", ts),
+            Statement::BlockStatement(bs) => write!(f, "{}", bs),
+        }
+    }
+}
 impl Statement {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
     pub fn string(&self) -> String {
+        self.to_string()
+    }
+
+    // Where the statement starts: its keyword, or the first token of the
+    // expression
+    pub fn span(&self) -> Span {
+        self.token().span()
+    }
+
+    pub fn token(&self) -> &Token {
         match self {
-            Statement::LetStatement(ls) => ls.string(),
-            Statement::ReturnStatement(rs) => rs.string(),
-            Statement::ExpressionStatement(es) => es.string(),
+            Statement::LetStatement(ls) => &ls.token,
+            Statement::ReturnStatement(rs) => &rs.token,
+            Statement::ExpressionStatement(es) => &es.token,
+            Statement::ForStatement(fs) => &fs.token,
+            Statement::ForInStatement(fs) => &fs.token,
+            Statement::ImportStatement(is) => &is.token,
+            Statement::StructStatement(ss) => &ss.token,
+            Statement::ThrowStatement(ts) => &ts.token,
+            Statement::BlockStatement(bs) => &bs.token,
         }
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expression {
     IntegerLiteral(IntegerLiteral),
+    FloatLiteral(FloatLiteral),
+    // A literal too large for an i64, only produced with big integers enabled
+    BigIntegerLiteral(Token),
     StringLiteral(Token),
+    InterpolatedString(InterpolatedString),
+    CharLiteral(CharLiteral),
     BooleanLiteral(Token),
+    NullLiteral(Token),
     Identifier(Identifier),
     Prefix(Prefix),
     Infix(Infix),
     Postfix(Postfix),
+    // `value?`, returning early with an err or null
+    Propagate(Propagate),
     Ternary(Ternary),
+    Assignment(Assignment),
+    ArrayLiteral(ArrayLiteral),
+    RangeLiteral(RangeLiteral),
+    HashLiteral(HashLiteral),
+    FunctionLiteral(FunctionLiteral),
+    Call(Call),
+    Member(Member),
+    MethodCall(MethodCall),
+    Index(Index),
+    Import(Import),
+    Match(MatchExpression),
+    // Boxed, as two blocks would make every expression larger
+    Try(Box<TryExpression>),
+    StructLiteral(StructLiteral),
+    // A block whose value is that of its last statement
+    Block(BlockStatement),
 }
 
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::IntegerLiteral(l) => write!(f, "{}", l),
+            Expression::FloatLiteral(l) => write!(f, "{}", l),
+            Expression::BigIntegerLiteral(l) => write!(f, "{}", l.literal),
+            Expression::StringLiteral(l) => write!(f, "{}", l.literal),
+            Expression::InterpolatedString(s) => write!(f, "{}", s),
+            Expression::CharLiteral(c) => write!(f, "{}", c),
+            Expression::BooleanLiteral(l) => write!(f, "{}", l.literal),
+            Expression::NullLiteral(l) => write!(f, "{}", l.literal),
+            Expression::Identifier(i) => write!(f, "{}", i),
+            Expression::Prefix(p) => write!(f, "{}", p),
+            Expression::Infix(i) => write!(f, "{}", i),
+            Expression::Postfix(p) => write!(f, "{}", p),
+            Expression::Propagate(p) => write!(f, "{}", p),
+            Expression::Ternary(t) => write!(f, "{}", t),
+            Expression::Assignment(a) => write!(f, "{}", a),
+            Expression::ArrayLiteral(a) => write!(f, "{}", a),
+            Expression::RangeLiteral(r) => write!(f, "{}", r),
+            Expression::HashLiteral(h) => write!(f, "{}", h),
+            Expression::FunctionLiteral(func) => write!(f, "{}", func),
+            Expression::Call(c) => write!(f, "{}", c),
+            Expression::Member(m) => write!(f, "{}", m),
+            Expression::MethodCall(m) => write!(f, "{}", m),
+            Expression::Index(i) => write!(f, "{}", i),
+            Expression::Import(i) => write!(f, "{}", i),
+            Expression::Match(m) => write!(f, "{}", m),
+            Expression::Try(t) => write!(f, "{}", t),
+            Expression::StructLiteral(s) => write!(f, "{}", s),
+            Expression::Block(b) => write!(f, "{}", b),
+        }
+    }
+}
 impl Expression {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
     pub fn string(&self) -> String {
+        self.to_string()
+    }
+
+    // Where the expression is reported in errors: its operator for
+    // operations, otherwise its first token
+    pub fn span(&self) -> Span {
         match self {
-            Expression::IntegerLiteral(l) => l.string(),
-            Expression::StringLiteral(l) => l.literal.clone(),
-            Expression::BooleanLiteral(l) => l.literal.clone(),
-            Expression::Identifier(i) => i.string(),
-            Expression::Prefix(p) => p.string(),
-            Expression::Infix(i) => i.string(),
-            Expression::Postfix(p) => p.string(),
-            Expression::Ternary(t) => t.string(),
+            Expression::IntegerLiteral(l) => l.token.span(),
+            Expression::FloatLiteral(l) => l.token.span(),
+            Expression::BigIntegerLiteral(token)
+            | Expression::StringLiteral(token)
+            | Expression::BooleanLiteral(token)
+            | Expression::NullLiteral(token) => token.span(),
+            Expression::InterpolatedString(s) => s.token.span(),
+            Expression::CharLiteral(c) => c.token.span(),
+            Expression::Identifier(i) => i.token.span(),
+            Expression::Prefix(p) => p.operator.span(),
+            Expression::Infix(i) => i.operator.span(),
+            Expression::Postfix(p) => p.operator.span(),
+            Expression::Propagate(p) => p.operator.span(),
+            Expression::Ternary(t) => t.condition.span(),
+            Expression::Assignment(a) => a.token.span(),
+            Expression::ArrayLiteral(a) => a.token.span(),
+            Expression::RangeLiteral(r) => r.token.span(),
+            Expression::HashLiteral(h) => h.token.span(),
+            Expression::FunctionLiteral(f) => f.token.span(),
+            Expression::Call(c) => c.token.span(),
+            Expression::Member(m) => m.token.span(),
+            Expression::MethodCall(m) => m.token.span(),
+            Expression::Index(i) => i.token.span(),
+            Expression::Import(i) => i.token.span(),
+            Expression::Match(m) => m.token.span(),
+            Expression::Try(t) => t.token.span(),
+            Expression::StructLiteral(s) => s.name.token.span(),
+            Expression::Block(b) => b.token.span(),
         }
     }
 }
 
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LetStatement {
     pub token: Token,
     pub name: Identifier,
+    // The type after `name:`, which only the type checker looks at. Boxed,
+    // like the return type of a function, so that untyped programs do not
+    // pay for it in stack space while parsing deep nesting.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub annotation: Option<Box<Identifier>>,
     pub value: Expression,
+    // The `///` lines right before the statement, without the slashes and
+    // the space after them
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub docs: Option<Box<str>>,
+}
+impl fmt::Display for LetStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.token.literal, self.name.value)?;
+        if let Some(annotation) = &self.annotation {
+            write!(f, ": {}", annotation)?;
+        }
+        write!(f, " = {};", self.value)
+    }
 }
 impl LetStatement {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
     pub fn string(&self) -> String {
-        format!("{} {} = {};", self.token.literal, self.name.value, self.value.string())
+        self.to_string()
     }
 }
 
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ReturnStatement {
     pub token: Token,
     pub return_value: Expression,
 }
+impl fmt::Display for ReturnStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {};", self.token.literal, self.return_value)
+    }
+}
 impl ReturnStatement {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
     pub fn string(&self) -> String {
-        format!("{} {};", self.token.literal, self.return_value.string())
+        self.to_string()
     }
 }
 
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExpressionStatement {
     pub token: Token,
     pub expression: Expression,
+    // Whether a semicolon ended it, which at the end of a block discards
+    // the value
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub semicolon: bool,
+}
+impl fmt::Display for ExpressionStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{};", self.expression)
+    }
 }
 impl ExpressionStatement {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
     pub fn string(&self) -> String {
-        format!("{};", self.expression.string())
+        self.to_string()
     }
 }
 
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockStatement {
+    pub token: Token,
+    pub statements: Vec<Statement>,
+}
+impl fmt::Display for BlockStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{ ")?;
+        for (i, statement) in self.statements.iter().enumerate() {
+            match self.value() {
+                Some(value) if i + 1 == self.statements.len() => write!(f, "{} ", value.expression)?,
+                _ => write!(f, "{} ", statement)?,
+            }
+        }
+        write!(f, "}}")
+    }
+}
+impl BlockStatement {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
+    pub fn string(&self) -> String {
+        self.to_string()
+    }
+
+    // The expression the block ends in without a semicolon, which it
+    // evaluates to. Blocks ending in anything else evaluate to null.
+    pub fn value(&self) -> Option<&ExpressionStatement> {
+        match self.statements.last() {
+            Some(Statement::ExpressionStatement(es)) if !es.semicolon => Some(es),
+            _ => None,
+        }
+    }
+}
+
+
+// for (init; condition; update) { body }
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ForStatement {
+    pub token: Token,
+    pub init: Box<Statement>,
+    pub condition: Expression,
+    pub update: Expression,
+    pub body: BlockStatement,
+}
+impl fmt::Display for ForStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({} {}; {}) {}", self.token.literal, self.init, self.condition, self.update, self.body)
+    }
+}
+impl ForStatement {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
+    pub fn string(&self) -> String {
+        self.to_string()
+    }
+}
+
+
+// for (variable in iterable) { body }
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ForInStatement {
+    pub token: Token,
+    pub variable: Identifier,
+    pub iterable: Expression,
+    pub body: BlockStatement,
+}
+impl fmt::Display for ForInStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({} in {}) {}", self.token.literal, self.variable, self.iterable, self.body)
+    }
+}
+impl ForInStatement {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
+    pub fn string(&self) -> String {
+        self.to_string()
+    }
+}
+
+
+// import "path/to/module"; binds the module to the last part of its path
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImportStatement {
+    pub token: Token,
+    pub path: Token,
+    pub name: Identifier,
+}
+impl fmt::Display for ImportStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {};", self.token.literal, self.path.literal)
+    }
+}
+
+// struct Point { x, y } binds Point to a type whose instances have exactly
+// these fields
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StructStatement {
+    pub token: Token,
+    pub name: Identifier,
+    pub fields: Vec<Identifier>,
+}
+impl fmt::Display for StructStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fields: Vec<String> = self.fields.iter().map(|field| field.to_string()).collect();
+        match fields.is_empty() {
+            true => write!(f, "{} {} {{}}", self.token.literal, self.name),
+            false => write!(f, "{} {} {{ {} }}", self.token.literal, self.name, fields.join(", ")),
+        }
+    }
+}
+
+// throw value; leaves the block it is in and every call up to the nearest
+// try, whose handler gets the value
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ThrowStatement {
+    pub token: Token,
+    pub value: Expression,
+}
+impl fmt::Display for ThrowStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {};", self.token.literal, self.value)
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IntegerLiteral {
     pub token: Token,
     pub value: i64,
 }
+impl fmt::Display for IntegerLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.token.literal)
+    }
+}
 impl IntegerLiteral {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
     pub fn string(&self) -> String {
-        self.token.literal.clone()
+        self.to_string()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FloatLiteral {
+    pub token: Token,
+    pub value: f64,
+}
+impl fmt::Display for FloatLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.token.literal)
     }
 }
 
+// The token keeps the literal as written, escapes included
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CharLiteral {
+    pub token: Token,
+    pub value: char,
+}
+impl fmt::Display for CharLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.token.literal)
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Identifier {
     pub token: Token,
-    pub value: String,
+    pub value: Symbol,
+}
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
 }
 impl Identifier {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
     pub fn string(&self) -> String {
-        self.value.clone()
+        self.to_string()
     }
 }
 
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Prefix {
     pub operator: Token,
     pub right: Box<Expression>,
 }
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let right = self.right.to_string();
+        // `- -x` must not run together into a `--` token
+        let gap = if self.operator.ttype == TokenType::Minus && right.starts_with('-') { " " } else { "" };
+        write!(f, "({}{}{})", self.operator.literal, gap, right)
+    }
+}
 impl Prefix {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
     pub fn string(&self) -> String {
-        format!("({}{})", self.operator.literal, self.right.clone().string())
+        self.to_string()
     }
 }
 
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Infix {
     pub left: Box<Expression>,
     pub operator: Token,
     pub right: Box<Expression>,
 }
+impl fmt::Display for Infix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({} {} {})", self.left, self.operator.literal, self.right)
+    }
+}
 impl Infix {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
     pub fn string(&self) -> String {
-        format!("({} {} {})", self.left.string(), self.operator.literal, self.right.string())
+        self.to_string()
     }
 }
 
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Postfix {
     pub left: Box<Expression>,
     pub operator: Token,
 }
+impl fmt::Display for Postfix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}{})", self.left, self.operator.literal)
+    }
+}
 impl Postfix {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
     pub fn string(&self) -> String {
-        format!("({}{})", self.left.clone().string(), self.operator.literal)
+        self.to_string()
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Propagate {
+    pub value: Box<Expression>,
+    pub operator: Token,
+}
+impl fmt::Display for Propagate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}?)", self.value)
     }
 }
 
 // TODO
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Ternary {
     pub condition: Box<Expression>,
     pub if_true: Box<Expression>,
     pub if_false: Box<Expression>,
 }
+impl fmt::Display for Ternary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({} ? {} : {})", self.condition, self.if_true, self.if_false)
+    }
+}
 impl Ternary {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
+    pub fn string(&self) -> String {
+        self.to_string()
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Assignment {
+    pub target: Box<Expression>,
+    pub token: Token,
+    pub value: Box<Expression>,
+}
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({} {} {})", self.target, self.token.literal, self.value)
+    }
+}
+impl Assignment {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
     pub fn string(&self) -> String {
-        format!("{} ? {} : {}", self.condition.string(), self.if_true.string(), self.if_false.string())
+        self.to_string()
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArrayLiteral {
+    pub token: Token,
+    pub elements: Vec<Expression>,
+}
+impl fmt::Display for ArrayLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let elements: Vec<String> = self.elements.iter().map(|e| e.to_string()).collect();
+        write!(f, "[{}]", elements.join(", "))
+    }
+}
+impl ArrayLiteral {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
+    pub fn string(&self) -> String {
+        self.to_string()
+    }
+}
+
+
+// "text ${expression} text", the token being the whole literal
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InterpolatedString {
+    pub token: Token,
+    pub parts: Vec<StringPart>,
+}
+impl fmt::Display for InterpolatedString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"")?;
+        for part in &self.parts {
+            match part {
+                StringPart::Text(text) => write!(f, "{}", text)?,
+                StringPart::Expression(expression) => write!(f, "${{{}}}", expression)?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StringPart {
+    Text(String),
+    Expression(Expression),
+}
+
+
+// start..end or start..=end, the token being the operator
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RangeLiteral {
+    pub token: Token,
+    pub start: Box<Expression>,
+    pub end: Box<Expression>,
+}
+impl fmt::Display for RangeLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}{}{})", self.start, self.token.literal, self.end)
+    }
+}
+impl RangeLiteral {
+    pub fn inclusive(&self) -> bool {
+        self.token.ttype == TokenType::DotDotEq
+    }
+}
+
+
+// {key: value, ...}, pairs kept in source order
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HashLiteral {
+    pub token: Token,
+    pub pairs: Vec<(Expression, Expression)>,
+}
+impl fmt::Display for HashLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pairs: Vec<String> = self.pairs.iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+        write!(f, "{{{}}}", pairs.join(", "))
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FunctionLiteral {
+    pub token: Token,
+    pub parameters: Vec<Identifier>,
+    // One per parameter, None where it has no annotation
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub parameter_types: Vec<Option<Identifier>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub return_type: Option<Box<Identifier>>,
+    pub body: BlockStatement,
+}
+impl fmt::Display for FunctionLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}({})", self.token.literal, self.signature())?;
+        if let Some(return_type) = &self.return_type {
+            write!(f, " -> {}", return_type)?;
+        }
+        write!(f, " {}", self.body)
+    }
+}
+impl FunctionLiteral {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
+    pub fn string(&self) -> String {
+        self.to_string()
+    }
+
+    // The parameters as written between the parentheses, with any types
+    pub fn signature(&self) -> String {
+        let parameters: Vec<String> = self.parameters.iter().enumerate()
+            .map(|(i, parameter)| match self.parameter_types.get(i) {
+                Some(Some(annotation)) => format!("{}: {}", parameter, annotation),
+                _ => parameter.to_string(),
+            })
+            .collect();
+        parameters.join(", ")
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Call {
+    pub token: Token,
+    pub function: Box<Expression>,
+    pub arguments: Vec<Expression>,
+}
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let arguments: Vec<String> = self.arguments.iter().map(|a| a.to_string()).collect();
+        write!(f, "{}({})", self.function, arguments.join(", "))
+    }
+}
+impl Call {
+    #[deprecated(note = "use to_string() or {} formatting instead")]
+    pub fn string(&self) -> String {
+        self.to_string()
+    }
+}
+
+
+// object.property, the token being the dot
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Member {
+    pub object: Box<Expression>,
+    pub token: Token,
+    pub property: Identifier,
+}
+impl fmt::Display for Member {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.object, self.property)
+    }
+}
+
+
+// object.method(arguments), the token being the opening parenthesis
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MethodCall {
+    pub token: Token,
+    pub object: Box<Expression>,
+    pub method: Identifier,
+    pub arguments: Vec<Expression>,
+}
+impl fmt::Display for MethodCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let arguments: Vec<String> = self.arguments.iter().map(|a| a.to_string()).collect();
+        write!(f, "{}.{}({})", self.object, self.method, arguments.join(", "))
+    }
+}
+
+
+// left[index], the token being the opening bracket
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Index {
+    pub token: Token,
+    pub left: Box<Expression>,
+    pub index: Box<Expression>,
+}
+impl fmt::Display for Index {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}[{}])", self.left, self.index)
+    }
+}
+
+
+// import("path/to/module") evaluates to the module without binding it. The
+// path keeps its quotes, like a string literal.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Import {
+    pub token: Token,
+    pub path: Token,
+}
+impl fmt::Display for Import {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}({})", self.token.literal, self.path.literal)
+    }
+}
+
+// An instance of a struct, as in Point { x: 1, y: 2 }
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StructLiteral {
+    pub name: Identifier,
+    pub fields: Vec<(Identifier, Expression)>,
+}
+impl fmt::Display for StructLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fields: Vec<String> = self.fields.iter().map(|(name, value)| format!("{}: {}", name, value)).collect();
+        match fields.is_empty() {
+            true => write!(f, "{} {{}}", self.name),
+            false => write!(f, "{} {{ {} }}", self.name, fields.join(", ")),
+        }
+    }
+}
+
+// Arms are tried in order; the value is that of the first arm whose pattern
+// matches, or null if none does
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MatchExpression {
+    pub token: Token,
+    pub subject: Box<Expression>,
+    pub arms: Vec<MatchArm>,
+}
+impl MatchExpression {
+    // A struct literal in the subject would take the arms for its fields
+    // unless it is parenthesized
+    pub fn subject_needs_parens(&self) -> bool {
+        struct Finder(bool);
+        impl visit::Visitor for Finder {
+            fn visit_expression(&mut self, expression: &Expression) {
+                self.0 |= matches!(expression, Expression::StructLiteral(_));
+                visit::walk_expression(self, expression);
+            }
+        }
+        let mut finder = Finder(false);
+        visit::Visitor::visit_expression(&mut finder, &self.subject);
+        finder.0
+    }
+}
+
+impl fmt::Display for MatchExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.subject_needs_parens() {
+            true => write!(f, "{} ({}) {{", self.token.literal, self.subject)?,
+            false => write!(f, "{} {} {{", self.token.literal, self.subject)?,
+        }
+        let arms: Vec<String> = self.arms.iter().map(|arm| arm.to_string()).collect();
+        match arms.is_empty() {
+            true => write!(f, "}}"),
+            false => write!(f, " {} }}", arms.join(", ")),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Expression,
+}
+impl fmt::Display for MatchArm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} => {}", self.pattern, self.body)
+    }
+}
+
+// try { body } catch (parameter) { handler }, whose value is that of the
+// body, or of the handler if the body throws or fails
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TryExpression {
+    pub token: Token,
+    pub body: BlockStatement,
+    pub parameter: Identifier,
+    pub handler: BlockStatement,
+}
+impl fmt::Display for TryExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} catch ({}) {}", self.token.literal, self.body, self.parameter, self.handler)
+    }
+}
+
+// Literals are integers, optionally negated, strings, characters, booleans
+// and null. Ranges include their start but not their end.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Pattern {
+    Literal(Expression),
+    Range { start: Expression, end: Expression },
+    Wildcard(Token),
+}
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Literal(literal) => write_pattern_literal(f, literal),
+            Pattern::Range { start, end } => {
+                write_pattern_literal(f, start)?;
+                write!(f, "..")?;
+                write_pattern_literal(f, end)
+            },
+            Pattern::Wildcard(token) => write!(f, "{}", token.literal),
+        }
+    }
+}
+
+// Negative literals are written without the parentheses of a prefix
+// expression, as patterns do not accept those
+fn write_pattern_literal(f: &mut fmt::Formatter, literal: &Expression) -> fmt::Result {
+    match literal {
+        Expression::Prefix(prefix) => write!(f, "{}{}", prefix.operator.literal, prefix.right),
+        literal => write!(f, "{}", literal),
     }
 }
 
@@ -183,14 +916,16 @@ mod tests {
                         token: Token::new(TokenType::Let, "let".to_string()),
                         name: Identifier {
                             token: Token::new(TokenType::Identifier, "myVar".to_string()),
-                            value: "myVar".to_string(),
+                            value: Symbol::intern("myVar"),
                         },
+                        annotation: None,
                         value: Expression::Identifier(
                             Identifier {
                                 token: Token::new(TokenType::Identifier, "anotherVar".to_string()),
-                                value: "anotherVar".to_string(),
+                                value: Symbol::intern("anotherVar"),
                             }
                         ),
+                        docs: None,
                     }
                 ),
                 Statement::ReturnStatement(
@@ -199,7 +934,7 @@ mod tests {
                         return_value: Expression::Identifier(
                             Identifier {
                                 token: Token::new(TokenType::Identifier, "myVar".to_string()),
-                                value: "myVar".to_string(),
+                                value: Symbol::intern("myVar"),
                             }
                         ),
                     }
@@ -207,7 +942,12 @@ mod tests {
             ],
         };
 
-        assert_eq!(program.statements[0].string(), "let myVar = anotherVar;");
-        assert_eq!(program.statements[1].string(), "return myVar;");
+        assert_eq!(program.statements[0].to_string(), "let myVar = anotherVar;");
+        assert_eq!(program.statements[1].to_string(), "return myVar;");
+        assert_eq!(format!("{}", program), "let myVar = anotherVar;return myVar;");
+
+        #[allow(deprecated)]
+        let shim = program.string();
+        assert_eq!(shim, program.to_string());
     }
 }
\ No newline at end of file
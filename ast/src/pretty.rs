@@ -0,0 +1,402 @@
+use token::TokenType;
+use crate::{Program, Statement, BlockStatement, Expression, StringPart};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Indent {
+    Spaces(usize),
+    Tab,
+}
+
+// Multi-line source for a program, indenting nested blocks by `indent` spaces
+pub fn pretty(program: &Program, indent: usize) -> String {
+    pretty_with(program, Indent::Spaces(indent))
+}
+
+pub fn pretty_with(program: &Program, indent: Indent) -> String {
+    let mut printer = Printer { indent, level: 0, out: String::new() };
+    for statement in &program.statements {
+        printer.statement(statement);
+        printer.out.push('\n');
+    }
+    printer.out
+}
+
+// Binding strength of each expression, mirroring the parser's precedences,
+// used to decide where parentheses are needed
+fn precedence(expression: &Expression) -> u8 {
+    match expression {
+        Expression::Assignment(_) => 1,
+        Expression::Ternary(_) => 2,
+        Expression::Infix(infix) => infix_precedence(&infix.operator.ttype),
+        Expression::RangeLiteral(_) => 6,
+        Expression::Prefix(_) | Expression::Postfix(_) => 9,
+        Expression::Call(_) | Expression::Member(_) | Expression::MethodCall(_) | Expression::Index(_) | Expression::Propagate(_) => 10,
+        _ => 11,
+    }
+}
+
+fn infix_precedence(operator: &TokenType) -> u8 {
+    match operator {
+        TokenType::NullCoalesce => 3,
+        TokenType::Equal | TokenType::NotEqual => 4,
+        TokenType::LessThan | TokenType::GreaterThan | TokenType::LessThanOrEqual | TokenType::GreaterThanOrEqual => 5,
+        TokenType::Plus | TokenType::Minus => 7,
+        _ => 8,
+    }
+}
+
+struct Printer {
+    indent: Indent,
+    level: usize,
+    out: String,
+}
+
+impl Printer {
+    fn push_indent(&mut self) {
+        for _ in 0..self.level {
+            match self.indent {
+                Indent::Spaces(n) => self.out.push_str(&" ".repeat(n)),
+                Indent::Tab => self.out.push('\t'),
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::LetStatement(ls) => {
+                self.out.push_str(&format!("let {}", ls.name.value));
+                if let Some(annotation) = &ls.annotation {
+                    self.out.push_str(&format!(": {}", annotation));
+                }
+                self.out.push_str(" = ");
+                self.expression(&ls.value);
+                self.out.push(';');
+            },
+            Statement::ReturnStatement(rs) => {
+                self.out.push_str("return ");
+                self.expression(&rs.return_value);
+                self.out.push(';');
+            },
+            Statement::ExpressionStatement(es) => {
+                self.expression(&es.expression);
+                self.out.push(';');
+            },
+            Statement::ForStatement(fs) => {
+                self.out.push_str("for (");
+                self.statement(&fs.init);
+                self.out.push(' ');
+                self.expression(&fs.condition);
+                self.out.push_str("; ");
+                self.expression(&fs.update);
+                self.out.push_str(") ");
+                self.block(&fs.body);
+            },
+            Statement::ForInStatement(fs) => {
+                self.out.push_str(&format!("for ({} in ", fs.variable.value));
+                self.expression(&fs.iterable);
+                self.out.push_str(") ");
+                self.block(&fs.body);
+            },
+            Statement::ImportStatement(is) => self.out.push_str(&format!("import {};", is.path.literal)),
+            Statement::StructStatement(ss) => self.out.push_str(&ss.to_string()),
+            Statement::ThrowStatement(ts) => {
+                self.out.push_str("throw ");
+                self.expression(&ts.value);
+                self.out.push(';');
+            },
+            Statement::BlockStatement(bs) => self.block(bs),
+        }
+    }
+
+    fn block(&mut self, block: &BlockStatement) {
+        if block.statements.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+
+        self.out.push_str("{\n");
+        self.level += 1;
+        for (i, statement) in block.statements.iter().enumerate() {
+            self.push_indent();
+            match block.value() {
+                Some(value) if i + 1 == block.statements.len() => self.expression(&value.expression),
+                _ => self.statement(statement),
+            }
+            self.out.push('\n');
+        }
+        self.level -= 1;
+        self.push_indent();
+        self.out.push('}');
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::IntegerLiteral(il) => self.out.push_str(&il.token.literal),
+            Expression::FloatLiteral(fl) => self.out.push_str(&fl.token.literal),
+            Expression::CharLiteral(c) => self.out.push_str(&c.token.literal),
+            Expression::BigIntegerLiteral(token)
+            | Expression::StringLiteral(token)
+            | Expression::BooleanLiteral(token)
+            | Expression::NullLiteral(token) => {
+                self.out.push_str(&token.literal)
+            },
+            Expression::Identifier(ident) => self.out.push_str(ident.value.as_str()),
+            Expression::InterpolatedString(string) => {
+                self.out.push('"');
+                for part in &string.parts {
+                    match part {
+                        StringPart::Text(text) => self.out.push_str(text),
+                        StringPart::Expression(expression) => {
+                            self.out.push_str("${");
+                            self.expression(expression);
+                            self.out.push('}');
+                        },
+                    }
+                }
+                self.out.push('"');
+            },
+            Expression::Prefix(prefix) => {
+                self.out.push_str(&prefix.operator.literal);
+                // `- -x` must not run together into a `--` token
+                if prefix.operator.ttype == TokenType::Minus && starts_with_minus(&prefix.right) {
+                    self.out.push(' ');
+                }
+                self.operand(&prefix.right, precedence(expression));
+            },
+            Expression::Infix(infix) => {
+                let own = precedence(expression);
+                self.operand(&infix.left, own);
+                self.out.push_str(&format!(" {} ", infix.operator.literal));
+                // Infix operators are left-associative
+                self.operand(&infix.right, own + 1);
+            },
+            Expression::Postfix(postfix) => {
+                self.operand(&postfix.left, precedence(expression) + 1);
+                self.out.push_str(&postfix.operator.literal);
+            },
+            Expression::Propagate(propagate) => {
+                self.operand(&propagate.value, precedence(expression));
+                // `x? ?` must not run together into a `??` token
+                if self.out.ends_with('?') {
+                    self.out.push(' ');
+                }
+                self.out.push('?');
+            },
+            Expression::Ternary(ternary) => {
+                let own = precedence(expression);
+                self.operand(&ternary.condition, own + 1);
+                self.out.push_str(" ? ");
+                self.operand(&ternary.if_true, own);
+                self.out.push_str(" : ");
+                self.operand(&ternary.if_false, own);
+            },
+            Expression::Assignment(assignment) => {
+                let own = precedence(expression);
+                // Assignment is right-associative
+                self.operand(&assignment.target, own + 1);
+                self.out.push_str(&format!(" {} ", assignment.token.literal));
+                self.operand(&assignment.value, own);
+            },
+            // Ranges do not chain, so both sides bind tighter
+            Expression::RangeLiteral(range) => {
+                let own = precedence(expression);
+                self.operand(&range.start, own + 1);
+                self.out.push_str(&range.token.literal);
+                self.operand(&range.end, own + 1);
+            },
+            Expression::ArrayLiteral(array) => {
+                self.out.push('[');
+                self.list(&array.elements);
+                self.out.push(']');
+            },
+            Expression::HashLiteral(hash) => {
+                self.out.push('{');
+                for (i, (key, value)) in hash.pairs.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expression(key);
+                    self.out.push_str(": ");
+                    self.expression(value);
+                }
+                self.out.push('}');
+            },
+            Expression::FunctionLiteral(func) => {
+                self.out.push_str(&format!("fn({}) ", func.signature()));
+                if let Some(return_type) = &func.return_type {
+                    self.out.push_str(&format!("-> {} ", return_type));
+                }
+                self.block(&func.body);
+            },
+            Expression::Call(call) => {
+                self.operand(&call.function, precedence(expression));
+                self.out.push('(');
+                self.list(&call.arguments);
+                self.out.push(')');
+            },
+            Expression::Member(member) => {
+                self.operand(&member.object, precedence(expression));
+                self.out.push_str(&format!(".{}", member.property.value));
+            },
+            Expression::MethodCall(call) => {
+                self.operand(&call.object, precedence(expression));
+                self.out.push_str(&format!(".{}(", call.method.value));
+                self.list(&call.arguments);
+                self.out.push(')');
+            },
+            Expression::Index(index) => {
+                self.operand(&index.left, precedence(expression));
+                self.out.push('[');
+                self.expression(&index.index);
+                self.out.push(']');
+            },
+            Expression::Import(import) => self.out.push_str(&format!("import({})", import.path.literal)),
+            Expression::StructLiteral(literal) => {
+                self.out.push_str(&format!("{} {{", literal.name));
+                for (i, (name, value)) in literal.fields.iter().enumerate() {
+                    self.out.push_str(if i > 0 { ", " } else { " " });
+                    self.out.push_str(&format!("{}: ", name));
+                    self.expression(value);
+                }
+                self.out.push_str(if literal.fields.is_empty() { "}" } else { " }" });
+            },
+            Expression::Match(m) => {
+                self.out.push_str("match ");
+                match m.subject_needs_parens() {
+                    true => {
+                        self.out.push('(');
+                        self.expression(&m.subject);
+                        self.out.push(')');
+                    },
+                    false => self.expression(&m.subject),
+                }
+                if m.arms.is_empty() {
+                    self.out.push_str(" {}");
+                    return;
+                }
+                self.out.push_str(" {\n");
+                self.level += 1;
+                for arm in &m.arms {
+                    self.push_indent();
+                    self.out.push_str(&format!("{} => ", arm.pattern));
+                    self.expression(&arm.body);
+                    self.out.push_str(",\n");
+                }
+                self.level -= 1;
+                self.push_indent();
+                self.out.push('}');
+            },
+            Expression::Try(t) => {
+                self.out.push_str("try ");
+                self.block(&t.body);
+                self.out.push_str(&format!(" catch ({}) ", t.parameter));
+                self.block(&t.handler);
+            },
+            Expression::Block(block) => self.block(block),
+        }
+    }
+
+    // Prints a subexpression, wrapping it in parentheses when it binds
+    // more loosely than its position requires
+    fn operand(&mut self, expression: &Expression, min_precedence: u8) {
+        if precedence(expression) < min_precedence {
+            self.out.push('(');
+            self.expression(expression);
+            self.out.push(')');
+        } else {
+            self.expression(expression);
+        }
+    }
+
+    fn list(&mut self, expressions: &[Expression]) {
+        for (i, expression) in expressions.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.expression(expression);
+        }
+    }
+}
+
+fn starts_with_minus(expression: &Expression) -> bool {
+    match expression {
+        Expression::Prefix(prefix) => prefix.operator.ttype == TokenType::Minus || prefix.operator.ttype == TokenType::Decrement,
+        Expression::IntegerLiteral(il) => il.value < 0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use token::{Symbol, Token};
+    use crate::{ExpressionStatement, Identifier, IntegerLiteral, Infix, Prefix};
+
+    fn ident(name: &str) -> Expression {
+        Expression::Identifier(Identifier{
+            token: Token::new(TokenType::Identifier, name.to_string()),
+            value: Symbol::intern(name),
+        })
+    }
+
+    fn int(value: i64) -> Expression {
+        Expression::IntegerLiteral(IntegerLiteral{token: Token::new(TokenType::Integer, value.to_string()), value})
+    }
+
+    fn infix(left: Expression, operator: TokenType, literal: &str, right: Expression) -> Expression {
+        Expression::Infix(Infix{
+            left: Box::new(left),
+            operator: Token::new(operator, literal.to_string()),
+            right: Box::new(right),
+        })
+    }
+
+    fn prefix(operator: TokenType, literal: &str, right: Expression) -> Expression {
+        Expression::Prefix(Prefix{operator: Token::new(operator, literal.to_string()), right: Box::new(right)})
+    }
+
+    fn program(expression: Expression) -> Program {
+        Program {
+            statements: vec![Statement::ExpressionStatement(ExpressionStatement{
+                token: Token::new(TokenType::Identifier, String::new()),
+                expression,
+                semicolon: true,
+            })],
+        }
+    }
+
+    #[test]
+    fn test_parentheses() {
+        let tests = vec![
+            (infix(ident("a"), TokenType::Plus, "+", infix(ident("b"), TokenType::Asterisk, "*", int(2))), "a + b * 2;\n"),
+            (infix(infix(ident("a"), TokenType::Plus, "+", ident("b")), TokenType::Asterisk, "*", int(2)), "(a + b) * 2;\n"),
+            (infix(infix(ident("a"), TokenType::Minus, "-", ident("b")), TokenType::Minus, "-", ident("c")), "a - b - c;\n"),
+            (infix(ident("a"), TokenType::Minus, "-", infix(ident("b"), TokenType::Minus, "-", ident("c"))), "a - (b - c);\n"),
+            (prefix(TokenType::Minus, "-", infix(ident("a"), TokenType::Plus, "+", ident("b"))), "-(a + b);\n"),
+            (prefix(TokenType::Minus, "-", prefix(TokenType::Minus, "-", ident("a"))), "- -a;\n"),
+            (prefix(TokenType::Bang, "!", prefix(TokenType::Bang, "!", ident("a"))), "!!a;\n"),
+        ];
+
+        for (expression, expected) in tests {
+            assert_eq!(pretty(&program(expression), 4), expected);
+        }
+    }
+
+    #[test]
+    fn test_indent_styles() {
+        let body = crate::BlockStatement {
+            token: Token::new(TokenType::LeftCurlyBracket, "{".to_string()),
+            statements: program(ident("x")).statements,
+        };
+        let func = Expression::FunctionLiteral(crate::FunctionLiteral{
+            token: Token::new(TokenType::Fn, "fn".to_string()),
+            parameters: vec![],
+            parameter_types: vec![],
+            return_type: None,
+            body,
+        });
+
+        assert_eq!(pretty(&program(func.clone()), 2), "fn() {\n  x;\n};\n");
+        assert_eq!(pretty_with(&program(func), Indent::Tab), "fn() {\n\tx;\n};\n");
+    }
+}
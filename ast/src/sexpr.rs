@@ -0,0 +1,221 @@
+use crate::{BlockStatement, Expression, Pattern, Program, Statement, StringPart};
+
+// Lists shorter than this, indentation included, stay on one line
+const WIDTH: usize = 80;
+
+enum Sexpr {
+    Atom(String),
+    List(Vec<Sexpr>),
+}
+
+fn atom(text: impl Into<String>) -> Sexpr {
+    Sexpr::Atom(text.into())
+}
+
+fn list(head: &str, rest: impl IntoIterator<Item = Sexpr>) -> Sexpr {
+    Sexpr::List(std::iter::once(atom(head)).chain(rest).collect())
+}
+
+impl Program {
+    // The tree as an S-expression, one node per list, with every operator
+    // and grouping explicit. Tokens and positions are left out, so the
+    // output only changes when the shape of the tree does.
+    pub fn to_sexpr(&self) -> String {
+        let tree = list("program", self.statements.iter().map(statement));
+        let mut out = String::new();
+        render(&tree, 0, &mut out);
+        out.push('\n');
+        out
+    }
+}
+
+fn statement(node: &Statement) -> Sexpr {
+    match node {
+        Statement::LetStatement(ls) => {
+            let annotation = ls.annotation.iter().map(|annotation| list("type", [atom(annotation.value.as_str())]));
+            list("let", std::iter::once(atom(ls.name.value.as_str())).chain(annotation).chain([expression(&ls.value)]))
+        },
+        Statement::ReturnStatement(rs) => list("return", [expression(&rs.return_value)]),
+        Statement::ExpressionStatement(es) => expression(&es.expression),
+        Statement::ForStatement(fs) => list("for", [
+            statement(&fs.init),
+            expression(&fs.condition),
+            expression(&fs.update),
+            block(&fs.body),
+        ]),
+        Statement::ForInStatement(fs) => list("for-in", [
+            atom(fs.variable.value.as_str()),
+            expression(&fs.iterable),
+            block(&fs.body),
+        ]),
+        Statement::ImportStatement(is) => list("import", [atom(is.path.literal.as_str()), atom(is.name.value.as_str())]),
+        Statement::StructStatement(ss) => list("struct", [
+            atom(ss.name.value.as_str()),
+            list("fields", ss.fields.iter().map(|field| atom(field.value.as_str()))),
+        ]),
+        Statement::ThrowStatement(ts) => list("throw", [expression(&ts.value)]),
+        Statement::BlockStatement(bs) => block(bs),
+    }
+}
+
+fn block(node: &BlockStatement) -> Sexpr {
+    list("block", node.statements.iter().map(statement))
+}
+
+fn expression(node: &Expression) -> Sexpr {
+    match node {
+        Expression::IntegerLiteral(il) => atom(il.value.to_string()),
+        Expression::FloatLiteral(fl) => atom(fl.token.literal.as_str()),
+        Expression::CharLiteral(c) => atom(c.token.literal.as_str()),
+        Expression::BigIntegerLiteral(token)
+        | Expression::StringLiteral(token)
+        | Expression::BooleanLiteral(token)
+        | Expression::NullLiteral(token) => atom(token.literal.as_str()),
+        Expression::Identifier(ident) => atom(ident.value.as_str()),
+        Expression::InterpolatedString(string) => list("interpolate", string.parts.iter().map(|part| match part {
+            StringPart::Text(text) => atom(format!("\"{}\"", text)),
+            StringPart::Expression(expression) => self::expression(expression),
+        })),
+        Expression::Prefix(prefix) => list("prefix", [atom(prefix.operator.literal.as_str()), expression(&prefix.right)]),
+        Expression::Infix(infix) => list("infix", [
+            atom(infix.operator.literal.as_str()),
+            expression(&infix.left),
+            expression(&infix.right),
+        ]),
+        Expression::Postfix(postfix) => list("postfix", [atom(postfix.operator.literal.as_str()), expression(&postfix.left)]),
+        Expression::Propagate(propagate) => list("propagate", [expression(&propagate.value)]),
+        Expression::Ternary(ternary) => list("ternary", [
+            expression(&ternary.condition),
+            expression(&ternary.if_true),
+            expression(&ternary.if_false),
+        ]),
+        Expression::Assignment(assignment) => list("assign", [
+            atom(assignment.token.literal.as_str()),
+            expression(&assignment.target),
+            expression(&assignment.value),
+        ]),
+        Expression::ArrayLiteral(array) => list("array", array.elements.iter().map(expression)),
+        Expression::RangeLiteral(range) => list("range", [
+            atom(range.token.literal.as_str()),
+            expression(&range.start),
+            expression(&range.end),
+        ]),
+        Expression::HashLiteral(hash) => list("hash", hash.pairs.iter().map(|(key, value)| {
+            list("pair", [expression(key), expression(value)])
+        })),
+        Expression::FunctionLiteral(func) => {
+            // Annotated parameters are written (name type)
+            let parameters = func.parameters.iter().enumerate().map(|(i, parameter)| match func.parameter_types.get(i) {
+                Some(Some(annotation)) => Sexpr::List(vec![atom(parameter.value.as_str()), atom(annotation.value.as_str())]),
+                _ => atom(parameter.value.as_str()),
+            });
+            let return_type = func.return_type.iter().map(|annotation| list("returns", [atom(annotation.value.as_str())]));
+            list("fn", std::iter::once(list("params", parameters)).chain(return_type).chain([block(&func.body)]))
+        },
+        Expression::Call(call) => list("call", std::iter::once(expression(&call.function)).chain(call.arguments.iter().map(expression))),
+        Expression::Member(member) => list("member", [expression(&member.object), atom(member.property.value.as_str())]),
+        Expression::MethodCall(call) => list("method", [expression(&call.object), atom(call.method.value.as_str())].into_iter().chain(call.arguments.iter().map(expression))),
+        Expression::Index(index) => list("index", [expression(&index.left), expression(&index.index)]),
+        Expression::Import(import) => list("import", [atom(import.path.literal.as_str())]),
+        Expression::StructLiteral(literal) => list("instance", std::iter::once(atom(literal.name.value.as_str())).chain(
+            literal.fields.iter().map(|(name, value)| list("field", [atom(name.value.as_str()), expression(value)])),
+        )),
+        Expression::Match(m) => list("match", std::iter::once(expression(&m.subject)).chain(m.arms.iter().map(|arm| {
+            list("arm", [pattern(&arm.pattern), expression(&arm.body)])
+        }))),
+        Expression::Try(t) => list("try", [
+            block(&t.body),
+            list("catch", [atom(t.parameter.value.as_str()), block(&t.handler)]),
+        ]),
+        Expression::Block(b) => block(b),
+    }
+}
+
+fn pattern(node: &Pattern) -> Sexpr {
+    match node {
+        Pattern::Literal(literal) => expression(literal),
+        Pattern::Range { start, end } => list("range", [expression(start), expression(end)]),
+        Pattern::Wildcard(token) => atom(token.literal.as_str()),
+    }
+}
+
+fn flat(sexpr: &Sexpr) -> String {
+    match sexpr {
+        Sexpr::Atom(text) => text.clone(),
+        Sexpr::List(items) => format!("({})", items.iter().map(flat).collect::<Vec<_>>().join(" ")),
+    }
+}
+
+// A list that does not fit keeps its head and any atoms straight after it
+// on the first line, then puts the rest one to a line beneath
+fn render(sexpr: &Sexpr, level: usize, out: &mut String) {
+    let line = flat(sexpr);
+    let items = match sexpr {
+        Sexpr::List(items) if level * 2 + line.len() > WIDTH => items,
+        _ => {
+            out.push_str(&line);
+            return;
+        },
+    };
+
+    let leading = 1 + items[1..].iter().take_while(|item| matches!(item, Sexpr::Atom(_))).count();
+    out.push('(');
+    out.push_str(&items[..leading].iter().map(flat).collect::<Vec<_>>().join(" "));
+    for item in &items[leading..] {
+        out.push('\n');
+        out.push_str(&"  ".repeat(level + 1));
+        render(item, level + 1, out);
+    }
+    out.push(')');
+}
+
+#[cfg(test)]
+mod tests {
+    use token::{Symbol, Token, TokenType};
+    use crate::{ExpressionStatement, Identifier, IntegerLiteral, Infix, LetStatement};
+    use super::*;
+
+    fn ident(name: &str) -> Identifier {
+        Identifier { token: Token::new(TokenType::Identifier, name.to_string()), value: Symbol::intern(name) }
+    }
+
+    fn int(value: i64) -> Expression {
+        Expression::IntegerLiteral(IntegerLiteral { token: Token::new(TokenType::Integer, value.to_string()), value })
+    }
+
+    fn plus(left: Expression, right: Expression) -> Expression {
+        Expression::Infix(Infix {
+            left: Box::new(left),
+            operator: Token::new(TokenType::Plus, "+".to_string()),
+            right: Box::new(right),
+        })
+    }
+
+    #[test]
+    fn test_to_sexpr() {
+        let let_statement = |value| Statement::LetStatement(LetStatement {
+            token: Token::new(TokenType::Let, "let".to_string()),
+            name: ident("x"),
+            annotation: None,
+            value,
+            docs: None,
+        });
+        let long = (1..30).map(int).reduce(plus).unwrap();
+        let program = Program {
+            statements: vec![
+                let_statement(plus(int(1), plus(int(2), int(3)))),
+                Statement::ExpressionStatement(ExpressionStatement {
+                    token: Token::new(TokenType::Identifier, "x".to_string()),
+                    expression: Expression::Identifier(ident("x")),
+                    semicolon: true,
+                }),
+            ],
+        };
+        assert_eq!(program.to_sexpr(), "(program (let x (infix + 1 (infix + 2 3))) x)\n");
+
+        let program = Program { statements: vec![let_statement(long)] };
+        let sexpr = program.to_sexpr();
+        assert!(sexpr.starts_with("(program\n  (let x\n    (infix +\n      (infix +\n"), "{}", sexpr);
+        assert!(sexpr.lines().all(|line| line.len() <= WIDTH), "{}", sexpr);
+    }
+}
@@ -0,0 +1,386 @@
+use crate::{Program, Statement, BlockStatement, Expression, Identifier, StringPart};
+
+// Read-only traversal. Every method defaults to walking the node's children,
+// so implementors only override the nodes they care about and call the
+// matching walk_* function to keep descending.
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_block_statement(&mut self, block: &BlockStatement) {
+        walk_block_statement(self, block);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression);
+    }
+
+    fn visit_identifier(&mut self, _identifier: &Identifier) {}
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for statement in &program.statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_block_statement<V: Visitor + ?Sized>(visitor: &mut V, block: &BlockStatement) {
+    for statement in &block.statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match statement {
+        Statement::LetStatement(ls) => {
+            visitor.visit_identifier(&ls.name);
+            visitor.visit_expression(&ls.value);
+        },
+        Statement::ReturnStatement(rs) => visitor.visit_expression(&rs.return_value),
+        Statement::ExpressionStatement(es) => visitor.visit_expression(&es.expression),
+        Statement::ForStatement(fs) => {
+            visitor.visit_statement(&fs.init);
+            visitor.visit_expression(&fs.condition);
+            visitor.visit_expression(&fs.update);
+            visitor.visit_block_statement(&fs.body);
+        },
+        Statement::ForInStatement(fs) => {
+            visitor.visit_identifier(&fs.variable);
+            visitor.visit_expression(&fs.iterable);
+            visitor.visit_block_statement(&fs.body);
+        },
+        Statement::ImportStatement(is) => visitor.visit_identifier(&is.name),
+        Statement::StructStatement(ss) => visitor.visit_identifier(&ss.name),
+        Statement::ThrowStatement(ts) => visitor.visit_expression(&ts.value),
+        Statement::BlockStatement(bs) => visitor.visit_block_statement(bs),
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    match expression {
+        Expression::IntegerLiteral(_) | Expression::FloatLiteral(_) | Expression::BigIntegerLiteral(_) | Expression::StringLiteral(_) | Expression::CharLiteral(_) | Expression::BooleanLiteral(_) | Expression::NullLiteral(_) => {},
+        Expression::Identifier(ident) => visitor.visit_identifier(ident),
+        Expression::InterpolatedString(string) => {
+            for part in &string.parts {
+                if let StringPart::Expression(expression) = part {
+                    visitor.visit_expression(expression);
+                }
+            }
+        },
+        Expression::Prefix(prefix) => visitor.visit_expression(&prefix.right),
+        Expression::Infix(infix) => {
+            visitor.visit_expression(&infix.left);
+            visitor.visit_expression(&infix.right);
+        },
+        Expression::Postfix(postfix) => visitor.visit_expression(&postfix.left),
+        Expression::Propagate(propagate) => visitor.visit_expression(&propagate.value),
+        Expression::Ternary(ternary) => {
+            visitor.visit_expression(&ternary.condition);
+            visitor.visit_expression(&ternary.if_true);
+            visitor.visit_expression(&ternary.if_false);
+        },
+        Expression::Assignment(assignment) => {
+            visitor.visit_expression(&assignment.target);
+            visitor.visit_expression(&assignment.value);
+        },
+        Expression::ArrayLiteral(array) => {
+            for element in &array.elements {
+                visitor.visit_expression(element);
+            }
+        },
+        Expression::RangeLiteral(range) => {
+            visitor.visit_expression(&range.start);
+            visitor.visit_expression(&range.end);
+        },
+        Expression::HashLiteral(hash) => {
+            for (key, value) in &hash.pairs {
+                visitor.visit_expression(key);
+                visitor.visit_expression(value);
+            }
+        },
+        Expression::FunctionLiteral(func) => {
+            for parameter in &func.parameters {
+                visitor.visit_identifier(parameter);
+            }
+            visitor.visit_block_statement(&func.body);
+        },
+        Expression::Call(call) => {
+            visitor.visit_expression(&call.function);
+            for argument in &call.arguments {
+                visitor.visit_expression(argument);
+            }
+        },
+        // The property names a member, not a binding, so it is not visited
+        Expression::Member(member) => visitor.visit_expression(&member.object),
+        Expression::MethodCall(call) => {
+            visitor.visit_expression(&call.object);
+            for argument in &call.arguments {
+                visitor.visit_expression(argument);
+            }
+        },
+        Expression::Index(index) => {
+            visitor.visit_expression(&index.left);
+            visitor.visit_expression(&index.index);
+        },
+        Expression::Import(_) => {},
+        // Patterns only hold literals
+        Expression::Match(m) => {
+            visitor.visit_expression(&m.subject);
+            for arm in &m.arms {
+                visitor.visit_expression(&arm.body);
+            }
+        },
+        Expression::Try(t) => {
+            visitor.visit_block_statement(&t.body);
+            visitor.visit_identifier(&t.parameter);
+            visitor.visit_block_statement(&t.handler);
+        },
+        Expression::StructLiteral(literal) => {
+            visitor.visit_identifier(&literal.name);
+            for (_, value) in &literal.fields {
+                visitor.visit_expression(value);
+            }
+        },
+        Expression::Block(block) => visitor.visit_block_statement(block),
+    }
+}
+
+// The same traversal over mutable references, for passes that rewrite the tree
+pub trait VisitorMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program);
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement);
+    }
+
+    fn visit_block_statement_mut(&mut self, block: &mut BlockStatement) {
+        walk_block_statement_mut(self, block);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression);
+    }
+
+    fn visit_identifier_mut(&mut self, _identifier: &mut Identifier) {}
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for statement in &mut program.statements {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_block_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, block: &mut BlockStatement) {
+    for statement in &mut block.statements {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match statement {
+        Statement::LetStatement(ls) => {
+            visitor.visit_identifier_mut(&mut ls.name);
+            visitor.visit_expression_mut(&mut ls.value);
+        },
+        Statement::ReturnStatement(rs) => visitor.visit_expression_mut(&mut rs.return_value),
+        Statement::ExpressionStatement(es) => visitor.visit_expression_mut(&mut es.expression),
+        Statement::ForStatement(fs) => {
+            visitor.visit_statement_mut(&mut fs.init);
+            visitor.visit_expression_mut(&mut fs.condition);
+            visitor.visit_expression_mut(&mut fs.update);
+            visitor.visit_block_statement_mut(&mut fs.body);
+        },
+        Statement::ForInStatement(fs) => {
+            visitor.visit_identifier_mut(&mut fs.variable);
+            visitor.visit_expression_mut(&mut fs.iterable);
+            visitor.visit_block_statement_mut(&mut fs.body);
+        },
+        Statement::ImportStatement(is) => visitor.visit_identifier_mut(&mut is.name),
+        Statement::StructStatement(ss) => visitor.visit_identifier_mut(&mut ss.name),
+        Statement::ThrowStatement(ts) => visitor.visit_expression_mut(&mut ts.value),
+        Statement::BlockStatement(bs) => visitor.visit_block_statement_mut(bs),
+    }
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    match expression {
+        Expression::IntegerLiteral(_) | Expression::FloatLiteral(_) | Expression::BigIntegerLiteral(_) | Expression::StringLiteral(_) | Expression::CharLiteral(_) | Expression::BooleanLiteral(_) | Expression::NullLiteral(_) => {},
+        Expression::Identifier(ident) => visitor.visit_identifier_mut(ident),
+        Expression::InterpolatedString(string) => {
+            for part in &mut string.parts {
+                if let StringPart::Expression(expression) = part {
+                    visitor.visit_expression_mut(expression);
+                }
+            }
+        },
+        Expression::Prefix(prefix) => visitor.visit_expression_mut(&mut prefix.right),
+        Expression::Infix(infix) => {
+            visitor.visit_expression_mut(&mut infix.left);
+            visitor.visit_expression_mut(&mut infix.right);
+        },
+        Expression::Postfix(postfix) => visitor.visit_expression_mut(&mut postfix.left),
+        Expression::Propagate(propagate) => visitor.visit_expression_mut(&mut propagate.value),
+        Expression::Ternary(ternary) => {
+            visitor.visit_expression_mut(&mut ternary.condition);
+            visitor.visit_expression_mut(&mut ternary.if_true);
+            visitor.visit_expression_mut(&mut ternary.if_false);
+        },
+        Expression::Assignment(assignment) => {
+            visitor.visit_expression_mut(&mut assignment.target);
+            visitor.visit_expression_mut(&mut assignment.value);
+        },
+        Expression::ArrayLiteral(array) => {
+            for element in &mut array.elements {
+                visitor.visit_expression_mut(element);
+            }
+        },
+        Expression::RangeLiteral(range) => {
+            visitor.visit_expression_mut(&mut range.start);
+            visitor.visit_expression_mut(&mut range.end);
+        },
+        Expression::HashLiteral(hash) => {
+            for (key, value) in &mut hash.pairs {
+                visitor.visit_expression_mut(key);
+                visitor.visit_expression_mut(value);
+            }
+        },
+        Expression::FunctionLiteral(func) => {
+            for parameter in &mut func.parameters {
+                visitor.visit_identifier_mut(parameter);
+            }
+            visitor.visit_block_statement_mut(&mut func.body);
+        },
+        Expression::Call(call) => {
+            visitor.visit_expression_mut(&mut call.function);
+            for argument in &mut call.arguments {
+                visitor.visit_expression_mut(argument);
+            }
+        },
+        Expression::Member(member) => visitor.visit_expression_mut(&mut member.object),
+        Expression::MethodCall(call) => {
+            visitor.visit_expression_mut(&mut call.object);
+            for argument in &mut call.arguments {
+                visitor.visit_expression_mut(argument);
+            }
+        },
+        Expression::Index(index) => {
+            visitor.visit_expression_mut(&mut index.left);
+            visitor.visit_expression_mut(&mut index.index);
+        },
+        Expression::Import(_) => {},
+        Expression::Match(m) => {
+            visitor.visit_expression_mut(&mut m.subject);
+            for arm in &mut m.arms {
+                visitor.visit_expression_mut(&mut arm.body);
+            }
+        },
+        Expression::Try(t) => {
+            visitor.visit_block_statement_mut(&mut t.body);
+            visitor.visit_identifier_mut(&mut t.parameter);
+            visitor.visit_block_statement_mut(&mut t.handler);
+        },
+        Expression::StructLiteral(literal) => {
+            visitor.visit_identifier_mut(&mut literal.name);
+            for (_, value) in &mut literal.fields {
+                visitor.visit_expression_mut(value);
+            }
+        },
+        Expression::Block(block) => visitor.visit_block_statement_mut(block),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use token::{Symbol, Token, TokenType};
+    use crate::{LetStatement, ExpressionStatement, IntegerLiteral, Infix, Call};
+
+    fn ident(name: &str) -> Identifier {
+        Identifier { token: Token::new(TokenType::Identifier, name.to_string()), value: Symbol::intern(name) }
+    }
+
+    // let x = a + f(b, 1); x;
+    fn program() -> Program {
+        let call = Expression::Call(Call{
+            token: Token::new(TokenType::LeftParen, "(".to_string()),
+            function: Box::new(Expression::Identifier(ident("f"))),
+            arguments: vec![
+                Expression::Identifier(ident("b")),
+                Expression::IntegerLiteral(IntegerLiteral{token: Token::new(TokenType::Integer, "1".to_string()), value: 1}),
+            ],
+        });
+        Program {
+            statements: vec![
+                Statement::LetStatement(LetStatement{
+                    token: Token::new(TokenType::Let, "let".to_string()),
+                    name: ident("x"),
+                    annotation: None,
+                    value: Expression::Infix(Infix{
+                        left: Box::new(Expression::Identifier(ident("a"))),
+                        operator: Token::new(TokenType::Plus, "+".to_string()),
+                        right: Box::new(call),
+                    }),
+                    docs: None,
+                }),
+                Statement::ExpressionStatement(ExpressionStatement{
+                    token: Token::new(TokenType::Identifier, "x".to_string()),
+                    expression: Expression::Identifier(ident("x")),
+                    semicolon: true,
+                }),
+            ],
+        }
+    }
+
+    struct Names(Vec<String>);
+
+    impl Visitor for Names {
+        fn visit_identifier(&mut self, identifier: &Identifier) {
+            self.0.push(identifier.value.to_string());
+        }
+    }
+
+    // Counts integer literals without caring about identifiers
+    struct Integers(usize);
+
+    impl Visitor for Integers {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let Expression::IntegerLiteral(_) = expression {
+                self.0 += 1;
+            }
+            walk_expression(self, expression);
+        }
+    }
+
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_identifier_mut(&mut self, identifier: &mut Identifier) {
+            identifier.value = Symbol::intern(&identifier.value.as_str().to_uppercase());
+        }
+    }
+
+    #[test]
+    fn test_visitor() {
+        let mut names = Names(Vec::new());
+        names.visit_program(&program());
+        assert_eq!(names.0, vec!["x", "a", "f", "b", "x"]);
+
+        let mut integers = Integers(0);
+        integers.visit_program(&program());
+        assert_eq!(integers.0, 1);
+    }
+
+    #[test]
+    fn test_visitor_mut() {
+        let mut program = program();
+        Rename.visit_program_mut(&mut program);
+        assert_eq!(program.to_string(), "let X = (A + F(B, 1));X;");
+    }
+}
@@ -0,0 +1,151 @@
+use token::Span;
+
+// Where a closure finds each variable it captures when it is created:
+// a local slot of the enclosing function or one of that function's own captures
+#[derive(Debug, PartialEq, Clone)]
+pub enum Capture {
+    Local(usize),
+    Free(usize),
+}
+
+// A variable an instruction changes where it is
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Variable {
+    Global(usize),
+    Local(usize),
+    Free(usize),
+}
+
+// One step from a variable to the element or member of it an assignment
+// changes: an index taken from the stack, or a member named by a string
+// constant
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Step {
+    Index,
+    Member(usize),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Op {
+    Constant(usize),
+    Pop,
+    Dup,
+    True,
+    False,
+    Null,
+
+    // Operators
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Equal,
+    NotEqual,
+    LessThan,
+    GreaterThan,
+    LessThanOrEqual,
+    GreaterThanOrEqual,
+    Minus,
+    Bang,
+    Increment,
+    Decrement,
+
+    // Control flow, jump targets are instruction indexes
+    Jump(usize),
+    JumpIfFalse(usize),
+    // Keeps the value and jumps unless it is null, in which case it is dropped
+    JumpIfNotNull(usize),
+    IterStart,
+    IterNext(usize),
+
+    // Bindings
+    GetGlobal(usize),
+    SetGlobal(usize),
+    GetLocal(usize),
+    SetLocal(usize),
+    GetFree(usize),
+    SetFree(usize),
+    GetBuiltin(usize),
+    // Pushes what the steps lead to from the variable, leaving the indexes
+    // they take on the stack for the SetPath that follows
+    GetPath { variable: Variable, steps: Vec<Step> },
+    // Puts the value on top at the end of the steps, taking the indexes
+    // below it, and leaves the value
+    SetPath { variable: Variable, steps: Vec<Step> },
+
+    Array(usize),
+    // Builds a range from the start and end on the stack
+    Range { inclusive: bool },
+    // Builds a hash from this many key, value pairs
+    Hash(usize),
+    // Joins this many values into a string, each shown as puts shows it
+    Interpolate(usize),
+    Index,
+    // Reads the member named by a string constant
+    Member(usize),
+    Call(usize),
+    ReturnValue,
+    Return,
+    Closure { constant: usize, captures: Vec<Capture> },
+}
+
+pub type Instructions = Vec<Op>;
+
+// Which source each instruction was compiled from, so errors can point at
+// it and a debugger can find the instructions for a line. Each entry covers
+// the instructions from its index up to the next entry's.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SourceMap {
+    entries: Vec<(usize, Span)>,
+}
+
+impl SourceMap {
+    // Instructions must be added in order
+    pub fn add(&mut self, instruction: usize, span: Span) {
+        if self.entries.last().is_none_or(|(_, last)| *last != span) {
+            self.entries.push((instruction, span));
+        }
+    }
+
+    pub fn span(&self, instruction: usize) -> Option<Span> {
+        let after = self.entries.partition_point(|(index, _)| *index <= instruction);
+        after.checked_sub(1).map(|entry| self.entries[entry].1)
+    }
+
+    // Where each run of instructions compiled from the line starts
+    pub fn line_starts(&self, line: usize) -> Vec<usize> {
+        self.entries.iter().filter(|(_, span)| span.line == line).map(|(index, _)| *index).collect()
+    }
+
+    pub fn entries(&self) -> &[(usize, Span)] {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_map() {
+        let span = |line, column| Span { line, column };
+        let mut map = SourceMap::default();
+        map.add(0, span(1, 1));
+        map.add(1, span(1, 1));
+        map.add(2, span(1, 5));
+        map.add(3, span(1, 5));
+        map.add(4, span(2, 3));
+        map.add(6, span(1, 9));
+
+        assert_eq!(map.entries(), [(0, span(1, 1)), (2, span(1, 5)), (4, span(2, 3)), (6, span(1, 9))]);
+        let spans: Vec<_> = (0..8).map(|instruction| map.span(instruction)).collect();
+        assert_eq!(spans, [
+            Some(span(1, 1)), Some(span(1, 1)), Some(span(1, 5)), Some(span(1, 5)),
+            Some(span(2, 3)), Some(span(2, 3)), Some(span(1, 9)), Some(span(1, 9)),
+        ]);
+        assert_eq!(SourceMap::default().span(0), None);
+        assert_eq!(map.line_starts(1), [0, 2, 6]);
+        assert_eq!(map.line_starts(3), Vec::<usize>::new());
+    }
+}
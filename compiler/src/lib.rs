@@ -0,0 +1,698 @@
+mod serialize;
+mod symbol_table;
+
+use std::fmt;
+use object::shared::Rc;
+use token::{Span, Token, TokenType};
+use ast::{
+    Program, Statement, BlockStatement,
+    ForStatement, ForInStatement,
+    Expression, Assignment, FunctionLiteral, StringPart,
+};
+use code::{Op, Capture, Instructions, SourceMap, Step, Variable};
+use object::{Object, CompiledFunction};
+
+pub use serialize::DecodeError;
+pub use symbol_table::{Symbol, SymbolScope, SymbolTable};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Bytecode {
+    pub instructions: Instructions,
+    pub spans: SourceMap,
+    pub constants: Vec<Object>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CompileError {
+    UndefinedVariable(String),
+    InvalidAssignmentTarget(String),
+    UnsupportedOperator(String),
+    IntegerOutOfRange(String),
+    // Language features only the tree-walking evaluator implements
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::UndefinedVariable(name) => write!(f, "undefined variable {}", name),
+            CompileError::InvalidAssignmentTarget(target) => write!(f, "invalid assignment target: {}", target),
+            CompileError::UnsupportedOperator(operator) => write!(f, "unsupported operator: {}", operator),
+            CompileError::IntegerOutOfRange(literal) => write!(f, "integer literal out of range: {}", literal),
+            CompileError::Unsupported(feature) => write!(f, "{} is not supported by the compiler", feature),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+// The symbol table and constants survive between calls to compile so a
+// REPL can keep feeding the same compiler one line at a time
+pub struct Compiler {
+    constants: Vec<Object>,
+    symbol_table: SymbolTable,
+    // One per function being compiled, innermost last
+    scopes: Vec<Scope>,
+    // Where the code being compiled came from, recorded with each instruction
+    span: Span,
+}
+
+#[derive(Default)]
+struct Scope {
+    instructions: Instructions,
+    spans: SourceMap,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler {
+            constants: Vec::new(),
+            symbol_table: SymbolTable::new(),
+            scopes: vec![Scope::default()],
+            span: Span::default(),
+        }
+    }
+
+    pub fn compile(&mut self, program: &Program) -> Result<Bytecode, CompileError> {
+        self.scopes = vec![Scope::default()];
+        for statement in &program.statements {
+            self.compile_statement(statement)?;
+        }
+
+        // The program evaluates to its last expression; anything else yields null
+        if !matches!(program.statements.last(), None | Some(Statement::ExpressionStatement(_)) | Some(Statement::ReturnStatement(_))) {
+            self.span = program.statements.last().map_or(Span::default(), Statement::span);
+            self.emit(Op::Null);
+            self.emit(Op::Pop);
+        }
+
+        let main = self.scopes.pop().unwrap();
+        Ok(Bytecode {
+            instructions: main.instructions,
+            spans: main.spans,
+            constants: self.constants.clone(),
+        })
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let scope = self.scopes.last_mut().unwrap();
+        scope.spans.add(scope.instructions.len(), self.span);
+        scope.instructions.push(op);
+        scope.instructions.len() - 1
+    }
+
+    fn position(&self) -> usize {
+        self.scopes.last().unwrap().instructions.len()
+    }
+
+    fn patch_jump(&mut self, at: usize, target: usize) {
+        let instructions = &mut self.scopes.last_mut().unwrap().instructions;
+        instructions[at] = match instructions[at] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::JumpIfNotNull(_) => Op::JumpIfNotNull(target),
+            Op::IterNext(_) => Op::IterNext(target),
+            ref other => panic!("cannot patch {:?}", other),
+        };
+    }
+
+    fn add_constant(&mut self, value: Object) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    fn compile_block_statement(&mut self, block: &BlockStatement) -> Result<(), CompileError> {
+        for statement in &block.statements {
+            self.compile_statement(statement)?;
+        }
+        Ok(())
+    }
+
+    // Instructions are attributed to the innermost statement or expression
+    // they were compiled for, the same place the evaluator reports errors at
+    fn compile_statement(&mut self, statement: &Statement) -> Result<(), CompileError> {
+        let outer = std::mem::replace(&mut self.span, statement.span());
+        let result = self.compile_statement_at(statement);
+        self.span = outer;
+        result
+    }
+
+    fn compile_statement_at(&mut self, statement: &Statement) -> Result<(), CompileError> {
+        match statement {
+            Statement::LetStatement(ls) => {
+                // Defined before the value is compiled so functions can call themselves
+                let symbol = self.symbol_table.define(ls.name.value.as_str());
+                self.compile_expression(&ls.value)?;
+                self.emit_set(&symbol);
+            },
+            Statement::ReturnStatement(rs) => {
+                self.compile_expression(&rs.return_value)?;
+                self.emit(Op::ReturnValue);
+            },
+            Statement::ExpressionStatement(es) => {
+                self.compile_expression(&es.expression)?;
+                self.emit(Op::Pop);
+            },
+            Statement::ForStatement(fs) => self.compile_for_statement(fs)?,
+            Statement::ForInStatement(fs) => self.compile_for_in_statement(fs)?,
+            Statement::ImportStatement(_) => return Err(CompileError::Unsupported("import")),
+            Statement::StructStatement(_) => return Err(CompileError::Unsupported("struct")),
+            Statement::ThrowStatement(_) => return Err(CompileError::Unsupported("throw")),
+            Statement::BlockStatement(_) => return Err(CompileError::Unsupported("block")),
+        }
+        Ok(())
+    }
+
+    fn compile_for_statement(&mut self, stmt: &ForStatement) -> Result<(), CompileError> {
+        self.compile_statement(&stmt.init)?;
+
+        let condition = self.position();
+        self.compile_expression(&stmt.condition)?;
+        let exit = self.emit(Op::JumpIfFalse(0));
+
+        self.compile_block_statement(&stmt.body)?;
+        self.compile_expression(&stmt.update)?;
+        self.emit(Op::Pop);
+        self.emit(Op::Jump(condition));
+
+        let end = self.position();
+        self.patch_jump(exit, end);
+        Ok(())
+    }
+
+    fn compile_for_in_statement(&mut self, stmt: &ForInStatement) -> Result<(), CompileError> {
+        self.compile_expression(&stmt.iterable)?;
+        self.emit(Op::IterStart);
+
+        let next = self.emit(Op::IterNext(0));
+        let symbol = self.symbol_table.define(stmt.variable.value.as_str());
+        self.emit_set(&symbol);
+        self.compile_block_statement(&stmt.body)?;
+        self.emit(Op::Jump(next));
+
+        let end = self.position();
+        self.patch_jump(next, end);
+        Ok(())
+    }
+
+    fn compile_expression(&mut self, expression: &Expression) -> Result<(), CompileError> {
+        let outer = std::mem::replace(&mut self.span, expression.span());
+        let result = self.compile_expression_at(expression);
+        self.span = outer;
+        result
+    }
+
+    fn compile_expression_at(&mut self, expression: &Expression) -> Result<(), CompileError> {
+        match expression {
+            Expression::IntegerLiteral(il) => {
+                let constant = self.add_constant(Object::Integer(il.value));
+                self.emit(Op::Constant(constant));
+            },
+            Expression::FloatLiteral(fl) => {
+                let constant = self.add_constant(Object::Float(fl.value));
+                self.emit(Op::Constant(constant));
+            },
+            Expression::BigIntegerLiteral(token) => {
+                let value = eval::big_integer_literal(&token.literal)
+                    .map_err(|_| CompileError::IntegerOutOfRange(token.literal.clone()))?;
+                let constant = self.add_constant(value);
+                self.emit(Op::Constant(constant));
+            },
+            Expression::StringLiteral(token) => {
+                let constant = self.add_constant(Object::String(token::string_contents(&token.literal).to_string()));
+                self.emit(Op::Constant(constant));
+            },
+            Expression::InterpolatedString(string) => {
+                for part in &string.parts {
+                    match part {
+                        StringPart::Text(text) => {
+                            let constant = self.add_constant(Object::String(text.clone()));
+                            self.emit(Op::Constant(constant));
+                        },
+                        StringPart::Expression(expression) => self.compile_expression(expression)?,
+                    }
+                }
+                self.emit(Op::Interpolate(string.parts.len()));
+            },
+            Expression::CharLiteral(c) => {
+                let constant = self.add_constant(Object::Char(c.value));
+                self.emit(Op::Constant(constant));
+            },
+            Expression::BooleanLiteral(token) => {
+                self.emit(if token.ttype == TokenType::True { Op::True } else { Op::False });
+            },
+            Expression::NullLiteral(_) => {
+                self.emit(Op::Null);
+            },
+            Expression::Identifier(ident) => {
+                let symbol = self.resolve(ident.value.as_str())?;
+                self.emit_get(&symbol);
+            },
+            Expression::Prefix(prefix) if matches!(prefix.operator.ttype, TokenType::Increment | TokenType::Decrement) => {
+                self.compile_step(&prefix.right, &prefix.operator, true)?;
+            },
+            Expression::Prefix(prefix) => {
+                self.compile_expression(&prefix.right)?;
+                match prefix.operator.ttype {
+                    TokenType::Bang => self.emit(Op::Bang),
+                    TokenType::Minus => self.emit(Op::Minus),
+                    _ => return Err(CompileError::UnsupportedOperator(prefix.operator.literal.clone())),
+                };
+            },
+            Expression::Infix(infix) if infix.operator.ttype == TokenType::NullCoalesce => {
+                self.compile_expression(&infix.left)?;
+                let end = self.emit(Op::JumpIfNotNull(0));
+                self.compile_expression(&infix.right)?;
+                let position = self.position();
+                self.patch_jump(end, position);
+            },
+            Expression::Infix(infix) => {
+                self.compile_expression(&infix.left)?;
+                self.compile_expression(&infix.right)?;
+                self.emit(infix_op(&infix.operator.ttype, &infix.operator.literal)?);
+            },
+            Expression::Postfix(postfix) => self.compile_step(&postfix.left, &postfix.operator, false)?,
+            Expression::Assignment(assignment) => self.compile_assignment_expression(assignment)?,
+            Expression::ArrayLiteral(array) => {
+                for element in &array.elements {
+                    self.compile_expression(element)?;
+                }
+                self.emit(Op::Array(array.elements.len()));
+            },
+            Expression::RangeLiteral(range) => {
+                self.compile_expression(&range.start)?;
+                self.compile_expression(&range.end)?;
+                self.emit(Op::Range { inclusive: range.inclusive() });
+            },
+            Expression::HashLiteral(hash) => {
+                for (key, value) in &hash.pairs {
+                    self.compile_expression(key)?;
+                    self.compile_expression(value)?;
+                }
+                self.emit(Op::Hash(hash.pairs.len()));
+            },
+            Expression::FunctionLiteral(func) => self.compile_function_literal(func)?,
+            Expression::Call(call) => {
+                self.compile_expression(&call.function)?;
+                for argument in &call.arguments {
+                    self.compile_expression(argument)?;
+                }
+                self.emit(Op::Call(call.arguments.len()));
+            },
+            Expression::Ternary(ternary) => {
+                self.compile_expression(&ternary.condition)?;
+                let if_false = self.emit(Op::JumpIfFalse(0));
+                self.compile_expression(&ternary.if_true)?;
+                let end = self.emit(Op::Jump(0));
+
+                let position = self.position();
+                self.patch_jump(if_false, position);
+                self.compile_expression(&ternary.if_false)?;
+                let position = self.position();
+                self.patch_jump(end, position);
+            },
+            Expression::Index(index) => {
+                self.compile_expression(&index.left)?;
+                self.compile_expression(&index.index)?;
+                self.emit(Op::Index);
+            },
+            Expression::Member(member) => {
+                self.compile_expression(&member.object)?;
+                let name = self.add_constant(Object::String(member.property.value.to_string()));
+                self.emit(Op::Member(name));
+            },
+            Expression::MethodCall(_) => return Err(CompileError::Unsupported("method call")),
+            Expression::Import(_) => return Err(CompileError::Unsupported("import")),
+            Expression::Match(_) => return Err(CompileError::Unsupported("match")),
+            Expression::Try(_) => return Err(CompileError::Unsupported("try")),
+            Expression::Propagate(_) => return Err(CompileError::Unsupported("?")),
+            Expression::StructLiteral(_) => return Err(CompileError::Unsupported("struct")),
+            Expression::Block(_) => return Err(CompileError::Unsupported("block")),
+        }
+        Ok(())
+    }
+
+    fn compile_assignment_expression(&mut self, assignment: &Assignment) -> Result<(), CompileError> {
+        if !matches!(*assignment.target, Expression::Identifier(_)) {
+            return self.compile_path_assignment(assignment);
+        }
+        let symbol = self.resolve_target(&assignment.target)?;
+        match compound_op(&assignment.token.ttype) {
+            Some(op) => {
+                self.emit_get(&symbol);
+                self.compile_expression(&assignment.value)?;
+                self.emit(op);
+            },
+            None => self.compile_expression(&assignment.value)?,
+        }
+        self.emit(Op::Dup);
+        self.emit_set(&symbol);
+        Ok(())
+    }
+
+    // Assigns into an element or member of a variable, which is changed
+    // where it is. Indexes in the target are evaluated first, left to right.
+    fn compile_path_assignment(&mut self, assignment: &Assignment) -> Result<(), CompileError> {
+        let mut target: &Expression = &assignment.target;
+        let mut places = Vec::new();
+        let root = loop {
+            match target {
+                Expression::Identifier(_) => break target,
+                Expression::Index(index) => {
+                    places.push(target);
+                    target = &index.left;
+                },
+                Expression::Member(member) => {
+                    places.push(target);
+                    target = &member.object;
+                },
+                other => return Err(CompileError::InvalidAssignmentTarget(other.to_string())),
+            }
+        };
+        let symbol = self.resolve_target(root)?;
+        let variable = match symbol.scope {
+            SymbolScope::Global => Variable::Global(symbol.index),
+            SymbolScope::Local => Variable::Local(symbol.index),
+            SymbolScope::Free => Variable::Free(symbol.index),
+            SymbolScope::Builtin => unreachable!("builtins cannot be assigned"),
+        };
+
+        let mut steps = Vec::new();
+        for place in places.into_iter().rev() {
+            match place {
+                Expression::Index(index) => {
+                    self.compile_expression(&index.index)?;
+                    steps.push(Step::Index);
+                },
+                Expression::Member(member) => {
+                    let name = self.add_constant(Object::String(member.property.value.to_string()));
+                    steps.push(Step::Member(name));
+                },
+                _ => unreachable!("only indexes and members are collected"),
+            }
+        }
+        match compound_op(&assignment.token.ttype) {
+            Some(op) => {
+                self.emit(Op::GetPath { variable, steps: steps.clone() });
+                self.compile_expression(&assignment.value)?;
+                self.emit(op);
+            },
+            None => self.compile_expression(&assignment.value)?,
+        }
+        self.emit(Op::SetPath { variable, steps });
+        Ok(())
+    }
+
+    // Leaves the previous value on the stack, like the evaluator
+    // The copy left on the stack is taken after the step for ++x and before it for x++
+    fn compile_step(&mut self, target: &Expression, operator: &Token, prefix: bool) -> Result<(), CompileError> {
+        let symbol = self.resolve_target(target)?;
+        self.emit_get(&symbol);
+        if !prefix {
+            self.emit(Op::Dup);
+        }
+        match operator.ttype {
+            TokenType::Increment => self.emit(Op::Increment),
+            TokenType::Decrement => self.emit(Op::Decrement),
+            _ => return Err(CompileError::UnsupportedOperator(operator.literal.clone())),
+        };
+        if prefix {
+            self.emit(Op::Dup);
+        }
+        self.emit_set(&symbol);
+        Ok(())
+    }
+
+    fn compile_function_literal(&mut self, func: &FunctionLiteral) -> Result<(), CompileError> {
+        self.symbol_table.enter_scope();
+        self.scopes.push(Scope::default());
+        for parameter in &func.parameters {
+            self.symbol_table.define(parameter.value.as_str());
+        }
+
+        let body = self.compile_block_statement(&func.body);
+        let Scope { mut instructions, mut spans } = self.scopes.pop().unwrap();
+        let (num_locals, free) = self.symbol_table.leave_scope();
+        body?;
+
+        // A trailing expression is the implicit result of the call
+        if func.body.value().is_some() {
+            instructions.pop();
+            instructions.push(Op::ReturnValue);
+        } else {
+            spans.add(instructions.len(), self.span);
+            instructions.push(Op::Return);
+        }
+
+        let captures = free.iter().map(|symbol| match symbol.scope {
+            SymbolScope::Local => Capture::Local(symbol.index),
+            SymbolScope::Free => Capture::Free(symbol.index),
+            _ => unreachable!("globals and builtins are never captured"),
+        }).collect();
+
+        let constant = self.add_constant(Object::CompiledFunction(Rc::new(CompiledFunction{
+            instructions,
+            spans,
+            num_locals,
+            num_parameters: func.parameters.len(),
+        })));
+        self.emit(Op::Closure { constant, captures });
+        Ok(())
+    }
+
+    fn resolve(&mut self, name: &str) -> Result<Symbol, CompileError> {
+        self.symbol_table.resolve(name).ok_or_else(|| CompileError::UndefinedVariable(name.to_string()))
+    }
+
+    fn resolve_target(&mut self, target: &Expression) -> Result<Symbol, CompileError> {
+        match target {
+            Expression::Identifier(ident) => match self.resolve(ident.value.as_str())? {
+                symbol if symbol.scope == SymbolScope::Builtin => Err(CompileError::InvalidAssignmentTarget(ident.value.to_string())),
+                symbol => Ok(symbol),
+            },
+            other => Err(CompileError::InvalidAssignmentTarget(other.to_string())),
+        }
+    }
+
+    fn emit_get(&mut self, symbol: &Symbol) {
+        self.emit(match symbol.scope {
+            SymbolScope::Global => Op::GetGlobal(symbol.index),
+            SymbolScope::Local => Op::GetLocal(symbol.index),
+            SymbolScope::Free => Op::GetFree(symbol.index),
+            SymbolScope::Builtin => Op::GetBuiltin(symbol.index),
+        });
+    }
+
+    // Set instructions consume the value on top of the stack
+    fn emit_set(&mut self, symbol: &Symbol) {
+        self.emit(match symbol.scope {
+            SymbolScope::Global => Op::SetGlobal(symbol.index),
+            SymbolScope::Local => Op::SetLocal(symbol.index),
+            SymbolScope::Free => Op::SetFree(symbol.index),
+            SymbolScope::Builtin => unreachable!("builtins cannot be assigned"),
+        });
+    }
+}
+
+fn infix_op(operator: &TokenType, literal: &str) -> Result<Op, CompileError> {
+    let op = match operator {
+        TokenType::Plus => Op::Add,
+        TokenType::Minus => Op::Sub,
+        TokenType::Asterisk => Op::Mul,
+        TokenType::Slash => Op::Div,
+        TokenType::Percent => Op::Mod,
+        TokenType::Equal => Op::Equal,
+        TokenType::NotEqual => Op::NotEqual,
+        TokenType::LessThan => Op::LessThan,
+        TokenType::GreaterThan => Op::GreaterThan,
+        TokenType::LessThanOrEqual => Op::LessThanOrEqual,
+        TokenType::GreaterThanOrEqual => Op::GreaterThanOrEqual,
+        _ => return Err(CompileError::UnsupportedOperator(literal.to_string())),
+    };
+    Ok(op)
+}
+
+fn compound_op(operator: &TokenType) -> Option<Op> {
+    let (operator, literal) = eval::compound_operator(operator)?;
+    infix_op(&operator, literal).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn compile(input: &str) -> Result<Bytecode, CompileError> {
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program().unwrap();
+        Compiler::new().compile(&program)
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        let tests = vec![
+            ("1 + 2", vec![Op::Constant(0), Op::Constant(1), Op::Add, Op::Pop]),
+            ("1; 2", vec![Op::Constant(0), Op::Pop, Op::Constant(1), Op::Pop]),
+            ("-1", vec![Op::Constant(0), Op::Minus, Op::Pop]),
+            ("1 % 2", vec![Op::Constant(0), Op::Constant(1), Op::Mod, Op::Pop]),
+            ("!true", vec![Op::True, Op::Bang, Op::Pop]),
+        ];
+
+        for (input, expected) in tests {
+            let bytecode = compile(input).unwrap();
+            assert_eq!(bytecode.instructions, expected, "{}", input);
+        }
+        assert_eq!(compile("1 + 2").unwrap().constants, vec![Object::Integer(1), Object::Integer(2)]);
+    }
+
+    #[test]
+    fn test_bindings() {
+        let tests = vec![
+            ("let x = 1;", vec![Op::Constant(0), Op::SetGlobal(0), Op::Null, Op::Pop]),
+            ("let x = 1; x", vec![Op::Constant(0), Op::SetGlobal(0), Op::GetGlobal(0), Op::Pop]),
+            ("let x = 1; x = 2", vec![
+                Op::Constant(0), Op::SetGlobal(0),
+                Op::Constant(1), Op::Dup, Op::SetGlobal(0), Op::Pop,
+            ]),
+            ("let x = 1; x += 2", vec![
+                Op::Constant(0), Op::SetGlobal(0),
+                Op::GetGlobal(0), Op::Constant(1), Op::Add, Op::Dup, Op::SetGlobal(0), Op::Pop,
+            ]),
+            ("let x = 1; x++", vec![
+                Op::Constant(0), Op::SetGlobal(0),
+                Op::GetGlobal(0), Op::Dup, Op::Increment, Op::SetGlobal(0), Op::Pop,
+            ]),
+            ("let x = 1; --x", vec![
+                Op::Constant(0), Op::SetGlobal(0),
+                Op::GetGlobal(0), Op::Decrement, Op::Dup, Op::SetGlobal(0), Op::Pop,
+            ]),
+            ("let a = [1]; a[0] += 2", vec![
+                Op::Constant(0), Op::Array(1), Op::SetGlobal(0),
+                Op::Constant(1),
+                Op::GetPath { variable: Variable::Global(0), steps: vec![Step::Index] },
+                Op::Constant(2), Op::Add,
+                Op::SetPath { variable: Variable::Global(0), steps: vec![Step::Index] }, Op::Pop,
+            ]),
+            ("let h = {}; h[\"k\"] = 1", vec![
+                Op::Hash(0), Op::SetGlobal(0),
+                Op::Constant(0), Op::Constant(1),
+                Op::SetPath { variable: Variable::Global(0), steps: vec![Step::Index] }, Op::Pop,
+            ]),
+            ("len", vec![Op::GetBuiltin(0), Op::Pop]),
+            ("1..=3", vec![Op::Constant(0), Op::Constant(1), Op::Range { inclusive: true }, Op::Pop]),
+        ];
+
+        for (input, expected) in tests {
+            let bytecode = compile(input).unwrap();
+            assert_eq!(bytecode.instructions, expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_loops() {
+        let tests = vec![
+            ("for (let i = 0; i < 1; i++) { i }", vec![
+                Op::Constant(0), Op::SetGlobal(0),
+                Op::GetGlobal(0), Op::Constant(1), Op::LessThan, Op::JumpIfFalse(14),
+                Op::GetGlobal(0), Op::Pop,
+                Op::GetGlobal(0), Op::Dup, Op::Increment, Op::SetGlobal(0), Op::Pop,
+                Op::Jump(2),
+                Op::Null, Op::Pop,
+            ]),
+            ("for (x in [1]) { x }", vec![
+                Op::Constant(0), Op::Array(1), Op::IterStart,
+                Op::IterNext(8), Op::SetGlobal(0), Op::GetGlobal(0), Op::Pop, Op::Jump(3),
+                Op::Null, Op::Pop,
+            ]),
+        ];
+
+        for (input, expected) in tests {
+            let bytecode = compile(input).unwrap();
+            assert_eq!(bytecode.instructions, expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_functions() {
+        let bytecode = compile("fn(a) { let b = a; fn() { a + b } }").unwrap();
+        assert_eq!(bytecode.instructions, vec![Op::Closure { constant: 1, captures: vec![]}, Op::Pop]);
+
+        let inner = match &bytecode.constants[0] {
+            Object::CompiledFunction(func) => func,
+            other => panic!("expected a compiled function, got {:?}", other),
+        };
+        assert_eq!(inner.instructions, vec![Op::GetFree(0), Op::GetFree(1), Op::Add, Op::ReturnValue]);
+        assert_eq!(inner.num_locals, 0);
+
+        let outer = match &bytecode.constants[1] {
+            Object::CompiledFunction(func) => func,
+            other => panic!("expected a compiled function, got {:?}", other),
+        };
+        assert_eq!(outer.instructions, vec![
+            Op::GetLocal(0), Op::SetLocal(1),
+            Op::Closure { constant: 0, captures: vec![Capture::Local(0), Capture::Local(1)] },
+            Op::ReturnValue,
+        ]);
+        assert_eq!(outer.num_locals, 2);
+        assert_eq!(outer.num_parameters, 1);
+
+        let bytecode = compile("fn() { }").unwrap();
+        match &bytecode.constants[0] {
+            Object::CompiledFunction(func) => assert_eq!(func.instructions, vec![Op::Return]),
+            other => panic!("expected a compiled function, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_source_map() {
+        let bytecode = compile("let x = 1;\nx + 2;\nlet f = fn() {\n    x\n};").unwrap();
+        let span = |line, column| Some(Span { line, column });
+        let spans: Vec<_> = (0..bytecode.instructions.len()).map(|i| bytecode.spans.span(i)).collect();
+        assert_eq!(spans, [
+            span(1, 9), span(1, 1),
+            span(2, 1), span(2, 5), span(2, 3), span(2, 1),
+            span(3, 9), span(3, 1),
+            span(3, 1), span(3, 1),
+        ]);
+        assert_eq!(bytecode.spans.line_starts(2), [2, 3, 4, 5]);
+
+        let function = match &bytecode.constants[2] {
+            Object::CompiledFunction(func) => func,
+            other => panic!("expected a compiled function, got {:?}", other),
+        };
+        assert_eq!(function.spans.span(0), span(4, 5));
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let tests = vec![
+            ("x", CompileError::UndefinedVariable("x".to_string())),
+            ("x = 1", CompileError::UndefinedVariable("x".to_string())),
+            ("len = 1", CompileError::InvalidAssignmentTarget("len".to_string())),
+            ("++len", CompileError::InvalidAssignmentTarget("len".to_string())),
+            ("import \"math\"", CompileError::Unsupported("import")),
+            ("let m = import(\"math\"); m.pi", CompileError::Unsupported("import")),
+            ("match 1 { _ => 2 }", CompileError::Unsupported("match")),
+            ("struct P { x }", CompileError::Unsupported("struct")),
+            ("try { 1 } catch (e) { 2 }", CompileError::Unsupported("try")),
+            ("f()?", CompileError::Unsupported("?")),
+            ("throw 1", CompileError::Unsupported("throw")),
+            ("[1].len()", CompileError::Unsupported("method call")),
+        ];
+
+        for (input, expected) in tests {
+            match compile(input) {
+                Err(err) => assert_eq!(err, expected, "{}", input),
+                Ok(bytecode) => panic!("expected an error for {}, got {:?}", input, bytecode.instructions),
+            }
+        }
+    }
+}
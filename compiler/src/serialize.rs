@@ -0,0 +1,488 @@
+use std::fmt;
+use object::shared::Rc;
+use token::Span;
+use code::{Op, Capture, Instructions, SourceMap, Step, Variable};
+use object::{Object, CompiledFunction};
+use crate::Bytecode;
+
+// Every file starts with the magic bytes and the format version. Numbers
+// after that are LEB128 varints, signed ones zigzag encoded first.
+pub const MAGIC: &[u8; 4] = b"MKYC";
+pub const VERSION: u8 = 2;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum DecodeError {
+    NotBytecode,
+    UnsupportedVersion(u8),
+    UnexpectedEnd,
+    InvalidTag { what: &'static str, tag: u8 },
+    InvalidString,
+    InvalidChar(u32),
+    IntegerOutOfRange(String),
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::NotBytecode => write!(f, "not a compiled program"),
+            DecodeError::UnsupportedVersion(version) => write!(f, "unsupported bytecode version {}, expected {}", version, VERSION),
+            DecodeError::UnexpectedEnd => write!(f, "bytecode ends unexpectedly"),
+            DecodeError::InvalidTag { what, tag } => write!(f, "invalid {} tag {}", what, tag),
+            DecodeError::InvalidString => write!(f, "string constant is not valid UTF-8"),
+            DecodeError::InvalidChar(code) => write!(f, "invalid char constant {:#x}", code),
+            DecodeError::IntegerOutOfRange(literal) => write!(f, "integer constant out of range: {}", literal),
+            DecodeError::TrailingBytes(count) => write!(f, "{} unexpected bytes after the program", count),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Bytecode {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut writer = Writer(MAGIC.to_vec());
+        writer.0.push(VERSION);
+        writer.unsigned(self.constants.len());
+        for constant in &self.constants {
+            writer.constant(constant);
+        }
+        writer.instructions(&self.instructions);
+        writer.spans(&self.spans);
+        writer.0
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Bytecode, DecodeError> {
+        let header = bytes.get(..MAGIC.len() + 1).filter(|header| header.starts_with(MAGIC)).ok_or(DecodeError::NotBytecode)?;
+        if header[MAGIC.len()] != VERSION {
+            return Err(DecodeError::UnsupportedVersion(header[MAGIC.len()]));
+        }
+
+        let mut reader = Reader { bytes, position: header.len() };
+        let count = reader.unsigned()?;
+        let constants = (0..count).map(|_| reader.constant()).collect::<Result<_, _>>()?;
+        let instructions = reader.instructions()?;
+        let spans = reader.spans()?;
+        match bytes.len() - reader.position {
+            0 => Ok(Bytecode { instructions, spans, constants }),
+            count => Err(DecodeError::TrailingBytes(count)),
+        }
+    }
+
+    // Whether the bytes look like the output of serialize, so callers can
+    // tell a compiled program from source
+    pub fn is_serialized(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+}
+
+// Constant tags
+const INTEGER: u8 = 0;
+const BIG_INTEGER: u8 = 1;
+const STRING: u8 = 2;
+const CHAR: u8 = 3;
+const FUNCTION: u8 = 4;
+const FLOAT: u8 = 5;
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn unsigned(&mut self, value: usize) {
+        let mut value = value as u64;
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn signed(&mut self, value: i64) {
+        self.unsigned(((value << 1) ^ (value >> 63)) as usize);
+    }
+
+    fn string(&mut self, value: &str) {
+        self.unsigned(value.len());
+        self.0.extend_from_slice(value.as_bytes());
+    }
+
+    // Only the kinds of objects the compiler makes constants of
+    fn constant(&mut self, constant: &Object) {
+        match constant {
+            Object::Integer(value) => {
+                self.0.push(INTEGER);
+                self.signed(*value);
+            },
+            // Only eval's bigint feature makes these, written out in decimal
+            other if other.type_name() == "INTEGER" => {
+                self.0.push(BIG_INTEGER);
+                self.string(&other.inspect());
+            },
+            // The bits as they are, little-endian, so NaN payloads and -0.0 survive
+            Object::Float(value) => {
+                self.0.push(FLOAT);
+                self.0.extend_from_slice(&value.to_bits().to_le_bytes());
+            },
+            Object::String(value) => {
+                self.0.push(STRING);
+                self.string(value);
+            },
+            Object::Char(value) => {
+                self.0.push(CHAR);
+                self.unsigned(*value as usize);
+            },
+            Object::CompiledFunction(function) => {
+                self.0.push(FUNCTION);
+                self.unsigned(function.num_locals);
+                self.unsigned(function.num_parameters);
+                self.instructions(&function.instructions);
+                self.spans(&function.spans);
+            },
+            other => panic!("the compiler does not make constants of {}", other.type_name()),
+        }
+    }
+
+    fn instructions(&mut self, instructions: &Instructions) {
+        self.unsigned(instructions.len());
+        for op in instructions {
+            self.op(op);
+        }
+    }
+
+    fn spans(&mut self, spans: &SourceMap) {
+        self.unsigned(spans.entries().len());
+        for (instruction, span) in spans.entries() {
+            self.unsigned(*instruction);
+            self.unsigned(span.line);
+            self.unsigned(span.column);
+        }
+    }
+
+    // Which kind of variable it is goes in the lowest two bits of its
+    // number, and whether a step is a member in the lowest bit of its own
+    fn path(&mut self, tag: u8, variable: Variable, steps: &[Step]) {
+        self.0.push(tag);
+        self.unsigned(match variable {
+            Variable::Global(index) => index << 2,
+            Variable::Local(index) => index << 2 | 1,
+            Variable::Free(index) => index << 2 | 2,
+        });
+        self.unsigned(steps.len());
+        for step in steps {
+            self.unsigned(match step {
+                Step::Index => 0,
+                Step::Member(constant) => constant << 1 | 1,
+            });
+        }
+    }
+
+    fn op(&mut self, op: &Op) {
+        let (tag, operand) = match op {
+            Op::Constant(index) => (0, Some(*index)),
+            Op::Pop => (1, None),
+            Op::Dup => (2, None),
+            Op::True => (3, None),
+            Op::False => (4, None),
+            Op::Null => (5, None),
+            Op::Add => (6, None),
+            Op::Sub => (7, None),
+            Op::Mul => (8, None),
+            Op::Div => (9, None),
+            Op::Mod => (10, None),
+            Op::Equal => (11, None),
+            Op::NotEqual => (12, None),
+            Op::LessThan => (13, None),
+            Op::GreaterThan => (14, None),
+            Op::LessThanOrEqual => (15, None),
+            Op::GreaterThanOrEqual => (16, None),
+            Op::Minus => (17, None),
+            Op::Bang => (18, None),
+            Op::Increment => (19, None),
+            Op::Decrement => (20, None),
+            Op::Jump(target) => (21, Some(*target)),
+            Op::JumpIfFalse(target) => (22, Some(*target)),
+            Op::JumpIfNotNull(target) => (23, Some(*target)),
+            Op::IterStart => (24, None),
+            Op::IterNext(target) => (25, Some(*target)),
+            Op::GetGlobal(index) => (26, Some(*index)),
+            Op::SetGlobal(index) => (27, Some(*index)),
+            Op::GetLocal(index) => (28, Some(*index)),
+            Op::SetLocal(index) => (29, Some(*index)),
+            Op::GetFree(index) => (30, Some(*index)),
+            Op::SetFree(index) => (31, Some(*index)),
+            Op::GetBuiltin(index) => (32, Some(*index)),
+            Op::Array(count) => (33, Some(*count)),
+            Op::Range { inclusive } => (34, Some(*inclusive as usize)),
+            Op::Hash(count) => (35, Some(*count)),
+            Op::Interpolate(count) => (36, Some(*count)),
+            Op::Index => (37, None),
+            Op::Call(count) => (38, Some(*count)),
+            Op::ReturnValue => (39, None),
+            Op::Return => (40, None),
+            Op::Closure { constant, captures } => {
+                self.0.push(41);
+                self.unsigned(*constant);
+                self.unsigned(captures.len());
+                // Locals and captures of the enclosing function share one
+                // number, told apart by the lowest bit
+                for capture in captures {
+                    self.unsigned(match capture {
+                        Capture::Local(index) => index << 1,
+                        Capture::Free(index) => index << 1 | 1,
+                    });
+                }
+                return;
+            },
+            Op::GetPath { variable, steps } => return self.path(42, *variable, steps),
+            Op::SetPath { variable, steps } => return self.path(43, *variable, steps),
+            Op::Member(constant) => (44, Some(*constant)),
+        };
+        self.0.push(tag);
+        if let Some(operand) = operand {
+            self.unsigned(operand);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self.bytes.get(self.position).ok_or(DecodeError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn unsigned(&mut self) -> Result<usize, DecodeError> {
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value as usize);
+            }
+        }
+    }
+
+    fn signed(&mut self) -> Result<i64, DecodeError> {
+        let value = self.unsigned()? as u64;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let len = self.unsigned()?;
+        let end = self.position.checked_add(len).filter(|&end| end <= self.bytes.len()).ok_or(DecodeError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidString)
+    }
+
+    fn constant(&mut self) -> Result<Object, DecodeError> {
+        let constant = match self.byte()? {
+            INTEGER => Object::Integer(self.signed()?),
+            BIG_INTEGER => {
+                let literal = self.string()?;
+                eval::big_integer_literal(&literal).map_err(|_| DecodeError::IntegerOutOfRange(literal))?
+            },
+            FLOAT => {
+                let end = self.position.checked_add(8).filter(|&end| end <= self.bytes.len()).ok_or(DecodeError::UnexpectedEnd)?;
+                let bits = u64::from_le_bytes(self.bytes[self.position..end].try_into().unwrap());
+                self.position = end;
+                Object::Float(f64::from_bits(bits))
+            },
+            STRING => Object::String(self.string()?),
+            CHAR => {
+                let code = self.unsigned()? as u32;
+                Object::Char(char::from_u32(code).ok_or(DecodeError::InvalidChar(code))?)
+            },
+            FUNCTION => {
+                let num_locals = self.unsigned()?;
+                let num_parameters = self.unsigned()?;
+                let instructions = self.instructions()?;
+                let spans = self.spans()?;
+                Object::CompiledFunction(Rc::new(CompiledFunction { instructions, spans, num_locals, num_parameters }))
+            },
+            tag => return Err(DecodeError::InvalidTag { what: "constant", tag }),
+        };
+        Ok(constant)
+    }
+
+    fn instructions(&mut self) -> Result<Instructions, DecodeError> {
+        let count = self.unsigned()?;
+        // Not trusting the count with the allocation, as each op is at least a byte
+        let mut instructions = Vec::with_capacity(count.min(self.bytes.len() - self.position));
+        for _ in 0..count {
+            instructions.push(self.op()?);
+        }
+        Ok(instructions)
+    }
+
+    fn spans(&mut self) -> Result<SourceMap, DecodeError> {
+        let mut spans = SourceMap::default();
+        for _ in 0..self.unsigned()? {
+            let instruction = self.unsigned()?;
+            spans.add(instruction, Span { line: self.unsigned()?, column: self.unsigned()? });
+        }
+        Ok(spans)
+    }
+
+    fn path(&mut self) -> Result<(Variable, Vec<Step>), DecodeError> {
+        let variable = self.unsigned()?;
+        let variable = match variable & 3 {
+            0 => Variable::Global(variable >> 2),
+            1 => Variable::Local(variable >> 2),
+            2 => Variable::Free(variable >> 2),
+            kind => return Err(DecodeError::InvalidTag { what: "variable", tag: kind as u8 }),
+        };
+        let count = self.unsigned()?;
+        let steps = (0..count)
+            .map(|_| self.unsigned().map(|step| match step & 1 {
+                0 => Step::Index,
+                _ => Step::Member(step >> 1),
+            }))
+            .collect::<Result<_, _>>()?;
+        Ok((variable, steps))
+    }
+
+    fn op(&mut self) -> Result<Op, DecodeError> {
+        let op = match self.byte()? {
+            0 => Op::Constant(self.unsigned()?),
+            1 => Op::Pop,
+            2 => Op::Dup,
+            3 => Op::True,
+            4 => Op::False,
+            5 => Op::Null,
+            6 => Op::Add,
+            7 => Op::Sub,
+            8 => Op::Mul,
+            9 => Op::Div,
+            10 => Op::Mod,
+            11 => Op::Equal,
+            12 => Op::NotEqual,
+            13 => Op::LessThan,
+            14 => Op::GreaterThan,
+            15 => Op::LessThanOrEqual,
+            16 => Op::GreaterThanOrEqual,
+            17 => Op::Minus,
+            18 => Op::Bang,
+            19 => Op::Increment,
+            20 => Op::Decrement,
+            21 => Op::Jump(self.unsigned()?),
+            22 => Op::JumpIfFalse(self.unsigned()?),
+            23 => Op::JumpIfNotNull(self.unsigned()?),
+            24 => Op::IterStart,
+            25 => Op::IterNext(self.unsigned()?),
+            26 => Op::GetGlobal(self.unsigned()?),
+            27 => Op::SetGlobal(self.unsigned()?),
+            28 => Op::GetLocal(self.unsigned()?),
+            29 => Op::SetLocal(self.unsigned()?),
+            30 => Op::GetFree(self.unsigned()?),
+            31 => Op::SetFree(self.unsigned()?),
+            32 => Op::GetBuiltin(self.unsigned()?),
+            33 => Op::Array(self.unsigned()?),
+            34 => Op::Range { inclusive: self.unsigned()? != 0 },
+            35 => Op::Hash(self.unsigned()?),
+            36 => Op::Interpolate(self.unsigned()?),
+            37 => Op::Index,
+            38 => Op::Call(self.unsigned()?),
+            39 => Op::ReturnValue,
+            40 => Op::Return,
+            41 => {
+                let constant = self.unsigned()?;
+                let count = self.unsigned()?;
+                let captures = (0..count)
+                    .map(|_| self.unsigned().map(|index| match index & 1 {
+                        0 => Capture::Local(index >> 1),
+                        _ => Capture::Free(index >> 1),
+                    }))
+                    .collect::<Result<_, _>>()?;
+                Op::Closure { constant, captures }
+            },
+            42 => {
+                let (variable, steps) = self.path()?;
+                Op::GetPath { variable, steps }
+            },
+            43 => {
+                let (variable, steps) = self.path()?;
+                Op::SetPath { variable, steps }
+            },
+            44 => Op::Member(self.unsigned()?),
+            tag => return Err(DecodeError::InvalidTag { what: "instruction", tag }),
+        };
+        Ok(op)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+    use crate::Compiler;
+
+    fn compile(input: &str) -> Bytecode {
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        Compiler::new().compile(&program).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let inputs = vec![
+            "1 + 2",
+            "1.5 * -0.25",
+            "let x = -4611686018427387904; x * 2 - 1",
+            "let s = \"héllo\"; \"${s} ${'é'}\"",
+            "let add = fn(a) { fn(b) { let c = a + b; fn() { c + a } } }; add(1)(2)()",
+            "for (x in 0..=3) { x ?? 1 } {\"a\": [1, 2][0]}",
+            "let h = {\"a\": [1]}; let f = fn() { let b = [h]; fn() { b[0].a[0] += 1; h.a[0] = 2 } }; f()()",
+        ];
+
+        for input in inputs {
+            let bytecode = compile(input);
+            let bytes = bytecode.serialize();
+            assert!(Bytecode::is_serialized(&bytes), "{}", input);
+            let decoded = Bytecode::deserialize(&bytes).unwrap();
+            assert_eq!(decoded.instructions, bytecode.instructions, "{}", input);
+            assert_eq!(decoded.spans, bytecode.spans, "{}", input);
+            // Compiled functions only compare equal to themselves as objects
+            let constants = |bytecode: &Bytecode| bytecode.constants.iter()
+                .map(|constant| match constant {
+                    Object::CompiledFunction(function) => format!("{:?}", function),
+                    other => format!("{:?}", other),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(constants(&decoded), constants(&bytecode), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let bytes = compile("fn(x) { x }").serialize();
+        let mut newer = bytes.clone();
+        newer[MAGIC.len()] = VERSION + 1;
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+
+        let tests = vec![
+            (b"let x = 1;".to_vec(), DecodeError::NotBytecode),
+            (b"MKY".to_vec(), DecodeError::NotBytecode),
+            (newer, DecodeError::UnsupportedVersion(VERSION + 1)),
+            (bytes[..bytes.len() - 1].to_vec(), DecodeError::UnexpectedEnd),
+            (trailing, DecodeError::TrailingBytes(1)),
+            (b"MKYC\x02\x01\x09".to_vec(), DecodeError::InvalidTag { what: "constant", tag: 9 }),
+            (b"MKYC\x02\x00\x01\xff".to_vec(), DecodeError::InvalidTag { what: "instruction", tag: 255 }),
+            (b"MKYC\x02\x01\x02\x01\xff\x00".to_vec(), DecodeError::InvalidString),
+            (b"MKYC\x02\x01\x03\x80\xb0\x03\x00".to_vec(), DecodeError::InvalidChar(0xd800)),
+        ];
+
+        for (bytes, expected) in tests {
+            assert_eq!(Bytecode::deserialize(&bytes), Err(expected), "{:?}", bytes);
+        }
+    }
+}
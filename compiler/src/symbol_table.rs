@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SymbolScope {
+    Global,
+    Local,
+    Free,
+    Builtin,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub scope: SymbolScope,
+    pub index: usize,
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    store: HashMap<String, Symbol>,
+    num_definitions: usize,
+    // Symbols captured from the enclosing scope, as seen from that scope
+    free: Vec<Symbol>,
+}
+
+// One scope per function being compiled, the first one holding the globals
+#[derive(Debug)]
+pub struct SymbolTable {
+    scopes: Vec<Scope>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        SymbolTable::new()
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable { scopes: vec![Scope::default()] }
+    }
+
+    pub fn enter_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    // Returns the number of locals and the captured symbols of the scope left
+    pub fn leave_scope(&mut self) -> (usize, Vec<Symbol>) {
+        let scope = self.scopes.pop().expect("cannot leave the global scope");
+        (scope.num_definitions, scope.free)
+    }
+
+    // Defining an existing name in the same scope reuses its slot
+    pub fn define(&mut self, name: &str) -> Symbol {
+        let global = self.scopes.len() == 1;
+        let scope = self.scopes.last_mut().unwrap();
+        if let Some(symbol) = scope.store.get(name) {
+            if symbol.scope != SymbolScope::Free {
+                return symbol.clone();
+            }
+        }
+
+        let symbol = Symbol {
+            name: name.to_string(),
+            scope: if global { SymbolScope::Global } else { SymbolScope::Local },
+            index: scope.num_definitions,
+        };
+        scope.num_definitions += 1;
+        scope.store.insert(name.to_string(), symbol.clone());
+        symbol
+    }
+
+    pub fn resolve(&mut self, name: &str) -> Option<Symbol> {
+        self.resolve_in(self.scopes.len() - 1, name)
+    }
+
+    fn resolve_in(&mut self, depth: usize, name: &str) -> Option<Symbol> {
+        if let Some(symbol) = self.scopes[depth].store.get(name) {
+            return Some(symbol.clone());
+        }
+        if depth == 0 {
+            return eval::builtins::BUILTINS.iter()
+                .position(|(builtin, _)| *builtin == name)
+                .map(|index| Symbol { name: name.to_string(), scope: SymbolScope::Builtin, index });
+        }
+
+        let outer = self.resolve_in(depth - 1, name)?;
+        if matches!(outer.scope, SymbolScope::Global | SymbolScope::Builtin) {
+            return Some(outer);
+        }
+
+        let scope = &mut self.scopes[depth];
+        scope.free.push(outer);
+        let symbol = Symbol { name: name.to_string(), scope: SymbolScope::Free, index: scope.free.len() - 1 };
+        scope.store.insert(name.to_string(), symbol.clone());
+        Some(symbol)
+    }
+}
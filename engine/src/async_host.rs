@@ -0,0 +1,150 @@
+// Scripts run by Interpreter::eval_str_async get a thread of their own,
+// which leaves the host's async runtime free while they compute or block
+// on I/O. When one calls an async host function, the thread hands the
+// future over to whoever awaits eval_str_async and waits for its result.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::{self, Future};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Poll, Waker};
+use object::EvalError;
+use crate::host::argument;
+use crate::{Error, Value};
+
+// What an async host function returns for the script to wait on
+pub type HostFuture = Pin<Box<dyn Future<Output = Result<Value, EvalError>> + Send>>;
+
+// Async Rust closures that can be registered with
+// Interpreter::register_async_fn, over typed parameters as for
+// HostFunction
+pub trait AsyncHostFunction<Args> {
+    fn arity(&self) -> usize;
+
+    fn call(&self, name: &str, arguments: &[Value]) -> Result<HostFuture, EvalError>;
+}
+
+macro_rules! typed_async_host_function {
+    ($count:expr $(, $arg:ident $index:tt)*) => {
+        impl<F, Fut, R $(, $arg)*> AsyncHostFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> Fut,
+            Fut: Future<Output = Result<R, EvalError>> + Send + 'static,
+            R: Into<Value>,
+            $($arg: TryFrom<Value>, EvalError: From<$arg::Error>,)*
+        {
+            fn arity(&self) -> usize {
+                $count
+            }
+
+            #[allow(unused_variables)]
+            fn call(&self, name: &str, arguments: &[Value]) -> Result<HostFuture, EvalError> {
+                let future = self($(argument::<$arg>(name, arguments, $index)?),*);
+                Ok(Box::pin(async move { future.await.map(Into::into) }))
+            }
+        }
+    };
+}
+
+typed_async_host_function!(0);
+typed_async_host_function!(1, A 0);
+typed_async_host_function!(2, A 0, B 1);
+typed_async_host_function!(3, A 0, B 1, C 2);
+typed_async_host_function!(4, A 0, B 1, C 2, D 3);
+
+thread_local! {
+    // Set on the threads running scripts for eval_str_async
+    static SCRIPT: RefCell<Option<Arc<Shared>>> = const { RefCell::new(None) };
+}
+
+// Between a script's thread and the future awaiting it
+#[derive(Default)]
+pub(crate) struct Shared(Mutex<State>);
+
+#[derive(Default)]
+struct State {
+    calls: VecDeque<(HostFuture, Sender<Result<Value, EvalError>>)>,
+    done: Option<Result<Value, Error>>,
+    // Set once nothing awaits the script any more
+    abandoned: bool,
+    waker: Option<Waker>,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Runs on the script's thread, reporting how the script ended
+    pub(crate) fn run(self: Arc<Shared>, script: impl FnOnce() -> Result<Value, Error>) {
+        SCRIPT.with(|current| *current.borrow_mut() = Some(Arc::clone(&self)));
+        let result = panic::catch_unwind(AssertUnwindSafe(script))
+            .unwrap_or_else(|_| Err(Error::Runtime(EvalError::Custom("the script's thread panicked".to_string()).into())));
+        let mut state = self.state();
+        state.done = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    // Awaits the script, running the futures it hands over one at a time
+    pub(crate) async fn wait(self: Arc<Shared>) -> Result<Value, Error> {
+        let _abandon = Abandon(Arc::clone(&self));
+        loop {
+            let next = future::poll_fn(|cx| {
+                let mut state = self.state();
+                if let Some(call) = state.calls.pop_front() {
+                    return Poll::Ready(Ok(call));
+                }
+                match state.done.take() {
+                    Some(result) => Poll::Ready(Err(result)),
+                    None => {
+                        state.waker = Some(cx.waker().clone());
+                        Poll::Pending
+                    },
+                }
+            });
+            match next.await {
+                Ok((future, reply)) => {
+                    let _ = reply.send(future.await);
+                },
+                Err(result) => return result,
+            }
+        }
+    }
+}
+
+// Dropped with the future awaiting the script, however it ends. A script
+// still running gets an error from any async function it calls after.
+struct Abandon(Arc<Shared>);
+
+impl Drop for Abandon {
+    fn drop(&mut self) {
+        let mut state = self.0.state();
+        state.abandoned = true;
+        state.calls.clear();
+    }
+}
+
+// Called by the script in place of an async host function, waiting on the
+// script's thread while the host awaits the future
+pub(crate) fn call(name: &str, future: HostFuture) -> Result<Value, EvalError> {
+    let Some(shared) = SCRIPT.with(|current| current.borrow().clone()) else {
+        return Err(EvalError::Custom(format!("`{}` is async and can only be called by scripts run with eval_str_async", name)));
+    };
+    let (reply, result) = mpsc::channel();
+    {
+        let mut state = shared.state();
+        if state.abandoned {
+            return Err(EvalError::Custom(format!("`{}` was called after eval_str_async was cancelled", name)));
+        }
+        state.calls.push_back((future, reply));
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+    result.recv().unwrap_or_else(|_| Err(EvalError::Custom(format!("`{}` was cancelled with eval_str_async", name))))
+}
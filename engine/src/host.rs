@@ -0,0 +1,75 @@
+use object::EvalError;
+use crate::Value;
+
+// Rust closures that can be registered with Interpreter::register_fn.
+//
+// A closure over typed parameters, such as |a: i64, b: i64|, takes exactly
+// that many arguments, each converted with TryFrom<Value>. A closure over
+// &[Value] is variadic and checks its arguments itself. Either kind returns
+// Result<R, EvalError> for any R that converts into a Value.
+//
+// Args only tells the two kinds apart; callers never name it.
+pub trait HostFunction<Args> {
+    fn arity(&self) -> Option<usize>;
+
+    fn call(&self, name: &str, arguments: &[Value]) -> Result<Value, EvalError>;
+}
+
+// Marks closures that take every argument as a slice
+pub struct Variadic;
+
+impl<F, R> HostFunction<Variadic> for F
+where
+    F: Fn(&[Value]) -> Result<R, EvalError>,
+    R: Into<Value>,
+{
+    fn arity(&self) -> Option<usize> {
+        None
+    }
+
+    fn call(&self, _name: &str, arguments: &[Value]) -> Result<Value, EvalError> {
+        self(arguments).map(Into::into)
+    }
+}
+
+// Converts one argument, naming the function and position when the script
+// passed the wrong type
+pub(crate) fn argument<T>(name: &str, arguments: &[Value], index: usize) -> Result<T, EvalError>
+where
+    T: TryFrom<Value>,
+    EvalError: From<T::Error>,
+{
+    T::try_from(arguments[index].clone()).map_err(|err| match EvalError::from(err) {
+        EvalError::UnexpectedType { expected, got } => {
+            EvalError::InvalidArgument { function: name.to_string(), position: index + 1, expected, got }
+        },
+        other => other,
+    })
+}
+
+macro_rules! typed_host_function {
+    ($count:expr $(, $arg:ident $index:tt)*) => {
+        impl<F, R $(, $arg)*> HostFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> Result<R, EvalError>,
+            R: Into<Value>,
+            $($arg: TryFrom<Value>, EvalError: From<$arg::Error>,)*
+        {
+            fn arity(&self) -> Option<usize> {
+                Some($count)
+            }
+
+            #[allow(unused_variables)]
+            fn call(&self, name: &str, arguments: &[Value]) -> Result<Value, EvalError> {
+                self($(argument::<$arg>(name, arguments, $index)?),*).map(Into::into)
+            }
+        }
+    };
+}
+
+typed_host_function!(0);
+typed_host_function!(1, A 0);
+typed_host_function!(2, A 0, B 1);
+typed_host_function!(3, A 0, B 1, C 2);
+typed_host_function!(4, A 0, B 1, C 2, D 3);
+
@@ -0,0 +1,444 @@
+#[cfg(feature = "async")]
+mod async_host;
+mod host;
+
+use std::fmt;
+use std::path::PathBuf;
+use lexer::Lexer;
+use parser::{Parser, ParseError};
+use object::{Environment, NativeFunction};
+use object::shared::{MaybeSync, Rc, RefCell};
+
+#[cfg(feature = "async")]
+pub use async_host::{AsyncHostFunction, HostFuture};
+pub use host::{HostFunction, Variadic};
+pub use eval::snapshot::SnapshotError;
+pub use object::{EvalConfig, EvalError, Io, Limits, Overflow, Resource, RuntimeError};
+
+// Script values. Convert to and from Rust types with From and TryFrom.
+pub type Value = object::Object;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    Parse(Vec<ParseError>),
+    Runtime(RuntimeError),
+    Snapshot(SnapshotError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(errors) => {
+                let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+                write!(f, "{}", messages.join("\n"))
+            },
+            Error::Runtime(err) => write!(f, "{}", err),
+            Error::Snapshot(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<RuntimeError> for Error {
+    fn from(err: RuntimeError) -> Error {
+        Error::Runtime(err)
+    }
+}
+
+impl From<SnapshotError> for Error {
+    fn from(err: SnapshotError) -> Error {
+        Error::Snapshot(err)
+    }
+}
+
+// Runs scripts for a Rust host. Globals persist across calls, so one call
+// can define functions and a later one use them.
+pub struct Interpreter {
+    env: Rc<RefCell<Environment>>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter { env: Rc::new(RefCell::new(Environment::new())) }
+    }
+
+    pub fn with_config(config: EvalConfig) -> Interpreter {
+        let interpreter = Interpreter::new();
+        interpreter.env.borrow_mut().set_config(config);
+        interpreter
+    }
+
+    pub fn eval_str(&mut self, source: &str) -> Result<Value, Error> {
+        let program = Parser::new(Lexer::new(source)).parse_program().map_err(Error::Parse)?;
+        Ok(eval::eval_program(&program, &self.env)?)
+    }
+
+    // Like eval_str, but runs the script on a thread of its own so that an
+    // async host waiting for it is never blocked, and awaits the async
+    // functions it calls. Dropping the future leaves the script running
+    // until it next calls one, which then fails.
+    #[cfg(feature = "async")]
+    pub async fn eval_str_async(&mut self, source: &str) -> Result<Value, Error> {
+        let program = Parser::new(Lexer::new(source)).parse_program().map_err(Error::Parse)?;
+        let shared = std::sync::Arc::new(async_host::Shared::default());
+        let script = std::sync::Arc::clone(&shared);
+        let env = Rc::clone(&self.env);
+        std::thread::spawn(move || script.run(|| Ok(eval::eval_program(&program, &env)?)));
+        shared.wait().await
+    }
+
+    pub fn set_global(&mut self, name: &str, value: impl Into<Value>) {
+        self.env.borrow_mut().set(name, value.into());
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.env.borrow().get(name)
+    }
+
+    // Where puts, print and println write. Defaults to stdin and stdout.
+    pub fn set_io(&mut self, io: Io) {
+        self.env.borrow_mut().set_io(io);
+    }
+
+    // Makes rand, rand_int and shuffle give the same numbers on every run,
+    // as a script calling seed(n) does
+    pub fn seed(&mut self, seed: u64) {
+        self.env.borrow().random().borrow_mut().seed(seed);
+    }
+
+    // The globals and everything they reach, functions included as source,
+    // along with the modules loaded so far. Fails on values that only the
+    // running program could make sense of, such as channels.
+    pub fn snapshot(&self) -> Result<Vec<u8>, Error> {
+        Ok(eval::snapshot::save(&self.env)?)
+    }
+
+    // Brings back the globals of a snapshot, over any of the same name. The
+    // interpreter keeps its own settings, and host functions the snapshot
+    // uses have to be registered on it first.
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        Ok(eval::snapshot::restore(bytes, &self.env)?)
+    }
+
+    // Imports read `<root>/<path>.mky`, relative to the working directory
+    // unless set
+    pub fn set_module_root(&mut self, root: impl Into<PathBuf>) {
+        self.env.borrow().modules().borrow_mut().set_root(root);
+    }
+
+    // Lets scripts import the source under this name without touching files
+    pub fn add_module(&mut self, name: &str, source: &str) {
+        self.env.borrow().modules().borrow_mut().add_source(name, source);
+    }
+
+    // Binds a Rust closure as a global function, see HostFunction for the
+    // closures accepted. Scripts can rebind the name like any other global.
+    // With the sync feature the closure has to be Send and Sync too.
+    pub fn register_fn<Args, F>(&mut self, name: &str, func: F)
+    where
+        F: HostFunction<Args> + MaybeSync + 'static,
+    {
+        let arity = func.arity();
+        let function_name = name.to_string();
+        let native = NativeFunction {
+            name: name.to_string(),
+            arity,
+            func: Rc::new(move |arguments| func.call(&function_name, arguments)),
+        };
+        self.env.borrow_mut().set(name, Value::Native(native));
+    }
+
+    // Binds an async Rust closure as a global function. Scripts wait for
+    // its future, which only those run by eval_str_async can.
+    #[cfg(feature = "async")]
+    pub fn register_async_fn<Args, F>(&mut self, name: &str, func: F)
+    where
+        F: AsyncHostFunction<Args> + Send + Sync + 'static,
+    {
+        let function_name = name.to_string();
+        let native = NativeFunction {
+            name: name.to_string(),
+            arity: Some(func.arity()),
+            func: Rc::new(move |arguments| async_host::call(&function_name, func.call(&function_name, arguments)?)),
+        };
+        self.env.borrow_mut().set(name, Value::Native(native));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed() {
+        let draw = |seed: u64| {
+            let mut interpreter = Interpreter::new();
+            interpreter.seed(seed);
+            interpreter.eval_str("[rand_int(0, 1000), shuffle([1, 2, 3, 4, 5]), rand()]")
+        };
+        assert_eq!(draw(42), draw(42));
+        assert_ne!(draw(42), draw(43));
+        // Seeding from the script starts over the same way
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.eval_str("seed(42); [rand_int(0, 1000), shuffle([1, 2, 3, 4, 5]), rand()]"), draw(42));
+    }
+
+    #[test]
+    fn test_eval_str() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.eval_str("let double = fn(x) { x * 2 };"), Ok(Value::Null));
+        assert_eq!(interpreter.eval_str("double(21)"), Ok(Value::Integer(42)));
+
+        let err = interpreter.eval_str("let = 1;").unwrap_err();
+        assert!(matches!(err, Error::Parse(ref errors) if errors.len() == 1));
+        assert_eq!(err.to_string(), "expected next token to be Identifier, got Assign at line 1, col 5");
+
+        let err = interpreter.eval_str("double(null)").unwrap_err();
+        assert!(matches!(err, Error::Runtime(RuntimeError { error: EvalError::TypeMismatch { .. }, .. })));
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("name", "world");
+        interpreter.set_global("scores", vec![1, 2, 3]);
+        interpreter.eval_str("let greeting = \"hello \" + name; let total = 0; for (s in scores) { total += s; }").unwrap();
+
+        assert_eq!(interpreter.get_global("greeting").map(String::try_from), Some(Ok("hello world".to_string())));
+        assert_eq!(interpreter.get_global("total").map(i64::try_from), Some(Ok(6)));
+        assert_eq!(interpreter.get_global("missing"), None);
+    }
+
+    #[test]
+    fn test_modules() {
+        let mut interpreter = Interpreter::new();
+        interpreter.add_module("shapes", "let area = fn(w, h) { w * h };");
+        assert_eq!(interpreter.eval_str("import \"shapes\"; shapes.area(2, 3)"), Ok(Value::Integer(6)));
+        // Imports made by one call are still bound in the next
+        assert_eq!(interpreter.eval_str("shapes.area(4, 4)"), Ok(Value::Integer(16)));
+
+        interpreter.set_module_root("no/such/dir");
+        let err = interpreter.eval_str("import \"geometry\"").unwrap_err();
+        assert_eq!(err.to_string(), "module not found: geometry at line 1, col 8");
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_fn("add", |a: i64, b: i64| Ok(a + b));
+        interpreter.add_module("counter", "let count = 0; let bump = fn() { count += 1; count };");
+        interpreter.eval_str("
+            import \"counter\"; import \"std/math\";
+            let make = fn() { let n = 0; fn() { n = add(n, 1); n } };
+            let next = make(); next();
+            let same = next;
+            struct Point { x, y }
+            let data = [1.5, {\"p\": Point { x: 1, y: 2 }}, ok('c'), 0..3, math.sqrt, len];
+            counter.bump();
+        ").unwrap();
+        let bytes = interpreter.snapshot().unwrap();
+
+        let mut restored = Interpreter::new();
+        restored.register_fn("add", |a: i64, b: i64| Ok(a + b));
+        restored.restore(&bytes).unwrap();
+        // Copies of a closure still share what they closed over, and the
+        // module is not run again on import
+        assert_eq!(restored.eval_str("next(); same()"), Ok(Value::Integer(3)));
+        assert_eq!(restored.eval_str("import \"counter\"; counter.bump()"), Ok(Value::Integer(2)));
+        assert_eq!(restored.eval_str("data[5](data) + data[4](16) + data[1][\"p\"].y").map(|value| value.inspect()), Ok("12.0".to_string()));
+        assert_eq!(restored.eval_str("Point { x: 1, y: 2 } == data[1][\"p\"]"), Ok(Value::Boolean(true)));
+        // The original carries on separately
+        assert_eq!(interpreter.eval_str("next()"), Ok(Value::Integer(2)));
+
+        let err = Interpreter::new().restore(&bytes).unwrap_err();
+        assert_eq!(err.to_string(), "host function add is not registered");
+        assert_eq!(Interpreter::new().restore(b"junk"), Err(Error::Snapshot(SnapshotError::NotSnapshot)));
+        assert_eq!(Interpreter::new().restore(&bytes[..bytes.len() / 2]), Err(Error::Snapshot(SnapshotError::UnexpectedEnd)));
+
+        interpreter.eval_str("let ch = channel();").unwrap();
+        assert_eq!(interpreter.snapshot(), Err(Error::Snapshot(SnapshotError::Unsupported("CHANNEL"))));
+    }
+
+    #[test]
+    fn test_register_fn() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_fn("add", |a: i64, b: i64| Ok(a + b));
+        interpreter.register_fn("shout", |s: String| Ok(s.to_uppercase() + "!"));
+        interpreter.register_fn("answer", || Ok(42));
+        interpreter.register_fn("sum", |values: Vec<i64>| Ok(values.iter().sum::<i64>()));
+        interpreter.register_fn("first_or", |value: Option<i64>, fallback: Value| Ok(value.map(Value::from).unwrap_or(fallback)));
+        interpreter.register_fn("count", |arguments: &[Value]| Ok(arguments.len() as i64));
+        interpreter.register_fn("checked_div", |a: i64, b: i64| {
+            if b == 0 {
+                return Err(EvalError::Custom(format!("cannot divide {} by zero", a)));
+            }
+            Ok(a / b)
+        });
+
+        let tests = vec![
+            ("add(2, 3) * 2", Ok(Value::Integer(10))),
+            ("shout(\"hi\")", Ok(Value::from("HI!"))),
+            ("answer()", Ok(Value::Integer(42))),
+            ("sum([1, 2, 3])", Ok(Value::Integer(6))),
+            ("first_or(null, \"none\")", Ok(Value::from("none"))),
+            ("count()", Ok(Value::Integer(0))),
+            ("count(1, \"a\", [])", Ok(Value::Integer(3))),
+            ("let f = add; f(1, 1)", Ok(Value::Integer(2))),
+            ("type(add)", Ok(Value::from("BUILTIN"))),
+            ("add(1)", Err("wrong number of arguments. got=1, want=2")),
+            ("add(1, \"2\")", Err("argument 2 to `add` must be INTEGER, got STRING")),
+            ("sum([1, true])", Err("argument 1 to `sum` must be INTEGER, got BOOLEAN")),
+            ("checked_div(1, 0)", Err("cannot divide 1 by zero")),
+        ];
+
+        for (source, expected) in tests {
+            let result = interpreter.eval_str(source).map_err(|err| match err {
+                Error::Runtime(err) => err.error.to_string(),
+                other => panic!("{}", other),
+            });
+            assert_eq!(result, expected.map_err(String::from), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_io() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_io(Io { input: Rc::new(RefCell::new(std::io::empty())), output: output.clone() });
+        interpreter.eval_str("puts(1, \"a\"); print(\"no newline\"); println(\"{} done\", 2);").unwrap();
+        // Functions defined earlier write to the same place when called later
+        interpreter.eval_str("let shout = fn(s) { puts(upper(s)) };").unwrap();
+        interpreter.eval_str("shout(\"hey\")").unwrap();
+
+        assert_eq!(String::from_utf8(output.take()).unwrap(), "1\na\nno newline2 done\nHEY\n");
+    }
+
+    #[test]
+    fn test_config() {
+        let mut interpreter = Interpreter::with_config(EvalConfig { overflow: Overflow::Wrap, ..EvalConfig::default() });
+        assert_eq!(interpreter.eval_str("9223372036854775807 + 1"), Ok(Value::Integer(i64::MIN)));
+
+        let limits = Limits { max_steps: Some(10_000), ..Limits::default() };
+        let mut limited = Interpreter::with_config(EvalConfig { limits, ..EvalConfig::default() });
+        let err = limited.eval_str("for (let i = 0; true; i++) {}").unwrap_err();
+        assert!(matches!(err, Error::Runtime(RuntimeError { error: EvalError::ResourceExceeded(Resource::Steps(10_000)), .. })));
+        // The interpreter is still usable afterwards
+        assert_eq!(limited.eval_str("1 + 1"), Ok(Value::Integer(2)));
+
+        let mut sandboxed = Interpreter::with_config(EvalConfig { filesystem: false, network: false, ..EvalConfig::default() });
+        let err = sandboxed.eval_str("import \"std/file\"; file.exists(\".\")").unwrap_err();
+        assert_eq!(err.to_string(), "file system access is disabled for this interpreter at line 1, col 8");
+        let err = sandboxed.eval_str("import \"std/http\"; http.get(\"http://localhost/\")").unwrap_err();
+        assert_eq!(err.to_string(), "network access is disabled for this interpreter at line 1, col 8");
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn test_send() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_fn("add", |a: i64, b: i64| Ok(a + b));
+        interpreter.eval_str("let counter = 0; let bump = fn() { counter += 1; add(counter, 0) };").unwrap();
+
+        let interpreter = std::sync::Arc::new(std::sync::Mutex::new(interpreter));
+        let handles: Vec<_> = (0..4).map(|_| {
+            let interpreter = interpreter.clone();
+            std::thread::spawn(move || interpreter.lock().unwrap().eval_str("bump()").unwrap())
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(interpreter.lock().unwrap().get_global("counter"), Some(Value::Integer(4)));
+
+        // Values made on one thread are read on another
+        let value = std::thread::spawn(|| Interpreter::new().eval_str("[fn(x) { x }, {\"a\": 1}]").unwrap()).join().unwrap();
+        assert_eq!(value.inspect(), "[fn(x) { x }, {a: 1}]");
+    }
+
+    // Polls on the test's thread, parking between wakes
+    #[cfg(feature = "async")]
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        use std::sync::Arc;
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct Unpark(std::thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(value) = future.as_mut().poll(&mut cx) {
+                return value;
+            }
+            std::thread::park();
+        }
+    }
+
+    // Ready once another thread has slept for the time given
+    #[cfg(feature = "async")]
+    async fn sleep(millis: u64) {
+        use std::sync::{Arc, Mutex};
+        use std::task::{Poll, Waker};
+
+        // Whether the time is up, and what to wake when it is
+        let state: Arc<Mutex<(bool, Option<Waker>)>> = Arc::default();
+        let timer = Arc::clone(&state);
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(millis));
+            let mut state = timer.lock().unwrap();
+            state.0 = true;
+            if let Some(waker) = state.1.take() {
+                waker.wake();
+            }
+        });
+        std::future::poll_fn(|cx| {
+            let mut state = state.lock().unwrap();
+            if state.0 {
+                return Poll::Ready(());
+            }
+            state.1 = Some(cx.waker().clone());
+            Poll::Pending
+        }).await
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_eval_str_async() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_async_fn("fetch", |path: String| async move {
+            sleep(10).await;
+            Ok(format!("contents of {}", path))
+        });
+        interpreter.register_async_fn("add_later", |a: i64, b: i64| async move {
+            sleep(1).await;
+            Ok(a + b)
+        });
+        interpreter.eval_str("let twice = fn(path) { [fetch(path), fetch(path + \"!\")] };").unwrap();
+
+        let future = interpreter.eval_str_async("let total = 0; for (i in 0..3) { total = add_later(total, i); } [twice(\"a\"), total]");
+        fn assert_send<T: Send>(_: &T) {}
+        assert_send(&future);
+        assert_eq!(block_on(future).unwrap().inspect(), "[[contents of a, contents of a!], 3]");
+
+        // Errors from either side come back as usual
+        let err = block_on(interpreter.eval_str_async("add_later(1, \"2\")")).unwrap_err();
+        assert!(matches!(err, Error::Runtime(RuntimeError { error: EvalError::InvalidArgument { position: 2, .. }, .. })));
+        assert!(matches!(block_on(interpreter.eval_str_async("let = 1;")), Err(Error::Parse(_))));
+
+        // Plain eval_str has nothing to await with
+        let err = interpreter.eval_str("fetch(\"a\")").unwrap_err();
+        assert!(err.to_string().starts_with("`fetch` is async and can only be called by scripts run with eval_str_async"));
+    }
+}
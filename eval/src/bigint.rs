@@ -0,0 +1,54 @@
+use token::TokenType;
+use object::{Object, EvalError};
+
+pub use num_bigint::BigInt;
+
+// Results that fit in an i64 go back to being plain integers, so a big
+// integer is never equal to a small one
+pub fn normalize(value: BigInt) -> Object {
+    match i64::try_from(&value) {
+        Ok(i) => Object::Integer(i),
+        Err(_) => Object::BigInteger(value),
+    }
+}
+
+pub fn to_big(value: &Object) -> BigInt {
+    match value {
+        Object::Integer(i) => BigInt::from(*i),
+        Object::BigInteger(i) => i.clone(),
+        other => panic!("not an integer: {}", other.type_name()),
+    }
+}
+
+pub fn parse(literal: &str) -> Option<Object> {
+    literal.parse::<BigInt>().ok().map(normalize)
+}
+
+pub fn negate(value: BigInt) -> Object {
+    normalize(-value)
+}
+
+// Division truncates toward zero and the remainder takes the sign of the
+// dividend, matching i64
+pub fn apply_infix_operator(operator: &TokenType, literal: &str, left: BigInt, right: BigInt) -> Result<Object, EvalError> {
+    let result = match operator {
+        TokenType::Plus => normalize(left + right),
+        TokenType::Minus => normalize(left - right),
+        TokenType::Asterisk => normalize(left * right),
+        TokenType::Slash | TokenType::Percent if right == BigInt::ZERO => return Err(EvalError::DivisionByZero),
+        TokenType::Slash => normalize(left / right),
+        TokenType::Percent => normalize(left % right),
+        TokenType::Equal => Object::Boolean(left == right),
+        TokenType::NotEqual => Object::Boolean(left != right),
+        TokenType::LessThan => Object::Boolean(left < right),
+        TokenType::GreaterThan => Object::Boolean(left > right),
+        TokenType::LessThanOrEqual => Object::Boolean(left <= right),
+        TokenType::GreaterThanOrEqual => Object::Boolean(left >= right),
+        _ => return Err(EvalError::UnknownInfixOperator {
+            left: "INTEGER",
+            operator: literal.to_string(),
+            right: "INTEGER",
+        }),
+    };
+    Ok(result)
+}
@@ -0,0 +1,859 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use object::shared::Rc;
+use object::{Object, EvalError, BuiltinFunction, Runtime, HashKey, InspectOptions, Limits, Channel, Received};
+
+// Arrays and hashes are values: builtins that change one return a new one
+// and leave their argument untouched, so `push(xs, 1)` alone has no effect and
+// `xs = push(xs, 1)` is how a variable grows
+
+pub const BUILTINS: &[(&str, BuiltinFunction)] = &[
+    ("len", len),
+    ("puts", puts),
+    ("print", print),
+    ("println", println),
+    ("format", format),
+    ("first", first),
+    ("last", last),
+    ("rest", rest),
+    ("push", push),
+    ("pop", pop),
+    ("insert", insert),
+    ("remove", remove),
+    ("sort", sort),
+    ("reverse", reverse),
+    ("join", join),
+    ("concat", concat),
+    ("type", type_of),
+    ("is_null", is_null),
+    ("ord", ord),
+    ("chr", chr),
+    ("assert", assert),
+    ("assert_eq", assert_eq),
+    ("split", split),
+    ("trim", trim),
+    ("upper", upper),
+    ("lower", lower),
+    ("contains", contains),
+    ("replace", replace),
+    ("starts_with", starts_with),
+    ("slice", slice),
+    ("map", map),
+    ("filter", filter),
+    ("reduce", reduce),
+    ("each", each),
+    ("find", find),
+    ("keys", keys),
+    ("values", values),
+    ("has_key", has_key),
+    ("delete", delete),
+    ("merge", merge),
+    ("gc_stats", gc_stats),
+    ("stats", stats),
+    ("memory", memory),
+    ("spawn", spawn),
+    ("channel", channel),
+    ("send", send),
+    ("recv", recv),
+    ("close", close),
+    ("select", select),
+    ("ok", ok),
+    ("err", err),
+    ("is_ok", is_ok),
+    ("unwrap_or", unwrap_or),
+    ("panic", panic),
+    ("rand", rand),
+    ("rand_int", rand_int),
+    ("shuffle", shuffle),
+    ("seed", seed),
+];
+
+pub fn lookup(name: &str) -> Option<Object> {
+    BUILTINS.iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, func)| Object::Builtin(*func))
+}
+
+// The builtins each type has as methods, called with the receiver as
+// their first argument
+const METHODS: &[(&str, &[&str])] = &[
+    ("ARRAY", &[
+        "len", "first", "last", "rest", "push", "pop", "insert", "remove", "sort", "reverse",
+        "join", "concat", "slice", "map", "filter", "reduce", "each", "find", "shuffle",
+    ]),
+    ("STRING", &["len", "split", "trim", "upper", "lower", "contains", "replace", "starts_with", "slice"]),
+    ("HASH", &["len", "keys", "values", "has_key", "delete", "merge"]),
+    ("RANGE", &["len", "map", "filter", "reduce", "each", "find", "shuffle"]),
+    ("TASK", &["join"]),
+    ("CHANNEL", &["send", "recv", "close"]),
+    ("RESULT", &["is_ok", "unwrap_or"]),
+];
+
+pub fn method(receiver: &Object, name: &str) -> Option<Object> {
+    methods(receiver.type_name()).contains(&name).then(|| lookup(name)).flatten()
+}
+
+// The names of the methods values of the type have
+pub fn methods(type_name: &str) -> &'static [&'static str] {
+    METHODS.iter().find(|(name, _)| *name == type_name).map_or(&[], |(_, methods)| methods)
+}
+
+pub(crate) fn check_argument_count(args: &[Object], want: usize) -> Result<(), EvalError> {
+    if args.len() != want {
+        return Err(EvalError::WrongArgumentCount { got: args.len(), want });
+    }
+    Ok(())
+}
+
+fn len(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    match &args[0] {
+        Object::String(s) => Ok(Object::Integer(s.chars().count() as i64)),
+        Object::Array(elements) => Ok(Object::Integer(elements.len() as i64)),
+        Object::Hash(pairs) => Ok(Object::Integer(pairs.len() as i64)),
+        Object::Range(start, end) => Ok(Object::Integer(end.saturating_sub(*start).max(0))),
+        other => Err(EvalError::UnsupportedArgument { function: "len", got: other.type_name() }),
+    }
+}
+
+fn puts(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    for arg in args {
+        let text = display(runtime, &arg)?;
+        runtime.write(&format!("{}\n", text))?;
+    }
+    Ok(Object::Null)
+}
+
+// How puts and interpolation show a value: as inspect does, except that a
+// struct with a to_string function among its fields is shown as whatever
+// string that returns. The function is called without the struct, so it
+// sees what it closed over when made rather than members assigned since.
+pub(crate) fn display(runtime: &mut dyn Runtime, value: &Object) -> Result<String, EvalError> {
+    value.display(&mut |instance| match instance.method("to_string") {
+        Some(function) => match runtime.call(function, Vec::new())? {
+            Object::String(text) => Ok(Some(text)),
+            other => Err(EvalError::UnexpectedType { expected: "STRING", got: other.type_name() }),
+        },
+        None => Ok(None),
+    })
+}
+
+// print(template, values...) writes format's result without a newline
+fn print(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    let text = format_arguments(runtime, &args, "print")?;
+    runtime.write(&text)?;
+    Ok(Object::Null)
+}
+
+fn println(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    let text = format_arguments(runtime, &args, "println")?;
+    runtime.write(&format!("{}\n", text))?;
+    Ok(Object::Null)
+}
+
+fn format(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    Ok(Object::String(format_arguments(runtime, &args, "format")?))
+}
+
+fn format_arguments(runtime: &mut dyn Runtime, args: &[Object], function: &str) -> Result<String, EvalError> {
+    if args.is_empty() {
+        return Err(EvalError::WrongArgumentCount { got: 0, want: 1 });
+    }
+    format_template(runtime, string_argument(args, 1, function)?, &args[1..])
+}
+
+// Fills each `{}` with the next value as puts shows it. `{:5}` pads to a
+// width, right-aligning numbers and left-aligning anything else, and
+// `{:05}` pads integers with zeros. `{{` and `}}` are literal braces.
+fn format_template(runtime: &mut dyn Runtime, template: &str, values: &[Object]) -> Result<String, EvalError> {
+    let invalid = |reason: &str| EvalError::InvalidFormat(reason.to_string());
+    let mut out = String::new();
+    let mut values = values.iter();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                out.push('{');
+            },
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                out.push('}');
+            },
+            '{' => {
+                let rest = chars.as_str();
+                let end = rest.find('}').ok_or_else(|| invalid("unclosed `{`"))?;
+                let spec = &rest[..end];
+                chars = rest[end + 1..].chars();
+                let value = values.next().ok_or_else(|| invalid("more placeholders than values"))?;
+                out.push_str(&format_value(runtime, value, spec)?.ok_or_else(|| invalid(&format!("bad placeholder `{{{}}}` for {}", spec, value.type_name())))?);
+            },
+            '}' => return Err(invalid("unmatched `}`")),
+            c => out.push(c),
+        }
+    }
+    if values.next().is_some() {
+        return Err(invalid("more values than placeholders"));
+    }
+    Ok(out)
+}
+
+// None when the placeholder makes no sense for the value. `{:?}` is the
+// debug form, so it leaves to_string out.
+fn format_value(runtime: &mut dyn Runtime, value: &Object, spec: &str) -> Result<Option<String>, EvalError> {
+    match spec {
+        "" => return display(runtime, value).map(Some),
+        ":?" => return Ok(Some(value.pretty(&InspectOptions::default()))),
+        _ => {},
+    }
+    let Some(width) = spec.strip_prefix(':') else { return Ok(None) };
+    let zero = width.starts_with('0');
+    let Ok(width) = width.parse::<usize>() else { return Ok(None) };
+    Ok(match value {
+        Object::Integer(i) if zero => Some(format!("{:0width$}", i, width = width)),
+        Object::Integer(i) => Some(format!("{:width$}", i, width = width)),
+        _ if zero => None,
+        other => Some(format!("{:width$}", display(runtime, other)?, width = width)),
+    })
+}
+
+fn first(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    match &args[0] {
+        Object::Array(elements) => Ok(elements.first().cloned().unwrap_or(Object::Null)),
+        other => Err(EvalError::UnsupportedArgument { function: "first", got: other.type_name() }),
+    }
+}
+
+fn last(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    match &args[0] {
+        Object::Array(elements) => Ok(elements.last().cloned().unwrap_or(Object::Null)),
+        other => Err(EvalError::UnsupportedArgument { function: "last", got: other.type_name() }),
+    }
+}
+
+fn rest(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    match &args[0] {
+        Object::Array(elements) if elements.is_empty() => Ok(Object::Null),
+        Object::Array(elements) => Ok(Object::Array(Rc::new(elements[1..].to_vec()))),
+        other => Err(EvalError::UnsupportedArgument { function: "rest", got: other.type_name() }),
+    }
+}
+
+fn push(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    match &args[0] {
+        Object::Array(elements) => {
+            let mut elements = elements.clone();
+            Rc::make_mut(&mut elements).push(args[1].clone());
+            Ok(Object::Array(elements))
+        },
+        other => Err(EvalError::UnsupportedArgument { function: "push", got: other.type_name() }),
+    }
+}
+
+// Everything but the last element, null for an empty array like rest
+fn pop(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    match &args[0] {
+        Object::Array(elements) if elements.is_empty() => Ok(Object::Null),
+        Object::Array(elements) => Ok(Object::Array(Rc::new(elements[..elements.len() - 1].to_vec()))),
+        other => Err(EvalError::UnsupportedArgument { function: "pop", got: other.type_name() }),
+    }
+}
+
+// insert(xs, i, x) puts x before index i; i may be len(xs) to append
+fn insert(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 3)?;
+    let mut elements = array_argument(&args, 1, "insert")?.to_vec();
+    let index = integer_argument(&args, 2, "insert")?;
+    match usize::try_from(index) {
+        Ok(i) if i <= elements.len() => {
+            elements.insert(i, args[2].clone());
+            Ok(Object::Array(Rc::new(elements)))
+        },
+        _ => Err(EvalError::IndexOutOfRange { index, len: elements.len() }),
+    }
+}
+
+fn remove(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    let mut elements = array_argument(&args, 1, "remove")?.to_vec();
+    let index = integer_argument(&args, 2, "remove")?;
+    match usize::try_from(index) {
+        Ok(i) if i < elements.len() => {
+            elements.remove(i);
+            Ok(Object::Array(Rc::new(elements)))
+        },
+        _ => Err(EvalError::IndexOutOfRange { index, len: elements.len() }),
+    }
+}
+
+// Sorts integers, strings or characters in ascending order. Elements that
+// `<` cannot compare are a type mismatch, as they would be in the script.
+fn sort(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    let mut elements = array_argument(&args, 1, "sort")?.to_vec();
+    let mut mismatch = None;
+    elements.sort_by(|a, b| compare(a, b).unwrap_or_else(|| {
+        mismatch.get_or_insert(EvalError::TypeMismatch { left: a.type_name(), operator: "<".to_string(), right: b.type_name() });
+        Ordering::Equal
+    }));
+    match mismatch {
+        Some(err) => Err(err),
+        None => Ok(Object::Array(Rc::new(elements))),
+    }
+}
+
+fn compare(a: &Object, b: &Object) -> Option<Ordering> {
+    match (a, b) {
+        (Object::Integer(a), Object::Integer(b)) => Some(a.cmp(b)),
+        (Object::String(a), Object::String(b)) => Some(a.cmp(b)),
+        (Object::Char(a), Object::Char(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+fn reverse(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    let mut elements = array_argument(&args, 1, "reverse")?.to_vec();
+    elements.reverse();
+    Ok(Object::Array(Rc::new(elements)))
+}
+
+// Elements are written the way puts shows them
+// Also waits for a task, see spawn
+fn join(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    #[cfg(feature = "sync")]
+    if let [Object::Task(task)] = args.as_slice() {
+        return task.join();
+    }
+    check_argument_count(&args, 2)?;
+    let elements = array_argument(&args, 1, "join")?;
+    let separator = string_argument(&args, 2, "join")?;
+    let parts: Vec<String> = elements.iter().map(Object::inspect).collect();
+    Ok(Object::String(parts.join(separator)))
+}
+
+// Any number of arrays, one after the other
+fn concat(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    let mut elements = Vec::new();
+    for position in 1..=args.len() {
+        elements.extend_from_slice(array_argument(&args, position, "concat")?);
+    }
+    Ok(Object::Array(Rc::new(elements)))
+}
+
+fn type_of(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    Ok(Object::String(args[0].type_name().to_string()))
+}
+
+fn is_null(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    Ok(Object::Boolean(args[0] == Object::Null))
+}
+
+// The Unicode code point of a character
+fn ord(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    match &args[0] {
+        Object::Char(c) => Ok(Object::Integer(*c as i64)),
+        other => Err(EvalError::UnsupportedArgument { function: "ord", got: other.type_name() }),
+    }
+}
+
+fn chr(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    match &args[0] {
+        Object::Integer(code) => u32::try_from(*code).ok()
+            .and_then(char::from_u32)
+            .map(Object::Char)
+            .ok_or(EvalError::InvalidCodePoint(*code)),
+        other => Err(EvalError::UnsupportedArgument { function: "chr", got: other.type_name() }),
+    }
+}
+
+// The string argument at a 1-based position
+pub(crate) fn string_argument<'a>(args: &'a [Object], position: usize, function: &str) -> Result<&'a str, EvalError> {
+    match &args[position - 1] {
+        Object::String(s) => Ok(s),
+        other => Err(EvalError::InvalidArgument {
+            function: function.to_string(),
+            position,
+            expected: "STRING",
+            got: other.type_name(),
+        }),
+    }
+}
+
+fn array_argument<'a>(args: &'a [Object], position: usize, function: &str) -> Result<&'a [Object], EvalError> {
+    match &args[position - 1] {
+        Object::Array(elements) => Ok(elements),
+        other => Err(EvalError::InvalidArgument {
+            function: function.to_string(),
+            position,
+            expected: "ARRAY",
+            got: other.type_name(),
+        }),
+    }
+}
+
+// An array, or a range standing in for the array of its integers
+fn sequence_argument<'a>(args: &'a [Object], position: usize, function: &str) -> Result<Cow<'a, [Object]>, EvalError> {
+    match &args[position - 1] {
+        Object::Range(start, end) => Ok(Cow::Owned((*start..*end).map(Object::Integer).collect())),
+        _ => array_argument(args, position, function).map(Cow::Borrowed),
+    }
+}
+
+fn hash_argument<'a>(args: &'a [Object], position: usize, function: &str) -> Result<&'a BTreeMap<HashKey, Object>, EvalError> {
+    match &args[position - 1] {
+        Object::Hash(pairs) => Ok(pairs),
+        other => Err(EvalError::InvalidArgument {
+            function: function.to_string(),
+            position,
+            expected: "HASH",
+            got: other.type_name(),
+        }),
+    }
+}
+
+fn integer_argument(args: &[Object], position: usize, function: &str) -> Result<i64, EvalError> {
+    match &args[position - 1] {
+        Object::Integer(i) => Ok(*i),
+        other => Err(EvalError::InvalidArgument {
+            function: function.to_string(),
+            position,
+            expected: "INTEGER",
+            got: other.type_name(),
+        }),
+    }
+}
+
+// An empty separator splits between every character
+fn split(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    let s = string_argument(&args, 1, "split")?;
+    let separator = string_argument(&args, 2, "split")?;
+    let parts: Vec<Object> = if separator.is_empty() {
+        s.chars().map(|c| Object::String(c.to_string())).collect()
+    } else {
+        s.split(separator).map(|part| Object::String(part.to_string())).collect()
+    };
+    Ok(Object::Array(Rc::new(parts)))
+}
+
+fn trim(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    Ok(Object::String(string_argument(&args, 1, "trim")?.trim().to_string()))
+}
+
+fn upper(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    Ok(Object::String(string_argument(&args, 1, "upper")?.to_uppercase()))
+}
+
+fn lower(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    Ok(Object::String(string_argument(&args, 1, "lower")?.to_lowercase()))
+}
+
+fn contains(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    let s = string_argument(&args, 1, "contains")?;
+    Ok(Object::Boolean(s.contains(string_argument(&args, 2, "contains")?)))
+}
+
+// Replaces every occurrence
+fn replace(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 3)?;
+    let s = string_argument(&args, 1, "replace")?;
+    let from = string_argument(&args, 2, "replace")?;
+    let to = string_argument(&args, 3, "replace")?;
+    Ok(Object::String(s.replace(from, to)))
+}
+
+fn starts_with(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    let s = string_argument(&args, 1, "starts_with")?;
+    Ok(Object::Boolean(s.starts_with(string_argument(&args, 2, "starts_with")?)))
+}
+
+// slice(s, start) or slice(s, start, end) on a string or an array, strings
+// counting characters. Bounds are clamped, so slicing never fails.
+fn slice(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(EvalError::WrongArgumentCount { got: args.len(), want: 3 });
+    }
+    let len = match &args[0] {
+        Object::String(s) => s.chars().count(),
+        Object::Array(elements) => elements.len(),
+        other => return Err(EvalError::UnsupportedArgument { function: "slice", got: other.type_name() }),
+    } as i64;
+    let start = integer_argument(&args, 2, "slice")?.clamp(0, len);
+    let end = match args.len() {
+        3 => integer_argument(&args, 3, "slice")?.clamp(start, len),
+        _ => len,
+    };
+
+    let (start, end) = (start as usize, end as usize);
+    match &args[0] {
+        Object::String(s) => Ok(Object::String(s.chars().skip(start).take(end - start).collect())),
+        Object::Array(elements) => Ok(Object::Array(Rc::new(elements[start..end].to_vec()))),
+        _ => unreachable!(),
+    }
+}
+
+// The higher-order builtins take the array or range first and the function last,
+// calling it through the caller so closures run on the current backend
+fn map(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    let elements = sequence_argument(&args, 1, "map")?;
+    let results = elements.iter()
+        .map(|element| runtime.call(args[1].clone(), vec![element.clone()]))
+        .collect::<Result<Vec<Object>, EvalError>>()?;
+    Ok(Object::Array(Rc::new(results)))
+}
+
+fn filter(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    let mut kept = Vec::new();
+    for element in sequence_argument(&args, 1, "filter")?.iter() {
+        if runtime.call(args[1].clone(), vec![element.clone()])?.is_truthy() {
+            kept.push(element.clone());
+        }
+    }
+    Ok(Object::Array(Rc::new(kept)))
+}
+
+// reduce(xs, initial, fn(accumulator, element) { ... })
+fn reduce(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 3)?;
+    let mut accumulator = args[1].clone();
+    for element in sequence_argument(&args, 1, "reduce")?.iter() {
+        accumulator = runtime.call(args[2].clone(), vec![accumulator, element.clone()])?;
+    }
+    Ok(accumulator)
+}
+
+fn each(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    for element in sequence_argument(&args, 1, "each")?.iter() {
+        runtime.call(args[1].clone(), vec![element.clone()])?;
+    }
+    Ok(Object::Null)
+}
+
+// The first element the function accepts, or null
+fn find(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    for element in sequence_argument(&args, 1, "find")?.iter() {
+        if runtime.call(args[1].clone(), vec![element.clone()])?.is_truthy() {
+            return Ok(element.clone());
+        }
+    }
+    Ok(Object::Null)
+}
+
+// In the hash's own order, see HashKey
+fn keys(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    let pairs = hash_argument(&args, 1, "keys")?;
+    Ok(Object::Array(Rc::new(pairs.keys().cloned().map(Object::from).collect())))
+}
+
+fn values(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    let pairs = hash_argument(&args, 1, "values")?;
+    Ok(Object::Array(Rc::new(pairs.values().cloned().collect())))
+}
+
+fn has_key(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    let pairs = hash_argument(&args, 1, "has_key")?;
+    Ok(Object::Boolean(pairs.contains_key(&args[1].hash_key()?)))
+}
+
+// A copy without the key, which need not be present
+fn delete(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    let mut pairs = hash_argument(&args, 1, "delete")?.clone();
+    pairs.remove(&args[1].hash_key()?);
+    Ok(Object::Hash(Rc::new(pairs)))
+}
+
+// Keys in later hashes win
+fn merge(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    let mut merged = BTreeMap::new();
+    for position in 1..=args.len() {
+        merged.extend(hash_argument(&args, position, "merge")?.clone());
+    }
+    Ok(Object::Hash(Rc::new(merged)))
+}
+
+// What the cycle collector has done so far: how many collections ran, how
+// many scopes they freed and how many scopes are tracked
+fn gc_stats(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 0)?;
+    let stats = runtime.heap_stats();
+    Ok(record([
+        ("collections", Object::Integer(stats.collections as i64)),
+        ("reclaimed", Object::Integer(stats.reclaimed as i64)),
+        ("tracked", Object::Integer(stats.tracked as i64)),
+    ]))
+}
+
+// What the run has used so far against its limits: steps taken, calls in
+// progress, scopes between the caller and the top level and heap objects
+// created. Limits that are not set are null.
+fn stats(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 0)?;
+    let usage = runtime.usage();
+    let Limits { max_steps, max_depth, max_nesting, max_objects, max_string_length, timeout } = usage.limits;
+    Ok(record([
+        ("steps", Object::Integer(usage.steps as i64)),
+        ("depth", Object::Integer(usage.depth as i64)),
+        ("scopes", Object::Integer(usage.scopes as i64)),
+        ("objects", Object::Integer(usage.objects as i64)),
+        ("limits", record([
+            ("steps", max_steps.map(|limit| limit as i64).into()),
+            ("depth", max_depth.map(|limit| limit as i64).into()),
+            ("nesting", max_nesting.map(|limit| limit as i64).into()),
+            ("objects", max_objects.map(|limit| limit as i64).into()),
+            ("string_length", max_string_length.map(|limit| limit as i64).into()),
+            ("timeout_ms", timeout.map(|limit| limit.as_millis() as i64).into()),
+        ])),
+    ]))
+}
+
+// Heap objects created so far, in total and by type, and the scopes alive
+// now and at most
+fn memory(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 0)?;
+    let usage = runtime.usage();
+    let heap = runtime.heap_stats();
+    let by_type = usage.objects_by_type.into_iter().map(|(name, count)| (name, Object::Integer(count as i64)));
+    Ok(record([
+        ("objects", Object::Integer(usage.objects as i64)),
+        ("by_type", record(by_type)),
+        ("scopes", Object::Integer(heap.tracked as i64)),
+        ("peak_scopes", Object::Integer(heap.peak as i64)),
+    ]))
+}
+
+// Calls the function with the rest of the arguments on another thread,
+// returning a task that join waits for
+fn spawn(runtime: &mut dyn Runtime, mut args: Vec<Object>) -> Result<Object, EvalError> {
+    if args.is_empty() {
+        return Err(EvalError::WrongArgumentCount { got: 0, want: 1 });
+    }
+    let function = args.remove(0);
+    runtime.spawn(function, args)
+}
+
+fn channel(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 0)?;
+    Ok(Object::Channel(Channel::new()))
+}
+
+// Sends a copy of the value, which the receiver then has to itself
+fn send(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    channel_argument(&args, 1, "send")?.send(&args[1])?;
+    Ok(Object::Null)
+}
+
+// Waits for the next value, null once the channel is closed and empty
+fn recv(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    Ok(channel_argument(&args, 1, "recv")?.recv()?.unwrap_or(Object::Null))
+}
+
+fn close(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    channel_argument(&args, 1, "close")?.close();
+    Ok(Object::Null)
+}
+
+// Waits for a value on any of the channels, returning the position of the
+// channel and the value. Channels that are closed and empty are passed
+// over, and once all are, the result is null.
+fn select(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    let channels = array_argument(&args, 1, "select")?.iter()
+        .map(|channel| match channel {
+            Object::Channel(channel) => Ok(channel),
+            other => Err(EvalError::UnexpectedType { expected: "CHANNEL", got: other.type_name() }),
+        })
+        .collect::<Result<Vec<&Channel>, EvalError>>()?;
+    loop {
+        let mut open = false;
+        for (i, channel) in channels.iter().enumerate() {
+            match channel.try_recv() {
+                Received::Value(value) => return Ok(Object::Array(Rc::new(vec![Object::Integer(i as i64), value]))),
+                Received::Empty => open = true,
+                Received::Closed => {},
+            }
+        }
+        if !open {
+            return Ok(Object::Null);
+        }
+        if !cfg!(feature = "sync") {
+            return Err(EvalError::CapabilityDisabled("waiting on an empty channel"));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+}
+
+fn ok(_: &mut dyn Runtime, mut args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    Ok(Object::Result(Ok(Box::new(args.remove(0)))))
+}
+
+fn err(_: &mut dyn Runtime, mut args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    Ok(Object::Result(Err(Box::new(args.remove(0)))))
+}
+
+fn is_ok(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    match &args[0] {
+        Object::Result(result) => Ok(Object::Boolean(result.is_ok())),
+        other => Err(EvalError::InvalidArgument {
+            function: "is_ok".to_string(),
+            position: 1,
+            expected: "RESULT",
+            got: other.type_name(),
+        }),
+    }
+}
+
+// The value of an ok, or the default for an err or null. Anything else is
+// taken as a value already, so optional values can be handled the same way.
+fn unwrap_or(_: &mut dyn Runtime, mut args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    let default = args.remove(1);
+    match args.remove(0) {
+        Object::Result(Ok(value)) => Ok(*value),
+        Object::Result(Err(_)) | Object::Null => Ok(default),
+        other => Ok(other),
+    }
+}
+
+// There are no floats, so a random number is a non-negative integer
+fn rand(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 0)?;
+    Ok(Object::Integer((runtime.random() >> 1) as i64))
+}
+
+// An integer from lo up to, but not including, hi, as in the range lo..hi
+fn rand_int(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    let lo = integer_argument(&args, 1, "rand_int")?;
+    let hi = integer_argument(&args, 2, "rand_int")?;
+    if hi <= lo {
+        return Err(EvalError::EmptyRange { start: lo, end: hi });
+    }
+    let offset = draw_below(runtime, hi.abs_diff(lo));
+    Ok(Object::Integer(lo.wrapping_add_unsigned(offset)))
+}
+
+// A copy of the array, or the integers of a range, in a random order
+fn shuffle(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    let mut elements = sequence_argument(&args, 1, "shuffle")?.into_owned();
+    for i in (1..elements.len()).rev() {
+        let j = draw_below(runtime, i as u64 + 1) as usize;
+        elements.swap(i, j);
+    }
+    Ok(Object::Array(Rc::new(elements)))
+}
+
+// Restarts the numbers rand, rand_int and shuffle give from a seed, so they
+// come out the same on every run
+fn seed(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    runtime.seed(integer_argument(&args, 1, "seed")? as u64);
+    Ok(Object::Null)
+}
+
+// Uniform in 0..bound, drawing again rather than favouring small numbers
+// when bound does not divide 2^64
+fn draw_below(runtime: &mut dyn Runtime, bound: u64) -> u64 {
+    let zone = u64::MAX - u64::MAX % bound;
+    loop {
+        let n = runtime.random();
+        if n < zone {
+            return n % bound;
+        }
+    }
+}
+
+fn channel_argument<'a>(args: &'a [Object], position: usize, function: &str) -> Result<&'a Channel, EvalError> {
+    match &args[position - 1] {
+        Object::Channel(channel) => Ok(channel),
+        other => Err(EvalError::InvalidArgument {
+            function: function.to_string(),
+            position,
+            expected: "CHANNEL",
+            got: other.type_name(),
+        }),
+    }
+}
+
+// A hash with the given names as keys
+fn record<'a>(fields: impl IntoIterator<Item = (&'a str, Object)>) -> Object {
+    Object::Hash(Rc::new(fields.into_iter().map(|(name, value)| (HashKey::String(name.to_string()), value)).collect()))
+}
+
+// assert(condition) or assert(condition, message)
+fn assert(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    match args.as_slice() {
+        [condition] | [condition, _] if condition.is_truthy() => Ok(Object::Null),
+        [_] => Err(EvalError::AssertionFailed(None)),
+        [_, message] => Err(EvalError::AssertionFailed(Some(message.inspect()))),
+        _ => Err(EvalError::WrongArgumentCount { got: args.len(), want: 2 }),
+    }
+}
+
+// Values of different types are never equal here, rather than an error
+fn assert_eq(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    if args[0] == args[1] {
+        return Ok(Object::Null);
+    }
+    // Values with several elements or too long to compare at a glance get
+    // a diff
+    let (left, right) = (crate::diff::lines(&args[0]), crate::diff::lines(&args[1]));
+    if left.len() <= 3 && right.len() <= 3 {
+        let (left, right) = (describe(&args[0]), describe(&args[1]));
+        if left.len() + right.len() <= 40 {
+            return Err(EvalError::AssertionFailed(Some(format!("{} != {}", left, right))));
+        }
+    }
+    Err(EvalError::AssertionFailed(Some(format!("values differ (- left, + right)\n{}", crate::diff::diff(&left, &right)))))
+}
+
+// Ends the run with the message, which unlike a thrown value no try can
+// catch
+fn panic(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    let message = match &args[0] {
+        Object::String(s) => s.clone(),
+        other => other.inspect(),
+    };
+    Err(EvalError::Panicked(message))
+}
+
+// Quotes strings and characters so "1" and 1 read differently
+pub(crate) fn describe(value: &Object) -> String {
+    match value {
+        Object::String(s) => format!("{:?}", s),
+        Object::Char(c) => format!("{:?}", c),
+        other => other.inspect(),
+    }
+}
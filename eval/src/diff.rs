@@ -0,0 +1,69 @@
+// Line diffs of the values assert_eq compares. Arrays and hashes get a line
+// per element, so a long value points at the elements that differ.
+
+use object::Object;
+use crate::builtins::describe;
+
+pub(crate) fn lines(value: &Object) -> Vec<String> {
+    let (open, close, items): (&str, &str, Vec<String>) = match value {
+        Object::Array(elements) => ("[", "]", elements.iter().map(describe).collect()),
+        Object::Hash(pairs) => ("{", "}", pairs.iter().map(|(key, value)| format!("{}: {}", key, describe(value))).collect()),
+        other => return vec![describe(other)],
+    };
+    if items.is_empty() {
+        return vec![format!("{}{}", open, close)];
+    }
+    let mut lines = vec![open.to_string()];
+    lines.extend(items.into_iter().map(|item| format!("    {},", item)));
+    lines.push(close.to_string());
+    lines
+}
+
+// Lines only on the left are marked -, those only on the right +, going by
+// a longest common subsequence
+pub(crate) fn diff(left: &[String], right: &[String]) -> String {
+    // common[i][j] is the length of the longest one of left[i..] and right[j..]
+    let mut common = vec![vec![0usize; right.len() + 1]; left.len() + 1];
+    for i in (0..left.len()).rev() {
+        for j in (0..right.len()).rev() {
+            common[i][j] = match left[i] == right[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+
+    let mut out = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() || j < right.len() {
+        if i < left.len() && j < right.len() && left[i] == right[j] {
+            out.push(format!("  {}", left[i]));
+            i += 1;
+            j += 1;
+        } else if j == right.len() || (i < left.len() && common[i + 1][j] >= common[i][j + 1]) {
+            out.push(format!("- {}", left[i]));
+            i += 1;
+        } else {
+            out.push(format!("+ {}", right[j]));
+            j += 1;
+        }
+    }
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object::shared::Rc;
+
+    #[test]
+    fn test_diff() {
+        let left = lines(&Object::Array(Rc::new(vec![Object::Integer(1), Object::String("two".to_string()), Object::Integer(3)])));
+        let right = lines(&Object::Array(Rc::new(vec![Object::Integer(1), Object::Integer(2), Object::Integer(3), Object::Integer(4)])));
+        assert_eq!(diff(&left, &right), "  [\n      1,\n-     \"two\",\n+     2,\n      3,\n+     4,\n  ]");
+
+        let left = lines(&Object::Integer(1));
+        let right = lines(&Object::Array(Rc::new(Vec::new())));
+        assert_eq!(diff(&left, &right), "- 1\n+ []");
+    }
+}
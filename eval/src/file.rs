@@ -0,0 +1,61 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use object::shared::Rc;
+use object::{BuiltinFunction, EvalError, Object, Runtime};
+use crate::builtins::{check_argument_count, string_argument};
+
+// The members of std/file, only importable while the interpreter allows
+// file system access
+pub const FUNCTIONS: &[(&str, BuiltinFunction)] = &[
+    ("read_to_string", read_to_string),
+    ("write", write),
+    ("append", append),
+    ("exists", exists),
+    ("lines", lines),
+];
+
+fn failed(path: &str, err: std::io::Error) -> EvalError {
+    EvalError::Io(format!("{}: {}", path, err))
+}
+
+fn read(path: &str) -> Result<String, EvalError> {
+    std::fs::read_to_string(path).map_err(|err| failed(path, err))
+}
+
+fn read_to_string(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    let path = string_argument(&args, 1, "read_to_string")?;
+    Ok(Object::String(read(path)?))
+}
+
+// Replaces the file, creating it if needed
+fn write(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    let path = string_argument(&args, 1, "write")?;
+    let contents = string_argument(&args, 2, "write")?;
+    std::fs::write(path, contents).map_err(|err| failed(path, err))?;
+    Ok(Object::Null)
+}
+
+fn append(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    let path = string_argument(&args, 1, "append")?;
+    let contents = string_argument(&args, 2, "append")?;
+    OpenOptions::new().create(true).append(true).open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|err| failed(path, err))?;
+    Ok(Object::Null)
+}
+
+fn exists(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    let path = string_argument(&args, 1, "exists")?;
+    Ok(Object::Boolean(std::path::Path::new(path).exists()))
+}
+
+// Without their line endings
+fn lines(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    let path = string_argument(&args, 1, "lines")?;
+    Ok(Object::Array(Rc::new(read(path)?.lines().map(|line| Object::String(line.to_string())).collect())))
+}
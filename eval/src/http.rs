@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use object::shared::Rc;
+use object::{BuiltinFunction, EvalError, HashKey, Object, Resource, Runtime};
+use crate::builtins::{check_argument_count, string_argument};
+
+// The members of std/http, only importable while the interpreter allows
+// network access. Plain http only, as there is no TLS to speak https with.
+pub const FUNCTIONS: &[(&str, BuiltinFunction)] = &[
+    ("get", get),
+    ("post", post),
+];
+
+fn get(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    let url = string_argument(&args, 1, "get")?;
+    request(runtime, "GET", url, Vec::new(), "")
+}
+
+// Header values other than strings are sent as they would print. Null
+// stands in for no headers.
+fn post(runtime: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 3)?;
+    let url = string_argument(&args, 1, "post")?;
+    let body = string_argument(&args, 2, "post")?;
+    let headers = match &args[2] {
+        Object::Hash(pairs) => pairs.iter().map(|(name, value)| (name.to_string(), value.inspect())).collect(),
+        Object::Null => Vec::new(),
+        other => return Err(EvalError::InvalidArgument {
+            function: "post".to_string(),
+            position: 3,
+            expected: "HASH",
+            got: other.type_name(),
+        }),
+    };
+    request(runtime, "POST", url, headers, body)
+}
+
+// Where to connect and what to ask for
+#[derive(Debug, PartialEq)]
+struct Target<'a> {
+    host: &'a str,
+    port: u16,
+    authority: &'a str,
+    path: String,
+}
+
+fn parse_url(url: &str) -> Result<Target<'_>, &'static str> {
+    if url.starts_with("https://") {
+        return Err("https is not supported, only http");
+    }
+    let rest = url.strip_prefix("http://").ok_or("expected a URL starting with http://")?;
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(end);
+    // The fragment is for the client alone
+    let path = path.split('#').next().unwrap_or_default();
+    let path = match path.starts_with('/') {
+        true => path.to_string(),
+        false => format!("/{}", path),
+    };
+
+    // IPv6 addresses are bracketed, as their colons would read as a port
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, after) = bracketed.split_once(']').ok_or("unclosed [ in the host")?;
+            (host, after.strip_prefix(':'))
+        },
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    if host.is_empty() {
+        return Err("missing host");
+    }
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| "invalid port")?,
+        None => 80,
+    };
+    Ok(Target { host, port, authority, path })
+}
+
+// The interpreter's timeout covers waiting on the network too, so each step
+// of a request gets whatever is left of it. Without a timeout nothing does.
+struct Deadline(Option<(Instant, Duration)>);
+
+impl Deadline {
+    fn left(&self) -> Result<Option<Duration>, EvalError> {
+        match self.0 {
+            None => Ok(None),
+            Some((deadline, timeout)) => match deadline.saturating_duration_since(Instant::now()) {
+                Duration::ZERO => Err(EvalError::ResourceExceeded(Resource::Time(timeout))),
+                left => Ok(Some(left)),
+            },
+        }
+    }
+
+    fn failed(&self, url: &str, err: std::io::Error) -> EvalError {
+        match (self.0, err.kind()) {
+            (Some((_, timeout)), ErrorKind::TimedOut | ErrorKind::WouldBlock) => EvalError::ResourceExceeded(Resource::Time(timeout)),
+            _ => EvalError::Io(format!("{}: {}", url, err)),
+        }
+    }
+}
+
+fn request(runtime: &mut dyn Runtime, method: &str, url: &str, headers: Vec<(String, String)>, body: &str) -> Result<Object, EvalError> {
+    let target = parse_url(url).map_err(|reason| EvalError::Io(format!("{}: {}", url, reason)))?;
+    let usage = runtime.usage();
+    let deadline = Deadline(usage.remaining.zip(usage.limits.timeout).map(|(left, timeout)| (Instant::now() + left, timeout)));
+    let failed = |err| deadline.failed(url, err);
+
+    // Ours are replaced by any of the same name the script gives
+    let mut lines = vec![
+        ("Host".to_string(), target.authority.to_string()),
+        ("Connection".to_string(), "close".to_string()),
+    ];
+    if method == "POST" {
+        lines.push(("Content-Length".to_string(), body.len().to_string()));
+    }
+    for (name, value) in headers {
+        lines.retain(|(ours, _)| !ours.eq_ignore_ascii_case(&name));
+        lines.push((name, value));
+    }
+    let mut message = format!("{} {} HTTP/1.1\r\n", method, target.path);
+    for (name, value) in lines {
+        message.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    message.push_str("\r\n");
+    message.push_str(body);
+
+    let mut stream = connect(&target, &deadline).map_err(failed)?;
+    stream.set_write_timeout(deadline.left()?).map_err(failed)?;
+    stream.write_all(message.as_bytes()).map_err(failed)?;
+
+    // The server closes the connection once it has sent everything
+    let mut response = Vec::new();
+    let mut buffer = [0; 8192];
+    loop {
+        stream.set_read_timeout(deadline.left()?).map_err(failed)?;
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => response.extend_from_slice(&buffer[..read]),
+            Err(err) if err.kind() == ErrorKind::Interrupted => {},
+            Err(err) => return Err(failed(err)),
+        }
+    }
+    parse_response(&response).ok_or_else(|| EvalError::Io(format!("{}: malformed response", url)))
+}
+
+// Tries each address the host resolves to in turn
+fn connect(target: &Target, deadline: &Deadline) -> std::io::Result<TcpStream> {
+    let mut last = None;
+    for address in (target.host, target.port).to_socket_addrs()? {
+        let left = deadline.left().map_err(|_| std::io::Error::from(ErrorKind::TimedOut))?;
+        let connected = match left {
+            Some(left) => TcpStream::connect_timeout(&address, left),
+            None => TcpStream::connect(address),
+        };
+        match connected {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last = Some(err),
+        }
+    }
+    Err(last.unwrap_or_else(|| std::io::Error::new(ErrorKind::NotFound, "no address for the host")))
+}
+
+// A hash of the status, the headers by lowercase name and the body. Headers
+// sent more than once have their values joined with commas.
+fn parse_response(response: &[u8]) -> Option<Object> {
+    let end = response.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&response[..end]).ok()?;
+    let mut lines = head.split("\r\n");
+    let status: i64 = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+
+    let mut headers: BTreeMap<HashKey, Object> = BTreeMap::new();
+    for line in lines {
+        let (name, value) = line.split_once(':')?;
+        let value = value.trim().to_string();
+        match headers.entry(HashKey::String(name.trim().to_ascii_lowercase())) {
+            Entry::Occupied(mut entry) => {
+                if let Object::String(joined) = entry.get_mut() {
+                    joined.push_str(", ");
+                    joined.push_str(&value);
+                }
+            },
+            Entry::Vacant(entry) => {
+                entry.insert(Object::String(value));
+            },
+        }
+    }
+
+    let header = |name: &str| match headers.get(&HashKey::String(name.to_string())) {
+        Some(Object::String(value)) => Some(value.clone()),
+        _ => None,
+    };
+    let mut body = &response[end + 4..];
+    let dechunked;
+    if header("transfer-encoding").is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked")) {
+        dechunked = dechunk(body)?;
+        body = &dechunked;
+    } else if let Some(length) = header("content-length").and_then(|length| length.parse::<usize>().ok()) {
+        body = &body[..length.min(body.len())];
+    }
+
+    let mut pairs = BTreeMap::new();
+    pairs.insert(HashKey::String("status".to_string()), Object::Integer(status));
+    pairs.insert(HashKey::String("headers".to_string()), Object::Hash(Rc::new(headers)));
+    pairs.insert(HashKey::String("body".to_string()), Object::String(String::from_utf8_lossy(body).into_owned()));
+    Some(Object::Hash(Rc::new(pairs)))
+}
+
+// Each chunk is its size in hex on a line of its own, then the data; a
+// chunk of size zero ends the body
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        // Extensions after a ; are ignored
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(out);
+        }
+        out.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        let target = |host, port, authority, path: &str| Ok(Target { host, port, authority, path: path.to_string() });
+        assert_eq!(parse_url("http://example.com"), target("example.com", 80, "example.com", "/"));
+        assert_eq!(parse_url("http://localhost:8080/a/b?q=1#top"), target("localhost", 8080, "localhost:8080", "/a/b?q=1"));
+        assert_eq!(parse_url("http://example.com?q"), target("example.com", 80, "example.com", "/?q"));
+        assert_eq!(parse_url("http://[::1]:3000/"), target("::1", 3000, "[::1]:3000", "/"));
+        assert_eq!(parse_url("https://example.com"), Err("https is not supported, only http"));
+        assert_eq!(parse_url("example.com"), Err("expected a URL starting with http://"));
+        assert_eq!(parse_url("http://:80/"), Err("missing host"));
+        assert_eq!(parse_url("http://example.com:http/"), Err("invalid port"));
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response = parse_response(b"HTTP/1.1 200 OK\r\nSet-Cookie: a\r\nset-cookie: b\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2;x=y\r\nde\r\n0\r\n\r\n").unwrap();
+        assert_eq!(response.inspect(), "{body: abcde, headers: {set-cookie: a, b, transfer-encoding: chunked}, status: 200}");
+
+        let response = parse_response(b"HTTP/1.0 404 Not Found\r\nContent-Length: 2\r\n\r\nnoextra").unwrap();
+        assert_eq!(response.inspect(), "{body: no, headers: {content-length: 2}, status: 404}");
+
+        assert_eq!(parse_response(b"HTTP/1.1 200 OK\r\n"), None);
+        assert_eq!(parse_response(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nab"), None);
+    }
+}
@@ -0,0 +1,2022 @@
+pub mod builtins;
+#[cfg(feature = "bigint")]
+mod bigint;
+mod diff;
+mod file;
+mod http;
+mod machine;
+mod math;
+mod modules;
+pub mod place;
+pub mod snapshot;
+pub mod stdlib;
+#[cfg(feature = "sync")]
+mod tasks;
+
+use object::shared::{Rc, RefCell};
+use typed_arena::Arena;
+use token::TokenType;
+use ast::Program;
+use object::{Object, Environment, Runtime, HeapStats, Usage, Budget};
+use machine::Machine;
+
+pub use object::{EvalError, RuntimeError, StackFrame, EvalConfig, Overflow, Limits, Resource};
+
+pub fn eval_program(program: &Program, env: &Rc<RefCell<Environment>>) -> Result<Object, RuntimeError> {
+    // Imports count towards the depth and nesting, as loading one recurses
+    // on the host stack
+    within_budget(env, || {
+        let bodies = Arena::new();
+        match Machine::new(&bodies, env).run_statements(&program.statements, env)? {
+            Object::ReturnValue(value) => Ok(*value),
+            value => Ok(value),
+        }
+    })
+}
+
+// Runs f one level deeper, starting a new count of the resources used when
+// nothing was running
+fn within_budget(env: &Rc<RefCell<Environment>>, f: impl FnOnce() -> Result<Object, RuntimeError>) -> Result<Object, RuntimeError> {
+    let budget = env.borrow().budget();
+    let config = env.borrow().config();
+    budget.borrow_mut().enter(&config)?;
+    let result = nested(&budget, f);
+    budget.borrow_mut().exit();
+    result
+}
+
+// Runs f a level further down the host stack
+fn nested<T>(budget: &Rc<RefCell<Budget>>, f: impl FnOnce() -> Result<T, RuntimeError>) -> Result<T, RuntimeError> {
+    budget.borrow_mut().nest()?;
+    let result = f();
+    budget.borrow_mut().unnest();
+    result
+}
+
+// Calls a function value from Rust, such as a test runner calling the
+// functions a script defined. Builtins write to the streams of env.
+pub fn call_function(function: Object, arguments: Vec<Object>, env: &Rc<RefCell<Environment>>) -> Result<Object, RuntimeError> {
+    let bodies = Arena::new();
+    Machine::new(&bodies, env).run_call(function, arguments, env)
+}
+
+// What a running builtin sees of the evaluator. Builtins only pass on the
+// error of a script function they called, so the full one with its
+// location is kept here to report instead.
+struct Context {
+    env: Rc<RefCell<Environment>>,
+    error: Option<RuntimeError>,
+}
+
+impl Runtime for Context {
+    fn call(&mut self, function: Object, arguments: Vec<Object>) -> Result<Object, EvalError> {
+        let budget = self.env.borrow().budget();
+        nested(&budget, || call_function(function, arguments, &self.env)).map_err(|err| {
+            let error = err.error.clone();
+            self.error = Some(err);
+            error
+        })
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), EvalError> {
+        self.env.borrow().io().write(text)
+    }
+
+    fn usage(&mut self) -> Usage {
+        let budget = self.env.borrow().budget();
+        let usage = budget.borrow().usage();
+        Usage { scopes: self.env.borrow().depth(), ..usage }
+    }
+
+    fn heap_stats(&mut self) -> HeapStats {
+        self.env.borrow().heap().borrow().stats()
+    }
+
+    fn random(&mut self) -> u64 {
+        self.env.borrow().random().borrow_mut().next_u64()
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.env.borrow().random().borrow_mut().seed(seed);
+    }
+
+    #[cfg(feature = "sync")]
+    fn spawn(&mut self, function: Object, arguments: Vec<Object>) -> Result<Object, EvalError> {
+        tasks::spawn(function, arguments, &self.env)
+    }
+}
+
+impl Context {
+    fn into_error(self, error: EvalError) -> RuntimeError {
+        match self.error {
+            Some(err) if err.error == error => err,
+            _ => error.into(),
+        }
+    }
+}
+
+// Operator semantics are public so the bytecode VM can share them with the
+// tree-walking evaluator; `literal` is only used in error messages
+// `!` works on any value by truthiness (only false and null are falsy),
+// while `-` is only defined for numbers
+pub fn apply_prefix_operator(operator: &TokenType, literal: &str, right: Object, config: &EvalConfig) -> Result<Object, EvalError> {
+    match (operator, &right) {
+        (TokenType::Bang, _) => Ok(Object::Boolean(!right.is_truthy())),
+        (TokenType::Minus, Object::Integer(i)) => match config.overflow.resolve(i.checked_neg(), i.wrapping_neg(), i.saturating_neg()) {
+            Some(negated) => Ok(Object::Integer(negated)),
+            #[cfg(feature = "bigint")]
+            None if config.overflow == Overflow::Promote => Ok(bigint::negate(bigint::BigInt::from(*i))),
+            None => Err(EvalError::IntegerOverflow(format!("-({})", i))),
+        },
+        #[cfg(feature = "bigint")]
+        (TokenType::Minus, Object::BigInteger(i)) => Ok(bigint::negate(i.clone())),
+        (TokenType::Minus, Object::Float(x)) => Ok(Object::Float(-x)),
+        _ => Err(EvalError::UnknownPrefixOperator {
+            operator: literal.to_string(),
+            right: right.type_name(),
+        }),
+    }
+}
+
+pub fn apply_infix_operator(operator: &TokenType, literal: &str, left: Object, right: Object, config: &EvalConfig) -> Result<Object, EvalError> {
+    match (&left, &right) {
+        (Object::Integer(l), Object::Integer(r)) => apply_integer_infix_operator(operator, literal, *l, *r, config),
+        #[cfg(feature = "bigint")]
+        (Object::Integer(_) | Object::BigInteger(_), Object::Integer(_) | Object::BigInteger(_)) => {
+            bigint::apply_infix_operator(operator, literal, bigint::to_big(&left), bigint::to_big(&right))
+        },
+        (Object::Float(_), Object::Integer(_) | Object::Float(_)) | (Object::Integer(_), Object::Float(_)) => {
+            apply_float_infix_operator(operator, literal, &left, &right)
+        },
+        #[cfg(feature = "bigint")]
+        (Object::Float(_), Object::BigInteger(_)) | (Object::BigInteger(_), Object::Float(_)) => {
+            apply_float_infix_operator(operator, literal, &left, &right)
+        },
+        (Object::String(l), Object::String(r)) => apply_string_infix_operator(operator, literal, l, r),
+        (Object::Char(l), Object::Char(r)) => apply_char_infix_operator(operator, literal, *l, *r),
+        (Object::String(s), Object::Integer(n)) | (Object::Integer(n), Object::String(s)) if *operator == TokenType::Asterisk => {
+            repeat_string(s, *n, config)
+        },
+        (Object::Boolean(l), Object::Boolean(r)) if *operator == TokenType::Equal => Ok(Object::Boolean(l == r)),
+        (Object::Boolean(l), Object::Boolean(r)) if *operator == TokenType::NotEqual => Ok(Object::Boolean(l != r)),
+        // Collections are equal when their elements are, and in the same
+        // order or under the same keys
+        (Object::Array(_), Object::Array(_)) | (Object::Hash(_), Object::Hash(_)) if *operator == TokenType::Equal => Ok(Object::Boolean(left == right)),
+        (Object::Array(_), Object::Array(_)) | (Object::Hash(_), Object::Hash(_)) if *operator == TokenType::NotEqual => Ok(Object::Boolean(left != right)),
+        // Instances are equal when they have the same type and field values
+        (Object::Struct(l), Object::Struct(r)) if *operator == TokenType::Equal => Ok(Object::Boolean(l == r)),
+        (Object::Struct(l), Object::Struct(r)) if *operator == TokenType::NotEqual => Ok(Object::Boolean(l != r)),
+        (Object::Range(..), Object::Range(..)) if *operator == TokenType::Equal => Ok(Object::Boolean(left == right)),
+        (Object::Range(..), Object::Range(..)) if *operator == TokenType::NotEqual => Ok(Object::Boolean(left != right)),
+        (Object::Result(..), Object::Result(..)) if *operator == TokenType::Equal => Ok(Object::Boolean(left == right)),
+        (Object::Result(..), Object::Result(..)) if *operator == TokenType::NotEqual => Ok(Object::Boolean(left != right)),
+        // Any value can be checked against null
+        (Object::Null, _) | (_, Object::Null) if *operator == TokenType::Equal => Ok(Object::Boolean(left == right)),
+        (Object::Null, _) | (_, Object::Null) if *operator == TokenType::NotEqual => Ok(Object::Boolean(left != right)),
+        _ if left.type_name() != right.type_name() => Err(EvalError::TypeMismatch {
+            left: left.type_name(),
+            operator: literal.to_string(),
+            right: right.type_name(),
+        }),
+        _ => Err(EvalError::UnknownInfixOperator {
+            left: left.type_name(),
+            operator: literal.to_string(),
+            right: right.type_name(),
+        }),
+    }
+}
+
+// What a for-in loop visits: the characters of a string, the elements of
+// an array or the keys of a hash
+pub fn make_range(start: Object, end: Object, inclusive: bool) -> Result<Object, EvalError> {
+    match (&start, &end) {
+        (Object::Integer(start), Object::Integer(end)) if inclusive => end.checked_add(1)
+            .map(|end| Object::Range(*start, end))
+            .ok_or_else(|| EvalError::IntegerOverflow(format!("{}..={}", start, end))),
+        (Object::Integer(start), Object::Integer(end)) => Ok(Object::Range(*start, *end)),
+        _ => Err(EvalError::InvalidRange { start: start.type_name(), end: end.type_name() }),
+    }
+}
+
+pub fn iterate(iterable: Object) -> Result<Vec<Object>, EvalError> {
+    match iterable {
+        Object::Range(start, end) => Ok((start..end).map(Object::Integer).collect()),
+        Object::String(s) => Ok(s.chars().map(|c| Object::String(c.to_string())).collect()),
+        Object::Array(elements) => Ok(Rc::unwrap_or_clone(elements)),
+        Object::Hash(pairs) => Ok(pairs.keys().cloned().map(Object::from).collect()),
+        other => Err(EvalError::NotIterable(other.type_name())),
+    }
+}
+
+// Strings index by character, giving a one-character string. Indexes past
+// either end give null rather than an error, as do missing hash keys.
+pub fn apply_index_operator(left: Object, index: Object) -> Result<Object, EvalError> {
+    match (&left, &index) {
+        (Object::Hash(pairs), _) => Ok(pairs.get(&index.hash_key()?).cloned().unwrap_or(Object::Null)),
+        (Object::String(s), Object::Integer(i)) => Ok(usize::try_from(*i).ok()
+            .and_then(|i| s.chars().nth(i))
+            .map(|c| Object::String(c.to_string()))
+            .unwrap_or(Object::Null)),
+        (Object::Array(elements), Object::Integer(i)) => Ok(usize::try_from(*i).ok()
+            .and_then(|i| elements.get(i).cloned())
+            .unwrap_or(Object::Null)),
+        _ => Err(EvalError::IndexNotSupported { left: left.type_name(), index: index.type_name() }),
+    }
+}
+
+// Index assignment replaces the one element where it is, copying the array
+// or hash first if another value still shares it. Arrays only take indexes
+// they already have.
+pub fn assign_index(left: &mut Object, index: Object, value: Object) -> Result<(), EvalError> {
+    match (left, index) {
+        (Object::Hash(pairs), index) => {
+            let key = index.hash_key()?;
+            Rc::make_mut(pairs).insert(key, value);
+        },
+        (Object::Array(elements), Object::Integer(i)) => {
+            let len = elements.len();
+            let position = usize::try_from(i).ok()
+                .filter(|&i| i < len)
+                .ok_or(EvalError::IndexOutOfRange { index: i, len })?;
+            Rc::make_mut(elements)[position] = value;
+        },
+        (left, index) => return Err(EvalError::IndexNotSupported { left: left.type_name(), index: index.type_name() }),
+    }
+    Ok(())
+}
+
+fn apply_string_infix_operator(operator: &TokenType, literal: &str, left: &str, right: &str) -> Result<Object, EvalError> {
+    let result = match operator {
+        TokenType::Plus => Object::String(format!("{}{}", left, right)),
+        TokenType::Equal => Object::Boolean(left == right),
+        TokenType::NotEqual => Object::Boolean(left != right),
+        TokenType::LessThan => Object::Boolean(left < right),
+        TokenType::GreaterThan => Object::Boolean(left > right),
+        TokenType::LessThanOrEqual => Object::Boolean(left <= right),
+        TokenType::GreaterThanOrEqual => Object::Boolean(left >= right),
+        _ => return Err(EvalError::UnknownInfixOperator {
+            left: "STRING",
+            operator: literal.to_string(),
+            right: "STRING",
+        }),
+    };
+    Ok(result)
+}
+
+// Characters compare by code point
+fn apply_char_infix_operator(operator: &TokenType, literal: &str, left: char, right: char) -> Result<Object, EvalError> {
+    let result = match operator {
+        TokenType::Equal => left == right,
+        TokenType::NotEqual => left != right,
+        TokenType::LessThan => left < right,
+        TokenType::GreaterThan => left > right,
+        TokenType::LessThanOrEqual => left <= right,
+        TokenType::GreaterThanOrEqual => left >= right,
+        _ => return Err(EvalError::UnknownInfixOperator {
+            left: "CHAR",
+            operator: literal.to_string(),
+            right: "CHAR",
+        }),
+    };
+    Ok(Object::Boolean(result))
+}
+
+// The length is checked before anything is allocated, as a large enough
+// count would otherwise abort the process
+fn repeat_string(s: &str, count: i64, config: &EvalConfig) -> Result<Object, EvalError> {
+    let times = usize::try_from(count).map_err(|_| EvalError::NegativeRepeatCount(count))?;
+    let length = s.len().checked_mul(times)
+        .ok_or_else(|| EvalError::IntegerOverflow(format!("{} * {}", s.len(), count)))?;
+    match config.limits.max_string_length {
+        Some(limit) if length > limit => Err(EvalError::ResourceExceeded(Resource::StringLength(limit))),
+        _ => Ok(Object::String(s.repeat(times))),
+    }
+}
+
+fn apply_integer_infix_operator(operator: &TokenType, literal: &str, left: i64, right: i64, config: &EvalConfig) -> Result<Object, EvalError> {
+    let result = match operator {
+        TokenType::Plus | TokenType::Minus | TokenType::Asterisk | TokenType::Slash | TokenType::Percent => {
+            integer_arithmetic(operator, literal, left, right, config)?
+        },
+        TokenType::Equal => Object::Boolean(left == right),
+        TokenType::NotEqual => Object::Boolean(left != right),
+        TokenType::LessThan => Object::Boolean(left < right),
+        TokenType::GreaterThan => Object::Boolean(left > right),
+        TokenType::LessThanOrEqual => Object::Boolean(left <= right),
+        TokenType::GreaterThanOrEqual => Object::Boolean(left >= right),
+        _ => return Err(EvalError::UnknownInfixOperator {
+            left: "INTEGER",
+            operator: literal.to_string(),
+            right: "INTEGER",
+        }),
+    };
+    Ok(result)
+}
+
+// An integer mixed with a float is converted to the nearest float first, so
+// 1 + 0.5 is 1.5 and 1 == 1.0. Dividing by zero is an error as it is for
+// integers; otherwise results are IEEE, infinities and NaN included.
+fn apply_float_infix_operator(operator: &TokenType, literal: &str, left: &Object, right: &Object) -> Result<Object, EvalError> {
+    let (l, r) = (to_float(left), to_float(right));
+    let result = match operator {
+        TokenType::Slash | TokenType::Percent if r == 0.0 => return Err(EvalError::DivisionByZero),
+        TokenType::Plus => Object::Float(l + r),
+        TokenType::Minus => Object::Float(l - r),
+        TokenType::Asterisk => Object::Float(l * r),
+        TokenType::Slash => Object::Float(l / r),
+        TokenType::Percent => Object::Float(l % r),
+        TokenType::Equal => Object::Boolean(l == r),
+        TokenType::NotEqual => Object::Boolean(l != r),
+        TokenType::LessThan => Object::Boolean(l < r),
+        TokenType::GreaterThan => Object::Boolean(l > r),
+        TokenType::LessThanOrEqual => Object::Boolean(l <= r),
+        TokenType::GreaterThanOrEqual => Object::Boolean(l >= r),
+        _ => return Err(EvalError::UnknownInfixOperator {
+            left: left.type_name(),
+            operator: literal.to_string(),
+            right: right.type_name(),
+        }),
+    };
+    Ok(result)
+}
+
+// Big integers go through their decimal form, which rounds correctly
+pub(crate) fn to_float(value: &Object) -> f64 {
+    match value {
+        Object::Float(x) => *x,
+        Object::Integer(i) => *i as f64,
+        other => other.inspect().parse().unwrap_or(f64::NAN),
+    }
+}
+
+// Only MIN / -1 and MIN % -1 overflow when dividing; the remainder of
+// those is 0 under every policy
+pub fn integer_arithmetic(operator: &TokenType, literal: &str, left: i64, right: i64, config: &EvalConfig) -> Result<Object, EvalError> {
+    let (checked, wrapped, saturated) = match operator {
+        TokenType::Plus => (left.checked_add(right), left.wrapping_add(right), left.saturating_add(right)),
+        TokenType::Minus => (left.checked_sub(right), left.wrapping_sub(right), left.saturating_sub(right)),
+        TokenType::Asterisk => (left.checked_mul(right), left.wrapping_mul(right), left.saturating_mul(right)),
+        TokenType::Slash | TokenType::Percent if right == 0 => return Err(EvalError::DivisionByZero),
+        TokenType::Slash => (left.checked_div(right), left.wrapping_div(right), left.saturating_div(right)),
+        TokenType::Percent => (left.checked_rem(right), left.wrapping_rem(right), left.wrapping_rem(right)),
+        _ => return Err(EvalError::UnknownInfixOperator {
+            left: "INTEGER",
+            operator: literal.to_string(),
+            right: "INTEGER",
+        }),
+    };
+    match config.overflow.resolve(checked, wrapped, saturated) {
+        Some(result) => Ok(Object::Integer(result)),
+        #[cfg(feature = "bigint")]
+        None if config.overflow == Overflow::Promote => {
+            bigint::apply_infix_operator(operator, literal, bigint::BigInt::from(left), bigint::BigInt::from(right))
+        },
+        None => Err(EvalError::IntegerOverflow(format!("{} {} {}", left, literal, right))),
+    }
+}
+
+// Adds or subtracts one for `++` and `--`, returning None for non-integers
+pub fn step_integer(value: &Object, increment: bool, config: &EvalConfig) -> Option<Result<Object, EvalError>> {
+    let (operator, literal) = if increment { (TokenType::Plus, "+") } else { (TokenType::Minus, "-") };
+    match value {
+        Object::Integer(i) => Some(integer_arithmetic(&operator, literal, *i, 1, config)),
+        #[cfg(feature = "bigint")]
+        Object::BigInteger(i) => Some(bigint::apply_infix_operator(&operator, literal, i.clone(), bigint::BigInt::from(1))),
+        _ => None,
+    }
+}
+
+// Literals past the i64 range only have a value when big integers are enabled
+pub fn big_integer_literal(literal: &str) -> Result<Object, EvalError> {
+    #[cfg(feature = "bigint")]
+    if let Some(value) = bigint::parse(literal) {
+        return Ok(value);
+    }
+    Err(EvalError::IntegerLiteralOutOfRange(literal.to_string()))
+}
+
+// Maps `+=` and friends onto the infix operator they apply before assigning
+pub fn compound_operator(operator: &TokenType) -> Option<(TokenType, &'static str)> {
+    match operator {
+        TokenType::PlusAssign => Some((TokenType::Plus, "+")),
+        TokenType::MinusAssign => Some((TokenType::Minus, "-")),
+        TokenType::AsteriskAssign => Some((TokenType::Asterisk, "*")),
+        TokenType::SlashAssign => Some((TokenType::Slash, "/")),
+        TokenType::PercentAssign => Some((TokenType::Percent, "%")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+    use token::Span;
+    use std::time::Duration;
+
+    fn eval(input: &str) -> Result<Object, EvalError> {
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program().unwrap();
+        let env = Rc::new(RefCell::new(Environment::new()));
+        eval_program(&program, &env).map_err(|err| err.error)
+    }
+
+    #[test]
+    fn test_eval_integer_expression() {
+        let tests = vec![
+            ("5", 5),
+            ("-10", -10),
+            ("5 + 5 + 5 + 5 - 10", 10),
+            ("2 * 2 * 2 * 2 * 2", 32),
+            ("-50 + 100 + -50", 0),
+            ("5 * 2 + 10", 20),
+            ("5 + 2 * 10", 25),
+            ("20 + 2 * -10", 0),
+            ("50 / 2 * 2 + 10", 60),
+            ("17 % 5", 2),
+            ("(5 + 10 * 2 + 15 / 3) * 2 + -10", 50),
+            ("-(2 - 3) * (4)", 4),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(Object::Integer(expected)), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        let max = "9223372036854775807";
+        let min = "let m = -9223372036854775807 - 1;";
+        let tests = vec![
+            (format!("{} + 1", max), Overflow::Error, Err(format!("integer overflow: {} + 1", max))),
+            (format!("{} + 1", max), Overflow::Wrap, Ok(i64::MIN)),
+            (format!("{} + 1", max), Overflow::Saturate, Ok(i64::MAX)),
+            (format!("-{} - 2", max), Overflow::Error, Err(format!("integer overflow: -{} - 2", max))),
+            (format!("-{} - 2", max), Overflow::Saturate, Ok(i64::MIN)),
+            (format!("{} * 2", max), Overflow::Error, Err(format!("integer overflow: {} * 2", max))),
+            (format!("{} * 2", max), Overflow::Wrap, Ok(-2)),
+            (format!("let x = {}; x++; x", max), Overflow::Error, Err(format!("integer overflow: {} + 1", max))),
+            (format!("let x = {}; x++; x", max), Overflow::Saturate, Ok(i64::MAX)),
+            (format!("let x = {}; x += 1", max), Overflow::Error, Err(format!("integer overflow: {} + 1", max))),
+            (format!("{} -m", min), Overflow::Error, Err(format!("integer overflow: -({})", i64::MIN))),
+            (format!("{} -m", min), Overflow::Wrap, Ok(i64::MIN)),
+            (format!("{} m / -1", min), Overflow::Error, Err(format!("integer overflow: {} / -1", i64::MIN))),
+            (format!("{} m / -1", min), Overflow::Saturate, Ok(i64::MAX)),
+            (format!("{} m % -1", min), Overflow::Wrap, Ok(0)),
+            (format!("{} - 1", max), Overflow::Error, Ok(i64::MAX - 1)),
+            // Function scopes inherit the setting of the environment they close over
+            (format!("let f = fn(x) {{ x + 1 }}; f({})", max), Overflow::Wrap, Ok(i64::MIN)),
+        ];
+
+        for (input, overflow, expected) in tests {
+            let env = Rc::new(RefCell::new(Environment::new()));
+            env.borrow_mut().set_config(EvalConfig { overflow, ..EvalConfig::default() });
+            let program = Parser::new(Lexer::new(&input)).parse_program().unwrap();
+            let result = eval_program(&program, &env).map_err(|err| err.error.to_string());
+            assert_eq!(result, expected.map(Object::Integer), "{} with {:?}", input, overflow);
+        }
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let limits = |limits: Limits| EvalConfig { limits, ..EvalConfig::default() };
+        let steps = limits(Limits { max_steps: Some(1000), ..Limits::default() });
+        let objects = limits(Limits { max_objects: Some(50), ..Limits::default() });
+        let depth = limits(Limits { max_depth: Some(10), ..Limits::default() });
+        let tests = vec![
+            ("for (let i = 0; true; i++) {}", steps, Err("resource limit exceeded: 1000 steps at line 1, col 24")),
+            ("let total = 0; for (let i = 0; i < 10; i++) { total += i; } total", steps, Ok(Object::Integer(45))),
+            ("let a = []; for (let i = 0; i < 100; i++) { a = push(a, \"x\"); }", objects, Err("resource limit exceeded: 50 heap objects at line 1, col 53")),
+            ("let a = []; for (let i = 0; i < 100; i++) { a = push(a, i); } len(a)", limits(Limits::default()), Ok(Object::Integer(100))),
+            ("let f = fn(n) { n == 0 ? 0 : f(n - 1) }; f(20)", depth, Err("resource limit exceeded: call depth of 10 at line 1, col 31\n    in f called at line 1, col 31 (9 times)\n    in f called at line 1, col 43")),
+            ("let f = fn(n) { n == 0 ? 0 : f(n - 1) }; f(5)", depth, Ok(Object::Integer(0))),
+        ];
+
+        for (input, config, expected) in tests {
+            let env = Rc::new(RefCell::new(Environment::new()));
+            env.borrow_mut().set_config(config);
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            let result = eval_program(&program, &env).map_err(|err| err.to_string());
+            assert_eq!(result, expected.map_err(String::from), "{} with {:?}", input, config.limits);
+            // Each run starts with a fresh budget
+            let program = Parser::new(Lexer::new("let g = fn(n) { n }; g(1)")).parse_program().unwrap();
+            assert_eq!(eval_program(&program, &env), Ok(Object::Integer(1)), "after {}", input);
+        }
+
+        // Where the clock runs out varies from run to run
+        let env = Rc::new(RefCell::new(Environment::new()));
+        env.borrow_mut().set_config(limits(Limits { timeout: Some(Duration::from_millis(10)), ..Limits::default() }));
+        let program = Parser::new(Lexer::new("for (let i = 0; true; i++) {}")).parse_program().unwrap();
+        let err = eval_program(&program, &env).unwrap_err();
+        assert_eq!(err.error, EvalError::ResourceExceeded(Resource::Time(Duration::from_millis(10))));
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let run = || {
+            let env = Rc::new(RefCell::new(Environment::new()));
+            let limits = Limits { timeout: Some(Duration::from_millis(1)), ..Limits::default() };
+            env.borrow_mut().set_config(EvalConfig { limits, deterministic: true, ..EvalConfig::default() });
+            let program = Parser::new(Lexer::new("[rand_int(0, 1000), shuffle([1, 2, 3, 4, 5]), rand()]")).parse_program().unwrap();
+            let drawn = eval_program(&program, &env).unwrap().inspect();
+            // The clock runs out after the same number of steps every time
+            let program = Parser::new(Lexer::new("let n = 0; for (let i = 0; true; i++) { n = i; }")).parse_program().unwrap();
+            let err = eval_program(&program, &env).unwrap_err();
+            assert_eq!(err.error, EvalError::ResourceExceeded(Resource::Time(Duration::from_millis(1))));
+            let reached = env.borrow().get("n");
+            (drawn, reached)
+        };
+        let (drawn, reached) = run();
+        assert_eq!(run(), (drawn, reached.clone()));
+        assert_eq!(reached, Some(Object::Integer(248)));
+    }
+
+    #[test]
+    fn test_default_depth_limit() {
+        // Runaway recursion stops before it overflows a stack the size of
+        // the main thread's
+        let results = std::thread::Builder::new().stack_size(8 << 20).spawn(|| {
+            [
+                "let f = fn() { f() }; f()",
+                "let f = fn() { map([1], fn(x) { f() }) }; f()",
+                "let f = fn(n) { n == 0 ? 0 : 1 + f(n - 1) }; f(1000)",
+            ].map(|input| eval(input).map(|value| value.inspect()).map_err(|err| err.to_string()))
+        }).unwrap().join().unwrap();
+        assert_eq!(results, [
+            Err(format!("resource limit exceeded: call depth of {}", object::DEFAULT_MAX_DEPTH)),
+            Err(format!("resource limit exceeded: {} calls nested in builtins or imports", object::DEFAULT_MAX_NESTING)),
+            Ok("1000".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_deep_evaluation() {
+        // Calls and nesting only grow the evaluator's own stacks, so neither
+        // needs much of the host's
+        let inputs = [
+            "let f = fn(n) { n == 0 ? 0 : 1 + f(n - 1) }; f(20000)".to_string(),
+            format!("len({}1{})", "[".repeat(200), "]".repeat(200)),
+        ];
+        let programs: Vec<_> = inputs.iter().map(|input| Parser::new(Lexer::new(input)).parse_program().unwrap()).collect();
+        let results = std::thread::Builder::new().stack_size(256 << 10).spawn(move || {
+            let env = Rc::new(RefCell::new(Environment::new()));
+            env.borrow_mut().set_config(EvalConfig { limits: Limits { max_depth: None, ..Limits::default() }, ..EvalConfig::default() });
+            programs.iter().map(|program| eval_program(program, &env).map(|value| value.inspect()).map_err(|err| err.to_string())).collect::<Vec<_>>()
+        }).unwrap().join().unwrap();
+        assert_eq!(results, vec![Ok("20000".to_string()), Ok("1".to_string())]);
+    }
+
+    #[test]
+    fn test_trace() {
+        let tracer = Rc::new(RefCell::new(object::WriteTracer(Vec::new())));
+        let env = Rc::new(RefCell::new(Environment::new()));
+        env.borrow_mut().set_trace(object::Trace::new(tracer.clone()));
+        let program = Parser::new(Lexer::new("let f = fn(x) { x * 2 };\nf(1 + 2)")).parse_program().unwrap();
+        eval_program(&program, &env).unwrap();
+
+        let trace = String::from_utf8(tracer.borrow().0.clone()).unwrap();
+        assert_eq!(trace, "\
+fn(x) { (x * 2) } => fn(x) { (x * 2) }  at line 1, col 9
+f => fn(x) { (x * 2) }  at line 2, col 1
+1 => 1  at line 2, col 3
+2 => 2  at line 2, col 7
+(1 + 2) => 3  at line 2, col 5
+  x => 3  at line 1, col 17
+  2 => 2  at line 1, col 21
+  (x * 2) => 6  at line 1, col 19
+f((1 + 2)) => 6  at line 2, col 2
+");
+    }
+
+    #[test]
+    fn test_profile() {
+        let profiler = Rc::new(RefCell::new(object::Profiler::new()));
+        let env = Rc::new(RefCell::new(Environment::new()));
+        env.borrow_mut().set_trace(object::Trace::new(profiler.clone()));
+        let input = "let fib = fn(n) {\n    n < 2 ? n : fib(n - 1) + fib(n - 2)\n};\nlet twice = fn(x) { x * 2 };\ntwice(fib(10))";
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        assert_eq!(eval_program(&program, &env), Ok(Object::Integer(110)));
+
+        let profiler = profiler.borrow();
+        let calls: Vec<_> = profiler.functions().into_iter().map(|(name, profile)| (name, profile.calls)).collect();
+        assert_eq!(calls.len(), 2);
+        assert!(calls.contains(&("fib".to_string(), 177)), "{:?}", calls);
+        assert!(calls.contains(&("twice".to_string(), 1)), "{:?}", calls);
+        let mut counts: Vec<_> = profiler.lines().into_iter().map(|(line, profile)| (line, profile.count)).collect();
+        counts.sort();
+        // 177 conditions, each with two operands, then 88 sums with the two
+        // calls, each with their argument and its two operands
+        assert_eq!(counts, [(1, 1), (2, 177 * 4 + 88 * 11 + 89), (4, 4), (5, 5)]);
+        assert!(profiler.report().starts_with("function"));
+    }
+
+    #[test]
+    fn test_usage_builtins() {
+        let input = "let f = fn(n) { n == 0 ? stats() : f(n - 1) }; f(3)";
+        assert_eq!(eval(input).unwrap().inspect(), "{depth: 5, limits: {depth: 10000, nesting: 150, objects: null, steps: null, string_length: 1073741824, timeout_ms: null}, objects: 1, scopes: 1, steps: 37}");
+        let input = "let xs = [\"a\", \"b\"]; let g = fn() { [1] }; g(); memory()";
+        assert_eq!(eval(input).unwrap().inspect(), "{by_type: {ARRAY: 3, FUNCTION: 1, STRING: 2}, objects: 6, peak_scopes: 1, scopes: 0}");
+
+        let env = Rc::new(RefCell::new(Environment::new()));
+        let limits = object::Limits { max_steps: Some(1000), max_objects: Some(10), timeout: Some(Duration::from_secs(2)), ..object::Limits::default() };
+        env.borrow_mut().set_config(EvalConfig { limits, ..EvalConfig::default() });
+        let program = Parser::new(Lexer::new("stats()[\"limits\"]")).parse_program().unwrap();
+        assert_eq!(eval_program(&program, &env).unwrap().inspect(), "{depth: 10000, nesting: 150, objects: 10, steps: 1000, string_length: 1073741824, timeout_ms: 2000}");
+    }
+
+    #[test]
+    fn test_cycle_collection() {
+        // Every call leaves a function in the scope it closes over
+        let input = "let make = fn() { let f = fn() { f }; 0 }; for (i in 0..3000) { make(); } gc_stats()";
+        assert_eq!(eval(input).unwrap().inspect(), "{collections: 2, reclaimed: 2046, tracked: 954}");
+        assert_eq!(eval("gc_stats()").unwrap().inspect(), "{collections: 0, reclaimed: 0, tracked: 0}");
+
+        // Scopes still in use survive, cycles and all
+        let input = "let keep = []; let make = fn() { let f = fn() { f }; f }; for (i in 0..1500) { keep = push(keep, make()); } [len(keep), gc_stats()[\"reclaimed\"]]";
+        assert_eq!(eval(input).unwrap().inspect(), "[1500, 0]");
+    }
+
+    #[test]
+    fn test_eval_boolean_expression() {
+        let tests = vec![
+            ("true", true),
+            ("false", false),
+            ("1 < 2", true),
+            ("1 > 2", false),
+            ("1 <= 1", true),
+            ("2 >= 3", false),
+            ("1 == 1", true),
+            ("1 != 1", false),
+            ("true == true", true),
+            ("true != false", true),
+            ("[1, [2]] == [1, [2]]", true),
+            ("[1] == [1, 1]", false),
+            ("[] != []", false),
+            ("{\"a\": 1} == {\"a\": 1}", true),
+            ("{\"a\": {1: true}} != {\"a\": {1: false}}", true),
+            ("!true", false),
+            ("!!5", true),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(Object::Boolean(expected)), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_prefix_operators() {
+        let tests = vec![
+            ("-5", Ok(Object::Integer(-5))),
+            ("-0", Ok(Object::Integer(0))),
+            ("- -5", Ok(Object::Integer(5))),
+            ("let x = 3; -x", Ok(Object::Integer(-3))),
+            ("-true", Err("unknown operator: -BOOLEAN")),
+            ("let s = \"a\"; -s", Err("unknown operator: -STRING")),
+            ("-[1]", Err("unknown operator: -ARRAY")),
+            ("-puts()", Err("unknown operator: -NULL")),
+            ("-fn() {}", Err("unknown operator: -FUNCTION")),
+            ("-len", Err("unknown operator: -BUILTIN")),
+            ("!true", Ok(Object::Boolean(false))),
+            ("!false", Ok(Object::Boolean(true))),
+            ("!puts()", Ok(Object::Boolean(true))),
+            ("!0", Ok(Object::Boolean(false))),
+            ("!5", Ok(Object::Boolean(false))),
+            ("let s = \"\"; !s", Ok(Object::Boolean(false))),
+            ("![]", Ok(Object::Boolean(false))),
+            ("!fn() {}", Ok(Object::Boolean(false))),
+            ("!len", Ok(Object::Boolean(false))),
+            ("!!puts()", Ok(Object::Boolean(false))),
+        ];
+
+        for (input, expected) in tests {
+            let result = eval(input).map_err(|err| err.to_string());
+            assert_eq!(result, expected.map_err(|e: &str| e.to_string()), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_eval_string_literal() {
+        assert_eq!(eval("\"hello world\""), Ok(Object::String("hello world".to_string())));
+    }
+
+    #[test]
+    fn test_interpolated_strings() {
+        let tests = vec![
+            (r#"let a = 1; let b = 2; "sum is ${a + b}""#, "sum is 3"),
+            (r#""${"nested ${1 + 1}"}!""#, "nested 2!"),
+            (r#"let h = {"k": "v"}; "${h} ${h["k"]} ${fn(x) { x }(4)}""#, "{k: v} v 4"),
+            (r#"let i = 0; "${i++}${i++}${i}""#, "012"),
+            (r#""$ and {} stay""#, "$ and {} stay"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(Object::from(expected)), "input: {}", input);
+        }
+
+        let program = Parser::new(Lexer::new("let s = \"v: ${missing}\";")).parse_program().unwrap();
+        let err = eval_program(&program, &Rc::new(RefCell::new(Environment::new()))).unwrap_err();
+        assert_eq!(err.to_string(), "identifier not found: missing at line 1, col 15");
+    }
+
+    #[test]
+    fn test_string_operators() {
+        let tests = vec![
+            ("\"foo\" + \"bar\"", Object::String("foobar".to_string())),
+            ("let s = \"a\"; s += \"b\"; s", Object::String("ab".to_string())),
+            ("\"ab\" * 3", Object::String("ababab".to_string())),
+            ("2 * \"ab\"", Object::String("abab".to_string())),
+            ("\"ab\" * 0", Object::String(String::new())),
+            ("\"a\" == \"a\"", Object::Boolean(true)),
+            ("\"a\" == \"b\"", Object::Boolean(false)),
+            ("\"a\" != \"b\"", Object::Boolean(true)),
+            ("\"a\" < \"b\"", Object::Boolean(true)),
+            ("\"b\" > \"ab\"", Object::Boolean(true)),
+            ("\"a\" <= \"a\"", Object::Boolean(true)),
+            ("\"a\" >= \"b\"", Object::Boolean(false)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_null() {
+        let tests = vec![
+            ("null", Object::Null),
+            ("null == null", Object::Boolean(true)),
+            ("let x = null; x != 1", Object::Boolean(true)),
+            ("\"a\" == null", Object::Boolean(false)),
+            ("null ?? 5", Object::Integer(5)),
+            ("0 ?? 5", Object::Integer(0)),
+            ("false ?? 5", Object::Boolean(false)),
+            ("first([]) ?? \"empty\"", Object::String("empty".to_string())),
+            ("null ?? null ?? 3", Object::Integer(3)),
+            ("1 ?? missing", Object::Integer(1)),
+            ("null ?? 1 + 2", Object::Integer(3)),
+            ("is_null(null)", Object::Boolean(true)),
+            ("is_null(puts())", Object::Boolean(true)),
+            ("is_null(0)", Object::Boolean(false)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(expected), "input: {}", input);
+        }
+        assert_eq!(eval("null + 1").unwrap_err().to_string(), "type mismatch: NULL + INTEGER");
+    }
+
+    #[test]
+    fn test_match_expressions() {
+        let describe = "let describe = fn(n) { match n { 1 => \"one\", 2..5 => \"few\", _ => \"many\" } };";
+        let tests = vec![
+            (format!("{} describe(1)", describe), Object::String("one".to_string())),
+            (format!("{} describe(2)", describe), Object::String("few".to_string())),
+            (format!("{} describe(4)", describe), Object::String("few".to_string())),
+            (format!("{} describe(5)", describe), Object::String("many".to_string())),
+            (format!("{} describe(\"1\")", describe), Object::String("many".to_string())),
+            ("match -3 { -5..0 => \"negative\", 0 => \"zero\" }".to_string(), Object::String("negative".to_string())),
+            ("match 'q' { 'a'..'n' => 1, 'n'..'{' => 2 }".to_string(), Object::Integer(2)),
+            ("match \"b\" { \"a\" => 1, \"b\" => 2 }".to_string(), Object::Integer(2)),
+            ("match true { false => 0, true => 1 }".to_string(), Object::Integer(1)),
+            ("match null { 0 => 0, null => 1 }".to_string(), Object::Integer(1)),
+            // Nothing matched
+            ("match 7 { 1 => 1, 2..5 => 2 }".to_string(), Object::Null),
+            ("match 7 {}".to_string(), Object::Null),
+            // Only the first matching arm runs
+            ("let x = 0; match 1 { 1 => x += 1, _ => x += 10 }; x".to_string(), Object::Integer(1)),
+            ("let x = 2; match x * 2 { 4 => x + 1, _ => 0 } + 1".to_string(), Object::Integer(4)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(&input), Ok(expected), "input: {}", input);
+        }
+        assert_eq!(eval("match missing { _ => 1 }").unwrap_err().to_string(), "identifier not found: missing");
+    }
+
+    #[test]
+    fn test_ranges() {
+        let tests = vec![
+            ("let sum = 0; for (i in 1..5) { sum += i; } sum", Object::Integer(10)),
+            ("let sum = 0; for (i in 1..=5) { sum += i; } sum", Object::Integer(15)),
+            ("let n = 0; for (i in 5..1) { n++; } n", Object::Integer(0)),
+            ("map(1..4, fn(x) { x * 2 })", Object::Array(Rc::new(vec![Object::Integer(2), Object::Integer(4), Object::Integer(6)]))),
+            ("(0..10).filter(fn(x) { x % 3 == 0 }).len()", Object::Integer(4)),
+            ("(1..=4).reduce(1, fn(a, b) { a * b })", Object::Integer(24)),
+            ("len(-2..2) + len(3..1)", Object::Integer(4)),
+            ("1..=3 == 1..4", Object::Boolean(true)),
+            ("0..2 != 0..3", Object::Boolean(true)),
+            ("type(0..1)", Object::String("RANGE".to_string())),
+            ("let n = 3; match n { 0..3 => \"low\", _ => \"high\" }", Object::String("high".to_string())),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(expected), "input: {}", input);
+        }
+        assert_eq!(eval("1..=3").unwrap().inspect(), "1..4");
+
+        let tests = vec![
+            ("1..\"a\"", "range bounds must be integers, got INTEGER..STRING"),
+            ("1..2..3", "range bounds must be integers, got RANGE..INTEGER"),
+            ("(0..3)[0]", "index operator not supported: RANGE[INTEGER]"),
+            ("0..=9223372036854775807", "integer overflow: 0..=9223372036854775807"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).unwrap_err().to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_method_calls() {
+        let tests = vec![
+            ("[1, 2, 3].len()", Object::Integer(3)),
+            ("\"abc\".upper()", Object::String("ABC".to_string())),
+            ("\" a,b \".trim().split(\",\").reverse().join(\"-\")", Object::String("b-a".to_string())),
+            ("[1, 2, 3].map(fn(x) { x * 2 }).filter(fn(x) { x > 2 }).first()", Object::Integer(4)),
+            ("{\"a\": 1}.keys().len()", Object::Integer(1)),
+            ("let xs = [3, 1]; xs.push(2).sort() == null", Object::Boolean(false)),
+            ("\"hello\".slice(1, 3)", Object::String("el".to_string())),
+            // Members of structs are called, not dispatched on their type
+            ("struct Counter { len } let c = Counter { len: fn() { 7 } }; c.len()", Object::Integer(7)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(expected), "input: {}", input);
+        }
+
+        let tests = vec![
+            ("1.len()", "INTEGER has no member len"),
+            ("[1].upper()", "ARRAY has no member upper"),
+            ("\"a\".keys()", "STRING has no member keys"),
+            ("struct P { x } P { x: 1 }.len()", "struct P has no member len"),
+            ("[1].push()", "wrong number of arguments. got=1, want=2"),
+            ("struct P { x } P { x: 1 }.x()", "not a function: INTEGER"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).unwrap_err().to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_structs() {
+        let point = "struct Point { x, y }";
+        let tests = vec![
+            (format!("{} let p = Point {{ x: 1, y: 2 }}; p.x + p.y", point), Object::Integer(3)),
+            (format!("{} Point {{ y: 2, x: 1 }} == Point {{ x: 1, y: 2 }}", point), Object::Boolean(true)),
+            (format!("{} Point {{ x: 1, y: 2 }} != Point {{ x: 1, y: 3 }}", point), Object::Boolean(true)),
+            // Equality looks at the fields, not at which statement declared them
+            (format!("{} let a = Point {{ x: 1, y: [2] }}; {} a == Point {{ x: 1, y: [2] }}", point, point), Object::Boolean(true)),
+            (format!("{} struct Other {{ x, y }} Point {{ x: 1, y: 2 }} == Other {{ x: 1, y: 2 }}", point), Object::Boolean(false)),
+            (format!("{} Point {{ x: 1, y: 2 }} == null", point), Object::Boolean(false)),
+            (format!("{} let f = fn(p) {{ p.y }}; f(Point {{ x: 1, y: 2 }})", point), Object::Integer(2)),
+            (format!("{} match (Point {{ x: 1, y: 2 }}).x {{ 1 => true, _ => false }}", point), Object::Boolean(true)),
+            (format!("{} type(Point {{ x: 1, y: 2 }})", point), Object::String("STRUCT".to_string())),
+            ("struct Unit {} Unit {} == Unit {}".to_string(), Object::Boolean(true)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(&input), Ok(expected), "input: {}", input);
+        }
+        assert_eq!(eval(&format!("{} Point {{ y: \"a\", x: 1 }}", point)).unwrap().inspect(), "Point { x: 1, y: a }");
+        assert_eq!(eval(&format!("{} Point", point)).unwrap().inspect(), "struct Point { x, y }");
+
+        let tests = vec![
+            (format!("{} Point {{ x: 1 }}", point), "missing field y for Point"),
+            (format!("{} Point {{ x: 1, y: 2, x: 3 }}", point), "field x given twice for Point"),
+            (format!("{} Point {{ x: 1, y: 2, z: 3 }}", point), "struct Point has no member z"),
+            (format!("{} Point {{ x: 1, y: 2 }}.z", point), "struct Point has no member z"),
+            (format!("{} Point {{ x: 1, y: 2 }} < Point {{ x: 1, y: 2 }}", point), "unknown operator: STRUCT < STRUCT"),
+            ("let p = 1; p { x: 1 }".to_string(), "not a struct: INTEGER"),
+            ("Missing { x: 1 }".to_string(), "identifier not found: Missing"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(&input).unwrap_err().to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_to_string() {
+        let point = "struct Point { x, y, to_string } let point = fn(x, y) { Point { x: x, y: y, to_string: fn() { \"(${x}, ${y})\" } } };";
+        let tests = vec![
+            (format!("{} \"at ${{point(1, 2)}}\"", point), "at (1, 2)"),
+            (format!("{} format(\"{{}} {{:8}}|\", point(1, 2), point(3, 4))", point), "(1, 2) (3, 4)  |"),
+            (format!("{} \"${{[point(1, 2), ok(point(3, 4))]}}\"", point), "[(1, 2), ok((3, 4))]"),
+            (format!("{} point(1, 2).to_string()", point), "(1, 2)"),
+            (format!("{} \"at \" + point(1, 2) + \"!\"", point), "at (1, 2)!"),
+            ("struct Plain { x } \"p: \" + Plain { x: 1 }".to_string(), "p: Plain { x: 1 }"),
+            // It is not given the struct, so assigned members go unseen
+            (format!("{} let p = point(1, 2); p.x = 10; [p.x, \"${{p}}\"]", point), "[10, (1, 2)]"),
+            // Fields of any other kind named to_string are only data
+            ("struct Named { to_string } \"${Named { to_string: 1 }}\"".to_string(), "Named { to_string: 1 }"),
+            ("struct Plain { x } \"${Plain { x: 1 }}\"".to_string(), "Plain { x: 1 }"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(&input).map(|value| value.inspect()), Ok(expected.to_string()), "input: {}", input);
+        }
+        // The debug form and inspect leave it out
+        assert_eq!(eval(&format!("{} format(\"{{:?}}\", point(1, 2))", point)).unwrap().inspect().split(", to_string").next(), Some("Point { x: 1, y: 2"));
+        assert!(eval(&format!("{} point(1, 2)", point)).unwrap().inspect().starts_with("Point { x: 1, y: 2, to_string: fn() "));
+
+        let tests = vec![
+            ("struct Bad { to_string } \"${Bad { to_string: fn() { 1 } }}\"", "expected STRING, got INTEGER"),
+            ("struct Bad { to_string } \"${Bad { to_string: fn() { missing } }}\"", "identifier not found: missing"),
+            ("struct Bad { to_string } format(\"{}\", Bad { to_string: fn(x) { x } })", "wrong number of arguments. got=0, want=1"),
+            ("struct Bad { to_string } \"\" + Bad { to_string: fn() { 1 } }", "expected STRING, got INTEGER"),
+            ("struct Plain { x } 1 + Plain { x: 1 }", "type mismatch: INTEGER + STRUCT"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).unwrap_err().to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_operator_overloading() {
+        let vector = "struct V { x, y, __add, __mul, __eq, __lt, __index } \
+            let v = fn(x, y) { V { x: x, y: y, \
+                __add: fn(o) { v(x + o.x, y + o.y) }, \
+                __mul: fn(k) { v(x * k, y * k) }, \
+                __eq: fn(o) { x * x + y * y == o.x * o.x + o.y * o.y }, \
+                __lt: fn(o) { x * x + y * y < o.x * o.x + o.y * o.y }, \
+                __index: fn(i) { [x, y][i] } } };";
+        let tests = vec![
+            (format!("{} let s = v(1, 2) + v(10, 20); [s.x, s.y]", vector), "[11, 22]"),
+            (format!("{} (v(1, 2) * 3)[1]", vector), "6"),
+            (format!("{} [v(3, 4) == v(0, 5), v(3, 4) != v(0, 5), v(1, 1) != v(2, 2)]", vector), "[true, false, true]"),
+            (format!("{} [v(1, 1) < v(2, 0), v(0, 3) < v(2, 0)]", vector), "[true, false]"),
+            (format!("{} let a = v(1, 1); a += v(1, 2); a *= 2; [a[0], a[1]]", vector), "[4, 6]"),
+            // Only the left operand's method is looked at
+            (format!("{} let w = v(1, 2); [w[0], w[5]]", vector), "[1, null]"),
+            // Without a method the operator means what it always did
+            ("struct P { x } P { x: 1 } == P { x: 1 }".to_string(), "true"),
+            ("struct P { x, __eq } P { x: 1, __eq: 0 } == P { x: 1, __eq: 0 }".to_string(), "true"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(&input).map(|value| value.inspect()), Ok(expected.to_string()), "input: {}", input);
+        }
+
+        let tests = vec![
+            (format!("{} 2 * v(1, 2)", vector), "type mismatch: INTEGER * STRUCT"),
+            (format!("{} v(1, 2) - v(1, 2)", vector), "unknown operator: STRUCT - STRUCT"),
+            (format!("{} v(1, 2) + 1", vector), "INTEGER has no member x"),
+            ("struct P { x } P { x: 1 }[0]".to_string(), "index operator not supported: STRUCT[INTEGER]"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(&input).unwrap_err().to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_chars() {
+        let tests = vec![
+            ("'a'", Object::Char('a')),
+            ("'\\n'", Object::Char('\n')),
+            ("'a' == 'a'", Object::Boolean(true)),
+            ("'a' != 'b'", Object::Boolean(true)),
+            ("'a' < 'b'", Object::Boolean(true)),
+            ("'z' >= 'é'", Object::Boolean(false)),
+            ("type('a')", Object::String("CHAR".to_string())),
+            ("ord('A')", Object::Integer(65)),
+            ("ord('é')", Object::Integer(233)),
+            ("chr(97)", Object::Char('a')),
+            ("chr(ord('a') + 1)", Object::Char('b')),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(expected), "input: {}", input);
+        }
+
+        let tests = vec![
+            ("'a' + 'b'", "unknown operator: CHAR + CHAR"),
+            ("'a' == \"a\"", "type mismatch: CHAR == STRING"),
+            ("-'a'", "unknown operator: -CHAR"),
+            ("ord(\"a\")", "argument to `ord` not supported, got STRING"),
+            ("chr('a')", "argument to `chr` not supported, got CHAR"),
+            ("chr(-1)", "-1 is not a valid character code"),
+            ("chr(55296)", "55296 is not a valid character code"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).unwrap_err().to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_let_and_return_statements() {
+        let tests = vec![
+            ("let a = 5; a;", 5),
+            ("let a = 5 * 5; a;", 25),
+            ("let a = 5; let b = a; let c = a + b + 5; c;", 15),
+            ("return 10; 9;", 10),
+            ("9; return 2 * 5; 9;", 10),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(Object::Integer(expected)), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_postfix_updates_binding() {
+        assert_eq!(eval("let i = 1; i++"), Ok(Object::Integer(1)));
+        assert_eq!(eval("let i = 1; i++; i"), Ok(Object::Integer(2)));
+        assert_eq!(eval("let i = 1; i--; i--; i"), Ok(Object::Integer(-1)));
+    }
+
+    #[test]
+    fn test_prefix_updates_binding() {
+        assert_eq!(eval("let i = 1; ++i"), Ok(Object::Integer(2)));
+        assert_eq!(eval("let i = 1; --i; --i; i"), Ok(Object::Integer(-1)));
+        assert_eq!(eval("let i = 1; let j = ++i + i++; [i, j]"), Ok(Object::Array(Rc::new(vec![Object::Integer(3), Object::Integer(4)]))));
+        assert_eq!(eval("let f = fn() { let n = 0; fn() { ++n } }(); f(); f()"), Ok(Object::Integer(2)));
+        assert_eq!(
+            eval("let s = \"a\"; ++s"),
+            Err(EvalError::UnknownPrefixOperator { operator: "++".to_string(), right: "STRING" }),
+        );
+        assert_eq!(
+            eval("let s = \"a\"; s--"),
+            Err(EvalError::UnknownPostfixOperator { left: "STRING", operator: "--".to_string() }),
+        );
+    }
+
+    #[test]
+    fn test_assignment_expressions() {
+        let tests = vec![
+            ("let x = 0; x = 5; x", Object::Integer(5)),
+            ("let x = 0; x = 5", Object::Integer(5)),
+            ("let x = 1; x = x + 1; x = x * 10; x", Object::Integer(20)),
+            ("let a = 0; let b = 0; a = b = 7; a + b", Object::Integer(14)),
+            ("let x = 1; x = \"now a string\"; x", Object::String("now a string".to_string())),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(expected), "input: {}", input);
+        }
+
+        assert_eq!(eval("y = 5"), Err(EvalError::IdentifierNotFound("y".to_string())));
+        assert_eq!(eval("y += 5"), Err(EvalError::IdentifierNotFound("y".to_string())));
+        assert_eq!(eval("let x = 1; x = z"), Err(EvalError::IdentifierNotFound("z".to_string())));
+    }
+
+    #[test]
+    fn test_index_assignment() {
+        let tests = vec![
+            ("let a = [1, 2, 3]; a[0] = 9; a", "[9, 2, 3]"),
+            ("let a = [1, 2, 3]; a[0] = 9", "9"),
+            ("let h = {\"k\": 1}; h[\"k\"] = 2; h[\"new\"] = 3; h", "{k: 2, new: 3}"),
+            ("let a = [1, 2]; a[0] += 5; a[1] *= 10; a", "[6, 20]"),
+            ("let m = {\"xs\": [[1], [2]]}; m[\"xs\"][1][0] = 7; m", "{xs: [[1], [7]]}"),
+            ("let i = 0; let a = [0, 0]; a[i++] = 5; [a, i]", "[[5, 0], 1]"),
+            // The old value is a value of its own and keeps what it held
+            ("let a = [1, 2]; let b = a; a[0] = 9; [a, b]", "[[9, 2], [1, 2]]"),
+            ("let a = [1]; let f = fn() { a[0] = 2; a }; [f(), a]", "[[2], [2]]"),
+            ("let m = {\"xs\": [1]}; let n = m; let xs = m[\"xs\"]; m[\"xs\"][0] = 2; [m, n, xs]", "[{xs: [2]}, {xs: [1]}, [1]]"),
+            ("struct P { x, y } let p = P { x: 1, y: 2 }; p.x = 5; p.y += 1; p", "P { x: 5, y: 3 }"),
+            ("struct P { x } let ps = [P { x: 1 }]; ps[0].x = 2; ps", "[P { x: 2 }]"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).map(|value| value.inspect()), Ok(expected.to_string()), "input: {}", input);
+        }
+
+        let tests = vec![
+            ("let a = [1]; a[1] = 2", "index 1 out of range for length 1"),
+            ("let a = [1]; a[-1] = 2", "index -1 out of range for length 1"),
+            ("let a = [1]; a[\"k\"] = 2", "index operator not supported: ARRAY[STRING]"),
+            ("let s = \"ab\"; s[0] = \"c\"", "index operator not supported: STRING[INTEGER]"),
+            ("let h = {}; h[[1]] = 2", "unusable as hash key: ARRAY"),
+            ("let h = {}; h[\"a\"][\"b\"] = 1", "index operator not supported: NULL[STRING]"),
+            ("let a = [\"x\"]; a[0] += 1", "type mismatch: STRING + INTEGER"),
+            ("struct P { x } let p = P { x: 1 }; p.y = 2", "struct P has no member y"),
+            ("import \"std/math\"; math.sqrt = 3", "invalid assignment target: std/math.sqrt"),
+            ("b[0] = 1", "identifier not found: b"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).unwrap_err().to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_compound_assignment_expressions() {
+        let tests = vec![
+            ("let x = 10; x += 5; x", 15),
+            ("let x = 10; x -= 5; x", 5),
+            ("let x = 10; x *= 5; x", 50),
+            ("let x = 10; x /= 5; x", 2),
+            ("let x = 10; x %= 4; x", 2),
+            ("let x = 10; x += 5", 15),
+            ("let x = 1; let y = 2; x += y += 3; x", 6),
+            ("let total = 0; for (let i = 1; i <= 4; i += 1) { total += i; } total", 10),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(Object::Integer(expected)), "input: {}", input);
+        }
+
+        let tests = vec![
+            ("let x = 1; x /= 0", "division by zero"),
+            ("let x = true; x += 1", "type mismatch: BOOLEAN + INTEGER"),
+            ("let x = true; x -= false", "unknown operator: BOOLEAN - BOOLEAN"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).unwrap_err().to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_for_statements() {
+        let tests = vec![
+            ("let sum = 0; for (let i = 0; i < 5; i++) { sum = sum + i; } sum", Object::Integer(10)),
+            ("let n = 1; for (let i = 0; i < 4; i = i + 1) { n = n * 2; } n", Object::Integer(16)),
+            ("let n = 0; for (let i = 10; i > 0; i--) { let n = n + 1; } n", Object::Integer(10)),
+            ("for (let i = 0; i < 5; i++) { i; }", Object::Null),
+            ("for (let i = 0; i < 5; i++) { return i * 10; }", Object::Integer(0)),
+            ("let i = 7; for (i; false; i++) { } i", Object::Integer(7)),
+            ("let s = \"\"; for (c in \"abc\") { let s = c; } s", Object::String("c".to_string())),
+            ("let n = 0; for (c in \"\") { let n = 1; } n", Object::Integer(0)),
+            ("let sum = 0; for (x in [1, 2, 3]) { sum += x; } sum", Object::Integer(6)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(expected), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_blocks() {
+        let tests = vec![
+            ("{ let x = 1; x + 1 }", Object::Integer(2)),
+            ("let y = { let x = 2; x * 3 }; y", Object::Integer(6)),
+            ("let x = 1; { let x = 2; } x", Object::Integer(1)),
+            ("let x = 1; { x = 2; } x", Object::Integer(2)),
+            ("let x = 1; { let x = 10; x += 1; } x", Object::Integer(1)),
+            ("{ 1; { let z = 2; z + 1 } }", Object::Integer(3)),
+            ("{ let x = 1; }", Object::Null),
+            ("{ let x = 1; x; }", Object::Null),
+            ("let x = { 1; }; x", Object::Null),
+            ("let f = fn() { { return 5; } 6 }; f()", Object::Integer(5)),
+            ("let f = { let n = 3; fn() { n } }; f()", Object::Integer(3)),
+            ("{\"a\": 1}[\"a\"]", Object::Integer(1)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(expected), "input: {}", input);
+        }
+
+        assert_eq!(eval("{ let inner = 1; } inner"), Err(EvalError::IdentifierNotFound("inner".to_string())));
+        assert_eq!(eval("let v = { let inner = 1; inner }; inner"), Err(EvalError::IdentifierNotFound("inner".to_string())));
+    }
+
+    #[test]
+    fn test_function_object() {
+        match eval("fn(x) { x + 2; };") {
+            Ok(Object::Function(func)) => {
+                assert_eq!(func.parameters.len(), 1);
+                assert_eq!(func.parameters[0].value, "x");
+                assert_eq!(func.body.to_string(), "{ (x + 2); }");
+            },
+            other => panic!("object is not Function. got={:?}", other),
+        }
+        assert_eq!(eval("fn(x, y) { x }").unwrap().inspect(), "fn(x, y) { x }");
+    }
+
+    #[test]
+    fn test_function_application() {
+        let tests = vec![
+            ("let identity = fn(x) { x }; identity(5);", 5),
+            ("let identity = fn(x) { return x; }; identity(5);", 5),
+            ("let double = fn(x) { x * 2 }; double(5);", 10),
+            ("let add = fn(x, y) { x + y }; add(5, 5);", 10),
+            ("let add = fn(x, y) { x + y }; add(5 + 5, add(5, 5));", 20),
+            ("fn(x) { x }(5)", 5),
+            ("let f = fn() { return 1; 2; }; f() + 10", 11),
+            ("let x = 1; let f = fn(x) { x = x + 10; x }; f(5) + x", 16),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(Object::Integer(expected)), "input: {}", input);
+        }
+
+        // A semicolon after the last expression discards its value
+        assert_eq!(eval("let identity = fn(x) { x; }; identity(5)"), Ok(Object::Null));
+        assert_eq!(eval("let f = fn(x) { x += 1; }; f(5)"), Ok(Object::Null));
+
+        assert_eq!(eval("let f = fn(x) { x }; f(1, 2)").unwrap_err().to_string(), "wrong number of arguments. got=2, want=1");
+        assert_eq!(eval("let f = fn(x) { y }; f(1)").unwrap_err().to_string(), "identifier not found: y");
+        assert_eq!(eval("let f = fn(x) { let inner = 1; x }; f(1); inner").unwrap_err().to_string(), "identifier not found: inner");
+    }
+
+    #[test]
+    fn test_closures() {
+        let tests = vec![
+            ("let newAdder = fn(x) { fn(y) { x + y } }; let addTwo = newAdder(2); addTwo(2);", 4),
+            ("let adder = fn(x) { fn(y) { x + y } }; adder(3)(4)", 7),
+            // Captured bindings are shared, not copied, so updates persist between calls
+            ("let counter = fn() { let n = 0; fn() { n += 1; n } }; let next = counter(); next(); next(); next()", 3),
+            ("let c = fn() { let n = 0; fn() { n++ } }; let a = c(); let b = c(); a(); a(); b()", 0),
+            // Functions see later changes to the environment they were defined in
+            ("let x = 1; let f = fn() { x }; x = 5; f()", 5),
+            ("let total = 0; let add = fn(n) { total += n }; for (i in [1, 2, 3]) { add(i); } total", 6),
+            ("let apply = fn(f, x) { f(x) }; apply(fn(n) { n * 3 }, 4)", 12),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(Object::Integer(expected)), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_array_literals() {
+        assert_eq!(
+            eval("[1, 2 * 2, 3 + 3]"),
+            Ok(Object::Array(Rc::new(vec![Object::Integer(1), Object::Integer(4), Object::Integer(6)])))
+        );
+        assert_eq!(eval("[]"), Ok(Object::Array(Rc::new(vec![]))));
+        assert_eq!(eval("[1, [true, \"a\"]]").unwrap().inspect(), "[1, [true, a]]");
+    }
+
+    #[test]
+    fn test_builtin_functions() {
+        let tests = vec![
+            ("len(\"\")", Object::Integer(0)),
+            ("len(\"four\")", Object::Integer(4)),
+            ("len(\"héllo\")", Object::Integer(5)),
+            ("len([1, 2, 3])", Object::Integer(3)),
+            ("first([1, 2, 3])", Object::Integer(1)),
+            ("first([])", Object::Null),
+            ("last([1, 2, 3])", Object::Integer(3)),
+            ("last([])", Object::Null),
+            ("rest([1, 2, 3])", Object::Array(Rc::new(vec![Object::Integer(2), Object::Integer(3)]))),
+            ("rest([])", Object::Null),
+            ("push([], 1)", Object::Array(Rc::new(vec![Object::Integer(1)]))),
+            ("let a = [1]; push(a, 2); a", Object::Array(Rc::new(vec![Object::Integer(1)]))),
+            ("type(1)", Object::String("INTEGER".to_string())),
+            ("type([])", Object::String("ARRAY".to_string())),
+            ("type(len)", Object::String("BUILTIN".to_string())),
+            ("puts(\"hello\")", Object::Null),
+            ("let len = 3; len", Object::Integer(3)),
+            ("assert(1 < 2)", Object::Null),
+            ("assert(\"yes\", \"unused\")", Object::Null),
+            ("assert_eq([1, \"a\"], [1, \"a\"])", Object::Null),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(expected), "input: {}", input);
+        }
+
+        let tests = vec![
+            ("len(1)", "argument to `len` not supported, got INTEGER"),
+            ("len(\"one\", \"two\")", "wrong number of arguments. got=2, want=1"),
+            ("first(1)", "argument to `first` not supported, got INTEGER"),
+            ("push(1, 1)", "argument to `push` not supported, got INTEGER"),
+            ("push([])", "wrong number of arguments. got=1, want=2"),
+            ("5(1)", "not a function: INTEGER"),
+            ("nope(1)", "identifier not found: nope"),
+            ("assert(1 > 2)", "assertion failed"),
+            ("assert(null, \"no value\")", "assertion failed: no value"),
+            ("assert()", "wrong number of arguments. got=0, want=2"),
+            ("assert_eq(1 + 1, 3)", "assertion failed: 2 != 3"),
+            ("assert_eq(\"1\", 1)", "assertion failed: \"1\" != 1"),
+            ("assert_eq('a', [\"a\"])", "assertion failed: 'a' != [a]"),
+            (
+                "assert_eq([\"apple\", \"banana\", \"cherry\"], [\"apple\", \"blueberry\", \"cherry\"])",
+                "assertion failed: values differ (- left, + right)\n  [\n      \"apple\",\n-     \"banana\",\n+     \"blueberry\",\n      \"cherry\",\n  ]",
+            ),
+            ("panic(\"out of cheese\")", "panicked: out of cheese"),
+            ("panic([1])", "panicked: [1]"),
+            // Unlike a throw, a panic goes straight past a try
+            ("try { panic(\"stop\") } catch (e) { 0 }", "panicked: stop"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).unwrap_err().to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_string_builtins() {
+        let strings = |items: &[&str]| Object::Array(Rc::new(items.iter().map(|s| Object::from(*s)).collect()));
+        let tests = vec![
+            ("split(\"a,b,,c\", \",\")", strings(&["a", "b", "", "c"])),
+            ("split(\"héllo\", \"\")", strings(&["h", "é", "l", "l", "o"])),
+            ("split(\"\", \",\")", strings(&[""])),
+            ("trim(\"  padded  \")", Object::from("padded")),
+            ("upper(\"straße\")", Object::from("STRASSE")),
+            ("lower(\"ÉCOLE\")", Object::from("école")),
+            ("contains(\"haystack\", \"st\")", Object::Boolean(true)),
+            ("contains(\"haystack\", \"needle\")", Object::Boolean(false)),
+            ("replace(\"a-b-c\", \"-\", \"+\")", Object::from("a+b+c")),
+            ("starts_with(\"prefix\", \"pre\")", Object::Boolean(true)),
+            ("starts_with(\"prefix\", \"fix\")", Object::Boolean(false)),
+            ("slice(\"héllo\", 1, 3)", Object::from("él")),
+            ("slice(\"héllo\", 2)", Object::from("llo")),
+            ("slice(\"héllo\", -5, 99)", Object::from("héllo")),
+            ("slice(\"héllo\", 3, 1)", Object::from("")),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(expected), "input: {}", input);
+        }
+
+        let tests = vec![
+            ("split(\"a b\")", "wrong number of arguments. got=1, want=2"),
+            ("split(1, \",\")", "argument 1 to `split` must be STRING, got INTEGER"),
+            ("contains(\"abc\", 'a')", "argument 2 to `contains` must be STRING, got CHAR"),
+            ("replace(\"abc\", \"a\", null)", "argument 3 to `replace` must be STRING, got NULL"),
+            ("slice(\"abc\", \"1\")", "argument 2 to `slice` must be INTEGER, got STRING"),
+            ("slice(\"abc\")", "wrong number of arguments. got=1, want=3"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).unwrap_err().to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_array_builtins() {
+        let ints = |items: &[i64]| Object::Array(Rc::new(items.iter().map(|i| Object::Integer(*i)).collect()));
+        let tests = vec![
+            ("pop([1, 2, 3])", ints(&[1, 2])),
+            ("pop([])", Object::Null),
+            ("insert([1, 3], 1, 2)", ints(&[1, 2, 3])),
+            ("insert([1], 1, 2)", ints(&[1, 2])),
+            ("remove([1, 2, 3], 0)", ints(&[2, 3])),
+            ("sort([3, -1, 2, 2])", ints(&[-1, 2, 2, 3])),
+            ("sort([\"pear\", \"apple\"])", Object::Array(Rc::new(vec![Object::from("apple"), Object::from("pear")]))),
+            ("sort([])", ints(&[])),
+            ("reverse([1, 2, 3])", ints(&[3, 2, 1])),
+            ("join([1, \"a\", 'b', [2]], \", \")", Object::from("1, a, b, [2]")),
+            ("join([], \"-\")", Object::from("")),
+            ("concat([1], [], [2, 3])", ints(&[1, 2, 3])),
+            ("concat()", ints(&[])),
+            ("slice([1, 2, 3, 4], 1, 3)", ints(&[2, 3])),
+            ("slice([1, 2, 3], 1)", ints(&[2, 3])),
+            ("slice([1, 2, 3], -1, 10)", ints(&[1, 2, 3])),
+            ("len(concat([1], [2]))", Object::Integer(2)),
+            // Builtins return new arrays and never change their arguments
+            ("let xs = [3, 1, 2]; sort(xs); reverse(xs); pop(xs); insert(xs, 0, 0); remove(xs, 0); xs", ints(&[3, 1, 2])),
+            ("let xs = [1]; let ys = xs; xs = push(xs, 2); [len(xs), len(ys)]", ints(&[2, 1])),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(expected), "input: {}", input);
+        }
+
+        let tests = vec![
+            ("insert([1], 2, 0)", "index 2 out of range for length 1"),
+            ("remove([], 0)", "index 0 out of range for length 0"),
+            ("remove([1], -1)", "index -1 out of range for length 1"),
+            ("sort([1, \"a\"])", "type mismatch: STRING < INTEGER"),
+            ("sort(\"cab\")", "argument 1 to `sort` must be ARRAY, got STRING"),
+            ("join([1], 1)", "argument 2 to `join` must be STRING, got INTEGER"),
+            ("concat([1], 2)", "argument 2 to `concat` must be ARRAY, got INTEGER"),
+            ("pop(1)", "argument to `pop` not supported, got INTEGER"),
+            ("slice(1, 0)", "argument to `slice` not supported, got INTEGER"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).unwrap_err().to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_higher_order_builtins() {
+        let ints = |items: &[i64]| Object::Array(Rc::new(items.iter().map(|i| Object::Integer(*i)).collect()));
+        let tests = vec![
+            ("map([1, 2, 3], fn(x) { x * 2 })", ints(&[2, 4, 6])),
+            ("map([\"a\", \"b\"], upper)", Object::Array(Rc::new(vec![Object::from("A"), Object::from("B")]))),
+            ("let offset = 10; map([1], fn(x) { x + offset })", ints(&[11])),
+            ("filter([1, 2, 3, 4], fn(x) { x % 2 == 0 })", ints(&[2, 4])),
+            ("filter([1, null, 2], fn(x) { x })", ints(&[1, 2])),
+            ("reduce([1, 2, 3], 10, fn(acc, x) { acc + x })", Object::Integer(16)),
+            ("reduce([], 0, fn(acc, x) { acc + x })", Object::Integer(0)),
+            ("let total = 0; each([1, 2], fn(x) { total += x; }); total", Object::Integer(3)),
+            ("each([], fn(x) { x })", Object::Null),
+            ("find([1, 5, 7], fn(x) { x > 2 })", Object::Integer(5)),
+            ("find([1, 2], fn(x) { x > 2 })", Object::Null),
+            ("map(map([[1], [2, 3]], len), fn(n) { map([n], fn(m) { m * n }) })", Object::Array(Rc::new(vec![ints(&[1]), ints(&[4])]))),
+            ("let f = fn(x) { return x * 3; 0 }; map([1], f)", ints(&[3])),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(expected), "input: {}", input);
+        }
+
+        let tests = vec![
+            ("map([1], fn(a, b) { a })", "wrong number of arguments. got=1, want=2"),
+            ("map([1], 1)", "not a function: INTEGER"),
+            ("filter(\"ab\", fn(c) { true })", "argument 1 to `filter` must be ARRAY, got STRING"),
+            ("reduce([1], fn(acc, x) { acc })", "wrong number of arguments. got=2, want=3"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).unwrap_err().to_string(), expected, "input: {}", input);
+        }
+
+        // Errors inside a callback point at the callback, not at the builtin
+        let input = "let xs = [1, 0];\nmap(xs, fn(x) { 1 / x })";
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let err = eval_program(&program, &Rc::new(RefCell::new(Environment::new()))).unwrap_err();
+        assert_eq!(err.error, EvalError::DivisionByZero);
+        assert_eq!(err.span, Some(Span { line: 2, column: 19 }));
+        let trace: Vec<(String, Span)> = err.trace.into_iter().map(|f| (f.function, f.call_site)).collect();
+        assert_eq!(trace, vec![("map".to_string(), Span { line: 2, column: 4 })]);
+    }
+
+    #[test]
+    fn test_hashes() {
+        let tests = vec![
+            ("{\"b\": 2, \"a\": 1}", "{a: 1, b: 2}"),
+            ("{2: \"two\", true: 1, \"x\": null, 1: [1]}", "{1: [1], 2: two, true: 1, x: null}"),
+            ("{\"k\": 1, \"k\": 2}", "{k: 2}"),
+            ("let key = \"n\"; {key: 1 + 1}[\"n\"]", "2"),
+            ("{\"a\": 1}[\"b\"]", "null"),
+            ("{1: \"int\", true: \"bool\"}[true]", "bool"),
+            ("len({1: 1, 2: 2})", "2"),
+            ("keys({\"b\": 1, \"a\": 2, 3: 3})", "[3, a, b]"),
+            ("values({\"b\": 1, \"a\": 2})", "[2, 1]"),
+            ("[has_key({1: null}, 1), has_key({1: null}, 2)]", "[true, false]"),
+            ("delete({1: 1, 2: 2}, 1)", "{2: 2}"),
+            ("delete({}, \"missing\")", "{}"),
+            ("merge({1: \"a\", 2: \"b\"}, {2: \"c\"}, {3: \"d\"})", "{1: a, 2: c, 3: d}"),
+            ("let h = {1: 1}; delete(h, 1); merge(h, {2: 2}); h", "{1: 1}"),
+            ("let out = []; for (k in {\"b\": 1, \"a\": 2}) { out = push(out, k); } out", "[a, b]"),
+            ("assert_eq({1: [2]}, {1: [2]})", "null"),
+            ("type({})", "HASH"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).map(|value| value.inspect()), Ok(expected.to_string()), "input: {}", input);
+        }
+
+        let tests = vec![
+            ("{[1]: 2}", "unusable as hash key: ARRAY"),
+            ("{\"a\": 1}[fn(x) { x }]", "unusable as hash key: FUNCTION"),
+            ("has_key({}, null)", "unusable as hash key: NULL"),
+            ("keys([1])", "argument 1 to `keys` must be HASH, got ARRAY"),
+            ("merge({}, 1)", "argument 2 to `merge` must be HASH, got INTEGER"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).unwrap_err().to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let tests = vec![
+            ("format(\"x = {}, y = {}\", 1, [true, \"s\"])", "x = 1, y = [true, s]"),
+            ("format(\"no placeholders\")", "no placeholders"),
+            ("format(\"{:02}:{:02}\", 7, 30)", "07:30"),
+            ("format(\"{:03}\", -5)", "-05"),
+            ("format(\"[{:4}] [{:4}]\", 12, \"ab\")", "[  12] [ab  ]"),
+            ("format(\"{:1}\", 12345)", "12345"),
+            ("format(\"{{}} {{{}}}\", \"é\")", "{} {é}"),
+            ("format(\"{}\", null)", "null"),
+            ("format(\"{:?}\", [\"s\", 'c', {\"k\": ok(1)}])", "[\"s\", 'c', {\"k\": ok(1)}]"),
+            ("print(\"no newline {}\", 1)", "null"),
+            ("println(\"{} items\", 3)", "null"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).map(|value| value.inspect()), Ok(expected.to_string()), "input: {}", input);
+        }
+
+        let tests = vec![
+            ("format(\"{} {}\", 1)", "invalid format string: more placeholders than values"),
+            ("format(\"{}\", 1, 2)", "invalid format string: more values than placeholders"),
+            ("format(\"{\")", "invalid format string: unclosed `{`"),
+            ("format(\"}\")", "invalid format string: unmatched `}`"),
+            ("format(\"{:02}\", \"a\")", "invalid format string: bad placeholder `{:02}` for STRING"),
+            ("format(\"{x}\", 1)", "invalid format string: bad placeholder `{x}` for INTEGER"),
+            ("format()", "wrong number of arguments. got=0, want=1"),
+            ("format(1)", "argument 1 to `format` must be STRING, got INTEGER"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).unwrap_err().to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_index_expressions() {
+        let tests = vec![
+            ("\"héllo\"[1]", Object::from("é")),
+            ("let s = \"abc\"; s[len(s) - 1]", Object::from("c")),
+            ("\"abc\"[3]", Object::Null),
+            ("\"abc\"[-1]", Object::Null),
+            ("[1, 2, 3][0]", Object::Integer(1)),
+            ("let i = 1; [1, 2, 3][i + 1]", Object::Integer(3)),
+            ("[[1, 2]][0][1]", Object::Integer(2)),
+            ("[1, 2, 3][3]", Object::Null),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), Ok(expected), "input: {}", input);
+        }
+
+        let program = Parser::new(Lexer::new("let s = \"abc\";\ns[true]")).parse_program().unwrap();
+        let err = eval_program(&program, &Rc::new(RefCell::new(Environment::new()))).unwrap_err();
+        assert_eq!(err.error, EvalError::IndexNotSupported { left: "STRING", index: "BOOLEAN" });
+        assert_eq!(err.span, Some(Span { line: 2, column: 2 }));
+        assert_eq!(eval("1[0]").unwrap_err().to_string(), "index operator not supported: INTEGER[INTEGER]");
+    }
+
+    fn double(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+        match args.as_slice() {
+            [Object::Integer(i)] => Ok(Object::Integer(i * 2)),
+            _ => Err(EvalError::UnsupportedArgument { function: "double", got: "ARGUMENTS" }),
+        }
+    }
+
+    fn custom_len(_: &mut dyn Runtime, _: Vec<Object>) -> Result<Object, EvalError> {
+        Ok(Object::String("custom len".to_string()))
+    }
+
+    #[test]
+    fn test_registered_builtins() {
+        let l = Lexer::new("double(21) + len(\"abc\")");
+        let program = Parser::new(l).parse_program().unwrap();
+        let env = Rc::new(RefCell::new(Environment::new()));
+        env.borrow_mut().register_builtin("double", double);
+        assert_eq!(eval_program(&program, &env), Ok(Object::Integer(45)));
+
+        // Registered builtins take precedence over the default ones
+        env.borrow_mut().register_builtin("len", custom_len);
+        let l = Lexer::new("[double(21), len()]");
+        let program = Parser::new(l).parse_program().unwrap();
+        assert_eq!(eval_program(&program, &env).unwrap().inspect(), "[42, custom len]");
+    }
+
+    #[test]
+    fn test_error_handling() {
+        let tests = vec![
+            ("5 + true;", "type mismatch: INTEGER + BOOLEAN"),
+            ("5 + true; 5;", "type mismatch: INTEGER + BOOLEAN"),
+            ("-true", "unknown operator: -BOOLEAN"),
+            ("true + false;", "unknown operator: BOOLEAN + BOOLEAN"),
+            ("5; true + false; 5", "unknown operator: BOOLEAN + BOOLEAN"),
+            ("foobar", "identifier not found: foobar"),
+            ("10 / 0", "division by zero"),
+            ("10 % 0", "division by zero"),
+            ("let b = true; ++b", "unknown operator: ++BOOLEAN"),
+            ("let b = true; b++", "unknown operator: BOOLEAN++"),
+            ("for (x in 5) { x; }", "cannot iterate over INTEGER"),
+            ("\"a\" - \"b\"", "unknown operator: STRING - STRING"),
+            ("\"a\" / \"b\"", "unknown operator: STRING / STRING"),
+            ("\"a\" - 1", "type mismatch: STRING - INTEGER"),
+            ("1 / \"a\"", "type mismatch: INTEGER / STRING"),
+            ("\"a\" + 1", "type mismatch: STRING + INTEGER"),
+            ("\"a\" * -1", "cannot repeat a string -1 times"),
+            ("\"ab\" * 9223372036854775807", "resource limit exceeded: strings of 1073741824 bytes"),
+            ("\"abc\" * 9223372036854775807", "integer overflow: 3 * 9223372036854775807"),
+        ];
+
+        for (input, expected) in tests {
+            match eval(input) {
+                Err(err) => assert_eq!(err.to_string(), expected, "input: {}", input),
+                Ok(obj) => panic!("no error returned for {}. got={:?}", input, obj),
+            }
+        }
+    }
+
+    #[test]
+    fn test_runtime_error_location() {
+        let input = "let inner = fn(x) { x / 0 };\nlet outer = fn() { inner(1) };\nouter();";
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let env = Rc::new(RefCell::new(Environment::new()));
+        let err = eval_program(&program, &env).unwrap_err();
+
+        assert_eq!(err.error, EvalError::DivisionByZero);
+        assert_eq!(err.span, Some(Span { line: 1, column: 23 }));
+        let trace: Vec<(String, Span)> = err.trace.into_iter().map(|f| (f.function, f.call_site)).collect();
+        assert_eq!(trace, vec![
+            ("inner".to_string(), Span { line: 2, column: 25 }),
+            ("outer".to_string(), Span { line: 3, column: 6 }),
+        ]);
+
+        let err = eval_program(&Parser::new(Lexer::new("missing")).parse_program().unwrap(), &env).unwrap_err();
+        assert_eq!(err.span, Some(Span { line: 1, column: 1 }));
+        assert!(err.trace.is_empty());
+    }
+
+    #[test]
+    fn test_try_catch() {
+        let tests = vec![
+            ("try { 1 + 1 } catch (e) { 0 }", Ok(Object::Integer(2))),
+            ("try { 1 / 0 } catch (e) { e }", Ok(Object::String("division by zero".to_string()))),
+            ("try { throw {\"code\": 7}; } catch (e) { e[\"code\"] }", Ok(Object::Integer(7))),
+            ("let f = fn(n) { throw n * 2; }; try { f(3); 0 } catch (e) { e }", Ok(Object::Integer(6))),
+            ("try { try { throw 1; } catch (e) { throw e + 1; } } catch (e) { e * 10 }", Ok(Object::Integer(20))),
+            ("try { map([1, 2, 3], fn(x) { throw x; }) } catch (e) { e }", Ok(Object::Integer(1))),
+            ("try { throw 1; } catch (e) {}", Ok(Object::Null)),
+            // Whatever the body had done so far is dropped
+            ("[1, 2, try { [3, 1 / 0] } catch (e) { 4 }, 5]", eval("[1, 2, 4, 5]")),
+            ("let f = fn() { try { return 1; } catch (e) { 2 }; 3 }; f()", Ok(Object::Integer(1))),
+            ("let x = 1; try { x = 2; throw x; } catch (e) { x += e; } x", Ok(Object::Integer(4))),
+            ("try { throw 1; } catch (e) { e }; e", Err(EvalError::IdentifierNotFound("e".to_string()))),
+            ("try { throw 1; } catch (e) { e / 0 }", Err(EvalError::DivisionByZero)),
+            ("throw \"boom\";", Err(EvalError::Thrown(Box::new(Object::String("boom".to_string()))))),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+
+        let err = eval_program(&Parser::new(Lexer::new("let f = fn() { throw [1]; };\nf();")).parse_program().unwrap(), &Rc::new(RefCell::new(Environment::new()))).unwrap_err();
+        assert_eq!(err.to_string(), "uncaught exception: [1] at line 1, col 16\n    in f called at line 2, col 2");
+    }
+
+    #[test]
+    fn test_results() {
+        let tests = vec![
+            ("ok(1)", Ok(Object::Result(Ok(Box::new(Object::Integer(1)))))),
+            ("[is_ok(ok(1)), err(\"no\").is_ok()]", eval("[true, false]")),
+            ("[unwrap_or(ok(1), 0), err(\"no\").unwrap_or(0), unwrap_or(null, 0), unwrap_or(5, 0)]", eval("[1, 0, 0, 5]")),
+            ("[ok(1) == ok(1), ok(1) == err(1), err([1]) != err([2])]", eval("[true, false, true]")),
+            ("type(err(1))", Ok(Object::String("RESULT".to_string()))),
+            ("is_ok(1)", Err(EvalError::InvalidArgument { function: "is_ok".to_string(), position: 1, expected: "RESULT", got: "INTEGER" })),
+            // `?` unwraps an ok and returns an err or null from the function
+            ("let f = fn(r) { r? + 1 }; [f(ok(1)), f(err(\"no\")), f(null), f(2)]", eval("[2, err(\"no\"), null, 3]")),
+            ("let parse = fn(s) { s == \"\" ? err(\"empty\") : ok(len(s)) };
+              let total = fn(a, b) { ok(parse(a)? + parse(b)?) };
+              [total(\"ab\", \"c\"), total(\"ab\", \"\")]", eval("[ok(3), err(\"empty\")]")),
+            // Only the innermost function returns, leaving what its caller
+            // was in the middle of
+            ("let f = fn() { [1, 2, err(3)?, 4] }; [0, f(), 5]", eval("[0, err(3), 5]")),
+            ("let f = fn() { for (let i = 0; i < 3; i++) { err(i)?; } 9 }; f()", eval("err(0)")),
+            ("let f = fn() { map([ok(1), err(2), ok(3)], fn(r) { r? * 10 }) }; f()", eval("[10, err(2), 30]")),
+            ("let f = fn() { try { err(1)? } catch (e) { 2 } }; f()", eval("err(1)")),
+            // Outside of a function it ends the program
+            ("let a = 1; err(a)?; a + 1", eval("err(1)")),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_floats() {
+        let tests = vec![
+            ("1.5 + 2.25", Ok(Object::Float(3.75))),
+            ("-0.5 * 4.0", Ok(Object::Float(-2.0))),
+            ("7.5 % 2.0", Ok(Object::Float(1.5))),
+            // An integer meeting a float is converted to one
+            ("1 + 0.5", Ok(Object::Float(1.5))),
+            ("3 / 2.0", Ok(Object::Float(1.5))),
+            ("[1 == 1.0, 2 > 1.5, 1.0 != 1, 0.1 + 0.2 == 0.3]", eval("[true, true, false, false]")),
+            // Only between numbers, and integers stay integers among themselves
+            ("3 / 2", Ok(Object::Integer(1))),
+            ("1.5 + \"a\"", Err(EvalError::TypeMismatch { left: "FLOAT", operator: "+".to_string(), right: "STRING" })),
+            ("1.0 / 0", Err(EvalError::DivisionByZero)),
+            ("1.5 % 0.0", Err(EvalError::DivisionByZero)),
+            ("-(2.5)", Ok(Object::Float(-2.5))),
+            ("type(1.0)", Ok(Object::String("FLOAT".to_string()))),
+            ("\"${2.0} ${0.1 + 0.2} ${-1.5}\"", Ok(Object::String("2.0 0.30000000000000004 -1.5".to_string()))),
+            ("{1.5: 1}", Err(EvalError::UnhashableKey("FLOAT"))),
+            ("match 2.5 { 2.5 => \"yes\", _ => \"no\" }", Ok(Object::String("yes".to_string()))),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_math_module() {
+        let tests = vec![
+            ("[math.sqrt(9), math.pow(2, 10), math.pow(2, -2), math.pow(2.0, 3)]", eval("[3.0, 1024, 0.25, 8.0]")),
+            ("[math.floor(-1.5), math.ceil(-1.5), math.round(-1.5), math.round(7)]", eval("[-2, -1, -2, 7]")),
+            ("[math.min(2, 1.5), math.max(2, 1.5), math.abs(-0.5)]", eval("[1.5, 2, 0.5]")),
+            ("math.PI * 2 > 6.28", Ok(Object::Boolean(true))),
+            ("math.pow(2, 63)", Err(EvalError::IntegerOverflow("pow(2, 63)".to_string()))),
+            ("math.floor(math.pow(10.0, 400))", Err(EvalError::IntegerOverflow("floor(inf)".to_string()))),
+            ("math.round(math.pow(10.0, 19))", Err(EvalError::IntegerOverflow("round(1e19)".to_string()))),
+            ("math.sqrt(\"4\")", Err(EvalError::InvalidArgument { function: "sqrt".to_string(), position: 1, expected: "NUMBER", got: "STRING" })),
+        ];
+
+        for (input, expected) in tests {
+            let input = format!("import \"std/math\"; {}", input);
+            assert_eq!(eval(&input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_random_builtins() {
+        let tests = vec![
+            ("seed(1); rand() == rand()", Ok(Object::Boolean(false))),
+            ("len(filter(0..200, fn(_) { let n = rand_int(3, 6); n < 3 ? true : n >= 6 }))", Ok(Object::Integer(0))),
+            ("rand_int(7, 8)", Ok(Object::Integer(7))),
+            ("rand_int(-9223372036854775807 - 1, 9223372036854775807) != null", Ok(Object::Boolean(true))),
+            ("rand() >= 0", Ok(Object::Boolean(true))),
+            ("let xs = [1, 2, 3, 4]; [sort(xs.shuffle()), xs]", eval("[[1, 2, 3, 4], [1, 2, 3, 4]]")),
+            ("shuffle([])", eval("[]")),
+            ("rand_int(3, 3)", Err(EvalError::EmptyRange { start: 3, end: 3 })),
+            ("shuffle(1)", Err(EvalError::InvalidArgument { function: "shuffle".to_string(), position: 1, expected: "ARRAY", got: "INTEGER" })),
+            ("seed(\"a\")", Err(EvalError::InvalidArgument { function: "seed".to_string(), position: 1, expected: "INTEGER", got: "STRING" })),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "input: {}", input);
+        }
+
+        // Seeded the same, the numbers are the same
+        let draw = "[rand(), rand_int(-5, 5), shuffle(0..10)]";
+        let first = eval(&format!("seed(1); {}", draw));
+        assert_eq!(eval(&format!("seed(1); {}; seed(1); {}", draw, draw)), first);
+        assert_ne!(eval(&format!("seed(2); {}", draw)), first);
+    }
+
+    #[test]
+    fn test_try_catch_limits() {
+        let config = EvalConfig { limits: Limits { max_steps: Some(1000), max_depth: Some(10), ..Limits::default() }, ..EvalConfig::default() };
+        let env = Rc::new(RefCell::new(Environment::new()));
+        env.borrow_mut().set_config(config);
+        let run = |input: &str| eval_program(&Parser::new(Lexer::new(input)).parse_program().unwrap(), &env).map_err(|err| err.error);
+
+        // Calls left by a throw no longer count towards the depth
+        let input = "let f = fn(n) { n == 0 ? 1 / 0 : f(n - 1) }; let caught = 0; for (let i = 0; i < 5; i++) { try { f(8) } catch (e) { caught++ } } caught";
+        assert_eq!(run(input), Ok(Object::Integer(5)));
+        // as do calls returned from early with `?`
+        let input = "let f = fn(n) { n == 0 ? err(0) : ok(f(n - 1)?) }; let errs = 0; for (let i = 0; i < 5; i++) { errs += f(8) == err(0) ? 1 : 0 } errs";
+        assert_eq!(run(input), Ok(Object::Integer(5)));
+        // Running out of a resource cannot be caught
+        let input = "try { for (let i = 0; true; i++) {} } catch (e) { 0 }";
+        assert!(matches!(run(input), Err(EvalError::ResourceExceeded(_))));
+    }
+
+    fn eval_with_modules(input: &str, modules: &[(&str, &str)]) -> Result<Object, RuntimeError> {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        for (name, source) in modules {
+            env.borrow().modules().borrow_mut().add_source(name, *source);
+        }
+        eval_program(&Parser::new(Lexer::new(input)).parse_program().unwrap(), &env)
+    }
+
+    #[test]
+    fn test_modules() {
+        let modules = [
+            ("lib/math", "let square = fn(x) { x * x }; let twice = fn(x) { add(x, x) }; let add = fn(a, b) { a + b };"),
+            ("counter", "let n = 0; let next = fn() { n += 1; n };"),
+            ("uses_builtins", "let size = len([1, 2, 3]);"),
+        ];
+        let tests: Vec<(&str, Result<Object, String>)> = vec![
+            ("import \"lib/math\"; math.square(4)", Ok(Object::Integer(16))),
+            ("import \"lib/math.mky\"; math.twice(4)", Ok(Object::Integer(8))),
+            ("let m = import(\"lib/math\"); let sq = m.square; sq(3)", Ok(Object::Integer(9))),
+            ("let add = import \"lib/math\".add; add(1, 2)", Ok(Object::Integer(3))),
+            // The second import gets the same module, state included
+            ("import \"counter\"; counter.next(); let again = import(\"counter\"); again.next()", Ok(Object::Integer(2))),
+            ("import \"uses_builtins\"; uses_builtins.size", Ok(Object::Integer(3))),
+            ("let f = fn() { import \"counter\"; counter.next() }; f()", Ok(Object::Integer(1))),
+            ("type(import(\"counter\"))", Ok(Object::String("MODULE".to_string()))),
+        ];
+
+        for (input, expected) in tests {
+            let result = eval_with_modules(input, &modules).map_err(|err| err.error.to_string());
+            assert_eq!(result, expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_module_errors() {
+        let modules = [
+            ("a", "import \"b\"; let x = 1;"),
+            ("b", "import \"c\";"),
+            ("c", "import \"a\";"),
+            ("self", "let me = import(\"self\");"),
+            ("bad", "let = 1;"),
+            ("boom", "let f = fn() { 1 / 0 };\nf();"),
+            ("nested", "import \"missing\";"),
+            ("peek", "let s = secret;"),
+            ("ok", "let x = 1;"),
+        ];
+        let tests = vec![
+            ("import \"a\"", "import cycle: a -> b -> c -> a"),
+            ("import \"self\"", "import cycle: self -> self"),
+            ("import \"missing\"", "module not found: missing"),
+            ("import \"nested\"", "module not found: missing"),
+            ("import \"bad\"", "cannot import bad: expected next token to be Identifier, got Assign at line 1, col 5"),
+            ("import \"boom\"", "cannot import boom: division by zero at line 1, col 18\n    in f called at line 2, col 2"),
+            // Modules do not see the bindings of the importer
+            ("let secret = 1; import \"peek\"", "cannot import peek: identifier not found: secret at line 1, col 9"),
+            ("import \"ok\"; ok.y", "module ok has no member y"),
+            ("let n = 5; n.x", "INTEGER has no member x"),
+        ];
+
+        for (input, expected) in tests {
+            let result = eval_with_modules(input, &modules).map_err(|err| err.error.to_string());
+            assert_eq!(result, Err(expected.to_string()), "input: {}", input);
+        }
+
+        // A failed import can be retried once the module is fixed
+        let err = eval_with_modules("import \"boom\"; 1", &modules).unwrap_err();
+        assert_eq!(err.span, Some(Span { line: 1, column: 8 }));
+        let env = Rc::new(RefCell::new(Environment::new()));
+        env.borrow().modules().borrow_mut().add_source("flaky", "let x = missing;");
+        let program = Parser::new(Lexer::new("import \"flaky\"; flaky.x")).parse_program().unwrap();
+        assert!(eval_program(&program, &env).is_err());
+        env.borrow().modules().borrow_mut().add_source("flaky", "let x = 2;");
+        assert_eq!(eval_program(&program, &env), Ok(Object::Integer(2)));
+    }
+
+    #[test]
+    fn test_errors_in_modules() {
+        let modules = [("shapes", "let area = fn(s) { s.w * s.h };\nlet total = fn(xs) { reduce(xs, 0, fn(acc, s) { acc + area(s) }) };")];
+        let tests = vec![
+            // Raised in the module, called from the program
+            ("import \"shapes\";\nshapes.area(1)", "INTEGER has no member w at line 1, col 21 of shapes\n    in shapes.area called at line 2, col 12"),
+            // and through a builtin back into the module
+            ("import \"shapes\";\nshapes.total([1])", "INTEGER has no member w at line 1, col 21 of shapes\n    in area called at line 2, col 59 of shapes\n    in reduce called at line 2, col 28 of shapes\n    in shapes.total called at line 2, col 13"),
+            // Functions from the program called by the standard library
+            // are placed back in it
+            ("import \"std/functional\";\nfunctional.map([1], fn(x) { x.w })", "INTEGER has no member w at line 2, col 30\n    in f called at line 11, col 32 of std/functional\n    in functional.map called at line 2, col 15"),
+            ("import \"std/functional\";\nfunctional.map(1, fn(x) { x })", "cannot iterate over INTEGER at line 10, col 18 of std/functional\n    in functional.map called at line 2, col 15"),
+        ];
+
+        for (input, expected) in tests {
+            let result = eval_with_modules(input, &modules).map_err(|err| err.to_string());
+            assert_eq!(result, Err(expected.to_string()), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_modules_from_files() {
+        let root = std::env::temp_dir().join(format!("eval-modules-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        std::fs::write(root.join("lib/greet.mky"), "let hello = fn(name) { \"hello \" + name };").unwrap();
+
+        let env = Rc::new(RefCell::new(Environment::new()));
+        env.borrow().modules().borrow_mut().set_root(&root);
+        let program = Parser::new(Lexer::new("import \"lib/greet\"; greet.hello(\"world\")")).parse_program().unwrap();
+        let result = eval_program(&program, &env);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(result, Ok(Object::String("hello world".to_string())));
+    }
+
+    #[test]
+    fn test_file_module() {
+        let root = std::env::temp_dir().join(format!("eval-file-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("input.txt"), "one\ntwo\r\nthree\n").unwrap();
+        let path = |name: &str| root.join(name).to_string_lossy().into_owned();
+
+        let tests = vec![
+            (format!("import \"std/file\"; file.lines(\"{}\")", path("input.txt")), Ok("[one, two, three]".to_string())),
+            (format!("import \"std/file\"; file.exists(\"{}\")", path("input.txt")), Ok("true".to_string())),
+            (format!("import \"std/file\"; file.exists(\"{}\")", path("missing.txt")), Ok("false".to_string())),
+            (
+                format!("import \"std/file\"; let p = \"{}\"; file.write(p, \"a\"); file.write(p, \"b\"); file.append(p, \"c\"); file.read_to_string(p)", path("output.txt")),
+                Ok("bc".to_string()),
+            ),
+            (format!("import \"std/file\"; file.append(\"{}\", \"new\"); file.read_to_string(\"{0}\")", path("appended.txt")), Ok("new".to_string())),
+            (format!("import \"std/file\"; file.read_to_string(\"{}\")", path("missing.txt")), Err(format!("i/o error: {}: No such file or directory (os error 2)", path("missing.txt")))),
+            ("import \"std/file\"; file.write(\"out.txt\", 1)".to_string(), Err("argument 2 to `write` must be STRING, got INTEGER".to_string())),
+        ];
+        let results: Vec<_> = tests.iter()
+            .map(|(input, _)| eval(input).map(|value| value.inspect()).map_err(|err| err.to_string()))
+            .collect();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        for ((input, expected), result) in tests.into_iter().zip(results) {
+            assert_eq!(result, expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_file_module_disabled() {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        env.borrow_mut().set_config(EvalConfig { filesystem: false, ..EvalConfig::default() });
+        let program = Parser::new(Lexer::new("let f = fn() { import \"std/file\" }; f()")).parse_program().unwrap();
+        let err = eval_program(&program, &env).unwrap_err();
+        assert_eq!(err.error, EvalError::CapabilityDisabled("file system access"));
+        assert_eq!(err.to_string(), "file system access is disabled for this interpreter at line 1, col 23\n    in f called at line 1, col 38");
+
+        // Other standard modules are still available
+        let program = Parser::new(Lexer::new("import \"std/math\"; math.abs(-1)")).parse_program().unwrap();
+        assert_eq!(eval_program(&program, &env), Ok(Object::Integer(1)));
+    }
+
+    // Answers a connection with each response in turn, handing back the
+    // requests that came in
+    fn serve(responses: Vec<&'static str>) -> (u16, std::thread::JoinHandle<Vec<String>>) {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || responses.into_iter().map(|response| {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            let mut buffer = [0; 1024];
+            // Up to the blank line, then as much body as it said it sent
+            let complete = |request: &str| request.split_once("\r\n\r\n").is_some_and(|(head, body)| {
+                let length = head.lines().find_map(|line| line.strip_prefix("Content-Length: "));
+                body.len() >= length.map_or(0, |length| length.parse().unwrap())
+            });
+            while !complete(&request) {
+                let read = stream.read(&mut buffer).unwrap();
+                request.push_str(std::str::from_utf8(&buffer[..read]).unwrap());
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            request
+        }).collect());
+        (port, server)
+    }
+
+    #[test]
+    fn test_http_module() {
+        let (port, server) = serve(vec![
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello",
+            "HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n",
+        ]);
+        let input = format!("import \"std/http\";
+            let r = http.get(\"http://127.0.0.1:{0}/greet?x=1\");
+            let p = http.post(\"http://127.0.0.1:{0}/items\", \"a=1\", {{\"Content-Type\": \"text/plain\", \"X-Count\": 2}});
+            [r[\"status\"], r[\"headers\"][\"content-type\"], r[\"body\"], p[\"status\"], p[\"body\"]]", port);
+        assert_eq!(eval(&input).map(|value| value.inspect()), Ok("[200, text/plain, hello, 201, ok]".to_string()));
+        assert_eq!(server.join().unwrap(), vec![
+            format!("GET /greet?x=1 HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nConnection: close\r\n\r\n", port),
+            format!("POST /items HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nConnection: close\r\nContent-Length: 3\r\nContent-Type: text/plain\r\nX-Count: 2\r\n\r\na=1", port),
+        ]);
+
+        let tests = vec![
+            ("http.get(\"https://example.com\")", "i/o error: https://example.com: https is not supported, only http"),
+            ("http.get(1)", "argument 1 to `get` must be STRING, got INTEGER"),
+            ("http.post(\"http://localhost\", \"\", [])", "argument 3 to `post` must be HASH, got ARRAY"),
+        ];
+        for (input, expected) in tests {
+            let input = format!("import \"std/http\"; {}", input);
+            assert_eq!(eval(&input).map_err(|err| err.to_string()), Err(expected.to_string()), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_http_limits() {
+        // Connecting succeeds while the listener is open, but nothing answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let program = format!("import \"std/http\"; http.get(\"http://{}/\")", listener.local_addr().unwrap());
+        let program = Parser::new(Lexer::new(&program)).parse_program().unwrap();
+
+        let env = Rc::new(RefCell::new(Environment::new()));
+        env.borrow_mut().set_config(EvalConfig { limits: Limits { timeout: Some(Duration::from_millis(100)), ..Limits::default() }, ..EvalConfig::default() });
+        let err = eval_program(&program, &env).unwrap_err();
+        assert_eq!(err.error, EvalError::ResourceExceeded(Resource::Time(Duration::from_millis(100))));
+
+        // A fresh environment, as the module is cached once imported
+        let env = Rc::new(RefCell::new(Environment::new()));
+        env.borrow_mut().set_config(EvalConfig { network: false, ..EvalConfig::default() });
+        let err = eval_program(&program, &env).unwrap_err();
+        assert_eq!(err.error, EvalError::CapabilityDisabled("network access"));
+    }
+
+    #[test]
+    #[cfg(not(feature = "sync"))]
+    fn test_without_threads() {
+        assert_eq!(eval("spawn(fn() { 1 })"), Err(EvalError::CapabilityDisabled("spawn")));
+        // Channels still work as queues, but nothing could fill an empty one
+        assert_eq!(eval("let ch = channel(); send(ch, 1); ch.send(2); [recv(ch), select([ch])]"), eval("[1, [0, 2]]"));
+        assert_eq!(eval("recv(channel())"), Err(EvalError::CapabilityDisabled("waiting on an empty channel")));
+        assert_eq!(eval("let ch = channel(); close(ch); [recv(ch), select([ch])]"), eval("[null, null]"));
+    }
+
+    #[test]
+    #[cfg(not(feature = "bigint"))]
+    fn test_big_integer_literal_unsupported() {
+        assert_eq!(big_integer_literal("99999999999999999999"), Err(EvalError::IntegerLiteralOutOfRange("99999999999999999999".to_string())));
+    }
+
+    #[test]
+    #[cfg(feature = "bigint")]
+    fn test_big_integers() {
+        let tests = vec![
+            ("99999999999999999999", "99999999999999999999"),
+            ("9223372036854775807 + 1", "9223372036854775808"),
+            ("-9223372036854775807 - 2", "-9223372036854775809"),
+            ("99999999999999999999 - 99999999999999999998", "1"),
+            ("99999999999999999999 / 10", "9999999999999999999"),
+            ("-99999999999999999999 % 10", "-9"),
+            ("99999999999999999999 > 1", "true"),
+            ("99999999999999999999 == 99999999999999999999", "true"),
+            ("let x = 9223372036854775807; x++; x", "9223372036854775808"),
+            ("let x = 9223372036854775808; x--; type(x)", "INTEGER"),
+            ("let f = 1; for (let i = 1; i <= 25; i++) { f *= i; } f", "15511210043330985984000000"),
+            ("99999999999999999999 * 0.5", "5e19"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(input).map(|value| value.inspect()), Ok(expected.to_string()), "input: {}", input);
+        }
+        assert_eq!(eval("99999999999999999999 / 0"), Err(EvalError::DivisionByZero));
+        // Shrinking back into range gives a plain integer again
+        assert_eq!(eval("9223372036854775808 - 1"), Ok(Object::Integer(i64::MAX)));
+    }
+}
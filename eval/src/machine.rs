@@ -0,0 +1,919 @@
+// Runs programs with explicit stacks of work and values rather than Rust
+// recursion, so however deeply a script nests expressions or calls its
+// functions the host stack stays the same size. Only a builtin calling back
+// into a script, and an import loading a module, start a machine of their
+// own inside the current one.
+
+use std::collections::{BTreeMap, HashMap};
+use std::vec;
+use object::shared::{Rc, RefCell};
+use typed_arena::Arena;
+use token::{Span, Symbol, Token, TokenType};
+use ast::{
+    Statement, BlockStatement, ForStatement, ForInStatement,
+    Assignment, Expression, FunctionLiteral, Index, Infix, Ternary, MethodCall, MatchExpression, Pattern, StructLiteral, InterpolatedString, StringPart,
+    TryExpression,
+};
+use object::{Budget, Environment, EvalError, Function, Heap, Object, RuntimeError, Struct, StructType, Trace, TraceEvent};
+use crate::{
+    builtins, modules, Context,
+    place::{get_in, set_in, Step},
+    apply_index_operator, apply_infix_operator, apply_prefix_operator, big_integer_literal,
+    compound_operator, iterate, make_range, step_integer,
+};
+
+// What is left to do, the next task last. Expressions and statements leave
+// their value on the value stack, where the tasks after them pick it up.
+enum Task<'a> {
+    Eval(&'a Expression, Rc<RefCell<Environment>>),
+    Exec(&'a Statement, Rc<RefCell<Environment>>),
+    // Runs the statements in turn, stopping early at a return
+    Sequence { statements: &'a [Statement], next: usize, env: Rc<RefCell<Environment>> },
+    EndBlock(&'a BlockStatement),
+    // Counts what an expression created. Errors raised while it is on the
+    // stack happened inside the expression and are reported at its span.
+    Finish(&'a Expression),
+    // Leaves a function, with the frame errors raised inside it add to
+    // their trace, the height of the value stack when it was called and
+    // the scope it was defined in, which tells the module it is from
+    Return { frame: Option<(String, Span)>, height: usize, scope: Rc<RefCell<Environment>> },
+    Discard,
+    Let(Symbol, Rc<RefCell<Environment>>),
+    WrapReturn,
+    ForTest(&'a ForStatement, Rc<RefCell<Environment>>),
+    ForBody(&'a ForStatement, Rc<RefCell<Environment>>),
+    ForUpdate(&'a ForStatement, Rc<RefCell<Environment>>),
+    Iterate(&'a ForInStatement, Rc<RefCell<Environment>>),
+    ForIn { stmt: &'a ForInStatement, items: vec::IntoIter<Object>, env: Rc<RefCell<Environment>> },
+    Prefix(&'a Token, Rc<RefCell<Environment>>),
+    InfixRight(&'a Infix, Rc<RefCell<Environment>>),
+    Infix(&'a Token, Rc<RefCell<Environment>>),
+    // Assigns the value on top, with the indexes in the target below it
+    Assign(&'a Assignment, Rc<RefCell<Environment>>),
+    // Assigns the value on top, which a struct's method for a compound
+    // assignment's operator returned
+    Store { name: Symbol, path: Vec<Step>, env: Rc<RefCell<Environment>> },
+    // Flips the truthiness of the value on top, for `!=` done with `__eq`
+    Not,
+    Array(usize),
+    Range(bool),
+    HashKey(&'a Expression),
+    Hash(usize),
+    // Calls the function below its arguments on the value stack
+    Call { name: String, span: Span, count: usize, env: Rc<RefCell<Environment>> },
+    Method(&'a MethodCall, Rc<RefCell<Environment>>),
+    Member(Symbol),
+    Index(&'a Index, Rc<RefCell<Environment>>),
+    MatchArm { m: &'a MatchExpression, arm: usize, env: Rc<RefCell<Environment>> },
+    MatchTest { m: &'a MatchExpression, arm: usize, env: Rc<RefCell<Environment>> },
+    // Fields are evaluated one at a time, with the field the value on top
+    // of the stack belongs to
+    StructField {
+        literal: &'a StructLiteral,
+        definition: Rc<StructType>,
+        values: Vec<Option<Object>>,
+        filling: Option<usize>,
+        next: usize,
+        env: Rc<RefCell<Environment>>,
+    },
+    Choose(&'a Ternary, Rc<RefCell<Environment>>),
+    Interpolate(&'a InterpolatedString, Rc<RefCell<Environment>>),
+    // Marks where a try's body ends, with the height of the value stack
+    // when it started. An error unwinding to it runs the handler instead.
+    Catch { t: &'a TryExpression, height: usize, env: Rc<RefCell<Environment>> },
+    Throw(Span),
+    // Unwraps an ok, or returns an err or null from the function
+    Propagate,
+}
+
+pub(crate) struct Machine<'a> {
+    tasks: Vec<Task<'a>>,
+    values: Vec<Object>,
+    budget: Rc<RefCell<Budget>>,
+    heap: Rc<RefCell<Heap>>,
+    trace: Option<Trace>,
+    // The bodies of the functions called, kept alive until the run ends so
+    // that tasks can point into them
+    bodies: &'a Arena<Rc<BlockStatement>>,
+    called: HashMap<*const BlockStatement, &'a BlockStatement>,
+    // Functions made by the same literal share its body
+    literals: HashMap<*const FunctionLiteral, Rc<BlockStatement>>,
+    // Where the run started, whose module errors not raised in a call are in
+    env: Rc<RefCell<Environment>>,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(bodies: &'a Arena<Rc<BlockStatement>>, env: &Rc<RefCell<Environment>>) -> Machine<'a> {
+        Machine {
+            tasks: Vec::new(),
+            values: Vec::new(),
+            budget: env.borrow().budget(),
+            heap: env.borrow().heap(),
+            trace: env.borrow().trace(),
+            bodies,
+            called: HashMap::new(),
+            literals: HashMap::new(),
+            env: Rc::clone(env),
+        }
+    }
+
+    pub fn run_statements(mut self, statements: &'a [Statement], env: &Rc<RefCell<Environment>>) -> Result<Object, RuntimeError> {
+        self.tasks.push(Task::Sequence { statements, next: 0, env: Rc::clone(env) });
+        self.run()
+    }
+
+    pub fn run_call(mut self, function: Object, arguments: Vec<Object>, env: &Rc<RefCell<Environment>>) -> Result<Object, RuntimeError> {
+        self.call(function, arguments, None, env)?;
+        self.run()
+    }
+
+    fn run(mut self) -> Result<Object, RuntimeError> {
+        while let Some(task) = self.tasks.pop() {
+            if let Err(err) = self.perform(task) {
+                self.unwind(err)?;
+            }
+        }
+        Ok(self.pop())
+    }
+
+    // Abandons tasks up to the innermost try, whose handler then gets the
+    // error, or else all of them. On the way the error gets the span of the
+    // innermost expression it was raised in and a frame for every call it
+    // leaves.
+    fn unwind(&mut self, mut err: RuntimeError) -> Result<(), RuntimeError> {
+        while let Some(task) = self.tasks.pop() {
+            match task {
+                Task::Finish(expression) => err = self.locate(err, expression.span()),
+                Task::Return { frame, .. } => {
+                    self.leave();
+                    err = self.in_frame(err, &frame);
+                },
+                // Running out of a resource or a panic ends the run wherever
+                // it happens
+                Task::Catch { t, height, env } if !matches!(err.error, EvalError::ResourceExceeded(_) | EvalError::Panicked(_)) => {
+                    self.values.truncate(height);
+                    // Errors raised by the interpreter are caught as their
+                    // message
+                    let value = match err.error {
+                        EvalError::Thrown(value) => *value,
+                        error => Object::String(error.to_string()),
+                    };
+                    let scope = self.enclose(&env);
+                    scope.borrow_mut().set(t.parameter.value, value);
+                    self.push_block(&t.handler, scope);
+                    return Ok(());
+                },
+                _ => {},
+            }
+        }
+        Err(err)
+    }
+
+    fn perform(&mut self, task: Task<'a>) -> Result<(), RuntimeError> {
+        match task {
+            Task::Eval(expression, env) => {
+                self.budget.borrow_mut().step().map_err(|err| self.locate(err.into(), expression.span()))?;
+                self.tasks.push(Task::Finish(expression));
+                self.eval(expression, env)?;
+            },
+            Task::Exec(statement, env) => self.exec(statement, env)?,
+            Task::Sequence { statements, next, env } => {
+                let result = if next == 0 { Object::Null } else { self.pop() };
+                match statements.get(next) {
+                    Some(statement) if !matches!(result, Object::ReturnValue(_)) => {
+                        self.tasks.push(Task::Sequence { statements, next: next + 1, env: Rc::clone(&env) });
+                        self.tasks.push(Task::Exec(statement, env));
+                    },
+                    _ => self.values.push(result),
+                }
+            },
+            Task::EndBlock(block) => {
+                // A semicolon after the last expression discards its value
+                let discarded = matches!(block.statements.last(), Some(Statement::ExpressionStatement(es)) if es.semicolon);
+                let result = self.values.last_mut().unwrap();
+                if discarded && !matches!(result, Object::ReturnValue(_)) {
+                    *result = Object::Null;
+                }
+            },
+            Task::Finish(expression) => {
+                // Reading a variable creates nothing new
+                if !matches!(expression, Expression::Identifier(_)) {
+                    let allocated = self.budget.borrow_mut().allocate(self.values.last().unwrap());
+                    allocated.map_err(|err| self.locate(err.into(), expression.span()))?;
+                }
+                if let Some(trace) = &self.trace {
+                    trace.record(&TraceEvent {
+                        node: expression.to_string(),
+                        span: Some(expression.span()),
+                        value: self.values.last(),
+                        depth: self.budget.borrow().depth() - 1,
+                    });
+                }
+            },
+            Task::Return { .. } => {
+                self.leave();
+                let value = match self.pop() {
+                    Object::ReturnValue(value) => *value,
+                    value => value,
+                };
+                self.values.push(value);
+            },
+            Task::Discard => {
+                self.pop();
+            },
+            Task::Let(name, env) => {
+                let value = self.pop();
+                env.borrow_mut().set(name, value);
+                self.values.push(Object::Null);
+            },
+            Task::WrapReturn => {
+                let value = self.pop();
+                self.values.push(Object::ReturnValue(Box::new(value)));
+            },
+            Task::ForTest(stmt, env) => {
+                self.tasks.push(Task::ForBody(stmt, Rc::clone(&env)));
+                self.tasks.push(Task::Eval(&stmt.condition, env));
+            },
+            Task::ForBody(stmt, env) => {
+                if self.pop().is_truthy() {
+                    self.tasks.push(Task::ForUpdate(stmt, Rc::clone(&env)));
+                    self.push_block(&stmt.body, env);
+                } else {
+                    self.values.push(Object::Null);
+                }
+            },
+            Task::ForUpdate(stmt, env) => {
+                if !matches!(self.values.last(), Some(Object::ReturnValue(_))) {
+                    self.pop();
+                    self.tasks.push(Task::ForTest(stmt, Rc::clone(&env)));
+                    self.tasks.push(Task::Discard);
+                    self.tasks.push(Task::Eval(&stmt.update, env));
+                }
+            },
+            Task::Iterate(stmt, env) => {
+                let items = iterate(self.pop()).map_err(|err| self.locate(err.into(), stmt.iterable.span()))?;
+                // Stands in for the value of the body before its first run
+                self.values.push(Object::Null);
+                self.tasks.push(Task::ForIn { stmt, items: items.into_iter(), env });
+            },
+            Task::ForIn { stmt, mut items, env } => {
+                if matches!(self.values.last(), Some(Object::ReturnValue(_))) {
+                    return Ok(());
+                }
+                self.pop();
+                match items.next() {
+                    Some(item) => {
+                        env.borrow_mut().set(stmt.variable.value, item);
+                        self.tasks.push(Task::ForIn { stmt, items, env: Rc::clone(&env) });
+                        self.push_block(&stmt.body, env);
+                    },
+                    None => self.values.push(Object::Null),
+                }
+            },
+            Task::Prefix(operator, env) => {
+                let right = self.pop();
+                let config = env.borrow().config();
+                self.values.push(apply_prefix_operator(&operator.ttype, &operator.literal, right, &config)?);
+            },
+            Task::InfixRight(infix, env) => {
+                // The fallback is only evaluated when it is needed
+                if infix.operator.ttype == TokenType::NullCoalesce {
+                    if let Some(Object::Null) = self.values.last() {
+                        self.pop();
+                        self.tasks.push(Task::Eval(&infix.right, env));
+                    }
+                    return Ok(());
+                }
+                self.tasks.push(Task::Infix(&infix.operator, Rc::clone(&env)));
+                self.tasks.push(Task::Eval(&infix.right, env));
+            },
+            Task::Infix(operator, env) => {
+                let right = self.pop();
+                let left = self.pop();
+                if let Some((name, method)) = overload(&left, operator.ttype.overload()) {
+                    if operator.ttype == TokenType::NotEqual {
+                        self.tasks.push(Task::Not);
+                    }
+                    return self.call(method, vec![right], Some((name, operator.span())), &env);
+                }
+                // A struct joined to a string is shown as interpolation
+                // shows it, to_string and all
+                if let (TokenType::Plus, Object::String(_), Object::Struct(_)) | (TokenType::Plus, Object::Struct(_), Object::String(_)) = (operator.ttype, &left, &right) {
+                    let mut context = Context { env, error: None };
+                    let mut joined = String::new();
+                    for value in [left, right] {
+                        match value {
+                            Object::String(text) => joined.push_str(&text),
+                            value => match builtins::display(&mut context, &value) {
+                                Ok(text) => joined.push_str(&text),
+                                Err(err) => return Err(context.into_error(err)),
+                            },
+                        }
+                    }
+                    self.values.push(Object::String(joined));
+                    return Ok(());
+                }
+                let config = env.borrow().config();
+                self.values.push(apply_infix_operator(&operator.ttype, &operator.literal, left, right, &config)?);
+            },
+            Task::Assign(assignment, env) => {
+                let (name, steps) = place(&assignment.target)?;
+                let mut value = self.pop();
+                let count = steps.iter().filter(|step| matches!(step, Expression::Index(_))).count();
+                let mut indexes = self.values.split_off(self.values.len() - count).into_iter();
+
+                let path: Vec<Step> = steps.into_iter().map(|step| match step {
+                    Expression::Member(member) => Step::Member(member.property.value),
+                    _ => Step::Index(indexes.next().unwrap()),
+                }).collect();
+                // Read first to find what is there now, which compound
+                // assignments start from
+                let current = get_in(&env.borrow().get(name).ok_or_else(|| EvalError::IdentifierNotFound(name.to_string()))?, &path)?;
+
+                let operator = &assignment.token;
+                if let Some((infix, literal)) = compound_operator(&operator.ttype) {
+                    if let Some((frame, method)) = overload(&current, infix.overload()) {
+                        self.tasks.push(Task::Store { name, path, env: Rc::clone(&env) });
+                        return self.call(method, vec![value], Some((frame, operator.span())), &env);
+                    }
+                    let config = env.borrow().config();
+                    value = apply_infix_operator(&infix, literal, current, value, &config)?;
+                }
+                self.store(name, path, value, &env)?;
+            },
+            Task::Store { name, path, env } => {
+                let value = self.pop();
+                self.store(name, path, value, &env)?;
+            },
+            Task::Not => {
+                let value = self.pop();
+                self.values.push(Object::Boolean(!value.is_truthy()));
+            },
+            Task::Array(count) => {
+                let elements = self.values.split_off(self.values.len() - count);
+                self.values.push(Object::Array(Rc::new(elements)));
+            },
+            Task::Range(inclusive) => {
+                let end = self.pop();
+                let start = self.pop();
+                self.values.push(make_range(start, end, inclusive)?);
+            },
+            Task::HashKey(key) => {
+                self.values.last().unwrap().hash_key().map_err(|err| self.locate(err.into(), key.span()))?;
+            },
+            Task::Hash(count) => {
+                let mut entries = self.values.split_off(self.values.len() - 2 * count).into_iter();
+                let mut pairs = BTreeMap::new();
+                while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                    pairs.insert(key.hash_key()?, value);
+                }
+                self.values.push(Object::Hash(Rc::new(pairs)));
+            },
+            Task::Call { name, span, count, env } => {
+                let arguments = self.values.split_off(self.values.len() - count);
+                let function = self.pop();
+                self.call(function, arguments, Some((name, span)), &env)?;
+            },
+            Task::Method(call, env) => self.method(call, env)?,
+            Task::Member(name) => {
+                let object = self.pop();
+                self.values.push(modules::member(object, name)?);
+            },
+            Task::Index(index, env) => {
+                let position = self.pop();
+                let left = self.pop();
+                if let Some((name, method)) = overload(&left, Some("__index")) {
+                    return self.call(method, vec![position], Some((name, index.token.span())), &env);
+                }
+                self.values.push(apply_index_operator(left, position)?);
+            },
+            Task::MatchArm { m, arm, env } => match m.arms.get(arm).map(|arm| &arm.pattern) {
+                // A match without a matching arm is null, like a function
+                // that returns nothing
+                None => {
+                    self.pop();
+                    self.values.push(Object::Null);
+                },
+                Some(Pattern::Wildcard(_)) => {
+                    self.pop();
+                    self.tasks.push(Task::Eval(&m.arms[arm].body, env));
+                },
+                Some(Pattern::Literal(literal)) => {
+                    self.tasks.push(Task::MatchTest { m, arm, env: Rc::clone(&env) });
+                    self.tasks.push(Task::Eval(literal, env));
+                },
+                Some(Pattern::Range { start, end }) => {
+                    self.tasks.push(Task::MatchTest { m, arm, env: Rc::clone(&env) });
+                    self.push_expressions([start, end], &env);
+                },
+            },
+            Task::MatchTest { m, arm, env } => {
+                // Values of different types never match, rather than being
+                // an error
+                let matches = match &m.arms[arm].pattern {
+                    Pattern::Range { .. } => {
+                        let end = self.pop();
+                        let start = self.pop();
+                        match (start, self.values.last().unwrap(), end) {
+                            (Object::Integer(start), Object::Integer(value), Object::Integer(end)) => (start..end).contains(value),
+                            (Object::Char(start), Object::Char(value), Object::Char(end)) => (start..end).contains(value),
+                            _ => false,
+                        }
+                    },
+                    _ => self.pop() == *self.values.last().unwrap(),
+                };
+                if matches {
+                    self.pop();
+                    self.tasks.push(Task::Eval(&m.arms[arm].body, env));
+                } else {
+                    self.tasks.push(Task::MatchArm { m, arm: arm + 1, env });
+                }
+            },
+            Task::StructField { literal, definition, mut values, filling, next, env } => {
+                if let Some(position) = filling {
+                    let value = self.pop();
+                    if values[position].replace(value).is_some() {
+                        return Err(EvalError::DuplicateField { name: definition.name.to_string(), field: literal.fields[next - 1].0.value.to_string() }.into());
+                    }
+                }
+                match literal.fields.get(next) {
+                    Some((field, value)) => {
+                        let position = definition.fields.iter().position(|&f| f == field.value).ok_or_else(|| EvalError::NoSuchMember {
+                            object: format!("struct {}", definition.name),
+                            member: field.value.to_string(),
+                        })?;
+                        self.tasks.push(Task::StructField { literal, definition, values, filling: Some(position), next: next + 1, env: Rc::clone(&env) });
+                        self.tasks.push(Task::Eval(value, env));
+                    },
+                    None => {
+                        let values = values.into_iter().zip(&definition.fields)
+                            .map(|(value, field)| value.ok_or_else(|| EvalError::MissingField {
+                                name: definition.name.to_string(),
+                                field: field.to_string(),
+                            }))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        self.values.push(Object::Struct(Struct { definition, values }));
+                    },
+                }
+            },
+            Task::Choose(ternary, env) => {
+                let branch = if self.pop().is_truthy() { &ternary.if_true } else { &ternary.if_false };
+                self.tasks.push(Task::Eval(branch, env));
+            },
+            Task::Interpolate(string, env) => {
+                let count = string.parts.iter().filter(|part| matches!(part, StringPart::Expression(_))).count();
+                let mut embedded = self.values.split_off(self.values.len() - count).into_iter();
+                // Embedded values are shown the way puts shows them, to_string
+                // and all
+                let mut context = Context { env, error: None };
+                let mut value = String::new();
+                for part in &string.parts {
+                    match part {
+                        StringPart::Text(text) => value.push_str(text),
+                        StringPart::Expression(_) => match builtins::display(&mut context, &embedded.next().unwrap()) {
+                            Ok(text) => value.push_str(&text),
+                            Err(err) => return Err(context.into_error(err)),
+                        },
+                    }
+                }
+                self.values.push(Object::String(value));
+            },
+            // The body ended without an error and its value stands
+            Task::Catch { .. } => {},
+            Task::Propagate => match self.pop() {
+                Object::Result(Ok(value)) => self.values.push(*value),
+                value @ (Object::Result(Err(_)) | Object::Null) => self.return_early(value),
+                value => self.values.push(value),
+            },
+            Task::Throw(span) => {
+                let value = self.pop();
+                return Err(self.locate(EvalError::Thrown(Box::new(value)).into(), span));
+            },
+        }
+        Ok(())
+    }
+
+    fn eval(&mut self, expression: &'a Expression, env: Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+        let value = match expression {
+            Expression::IntegerLiteral(il) => Object::Integer(il.value),
+            Expression::FloatLiteral(fl) => Object::Float(fl.value),
+            Expression::BigIntegerLiteral(token) => big_integer_literal(&token.literal)?,
+            Expression::StringLiteral(token) => Object::String(token::string_contents(&token.literal).to_string()),
+            Expression::InterpolatedString(string) => {
+                self.tasks.push(Task::Interpolate(string, Rc::clone(&env)));
+                let embedded = string.parts.iter().filter_map(|part| match part {
+                    StringPart::Expression(expression) => Some(expression),
+                    StringPart::Text(_) => None,
+                });
+                self.push_expressions(embedded, &env);
+                return Ok(());
+            },
+            Expression::CharLiteral(c) => Object::Char(c.value),
+            Expression::BooleanLiteral(token) => Object::Boolean(token.ttype == TokenType::True),
+            Expression::NullLiteral(_) => Object::Null,
+            Expression::Identifier(ident) => {
+                env.borrow().get(ident.value)
+                    .or_else(|| builtins::lookup(ident.value.as_str()))
+                    .ok_or_else(|| EvalError::IdentifierNotFound(ident.value.to_string()))?
+            },
+            Expression::Prefix(prefix) if matches!(prefix.operator.ttype, TokenType::Increment | TokenType::Decrement) => {
+                eval_step(&prefix.right, &prefix.operator, true, &env)?
+            },
+            Expression::Prefix(prefix) => {
+                self.tasks.push(Task::Prefix(&prefix.operator, Rc::clone(&env)));
+                self.tasks.push(Task::Eval(&prefix.right, env));
+                return Ok(());
+            },
+            Expression::Infix(infix) => {
+                self.tasks.push(Task::InfixRight(infix, Rc::clone(&env)));
+                self.tasks.push(Task::Eval(&infix.left, env));
+                return Ok(());
+            },
+            Expression::Postfix(postfix) => eval_step(&postfix.left, &postfix.operator, false, &env)?,
+            Expression::Assignment(assignment) => {
+                // Indexes in the target are evaluated first, left to right
+                let (_, steps) = place(&assignment.target)?;
+                self.tasks.push(Task::Assign(assignment, Rc::clone(&env)));
+                self.tasks.push(Task::Eval(&assignment.value, Rc::clone(&env)));
+                let indexes = steps.into_iter().filter_map(|step| match step {
+                    Expression::Index(index) => Some(&*index.index),
+                    _ => None,
+                });
+                self.push_expressions(indexes, &env);
+                return Ok(());
+            },
+            Expression::ArrayLiteral(array) => {
+                self.tasks.push(Task::Array(array.elements.len()));
+                self.push_expressions(&array.elements, &env);
+                return Ok(());
+            },
+            Expression::RangeLiteral(range) => {
+                self.tasks.push(Task::Range(range.inclusive()));
+                self.push_expressions([&*range.start, &*range.end], &env);
+                return Ok(());
+            },
+            Expression::HashLiteral(hash) => {
+                self.tasks.push(Task::Hash(hash.pairs.len()));
+                for (key, value) in hash.pairs.iter().rev() {
+                    self.tasks.push(Task::Eval(value, Rc::clone(&env)));
+                    self.tasks.push(Task::HashKey(key));
+                    self.tasks.push(Task::Eval(key, Rc::clone(&env)));
+                }
+                return Ok(());
+            },
+            Expression::FunctionLiteral(func) => {
+                let body = self.literals.entry(func as *const FunctionLiteral).or_insert_with(|| Rc::new(func.body.clone()));
+                Object::Function(Function {
+                    parameters: func.parameters.clone(),
+                    body: Rc::clone(body),
+                    env,
+                })
+            },
+            Expression::Call(call) => {
+                self.tasks.push(Task::Call {
+                    name: call.function.to_string(),
+                    span: call.token.span(),
+                    count: call.arguments.len(),
+                    env: Rc::clone(&env),
+                });
+                self.push_expressions(std::iter::once(&*call.function).chain(&call.arguments), &env);
+                return Ok(());
+            },
+            Expression::MethodCall(call) => {
+                self.tasks.push(Task::Method(call, Rc::clone(&env)));
+                self.tasks.push(Task::Eval(&call.object, env));
+                return Ok(());
+            },
+            Expression::Member(member) => {
+                self.tasks.push(Task::Member(member.property.value));
+                self.tasks.push(Task::Eval(&member.object, env));
+                return Ok(());
+            },
+            Expression::Index(index) => {
+                self.tasks.push(Task::Index(index, Rc::clone(&env)));
+                self.push_expressions([&*index.left, &*index.index], &env);
+                return Ok(());
+            },
+            Expression::Import(import) => modules::import(&import.path, &env)?,
+            Expression::Match(m) => {
+                self.tasks.push(Task::MatchArm { m, arm: 0, env: Rc::clone(&env) });
+                self.tasks.push(Task::Eval(&m.subject, env));
+                return Ok(());
+            },
+            Expression::StructLiteral(literal) => {
+                // Fields may be given in any order but each must be given
+                // exactly once
+                let definition = match env.borrow().get(literal.name.value) {
+                    Some(Object::StructType(definition)) => definition,
+                    Some(other) => return Err(EvalError::NotAStruct(other.type_name()).into()),
+                    None => return Err(EvalError::IdentifierNotFound(literal.name.value.to_string()).into()),
+                };
+                let values = vec![None; definition.fields.len()];
+                self.tasks.push(Task::StructField { literal, definition, values, filling: None, next: 0, env });
+                return Ok(());
+            },
+            Expression::Block(block) => {
+                self.push_scoped_block(block, &env);
+                return Ok(());
+            },
+            Expression::Try(t) => {
+                self.tasks.push(Task::Catch { t, height: self.values.len(), env: Rc::clone(&env) });
+                self.push_scoped_block(&t.body, &env);
+                return Ok(());
+            },
+            Expression::Propagate(propagate) => {
+                self.tasks.push(Task::Propagate);
+                self.tasks.push(Task::Eval(&propagate.value, env));
+                return Ok(());
+            },
+            Expression::Ternary(ternary) => {
+                self.tasks.push(Task::Choose(ternary, Rc::clone(&env)));
+                self.tasks.push(Task::Eval(&ternary.condition, env));
+                return Ok(());
+            },
+        };
+        self.values.push(value);
+        Ok(())
+    }
+
+    fn exec(&mut self, statement: &'a Statement, env: Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+        match statement {
+            Statement::LetStatement(ls) => {
+                self.tasks.push(Task::Let(ls.name.value, Rc::clone(&env)));
+                self.tasks.push(Task::Eval(&ls.value, env));
+            },
+            Statement::ReturnStatement(rs) => {
+                self.tasks.push(Task::WrapReturn);
+                self.tasks.push(Task::Eval(&rs.return_value, env));
+            },
+            Statement::ExpressionStatement(es) => self.tasks.push(Task::Eval(&es.expression, env)),
+            Statement::ForStatement(fs) => {
+                self.tasks.push(Task::ForTest(fs, Rc::clone(&env)));
+                self.tasks.push(Task::Discard);
+                self.tasks.push(Task::Exec(&fs.init, env));
+            },
+            Statement::ForInStatement(fs) => {
+                self.tasks.push(Task::Iterate(fs, Rc::clone(&env)));
+                self.tasks.push(Task::Eval(&fs.iterable, env));
+            },
+            Statement::ImportStatement(is) => {
+                let module = modules::import(&is.path, &env).map_err(|err| self.locate(err.into(), is.path.span()))?;
+                env.borrow_mut().set(is.name.value, module);
+                self.values.push(Object::Null);
+            },
+            Statement::StructStatement(ss) => {
+                let definition = StructType {
+                    name: ss.name.value,
+                    fields: ss.fields.iter().map(|f| f.value).collect(),
+                };
+                env.borrow_mut().set(ss.name.value, Object::StructType(Rc::new(definition)));
+                self.values.push(Object::Null);
+            },
+            Statement::ThrowStatement(ts) => {
+                self.tasks.push(Task::Throw(ts.token.span()));
+                self.tasks.push(Task::Eval(&ts.value, env));
+            },
+            Statement::BlockStatement(bs) => self.push_scoped_block(bs, &env),
+        }
+        Ok(())
+    }
+
+    // Members of modules and structs are called as they are. Anything else
+    // has the builtins listed for its type as methods, which get the receiver
+    // as their first argument.
+    fn method(&mut self, call: &'a MethodCall, env: Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+        let object = self.pop();
+        let (function, receiver) = match object {
+            Object::Module(_) | Object::Struct(_) => (modules::member(object, call.method.value)?, None),
+            object => match builtins::method(&object, call.method.value.as_str()) {
+                Some(method) => (method, Some(object)),
+                None => return Err(EvalError::NoSuchMember {
+                    object: object.type_name().to_string(),
+                    member: call.method.value.to_string(),
+                }.into()),
+            },
+        };
+
+        self.values.push(function);
+        let count = call.arguments.len() + usize::from(receiver.is_some());
+        self.values.extend(receiver);
+        self.tasks.push(Task::Call {
+            name: format!("{}.{}", call.object, call.method),
+            span: call.token.span(),
+            count,
+            env: Rc::clone(&env),
+        });
+        self.push_expressions(&call.arguments, &env);
+        Ok(())
+    }
+
+    // Starts a call, recording it in the trace of errors raised while the
+    // callee runs. Script functions run on the stacks of the machine, while
+    // builtins are done by the time this returns.
+    fn call(&mut self, function: Object, arguments: Vec<Object>, frame: Option<(String, Span)>, env: &Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+        match function {
+            Object::Function(func) => {
+                if arguments.len() != func.parameters.len() {
+                    return Err(self.in_frame(EvalError::WrongArgumentCount { got: arguments.len(), want: func.parameters.len() }.into(), &frame));
+                }
+
+                let scope = self.enclose(&func.env);
+                for (parameter, argument) in func.parameters.iter().zip(arguments) {
+                    scope.borrow_mut().set(parameter.value, argument);
+                }
+
+                let config = env.borrow().config();
+                self.budget.borrow_mut().enter(&config).map_err(|err| self.in_frame(err.into(), &frame))?;
+                if let Some(trace) = &self.trace {
+                    trace.enter(frame.as_ref().map_or("fn", |(name, _)| name));
+                }
+                let body = self.body(&func.body);
+                self.tasks.push(Task::Return { frame, height: self.values.len(), scope: Rc::clone(&func.env) });
+                self.push_block(body, scope);
+            },
+            Object::Builtin(func) => {
+                let mut context = Context { env: Rc::clone(env), error: None };
+                let value = func(&mut context, arguments).map_err(|err| self.in_frame(context.into_error(err), &frame))?;
+                self.values.push(value);
+            },
+            Object::Native(func) => {
+                let value = func.call(&arguments).map_err(|err| self.in_frame(err.into(), &frame))?;
+                self.values.push(value);
+            },
+            other => return Err(EvalError::NotCallable(other.type_name()).into()),
+        }
+        Ok(())
+    }
+
+    // Puts the value in its place within what the name is bound to, leaving
+    // it on the stack as the assignment's value
+    fn store(&mut self, name: Symbol, path: Vec<Step>, value: Object, env: &Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+        env.borrow_mut().update(name, |binding| set_in(binding, &path, value.clone()))
+            .ok_or_else(|| EvalError::IdentifierNotFound(name.to_string()))??;
+        self.values.push(value);
+        Ok(())
+    }
+
+    // Abandons what is left of the innermost call, which then returns the
+    // value. Outside of any call the run ends with it.
+    fn return_early(&mut self, value: Object) {
+        while let Some(task) = self.tasks.pop() {
+            if let Task::Return { frame, height, scope } = task {
+                self.values.truncate(height);
+                self.values.push(value);
+                self.tasks.push(Task::Return { frame, height, scope });
+                return;
+            }
+        }
+        self.values.clear();
+        self.values.push(value);
+    }
+
+    fn leave(&mut self) {
+        self.budget.borrow_mut().exit();
+        if let Some(trace) = &self.trace {
+            trace.exit();
+        }
+    }
+
+    // The body of a function, kept for the rest of the run the first time
+    // it is called
+    fn body(&mut self, body: &Rc<BlockStatement>) -> &'a BlockStatement {
+        let bodies = self.bodies;
+        self.called.entry(Rc::as_ptr(body)).or_insert_with(|| &**bodies.alloc(Rc::clone(body)))
+    }
+
+    fn push_block(&mut self, block: &'a BlockStatement, env: Rc<RefCell<Environment>>) {
+        self.tasks.push(Task::EndBlock(block));
+        self.tasks.push(Task::Sequence { statements: &block.statements, next: 0, env });
+    }
+
+    // A block standing on its own gets a scope of its own, so what it
+    // declares is gone once it ends while what it assigns outlives it
+    fn push_scoped_block(&mut self, block: &'a BlockStatement, env: &Rc<RefCell<Environment>>) {
+        let scope = self.enclose(env);
+        self.push_block(block, scope);
+    }
+
+    // Scopes are tracked so that functions stored in the scope they close
+    // over can be freed, collecting every so often as more are made
+    fn enclose(&mut self, outer: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let scope = Rc::new(RefCell::new(Environment::new_enclosed(Rc::clone(outer))));
+        let mut heap = self.heap.borrow_mut();
+        heap.track(&scope);
+        if heap.due() {
+            heap.collect();
+        }
+        scope
+    }
+
+    // Evaluates the expressions from first to last, leaving their values in
+    // the same order
+    fn push_expressions<I>(&mut self, expressions: I, env: &Rc<RefCell<Environment>>)
+    where
+        I: IntoIterator<Item = &'a Expression>,
+        I::IntoIter: DoubleEndedIterator,
+    {
+        for expression in expressions.into_iter().rev() {
+            self.tasks.push(Task::Eval(expression, Rc::clone(env)));
+        }
+    }
+
+    fn pop(&mut self) -> Object {
+        self.values.pop().expect("value stack underflow")
+    }
+
+    // The module the code running now is from: that of the innermost
+    // function called, or else of where the run started
+    fn module(&self) -> Option<Symbol> {
+        let scope = self.tasks.iter().rev()
+            .find_map(|task| match task {
+                Task::Return { scope, .. } => Some(scope),
+                _ => None,
+            })
+            .unwrap_or(&self.env);
+        let modules = scope.borrow().modules();
+        let name = modules.borrow().containing(scope).map(Symbol::intern);
+        name
+    }
+
+    // Errors are reported at the innermost expression they were raised in
+    fn locate(&self, err: RuntimeError, span: Span) -> RuntimeError {
+        match err.span {
+            Some(_) => err,
+            None => err.with_span_in(span, self.module()),
+        }
+    }
+
+    // Adds the call the error left, made from the code running now
+    fn in_frame(&self, err: RuntimeError, frame: &Option<(String, Span)>) -> RuntimeError {
+        match frame {
+            Some((name, span)) => err.with_frame_in(name.clone(), *span, self.module()),
+            None => err,
+        }
+    }
+}
+
+// The name an assignment target starts from and the indexes and members
+// that lead from it to the place assigned, outermost first
+fn place(target: &Expression) -> Result<(Symbol, Vec<&Expression>), EvalError> {
+    let mut steps = Vec::new();
+    let mut target = target;
+    loop {
+        match target {
+            Expression::Identifier(ident) => {
+                steps.reverse();
+                return Ok((ident.value, steps));
+            },
+            Expression::Index(index) => {
+                steps.push(target);
+                target = &index.left;
+            },
+            Expression::Member(member) => {
+                steps.push(target);
+                target = &member.object;
+            },
+            other => return Err(EvalError::InvalidAssignmentTarget(other.to_string())),
+        }
+    }
+}
+
+// The method a struct on the left of an operator has for it, which is called
+// with the other operand instead, and the name the call is traced under
+fn overload(value: &Object, method: Option<&str>) -> Option<(String, Object)> {
+    let (Object::Struct(instance), Some(method)) = (value, method) else {
+        return None;
+    };
+    let function = instance.method(method)?;
+    Some((format!("{}.{}", instance.definition.name, method), function))
+}
+
+// ++ and -- update the binding in place. The prefix forms evaluate to the
+// new value and the postfix forms to the previous one.
+fn eval_step(target: &Expression, operator: &Token, prefix: bool, env: &Rc<RefCell<Environment>>) -> Result<Object, RuntimeError> {
+    let name = match target {
+        Expression::Identifier(ident) => ident.value,
+        other => return Err(EvalError::InvalidAssignmentTarget(other.to_string()).into()),
+    };
+
+    let current = env.borrow().get(name).ok_or_else(|| EvalError::IdentifierNotFound(name.to_string()))?;
+    let config = env.borrow().config();
+    let increment = match operator.ttype {
+        TokenType::Increment => true,
+        TokenType::Decrement => false,
+        _ => unreachable!("not a step operator: {}", operator.literal),
+    };
+    let updated = match step_integer(&current, increment, &config) {
+        Some(result) => result?,
+        None if prefix => return Err(EvalError::UnknownPrefixOperator {
+            operator: operator.literal.clone(),
+            right: current.type_name(),
+        }.into()),
+        None => return Err(EvalError::UnknownPostfixOperator {
+            left: current.type_name(),
+            operator: operator.literal.clone(),
+        }.into()),
+    };
+    env.borrow_mut().assign(name, updated.clone());
+    Ok(if prefix { updated } else { current })
+}
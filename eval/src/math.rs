@@ -0,0 +1,76 @@
+use object::{BuiltinFunction, EvalError, Object, Runtime};
+use crate::builtins::check_argument_count;
+use crate::to_float;
+
+// The members of std/math written in Rust, bound before the rest of the
+// module runs. Integers stay integers where the answer is one.
+pub const FUNCTIONS: &[(&str, BuiltinFunction)] = &[
+    ("sqrt", sqrt),
+    ("floor", floor),
+    ("ceil", ceil),
+    ("round", round),
+    ("pow", pow),
+];
+
+// An integer or a float, as a float
+fn number_argument(args: &[Object], position: usize, function: &str) -> Result<f64, EvalError> {
+    match &args[position - 1] {
+        value if matches!(value.type_name(), "INTEGER" | "FLOAT") => Ok(to_float(value)),
+        other => Err(EvalError::InvalidArgument {
+            function: function.to_string(),
+            position,
+            expected: "NUMBER",
+            got: other.type_name(),
+        }),
+    }
+}
+
+// Always a float, NaN for negative numbers
+fn sqrt(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    Ok(Object::Float(number_argument(&args, 1, "sqrt")?.sqrt()))
+}
+
+fn floor(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    to_integer(args, "floor", f64::floor)
+}
+
+fn ceil(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    to_integer(args, "ceil", f64::ceil)
+}
+
+// Halfway cases round away from zero
+fn round(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    to_integer(args, "round", f64::round)
+}
+
+// Integers are already whole, so they come back as they are. A float has to
+// land in the integer range, which NaN and the infinities never do.
+fn to_integer(args: Vec<Object>, function: &str, whole: fn(f64) -> f64) -> Result<Object, EvalError> {
+    check_argument_count(&args, 1)?;
+    let value = number_argument(&args, 1, function)?;
+    if args[0].type_name() == "INTEGER" {
+        return Ok(args.into_iter().next().unwrap());
+    }
+    let rounded = whole(value);
+    // i64::MAX is not a float, the nearest one is 2^63 just past it
+    if rounded.is_nan() || rounded < i64::MIN as f64 || rounded >= i64::MAX as f64 {
+        return Err(EvalError::IntegerOverflow(format!("{}({:?})", function, value)));
+    }
+    Ok(Object::Integer(rounded as i64))
+}
+
+// An integer raised to a non-negative integer is an integer, checked for
+// overflow. A float on either side, or a negative exponent, gives a float.
+fn pow(_: &mut dyn Runtime, args: Vec<Object>) -> Result<Object, EvalError> {
+    check_argument_count(&args, 2)?;
+    let base = number_argument(&args, 1, "pow")?;
+    let exponent = number_argument(&args, 2, "pow")?;
+    match (&args[0], &args[1]) {
+        (Object::Integer(b), Object::Integer(e)) if *e >= 0 => u32::try_from(*e).ok()
+            .and_then(|e| b.checked_pow(e))
+            .map(Object::Integer)
+            .ok_or_else(|| EvalError::IntegerOverflow(format!("pow({}, {})", b, e))),
+        _ => Ok(Object::Float(base.powf(exponent))),
+    }
+}
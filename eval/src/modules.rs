@@ -0,0 +1,83 @@
+use object::shared::{Rc, RefCell};
+use token::{Symbol, Token};
+use lexer::Lexer;
+use parser::Parser;
+use object::{Environment, EvalError, Module, Object};
+
+// Loads the module named by a path literal the first time it is imported and
+// hands back the cached one afterwards
+pub fn import(path: &Token, env: &Rc<RefCell<Environment>>) -> Result<Object, EvalError> {
+    let name = object::module_name(token::string_contents(&path.literal));
+    let modules = env.borrow().modules();
+    if let Some(module) = modules.borrow().get(name) {
+        return Ok(Object::Module(module));
+    }
+
+    modules.borrow_mut().begin(name)?;
+    let result = load(name, env);
+    modules.borrow_mut().finish(result.as_ref().ok().cloned());
+    result.map(Object::Module)
+}
+
+// Spans inside the module do not point into the importing source, so errors
+// are reported at the import with the module's own position in the message
+fn load(name: &str, env: &Rc<RefCell<Environment>>) -> Result<Rc<Module>, EvalError> {
+    let failed = |reason: String| EvalError::ImportFailed { module: name.to_string(), reason };
+    if let Some((capability, functions)) = crate::stdlib::native(name) {
+        crate::stdlib::allowed(capability, &env.borrow().config())?;
+        let mut scope = env.borrow().new_module();
+        for (function, func) in functions {
+            scope.set(*function, Object::Builtin(*func));
+        }
+        return Ok(Rc::new(Module { name: name.to_string(), env: Rc::new(RefCell::new(scope)) }));
+    }
+    let source = match crate::stdlib::source(name) {
+        Some(source) => source.to_string(),
+        None => env.borrow().modules().borrow().source(name)?,
+    };
+    let program = Parser::new(Lexer::new(&source)).parse_program().map_err(|errors| failed(errors[0].to_string()))?;
+
+    let mut scope = env.borrow().new_module();
+    for (function, func) in crate::stdlib::module_builtins(name) {
+        scope.set(*function, Object::Builtin(*func));
+    }
+    let scope = Rc::new(RefCell::new(scope));
+    crate::eval_program(&program, &scope).map_err(|err| match err.error {
+        // Already describes the innermost module that failed
+        EvalError::ModuleNotFound(_) | EvalError::ImportFailed { .. } | EvalError::ImportCycle(_) => err.error,
+        _ => failed(err.to_string()),
+    })?;
+    Ok(Rc::new(Module { name: name.to_string(), env: scope }))
+}
+
+pub fn member(object: Object, name: Symbol) -> Result<Object, EvalError> {
+    let found = match &object {
+        Object::Module(module) => module.member(name),
+        Object::Struct(instance) => instance.member(name),
+        _ => None,
+    };
+    found.ok_or_else(|| EvalError::NoSuchMember {
+        object: match &object {
+            Object::Module(module) => format!("module {}", module.name),
+            Object::Struct(instance) => format!("struct {}", instance.definition.name),
+            other => other.type_name().to_string(),
+        },
+        member: name.to_string(),
+    })
+}
+
+// Changes the struct's member where it is. Modules share their bindings with
+// everything that imported them, so theirs stay as they are.
+pub fn assign_member(object: &mut Object, name: Symbol, value: Object) -> Result<(), EvalError> {
+    match object {
+        Object::Struct(instance) => match instance.definition.fields.iter().position(|&field| field == name) {
+            Some(position) => {
+                instance.values[position] = value;
+                Ok(())
+            },
+            None => member(object.clone(), name).map(drop),
+        },
+        Object::Module(module) => Err(EvalError::InvalidAssignmentTarget(format!("{}.{}", module.name, name))),
+        other => member(other.clone(), name).map(drop),
+    }
+}
@@ -0,0 +1,75 @@
+use object::shared::Rc;
+use token::Symbol;
+use object::{EvalError, Object};
+use crate::{apply_index_operator, assign_index, modules};
+
+// One step from a value to an element or member of it, on the way to the
+// place an assignment changes
+pub enum Step {
+    Index(Object),
+    Member(Symbol),
+}
+
+impl Step {
+    fn get(&self, container: &Object) -> Result<Object, EvalError> {
+        match self {
+            Step::Index(index) => apply_index_operator(container.clone(), index.clone()),
+            Step::Member(name) => modules::member(container.clone(), *name),
+        }
+    }
+
+    // The element or member where it is, if it is there to be changed
+    fn get_mut<'v>(&self, container: &'v mut Object) -> Option<&'v mut Object> {
+        match (self, container) {
+            (Step::Index(Object::Integer(i)), Object::Array(elements)) => {
+                let i = usize::try_from(*i).ok().filter(|&i| i < elements.len())?;
+                Some(&mut Rc::make_mut(elements)[i])
+            },
+            (Step::Index(index), Object::Hash(pairs)) => {
+                let key = index.hash_key().ok().filter(|key| pairs.contains_key(key))?;
+                Rc::make_mut(pairs).get_mut(&key)
+            },
+            (Step::Member(name), Object::Struct(instance)) => {
+                let position = instance.definition.fields.iter().position(|field| field == name)?;
+                Some(&mut instance.values[position])
+            },
+            _ => None,
+        }
+    }
+
+    fn set(&self, container: &mut Object, value: Object) -> Result<(), EvalError> {
+        match self {
+            Step::Index(index) => assign_index(container, index.clone(), value),
+            Step::Member(name) => modules::assign_member(container, *name, value),
+        }
+    }
+}
+
+// What the steps lead to from the value, as reading it would give
+pub fn get_in(value: &Object, steps: &[Step]) -> Result<Object, EvalError> {
+    let mut current = value.clone();
+    for step in steps {
+        current = step.get(&current)?;
+    }
+    Ok(current)
+}
+
+// Puts the value at the end of the steps. Arrays, hashes and structs are
+// values, so one shared with another value is copied before it changes, see
+// Rc::make_mut. What has no place to change in is rebuilt around the value
+// instead, which is where anything that cannot be assigned to errors.
+pub fn set_in(container: &mut Object, steps: &[Step], value: Object) -> Result<(), EvalError> {
+    match steps {
+        [] => *container = value,
+        [step] => step.set(container, value)?,
+        [step, rest @ ..] => match step.get_mut(container) {
+            Some(inner) => set_in(inner, rest, value)?,
+            None => {
+                let mut inner = step.get(container)?;
+                set_in(&mut inner, rest, value)?;
+                step.set(container, inner)?;
+            },
+        },
+    }
+    Ok(())
+}
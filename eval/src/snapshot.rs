@@ -0,0 +1,589 @@
+use std::collections::HashMap;
+use std::fmt;
+use object::shared::{Rc, RefCell};
+use object::{BuiltinFunction, Environment, EvalError, Function, Module, Object, Struct, StructType};
+use ast::{BlockStatement, Expression, ExpressionStatement, FunctionLiteral, Identifier, Program, Statement};
+use token::{Symbol, Token, TokenType};
+use lexer::Lexer;
+use parser::Parser;
+use crate::builtins::BUILTINS;
+use crate::stdlib::{self, Capability};
+
+// A saved global scope and everything its bindings reach: the scopes
+// functions close over, the modules loaded so far and the results an
+// interactive session kept. Functions are saved as source and parsed again
+// on restore. Like compiled programs, snapshots start with magic bytes and a
+// version, and numbers are LEB128 varints, signed ones zigzag encoded.
+pub const MAGIC: &[u8; 4] = b"MKYS";
+pub const VERSION: u8 = 1;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum SnapshotError {
+    // Values that only mean something to the running program, such as
+    // channels, or to the VM
+    Unsupported(&'static str),
+    NotSnapshot,
+    UnsupportedVersion(u8),
+    UnexpectedEnd,
+    InvalidTag { what: &'static str, tag: u8 },
+    Malformed(&'static str),
+    InvalidSource(String),
+    IntegerOutOfRange(String),
+    UnknownBuiltin(String),
+    // Host functions are saved by name only, to be registered again
+    // before restoring
+    MissingHostFunction(String),
+    // A member of a native module the interpreter does not allow
+    CapabilityDisabled(&'static str),
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Unsupported(what) => write!(f, "cannot save a {} value", what),
+            SnapshotError::NotSnapshot => write!(f, "not an interpreter snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "unsupported snapshot version {}, expected {}", version, VERSION),
+            SnapshotError::UnexpectedEnd => write!(f, "snapshot ends unexpectedly"),
+            SnapshotError::InvalidTag { what, tag } => write!(f, "invalid {} tag {}", what, tag),
+            SnapshotError::Malformed(problem) => write!(f, "malformed snapshot: {}", problem),
+            SnapshotError::InvalidSource(source) => write!(f, "saved function does not parse: {}", source),
+            SnapshotError::IntegerOutOfRange(literal) => write!(f, "saved integer out of range: {}", literal),
+            SnapshotError::UnknownBuiltin(name) => write!(f, "unknown builtin {}", name),
+            SnapshotError::MissingHostFunction(name) => write!(f, "host function {} is not registered", name),
+            SnapshotError::CapabilityDisabled(what) => write!(f, "{} is disabled for this interpreter", what),
+            SnapshotError::TrailingBytes(count) => write!(f, "{} unexpected bytes after the snapshot", count),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+const GLOBAL: u8 = 0;
+const TOP_LEVEL: u8 = 1;
+const ENCLOSED: u8 = 2;
+
+const INTEGER: u8 = 0;
+const BIG_INTEGER: u8 = 1;
+const FLOAT: u8 = 2;
+const BOOLEAN: u8 = 3;
+const STRING: u8 = 4;
+const CHAR: u8 = 5;
+const ARRAY: u8 = 6;
+const HASH: u8 = 7;
+const RANGE: u8 = 8;
+const NULL: u8 = 9;
+const OK: u8 = 10;
+const ERR: u8 = 11;
+const FUNCTION: u8 = 12;
+const BUILTIN: u8 = 13;
+const NATIVE: u8 = 14;
+const MODULE: u8 = 15;
+const STRUCT_TYPE: u8 = 16;
+const STRUCT: u8 = 17;
+
+// Builtins are saved by name, members of modules as module.member, with
+// what a native module needs to be allowed
+fn builtins() -> impl Iterator<Item = (String, BuiltinFunction, Option<Capability>)> {
+    let members = stdlib::NATIVE_MODULES.iter()
+        .map(|(module, capability, members)| (module, Some(*capability), members))
+        .chain(stdlib::MODULE_BUILTINS.iter().map(|(module, members)| (module, None, members)))
+        .flat_map(|(module, capability, members)| {
+            members.iter().map(move |(name, func)| (format!("{}.{}", module, name), *func, capability))
+        });
+    BUILTINS.iter().map(|(name, func)| (name.to_string(), *func, None)).chain(members)
+}
+
+// Writes every binding of env, which has to be a top-level scope. Bindings
+// it only sees through builtins registered by the host are left out.
+pub fn save(env: &Rc<RefCell<Environment>>) -> Result<Vec<u8>, SnapshotError> {
+    let mut saver = Saver::default();
+    saver.scope(env);
+    let mut body = Writer::default();
+
+    let loaded = env.borrow().modules().borrow().loaded();
+    body.unsigned(loaded.len());
+    for (name, module) in &loaded {
+        body.string(name);
+        let id = saver.module(module);
+        body.unsigned(id);
+    }
+    let results = env.borrow().results().to_vec();
+    body.unsigned(results.len());
+    for result in &results {
+        saver.value(&mut body, result)?;
+    }
+    // Scopes found along the way are written after those before them
+    let mut next = 0;
+    while next < saver.scopes.len() {
+        let bindings = saver.scopes[next].0.borrow().bindings();
+        body.unsigned(bindings.len());
+        for (name, value) in &bindings {
+            body.string(name);
+            saver.value(&mut body, value)?;
+        }
+        next += 1;
+    }
+
+    let mut out = Writer(MAGIC.to_vec());
+    out.0.push(VERSION);
+    out.unsigned(saver.scopes.len());
+    for (id, (_, outer)) in saver.scopes.iter().enumerate() {
+        match outer {
+            _ if id == 0 => out.0.push(GLOBAL),
+            Some(outer) => {
+                out.0.push(ENCLOSED);
+                out.unsigned(*outer);
+            },
+            // Any other top level is a module's, made again with the
+            // settings of the global scope
+            None => out.0.push(TOP_LEVEL),
+        }
+    }
+    out.unsigned(saver.modules.len());
+    for (module, scope) in &saver.modules {
+        out.string(&module.name);
+        out.unsigned(*scope);
+    }
+    out.unsigned(saver.sources.len());
+    for source in &saver.sources {
+        out.string(source);
+    }
+    out.0.extend(body.0);
+    Ok(out.0)
+}
+
+// Binds the saved values in env, a top-level scope, next to those it
+// already has. Host functions the snapshot names must be bound there
+// already, and keep their new definitions.
+pub fn restore(bytes: &[u8], env: &Rc<RefCell<Environment>>) -> Result<(), SnapshotError> {
+    let header = bytes.get(..MAGIC.len() + 1).filter(|header| header.starts_with(MAGIC)).ok_or(SnapshotError::NotSnapshot)?;
+    if header[MAGIC.len()] != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(header[MAGIC.len()]));
+    }
+    let mut reader = Reader { bytes, position: MAGIC.len() + 1, global: Rc::clone(env), scopes: Vec::new(), modules: Vec::new(), functions: Vec::new() };
+
+    for _ in 0..reader.unsigned()? {
+        let scope = match reader.byte()? {
+            GLOBAL => Rc::clone(env),
+            TOP_LEVEL => Rc::new(RefCell::new(env.borrow().new_module())),
+            ENCLOSED => {
+                let outer = reader.scope()?;
+                let scope = Rc::new(RefCell::new(Environment::new_enclosed(outer)));
+                env.borrow().heap().borrow_mut().track(&scope);
+                scope
+            },
+            tag => return Err(SnapshotError::InvalidTag { what: "scope", tag }),
+        };
+        reader.scopes.push(scope);
+    }
+    for _ in 0..reader.unsigned()? {
+        let name = reader.string()?;
+        let scope = reader.scope()?;
+        reader.modules.push(Rc::new(Module { name, env: scope }));
+    }
+    for _ in 0..reader.unsigned()? {
+        let source = reader.string()?;
+        let function = parse_function(&source).ok_or(SnapshotError::InvalidSource(source))?;
+        reader.functions.push(function);
+    }
+
+    let mut loaded = Vec::new();
+    for _ in 0..reader.unsigned()? {
+        let name = reader.string()?;
+        loaded.push((name, reader.module()?));
+    }
+    let mut results = Vec::new();
+    for _ in 0..reader.unsigned()? {
+        results.push(reader.value()?);
+    }
+    // Read in full before any of it is bound, so a broken snapshot leaves
+    // env as it was
+    let mut bindings = Vec::new();
+    for scope in reader.scopes.clone() {
+        for _ in 0..reader.unsigned()? {
+            let name = reader.string()?;
+            bindings.push((Rc::clone(&scope), name, reader.value()?));
+        }
+    }
+    if reader.position != bytes.len() {
+        return Err(SnapshotError::TrailingBytes(bytes.len() - reader.position));
+    }
+
+    for (name, module) in loaded {
+        env.borrow().modules().borrow_mut().insert(&name, module);
+    }
+    for result in results {
+        env.borrow_mut().push_result(result);
+    }
+    for (scope, name, value) in bindings {
+        scope.borrow_mut().set(name.as_str(), value);
+    }
+    Ok(())
+}
+
+// Printed as a function literal, the same as a script would write it
+fn function_source(function: &Function) -> String {
+    let literal = FunctionLiteral {
+        token: Token::new(TokenType::Fn, "fn".to_string()),
+        parameters: function.parameters.clone(),
+        parameter_types: vec![None; function.parameters.len()],
+        return_type: None,
+        body: (*function.body).clone(),
+    };
+    let statement = ExpressionStatement { token: literal.token.clone(), expression: Expression::FunctionLiteral(literal), semicolon: false };
+    ast::pretty::pretty(&Program { statements: vec![Statement::ExpressionStatement(statement)] }, 4)
+}
+
+fn parse_function(source: &str) -> Option<(Vec<Identifier>, Rc<BlockStatement>)> {
+    let mut program = Parser::new(Lexer::new(source)).parse_program().ok()?;
+    match program.statements.pop() {
+        Some(Statement::ExpressionStatement(ExpressionStatement { expression: Expression::FunctionLiteral(literal), .. })) if program.statements.is_empty() => {
+            Some((literal.parameters, Rc::new(literal.body)))
+        },
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct Saver {
+    // Each with the scope enclosing it, which always comes first. The
+    // global scope is the very first.
+    scopes: Vec<(Rc<RefCell<Environment>>, Option<usize>)>,
+    scope_ids: HashMap<*const RefCell<Environment>, usize>,
+    modules: Vec<(Rc<Module>, usize)>,
+    module_ids: HashMap<*const Module, usize>,
+    // Copies of a function share their body, and so its source
+    sources: Vec<String>,
+    source_ids: HashMap<*const BlockStatement, usize>,
+}
+
+impl Saver {
+    fn scope(&mut self, env: &Rc<RefCell<Environment>>) -> usize {
+        if let Some(&id) = self.scope_ids.get(&Rc::as_ptr(env)) {
+            return id;
+        }
+        let outer = env.borrow().outer().map(|outer| self.scope(&outer));
+        let id = self.scopes.len();
+        self.scope_ids.insert(Rc::as_ptr(env), id);
+        self.scopes.push((Rc::clone(env), outer));
+        id
+    }
+
+    fn module(&mut self, module: &Rc<Module>) -> usize {
+        if let Some(&id) = self.module_ids.get(&Rc::as_ptr(module)) {
+            return id;
+        }
+        let scope = self.scope(&module.env);
+        let id = self.modules.len();
+        self.module_ids.insert(Rc::as_ptr(module), id);
+        self.modules.push((Rc::clone(module), scope));
+        id
+    }
+
+    fn source(&mut self, function: &Function) -> usize {
+        if let Some(&id) = self.source_ids.get(&Rc::as_ptr(&function.body)) {
+            return id;
+        }
+        let id = self.sources.len();
+        self.source_ids.insert(Rc::as_ptr(&function.body), id);
+        self.sources.push(function_source(function));
+        id
+    }
+
+    fn value(&mut self, out: &mut Writer, value: &Object) -> Result<(), SnapshotError> {
+        match value {
+            Object::Integer(value) => {
+                out.0.push(INTEGER);
+                out.signed(*value);
+            },
+            // Only the bigint feature makes these, written out in decimal
+            other if other.type_name() == "INTEGER" => {
+                out.0.push(BIG_INTEGER);
+                out.string(&other.inspect());
+            },
+            Object::Float(value) => {
+                out.0.push(FLOAT);
+                out.0.extend_from_slice(&value.to_bits().to_le_bytes());
+            },
+            Object::Boolean(value) => {
+                out.0.push(BOOLEAN);
+                out.0.push(*value as u8);
+            },
+            Object::String(value) => {
+                out.0.push(STRING);
+                out.string(value);
+            },
+            Object::Char(value) => {
+                out.0.push(CHAR);
+                out.unsigned(*value as usize);
+            },
+            Object::Array(elements) => {
+                out.0.push(ARRAY);
+                out.unsigned(elements.len());
+                for element in elements.iter() {
+                    self.value(out, element)?;
+                }
+            },
+            Object::Hash(pairs) => {
+                out.0.push(HASH);
+                out.unsigned(pairs.len());
+                for (key, value) in pairs.iter() {
+                    self.value(out, &Object::from(key.clone()))?;
+                    self.value(out, value)?;
+                }
+            },
+            Object::Range(start, end) => {
+                out.0.push(RANGE);
+                out.signed(*start);
+                out.signed(*end);
+            },
+            Object::Null => out.0.push(NULL),
+            Object::Result(Ok(value)) => {
+                out.0.push(OK);
+                self.value(out, value)?;
+            },
+            Object::Result(Err(error)) => {
+                out.0.push(ERR);
+                self.value(out, error)?;
+            },
+            Object::Function(function) => {
+                out.0.push(FUNCTION);
+                let source = self.source(function);
+                out.unsigned(source);
+                let scope = self.scope(&function.env);
+                out.unsigned(scope);
+            },
+            Object::Builtin(func) => {
+                let (name, _, _) = builtins().find(|(_, builtin, _)| std::ptr::fn_addr_eq(*builtin, *func)).ok_or(SnapshotError::Unsupported("host builtin"))?;
+                out.0.push(BUILTIN);
+                out.string(&name);
+            },
+            Object::Native(native) => {
+                out.0.push(NATIVE);
+                out.string(&native.name);
+            },
+            Object::Module(module) => {
+                out.0.push(MODULE);
+                let id = self.module(module);
+                out.unsigned(id);
+            },
+            Object::StructType(definition) => {
+                out.0.push(STRUCT_TYPE);
+                out.struct_type(definition);
+            },
+            Object::Struct(instance) => {
+                out.0.push(STRUCT);
+                out.struct_type(&instance.definition);
+                for value in &instance.values {
+                    self.value(out, value)?;
+                }
+            },
+            other => return Err(SnapshotError::Unsupported(other.type_name())),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn unsigned(&mut self, mut value: usize) {
+        while value >= 0x80 {
+            self.0.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn signed(&mut self, value: i64) {
+        self.unsigned(((value << 1) ^ (value >> 63)) as usize);
+    }
+
+    fn string(&mut self, value: &str) {
+        self.unsigned(value.len());
+        self.0.extend_from_slice(value.as_bytes());
+    }
+
+    fn struct_type(&mut self, definition: &StructType) {
+        self.string(definition.name.as_str());
+        self.unsigned(definition.fields.len());
+        for field in &definition.fields {
+            self.string(field.as_str());
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    global: Rc<RefCell<Environment>>,
+    scopes: Vec<Rc<RefCell<Environment>>>,
+    modules: Vec<Rc<Module>>,
+    functions: Vec<(Vec<Identifier>, Rc<BlockStatement>)>,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, SnapshotError> {
+        let byte = *self.bytes.get(self.position).ok_or(SnapshotError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], SnapshotError> {
+        let end = self.position.checked_add(len).filter(|&end| end <= self.bytes.len()).ok_or(SnapshotError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn unsigned(&mut self) -> Result<usize, SnapshotError> {
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift >= 64 {
+                return Err(SnapshotError::Malformed("number too long"));
+            }
+            value |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value as usize);
+            }
+        }
+    }
+
+    fn signed(&mut self) -> Result<i64, SnapshotError> {
+        let value = self.unsigned()? as u64;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn string(&mut self) -> Result<String, SnapshotError> {
+        let len = self.unsigned()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| SnapshotError::Malformed("string is not valid UTF-8"))
+    }
+
+    // References to scopes, modules and functions are indexes into what
+    // was read before them
+    fn index<T: Clone>(&mut self, items: fn(&Self) -> &[T], missing: &'static str) -> Result<T, SnapshotError> {
+        let index = self.unsigned()?;
+        items(self).get(index).cloned().ok_or(SnapshotError::Malformed(missing))
+    }
+
+    fn scope(&mut self) -> Result<Rc<RefCell<Environment>>, SnapshotError> {
+        self.index(|reader| &reader.scopes, "reference to a missing scope")
+    }
+
+    fn module(&mut self) -> Result<Rc<Module>, SnapshotError> {
+        self.index(|reader| &reader.modules, "reference to a missing module")
+    }
+
+    fn struct_type(&mut self) -> Result<Rc<StructType>, SnapshotError> {
+        let name = Symbol::intern(&self.string()?);
+        let mut fields = Vec::new();
+        for _ in 0..self.unsigned()? {
+            fields.push(Symbol::intern(&self.string()?));
+        }
+        Ok(Rc::new(StructType { name, fields }))
+    }
+
+    fn value(&mut self) -> Result<Object, SnapshotError> {
+        let value = match self.byte()? {
+            INTEGER => Object::Integer(self.signed()?),
+            BIG_INTEGER => {
+                let literal = self.string()?;
+                crate::big_integer_literal(&literal).map_err(|_| SnapshotError::IntegerOutOfRange(literal))?
+            },
+            FLOAT => Object::Float(f64::from_bits(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))),
+            BOOLEAN => Object::Boolean(self.byte()? != 0),
+            STRING => Object::String(self.string()?),
+            CHAR => {
+                let code = self.unsigned()?;
+                Object::Char(u32::try_from(code).ok().and_then(char::from_u32).ok_or(SnapshotError::Malformed("invalid char"))?)
+            },
+            ARRAY => {
+                let mut elements = Vec::new();
+                for _ in 0..self.unsigned()? {
+                    elements.push(self.value()?);
+                }
+                Object::Array(Rc::new(elements))
+            },
+            HASH => {
+                let mut pairs = std::collections::BTreeMap::new();
+                for _ in 0..self.unsigned()? {
+                    let key = self.value()?.hash_key().map_err(|_| SnapshotError::Malformed("unhashable key"))?;
+                    pairs.insert(key, self.value()?);
+                }
+                Object::Hash(Rc::new(pairs))
+            },
+            RANGE => Object::Range(self.signed()?, self.signed()?),
+            NULL => Object::Null,
+            OK => Object::Result(Ok(Box::new(self.value()?))),
+            ERR => Object::Result(Err(Box::new(self.value()?))),
+            FUNCTION => {
+                let (parameters, body) = self.index(|reader| &reader.functions, "reference to a missing function")?;
+                Object::Function(Function { parameters, body, env: self.scope()? })
+            },
+            BUILTIN => {
+                let name = self.string()?;
+                let (_, func, capability) = builtins().find(|(builtin, _, _)| *builtin == name).ok_or(SnapshotError::UnknownBuiltin(name))?;
+                if let Some(capability) = capability {
+                    stdlib::allowed(capability, &self.global.borrow().config()).map_err(|err| match err {
+                        EvalError::CapabilityDisabled(what) => SnapshotError::CapabilityDisabled(what),
+                        _ => unreachable!(),
+                    })?;
+                }
+                Object::Builtin(func)
+            },
+            NATIVE => {
+                let name = self.string()?;
+                match self.global.borrow().get(name.as_str()) {
+                    Some(Object::Native(native)) if native.name == name => Object::Native(native),
+                    _ => return Err(SnapshotError::MissingHostFunction(name)),
+                }
+            },
+            MODULE => Object::Module(self.module()?),
+            STRUCT_TYPE => Object::StructType(self.struct_type()?),
+            STRUCT => {
+                let definition = self.struct_type()?;
+                let mut values = Vec::new();
+                for _ in 0..definition.fields.len() {
+                    values.push(self.value()?);
+                }
+                Object::Struct(Struct { definition, values })
+            },
+            tag => return Err(SnapshotError::InvalidTag { what: "value", tag }),
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers() {
+        let values = [0, 1, -1, 63, -64, 300, i64::MAX, i64::MIN];
+        let mut writer = Writer::default();
+        for value in values {
+            writer.signed(value);
+        }
+        writer.0.push(0xff);
+
+        let mut reader = Reader {
+            bytes: &writer.0,
+            position: 0,
+            global: Rc::new(RefCell::new(Environment::new())),
+            scopes: Vec::new(),
+            modules: Vec::new(),
+            functions: Vec::new(),
+        };
+        for value in values {
+            assert_eq!(reader.signed(), Ok(value));
+        }
+        // a continuation bit with nothing after it
+        assert_eq!(reader.unsigned(), Err(SnapshotError::UnexpectedEnd));
+    }
+}
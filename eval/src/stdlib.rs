@@ -0,0 +1,114 @@
+use object::{BuiltinFunction, EvalConfig, EvalError};
+
+// Modules written in the language itself, shipped inside the binary and
+// imported as "std/<name>". They are only parsed when first imported.
+pub const MODULES: &[(&str, &str)] = &[
+    ("std/arrays", include_str!("../stdlib/arrays.mky")),
+    ("std/functional", include_str!("../stdlib/functional.mky")),
+    ("std/math", include_str!("../stdlib/math.mky")),
+    ("std/strings", include_str!("../stdlib/strings.mky")),
+];
+
+// The builtins a module is made of, by name
+pub type Members = &'static [(&'static str, BuiltinFunction)];
+
+// What a native module reaches outside the interpreter for, which the host
+// can turn off in its EvalConfig
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Capability {
+    Filesystem,
+    Network,
+}
+
+// Modules implemented in Rust. Their members are builtins.
+pub const NATIVE_MODULES: &[(&str, Capability, Members)] = &[
+    ("std/file", Capability::Filesystem, crate::file::FUNCTIONS),
+    ("std/http", Capability::Network, crate::http::FUNCTIONS),
+];
+
+// Rust functions bound in one of MODULES before its source runs, for what
+// the language cannot do itself
+pub const MODULE_BUILTINS: &[(&str, Members)] = &[
+    ("std/math", crate::math::FUNCTIONS),
+];
+
+// Fails unless config lets scripts use what the capability covers
+pub fn allowed(capability: Capability, config: &EvalConfig) -> Result<(), EvalError> {
+    let (allowed, what) = match capability {
+        Capability::Filesystem => (config.filesystem, "file system access"),
+        Capability::Network => (config.network, "network access"),
+    };
+    match allowed {
+        true => Ok(()),
+        false => Err(EvalError::CapabilityDisabled(what)),
+    }
+}
+
+pub fn source(name: &str) -> Option<&'static str> {
+    MODULES.iter()
+        .find(|(module, _)| *module == name)
+        .map(|(_, source)| *source)
+}
+
+pub fn native(name: &str) -> Option<(Capability, Members)> {
+    NATIVE_MODULES.iter()
+        .find(|(module, _, _)| *module == name)
+        .map(|(_, capability, functions)| (*capability, *functions))
+}
+
+pub fn module_builtins(name: &str) -> Members {
+    MODULE_BUILTINS.iter()
+        .find(|(module, _)| *module == name)
+        .map_or(&[], |(_, functions)| *functions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object::shared::{Rc, RefCell};
+    use lexer::Lexer;
+    use parser::Parser;
+    use ast::Statement;
+    use object::Environment;
+
+    const TESTS: &[(&str, &str)] = &[
+        ("arrays_test.mky", include_str!("../stdlib/tests/arrays_test.mky")),
+        ("functional_test.mky", include_str!("../stdlib/tests/functional_test.mky")),
+        ("math_test.mky", include_str!("../stdlib/tests/math_test.mky")),
+        ("strings_test.mky", include_str!("../stdlib/tests/strings_test.mky")),
+    ];
+
+    // Each test file defines test_* functions, the same ones `interpreter
+    // test` runs
+    #[test]
+    fn test_stdlib() {
+        for (file, source) in TESTS {
+            let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+            let env = Rc::new(RefCell::new(Environment::new()));
+            crate::eval_program(&program, &env).unwrap();
+
+            let tests: Vec<_> = program.statements.iter()
+                .filter_map(|statement| match statement {
+                    Statement::LetStatement(ls) if ls.name.value.as_str().starts_with("test_") => Some(ls.name.value),
+                    _ => None,
+                })
+                .collect();
+            assert!(!tests.is_empty(), "{} has no tests", file);
+
+            for test in tests {
+                let function = env.borrow().get(test).unwrap();
+                if let Err(err) = crate::call_function(function, Vec::new(), &env) {
+                    panic!("{}::{}: {}", file, test, err);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_module_parses() {
+        for (name, source) in MODULES {
+            let parsed = Parser::new(Lexer::new(source)).parse_program();
+            assert!(parsed.is_ok(), "{}: {:?}", name, parsed.err());
+        }
+    }
+}
@@ -0,0 +1,156 @@
+// Running spawned functions on a pool of worker threads. A task gets a
+// copy of the function, its arguments and every scope they can reach, the
+// caller's included, so values only get out as the result join returns or
+// as copies sent on a channel. Nothing a task changes is seen by others,
+// which leaves scripts no way to race.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use object::shared::{Rc, RefCell};
+use object::{Environment, EvalError, Isolate, Object, Task};
+
+static POOL: OnceLock<Pool> = OnceLock::new();
+
+// Worker threads, kept around once started. Every task queued has a worker
+// of its own, idle or newly started, as a task can wait on a channel for
+// one queued after it.
+struct Pool {
+    queue: Sender<Task>,
+    receiver: Mutex<Receiver<Task>>,
+    // Workers waiting for a task no other one has been started for
+    idle: AtomicUsize,
+}
+
+pub(crate) fn spawn(function: Object, arguments: Vec<Object>, env: &Rc<RefCell<Environment>>) -> Result<Object, EvalError> {
+    if !matches!(function, Object::Function(_) | Object::Builtin(_) | Object::Native(_)) {
+        return Err(EvalError::NotCallable(function.type_name()));
+    }
+    let mut isolate = Isolate::new();
+    let function = isolate.copy(&function);
+    let arguments: Vec<Object> = arguments.iter().map(|argument| isolate.copy(argument)).collect();
+    // Builtins the function calls write to the caller's streams
+    let env = isolate.scope(env);
+    let task = Task::new(move || crate::call_function(function, arguments, &env).map_err(|err| err.error));
+    pool().submit(task.clone());
+    Ok(Object::Task(task))
+}
+
+fn pool() -> &'static Pool {
+    POOL.get_or_init(|| {
+        let (queue, receiver) = mpsc::channel();
+        Pool { queue, receiver: Mutex::new(receiver), idle: AtomicUsize::new(0) }
+    })
+}
+
+impl Pool {
+    fn submit(&'static self, task: Task) {
+        if self.idle.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |idle| idle.checked_sub(1)).is_err() {
+            thread::spawn(move || self.work());
+        }
+        // Joining runs tasks no worker has taken, so they finish even if
+        // the worker never does
+        let _ = self.queue.send(task);
+    }
+
+    fn work(&self) {
+        loop {
+            let task = match self.receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => return,
+            };
+            match task {
+                Ok(task) => task.run(),
+                Err(_) => return,
+            }
+            self.idle.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lexer::Lexer;
+    use parser::Parser;
+    use object::shared::{Rc, RefCell};
+    use object::{Environment, EvalError, Object};
+
+    fn eval(input: &str) -> Result<Object, EvalError> {
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        crate::eval_program(&program, &Rc::new(RefCell::new(Environment::new()))).map_err(|err| err.error)
+    }
+
+    #[test]
+    fn test_spawn() {
+        let tests = vec![
+            ("let t = spawn(fn() { 1 + 2 }); join(t)", Ok(Object::Integer(3))),
+            ("spawn(fn(a, b) { a * b }, 6, 7).join()", Ok(Object::Integer(42))),
+            ("type(spawn(fn() {}))", Ok(Object::String("TASK".to_string()))),
+            ("let t = spawn(fn() { [1] }); [join(t), join(t)]", eval("[[1], [1]]")),
+            ("spawn(len, \"abc\").join()", Ok(Object::Integer(3))),
+            ("let square = fn(x) { x * x }; let ts = map([1, 2, 3], fn(x) { spawn(square, x) }); map(ts, join)", eval("[1, 4, 9]")),
+            // Tasks spawning and joining tasks of their own
+            ("let fib = fn(n) { n < 2 ? n : spawn(fib, n - 1).join() + fib(n - 2) }; spawn(fib, 12).join()", Ok(Object::Integer(144))),
+            ("join([\"a\", \"b\"], \"-\")", Ok(Object::String("a-b".to_string()))),
+            ("spawn(1)", Err(EvalError::NotCallable("INTEGER"))),
+            ("spawn()", Err(EvalError::WrongArgumentCount { got: 0, want: 1 })),
+            ("spawn(fn() { 1 / 0 }).join()", Err(EvalError::DivisionByZero)),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_channels() {
+        let tests = vec![
+            // Workers wait on each other, more of them than there are cores
+            ("let chs = map(0..32, fn(_) { channel() });
+              let ts = map(0..32, fn(i) { spawn(fn() { i == 31 ? send(chs[i], i) : send(chs[i], recv(chs[i + 1]) + i) }) });
+              recv(chs[0])", Ok(Object::Integer(496))),
+            ("let ch = channel(); spawn(fn() { for (let i = 0; i < 3; i++) { ch.send(i); } ch.close(); });
+              let got = []; for (let v = recv(ch); v != null; v = recv(ch)) { got = push(got, v); } got", eval("[0, 1, 2]")),
+            ("let a = channel(); let b = channel(); spawn(fn() { send(b, \"b\") }); select([a, b])", eval("[1, \"b\"]")),
+            ("let a = channel(); close(a); select([a])", Ok(Object::Null)),
+            ("let ch = channel(); close(ch); send(ch, 1)", Err(EvalError::ChannelClosed)),
+            // What is sent is a copy
+            ("let ch = channel(); let n = 0; let f = fn() { n += 1; n }; send(ch, f); let g = recv(ch); g(); g(); [f(), n]", eval("[1, 1]")),
+            ("select([1])", Err(EvalError::UnexpectedType { expected: "CHANNEL", got: "INTEGER" })),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(eval(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_spawn_isolation() {
+        // A task changes copies of what it closes over, never the originals
+        let input = "
+            let count = 0;
+            let items = [];
+            let bump = fn() { count += 1; items = push(items, count); count };
+            let tasks = map(0..8, fn(_) { spawn(bump) });
+            [map(tasks, join), count, items]
+        ";
+        assert_eq!(eval(input), eval("[[1, 1, 1, 1, 1, 1, 1, 1], 0, []]"));
+
+        // Results are copies too: a counter made by a task keeps counting
+        // in whoever joined it
+        let input = "
+            let make = fn() { let n = 0; fn() { n += 1; n } };
+            let t = spawn(make);
+            let a = join(t);
+            let b = join(t);
+            a(); a();
+            [a(), b()]
+        ";
+        assert_eq!(eval(input), eval("[3, 1]"));
+
+        // Tasks draw numbers of their own, which still follow from the seed
+        let input = "seed(9); map(map(0..4, fn(_) { spawn(rand) }), join)";
+        let Ok(Object::Array(first)) = eval(input) else { panic!("not an array") };
+        assert_eq!(eval(input), Ok(Object::Array(first.clone())));
+        assert_ne!(first[0], first[1]);
+    }
+}
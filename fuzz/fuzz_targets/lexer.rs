@@ -0,0 +1,17 @@
+#![no_main]
+
+use lexer::Lexer;
+use libfuzzer_sys::fuzz_target;
+use token::TokenType;
+
+// Every input lexes to tokens cut from it, ending in exactly one Eof
+fuzz_target!(|data: &[u8]| {
+    let input = String::from_utf8_lossy(data);
+    let tokens: Vec<_> = Lexer::new(&input).collect();
+
+    assert_eq!(tokens.last().map(|t| t.ttype), Some(TokenType::Eof));
+    for token in &tokens[..tokens.len() - 1] {
+        assert!(token.ttype != TokenType::Eof);
+        assert!(!token.literal.is_empty() && input.contains(token.literal));
+    }
+});
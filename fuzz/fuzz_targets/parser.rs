@@ -0,0 +1,16 @@
+#![no_main]
+
+use lexer::Lexer;
+use libfuzzer_sys::fuzz_target;
+use parser::Parser;
+
+// Any input is either a program or a list of errors, never a panic
+fuzz_target!(|data: &[u8]| {
+    let input = String::from_utf8_lossy(data);
+    if let Err(errors) = Parser::new(Lexer::new(&input)).parse_program() {
+        assert!(!errors.is_empty());
+        for err in errors {
+            let _ = err.to_string();
+        }
+    }
+});
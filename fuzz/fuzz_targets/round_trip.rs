@@ -0,0 +1,22 @@
+#![no_main]
+
+use lexer::Lexer;
+use libfuzzer_sys::fuzz_target;
+use parser::Parser;
+
+// Whatever parses prints back to source that parses to the same program,
+// both fully parenthesised and formatted
+fuzz_target!(|data: &[u8]| {
+    let input = String::from_utf8_lossy(data);
+    let Ok(program) = Parser::new(Lexer::new(&input)).parse_program() else {
+        return;
+    };
+
+    let printed = program.to_string();
+    let reparsed = Parser::new(Lexer::new(&printed)).parse_program();
+    assert_eq!(reparsed.map(|p| p.to_string()), Ok(printed.clone()), "{} printed as {}", input, printed);
+
+    let pretty = ast::pretty(&program, 4);
+    let reparsed = Parser::new(Lexer::new(&pretty)).parse_program();
+    assert_eq!(reparsed.map(|p| p.to_string()), Ok(printed), "{} formatted as {}", input, pretty);
+});
@@ -0,0 +1,25 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use lexer::Lexer;
+
+// A few kilobytes covering every kind of token, repeated into a large input
+const SNIPPET: &str = r#"
+let add = fn(x, y) { x + y; };
+let result = add(five, ten) * 2 - -3 / 4 % 5;
+// line comment
+for (let i = 0; i < 10; i += 1) { total = total ?? 0 + i; }
+for (c in "hello world") { out = out + c; } /* block */
+let c = '\n'; let flags = [true, false, null]; x != y; x <= y;
+"#;
+
+fn lexer(c: &mut Criterion) {
+    let input = SNIPPET.repeat(1000);
+
+    let mut group = c.benchmark_group("lexer");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.bench_function("tokens", |b| b.iter(|| Lexer::new(&input).count()));
+    group.bench_function("owned", |b| b.iter(|| Lexer::new(&input).owned().count()));
+    group.finish();
+}
+
+criterion_group!(benches, lexer);
+criterion_main!(benches);
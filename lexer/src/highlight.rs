@@ -0,0 +1,88 @@
+use std::ops::Range;
+use token::TokenType;
+use crate::Lexer;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HighlightKind {
+    Keyword,
+    Identifier,
+    Number,
+    String,
+    Operator,
+    Comment,
+    Error,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct HighlightSpan {
+    // Byte offsets into the input
+    pub range: Range<usize>,
+    pub kind: HighlightKind,
+}
+
+// Classifies the source for editors and terminal colouring. Spans are in
+// order and cover everything except whitespace, so unknown characters and
+// unterminated literals show up as errors rather than going missing.
+pub fn highlight(input: &str) -> Vec<HighlightSpan> {
+    let mut lexer = Lexer::new(input).with_comments();
+    let mut spans = Vec::new();
+    while let Some(token) = lexer.next() {
+        let kind = match token.ttype {
+            TokenType::Eof => break,
+            TokenType::Let | TokenType::Fn | TokenType::True | TokenType::False | TokenType::If | TokenType::Else
+            | TokenType::Return | TokenType::For | TokenType::In | TokenType::Null | TokenType::Import | TokenType::Match
+            | TokenType::Struct | TokenType::Try | TokenType::Catch | TokenType::Throw => HighlightKind::Keyword,
+            TokenType::Identifier => HighlightKind::Identifier,
+            TokenType::Integer | TokenType::Float => HighlightKind::Number,
+            TokenType::String | TokenType::InterpolatedString | TokenType::RawString | TokenType::Char => HighlightKind::String,
+            TokenType::Comment | TokenType::DocComment => HighlightKind::Comment,
+            TokenType::Illegal => HighlightKind::Error,
+            _ => HighlightKind::Operator,
+        };
+        spans.push(HighlightSpan { range: lexer.start..lexer.start + token.literal.len(), kind });
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight() {
+        let input = "let s = \"héllo\" + 'c'; // note\nfn(x) { x ?? 10 } /* a\nb */ # \"open";
+        let spans: Vec<(&str, HighlightKind)> = highlight(input).into_iter().map(|span| (&input[span.range], span.kind)).collect();
+
+        assert_eq!(spans, vec![
+            ("let", HighlightKind::Keyword),
+            ("s", HighlightKind::Identifier),
+            ("=", HighlightKind::Operator),
+            ("\"héllo\"", HighlightKind::String),
+            ("+", HighlightKind::Operator),
+            ("'c'", HighlightKind::String),
+            (";", HighlightKind::Operator),
+            ("// note", HighlightKind::Comment),
+            ("fn", HighlightKind::Keyword),
+            ("(", HighlightKind::Operator),
+            ("x", HighlightKind::Identifier),
+            (")", HighlightKind::Operator),
+            ("{", HighlightKind::Operator),
+            ("x", HighlightKind::Identifier),
+            ("??", HighlightKind::Operator),
+            ("10", HighlightKind::Number),
+            ("}", HighlightKind::Operator),
+            ("/* a\nb */", HighlightKind::Comment),
+            ("#", HighlightKind::Error),
+            ("\"open", HighlightKind::Error),
+        ]);
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        let spans = highlight("x /* never closed");
+        assert_eq!(spans, vec![
+            HighlightSpan { range: 0..1, kind: HighlightKind::Identifier },
+            HighlightSpan { range: 2..17, kind: HighlightKind::Error },
+        ]);
+    }
+}
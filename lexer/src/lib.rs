@@ -1,172 +1,508 @@
-use token::{Token, TokenType};
+pub mod highlight;
 
-pub struct Lexer {
-    input: Vec<char>,
+use std::fmt::Write;
+use token::{Token, TokenRef, TokenType};
+
+pub use token::LexError;
+
+pub use highlight::{highlight, HighlightKind, HighlightSpan};
+
+// Words read as keywords rather than identifiers
+pub const KEYWORDS: &[(&str, TokenType)] = &[
+    ("let", TokenType::Let),
+    ("fn", TokenType::Fn),
+    ("true", TokenType::True),
+    ("false", TokenType::False),
+    ("if", TokenType::If),
+    ("else", TokenType::Else),
+    ("return", TokenType::Return),
+    ("for", TokenType::For),
+    ("in", TokenType::In),
+    ("null", TokenType::Null),
+    ("import", TokenType::Import),
+    ("match", TokenType::Match),
+    ("struct", TokenType::Struct),
+    ("try", TokenType::Try),
+    ("catch", TokenType::Catch),
+    ("throw", TokenType::Throw),
+];
+
+// Positions are byte offsets into the input, so token literals are slices of
+// it rather than fresh allocations
+pub struct Lexer<'a> {
+    input: &'a str,
     position: usize,
     read_position: usize,
     ch: char,
+    line: usize,
+    column: usize,
+    // Byte offset where the last token returned started
+    start: usize,
+    comments: bool,
+    docs: bool,
 }
 
-impl Lexer {
-    pub fn new(input: String) -> Self {
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
         let mut l = Lexer {
-            input: input.chars().collect(),
+            input,
             position: 0,
             read_position: 0,
             ch: '\0',
+            line: 1,
+            column: 0,
+            start: 0,
+            comments: false,
+            docs: false,
         };
         l.read_char();
         l
     }
 
+    // Yields comments as Comment tokens instead of skipping them
+    pub fn with_comments(mut self) -> Self {
+        self.comments = true;
+        self
+    }
+
+    // Yields `///` comments as DocComment tokens, for the parser to attach
+    // to the statement after them, and still skips the rest
+    pub fn with_docs(mut self) -> Self {
+        self.docs = true;
+        self
+    }
+
+    // Positions tokens as if the input started at this line and column of a
+    // larger source, as the code inside an interpolated string does
+    pub fn starting_at(mut self, line: usize, column: usize) -> Self {
+        self.line = line;
+        self.column = column;
+        self
+    }
+
+    // Yields tokens that own their literals, for callers that keep tokens
+    // around after the source is gone
+    pub fn owned(self) -> impl Iterator<Item = Token> + 'a {
+        self.map(|token| token.to_token())
+    }
+
     fn read_char(&mut self) {
-        if self.read_position >= self.input.len() {
-            self.ch = '\0';
-        } else {
-            self.ch = self.input[self.read_position];
+        if self.ch == '\n' {
+            self.line += 1;
+            self.column = 0;
         }
+        self.column += 1;
+
         self.position = self.read_position;
-        self.read_position += 1;
+        match self.input.get(self.read_position..).and_then(|rest| rest.chars().next()) {
+            Some(ch) => {
+                self.ch = ch;
+                self.read_position += ch.len_utf8();
+            },
+            None => {
+                self.ch = '\0';
+                self.read_position += 1;
+            },
+        }
     }
 
-    #[allow(dead_code)]
     fn peek(&self) -> char {
-        if self.read_position >= self.input.len() {
-            return '\0';
-        }
-        self.input[self.read_position]
+        self.input.get(self.read_position..).and_then(|rest| rest.chars().next()).unwrap_or('\0')
     }
-}
 
-impl Iterator for Lexer {
-    type Item = Token;
+    // The source text from start up to the current character
+    fn slice_from(&self, start: usize) -> &'a str {
+        &self.input[start..self.position.min(self.input.len())]
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        // Skip whitespace
-        while self.ch.is_whitespace() {
+    // A token made of the current character and, if `len` is 2, the next one
+    fn operator(&mut self, ttype: TokenType, len: usize) -> TokenRef<'a> {
+        let start = self.position;
+        for _ in 1..len {
             self.read_char();
         }
+        TokenRef::new(ttype, &self.input[start..self.read_position])
+    }
 
-        // If the read head is past the end of the input plus the eof char, return None
-        if self.read_position > self.input.len() + 1 {
-            return None;
+    // Consumes a `/* ... */` comment and returns its text. If the input ends
+    // before the comment is closed, the text is an Illegal token instead.
+    fn read_block_comment(&mut self) -> TokenRef<'a> {
+        let start = self.position;
+        self.read_char();
+        self.read_char();
+        while !(self.ch == '*' && self.peek() == '/') {
+            if self.ch == '\0' {
+                return TokenRef::illegal(self.slice_from(start), LexError::UnterminatedComment);
+            }
+            self.read_char();
         }
+        self.read_char();
+        self.read_char();
+        TokenRef::new(TokenType::Comment, self.slice_from(start))
+    }
 
+    fn read_token(&mut self) -> TokenRef<'a> {
         let token = match self.ch {
-            '+' => {
-                if self.peek() == '+' {
-                    self.read_char();
-                    Token::new(TokenType::Increment, "++".to_string())
-                } else {
-                    Token::new(TokenType::Plus, self.ch.to_string())
-                }
-            }
-            '-' => {
-                if self.peek() == '-' {
-                    self.read_char();
-                    Token::new(TokenType::Decrement, "--".to_string())
-                } else {
-                    Token::new(TokenType::Minus, self.ch.to_string())
-                }
+            '+' => match self.peek() {
+                '+' => self.operator(TokenType::Increment, 2),
+                '=' => self.operator(TokenType::PlusAssign, 2),
+                _ => self.operator(TokenType::Plus, 1),
+            },
+            '-' => match self.peek() {
+                '-' => self.operator(TokenType::Decrement, 2),
+                '=' => self.operator(TokenType::MinusAssign, 2),
+                '>' => self.operator(TokenType::Arrow, 2),
+                _ => self.operator(TokenType::Minus, 1),
+            },
+            '*' => match self.peek() {
+                '=' => self.operator(TokenType::AsteriskAssign, 2),
+                _ => self.operator(TokenType::Asterisk, 1),
+            },
+            '/' => match self.peek() {
+                '=' => self.operator(TokenType::SlashAssign, 2),
+                _ => self.operator(TokenType::Slash, 1),
+            },
+            '?' => match self.peek() {
+                '?' => self.operator(TokenType::NullCoalesce, 2),
+                _ => self.operator(TokenType::Question, 1),
+            },
+            '|' if self.peek() == '>' => self.operator(TokenType::Pipe, 2),
+            '%' => match self.peek() {
+                '=' => self.operator(TokenType::PercentAssign, 2),
+                _ => self.operator(TokenType::Percent, 1),
+            },
+            '=' => match self.peek() {
+                '=' => self.operator(TokenType::Equal, 2),
+                '>' => self.operator(TokenType::FatArrow, 2),
+                _ => self.operator(TokenType::Assign, 1),
+            },
+            '!' => match self.peek() {
+                '=' => self.operator(TokenType::NotEqual, 2),
+                _ => self.operator(TokenType::Bang, 1),
+            },
+            '<' => match self.peek() {
+                '=' => self.operator(TokenType::LessThanOrEqual, 2),
+                _ => self.operator(TokenType::LessThan, 1),
+            },
+            '>' => match self.peek() {
+                '=' => self.operator(TokenType::GreaterThanOrEqual, 2),
+                _ => self.operator(TokenType::GreaterThan, 1),
+            },
+            ',' => self.operator(TokenType::Comma, 1),
+            ';' => self.operator(TokenType::Semicolon, 1),
+            ':' => self.operator(TokenType::Colon, 1),
+            '.' if self.input[self.position..].starts_with("..=") => self.operator(TokenType::DotDotEq, 3),
+            '.' => match self.peek() {
+                '.' => self.operator(TokenType::DotDot, 2),
+                _ => self.operator(TokenType::Dot, 1),
+            },
+            '(' => self.operator(TokenType::LeftParen, 1),
+            ')' => self.operator(TokenType::RightParen, 1),
+            '[' => self.operator(TokenType::LeftSquareBracket, 1),
+            ']' => self.operator(TokenType::RightSquareBracket, 1),
+            '{' => self.operator(TokenType::LeftCurlyBracket, 1),
+            '}' => self.operator(TokenType::RightCurlyBracket, 1),
+            // A NUL byte in the input itself is not the end of it
+            '\0' if self.position >= self.input.len() => TokenRef::new(TokenType::Eof, "\0"),
+            'r' if self.peek() == '"' => {
+                self.read_char();
+                return self.read_raw_string(self.position - 1, '"');
             }
-            '*' => Token::new(TokenType::Asterisk, self.ch.to_string()),
-            '/' => Token::new(TokenType::Slash, self.ch.to_string()),
-            '?' => Token::new(TokenType::Question, self.ch.to_string()),
-            '%' => Token::new(TokenType::Percent, self.ch.to_string()),
-            '=' => {
-                if self.peek() == '=' {
-                    self.read_char();
-                    Token::new(TokenType::Equal, "==".to_string())
-                } else {
-                    Token::new(TokenType::Assign, self.ch.to_string())
-                }},
-            '!' => {
-                if self.peek() == '=' {
-                    self.read_char();
-                    Token::new(TokenType::NotEqual, "!=".to_string())
-                } else {
-                    Token::new(TokenType::Bang, self.ch.to_string())
-                }},
-            '<' => {
-                if self.peek() == '=' {
-                    self.read_char();
-                    Token::new(TokenType::LessThanOrEqual, "<=".to_string())
-                } else {
-                    Token::new(TokenType::LessThan, self.ch.to_string())
-                }},
-            '>' => {
-                if self.peek() == '=' {
-                    self.read_char();
-                    Token::new(TokenType::GreaterThanOrEqual, ">=".to_string())
-                } else {
-                    Token::new(TokenType::GreaterThan, self.ch.to_string())
-                }},
-            ',' => Token::new(TokenType::Comma, self.ch.to_string()),
-            ';' => Token::new(TokenType::Semicolon, self.ch.to_string()),
-            ':' => Token::new(TokenType::Colon, self.ch.to_string()),
-            '(' => Token::new(TokenType::LeftParen, self.ch.to_string()),
-            ')' => Token::new(TokenType::RightParen, self.ch.to_string()),
-            '[' => Token::new(TokenType::LeftSquareBracket, self.ch.to_string()),
-            ']' => Token::new(TokenType::RightSquareBracket, self.ch.to_string()),
-            '{' => Token::new(TokenType::LeftCurlyBracket, self.ch.to_string()),
-            '}' => Token::new(TokenType::RightCurlyBracket, self.ch.to_string()),
-            '\0' => Token::new(TokenType::Eof, self.ch.to_string()),
+            '`' => return self.read_raw_string(self.position, '`'),
             'a'..='z'|'A'..='Z'|'_' => {
-                let mut ident = String::new();
-                while self.ch.is_alphabetic() || self.ch == '_' {
-                    ident.push(self.ch);
+                let start = self.position;
+                // Digits may follow the first character, as in _1
+                while self.ch.is_alphanumeric() || self.ch == '_' {
                     self.read_char();
                 }
-                let token = match ident.as_str() {
-                    "let" => Token::new(TokenType::Let, ident),
-                    "fn" => Token::new(TokenType::Fn, ident),
-                    "true" => Token::new(TokenType::True, ident),
-                    "false" => Token::new(TokenType::False, ident),
-                    "if" => Token::new(TokenType::If, ident),
-                    "else" => Token::new(TokenType::Else, ident),
-                    "return" => Token::new(TokenType::Return, ident),
-                    _ => Token::new(TokenType::Identifier, ident)
-                };
-                return Some(token);
+                let ident = self.slice_from(start);
+                let ttype = KEYWORDS.iter()
+                    .find(|(keyword, _)| *keyword == ident)
+                    .map_or(TokenType::Identifier, |(_, ttype)| *ttype);
+                return TokenRef::new(ttype, ident);
             }
             '0'..='9' => {
-                let mut number = String::new();
-                while self.ch.is_digit(10) {
-                    number.push(self.ch);
+                let start = self.position;
+                while self.ch.is_ascii_digit() {
+                    self.read_char();
+                }
+                // A dot only starts a fraction when a digit follows, which
+                // leaves 1..2 a range
+                if self.ch == '.' && self.peek().is_ascii_digit() {
                     self.read_char();
+                    while self.ch.is_ascii_digit() {
+                        self.read_char();
+                    }
+                    return TokenRef::new(TokenType::Float, self.slice_from(start));
                 }
-                return Some(Token::new(TokenType::Integer, number));
+                return TokenRef::new(TokenType::Integer, self.slice_from(start));
             }
             '"' => {
-                let mut string = String::new();
-                string.push(self.ch);
+                let start = self.position;
+                let mut ttype = TokenType::String;
                 self.read_char();
                 while self.ch != '"' {
+                    if self.ch == '\0' {
+                        return TokenRef::illegal(self.slice_from(start), LexError::UnterminatedString);
+                    }
+                    if self.ch == '$' && self.peek() == '{' {
+                        // Jump to the closing brace, past any quotes inside
+                        let code = self.position + 2;
+                        let end = match embedded_end(&self.input[code..]) {
+                            Some(len) => code + len,
+                            None => self.input[code..].find('\0').map_or(self.input.len(), |len| code + len),
+                        };
+                        while self.position < end {
+                            self.read_char();
+                        }
+                        ttype = TokenType::InterpolatedString;
+                        continue;
+                    }
+                    self.read_char();
+                }
+                self.read_char();
+                return TokenRef::new(ttype, self.slice_from(start));
+            }
+            '\'' => {
+                let start = self.position;
+                self.read_char();
+                while self.ch != '\'' {
+                    // An escaped quote does not end the literal
+                    if self.ch == '\\' {
+                        self.read_char();
+                    }
                     if self.ch == '\0' || self.ch == '\n' {
-                        return Some(Token::new(TokenType::Illegal, string));
+                        return TokenRef::illegal(self.slice_from(start), LexError::UnterminatedChar);
                     }
-                    string.push(self.ch);
                     self.read_char();
                 }
-                string.push(self.ch);
                 self.read_char();
-                return Some(Token::new(TokenType::String, string));
+                let literal = self.slice_from(start);
+                return match char_literal(literal) {
+                    Ok(_) => TokenRef::new(TokenType::Char, literal),
+                    Err(error) => TokenRef::illegal(literal, error),
+                };
             }
-            _ => Token::new(TokenType::Illegal, self.ch.to_string())
+            c => TokenRef::illegal(&self.input[self.position..self.read_position], LexError::UnknownCharacter(c)),
         };
 
         self.read_char();
+        token
+    }
+}
+
+impl<'a> Lexer<'a> {
+    // r"..." and `...`, taken exactly as written up to the closing character,
+    // newlines included. The current character is the opening quote.
+    fn read_raw_string(&mut self, start: usize, close: char) -> TokenRef<'a> {
+        self.read_char();
+        while self.ch != close {
+            if self.ch == '\0' {
+                return TokenRef::illegal(self.slice_from(start), LexError::UnterminatedString);
+            }
+            self.read_char();
+        }
+        self.read_char();
+        TokenRef::new(TokenType::RawString, self.slice_from(start))
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = TokenRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // If the read head is past the end of the input plus the eof char, return None
+        if self.read_position > self.input.len() + 1 {
+            return None;
+        }
+
+        // Skip whitespace and comments
+        let (line, column) = loop {
+            while self.ch.is_whitespace() {
+                self.read_char();
+            }
+
+            let (line, column) = (self.line, self.column);
+            self.start = self.position;
+            let comment = if self.ch == '/' && self.peek() == '/' {
+                while self.ch != '\n' && self.ch != '\0' {
+                    self.read_char();
+                }
+                let text = self.slice_from(self.start);
+                // Four slashes or more are an ordinary comment, as in Rust
+                match text.starts_with("///") && !text.starts_with("////") {
+                    true => TokenRef::new(TokenType::DocComment, text),
+                    false => TokenRef::new(TokenType::Comment, text),
+                }
+            } else if self.ch == '/' && self.peek() == '*' {
+                self.read_block_comment()
+            } else {
+                break (line, column);
+            };
+
+            let kept = match comment.ttype {
+                TokenType::DocComment => self.comments || self.docs,
+                TokenType::Comment => self.comments,
+                _ => true,
+            };
+            if kept {
+                return Some(TokenRef { line, column, ..comment });
+            }
+        };
+
+        let mut token = self.read_token();
+        token.line = line;
+        token.column = column;
         Some(token)
     }
 }
 
+// A piece of an interpolated string literal: text as written, or the source
+// of an embedded expression and the byte offset in the literal it starts at
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StringPart<'a> {
+    Text(&'a str),
+    Code { source: &'a str, offset: usize },
+}
+
+// Splits a string literal, quotes included, around its `${...}`s, or None if
+// one is never closed
+pub fn string_parts(literal: &str) -> Option<Vec<StringPart<'_>>> {
+    let mut parts = Vec::new();
+    let mut offset = 1;
+    let mut rest = literal.strip_prefix('"')?.strip_suffix('"')?;
+    while let Some(open) = rest.find("${") {
+        if open > 0 {
+            parts.push(StringPart::Text(&rest[..open]));
+        }
+        let len = embedded_end(&rest[open + 2..])?;
+        parts.push(StringPart::Code { source: &rest[open + 2..open + 2 + len], offset: offset + open + 2 });
+        offset += open + 3 + len;
+        rest = &rest[open + 3 + len..];
+    }
+    if !rest.is_empty() {
+        parts.push(StringPart::Text(rest));
+    }
+    Some(parts)
+}
+
+// What the text being scanned by embedded_end is inside of
+enum Level {
+    // Code with this many braces open
+    Code(usize),
+    String,
+    // A raw string, ended by this character
+    Raw(char),
+}
+
+// The length of the code after a `${` up to its closing brace. Strings in
+// the code may hold braces and interpolations of their own, so this keeps a
+// stack of the levels it is inside.
+fn embedded_end(code: &str) -> Option<usize> {
+    let mut levels = vec![Level::Code(0)];
+    let mut previous = '\0';
+    let mut chars = code.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, next)| next);
+        match (levels.last_mut()?, c) {
+            (_, '\0') => return None,
+            (Level::Code(0), '}') => {
+                levels.pop();
+                if levels.is_empty() {
+                    return Some(i);
+                }
+            },
+            (Level::Code(braces), '}') => *braces -= 1,
+            (Level::Code(braces), '{') => *braces += 1,
+            (Level::Code(_), '"') => levels.push(Level::String),
+            (Level::Code(_), '`') => levels.push(Level::Raw('`')),
+            // Only an r that does not end a name starts a raw string
+            (Level::Code(_), 'r') if next == Some('"') && !(previous.is_alphanumeric() || previous == '_') => {
+                chars.next();
+                levels.push(Level::Raw('"'));
+            },
+            (Level::String, '"') => {
+                levels.pop();
+            },
+            (Level::String, '$') if next == Some('{') => {
+                chars.next();
+                levels.push(Level::Code(0));
+            },
+            (Level::Raw(close), c) if c == *close => {
+                levels.pop();
+            },
+            _ => {},
+        }
+        previous = c;
+    }
+    None
+}
+
+// The character a quoted literal such as 'a' or '\n' stands for, or why
+// it is not exactly one character or escape
+pub fn char_literal(literal: &str) -> Result<char, LexError> {
+    let inner = literal.strip_prefix('\'').and_then(|rest| rest.strip_suffix('\'')).ok_or(LexError::UnterminatedChar)?;
+    let mut chars = inner.chars();
+    let value = match chars.next().ok_or(LexError::BadCharLiteral)? {
+        '\\' => match chars.next().ok_or(LexError::BadCharLiteral)? {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '\\' => '\\',
+            '\'' => '\'',
+            '"' => '"',
+            c if chars.as_str().is_empty() => return Err(LexError::BadEscape(c)),
+            _ => return Err(LexError::BadCharLiteral),
+        },
+        '\'' => return Err(LexError::BadCharLiteral),
+        c => c,
+    };
+    match chars.next() {
+        None => Ok(value),
+        Some(_) => Err(LexError::BadCharLiteral),
+    }
+}
+
+// Every token of the input, Eof included, one per row with its type,
+// literal, line:column and the byte offsets it spans
+pub fn token_table(input: &str) -> String {
+    let mut rows = vec![["type".to_string(), "literal".to_string(), "position".to_string(), "bytes".to_string()]];
+    let mut lexer = Lexer::new(input);
+    while let Some(token) = lexer.next() {
+        let end = match token.ttype {
+            TokenType::Eof => lexer.start,
+            _ => lexer.start + token.literal.len(),
+        };
+        rows.push([
+            format!("{:?}", token.ttype),
+            format!("{:?}", token.literal),
+            format!("{}:{}", token.line, token.column),
+            format!("{}..{}", lexer.start, end),
+        ]);
+    }
+
+    let mut widths = [0; 4];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    for row in &rows {
+        let line = format!("{:w0$}  {:w1$}  {:w2$}  {}", row[0], row[1], row[2], row[3], w0 = widths[0], w1 = widths[1], w2 = widths[2]);
+        writeln!(out, "{}", line.trim_end()).unwrap();
+    }
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_lexer_delimiters() {
-        let mut l = Lexer::new(String::from("+-*/=,;:()[]{}++--?%"));
+        let mut l = Lexer::new("+-*/ =,;:()[]{}++--?%??|>");
 
         assert_eq!(l.next().unwrap().ttype, TokenType::Plus);
         assert_eq!(l.next().unwrap().ttype, TokenType::Minus);
@@ -186,6 +522,8 @@ mod tests {
         assert_eq!(l.next().unwrap().ttype, TokenType::Decrement);
         assert_eq!(l.next().unwrap().ttype, TokenType::Question);
         assert_eq!(l.next().unwrap().ttype, TokenType::Percent);
+        assert_eq!(l.next().unwrap().ttype, TokenType::NullCoalesce);
+        assert_eq!(l.next().unwrap().ttype, TokenType::Pipe);
         assert_eq!(l.next().unwrap().ttype, TokenType::Eof);
     }
 
@@ -200,7 +538,7 @@ mod tests {
         };
         
         let result = add(five, ten);
-        !-/*5;
+        !-/ *5;
         5 < 10 > 5;
         
         if (5 < 10) {
@@ -216,7 +554,7 @@ mod tests {
         "te st" != "test";
         "#);
 
-        let mut l = Lexer::new(input);
+        let mut l = Lexer::new(&input);
 
         assert_eq!(l.next().unwrap().ttype, TokenType::Let);
         assert_eq!(l.next().unwrap(), Token::new(TokenType::Identifier, String::from("five")));
@@ -305,4 +643,445 @@ mod tests {
         assert_eq!(l.next().unwrap().ttype, TokenType::Semicolon);
         assert_eq!(l.next().unwrap().ttype, TokenType::Eof);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_token_positions() {
+        let input = String::from("let x = 5;\n  x + \"ab\";\n\nfoo");
+        let tokens: Vec<TokenRef> = Lexer::new(&input).collect();
+
+        let expected = vec![
+            (TokenType::Let, 1, 1),
+            (TokenType::Identifier, 1, 5),
+            (TokenType::Assign, 1, 7),
+            (TokenType::Integer, 1, 9),
+            (TokenType::Semicolon, 1, 10),
+            (TokenType::Identifier, 2, 3),
+            (TokenType::Plus, 2, 5),
+            (TokenType::String, 2, 7),
+            (TokenType::Semicolon, 2, 11),
+            (TokenType::Identifier, 4, 1),
+            (TokenType::Eof, 4, 4),
+        ];
+
+        assert_eq!(tokens.len(), expected.len());
+        for (token, (ttype, line, column)) in tokens.iter().zip(expected) {
+            assert_eq!(token.ttype, ttype);
+            assert_eq!((token.line, token.column), (line, column), "wrong position for {:?}", token);
+        }
+    }
+
+    #[test]
+    fn test_compound_assignment_operators() {
+        let mut l = Lexer::new("x += 1; x -= 1; x *= 2; x /= 2; x %= 3; x=-1; x+=+1");
+
+        let expected = vec![
+            (TokenType::PlusAssign, "+="),
+            (TokenType::MinusAssign, "-="),
+            (TokenType::AsteriskAssign, "*="),
+            (TokenType::SlashAssign, "/="),
+            (TokenType::PercentAssign, "%="),
+        ];
+        for (ttype, literal) in expected {
+            assert_eq!(l.next().unwrap().ttype, TokenType::Identifier);
+            assert_eq!(l.next().unwrap(), Token::new(ttype, String::from(literal)));
+            assert_eq!(l.next().unwrap().ttype, TokenType::Integer);
+            assert_eq!(l.next().unwrap().ttype, TokenType::Semicolon);
+        }
+
+        assert_eq!(l.next().unwrap().ttype, TokenType::Identifier);
+        assert_eq!(l.next().unwrap().ttype, TokenType::Assign);
+        assert_eq!(l.next().unwrap().ttype, TokenType::Minus);
+        assert_eq!(l.next().unwrap().ttype, TokenType::Integer);
+        assert_eq!(l.next().unwrap().ttype, TokenType::Semicolon);
+        assert_eq!(l.next().unwrap().ttype, TokenType::Identifier);
+        assert_eq!(l.next().unwrap().ttype, TokenType::PlusAssign);
+        assert_eq!(l.next().unwrap().ttype, TokenType::Plus);
+        assert_eq!(l.next().unwrap().ttype, TokenType::Integer);
+        assert_eq!(l.next().unwrap().ttype, TokenType::Eof);
+    }
+
+    #[test]
+    fn test_type_annotation_tokens() {
+        let tokens: Vec<Token> = Lexer::new("fn(x: int) -> bool x-->y").owned().collect();
+        let expected = vec![
+            Token::new(TokenType::Fn, String::from("fn")),
+            Token::new(TokenType::LeftParen, String::from("(")),
+            Token::new(TokenType::Identifier, String::from("x")),
+            Token::new(TokenType::Colon, String::from(":")),
+            Token::new(TokenType::Identifier, String::from("int")),
+            Token::new(TokenType::RightParen, String::from(")")),
+            Token::new(TokenType::Arrow, String::from("->")),
+            Token::new(TokenType::Identifier, String::from("bool")),
+            Token::new(TokenType::Identifier, String::from("x")),
+            Token::new(TokenType::Decrement, String::from("--")),
+            Token::new(TokenType::GreaterThan, String::from(">")),
+            Token::new(TokenType::Identifier, String::from("y")),
+            Token::new(TokenType::Eof, String::from("\0")),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_match_tokens() {
+        let tokens: Vec<Token> = Lexer::new("match x { 1..5 => a, _ => b.c } matches").owned().collect();
+        let expected = vec![
+            Token::new(TokenType::Match, String::from("match")),
+            Token::new(TokenType::Identifier, String::from("x")),
+            Token::new(TokenType::LeftCurlyBracket, String::from("{")),
+            Token::new(TokenType::Integer, String::from("1")),
+            Token::new(TokenType::DotDot, String::from("..")),
+            Token::new(TokenType::Integer, String::from("5")),
+            Token::new(TokenType::FatArrow, String::from("=>")),
+            Token::new(TokenType::Identifier, String::from("a")),
+            Token::new(TokenType::Comma, String::from(",")),
+            Token::new(TokenType::Identifier, String::from("_")),
+            Token::new(TokenType::FatArrow, String::from("=>")),
+            Token::new(TokenType::Identifier, String::from("b")),
+            Token::new(TokenType::Dot, String::from(".")),
+            Token::new(TokenType::Identifier, String::from("c")),
+            Token::new(TokenType::RightCurlyBracket, String::from("}")),
+            Token::new(TokenType::Identifier, String::from("matches")),
+            Token::new(TokenType::Eof, String::from("\0")),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_range_tokens() {
+        let tokens: Vec<Token> = Lexer::new("1..10 a..=b ...=").owned().collect();
+        let expected = vec![
+            Token::new(TokenType::Integer, String::from("1")),
+            Token::new(TokenType::DotDot, String::from("..")),
+            Token::new(TokenType::Integer, String::from("10")),
+            Token::new(TokenType::Identifier, String::from("a")),
+            Token::new(TokenType::DotDotEq, String::from("..=")),
+            Token::new(TokenType::Identifier, String::from("b")),
+            Token::new(TokenType::DotDot, String::from("..")),
+            Token::new(TokenType::Dot, String::from(".")),
+            Token::new(TokenType::Assign, String::from("=")),
+            Token::new(TokenType::Eof, String::from("\0")),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_struct_tokens() {
+        let tokens: Vec<Token> = Lexer::new("struct P { x } structs").owned().collect();
+        let expected = vec![
+            Token::new(TokenType::Struct, String::from("struct")),
+            Token::new(TokenType::Identifier, String::from("P")),
+            Token::new(TokenType::LeftCurlyBracket, String::from("{")),
+            Token::new(TokenType::Identifier, String::from("x")),
+            Token::new(TokenType::RightCurlyBracket, String::from("}")),
+            Token::new(TokenType::Identifier, String::from("structs")),
+            Token::new(TokenType::Eof, String::from("\0")),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_float_tokens() {
+        let tokens: Vec<Token> = Lexer::new("1.5 0.25..3 2.x 7.").owned().collect();
+        let expected = vec![
+            Token::new(TokenType::Float, String::from("1.5")),
+            Token::new(TokenType::Float, String::from("0.25")),
+            Token::new(TokenType::DotDot, String::from("..")),
+            Token::new(TokenType::Integer, String::from("3")),
+            Token::new(TokenType::Integer, String::from("2")),
+            Token::new(TokenType::Dot, String::from(".")),
+            Token::new(TokenType::Identifier, String::from("x")),
+            Token::new(TokenType::Integer, String::from("7")),
+            Token::new(TokenType::Dot, String::from(".")),
+            Token::new(TokenType::Eof, String::from("\0")),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_exception_tokens() {
+        let tokens: Vec<Token> = Lexer::new("try { throw e; } catch (e) {} tries").owned().collect();
+        let expected = vec![
+            Token::new(TokenType::Try, String::from("try")),
+            Token::new(TokenType::LeftCurlyBracket, String::from("{")),
+            Token::new(TokenType::Throw, String::from("throw")),
+            Token::new(TokenType::Identifier, String::from("e")),
+            Token::new(TokenType::Semicolon, String::from(";")),
+            Token::new(TokenType::RightCurlyBracket, String::from("}")),
+            Token::new(TokenType::Catch, String::from("catch")),
+            Token::new(TokenType::LeftParen, String::from("(")),
+            Token::new(TokenType::Identifier, String::from("e")),
+            Token::new(TokenType::RightParen, String::from(")")),
+            Token::new(TokenType::LeftCurlyBracket, String::from("{")),
+            Token::new(TokenType::RightCurlyBracket, String::from("}")),
+            Token::new(TokenType::Identifier, String::from("tries")),
+            Token::new(TokenType::Eof, String::from("\0")),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_loop_keywords() {
+        let tokens: Vec<Token> = Lexer::new("for (x in xs) { forx; inside }").owned().collect();
+        let expected = vec![
+            Token::new(TokenType::For, String::from("for")),
+            Token::new(TokenType::LeftParen, String::from("(")),
+            Token::new(TokenType::Identifier, String::from("x")),
+            Token::new(TokenType::In, String::from("in")),
+            Token::new(TokenType::Identifier, String::from("xs")),
+            Token::new(TokenType::RightParen, String::from(")")),
+            Token::new(TokenType::LeftCurlyBracket, String::from("{")),
+            Token::new(TokenType::Identifier, String::from("forx")),
+            Token::new(TokenType::Semicolon, String::from(";")),
+            Token::new(TokenType::Identifier, String::from("inside")),
+            Token::new(TokenType::RightCurlyBracket, String::from("}")),
+            Token::new(TokenType::Eof, String::from("\0")),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_identifiers_with_digits() {
+        let tokens: Vec<Token> = Lexer::new("_1 x2y 3z").owned().collect();
+        let expected = vec![
+            Token::new(TokenType::Identifier, String::from("_1")),
+            Token::new(TokenType::Identifier, String::from("x2y")),
+            Token::new(TokenType::Integer, String::from("3")),
+            Token::new(TokenType::Identifier, String::from("z")),
+            Token::new(TokenType::Eof, String::from("\0")),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_token_table() {
+        let expected = "\
+type     literal  position  bytes
+Let      \"let\"    1:1       0..3
+Illegal  \"é\"      1:5       4..6
+Assign   \"=\"      1:7       7..8
+Integer  \"1\"      2:1       9..10
+Eof      \"\\0\"     2:2       10..10
+";
+        assert_eq!(token_table("let é =\n1"), expected);
+    }
+
+    #[test]
+    fn test_import() {
+        let tokens: Vec<Token> = Lexer::new("import \"lib/math\"; math.sqrt importer").owned().collect();
+        let expected = vec![
+            Token::new(TokenType::Import, String::from("import")),
+            Token::new(TokenType::String, String::from("\"lib/math\"")),
+            Token::new(TokenType::Semicolon, String::from(";")),
+            Token::new(TokenType::Identifier, String::from("math")),
+            Token::new(TokenType::Dot, String::from(".")),
+            Token::new(TokenType::Identifier, String::from("sqrt")),
+            Token::new(TokenType::Identifier, String::from("importer")),
+            Token::new(TokenType::Eof, String::from("\0")),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = String::from(r#"// leading comment
+        let x = 5; // trailing comment
+        /* block
+           comment */ x / 2;
+        /**/ x/*inline*/+ 1;
+        //"#);
+
+        let tokens: Vec<TokenType> = Lexer::new(&input).map(|t| t.ttype).collect();
+        assert_eq!(tokens, vec![
+            TokenType::Let, TokenType::Identifier, TokenType::Assign, TokenType::Integer, TokenType::Semicolon,
+            TokenType::Identifier, TokenType::Slash, TokenType::Integer, TokenType::Semicolon,
+            TokenType::Identifier, TokenType::Plus, TokenType::Integer, TokenType::Semicolon,
+            TokenType::Eof,
+        ]);
+
+        let comments: Vec<String> = Lexer::new(&input).with_comments()
+            .filter(|t| t.ttype == TokenType::Comment)
+            .map(|t| t.literal.to_string())
+            .collect();
+        assert_eq!(comments, vec!["// leading comment", "// trailing comment", "/* block\n           comment */", "/**/", "/*inline*/", "//"]);
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let input = "/// Adds one\n///\n//// not docs\nlet inc = fn(x) { x + 1 }; // trailing";
+        let tokens: Vec<(TokenType, &str)> = Lexer::new(input).with_docs().map(|t| (t.ttype, t.literal)).take(4).collect();
+        assert_eq!(tokens, vec![
+            (TokenType::DocComment, "/// Adds one"),
+            (TokenType::DocComment, "///"),
+            (TokenType::Let, "let"),
+            (TokenType::Identifier, "inc"),
+        ]);
+        // Without asking they are skipped like any other comment
+        assert_eq!(Lexer::new(input).next().unwrap().ttype, TokenType::Let);
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let mut l = Lexer::new("x;\n  /* never closed\nstill open");
+
+        assert_eq!(l.next().unwrap().ttype, TokenType::Identifier);
+        assert_eq!(l.next().unwrap().ttype, TokenType::Semicolon);
+
+        let token = l.next().unwrap();
+        assert_eq!(token, Token::new(TokenType::Illegal, String::from("/* never closed\nstill open")));
+        assert_eq!((token.line, token.column), (2, 3));
+        assert_eq!(l.next().unwrap().ttype, TokenType::Eof);
+        assert_eq!(l.next(), None);
+    }
+
+    #[test]
+    fn test_char_literals() {
+        let input = String::from(r#"'a' 'é' '\n' '\'' '\\' '"' '' 'ab' '\q' 'x"#);
+        let tokens: Vec<(TokenType, String)> = Lexer::new(&input).map(|t| (t.ttype, t.literal.to_string())).collect();
+        assert_eq!(tokens, vec![
+            (TokenType::Char, "'a'".to_string()),
+            (TokenType::Char, "'é'".to_string()),
+            (TokenType::Char, r"'\n'".to_string()),
+            (TokenType::Char, r"'\''".to_string()),
+            (TokenType::Char, r"'\\'".to_string()),
+            (TokenType::Char, "'\"'".to_string()),
+            (TokenType::Illegal, "''".to_string()),
+            (TokenType::Illegal, "'ab'".to_string()),
+            (TokenType::Illegal, r"'\q'".to_string()),
+            (TokenType::Illegal, "'x".to_string()),
+            (TokenType::Eof, "\0".to_string()),
+        ]);
+
+        assert_eq!(char_literal("'a'"), Ok('a'));
+        assert_eq!(char_literal(r"'\t'"), Ok('\t'));
+        assert_eq!(char_literal(r"'\0'"), Ok('\0'));
+        assert_eq!(char_literal(r"'\''"), Ok('\''));
+        assert_eq!(char_literal("a"), Err(LexError::UnterminatedChar));
+    }
+
+    #[test]
+    fn test_interpolated_strings() {
+        let input = r#""a ${b} c" "$5 {}" "${f("}", "${x}")}!" "${" "${x" "${"}"#;
+        let tokens: Vec<(TokenType, String)> = Lexer::new(input).map(|t| (t.ttype, t.literal.to_string())).collect();
+        assert_eq!(tokens, vec![
+            (TokenType::InterpolatedString, r#""a ${b} c""#.to_string()),
+            (TokenType::String, r#""$5 {}""#.to_string()),
+            (TokenType::InterpolatedString, r#""${f("}", "${x}")}!""#.to_string()),
+            (TokenType::Illegal, r#""${" "${x" "${"}"#.to_string()),
+            (TokenType::Eof, "\0".to_string()),
+        ]);
+
+        assert_eq!(string_parts(r#""a ${b} c""#), Some(vec![
+            StringPart::Text("a "),
+            StringPart::Code { source: "b", offset: 5 },
+            StringPart::Text(" c"),
+        ]));
+        assert_eq!(string_parts(r#""${x}${ {"k": "}"}["k"] }""#), Some(vec![
+            StringPart::Code { source: "x", offset: 3 },
+            StringPart::Code { source: r#" {"k": "}"}["k"] "#, offset: 7 },
+        ]));
+        assert_eq!(string_parts(r#""${x""#), None);
+    }
+
+    #[test]
+    fn test_raw_and_multiline_strings() {
+        let input = "r\"a\\n ${x}\" `say \"hi\"\n${y}` \"one\ntwo\" bar\"s\" \"${r\"}\" + `}`}\" x\n`open";
+        let tokens: Vec<(TokenType, String, usize)> = Lexer::new(input).map(|t| (t.ttype, t.literal.to_string(), t.line)).collect();
+        assert_eq!(tokens, vec![
+            (TokenType::RawString, r#"r"a\n ${x}""#.to_string(), 1),
+            (TokenType::RawString, "`say \"hi\"\n${y}`".to_string(), 1),
+            (TokenType::String, "\"one\ntwo\"".to_string(), 2),
+            (TokenType::Identifier, "bar".to_string(), 3),
+            (TokenType::String, "\"s\"".to_string(), 3),
+            (TokenType::InterpolatedString, "\"${r\"}\" + `}`}\"".to_string(), 3),
+            (TokenType::Identifier, "x".to_string(), 3),
+            (TokenType::Illegal, "`open".to_string(), 4),
+            (TokenType::Eof, "\0".to_string(), 4),
+        ]);
+        assert_eq!(token::string_contents("r\"a\""), "a");
+        assert_eq!(token::string_contents("`b`"), "b");
+    }
+
+    #[test]
+    fn test_lex_errors() {
+        let tests = vec![
+            ("\"open", LexError::UnterminatedString),
+            ("`open\nstill", LexError::UnterminatedString),
+            ("r\"open", LexError::UnterminatedString),
+            ("\"${x\"", LexError::UnterminatedString),
+            ("'x", LexError::UnterminatedChar),
+            ("'\\'", LexError::UnterminatedChar),
+            ("'\\q'", LexError::BadEscape('q')),
+            ("''", LexError::BadCharLiteral),
+            ("'ab'", LexError::BadCharLiteral),
+            ("/* open", LexError::UnterminatedComment),
+            ("#", LexError::UnknownCharacter('#')),
+            ("é", LexError::UnknownCharacter('é')),
+        ];
+
+        for (input, expected) in tests {
+            let token = Lexer::new(input).next().unwrap();
+            assert_eq!(token.ttype, TokenType::Illegal, "{}", input);
+            assert_eq!(token.error, Some(expected), "{}", input);
+        }
+        assert_eq!(LexError::BadEscape('q').to_string(), "unknown escape \\q in character literal");
+    }
+
+    #[test]
+    fn test_non_ascii_input() {
+        let input = "let naïve = \"héllo\"; ünïcode";
+        let tokens: Vec<TokenRef> = Lexer::new(input).collect();
+        let expected = vec![
+            (TokenType::Let, "let", 1),
+            (TokenType::Identifier, "naïve", 5),
+            (TokenType::Assign, "=", 11),
+            (TokenType::String, "\"héllo\"", 13),
+            (TokenType::Semicolon, ";", 20),
+            (TokenType::Illegal, "ü", 22),
+            (TokenType::Identifier, "nïcode", 23),
+            (TokenType::Eof, "\0", 29),
+        ];
+
+        assert_eq!(tokens.len(), expected.len());
+        for (token, (ttype, literal, column)) in tokens.iter().zip(expected) {
+            assert_eq!((token.ttype, token.literal, token.column), (ttype, literal, column));
+        }
+    }
+
+    // Deterministic xorshift, enough to vary generated inputs without a
+    // dependency
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    // Any input lexes to a finite stream that ends in a single Eof, with
+    // every literal taken from the input
+    #[test]
+    fn test_arbitrary_input() {
+        let alphabet: Vec<char> = "abz_09 \t\n+-*/%=!<>?:;,.()[]{}\"'\\\0éü€😀".chars().collect();
+        let mut rng = Rng(0x9E3779B97F4A7C15);
+        for _ in 0..2_000 {
+            let len = rng.below(40);
+            let input: String = (0..len).map(|_| alphabet[rng.below(alphabet.len())]).collect();
+            let tokens: Vec<TokenRef> = Lexer::new(&input).with_comments().collect();
+
+            assert!(tokens.len() <= input.chars().count() + 1, "{:?}", input);
+            assert_eq!(tokens.iter().filter(|t| t.ttype == TokenType::Eof).count(), 1, "{:?}: {:?}", input, tokens);
+            assert_eq!(tokens.last().map(|t| t.ttype), Some(TokenType::Eof), "{:?}", input);
+            for token in &tokens[..tokens.len() - 1] {
+                assert!(!token.literal.is_empty() && input.contains(token.literal), "{:?}: {:?}", input, token);
+                // and only the Illegal ones say what is wrong with them
+                assert_eq!(token.error.is_some(), token.ttype == TokenType::Illegal, "{:?}: {:?}", input, token);
+            }
+        }
+
+        let types: Vec<TokenType> = Lexer::new("a\0b").map(|t| t.ttype).collect();
+        assert_eq!(types, vec![TokenType::Identifier, TokenType::Illegal, TokenType::Identifier, TokenType::Eof]);
+    }
+}
@@ -0,0 +1,467 @@
+use std::collections::HashMap;
+use ast::visit::{walk_expression, walk_statement, Visitor};
+use ast::{Expression, Identifier, Program, Statement};
+use parser::ParseError;
+use token::{Span, Symbol, Token, TokenType};
+
+// A stretch of source between two positions, end exclusive. Positions use the
+// lexer's coordinates: lines and columns count from 1, columns in characters.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Range {
+    pub start: Span,
+    pub end: Span,
+}
+
+impl Range {
+    pub fn of_token(token: &Token) -> Range {
+        let width = match token.ttype {
+            TokenType::Eof => 1,
+            _ => token.literal.chars().count().max(1),
+        };
+        let start = token.span();
+        Range { start, end: Span { line: start.line, column: start.column + width } }
+    }
+
+    pub fn contains(&self, position: Span) -> bool {
+        let key = |span: Span| (span.line, span.column);
+        key(self.start) <= key(position) && key(position) < key(self.end)
+    }
+
+    fn merge(self, other: Range) -> Range {
+        let key = |span: Span| (span.line, span.column);
+        Range {
+            start: if key(other.start) < key(self.start) { other.start } else { self.start },
+            end: if key(other.end) > key(self.end) { other.end } else { self.end },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SymbolKind {
+    Module,
+    Function,
+    Variable,
+    Struct,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct DocumentSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // The whole let statement, and just its name
+    pub range: Range,
+    pub selection_range: Range,
+    pub children: Vec<DocumentSymbol>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Hover {
+    pub expression: String,
+    pub range: Range,
+}
+
+pub fn diagnostics(errors: &[ParseError]) -> Vec<(Range, String)> {
+    errors.iter().map(|err| (Range::of_token(err.token()), err.message())).collect()
+}
+
+// One symbol per let statement, nesting the lets inside function bodies.
+// Loops do not open a scope, so their lets are listed alongside the loop,
+// and neither do blocks, which have no name to list them under.
+pub fn document_symbols(program: &Program) -> Vec<DocumentSymbol> {
+    let mut symbols = Vec::new();
+    for statement in &program.statements {
+        collect_symbols(statement, &mut symbols);
+    }
+    symbols
+}
+
+fn collect_symbols(statement: &Statement, symbols: &mut Vec<DocumentSymbol>) {
+    match statement {
+        Statement::LetStatement(ls) => {
+            let (kind, children) = match &ls.value {
+                Expression::FunctionLiteral(func) => {
+                    let mut children = Vec::new();
+                    for statement in &func.body.statements {
+                        collect_symbols(statement, &mut children);
+                    }
+                    (SymbolKind::Function, children)
+                },
+                _ => (SymbolKind::Variable, Vec::new()),
+            };
+            symbols.push(DocumentSymbol {
+                name: ls.name.value.to_string(),
+                kind,
+                range: statement_range(statement),
+                selection_range: Range::of_token(&ls.name.token),
+                children,
+            });
+        },
+        Statement::ForStatement(fs) => {
+            collect_symbols(&fs.init, symbols);
+            for statement in &fs.body.statements {
+                collect_symbols(statement, symbols);
+            }
+        },
+        Statement::ForInStatement(fs) => {
+            for statement in &fs.body.statements {
+                collect_symbols(statement, symbols);
+            }
+        },
+        Statement::BlockStatement(bs) => {
+            for statement in &bs.statements {
+                collect_symbols(statement, symbols);
+            }
+        },
+        Statement::ImportStatement(is) => symbols.push(DocumentSymbol {
+            name: is.name.value.to_string(),
+            kind: SymbolKind::Module,
+            range: statement_range(statement),
+            selection_range: Range::of_token(&is.path),
+            children: Vec::new(),
+        }),
+        Statement::StructStatement(ss) => symbols.push(DocumentSymbol {
+            name: ss.name.value.to_string(),
+            kind: SymbolKind::Struct,
+            range: statement_range(statement),
+            selection_range: Range::of_token(&ss.name.token),
+            children: Vec::new(),
+        }),
+        Statement::ReturnStatement(_) | Statement::ThrowStatement(_) | Statement::ExpressionStatement(_) => {},
+    }
+}
+
+// The innermost expression under the cursor, printed the way the parser
+// grouped it
+pub fn hover(program: &Program, position: Span) -> Option<Hover> {
+    let mut finder = HoverFinder { position, found: None };
+    finder.visit_program(program);
+    finder.found
+}
+
+// Where the identifier under the cursor was bound: by a let, an import, a
+// function parameter, a for-in variable or a catch
+pub fn definition(program: &Program, position: Span) -> Option<Range> {
+    let mut resolver = Resolver { position, scopes: vec![HashMap::new()], found: None };
+    resolver.visit_program(program);
+    resolver.found
+}
+
+fn statement_range(statement: &Statement) -> Range {
+    let mut extent = Extent { range: None };
+    extent.visit_statement(statement);
+    extent.range.expect("every statement has a token")
+}
+
+fn expression_range(expression: &Expression) -> Option<Range> {
+    let mut extent = Extent { range: None };
+    extent.visit_expression(expression);
+    extent.range
+}
+
+// Spans every token in the nodes it visits. Closing brackets are not kept
+// in the tree, so ranges end at the last token that is.
+struct Extent {
+    range: Option<Range>,
+}
+
+impl Extent {
+    fn add(&mut self, token: &Token) {
+        let range = Range::of_token(token);
+        self.range = Some(match self.range {
+            Some(current) => current.merge(range),
+            None => range,
+        });
+    }
+}
+
+impl Visitor for Extent {
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::LetStatement(ls) => self.add(&ls.token),
+            Statement::ReturnStatement(rs) => self.add(&rs.token),
+            Statement::ThrowStatement(ts) => self.add(&ts.token),
+            Statement::ExpressionStatement(es) => self.add(&es.token),
+            Statement::ForStatement(fs) => self.add(&fs.token),
+            Statement::ForInStatement(fs) => self.add(&fs.token),
+            Statement::ImportStatement(is) => {
+                self.add(&is.token);
+                self.add(&is.path);
+            },
+            Statement::StructStatement(ss) => {
+                self.add(&ss.token);
+                for field in &ss.fields {
+                    self.add(&field.token);
+                }
+            },
+            Statement::BlockStatement(bs) => self.add(&bs.token),
+        }
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::IntegerLiteral(l) => self.add(&l.token),
+            Expression::FloatLiteral(l) => self.add(&l.token),
+            Expression::BigIntegerLiteral(token)
+            | Expression::StringLiteral(token)
+            | Expression::BooleanLiteral(token)
+            | Expression::NullLiteral(token) => self.add(token),
+            Expression::InterpolatedString(s) => self.add(&s.token),
+            Expression::CharLiteral(c) => self.add(&c.token),
+            Expression::Prefix(p) => self.add(&p.operator),
+            Expression::Infix(i) => self.add(&i.operator),
+            Expression::Postfix(p) => self.add(&p.operator),
+            Expression::Propagate(p) => self.add(&p.operator),
+            Expression::Assignment(a) => self.add(&a.token),
+            Expression::ArrayLiteral(a) => self.add(&a.token),
+            Expression::RangeLiteral(r) => self.add(&r.token),
+            Expression::HashLiteral(h) => self.add(&h.token),
+            Expression::FunctionLiteral(f) => self.add(&f.token),
+            Expression::Call(c) => self.add(&c.token),
+            Expression::Index(i) => self.add(&i.token),
+            Expression::Member(m) => self.add(&m.property.token),
+            Expression::MethodCall(m) => {
+                self.add(&m.method.token);
+                self.add(&m.token);
+            },
+            Expression::Import(i) => self.add(&i.path),
+            Expression::Match(m) => self.add(&m.token),
+            Expression::Try(t) => self.add(&t.token),
+            Expression::Block(b) => self.add(&b.token),
+            Expression::StructLiteral(s) => {
+                for (field, _) in &s.fields {
+                    self.add(&field.token);
+                }
+            },
+            Expression::Identifier(_) | Expression::Ternary(_) => {},
+        }
+        walk_expression(self, expression);
+    }
+
+    fn visit_identifier(&mut self, identifier: &Identifier) {
+        self.add(&identifier.token);
+    }
+}
+
+struct HoverFinder {
+    position: Span,
+    found: Option<Hover>,
+}
+
+impl Visitor for HoverFinder {
+    // Children are visited after their parent, so the last match is the innermost
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let Some(range) = expression_range(expression) {
+            if range.contains(self.position) {
+                self.found = Some(Hover { expression: expression.to_string(), range });
+            }
+        }
+        // The method of a call is shown as the member it is, without the call
+        if let Expression::MethodCall(call) = expression {
+            let method = Range::of_token(&call.method.token);
+            if method.contains(self.position) {
+                let range = expression_range(&call.object).map_or(method, |object| object.merge(method));
+                self.found = Some(Hover { expression: format!("{}.{}", call.object, call.method), range });
+            }
+        }
+        walk_expression(self, expression);
+    }
+}
+
+// Tracks the bindings visible at each point, one scope per function body
+// or block
+struct Resolver {
+    position: Span,
+    scopes: Vec<HashMap<Symbol, Range>>,
+    found: Option<Range>,
+}
+
+impl Resolver {
+    fn declare(&mut self, identifier: &Identifier) {
+        let range = Range::of_token(&identifier.token);
+        if range.contains(self.position) {
+            self.found = Some(range);
+        }
+        self.scopes.last_mut().unwrap().insert(identifier.value, range);
+    }
+
+    fn lookup(&self, name: Symbol) -> Option<Range> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(&name).copied())
+    }
+}
+
+impl Visitor for Resolver {
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            // A function may call itself, so its name is bound before the body
+            Statement::LetStatement(ls) if matches!(ls.value, Expression::FunctionLiteral(_)) => {
+                self.declare(&ls.name);
+                self.visit_expression(&ls.value);
+            },
+            Statement::LetStatement(ls) => {
+                self.visit_expression(&ls.value);
+                self.declare(&ls.name);
+            },
+            Statement::ForInStatement(fs) => {
+                self.visit_expression(&fs.iterable);
+                self.declare(&fs.variable);
+                self.visit_block_statement(&fs.body);
+            },
+            Statement::ImportStatement(is) => self.declare(&is.name),
+            Statement::StructStatement(ss) => self.declare(&ss.name),
+            Statement::BlockStatement(bs) => {
+                self.scopes.push(HashMap::new());
+                self.visit_block_statement(bs);
+                self.scopes.pop();
+            },
+            _ => walk_statement(self, statement),
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::FunctionLiteral(func) => {
+                self.scopes.push(HashMap::new());
+                for parameter in &func.parameters {
+                    self.declare(parameter);
+                }
+                self.visit_block_statement(&func.body);
+                self.scopes.pop();
+            },
+            Expression::Block(block) => {
+                self.scopes.push(HashMap::new());
+                self.visit_block_statement(block);
+                self.scopes.pop();
+            },
+            // The parameter is only bound in the handler
+            Expression::Try(t) => {
+                self.scopes.push(HashMap::new());
+                self.visit_block_statement(&t.body);
+                self.scopes.pop();
+                self.scopes.push(HashMap::new());
+                self.declare(&t.parameter);
+                self.visit_block_statement(&t.handler);
+                self.scopes.pop();
+            },
+            _ => walk_expression(self, expression),
+        }
+    }
+
+    fn visit_identifier(&mut self, identifier: &Identifier) {
+        if Range::of_token(&identifier.token).contains(self.position) {
+            self.found = self.lookup(identifier.value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn parse(source: &str) -> Program {
+        Parser::new(Lexer::new(source)).parse_program().unwrap()
+    }
+
+    fn at(line: usize, column: usize) -> Span {
+        Span { line, column }
+    }
+
+    fn range(line: usize, start: usize, end: usize) -> Range {
+        Range { start: at(line, start), end: at(line, end) }
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let errors = Parser::new(Lexer::new("let = 1;\nlet y 2;")).parse_program().unwrap_err();
+        assert_eq!(diagnostics(&errors), vec![
+            (range(1, 5, 6), "expected next token to be Identifier, got Assign".to_string()),
+            (range(2, 7, 8), "expected next token to be Assign, got Integer".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_document_symbols() {
+        let program = parse("let add = fn(x, y) {\n  let sum = x + y;\n  sum;\n};\nfor (i in [1]) { let last = i; }");
+        let symbols = document_symbols(&program);
+
+        let names: Vec<(&str, SymbolKind)> = symbols.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+        assert_eq!(names, vec![("add", SymbolKind::Function), ("last", SymbolKind::Variable)]);
+        assert_eq!(symbols[0].selection_range, range(1, 5, 8));
+        assert_eq!(symbols[0].range, Range { start: at(1, 1), end: at(3, 6) });
+        assert_eq!(symbols[0].children.len(), 1);
+        assert_eq!(symbols[0].children[0].name, "sum");
+        assert_eq!(symbols[0].children[0].range, range(2, 3, 18));
+    }
+
+    #[test]
+    fn test_hover() {
+        let program = parse("let x = 1 + 2 * 3;\nadd(x, -4);");
+        let tests = vec![
+            (at(1, 15), Some(("(2 * 3)", range(1, 13, 18)))),
+            (at(1, 11), Some(("(1 + (2 * 3))", range(1, 9, 18)))),
+            (at(1, 9), Some(("1", range(1, 9, 10)))),
+            (at(2, 8), Some(("(-4)", range(2, 8, 10)))),
+            (at(2, 4), Some(("add(x, (-4))", range(2, 1, 10)))),
+            (at(1, 2), None),
+        ];
+
+        for (position, expected) in tests {
+            let found = hover(&program, position).map(|h| (h.expression, h.range));
+            assert_eq!(found, expected.map(|(e, r)| (e.to_string(), r)), "hover at {}", position);
+        }
+    }
+
+    #[test]
+    fn test_definition() {
+        let source = "let x = 1;\nlet f = fn(x) { x + y; f(x) };\nlet y = x;\nfor (item in [x]) { item; }";
+        let program = parse(source);
+        let tests = vec![
+            // The parameter shadows the outer x
+            (at(2, 17), Some(range(2, 12, 13))),
+            (at(3, 9), Some(range(1, 5, 6))),
+            // y is only bound after the function
+            (at(2, 21), None),
+            (at(2, 24), Some(range(2, 5, 6))),
+            (at(4, 21), Some(range(4, 6, 10))),
+            (at(1, 5), Some(range(1, 5, 6))),
+            (at(1, 9), None),
+        ];
+
+        for (position, expected) in tests {
+            assert_eq!(definition(&program, position), expected, "definition at {}", position);
+        }
+    }
+
+    #[test]
+    fn test_definition_in_blocks() {
+        let program = parse("let x = 1;\n{ let x = 2; x; }\nx;");
+        assert_eq!(definition(&program, at(2, 14)), Some(range(2, 7, 8)));
+        assert_eq!(definition(&program, at(3, 1)), Some(range(1, 5, 6)));
+    }
+
+    #[test]
+    fn test_definition_in_catch() {
+        let program = parse("let e = 1;
+try { e } catch (e) { e }
+e;");
+        assert_eq!(definition(&program, at(2, 7)), Some(range(1, 5, 6)));
+        assert_eq!(definition(&program, at(2, 23)), Some(range(2, 18, 19)));
+        assert_eq!(definition(&program, at(3, 1)), Some(range(1, 5, 6)));
+    }
+
+    #[test]
+    fn test_imports() {
+        let program = parse("import \"lib/math\";\nmath.square(2);");
+        let symbols = document_symbols(&program);
+        assert_eq!(symbols.len(), 1);
+        assert_eq!((symbols[0].name.as_str(), symbols[0].kind), ("math", SymbolKind::Module));
+        assert_eq!(symbols[0].range, range(1, 1, 18));
+
+        // Members are not bindings of this file
+        assert_eq!(definition(&program, at(2, 2)), Some(range(1, 8, 12)));
+        assert_eq!(definition(&program, at(2, 7)), None);
+        assert_eq!(hover(&program, at(2, 7)).map(|h| h.expression), Some("math.square".to_string()));
+    }
+}
@@ -0,0 +1,335 @@
+pub mod analysis;
+pub mod protocol;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use serde_json::{json, Value};
+use ast::Program;
+use lexer::Lexer;
+use parser::{ParseError, Parser, TextEdit};
+use token::Span;
+use analysis::{DocumentSymbol, Range, SymbolKind};
+
+// Error code for requests the server does not implement
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// Open documents by URI. Clients send the ranges that changed, and each
+// document is reparsed only around them.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+struct Document {
+    source: String,
+    program: Result<Program, Vec<ParseError>>,
+}
+
+impl Document {
+    fn new(source: String) -> Document {
+        let program = Parser::new(Lexer::new(&source)).parse_program();
+        Document { source, program }
+    }
+
+    // Applies one entry of a didChange's contentChanges: the text of a
+    // range, or of the whole document when there is no range
+    fn change(&mut self, change: &Value) {
+        let text = change["text"].as_str().unwrap_or_default().to_string();
+        let range = &change["range"];
+        if range.is_null() {
+            *self = Document::new(text);
+            return;
+        }
+        let edit = TextEdit { start: to_span(&self.source, &range["start"]), end: to_span(&self.source, &range["end"]), text };
+        let source = edit.apply(&self.source);
+        // A tree is only there to reuse when the last parse succeeded
+        match std::mem::replace(&mut self.program, Err(Vec::new())) {
+            Ok(old) => {
+                self.program = Parser::reparse(old, &source, &edit);
+                self.source = source;
+            },
+            Err(_) => *self = Document::new(source),
+        }
+    }
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server::default()
+    }
+
+    // The responses and notifications to send back for one client message
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let id = message.get("id").cloned();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+
+        let result = match message["method"].as_str().unwrap_or_default() {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 2,
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                    "definitionProvider": true,
+                },
+                "serverInfo": { "name": "interpreter-lsp" },
+            }),
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), Document::new(text.to_string()));
+                return vec![self.publish_diagnostics(&uri)];
+            },
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array().map(Vec::as_slice).unwrap_or_default();
+                if let Some(document) = self.documents.get_mut(&uri) {
+                    for change in changes {
+                        document.change(change);
+                    }
+                }
+                return vec![self.publish_diagnostics(&uri)];
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![notification("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": [] }))];
+            },
+            "textDocument/hover" => self.with_program(&uri, |source, program| {
+                let hover = analysis::hover(program, to_span(source, &params["position"]))?;
+                Some(json!({
+                    "contents": { "kind": "markdown", "value": format!("```\n{}\n```", hover.expression) },
+                    "range": to_range(source, hover.range),
+                }))
+            }),
+            "textDocument/documentSymbol" => self.with_program(&uri, |source, program| {
+                let symbols: Vec<Value> = analysis::document_symbols(program).iter().map(|s| symbol(source, s)).collect();
+                Some(Value::Array(symbols))
+            }),
+            "textDocument/definition" => self.with_program(&uri, |source, program| {
+                let range = analysis::definition(program, to_span(source, &params["position"]))?;
+                Some(json!({ "uri": uri, "range": to_range(source, range) }))
+            }),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            },
+            method => match id {
+                Some(id) => return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": METHOD_NOT_FOUND, "message": format!("unsupported method {}", method) },
+                })],
+                // Notifications such as initialized need no reply
+                None => return Vec::new(),
+            },
+        };
+
+        match id {
+            Some(id) => vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })],
+            None => Vec::new(),
+        }
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> Value {
+        let diagnostics: Vec<Value> = match self.documents.get(uri) {
+            None | Some(Document { program: Ok(_), .. }) => Vec::new(),
+            Some(Document { source, program: Err(errors) }) => analysis::diagnostics(errors).into_iter().map(|(range, message)| json!({
+                "range": to_range(source, range),
+                "severity": 1,
+                "source": "interpreter",
+                "message": message,
+            })).collect(),
+        };
+        notification("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": diagnostics }))
+    }
+
+    // Runs a query against the parsed document. Documents with syntax errors
+    // have no tree to query, so those requests get a null result.
+    fn with_program<F>(&self, uri: &str, query: F) -> Value
+    where
+        F: FnOnce(&str, &Program) -> Option<Value>,
+    {
+        let Some(Document { source, program: Ok(program) }) = self.documents.get(uri) else { return Value::Null };
+        query(source, program).unwrap_or(Value::Null)
+    }
+}
+
+// Serves a client over a pair of streams until it sends exit. Returns whether
+// the client asked for a shutdown first, which decides the exit status.
+pub fn run(reader: &mut impl BufRead, writer: &mut impl Write) -> io::Result<bool> {
+    let mut server = Server::new();
+    while let Some(message) = protocol::read_message(reader)? {
+        if message["method"] == "exit" {
+            break;
+        }
+        for reply in server.handle(&message) {
+            protocol::write_message(writer, &reply)?;
+        }
+    }
+    Ok(server.shutdown)
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn symbol(source: &str, symbol: &DocumentSymbol) -> Value {
+    let kind = match symbol.kind {
+        SymbolKind::Module => 2,
+        SymbolKind::Function => 12,
+        SymbolKind::Variable => 13,
+        SymbolKind::Struct => 23,
+    };
+    json!({
+        "name": symbol.name,
+        "kind": kind,
+        "range": to_range(source, symbol.range),
+        "selectionRange": to_range(source, symbol.selection_range),
+        "children": symbol.children.iter().map(|child| self::symbol(source, child)).collect::<Vec<Value>>(),
+    })
+}
+
+// LSP positions count lines from 0 and characters in UTF-16 code units,
+// where spans count both from 1 and columns in characters
+fn to_span(source: &str, position: &Value) -> Span {
+    let line = position["line"].as_u64().unwrap_or_default() as usize;
+    let character = position["character"].as_u64().unwrap_or_default() as usize;
+    let text = source.lines().nth(line).unwrap_or_default();
+
+    let mut units = 0;
+    let mut column = 1;
+    for c in text.chars() {
+        if units >= character {
+            break;
+        }
+        units += c.len_utf16();
+        column += 1;
+    }
+    Span { line: line + 1, column }
+}
+
+fn to_position(source: &str, span: Span) -> Value {
+    let text = source.lines().nth(span.line.saturating_sub(1)).unwrap_or_default();
+    let columns = span.column.saturating_sub(1);
+    let character: usize = text.chars().take(columns).map(char::len_utf16).sum();
+    // The end of input sits one column past the last character
+    let past_end = columns.saturating_sub(text.chars().count());
+    json!({ "line": span.line.saturating_sub(1), "character": character + past_end })
+}
+
+fn to_range(source: &str, range: Range) -> Value {
+    json!({ "start": to_position(source, range.start), "end": to_position(source, range.end) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: i64, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    fn open(server: &mut Server, text: &str) -> Value {
+        let message = notification("textDocument/didOpen", json!({
+            "textDocument": { "uri": "file:///a.txt", "languageId": "interpreter", "version": 1, "text": text },
+        }));
+        server.handle(&message).remove(0)
+    }
+
+    fn at(line: u64, character: u64) -> Value {
+        json!({ "textDocument": { "uri": "file:///a.txt" }, "position": { "line": line, "character": character } })
+    }
+
+    #[test]
+    fn test_diagnostics_on_open_and_change() {
+        let mut server = Server::new();
+        let published = open(&mut server, "let x = 1;\nlet = 2;");
+        assert_eq!(published["method"], "textDocument/publishDiagnostics");
+        assert_eq!(published["params"]["diagnostics"], json!([{
+            "range": { "start": { "line": 1, "character": 4 }, "end": { "line": 1, "character": 5 } },
+            "severity": 1,
+            "source": "interpreter",
+            "message": "expected next token to be Identifier, got Assign",
+        }]));
+
+        let change = notification("textDocument/didChange", json!({
+            "textDocument": { "uri": "file:///a.txt", "version": 2 },
+            "contentChanges": [{ "text": "let x = 1;" }],
+        }));
+        let published = server.handle(&change).remove(0);
+        assert_eq!(published["params"]["diagnostics"], json!([]));
+    }
+
+    #[test]
+    fn test_incremental_change() {
+        let mut server = Server::new();
+        open(&mut server, "let x = 1;\nlet y = x;\nputs(y);");
+        let change = |changes: Value| notification("textDocument/didChange", json!({
+            "textDocument": { "uri": "file:///a.txt", "version": 2 },
+            "contentChanges": changes,
+        }));
+        let range = |start: (u64, u64), end: (u64, u64)| json!({
+            "start": { "line": start.0, "character": start.1 },
+            "end": { "line": end.0, "character": end.1 },
+        });
+
+        // Broken and then fixed again, one keystroke at a time
+        let published = server.handle(&change(json!([{ "range": range((1, 8), (1, 9)), "text": "" }]))).remove(0);
+        assert_eq!(published["params"]["diagnostics"][0]["message"], "no prefix parse function for Semicolon");
+        let published = server.handle(&change(json!([
+            { "range": range((1, 8), (1, 8)), "text": "x +" },
+            { "range": range((1, 11), (1, 11)), "text": " 2" },
+        ]))).remove(0);
+        assert_eq!(published["params"]["diagnostics"], json!([]));
+        assert_eq!(server.documents["file:///a.txt"].source, "let x = 1;\nlet y = x + 2;\nputs(y);");
+
+        let hover = server.handle(&request(1, "textDocument/hover", at(1, 10))).remove(0);
+        assert_eq!(hover["result"]["contents"]["value"], "```\n(x + 2)\n```");
+    }
+
+    #[test]
+    fn test_queries() {
+        let mut server = Server::new();
+        open(&mut server, "let s = \"é\" + 1;\nlet f = fn(a) { a };\nf(s);");
+
+        let hover = server.handle(&request(1, "textDocument/hover", at(0, 12))).remove(0);
+        assert_eq!(hover["result"]["contents"]["value"], "```\n(\"é\" + 1)\n```");
+        assert_eq!(hover["result"]["range"]["end"], json!({ "line": 0, "character": 15 }));
+
+        let definition = server.handle(&request(2, "textDocument/definition", at(2, 2))).remove(0);
+        assert_eq!(definition["result"], json!({
+            "uri": "file:///a.txt",
+            "range": { "start": { "line": 0, "character": 4 }, "end": { "line": 0, "character": 5 } },
+        }));
+
+        let symbols = server.handle(&request(3, "textDocument/documentSymbol", at(0, 0))).remove(0);
+        let names: Vec<(&str, i64)> = symbols["result"].as_array().unwrap().iter()
+            .map(|s| (s["name"].as_str().unwrap(), s["kind"].as_i64().unwrap()))
+            .collect();
+        assert_eq!(names, vec![("s", 13), ("f", 12)]);
+
+        let missing = server.handle(&request(4, "textDocument/hover", at(5, 0))).remove(0);
+        assert_eq!(missing["result"], Value::Null);
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let mut input = Vec::new();
+        protocol::write_message(&mut input, &request(1, "initialize", json!({}))).unwrap();
+        protocol::write_message(&mut input, &notification("initialized", json!({}))).unwrap();
+        protocol::write_message(&mut input, &request(2, "textDocument/completion", at(0, 0))).unwrap();
+        protocol::write_message(&mut input, &request(3, "shutdown", Value::Null)).unwrap();
+        protocol::write_message(&mut input, &notification("exit", Value::Null)).unwrap();
+
+        let mut output = Vec::new();
+        assert!(run(&mut &input[..], &mut output).unwrap());
+
+        let mut replies = &output[..];
+        let initialize = protocol::read_message(&mut replies).unwrap().unwrap();
+        assert_eq!(initialize["result"]["capabilities"]["hoverProvider"], true);
+        let unsupported = protocol::read_message(&mut replies).unwrap().unwrap();
+        assert_eq!(unsupported["error"]["code"], METHOD_NOT_FOUND);
+        let shutdown = protocol::read_message(&mut replies).unwrap().unwrap();
+        assert_eq!(shutdown, json!({ "jsonrpc": "2.0", "id": 3, "result": null }));
+        assert_eq!(protocol::read_message(&mut replies).unwrap(), None);
+    }
+}
@@ -0,0 +1,15 @@
+use std::io;
+use std::process::ExitCode;
+
+// Speaks the Language Server Protocol over stdin and stdout
+fn main() -> ExitCode {
+    match lsp::run(&mut io::stdin().lock(), &mut io::stdout().lock()) {
+        Ok(true) => ExitCode::SUCCESS,
+        // Exiting without a shutdown request is an error per the protocol
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("lsp: {}", err);
+            ExitCode::FAILURE
+        },
+    }
+}
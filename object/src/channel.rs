@@ -0,0 +1,92 @@
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Mutex, PoisonError, TryLockError};
+use crate::shared::Rc;
+use crate::{EvalError, Isolate, Object};
+
+// A queue of values for tasks to pass each other. Every copy of a channel,
+// even one in another task, is the same channel, while the values sent on
+// it are copies that share nothing with the sender's.
+#[derive(Clone)]
+pub struct Channel(Rc<Inner>);
+
+struct Inner {
+    // Gone once closed
+    sender: Mutex<Option<Sender<Object>>>,
+    receiver: Mutex<Receiver<Object>>,
+}
+
+// What receiving without waiting found
+#[derive(Debug, PartialEq)]
+pub enum Received {
+    Value(Object),
+    Empty,
+    Closed,
+}
+
+impl Default for Channel {
+    fn default() -> Channel {
+        Channel::new()
+    }
+}
+
+impl Channel {
+    pub fn new() -> Channel {
+        let (sender, receiver) = mpsc::channel();
+        Channel(Rc::new(Inner { sender: Mutex::new(Some(sender)), receiver: Mutex::new(receiver) }))
+    }
+
+    pub fn send(&self, value: &Object) -> Result<(), EvalError> {
+        let sender = self.0.sender.lock().unwrap_or_else(PoisonError::into_inner);
+        match &*sender {
+            Some(sender) => sender.send(Isolate::new().copy(value)).map_err(|_| EvalError::ChannelClosed),
+            None => Err(EvalError::ChannelClosed),
+        }
+    }
+
+    // Waits for the next value, or None once the channel is closed and
+    // everything sent has been received. Without the sync feature nothing
+    // else runs that could send one, so finding the channel empty fails.
+    pub fn recv(&self) -> Result<Option<Object>, EvalError> {
+        let receiver = self.0.receiver.lock().unwrap_or_else(PoisonError::into_inner);
+        if cfg!(feature = "sync") {
+            return Ok(receiver.recv().ok());
+        }
+        match receiver.try_recv() {
+            Ok(value) => Ok(Some(value)),
+            Err(TryRecvError::Disconnected) => Ok(None),
+            Err(TryRecvError::Empty) => Err(EvalError::CapabilityDisabled("waiting on an empty channel")),
+        }
+    }
+
+    // A receiver waiting in recv counts as the channel being empty
+    pub fn try_recv(&self) -> Received {
+        let receiver = match self.0.receiver.try_lock() {
+            Ok(receiver) => receiver,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => return Received::Empty,
+        };
+        match receiver.try_recv() {
+            Ok(value) => Received::Value(value),
+            Err(TryRecvError::Empty) => Received::Empty,
+            Err(TryRecvError::Disconnected) => Received::Closed,
+        }
+    }
+
+    // Stops further sends. Values already sent can still be received.
+    pub fn close(&self) {
+        self.0.sender.lock().unwrap_or_else(PoisonError::into_inner).take();
+    }
+}
+
+impl PartialEq for Channel {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Channel").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,181 @@
+// Conversions between objects and plain Rust values, for hosts embedding the
+// interpreter. Going back to Rust fails with UnexpectedType when the object
+// holds something else.
+use crate::shared::Rc;
+use crate::{EvalError, Object};
+
+impl From<i64> for Object {
+    fn from(value: i64) -> Object {
+        Object::Integer(value)
+    }
+}
+
+impl From<i32> for Object {
+    fn from(value: i32) -> Object {
+        Object::Integer(value.into())
+    }
+}
+
+impl From<f64> for Object {
+    fn from(value: f64) -> Object {
+        Object::Float(value)
+    }
+}
+
+impl From<bool> for Object {
+    fn from(value: bool) -> Object {
+        Object::Boolean(value)
+    }
+}
+
+impl From<char> for Object {
+    fn from(value: char) -> Object {
+        Object::Char(value)
+    }
+}
+
+impl From<String> for Object {
+    fn from(value: String) -> Object {
+        Object::String(value)
+    }
+}
+
+impl From<&str> for Object {
+    fn from(value: &str) -> Object {
+        Object::String(value.to_string())
+    }
+}
+
+impl From<()> for Object {
+    fn from(_: ()) -> Object {
+        Object::Null
+    }
+}
+
+impl<T: Into<Object>> From<Vec<T>> for Object {
+    fn from(values: Vec<T>) -> Object {
+        Object::Array(Rc::new(values.into_iter().map(Into::into).collect()))
+    }
+}
+
+impl<T: Into<Object>> From<Option<T>> for Object {
+    fn from(value: Option<T>) -> Object {
+        value.map_or(Object::Null, Into::into)
+    }
+}
+
+fn unexpected(expected: &'static str, got: &Object) -> EvalError {
+    EvalError::UnexpectedType { expected, got: got.type_name() }
+}
+
+impl TryFrom<Object> for i64 {
+    type Error = EvalError;
+
+    fn try_from(object: Object) -> Result<i64, EvalError> {
+        match object {
+            Object::Integer(value) => Ok(value),
+            other => Err(unexpected("INTEGER", &other)),
+        }
+    }
+}
+
+// Integers are taken as floats too, as they are in arithmetic
+impl TryFrom<Object> for f64 {
+    type Error = EvalError;
+
+    fn try_from(object: Object) -> Result<f64, EvalError> {
+        match object {
+            Object::Float(value) => Ok(value),
+            Object::Integer(value) => Ok(value as f64),
+            other => Err(unexpected("FLOAT", &other)),
+        }
+    }
+}
+
+impl TryFrom<Object> for bool {
+    type Error = EvalError;
+
+    fn try_from(object: Object) -> Result<bool, EvalError> {
+        match object {
+            Object::Boolean(value) => Ok(value),
+            other => Err(unexpected("BOOLEAN", &other)),
+        }
+    }
+}
+
+impl TryFrom<Object> for char {
+    type Error = EvalError;
+
+    fn try_from(object: Object) -> Result<char, EvalError> {
+        match object {
+            Object::Char(value) => Ok(value),
+            other => Err(unexpected("CHAR", &other)),
+        }
+    }
+}
+
+impl TryFrom<Object> for String {
+    type Error = EvalError;
+
+    fn try_from(object: Object) -> Result<String, EvalError> {
+        match object {
+            Object::String(value) => Ok(value),
+            other => Err(unexpected("STRING", &other)),
+        }
+    }
+}
+
+impl<T: TryFrom<Object, Error = EvalError>> TryFrom<Object> for Vec<T> {
+    type Error = EvalError;
+
+    fn try_from(object: Object) -> Result<Vec<T>, EvalError> {
+        match object {
+            Object::Array(elements) => Rc::unwrap_or_clone(elements).into_iter().map(T::try_from).collect(),
+            other => Err(unexpected("ARRAY", &other)),
+        }
+    }
+}
+
+// Null becomes None, anything else must convert to T
+impl<T: TryFrom<Object, Error = EvalError>> TryFrom<Object> for Option<T> {
+    type Error = EvalError;
+
+    fn try_from(object: Object) -> Result<Option<T>, EvalError> {
+        match object {
+            Object::Null => Ok(None),
+            other => T::try_from(other).map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_object() {
+        assert_eq!(Object::from(5), Object::Integer(5));
+        assert_eq!(Object::from("hi"), Object::String("hi".to_string()));
+        assert_eq!(Object::from(vec![true, false]), Object::Array(Rc::new(vec![Object::Boolean(true), Object::Boolean(false)])));
+        assert_eq!(Object::from(None::<i64>), Object::Null);
+        assert_eq!(Object::from(Some('x')), Object::Char('x'));
+        assert_eq!(Object::from(()), Object::Null);
+        assert_eq!(Object::from(0.5), Object::Float(0.5));
+    }
+
+    #[test]
+    fn test_from_object() {
+        assert_eq!(i64::try_from(Object::Integer(3)), Ok(3));
+        assert_eq!(String::try_from(Object::from("a")), Ok("a".to_string()));
+        assert_eq!(Vec::<i64>::try_from(Object::from(vec![1, 2])), Ok(vec![1, 2]));
+        assert_eq!(Option::<bool>::try_from(Object::Null), Ok(None));
+        assert_eq!(Option::<bool>::try_from(Object::Boolean(true)), Ok(Some(true)));
+        assert_eq!(i64::try_from(Object::from("a")), Err(EvalError::UnexpectedType { expected: "INTEGER", got: "STRING" }));
+        assert_eq!(f64::try_from(Object::Integer(2)), Ok(2.0));
+        assert_eq!(f64::try_from(Object::Boolean(true)), Err(EvalError::UnexpectedType { expected: "FLOAT", got: "BOOLEAN" }));
+        assert_eq!(
+            Vec::<i64>::try_from(Object::Array(Rc::new(vec![Object::Integer(1), Object::Null]))),
+            Err(EvalError::UnexpectedType { expected: "INTEGER", got: "NULL" }),
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use crate::shared::{Counter, Rc, RefCell, Weak};
+use crate::{Environment, Object};
+
+// Collects once at least this many scopes are tracked, or twice as many as
+// survived the last collection if that is more
+const MIN_THRESHOLD: usize = 1024;
+
+// The scopes the evaluator created. A function stored in the scope it
+// closes over keeps that scope alive and so itself, which plain Rc never
+// frees. Collecting finds the scopes only such cycles hold on to and empties
+// them, which breaks the cycles.
+#[derive(Debug, Default)]
+pub struct Heap {
+    scopes: Vec<Weak<RefCell<Environment>>>,
+    threshold: usize,
+    collections: u64,
+    reclaimed: u64,
+    // Tracked scopes not yet dropped, and the most there were at once
+    live: Rc<Counter>,
+    peak: usize,
+}
+
+// Held by a tracked scope, counting it as alive until it is dropped
+#[derive(Debug)]
+pub(crate) struct Alive(Rc<Counter>);
+
+impl Drop for Alive {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+    }
+}
+
+// What gc_stats and memory report
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct HeapStats {
+    // Scopes alive now
+    pub tracked: usize,
+    pub collections: u64,
+    // Scopes freed by collections, not counting those freed as usual
+    pub reclaimed: u64,
+    // The most scopes alive at once
+    pub peak: usize,
+}
+
+impl Heap {
+    pub fn track(&mut self, scope: &Rc<RefCell<Environment>>) {
+        self.scopes.push(Rc::downgrade(scope));
+        scope.borrow_mut().alive = Some(Alive(Rc::clone(&self.live)));
+        self.live.set(self.live.get() + 1);
+        self.peak = self.peak.max(self.live.get());
+    }
+
+    pub fn due(&self) -> bool {
+        self.scopes.len() >= self.threshold.max(MIN_THRESHOLD)
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats { tracked: self.live.get(), collections: self.collections, reclaimed: self.reclaimed, peak: self.peak }
+    }
+
+    // Frees the scopes that nothing outside the tracked scopes refers to,
+    // directly or through them, returning how many there were. Scopes only
+    // count as referred to from outside when something other than a tracked
+    // scope holds them: a host variable, a module, a call in progress.
+    pub fn collect(&mut self) -> usize {
+        self.scopes.retain(|scope| scope.strong_count() > 0);
+        let scopes: Vec<Rc<RefCell<Environment>>> = self.scopes.iter().filter_map(Weak::upgrade).collect();
+        let index: HashMap<*const RefCell<Environment>, usize> = scopes.iter()
+            .enumerate()
+            .map(|(i, scope)| (Rc::as_ptr(scope), i))
+            .collect();
+
+        let mut internal = vec![0; scopes.len()];
+        let mut edges = vec![Vec::new(); scopes.len()];
+        let mut roots = Vec::new();
+        for (i, scope) in scopes.iter().enumerate() {
+            match scope.try_borrow() {
+                Ok(env) => env.references(&mut |target| {
+                    if let Some(&j) = index.get(&Rc::as_ptr(target)) {
+                        internal[j] += 1;
+                        edges[i].push(j);
+                    }
+                }),
+                // Only a scope in use can be borrowed
+                Err(_) => roots.push(i),
+            }
+        }
+        // One of the references is the upgrade above
+        roots.extend((0..scopes.len()).filter(|&i| Rc::strong_count(&scopes[i]) - 1 > internal[i]));
+
+        let mut live = vec![false; scopes.len()];
+        while let Some(i) = roots.pop() {
+            if !live[i] {
+                live[i] = true;
+                roots.extend(&edges[i]);
+            }
+        }
+
+        // Emptied first and dropped afterwards, as dropping the bindings of
+        // one scope can free others
+        let garbage: Vec<_> = scopes.iter().zip(&live)
+            .filter(|(_, live)| !**live)
+            .map(|(scope, _)| {
+                let mut env = scope.borrow_mut();
+                (std::mem::take(&mut env.store), env.outer.take())
+            })
+            .collect();
+        let reclaimed = garbage.len();
+        drop(garbage);
+        drop(scopes);
+
+        self.scopes.retain(|scope| scope.strong_count() > 0);
+        self.threshold = self.scopes.len() * 2;
+        self.collections += 1;
+        self.reclaimed += reclaimed as u64;
+        reclaimed
+    }
+}
+
+impl Environment {
+    // Calls found with each scope this one holds a reference to itself.
+    // Those behind another shared value, such as a module, are left out, as
+    // the reference is to that value instead.
+    fn references(&self, found: &mut impl FnMut(&Rc<RefCell<Environment>>)) {
+        if let Some(outer) = &self.outer {
+            found(outer);
+        }
+        for value in self.store.values() {
+            scopes_in(value, found);
+        }
+    }
+}
+
+fn scopes_in(value: &Object, found: &mut impl FnMut(&Rc<RefCell<Environment>>)) {
+    match value {
+        Object::Function(func) => found(&func.env),
+        Object::Array(elements) => elements.iter().for_each(|element| scopes_in(element, found)),
+        Object::Hash(pairs) => pairs.values().for_each(|value| scopes_in(value, found)),
+        Object::Struct(instance) => instance.values.iter().for_each(|value| scopes_in(value, found)),
+        Object::Result(Ok(value) | Err(value)) => scopes_in(value, found),
+        Object::ReturnValue(value) => scopes_in(value, found),
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::BlockStatement;
+    use token::{Token, TokenType};
+    use crate::Function;
+
+    fn function(env: &Rc<RefCell<Environment>>) -> Object {
+        let body = BlockStatement { token: Token::new(TokenType::LeftCurlyBracket, "{".to_string()), statements: Vec::new() };
+        Object::Function(Function { parameters: Vec::new(), body: Rc::new(body), env: Rc::clone(env) })
+    }
+
+    #[test]
+    fn test_collect_cycles() {
+        let global = Rc::new(RefCell::new(Environment::new()));
+        let mut heap = Heap::default();
+        let scope = |heap: &mut Heap| {
+            let scope = Rc::new(RefCell::new(Environment::new_enclosed(Rc::clone(&global))));
+            heap.track(&scope);
+            scope
+        };
+
+        // A function stored in the scope it closes over
+        let cyclic = scope(&mut heap);
+        cyclic.borrow_mut().set("f", function(&cyclic));
+        // Two scopes holding each other's functions, one inside an array
+        let (a, b) = (scope(&mut heap), scope(&mut heap));
+        a.borrow_mut().set("g", Object::Array(Rc::new(vec![function(&b)])));
+        b.borrow_mut().set("g", function(&a));
+        // A cycle the host still holds on to, and one a global binding does
+        let held = scope(&mut heap);
+        held.borrow_mut().set("f", function(&held));
+        let bound = scope(&mut heap);
+        bound.borrow_mut().set("f", function(&bound));
+        global.borrow_mut().set("f", function(&bound));
+        // Inside a live scope, but not part of a cycle
+        let inner = scope(&mut heap);
+        held.borrow_mut().set("inner", function(&inner));
+
+        let weak = [&cyclic, &a, &b, &bound, &inner].map(Rc::downgrade);
+        drop((cyclic, a, b, bound, inner));
+        assert_eq!(heap.collect(), 3);
+        assert_eq!(weak.iter().map(|scope| scope.upgrade().is_some()).collect::<Vec<_>>(), [false, false, false, true, true]);
+        assert_eq!(heap.stats(), HeapStats { tracked: 3, collections: 1, reclaimed: 3, peak: 6 });
+        drop((held, global));
+        assert_eq!(heap.stats(), HeapStats { tracked: 3, collections: 1, reclaimed: 3, peak: 6 });
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use crate::shared::{Rc, RefCell};
+use crate::{Budget, Closure, Environment, Function, Heap, Module, Modules, Object, Random, Struct};
+
+// Copies values so that the copies share no scopes with the originals.
+// A function gets copies of the scopes it closes over and of those its
+// bindings close over in turn, ending in a top-level scope with a budget,
+// heap, random number generator and set of loaded modules of its own. Values nothing can change,
+// such as strings or builtins, are shared instead. Copies made by the same
+// Isolate share scopes with each other wherever the originals did.
+#[derive(Default)]
+pub struct Isolate {
+    scopes: HashMap<*const RefCell<Environment>, Rc<RefCell<Environment>>>,
+    modules: HashMap<*const Module, Rc<Module>>,
+    cells: HashMap<*const RefCell<Object>, Rc<RefCell<Object>>>,
+    // What the copied top-level scopes share, made with the first one
+    registry: Option<Rc<RefCell<Modules>>>,
+    random: Option<Rc<RefCell<Random>>>,
+    budget: Rc<RefCell<Budget>>,
+    heap: Rc<RefCell<Heap>>,
+}
+
+impl Isolate {
+    pub fn new() -> Isolate {
+        Isolate::default()
+    }
+
+    pub fn copy(&mut self, value: &Object) -> Object {
+        match value {
+            Object::Function(func) => Object::Function(Function {
+                parameters: func.parameters.clone(),
+                body: Rc::clone(&func.body),
+                env: self.scope(&func.env),
+            }),
+            Object::Closure(closure) => Object::Closure(Closure {
+                function: Rc::clone(&closure.function),
+                free: closure.free.iter().map(|cell| self.cell(cell)).collect(),
+            }),
+            Object::Module(module) => Object::Module(self.module(module)),
+            Object::Array(elements) => Object::Array(Rc::new(elements.iter().map(|element| self.copy(element)).collect())),
+            Object::Hash(pairs) => Object::Hash(Rc::new(pairs.iter().map(|(key, value)| (key.clone(), self.copy(value))).collect())),
+            Object::Struct(instance) => Object::Struct(Struct {
+                definition: Rc::clone(&instance.definition),
+                values: instance.values.iter().map(|value| self.copy(value)).collect(),
+            }),
+            Object::Result(result) => Object::Result(match result {
+                Ok(value) => Ok(Box::new(self.copy(value))),
+                Err(error) => Err(Box::new(self.copy(error))),
+            }),
+            Object::ReturnValue(value) => Object::ReturnValue(Box::new(self.copy(value))),
+            // How tasks talk to each other, so every copy is the same one
+            Object::Channel(channel) => Object::Channel(channel.clone()),
+            other => other.clone(),
+        }
+    }
+
+    pub fn scope(&mut self, env: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        if let Some(copy) = self.scopes.get(&Rc::as_ptr(env)) {
+            return Rc::clone(copy);
+        }
+        let original = env.borrow();
+        let copy = match &original.outer {
+            Some(outer) => Environment::new_enclosed(self.scope(outer)),
+            None => self.top_level(&original),
+        };
+        let copy = Rc::new(RefCell::new(copy));
+        if original.outer.is_some() {
+            self.heap.borrow_mut().track(&copy);
+        }
+        // Known before the bindings are copied, as they can refer back to it
+        self.scopes.insert(Rc::as_ptr(env), Rc::clone(&copy));
+        for (name, value) in &original.store {
+            let value = self.copy(value);
+            copy.borrow_mut().store.insert(*name, value);
+        }
+        copy
+    }
+
+    fn top_level(&mut self, original: &Environment) -> Environment {
+        let mut env = Environment::new();
+        env.builtins = original.builtins.clone();
+        env.config = original.config;
+        env.io = original.io.clone();
+        env.trace = original.trace.clone();
+        env.modules = Some(self.registry(original));
+        env.budget = Some(Rc::clone(&self.budget));
+        env.heap = Some(Rc::clone(&self.heap));
+        let random = self.random.get_or_insert_with(|| Rc::new(RefCell::new(original.random().borrow_mut().fork())));
+        env.random = Some(Rc::clone(random));
+        env
+    }
+
+    fn registry(&mut self, original: &Environment) -> Rc<RefCell<Modules>> {
+        if let Some(registry) = &self.registry {
+            return Rc::clone(registry);
+        }
+        let modules = original.modules();
+        let modules = modules.borrow();
+        let registry = Rc::new(RefCell::new(Modules {
+            root: modules.root.clone(),
+            sources: modules.sources.clone(),
+            ..Modules::default()
+        }));
+        // Loaded modules are copied once the registry their scopes share is
+        // in place
+        self.registry = Some(Rc::clone(&registry));
+        for (name, module) in &modules.loaded {
+            let copy = self.module(module);
+            registry.borrow_mut().loaded.insert(name.clone(), copy);
+        }
+        registry
+    }
+
+    fn module(&mut self, module: &Rc<Module>) -> Rc<Module> {
+        if let Some(copy) = self.modules.get(&Rc::as_ptr(module)) {
+            return Rc::clone(copy);
+        }
+        let copy = Rc::new(Module { name: module.name.clone(), env: self.scope(&module.env) });
+        self.modules.insert(Rc::as_ptr(module), Rc::clone(&copy));
+        copy
+    }
+
+    fn cell(&mut self, cell: &Rc<RefCell<Object>>) -> Rc<RefCell<Object>> {
+        if let Some(copy) = self.cells.get(&Rc::as_ptr(cell)) {
+            return Rc::clone(copy);
+        }
+        let copy = Rc::new(RefCell::new(Object::Null));
+        self.cells.insert(Rc::as_ptr(cell), Rc::clone(&copy));
+        let value = self.copy(&cell.borrow());
+        *copy.borrow_mut() = value;
+        copy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::BlockStatement;
+    use token::{Token, TokenType};
+
+    fn function(env: &Rc<RefCell<Environment>>) -> Object {
+        let body = BlockStatement { token: Token::new(TokenType::LeftCurlyBracket, "{".to_string()), statements: Vec::new() };
+        Object::Function(Function { parameters: Vec::new(), body: Rc::new(body), env: Rc::clone(env) })
+    }
+
+    fn scope_of(value: &Object) -> Rc<RefCell<Environment>> {
+        match value {
+            Object::Function(func) => Rc::clone(&func.env),
+            other => panic!("not a function: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_copy() {
+        let global = Rc::new(RefCell::new(Environment::new()));
+        global.borrow_mut().set("count", Object::Integer(1));
+        let scope = Rc::new(RefCell::new(Environment::new_enclosed(Rc::clone(&global))));
+        // A function stored in the scope it closes over, and another beside it
+        let f = function(&scope);
+        scope.borrow_mut().set("f", f.clone());
+        let pair = Object::Array(Rc::new(vec![f.clone(), function(&scope)]));
+
+        let mut isolate = Isolate::new();
+        let copy = isolate.copy(&pair);
+        let Object::Array(copies) = &copy else { panic!("not an array: {:?}", copy) };
+        let copied_scope = scope_of(&copies[0]);
+        assert!(!Rc::ptr_eq(&copied_scope, &scope));
+        assert!(Rc::ptr_eq(&copied_scope, &scope_of(&copies[1])));
+        assert!(Rc::ptr_eq(&copied_scope, &scope_of(&copied_scope.borrow().get("f").unwrap())));
+
+        // Changes on either side stay there
+        copied_scope.borrow_mut().assign("count", Object::Integer(2));
+        assert_eq!(global.borrow().get("count"), Some(Object::Integer(1)));
+        global.borrow_mut().assign("count", Object::Integer(3));
+        assert_eq!(copied_scope.borrow().get("count"), Some(Object::Integer(2)));
+
+        // The copied global gets its own budget and heap
+        let copied_global = isolate.scope(&global);
+        assert!(!Rc::ptr_eq(&copied_global.borrow().heap(), &global.borrow().heap()));
+        assert!(Rc::ptr_eq(&copied_scope.borrow().heap(), &copied_global.borrow().heap()));
+        assert_eq!(copied_global.borrow().heap().borrow().stats().tracked, 1);
+    }
+}
@@ -0,0 +1,1276 @@
+mod channel;
+mod convert;
+mod heap;
+mod isolate;
+mod pretty;
+mod profile;
+mod random;
+pub mod shared;
+#[cfg(feature = "sync")]
+mod task;
+mod trace;
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::fmt;
+use std::io::{self as stdio, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use token::{Span, Symbol};
+use ast::{BlockStatement, Identifier};
+use code::{Instructions, SourceMap};
+use shared::{MaybeSync, Rc, RefCell};
+
+pub use channel::{Channel, Received};
+pub use heap::{Heap, HeapStats};
+pub use isolate::Isolate;
+pub use pretty::InspectOptions;
+pub use profile::{FunctionProfile, LineProfile, Profiler};
+pub use random::Random;
+#[cfg(feature = "sync")]
+pub use task::Task;
+pub use trace::{Trace, TraceEvent, Tracer, WriteTracer};
+
+pub type BuiltinFunction = fn(&mut dyn Runtime, Vec<Object>) -> Result<Object, EvalError>;
+#[cfg(not(feature = "sync"))]
+pub type NativeFn = dyn Fn(&[Object]) -> Result<Object, EvalError>;
+#[cfg(feature = "sync")]
+pub type NativeFn = dyn Fn(&[Object]) -> Result<Object, EvalError> + Send + Sync;
+
+#[derive(Debug, Clone)]
+pub enum Object {
+    Integer(i64),
+    // Only holds values outside the i64 range, see eval's bigint module
+    #[cfg(feature = "bigint")]
+    BigInteger(num_bigint::BigInt),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+    Char(char),
+    // Shared until one is changed, see Rc::make_mut
+    Array(Rc<Vec<Object>>),
+    Hash(Rc<BTreeMap<HashKey, Object>>),
+    // The integers from the first up to, but not including, the second.
+    // An inclusive range ends one further along.
+    Range(i64, i64),
+    Null,
+    // Made by ok and err, for functions to return failures as values
+    Result(Result<Box<Object>, Box<Object>>),
+    ReturnValue(Box<Object>),
+    Function(Function),
+    Builtin(BuiltinFunction),
+    Native(NativeFunction),
+    CompiledFunction(Rc<CompiledFunction>),
+    Closure(Closure),
+    Module(Rc<Module>),
+    StructType(Rc<StructType>),
+    Struct(Struct),
+    // A function started by spawn, see Task
+    #[cfg(feature = "sync")]
+    Task(Task),
+    Channel(Channel),
+}
+
+// What builtins can ask of whichever backend is running them: calling back
+// into script functions, as map does, writing the script's output and
+// reporting on its heap and budget
+pub trait Runtime {
+    fn call(&mut self, function: Object, arguments: Vec<Object>) -> Result<Object, EvalError>;
+    fn write(&mut self, text: &str) -> Result<(), EvalError>;
+    fn usage(&mut self) -> Usage;
+
+    // The generator the random builtins draw from
+    fn random(&mut self) -> u64;
+    fn seed(&mut self, seed: u64);
+
+    // Backends that leave everything to reference counting have nothing to
+    // report
+    fn heap_stats(&mut self) -> HeapStats {
+        HeapStats::default()
+    }
+
+    // Starts calling the function with the arguments on another thread,
+    // returning the task to join for the result. Only the evaluator, built
+    // with the sync feature, can.
+    fn spawn(&mut self, _function: Object, _arguments: Vec<Object>) -> Result<Object, EvalError> {
+        Err(EvalError::CapabilityDisabled("spawn"))
+    }
+}
+
+// Written out by hand because builtins can only be compared by address
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Object::Integer(a), Object::Integer(b)) => a == b,
+            #[cfg(feature = "bigint")]
+            (Object::BigInteger(a), Object::BigInteger(b)) => a == b,
+            (Object::Float(a), Object::Float(b)) => a == b,
+            (Object::Boolean(a), Object::Boolean(b)) => a == b,
+            (Object::String(a), Object::String(b)) => a == b,
+            (Object::Char(a), Object::Char(b)) => a == b,
+            (Object::Array(a), Object::Array(b)) => a == b,
+            (Object::Hash(a), Object::Hash(b)) => a == b,
+            (Object::Range(a, b), Object::Range(c, d)) => a == c && b == d,
+            (Object::Null, Object::Null) => true,
+            (Object::Result(a), Object::Result(b)) => a == b,
+            (Object::ReturnValue(a), Object::ReturnValue(b)) => a == b,
+            (Object::Function(a), Object::Function(b)) => a == b,
+            (Object::Builtin(a), Object::Builtin(b)) => std::ptr::fn_addr_eq(*a, *b),
+            (Object::Native(a), Object::Native(b)) => a == b,
+            (Object::CompiledFunction(a), Object::CompiledFunction(b)) => Rc::ptr_eq(a, b),
+            (Object::Closure(a), Object::Closure(b)) => a == b,
+            (Object::Module(a), Object::Module(b)) => Rc::ptr_eq(a, b),
+            (Object::StructType(a), Object::StructType(b)) => a == b,
+            (Object::Struct(a), Object::Struct(b)) => a == b,
+            #[cfg(feature = "sync")]
+            (Object::Task(a), Object::Task(b)) => a == b,
+            (Object::Channel(a), Object::Channel(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Object {
+    pub fn inspect(&self) -> String {
+        self.display(&mut |_| Ok::<_, Infallible>(None)).unwrap_or_else(|never| match never {})
+    }
+
+    // As inspect, except that each struct is first offered to show, which
+    // can give the text for it instead, as a script's to_string does
+    pub fn display<E>(&self, show: &mut dyn FnMut(&Struct) -> Result<Option<String>, E>) -> Result<String, E> {
+        Ok(match self {
+            Object::Integer(i) => i.to_string(),
+            #[cfg(feature = "bigint")]
+            Object::BigInteger(i) => i.to_string(),
+            // Always with a fraction or exponent, so 1.0 does not read as 1
+            Object::Float(x) => format!("{:?}", x),
+            Object::Boolean(b) => b.to_string(),
+            Object::String(s) => s.clone(),
+            Object::Char(c) => c.to_string(),
+            Object::Array(elements) => {
+                let elements = elements.iter().map(|e| e.display(show)).collect::<Result<Vec<_>, _>>()?;
+                format!("[{}]", elements.join(", "))
+            },
+            Object::Hash(pairs) => {
+                let pairs = pairs.iter().map(|(k, v)| Ok(format!("{}: {}", k, v.display(show)?))).collect::<Result<Vec<_>, _>>()?;
+                format!("{{{}}}", pairs.join(", "))
+            },
+            Object::Range(start, end) => format!("{}..{}", start, end),
+            Object::Null => "null".to_string(),
+            Object::Result(Ok(v)) => format!("ok({})", v.display(show)?),
+            Object::Result(Err(e)) => format!("err({})", e.display(show)?),
+            Object::ReturnValue(v) => return v.display(show),
+            Object::Function(f) => f.inspect(),
+            Object::Builtin(_) | Object::Native(_) => "builtin function".to_string(),
+            Object::CompiledFunction(_) => "compiled function".to_string(),
+            Object::Closure(_) => "closure".to_string(),
+            Object::Module(m) => format!("module {}", m.name),
+            Object::StructType(t) => t.inspect(),
+            Object::Struct(s) => return s.display(show),
+            #[cfg(feature = "sync")]
+            Object::Task(_) => "task".to_string(),
+            Object::Channel(_) => "channel".to_string(),
+        })
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Integer(_) => "INTEGER",
+            #[cfg(feature = "bigint")]
+            Object::BigInteger(_) => "INTEGER",
+            Object::Float(_) => "FLOAT",
+            Object::Boolean(_) => "BOOLEAN",
+            Object::String(_) => "STRING",
+            Object::Char(_) => "CHAR",
+            Object::Array(_) => "ARRAY",
+            Object::Hash(_) => "HASH",
+            Object::Range(..) => "RANGE",
+            Object::Null => "NULL",
+            Object::Result(_) => "RESULT",
+            Object::ReturnValue(v) => v.type_name(),
+            Object::Function(_) => "FUNCTION",
+            Object::Builtin(_) | Object::Native(_) => "BUILTIN",
+            Object::CompiledFunction(_) | Object::Closure(_) => "FUNCTION",
+            Object::Module(_) => "MODULE",
+            Object::StructType(_) => "STRUCT_TYPE",
+            Object::Struct(_) => "STRUCT",
+            #[cfg(feature = "sync")]
+            Object::Task(_) => "TASK",
+            Object::Channel(_) => "CHANNEL",
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Object::Boolean(false) | Object::Null)
+    }
+
+    pub fn hash_key(&self) -> Result<HashKey, EvalError> {
+        match self {
+            Object::Integer(i) => Ok(HashKey::Integer(*i)),
+            Object::Boolean(b) => Ok(HashKey::Boolean(*b)),
+            Object::String(s) => Ok(HashKey::String(s.clone())),
+            other => Err(EvalError::UnhashableKey(other.type_name())),
+        }
+    }
+}
+
+// The values usable as hash keys. Hashes keep their keys ordered, integers
+// then booleans then strings, so iterating one is the same on every run.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
+pub enum HashKey {
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+}
+
+impl From<HashKey> for Object {
+    fn from(key: HashKey) -> Object {
+        match key {
+            HashKey::Integer(i) => Object::Integer(i),
+            HashKey::Boolean(b) => Object::Boolean(b),
+            HashKey::String(s) => Object::String(s),
+        }
+    }
+}
+
+impl fmt::Display for HashKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HashKey::Integer(i) => write!(f, "{}", i),
+            HashKey::Boolean(b) => write!(f, "{}", b),
+            HashKey::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+// A function value closes over the environment it was defined in
+#[derive(Clone)]
+pub struct Function {
+    pub parameters: Vec<Identifier>,
+    // Shared by the copies of the function and the evaluator running it
+    pub body: Rc<BlockStatement>,
+    pub env: Rc<RefCell<Environment>>,
+}
+
+impl Function {
+    pub fn inspect(&self) -> String {
+        let parameters: Vec<String> = self.parameters.iter().map(|p| p.to_string()).collect();
+        format!("fn({}) {}", parameters.join(", "), self.body)
+    }
+}
+
+// The captured environment can contain the function itself, so it is
+// compared by identity and left out of the debug output
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        self.parameters == other.parameters && self.body == other.body && Rc::ptr_eq(&self.env, &other.env)
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("parameters", &self.parameters)
+            .field("body", &self.body)
+            .finish_non_exhaustive()
+    }
+}
+
+// A Rust closure registered by a host application. Calls with the wrong
+// number of arguments are rejected unless the arity is None (variadic).
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: Option<usize>,
+    pub func: Rc<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn call(&self, arguments: &[Object]) -> Result<Object, EvalError> {
+        match self.arity {
+            Some(want) if want != arguments.len() => Err(EvalError::WrongArgumentCount { got: arguments.len(), want }),
+            _ => (self.func)(arguments),
+        }
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.func, &other.func)
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+// An imported file. Its members are the bindings at the top level of its
+// scope, which stay shared with the module's own functions.
+pub struct Module {
+    pub name: String,
+    pub env: Rc<RefCell<Environment>>,
+}
+
+impl Module {
+    pub fn member(&self, name: Symbol) -> Option<Object> {
+        self.env.borrow().store.get(&name).cloned()
+    }
+}
+
+impl fmt::Debug for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Module")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+// Where imports are read from and what has been loaded so far. A program and
+// every module it imports share one set, so each module runs only once.
+#[derive(Debug, Default)]
+pub struct Modules {
+    root: PathBuf,
+    sources: HashMap<String, String>,
+    loaded: HashMap<String, Rc<Module>>,
+    // Imports in progress, outermost first
+    loading: Vec<String>,
+}
+
+impl Modules {
+    // Files are looked up relative to root
+    pub fn new(root: impl Into<PathBuf>) -> Modules {
+        Modules { root: root.into(), ..Modules::default() }
+    }
+
+    pub fn set_root(&mut self, root: impl Into<PathBuf>) {
+        self.root = root.into();
+    }
+
+    // Serves a module from memory rather than the file system
+    pub fn add_source(&mut self, name: &str, source: impl Into<String>) {
+        self.sources.insert(module_name(name).to_string(), source.into());
+    }
+
+    pub fn get(&self, name: &str) -> Option<Rc<Module>> {
+        self.loaded.get(name).cloned()
+    }
+
+    // Every module loaded so far, sorted by name
+    pub fn loaded(&self) -> Vec<(String, Rc<Module>)> {
+        let mut loaded: Vec<_> = self.loaded.iter().map(|(name, module)| (name.clone(), Rc::clone(module))).collect();
+        loaded.sort_by(|a, b| a.0.cmp(&b.0));
+        loaded
+    }
+
+    // Caches a module loaded elsewhere, so importing it does not run it
+    pub fn insert(&mut self, name: &str, module: Rc<Module>) {
+        self.loaded.insert(name.to_string(), module);
+    }
+
+    // The name of the module whose top-level scope encloses env, if any
+    pub fn containing(&self, env: &Rc<RefCell<Environment>>) -> Option<&str> {
+        let mut scope = Rc::clone(env);
+        loop {
+            let outer = scope.borrow().outer();
+            match outer {
+                Some(outer) => scope = outer,
+                None => break,
+            }
+        }
+        self.loaded.values()
+            .find(|module| Rc::ptr_eq(&module.env, &scope))
+            .map(|module| module.name.as_str())
+    }
+
+    pub fn source(&self, name: &str) -> Result<String, EvalError> {
+        if let Some(source) = self.sources.get(name) {
+            return Ok(source.clone());
+        }
+        let path = self.root.join(format!("{}.mky", name));
+        std::fs::read_to_string(&path).map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => EvalError::ModuleNotFound(name.to_string()),
+            _ => EvalError::ImportFailed { module: name.to_string(), reason: err.to_string() },
+        })
+    }
+
+    // Marks the module as being loaded, failing if it is already on the way
+    // to this import
+    pub fn begin(&mut self, name: &str) -> Result<(), EvalError> {
+        if let Some(start) = self.loading.iter().position(|loading| loading == name) {
+            let mut cycle = self.loading[start..].to_vec();
+            cycle.push(name.to_string());
+            return Err(EvalError::ImportCycle(cycle));
+        }
+        self.loading.push(name.to_string());
+        Ok(())
+    }
+
+    // Ends the innermost import, caching the module if it loaded
+    pub fn finish(&mut self, module: Option<Rc<Module>>) {
+        let name = self.loading.pop().expect("finish without begin");
+        if let Some(module) = module {
+            self.loaded.insert(name, module);
+        }
+    }
+}
+
+// "lib/math.mky" and "lib/math" name the same module
+pub fn module_name(path: &str) -> &str {
+    path.strip_suffix(".mky").unwrap_or(path)
+}
+
+// Function bodies produced by the bytecode compiler
+#[derive(Debug, PartialEq)]
+pub struct CompiledFunction {
+    pub instructions: Instructions,
+    pub spans: SourceMap,
+    pub num_locals: usize,
+    pub num_parameters: usize,
+}
+
+// A compiled function paired with the variables it captured. Captures are
+// shared cells so that updates are seen by the closure and its creator alike.
+#[derive(Clone)]
+pub struct Closure {
+    pub function: Rc<CompiledFunction>,
+    pub free: Vec<Rc<RefCell<Object>>>,
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.function, &other.function)
+            && self.free.len() == other.free.len()
+            && self.free.iter().zip(&other.free).all(|(a, b)| Rc::ptr_eq(a, b))
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Closure")
+            .field("function", &self.function)
+            .finish_non_exhaustive()
+    }
+}
+
+// What a struct statement declares. Two declarations with the same name and
+// fields describe the same type.
+#[derive(Debug, PartialEq)]
+pub struct StructType {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>,
+}
+
+impl StructType {
+    pub fn inspect(&self) -> String {
+        let fields: Vec<&str> = self.fields.iter().map(|f| f.as_str()).collect();
+        if fields.is_empty() {
+            format!("struct {} {{}}", self.name)
+        } else {
+            format!("struct {} {{ {} }}", self.name, fields.join(", "))
+        }
+    }
+}
+
+// An instance of a struct, its values in the order the fields were declared
+#[derive(Debug, PartialEq, Clone)]
+pub struct Struct {
+    pub definition: Rc<StructType>,
+    pub values: Vec<Object>,
+}
+
+impl Struct {
+    pub fn member(&self, name: Symbol) -> Option<Object> {
+        let position = self.definition.fields.iter().position(|&f| f == name)?;
+        Some(self.values[position].clone())
+    }
+
+    // A member that holds something to call, which scripts give structs as
+    // to_string or an operator's method
+    pub fn method(&self, name: &str) -> Option<Object> {
+        self.member(Symbol::intern(name))
+            .filter(|value| matches!(value, Object::Function(_) | Object::Builtin(_) | Object::Native(_) | Object::Closure(_)))
+    }
+
+    pub fn inspect(&self) -> String {
+        self.display(&mut |_| Ok::<_, Infallible>(None)).unwrap_or_else(|never| match never {})
+    }
+
+    fn display<E>(&self, show: &mut dyn FnMut(&Struct) -> Result<Option<String>, E>) -> Result<String, E> {
+        if let Some(text) = show(self)? {
+            return Ok(text);
+        }
+        let fields = self.definition.fields.iter()
+            .zip(&self.values)
+            .map(|(f, v)| Ok(format!("{}: {}", f, v.display(show)?)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(if fields.is_empty() {
+            format!("{} {{}}", self.definition.name)
+        } else {
+            format!("{} {{ {} }}", self.definition.name, fields.join(", "))
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum EvalError {
+    IdentifierNotFound(String),
+    TypeMismatch { left: &'static str, operator: String, right: &'static str },
+    UnknownPrefixOperator { operator: String, right: &'static str },
+    UnknownInfixOperator { left: &'static str, operator: String, right: &'static str },
+    UnknownPostfixOperator { left: &'static str, operator: String },
+    InvalidAssignmentTarget(String),
+    DivisionByZero,
+    IntegerOverflow(String),
+    IntegerLiteralOutOfRange(String),
+    NegativeRepeatCount(i64),
+    InvalidCodePoint(i64),
+    NotIterable(&'static str),
+    InvalidRange { start: &'static str, end: &'static str },
+    EmptyRange { start: i64, end: i64 },
+    NotCallable(&'static str),
+    IndexNotSupported { left: &'static str, index: &'static str },
+    IndexOutOfRange { index: i64, len: usize },
+    UnhashableKey(&'static str),
+    InvalidFormat(String),
+    Io(String),
+    CapabilityDisabled(&'static str),
+    ResourceExceeded(Resource),
+    WrongArgumentCount { got: usize, want: usize },
+    UnsupportedArgument { function: &'static str, got: &'static str },
+    UnexpectedType { expected: &'static str, got: &'static str },
+    InvalidArgument { function: String, position: usize, expected: &'static str, got: &'static str },
+    NoSuchMember { object: String, member: String },
+    NotAStruct(&'static str),
+    MissingField { name: String, field: String },
+    DuplicateField { name: String, field: String },
+    ModuleNotFound(String),
+    ImportFailed { module: String, reason: String },
+    ImportCycle(Vec<String>),
+    AssertionFailed(Option<String>),
+    // Raised by panic, ending the run even inside a try
+    Panicked(String),
+    ChannelClosed,
+    // A value thrown by a script that no try caught
+    Thrown(Box<Object>),
+    // Raised by host functions with their own message
+    Custom(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::IdentifierNotFound(name) => write!(f, "identifier not found: {}", name),
+            EvalError::TypeMismatch { left, operator, right } => write!(f, "type mismatch: {} {} {}", left, operator, right),
+            EvalError::UnknownPrefixOperator { operator, right } => write!(f, "unknown operator: {}{}", operator, right),
+            EvalError::UnknownInfixOperator { left, operator, right } => write!(f, "unknown operator: {} {} {}", left, operator, right),
+            EvalError::UnknownPostfixOperator { left, operator } => write!(f, "unknown operator: {}{}", left, operator),
+            EvalError::InvalidAssignmentTarget(target) => write!(f, "invalid assignment target: {}", target),
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::IntegerOverflow(operation) => write!(f, "integer overflow: {}", operation),
+            EvalError::IntegerLiteralOutOfRange(literal) => write!(f, "integer literal out of range: {}", literal),
+            EvalError::NegativeRepeatCount(count) => write!(f, "cannot repeat a string {} times", count),
+            EvalError::InvalidCodePoint(code) => write!(f, "{} is not a valid character code", code),
+            EvalError::NotIterable(type_name) => write!(f, "cannot iterate over {}", type_name),
+            EvalError::InvalidRange { start, end } => write!(f, "range bounds must be integers, got {}..{}", start, end),
+            EvalError::EmptyRange { start, end } => write!(f, "empty range: {}..{}", start, end),
+            EvalError::NotCallable(type_name) => write!(f, "not a function: {}", type_name),
+            EvalError::IndexNotSupported { left, index } => write!(f, "index operator not supported: {}[{}]", left, index),
+            EvalError::IndexOutOfRange { index, len } => write!(f, "index {} out of range for length {}", index, len),
+            EvalError::UnhashableKey(type_name) => write!(f, "unusable as hash key: {}", type_name),
+            EvalError::InvalidFormat(reason) => write!(f, "invalid format string: {}", reason),
+            EvalError::Io(message) => write!(f, "i/o error: {}", message),
+            EvalError::CapabilityDisabled(capability) => write!(f, "{} is disabled for this interpreter", capability),
+            EvalError::ResourceExceeded(resource) => write!(f, "resource limit exceeded: {}", resource),
+            EvalError::WrongArgumentCount { got, want } => write!(f, "wrong number of arguments. got={}, want={}", got, want),
+            EvalError::UnsupportedArgument { function, got } => write!(f, "argument to `{}` not supported, got {}", function, got),
+            EvalError::UnexpectedType { expected, got } => write!(f, "expected {}, got {}", expected, got),
+            EvalError::InvalidArgument { function, position, expected, got } => {
+                write!(f, "argument {} to `{}` must be {}, got {}", position, function, expected, got)
+            },
+            EvalError::NoSuchMember { object, member } => write!(f, "{} has no member {}", object, member),
+            EvalError::NotAStruct(type_name) => write!(f, "not a struct: {}", type_name),
+            EvalError::MissingField { name, field } => write!(f, "missing field {} for {}", field, name),
+            EvalError::DuplicateField { name, field } => write!(f, "field {} given twice for {}", field, name),
+            EvalError::ModuleNotFound(module) => write!(f, "module not found: {}", module),
+            EvalError::ImportFailed { module, reason } => write!(f, "cannot import {}: {}", module, reason),
+            EvalError::ImportCycle(modules) => write!(f, "import cycle: {}", modules.join(" -> ")),
+            EvalError::AssertionFailed(None) => write!(f, "assertion failed"),
+            EvalError::AssertionFailed(Some(message)) => write!(f, "assertion failed: {}", message),
+            EvalError::Panicked(message) => write!(f, "panicked: {}", message),
+            EvalError::ChannelClosed => write!(f, "send on a closed channel"),
+            EvalError::Thrown(value) => write!(f, "uncaught exception: {}", value.inspect()),
+            EvalError::Custom(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+// Lets conversions that cannot fail, such as Object to Object, be used
+// wherever a fallible one is expected
+impl From<std::convert::Infallible> for EvalError {
+    fn from(never: std::convert::Infallible) -> EvalError {
+        match never {}
+    }
+}
+
+// A function call that was in progress when an error occurred
+#[derive(Debug, PartialEq, Clone)]
+pub struct StackFrame {
+    // The callee as written at the call site, e.g. `add` or `make(1)`
+    pub function: String,
+    pub call_site: Span,
+    // The module the call site is in, or None for the program that was run
+    pub module: Option<Symbol>,
+}
+
+// An EvalError together with where it happened: the span of the failing
+// expression and the calls that led there, innermost first
+#[derive(Debug, PartialEq, Clone)]
+pub struct RuntimeError {
+    pub error: EvalError,
+    pub span: Option<Span>,
+    // The module the span is in, or None for the program that was run
+    pub module: Option<Symbol>,
+    pub trace: Vec<StackFrame>,
+}
+
+impl RuntimeError {
+    // Only the innermost expression's span is kept
+    pub fn with_span(self, span: Span) -> RuntimeError {
+        self.with_span_in(span, None)
+    }
+
+    // The same, for a span in the module named
+    pub fn with_span_in(mut self, span: Span, module: Option<Symbol>) -> RuntimeError {
+        if self.span.is_none() {
+            self.span = Some(span);
+            self.module = module;
+        }
+        self
+    }
+
+    pub fn with_frame(self, function: String, call_site: Span) -> RuntimeError {
+        self.with_frame_in(function, call_site, None)
+    }
+
+    pub fn with_frame_in(mut self, function: String, call_site: Span, module: Option<Symbol>) -> RuntimeError {
+        self.trace.push(StackFrame { function, call_site, module });
+        self
+    }
+
+    // The trace with each run of the same frame, as recursion leaves, given
+    // once with how many times it repeats
+    pub fn frames(&self) -> Vec<(&StackFrame, usize)> {
+        let mut frames: Vec<(&StackFrame, usize)> = Vec::new();
+        for frame in &self.trace {
+            match frames.last_mut() {
+                Some((last, count)) if *last == frame => *count += 1,
+                _ => frames.push((frame, 1)),
+            }
+        }
+        frames
+    }
+}
+
+impl StackFrame {
+    pub fn describe(&self, count: usize) -> String {
+        match count {
+            1 => format!("in {} called at {}", self.function, location(self.call_site, self.module)),
+            _ => format!("in {} called at {} ({} times)", self.function, location(self.call_site, self.module), count),
+        }
+    }
+}
+
+// Where a span is, naming the module it is in unless that is the program
+// that was run
+pub fn location(span: Span, module: Option<Symbol>) -> String {
+    match module {
+        Some(module) => format!("{} of {}", span, module),
+        None => span.to_string(),
+    }
+}
+
+impl From<EvalError> for RuntimeError {
+    fn from(error: EvalError) -> RuntimeError {
+        RuntimeError { error, span: None, module: None, trace: Vec::new() }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        if let Some(span) = self.span {
+            write!(f, " at {}", location(span, self.module))?;
+        }
+        for (frame, count) in self.frames() {
+            write!(f, "\n    {}", frame.describe(count))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+// What integer arithmetic does when the result does not fit in an i64
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Overflow {
+    Wrap,
+    Saturate,
+    #[cfg_attr(not(feature = "bigint"), default)]
+    Error,
+    // Continues with arbitrary-precision integers
+    #[cfg(feature = "bigint")]
+    #[default]
+    Promote,
+}
+
+impl Overflow {
+    // Picks the result for this policy, None meaning the operation does not
+    // fit in an i64 and must fail or be promoted
+    pub fn resolve(self, checked: Option<i64>, wrapped: i64, saturated: i64) -> Option<i64> {
+        match self {
+            Overflow::Wrap => Some(wrapped),
+            Overflow::Saturate => Some(saturated),
+            Overflow::Error => checked,
+            #[cfg(feature = "bigint")]
+            Overflow::Promote => checked,
+        }
+    }
+}
+
+// Host settings for running scripts
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct EvalConfig {
+    pub overflow: Overflow,
+    // Whether scripts may import std/file. Turned off to sandbox them.
+    pub filesystem: bool,
+    // Whether scripts may import std/http
+    pub network: bool,
+    pub limits: Limits,
+    // Runs give the same output every time: the random builtins start from
+    // a fixed seed and the timeout is kept on a virtual clock, see
+    // VIRTUAL_STEP. Hashes iterate in key order either way.
+    pub deterministic: bool,
+}
+
+impl Default for EvalConfig {
+    fn default() -> EvalConfig {
+        EvalConfig { overflow: Overflow::default(), filesystem: true, network: true, limits: Limits::default(), deterministic: false }
+    }
+}
+
+// How long each step takes on the virtual clock of a deterministic run, so
+// a timeout stops it after the same number of steps on any machine
+pub const VIRTUAL_STEP: Duration = Duration::from_micros(1);
+
+// Neither backend uses the host stack for calls between script functions,
+// so deep recursion only costs memory
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+// A builtin calling back into a script, as map does, runs it nested inside
+// its own call on the host stack, as does loading a module. That takes a few
+// kilobytes per level in release builds and around ten times that in debug
+// ones, which stays inside the main thread's 8 MiB either way.
+pub const DEFAULT_MAX_NESTING: usize = 150;
+
+// Well short of what the allocator gives up on, which aborts the process
+pub const DEFAULT_MAX_STRING_LENGTH: usize = 1 << 30;
+
+// What a single run may use before it is stopped, None meaning no limit.
+// Only the depth, nesting and string length are limited by default, so that
+// runaway recursion or repetition fails with an error rather than exhausting
+// memory or, through builtins, overflowing the stack.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Limits {
+    // Expressions evaluated, or instructions executed by the VM
+    pub max_steps: Option<u64>,
+    // Function calls in progress at once
+    pub max_depth: Option<usize>,
+    // Of those, calls made back from builtins and imports being loaded,
+    // which each take a level of the host stack
+    pub max_nesting: Option<usize>,
+    // Strings, arrays, hashes and functions created
+    pub max_objects: Option<u64>,
+    // Bytes in a string made by repeating another
+    pub max_string_length: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_steps: None,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            max_nesting: Some(DEFAULT_MAX_NESTING),
+            max_objects: None,
+            max_string_length: Some(DEFAULT_MAX_STRING_LENGTH),
+            timeout: None,
+        }
+    }
+}
+
+// The limit a run went over
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Resource {
+    Steps(u64),
+    Depth(usize),
+    Nesting(usize),
+    Objects(u64),
+    StringLength(usize),
+    Time(Duration),
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Resource::Steps(limit) => write!(f, "{} steps", limit),
+            Resource::Depth(limit) => write!(f, "call depth of {}", limit),
+            Resource::Nesting(limit) => write!(f, "{} calls nested in builtins or imports", limit),
+            Resource::Objects(limit) => write!(f, "{} heap objects", limit),
+            Resource::StringLength(limit) => write!(f, "strings of {} bytes", limit),
+            Resource::Time(limit) => write!(f, "{:?} of running time", limit),
+        }
+    }
+}
+
+// Counts what the current run has used against its limits. Shared by
+// every scope of a program, the modules it imports and the functions it
+// calls back from builtins.
+#[derive(Debug, Default)]
+pub struct Budget {
+    limits: Limits,
+    steps: u64,
+    depth: usize,
+    nesting: usize,
+    objects: u64,
+    by_type: BTreeMap<&'static str, u64>,
+    deadline: Option<Instant>,
+    virtual_clock: bool,
+}
+
+// What a run has used so far, next to what it may use
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Usage {
+    pub steps: u64,
+    pub depth: usize,
+    // Scopes between the running code and the top level, or frames on the VM
+    pub scopes: usize,
+    pub objects: u64,
+    pub objects_by_type: BTreeMap<&'static str, u64>,
+    pub limits: Limits,
+    // What is left of the timeout, for builtins that wait on the outside
+    pub remaining: Option<Duration>,
+}
+
+impl Budget {
+    // Entering from the outside, with nothing running yet, starts a new run
+    // and a fresh count
+    pub fn enter(&mut self, config: &EvalConfig) -> Result<(), EvalError> {
+        if self.depth == 0 {
+            // The clock is only read when there is a timeout, and never on
+            // a virtual one, as it is not available on every target
+            let limits = config.limits;
+            *self = match config.deterministic {
+                true => Budget { limits, virtual_clock: true, ..Budget::default() },
+                false => Budget { limits, deadline: limits.timeout.map(|timeout| Instant::now() + timeout), ..Budget::default() },
+            };
+        }
+        if let Some(limit) = self.limits.max_depth {
+            if self.depth >= limit {
+                return Err(EvalError::ResourceExceeded(Resource::Depth(limit)));
+            }
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn exit(&mut self) {
+        self.depth -= 1;
+    }
+
+    // Runs code nested on the host stack, inside a run already entered
+    pub fn nest(&mut self) -> Result<(), EvalError> {
+        if let Some(limit) = self.limits.max_nesting {
+            if self.nesting >= limit {
+                return Err(EvalError::ResourceExceeded(Resource::Nesting(limit)));
+            }
+        }
+        self.nesting += 1;
+        Ok(())
+    }
+
+    pub fn unnest(&mut self) {
+        self.nesting -= 1;
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // Scopes are left for the backend to fill in
+    pub fn usage(&self) -> Usage {
+        Usage {
+            steps: self.steps,
+            depth: self.depth,
+            scopes: 0,
+            objects: self.objects,
+            objects_by_type: self.by_type.clone(),
+            limits: self.limits,
+            remaining: self.remaining(),
+        }
+    }
+
+    fn remaining(&self) -> Option<Duration> {
+        match self.virtual_clock {
+            true => {
+                let elapsed = Duration::from_nanos(self.steps.saturating_mul(VIRTUAL_STEP.as_nanos() as u64));
+                self.limits.timeout.map(|timeout| timeout.saturating_sub(elapsed))
+            },
+            false => self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now())),
+        }
+    }
+
+    pub fn step(&mut self) -> Result<(), EvalError> {
+        self.steps += 1;
+        if let Some(limit) = self.limits.max_steps {
+            if self.steps > limit {
+                return Err(EvalError::ResourceExceeded(Resource::Steps(limit)));
+            }
+        }
+        if let Some(timeout) = self.limits.timeout {
+            if self.remaining() == Some(Duration::ZERO) {
+                return Err(EvalError::ResourceExceeded(Resource::Time(timeout)));
+            }
+        }
+        Ok(())
+    }
+
+    // Values that live on the heap count towards the object limit
+    pub fn allocate(&mut self, value: &Object) -> Result<(), EvalError> {
+        match value {
+            Object::String(_) | Object::Array(_) | Object::Hash(_) | Object::Struct(_) | Object::Result(_) | Object::Function(_) | Object::Closure(_) => {},
+            #[cfg(feature = "bigint")]
+            Object::BigInteger(_) => {},
+            _ => return Ok(()),
+        }
+        self.objects += 1;
+        *self.by_type.entry(value.type_name()).or_default() += 1;
+        match self.limits.max_objects {
+            Some(limit) if self.objects > limit => Err(EvalError::ResourceExceeded(Resource::Objects(limit))),
+            _ => Ok(()),
+        }
+    }
+}
+
+// The streams scripts read from and write to, stdin and stdout unless a
+// host swaps them, for example to capture output in a test. Clones share
+// the same streams.
+#[derive(Clone)]
+pub struct Io {
+    pub input: Rc<RefCell<dyn Input>>,
+    pub output: Rc<RefCell<dyn Output>>,
+}
+
+pub trait Input: BufRead + MaybeSync {}
+impl<T: BufRead + MaybeSync> Input for T {}
+
+pub trait Output: Write + MaybeSync {}
+impl<T: Write + MaybeSync> Output for T {}
+
+impl Io {
+    pub fn new(input: impl Input + 'static, output: impl Output + 'static) -> Io {
+        Io { input: Rc::new(RefCell::new(input)), output: Rc::new(RefCell::new(output)) }
+    }
+
+    pub fn stdio() -> Io {
+        Io::new(BufReader::new(stdio::stdin()), stdio::stdout())
+    }
+
+    pub fn write(&self, text: &str) -> Result<(), EvalError> {
+        self.output.borrow_mut().write_all(text.as_bytes()).map_err(|err| EvalError::Io(err.to_string()))
+    }
+}
+
+impl Default for Io {
+    fn default() -> Io {
+        Io::stdio()
+    }
+}
+
+impl fmt::Debug for Io {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Io").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub struct Environment {
+    store: HashMap<Symbol, Object>,
+    builtins: HashMap<Symbol, BuiltinFunction>,
+    config: Option<EvalConfig>,
+    io: Option<Io>,
+    trace: Option<Trace>,
+    // Only set on top-level scopes
+    modules: Option<Rc<RefCell<Modules>>>,
+    budget: Option<Rc<RefCell<Budget>>>,
+    heap: Option<Rc<RefCell<Heap>>>,
+    random: Option<Rc<RefCell<Random>>>,
+    // Set on the scopes the heap tracks
+    alive: Option<heap::Alive>,
+    // Earlier results an interactive session kept, read as _ for the last
+    // and _1, _2, ... in order. Bindings of the same names hide them.
+    results: Vec<Object>,
+    outer: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment::new()
+    }
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment {
+            store: HashMap::new(),
+            builtins: HashMap::new(),
+            config: None,
+            io: None,
+            trace: None,
+            modules: Some(Rc::new(RefCell::new(Modules::default()))),
+            budget: Some(Rc::new(RefCell::new(Budget::default()))),
+            heap: Some(Rc::new(RefCell::new(Heap::default()))),
+            random: Some(Rc::new(RefCell::new(Random::default()))),
+            alive: None,
+            results: Vec::new(),
+            outer: None,
+        }
+    }
+
+    pub fn new_enclosed(outer: Rc<RefCell<Environment>>) -> Environment {
+        Environment {
+            store: HashMap::new(),
+            builtins: HashMap::new(),
+            config: None,
+            io: None,
+            trace: None,
+            modules: None,
+            budget: None,
+            heap: None,
+            random: None,
+            alive: None,
+            results: Vec::new(),
+            outer: Some(outer),
+        }
+    }
+
+    // The top-level scope of a module imported from here. It sees the same
+    // builtins, settings, streams, tracer, loaded modules, resource budget,
+    // heap and random numbers, but none of the bindings.
+    pub fn new_module(&self) -> Environment {
+        let mut env = Environment::new();
+        env.builtins = self.all_builtins();
+        env.config = Some(self.config());
+        env.io = Some(self.io());
+        env.trace = self.trace();
+        env.modules = Some(self.modules());
+        env.budget = Some(self.budget());
+        env.heap = Some(self.heap());
+        env.random = Some(self.random());
+        env
+    }
+
+    fn all_builtins(&self) -> HashMap<Symbol, BuiltinFunction> {
+        let mut builtins = match &self.outer {
+            Some(outer) => outer.borrow().all_builtins(),
+            None => HashMap::new(),
+        };
+        builtins.extend(self.builtins.iter().map(|(name, func)| (*name, *func)));
+        builtins
+    }
+
+    pub fn modules(&self) -> Rc<RefCell<Modules>> {
+        match (&self.modules, &self.outer) {
+            (Some(modules), _) => Rc::clone(modules),
+            (None, Some(outer)) => outer.borrow().modules(),
+            (None, None) => unreachable!("top-level scopes always have modules"),
+        }
+    }
+
+    pub fn budget(&self) -> Rc<RefCell<Budget>> {
+        match (&self.budget, &self.outer) {
+            (Some(budget), _) => Rc::clone(budget),
+            (None, Some(outer)) => outer.borrow().budget(),
+            (None, None) => unreachable!("top-level scopes always have a budget"),
+        }
+    }
+
+    pub fn heap(&self) -> Rc<RefCell<Heap>> {
+        match (&self.heap, &self.outer) {
+            (Some(heap), _) => Rc::clone(heap),
+            (None, Some(outer)) => outer.borrow().heap(),
+            (None, None) => unreachable!("top-level scopes always have a heap"),
+        }
+    }
+
+    pub fn random(&self) -> Rc<RefCell<Random>> {
+        match (&self.random, &self.outer) {
+            (Some(random), _) => Rc::clone(random),
+            (None, Some(outer)) => outer.borrow().random(),
+            (None, None) => unreachable!("top-level scopes always have a generator"),
+        }
+    }
+
+    // The scope this one is enclosed by, None for a top-level scope
+    pub fn outer(&self) -> Option<Rc<RefCell<Environment>>> {
+        self.outer.clone()
+    }
+
+    // How many scopes enclose this one, 0 for a top-level scope
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut outer = self.outer.clone();
+        while let Some(scope) = outer {
+            depth += 1;
+            outer = scope.borrow().outer.clone();
+        }
+        depth
+    }
+
+    // Makes a host function callable from scripts under the given name.
+    // Regular bindings with the same name take precedence over it.
+    pub fn register_builtin(&mut self, name: &str, func: BuiltinFunction) {
+        self.builtins.insert(Symbol::intern(name), func);
+    }
+
+    // Applies to this scope and every scope enclosed by it
+    pub fn set_config(&mut self, config: EvalConfig) {
+        if config.deterministic {
+            self.random().borrow_mut().seed(Random::FIXED_SEED);
+        }
+        self.config = Some(config);
+    }
+
+    pub fn config(&self) -> EvalConfig {
+        match (&self.config, &self.outer) {
+            (Some(config), _) => *config,
+            (None, Some(outer)) => outer.borrow().config(),
+            (None, None) => EvalConfig::default(),
+        }
+    }
+
+    // Like the config, applies to every scope enclosed by this one
+    pub fn set_io(&mut self, io: Io) {
+        self.io = Some(io);
+    }
+
+    pub fn io(&self) -> Io {
+        match (&self.io, &self.outer) {
+            (Some(io), _) => io.clone(),
+            (None, Some(outer)) => outer.borrow().io(),
+            (None, None) => Io::stdio(),
+        }
+    }
+
+    // Has every expression evaluated in this scope and those enclosed by it
+    // reported, see Tracer
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = Some(trace);
+    }
+
+    pub fn trace(&self) -> Option<Trace> {
+        match (&self.trace, &self.outer) {
+            (Some(trace), _) => Some(trace.clone()),
+            (None, Some(outer)) => outer.borrow().trace(),
+            (None, None) => None,
+        }
+    }
+
+    // Names are accepted as symbols or plain strings
+    pub fn get(&self, name: impl Into<Symbol>) -> Option<Object> {
+        self.lookup(name.into())
+    }
+
+    fn lookup(&self, name: Symbol) -> Option<Object> {
+        if let Some(value) = self.store.get(&name) {
+            return Some(value.clone());
+        }
+        if let Some(outer) = &self.outer {
+            if let Some(value) = outer.borrow().lookup(name) {
+                return Some(value);
+            }
+        }
+        if let Some(func) = self.builtins.get(&name) {
+            return Some(Object::Builtin(*func));
+        }
+        self.result(name.as_str())
+    }
+
+    fn result(&self, name: &str) -> Option<Object> {
+        let index = match name.strip_prefix('_')? {
+            "" => self.results.len().checked_sub(1)?,
+            number if !number.starts_with('0') => number.parse::<usize>().ok()? - 1,
+            _ => return None,
+        };
+        self.results.get(index).cloned()
+    }
+
+    // Keeps a result for later inputs to refer back to
+    pub fn push_result(&mut self, value: Object) {
+        self.results.push(value);
+    }
+
+    pub fn results(&self) -> &[Object] {
+        &self.results
+    }
+
+    // Every name visible from this scope, bound here or further out or
+    // registered as a builtin, sorted and without repeats
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.store.keys().chain(self.builtins.keys()).map(|name| name.to_string()).collect();
+        if let Some(outer) = &self.outer {
+            names.extend(outer.borrow().names());
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // Bindings of this scope only, sorted by name
+    pub fn bindings(&self) -> Vec<(String, Object)> {
+        let mut bindings: Vec<(String, Object)> = self.store.iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        bindings
+    }
+
+    pub fn set(&mut self, name: impl Into<Symbol>, value: Object) {
+        self.store.insert(name.into(), value);
+    }
+
+    // Rebinds an existing name in the innermost scope that declares it,
+    // returning false if it was never declared
+    pub fn assign(&mut self, name: impl Into<Symbol>, value: Object) -> bool {
+        self.update(name, |slot| *slot = value).is_some()
+    }
+
+    // Changes the value of an existing name where it is bound, in the
+    // innermost scope that declares it, giving None if it was never declared
+    pub fn update<T>(&mut self, name: impl Into<Symbol>, change: impl FnOnce(&mut Object) -> T) -> Option<T> {
+        self.update_symbol(name.into(), change)
+    }
+
+    fn update_symbol<T>(&mut self, name: Symbol, change: impl FnOnce(&mut Object) -> T) -> Option<T> {
+        match self.store.get_mut(&name) {
+            Some(slot) => Some(change(slot)),
+            None => self.outer.as_ref()?.borrow_mut().update_symbol(name, change),
+        }
+    }
+}
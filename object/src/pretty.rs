@@ -0,0 +1,192 @@
+// The debug form of values, for the REPL and `{:?}` placeholders. Unlike
+// inspect it quotes strings, opens modules up to show their members and
+// breaks collections too wide for one line into one element per line.
+
+use crate::shared::Rc;
+use crate::{HashKey, Module, Object};
+
+// How pretty lays values out
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct InspectOptions {
+    // Spaces per level of nesting
+    pub indent: usize,
+    // Collections nested deeper than this show as [...], None for no limit
+    pub max_depth: Option<usize>,
+    // Elements past this many are left out and counted instead
+    pub max_items: Option<usize>,
+    // Collections that fit in this many columns stay on one line
+    pub width: usize,
+}
+
+impl Default for InspectOptions {
+    fn default() -> InspectOptions {
+        InspectOptions { indent: 4, max_depth: Some(8), max_items: Some(100), width: 80 }
+    }
+}
+
+impl Object {
+    pub fn pretty(&self, options: &InspectOptions) -> String {
+        Printer { options, modules: Vec::new() }.value(self, 0)
+    }
+}
+
+struct Printer<'a> {
+    options: &'a InspectOptions,
+    // Modules being printed, as one can hold another that holds it back
+    modules: Vec<*const Module>,
+}
+
+impl Printer<'_> {
+    fn value(&mut self, value: &Object, level: usize) -> String {
+        match value {
+            Object::String(s) => format!("{:?}", s),
+            Object::Char(c) => format!("{:?}", c),
+            Object::Array(elements) => {
+                let items = self.items(elements.iter(), level, |printer, element| printer.value(element, level + 1));
+                self.collection("[", "]", items, level)
+            },
+            Object::Hash(pairs) => {
+                let items = self.items(pairs.iter(), level, |printer, (key, value)| format!("{}: {}", key_text(key), printer.value(value, level + 1)));
+                self.collection("{", "}", items, level)
+            },
+            Object::Struct(s) => {
+                let items = self.items(s.definition.fields.iter().zip(&s.values), level, |printer, (field, value)| format!("{}: {}", field, printer.value(value, level + 1)));
+                self.collection(&format!("{} {{", s.definition.name), "}", items, level)
+            },
+            Object::Module(module) => {
+                let open = format!("module {} {{", module.name);
+                if self.modules.contains(&Rc::as_ptr(module)) {
+                    return "<cycle>".to_string();
+                }
+                self.modules.push(Rc::as_ptr(module));
+                let members = module.env.borrow().bindings();
+                let items = self.items(members.iter(), level, |printer, (name, value)| format!("{}: {}", name, printer.value(value, level + 1)));
+                self.modules.pop();
+                self.collection(&open, "}", items, level)
+            },
+            Object::Result(Ok(value)) => format!("ok({})", self.value(value, level)),
+            Object::Result(Err(value)) => format!("err({})", self.value(value, level)),
+            Object::ReturnValue(value) => self.value(value, level),
+            other => other.inspect(),
+        }
+    }
+
+    // What goes between the brackets, None when the collection is nested
+    // too deep to show
+    fn items<T>(&mut self, elements: impl ExactSizeIterator<Item = T>, level: usize, mut show: impl FnMut(&mut Self, T) -> String) -> Option<Vec<String>> {
+        let len = elements.len();
+        if self.options.max_depth.is_some_and(|max| level >= max) && len > 0 {
+            return None;
+        }
+        let shown = self.options.max_items.unwrap_or(len).min(len);
+        let mut items: Vec<String> = elements.take(shown).map(|element| show(self, element)).collect();
+        if shown < len {
+            items.push(format!("... {} more", len - shown));
+        }
+        Some(items)
+    }
+
+    // On one line if it fits and nothing inside it needed more, otherwise
+    // an element to a line with a trailing comma
+    fn collection(&self, open: &str, close: &str, items: Option<Vec<String>>, level: usize) -> String {
+        let items = match items {
+            None => return format!("{}...{}", open, close),
+            Some(items) if items.is_empty() => return format!("{}{}", open, close),
+            Some(items) => items,
+        };
+        // Braces get a space inside, as they do in inspect
+        let pad = if open.ends_with('{') && open.len() > 1 { " " } else { "" };
+        let flat = format!("{}{}{}{}{}", open, pad, items.join(", "), pad, close);
+        if !flat.contains('\n') && level * self.options.indent + flat.chars().count() <= self.options.width {
+            return flat;
+        }
+        let inner = " ".repeat((level + 1) * self.options.indent);
+        let mut out = format!("{}\n", open);
+        for item in items {
+            out.push_str(&format!("{}{},\n", inner, item));
+        }
+        out.push_str(&" ".repeat(level * self.options.indent));
+        out.push_str(close);
+        out
+    }
+}
+
+fn key_text(key: &HashKey) -> String {
+    match key {
+        HashKey::String(s) => format!("{:?}", s),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::shared::RefCell;
+    use crate::{Environment, Struct, StructType};
+    use token::Symbol;
+
+    fn array(elements: Vec<Object>) -> Object {
+        Object::Array(Rc::new(elements))
+    }
+
+    fn int(i: i64) -> Object {
+        Object::Integer(i)
+    }
+
+    #[test]
+    fn test_pretty() {
+        let options = InspectOptions::default();
+        let mut pairs = BTreeMap::new();
+        pairs.insert(HashKey::String("name".to_string()), Object::String("Ada".to_string()));
+        pairs.insert(HashKey::Integer(1), Object::Result(Ok(Box::new(Object::Char('x')))));
+        let point = Rc::new(StructType { name: Symbol::intern("Point"), fields: vec![Symbol::intern("x"), Symbol::intern("y")] });
+        let value = array(vec![int(1), Object::Hash(Rc::new(pairs)), Object::Struct(Struct { definition: point, values: vec![int(2), array(vec![])] })]);
+        assert_eq!(value.pretty(&options), "[1, {1: ok('x'), \"name\": \"Ada\"}, Point { x: 2, y: [] }]");
+
+        // Too wide for one line, so the outer array breaks and the inner
+        // ones that fit stay whole
+        let wide = array((0..3).map(|i| array((0..12).map(|j| int(i * 100 + j)).collect())).collect());
+        let narrow = InspectOptions { width: 70, ..options };
+        assert_eq!(wide.pretty(&narrow), "[
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+    [100, 101, 102, 103, 104, 105, 106, 107, 108, 109, 110, 111],
+    [200, 201, 202, 203, 204, 205, 206, 207, 208, 209, 210, 211],
+]");
+        let narrower = InspectOptions { width: 20, indent: 2, ..options };
+        assert!(wide.pretty(&narrower).starts_with("[\n  [\n    0,\n    1,\n"), "{}", wide.pretty(&narrower));
+    }
+
+    #[test]
+    fn test_pretty_limits() {
+        let nested = array(vec![int(1), array(vec![int(2), array(vec![int(3)]), array(vec![])])]);
+        let shallow = InspectOptions { max_depth: Some(2), ..InspectOptions::default() };
+        assert_eq!(nested.pretty(&shallow), "[1, [2, [...], []]]");
+        let unlimited = InspectOptions { max_depth: None, ..InspectOptions::default() };
+        assert_eq!(nested.pretty(&unlimited), "[1, [2, [3], []]]");
+
+        let long = array((0..10).map(int).collect());
+        let short = InspectOptions { max_items: Some(3), ..InspectOptions::default() };
+        assert_eq!(long.pretty(&short), "[0, 1, 2, ... 7 more]");
+    }
+
+    #[test]
+    fn test_pretty_cycles() {
+        // Each of two modules holds the other
+        let module = |name: &str| Rc::new(Module { name: name.to_string(), env: Rc::new(RefCell::new(Environment::new())) });
+        let (a, b) = (module("a"), module("b"));
+        a.env.borrow_mut().set("b", Object::Module(Rc::clone(&b)));
+        a.env.borrow_mut().set("n", int(1));
+        b.env.borrow_mut().set("a", Object::Module(Rc::clone(&a)));
+        assert_eq!(Object::Module(Rc::clone(&a)).pretty(&InspectOptions::default()), "module a { b: module b { a: <cycle> }, n: 1 }");
+        // and the same module twice side by side is not one
+        let twice = array(vec![Object::Module(Rc::clone(&b)), Object::Module(Rc::clone(&b))]);
+        assert_eq!(twice.pretty(&InspectOptions::default()), "[
+    module b { a: module a { b: <cycle>, n: 1 } },
+    module b { a: module a { b: <cycle>, n: 1 } },
+]");
+
+        // Leave no cycle behind to leak
+        b.env.borrow_mut().set("a", Object::Null);
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use crate::{TraceEvent, Tracer};
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct LineProfile {
+    // Expressions evaluated, or instructions executed, on the line
+    pub count: u64,
+    pub time: Duration,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct FunctionProfile {
+    pub calls: u64,
+    // From entering the function to leaving it, counted once for recursive
+    // calls
+    pub time: Duration,
+}
+
+// Counts where a run spends its time, by instrumenting it as a Tracer. The
+// time between two events is charged to the line of the later one, which is
+// the expression finishing or the instruction running in that time.
+#[derive(Debug)]
+pub struct Profiler {
+    lines: HashMap<usize, LineProfile>,
+    functions: HashMap<String, FunctionProfile>,
+    calls: Vec<(String, Instant)>,
+    last: Instant,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler { lines: HashMap::new(), functions: HashMap::new(), calls: Vec::new(), last: Instant::now() }
+    }
+
+    // Hottest first, then in order
+    pub fn lines(&self) -> Vec<(usize, LineProfile)> {
+        let mut lines: Vec<_> = self.lines.iter().map(|(line, profile)| (*line, *profile)).collect();
+        lines.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(a.0.cmp(&b.0)));
+        lines
+    }
+
+    pub fn functions(&self) -> Vec<(String, FunctionProfile)> {
+        let mut functions: Vec<_> = self.functions.iter().map(|(name, profile)| (name.clone(), *profile)).collect();
+        functions.sort_by(|a, b| b.1.time.cmp(&a.1.time).then_with(|| a.0.cmp(&b.0)));
+        functions
+    }
+
+    // The hot spots as a table, functions first
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let functions = self.functions();
+        if !functions.is_empty() {
+            let width = functions.iter().map(|(name, _)| name.len()).max().unwrap().max("function".len());
+            writeln!(out, "{:width$}  {:>10}  {:>12}", "function", "calls", "time").unwrap();
+            for (name, profile) in functions {
+                writeln!(out, "{:width$}  {:>10}  {:>12}", name, profile.calls, format!("{:.3?}", profile.time)).unwrap();
+            }
+            out.push('\n');
+        }
+        writeln!(out, "{:>6}  {:>10}  {:>12}", "line", "count", "time").unwrap();
+        for (line, profile) in self.lines() {
+            writeln!(out, "{:>6}  {:>10}  {:>12}", line, profile.count, format!("{:.3?}", profile.time)).unwrap();
+        }
+        out
+    }
+}
+
+impl Tracer for Profiler {
+    fn trace(&mut self, event: &TraceEvent) {
+        let now = Instant::now();
+        if let Some(span) = event.span {
+            let line = self.lines.entry(span.line).or_default();
+            line.count += 1;
+            line.time += now - self.last;
+        }
+        self.last = now;
+    }
+
+    fn enter(&mut self, function: &str) {
+        self.functions.entry(function.to_string()).or_default().calls += 1;
+        self.calls.push((function.to_string(), Instant::now()));
+    }
+
+    fn exit(&mut self) {
+        if let Some((function, entered)) = self.calls.pop() {
+            if !self.calls.iter().any(|(outer, _)| *outer == function) {
+                self.functions.get_mut(&function).unwrap().time += entered.elapsed();
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+// The generator behind the random builtins, SplitMix64. Seeded the same it
+// gives the same numbers everywhere, so a script that calls seed, or a host
+// that seeds it, can be replayed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Random {
+    state: u64,
+}
+
+impl Default for Random {
+    // Seeded differently every time, as std seeds its hash maps
+    fn default() -> Random {
+        Random::new(RandomState::new().build_hasher().finish())
+    }
+}
+
+impl Random {
+    // Where deterministic runs start from
+    pub const FIXED_SEED: u64 = 0;
+
+    pub fn new(seed: u64) -> Random {
+        Random { state: seed }
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // A generator of its own for a spawned task, so tasks draw different
+    // numbers that still follow from the seed
+    pub fn fork(&mut self) -> Random {
+        Random::new(self.next_u64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random() {
+        // The first outputs for seed 0 given with SplitMix64
+        let mut random = Random::new(0);
+        assert_eq!(random.next_u64(), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(random.next_u64(), 0x6e78_9e6a_a1b9_65f4);
+
+        random.seed(7);
+        let first: Vec<u64> = (0..5).map(|_| random.next_u64()).collect();
+        random.seed(7);
+        let again: Vec<u64> = (0..5).map(|_| random.next_u64()).collect();
+        assert_eq!(first, again);
+        // and a fork follows from where it was taken
+        assert_eq!(random.clone().fork(), random.fork());
+    }
+}
@@ -0,0 +1,110 @@
+// The pointers and cells objects and environments are built from. They are
+// Rc and RefCell unless the sync feature swaps in Arc and a lock with the
+// same interface, which lets values and whole interpreters move between
+// threads. Code that builds objects takes them from here rather than std.
+
+#[cfg(not(feature = "sync"))]
+pub use std::cell::RefCell;
+#[cfg(not(feature = "sync"))]
+pub use std::rc::{Rc, Weak};
+
+#[cfg(feature = "sync")]
+pub use std::sync::{Arc as Rc, Weak};
+#[cfg(feature = "sync")]
+pub use lock::RefCell;
+
+// What a host hands the interpreter to keep, such as native functions and
+// streams, has to be Send and Sync when values are
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSync {}
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> MaybeSync for T {}
+
+#[cfg(feature = "sync")]
+pub trait MaybeSync: Send + Sync {}
+#[cfg(feature = "sync")]
+impl<T: ?Sized + Send + Sync> MaybeSync for T {}
+
+// A count shared between a heap and the scopes it tracks
+#[cfg(not(feature = "sync"))]
+pub(crate) type Counter = std::cell::Cell<usize>;
+#[cfg(feature = "sync")]
+pub(crate) use lock::Counter;
+
+#[cfg(feature = "sync")]
+mod lock {
+    use std::fmt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+    // A RefCell over a read-write lock. Borrows that would conflict wait for
+    // the other thread instead of panicking, and a panic while borrowed does
+    // not poison the value for everyone else.
+    #[derive(Debug)]
+    pub struct Borrowed;
+
+    #[derive(Default)]
+    pub struct RefCell<T: ?Sized>(RwLock<T>);
+
+    impl<T> RefCell<T> {
+        pub fn new(value: T) -> RefCell<T> {
+            RefCell(RwLock::new(value))
+        }
+
+        pub fn into_inner(self) -> T {
+            self.0.into_inner().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn replace(&self, value: T) -> T {
+            std::mem::replace(&mut *self.borrow_mut(), value)
+        }
+
+        pub fn take(&self) -> T
+        where
+            T: Default,
+        {
+            self.replace(T::default())
+        }
+    }
+
+    impl<T: ?Sized> RefCell<T> {
+        pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        // Fails only while a write borrow is held
+        pub fn try_borrow(&self) -> Result<RwLockReadGuard<'_, T>, Borrowed> {
+            match self.0.try_read() {
+                Ok(guard) => Ok(guard),
+                Err(TryLockError::Poisoned(poisoned)) => Ok(poisoned.into_inner()),
+                Err(TryLockError::WouldBlock) => Err(Borrowed),
+            }
+        }
+
+        pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+
+    impl<T: ?Sized + fmt::Debug> fmt::Debug for RefCell<T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.0.try_read() {
+                Ok(value) => f.debug_tuple("RefCell").field(&&*value).finish(),
+                Err(_) => f.write_str("RefCell(<borrowed>)"),
+            }
+        }
+    }
+
+    #[derive(Debug, Default)]
+    pub struct Counter(AtomicUsize);
+
+    impl Counter {
+        pub fn get(&self) -> usize {
+            self.0.load(Ordering::Relaxed)
+        }
+
+        pub fn set(&self, value: usize) {
+            self.0.store(value, Ordering::Relaxed);
+        }
+    }
+}
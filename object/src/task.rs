@@ -0,0 +1,62 @@
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use crate::{EvalError, Isolate, Object};
+
+type Job = Box<dyn FnOnce() -> Result<Object, EvalError> + Send>;
+
+// A function running apart from the code that spawned it, with copies of
+// everything it can reach so the two never change the same value. Whoever
+// gets to it first runs it, a worker thread or else the first join, so a
+// task joining another never waits for a worker to come free.
+#[derive(Clone)]
+pub struct Task(Arc<State>);
+
+struct State {
+    job: Mutex<Option<Job>>,
+    result: Mutex<Option<Result<Object, EvalError>>>,
+    done: Condvar,
+}
+
+impl Task {
+    pub fn new(job: impl FnOnce() -> Result<Object, EvalError> + Send + 'static) -> Task {
+        Task(Arc::new(State { job: Mutex::new(Some(Box::new(job))), result: Mutex::new(None), done: Condvar::new() }))
+    }
+
+    // Runs the function unless it has already been started
+    pub fn run(&self) {
+        let Some(job) = lock(&self.0.job).take() else { return };
+        let result = panic::catch_unwind(AssertUnwindSafe(job))
+            .unwrap_or_else(|_| Err(EvalError::Custom("spawned function panicked".to_string())));
+        *lock(&self.0.result) = Some(result);
+        self.0.done.notify_all();
+    }
+
+    // Waits for the result. Every join gets a copy of its own.
+    pub fn join(&self) -> Result<Object, EvalError> {
+        self.run();
+        let mut result = lock(&self.0.result);
+        loop {
+            match &*result {
+                Some(result) => return result.as_ref().map(|value| Isolate::new().copy(value)).map_err(Clone::clone),
+                None => result = self.0.done.wait(result).unwrap_or_else(PoisonError::into_inner),
+            }
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl PartialEq for Task {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for Task {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Task").finish_non_exhaustive()
+    }
+}
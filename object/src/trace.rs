@@ -0,0 +1,69 @@
+use std::fmt;
+use std::io::Write;
+use crate::shared::{MaybeSync, Rc, RefCell};
+use token::Span;
+use crate::Object;
+
+// One step of a run: an expression the evaluator finished or an
+// instruction the VM executed
+#[derive(Debug)]
+pub struct TraceEvent<'a> {
+    // The expression as source, or the instruction
+    pub node: String,
+    pub span: Option<Span>,
+    // What the expression evaluated to, or the top of the VM's stack after
+    // the instruction
+    pub value: Option<&'a Object>,
+    // Calls in progress, 0 at the top level
+    pub depth: usize,
+}
+
+pub trait Tracer: MaybeSync {
+    fn trace(&mut self, event: &TraceEvent);
+
+    // Calls into and out of script functions, named as in stack traces.
+    // Only the evaluator reports them.
+    fn enter(&mut self, _function: &str) {}
+    fn exit(&mut self) {}
+}
+
+// Writes one line per event, indented by depth
+pub struct WriteTracer<W: Write>(pub W);
+
+impl<W: Write + MaybeSync> Tracer for WriteTracer<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        let value = event.value.map_or("-".to_string(), Object::inspect);
+        let at = event.span.map_or(String::new(), |span| format!("  at {}", span));
+        // Tracing is best effort and never fails the run
+        let _ = writeln!(self.0, "{}{} => {}{}", "  ".repeat(event.depth), event.node, value, at);
+    }
+}
+
+// The tracer a run reports to, shared with the host that set it so that it
+// can read what was collected afterwards
+#[derive(Clone)]
+pub struct Trace(Rc<RefCell<dyn Tracer>>);
+
+impl Trace {
+    pub fn new(tracer: Rc<RefCell<dyn Tracer>>) -> Trace {
+        Trace(tracer)
+    }
+
+    pub fn record(&self, event: &TraceEvent) {
+        self.0.borrow_mut().trace(event);
+    }
+
+    pub fn enter(&self, function: &str) {
+        self.0.borrow_mut().enter(function);
+    }
+
+    pub fn exit(&self) {
+        self.0.borrow_mut().exit();
+    }
+}
+
+impl fmt::Debug for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Trace").finish_non_exhaustive()
+    }
+}
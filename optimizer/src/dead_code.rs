@@ -0,0 +1,226 @@
+use std::collections::HashSet;
+use ast::visit::{self, Visitor, VisitorMut};
+use ast::{BlockStatement, Expression, Program, Statement};
+use token::{Span, Symbol, Token, TokenType};
+use crate::{Pass, Warning};
+
+// Drops whatever follows a return or throw in the same block, which can
+// never run
+pub struct UnreachableCode;
+
+impl Pass for UnreachableCode {
+    fn name(&self) -> &'static str {
+        "unreachable_code"
+    }
+
+    fn run(&mut self, program: &mut Program, warnings: &mut Vec<Warning>) {
+        truncate_after_exit(&mut program.statements, warnings);
+        let mut pass = UnreachableVisitor { warnings };
+        pass.visit_program_mut(program);
+    }
+}
+
+struct UnreachableVisitor<'a> {
+    warnings: &'a mut Vec<Warning>,
+}
+
+impl VisitorMut for UnreachableVisitor<'_> {
+    fn visit_block_statement_mut(&mut self, block: &mut BlockStatement) {
+        truncate_after_exit(&mut block.statements, self.warnings);
+        visit::walk_block_statement_mut(self, block);
+    }
+}
+
+fn truncate_after_exit(statements: &mut Vec<Statement>, warnings: &mut Vec<Warning>) {
+    let Some(position) = statements.iter().position(|s| matches!(s, Statement::ReturnStatement(_) | Statement::ThrowStatement(_))) else {
+        return;
+    };
+    if let Some(first) = statements.get(position + 1) {
+        warnings.push(Warning { lint: "unreachable_code", message: "unreachable statement".to_string(), span: first.span() });
+        statements.truncate(position + 1);
+    }
+}
+
+// Replaces a conditional on true, false or null with the branch it always
+// takes. Other literals are left for the analysis lints to point out. The
+// grammar has no if statement, so a conditional written as a statement
+// stands in for one: the statements of the block it takes are put in its
+// place, and a branch with no effect, as `false ? { ... } : null;` takes,
+// goes away altogether.
+pub struct ConstantBranches;
+
+impl Pass for ConstantBranches {
+    fn name(&self) -> &'static str {
+        "constant_branches"
+    }
+
+    fn run(&mut self, program: &mut Program, _warnings: &mut Vec<Warning>) {
+        fold_statements(&mut program.statements);
+        ConstantBranches.visit_program_mut(program);
+    }
+}
+
+impl VisitorMut for ConstantBranches {
+    fn visit_block_statement_mut(&mut self, block: &mut BlockStatement) {
+        fold_statements(&mut block.statements);
+        visit::walk_block_statement_mut(self, block);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        visit::walk_expression_mut(self, expression);
+        if let Some(taken) = taken(expression) {
+            *expression = branch(expression, taken);
+        }
+    }
+}
+
+// Which branch a conditional on a literal takes
+fn taken(expression: &Expression) -> Option<bool> {
+    match expression {
+        Expression::Ternary(ternary) => match &*ternary.condition {
+            Expression::BooleanLiteral(token) => Some(token.ttype == TokenType::True),
+            Expression::NullLiteral(_) => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Takes the branch out of the conditional, leaving null in its place
+fn branch(expression: &mut Expression, taken: bool) -> Expression {
+    let placeholder = Expression::NullLiteral(Token::new(TokenType::Null, "null".to_string()));
+    match std::mem::replace(expression, placeholder) {
+        Expression::Ternary(ternary) if taken => *ternary.if_true,
+        Expression::Ternary(ternary) => *ternary.if_false,
+        _ => unreachable!("only conditionals have branches"),
+    }
+}
+
+fn fold_statements(statements: &mut Vec<Statement>) {
+    let count = statements.len();
+    let mut folded = Vec::with_capacity(count);
+    for (i, statement) in std::mem::take(statements).into_iter().enumerate() {
+        let Statement::ExpressionStatement(mut es) = statement else {
+            folded.push(statement);
+            continue;
+        };
+        let Some(taken) = taken(&es.expression) else {
+            folded.push(Statement::ExpressionStatement(es));
+            continue;
+        };
+        // The last statement's value is what the block evaluates to
+        let last = i + 1 == count;
+        match branch(&mut es.expression, taken) {
+            Expression::Block(mut block) if block.statements.iter().all(scoped) && !(last && block.statements.is_empty()) => {
+                fold_statements(&mut block.statements);
+                // A semicolon after the conditional discards the value
+                // of the block, so its last expression must not supply one
+                if let (true, true, Some(Statement::ExpressionStatement(value))) = (last, es.semicolon, block.statements.last_mut()) {
+                    value.semicolon = true;
+                }
+                folded.append(&mut block.statements);
+            },
+            expression if !last && no_effect(&expression) => {},
+            expression => {
+                es.expression = expression;
+                folded.push(Statement::ExpressionStatement(es));
+            },
+        }
+    }
+    *statements = folded;
+}
+
+// Whether a statement binds nothing that would outlive its block, as lets,
+// imports, struct declarations and loops all do
+fn scoped(statement: &Statement) -> bool {
+    matches!(statement,
+        Statement::ExpressionStatement(_) | Statement::ReturnStatement(_)
+        | Statement::ThrowStatement(_) | Statement::BlockStatement(_))
+}
+
+fn no_effect(expression: &Expression) -> bool {
+    match expression {
+        Expression::IntegerLiteral(_) | Expression::FloatLiteral(_) | Expression::StringLiteral(_)
+        | Expression::CharLiteral(_) | Expression::BooleanLiteral(_) | Expression::NullLiteral(_) => true,
+        Expression::Block(block) => block.statements.is_empty(),
+        // What `{}` parses as
+        Expression::HashLiteral(hash) => hash.pairs.is_empty(),
+        _ => false,
+    }
+}
+
+// Warns about bindings made inside a function that nothing reads. Globals
+// are exempt, since a module's importers may use them, as are names
+// starting with an underscore.
+pub struct UnusedBindings;
+
+impl Pass for UnusedBindings {
+    fn name(&self) -> &'static str {
+        "unused_bindings"
+    }
+
+    fn run(&mut self, program: &mut Program, warnings: &mut Vec<Warning>) {
+        // Found function by function, outermost first, but reported in the
+        // order of the source
+        let mut pass = UnusedVisitor { warnings: &mut Vec::new() };
+        pass.visit_program(program);
+        pass.warnings.sort_by_key(|warning| (warning.span.line, warning.span.column));
+        warnings.append(pass.warnings);
+    }
+}
+
+struct UnusedVisitor<'a> {
+    warnings: &'a mut Vec<Warning>,
+}
+
+impl Visitor for UnusedVisitor<'_> {
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let Expression::FunctionLiteral(func) = expression {
+            // Loop bodies share the function's scope, so a binding counts as
+            // used if anything in the function reads it
+            let mut used = Names::default();
+            used.visit_block_statement(&func.body);
+            let mut bindings = Bindings::default();
+            bindings.visit_block_statement(&func.body);
+
+            for (name, span) in bindings.0 {
+                if !used.0.contains(&name) && !name.as_str().starts_with('_') {
+                    self.warnings.push(Warning { lint: "unused_binding", message: format!("unused binding {}", name), span });
+                }
+            }
+        }
+        visit::walk_expression(self, expression);
+    }
+}
+
+// Every name read, including by nested functions
+#[derive(Default)]
+struct Names(HashSet<Symbol>);
+
+impl Visitor for Names {
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let Expression::Identifier(ident) = expression {
+            self.0.insert(ident.value);
+        }
+        visit::walk_expression(self, expression);
+    }
+}
+
+// The lets of one function, not counting those of functions inside it
+#[derive(Default)]
+struct Bindings(Vec<(Symbol, Span)>);
+
+impl Visitor for Bindings {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if let Statement::LetStatement(ls) = statement {
+            self.0.push((ls.name.value, ls.name.token.span()));
+        }
+        visit::walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if !matches!(expression, Expression::FunctionLiteral(_)) {
+            visit::walk_expression(self, expression);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+mod dead_code;
+
+use std::fmt;
+use ast::Program;
+use token::Span;
+
+pub use dead_code::{ConstantBranches, UnreachableCode, UnusedBindings};
+
+// Something a pass noticed that is probably a mistake, named so that it can
+// be looked up or silenced
+#[derive(Debug, PartialEq, Clone)]
+pub struct Warning {
+    pub lint: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {} [{}]", self.message, self.span, self.lint)
+    }
+}
+
+// One rewrite of the program. Passes must keep what the program does; they
+// may only drop what can never run or has no effect.
+pub trait Pass {
+    fn name(&self) -> &'static str;
+    fn run(&mut self, program: &mut Program, warnings: &mut Vec<Warning>);
+}
+
+// Passes run in the order they were added, each on the output of the last
+pub struct Pipeline {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Pipeline {
+        Pipeline::new()
+            .with(ConstantBranches)
+            .with(UnreachableCode)
+            .with(UnusedBindings)
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline { passes: Vec::new() }
+    }
+
+    pub fn with(mut self, pass: impl Pass + 'static) -> Pipeline {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    pub fn run(&mut self, program: &mut Program) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+        for pass in &mut self.passes {
+            pass.run(program, &mut warnings);
+        }
+        warnings
+    }
+}
+
+// Runs the default passes
+pub fn optimize(program: &mut Program) -> Vec<Warning> {
+    Pipeline::default().run(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use lexer::Lexer;
+    use parser::Parser;
+    use super::*;
+
+    fn run(mut pipeline: Pipeline, input: &str) -> (String, Vec<String>) {
+        let mut program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let warnings = pipeline.run(&mut program);
+        (program.to_string(), warnings.iter().map(|warning| warning.to_string()).collect())
+    }
+
+    #[test]
+    fn test_unreachable_code() {
+        let tests = vec![
+            ("let f = fn() { return 1; puts(2); 3 };", "let f = fn() { return 1; };", vec!["unreachable statement at line 1, col 26 [unreachable_code]"]),
+            ("return 1; puts(2);", "return 1;", vec!["unreachable statement at line 1, col 11 [unreachable_code]"]),
+            ("for (x in xs) { return x; x; }", "for (x in xs) { return x; }", vec!["unreachable statement at line 1, col 27 [unreachable_code]"]),
+            ("let f = fn() { fn() { return 1; 2; }; 3 };", "let f = fn() { fn() { return 1; }; 3 };", vec!["unreachable statement at line 1, col 33 [unreachable_code]"]),
+            ("try { throw 1; 2; } catch (e) { e }", "try { throw 1; } catch (e) { e };", vec!["unreachable statement at line 1, col 16 [unreachable_code]"]),
+            ("let f = fn() { puts(1); return 2; };", "let f = fn() { puts(1); return 2; };", vec![]),
+        ];
+
+        for (input, expected, warnings) in tests {
+            let (actual, actual_warnings) = run(Pipeline::new().with(UnreachableCode), input);
+            assert_eq!(actual, expected, "{}", input);
+            assert_eq!(actual_warnings, warnings, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_constant_branches() {
+        let tests = vec![
+            ("true ? a : b", "a;"),
+            ("false ? a : b", "b;"),
+            ("null ? a : b", "b;"),
+            ("false ? a : true ? b : c", "b;"),
+            ("let f = fn(x) { x + (false ? 1 : 2) };", "let f = fn(x) { (x + 2) };"),
+            ("x ? a : b", "(x ? a : b);"),
+            ("1 ? a : b", "(1 ? a : b);"),
+            // As statements, ternaries stand in for if and else. The block
+            // taken replaces them, and a branch that does nothing goes.
+            ("{ let y = 1; false ? { puts(y); y } : { y + 1 }; }", "{ let y = 1; (y + 1); }"),
+            ("let f = fn() { true ? { return 1; } : { puts(2); }; 3 };", "let f = fn() { return 1; 3 };"),
+            ("false ? { puts(1); } : null; puts(2);", "puts(2);"),
+            ("let f = fn() { false ? { puts(1); } : {}; 2 };", "let f = fn() { 2 };"),
+            ("let f = fn() { true ? { puts(1); 2 } : 3 };", "let f = fn() { puts(1); 2 };"),
+            ("let f = fn() { true ? { puts(1); 2 } : 3; };", "let f = fn() { puts(1); 2; };"),
+            ("let f = fn() { puts(1); false ? 2 : null };", "let f = fn() { puts(1); null };"),
+            ("true ? { true ? { puts(1); } : null; false ? 2 : { puts(3); }; } : null; 4", "puts(1);puts(3);4;"),
+            // unless its bindings would then outlive it
+            ("true ? { let x = 1; puts(x); } : null;", "{ let x = 1; puts(x); };"),
+            ("true ? { for (x in [1]) {} } : null;", "{ for (x in [1]) { } };"),
+            ("let y = true ? { puts(1); 2 } : 3;", "let y = { puts(1); 2 };"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(run(Pipeline::new().with(ConstantBranches), input), (expected.to_string(), vec![]), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_unused_bindings() {
+        let tests = vec![
+            ("let f = fn() { let x = 1; 2 };", vec!["unused binding x at line 1, col 20 [unused_binding]"]),
+            ("let f = fn() { let x = 1; x };", vec![]),
+            ("let f = fn() { let _x = 1; 2 };", vec![]),
+            // Used only by a closure, or on the next time round a loop
+            ("let f = fn() { let x = 1; fn() { x } };", vec![]),
+            ("let f = fn() { for (i in [1, 2]) { puts(x); let x = i; } };", vec![]),
+            // Loop bodies share the function's scope
+            ("let f = fn() { for (i in [1]) { let x = i; } x };", vec![]),
+            ("let f = fn() { fn() { let y = 1; }; let z = 2; };", vec![
+                "unused binding y at line 1, col 27 [unused_binding]",
+                "unused binding z at line 1, col 41 [unused_binding]",
+            ]),
+            // Globals may be used by whoever imports the module
+            ("let x = 1;", vec![]),
+        ];
+
+        for (input, warnings) in tests {
+            let (_, actual) = run(Pipeline::new().with(UnusedBindings), input);
+            assert_eq!(actual, warnings, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_optimize() {
+        // A binding only used after a return is unused once that is removed
+        let input = "let f = fn() { let x = 1; return true ? 2 : 3; x };";
+        let (program, warnings) = run(Pipeline::default(), input);
+        assert_eq!(program, "let f = fn() { let x = 1; return 2; };");
+        assert_eq!(warnings, vec![
+            "unreachable statement at line 1, col 48 [unreachable_code]",
+            "unused binding x at line 1, col 20 [unused_binding]",
+        ]);
+        // Folding comes first, so what follows a return it uncovers is
+        // unreachable
+        let input = "let f = fn() { true ? { return 1; } : null; 2 };";
+        let (program, warnings) = run(Pipeline::default(), input);
+        assert_eq!(program, "let f = fn() { return 1; };");
+        assert_eq!(warnings, vec!["unreachable statement at line 1, col 45 [unreachable_code]"]);
+        assert_eq!(Pipeline::default().names(), vec!["constant_branches", "unreachable_code", "unused_bindings"]);
+    }
+}
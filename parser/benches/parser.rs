@@ -0,0 +1,22 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use lexer::Lexer;
+use parser::Parser;
+
+const SNIPPET: &str = r#"
+let add = fn(x, y) { let z = x * y + 3 - -x; return z ?? 4 <= y == true; };
+let result = add(1, [2, 3, 4]) + counter++ * 5;
+for (let i = 0; i < 10; i += 1) { total = total + i; }
+for (c in "hello") { out = out + c; }
+"#;
+
+fn parser(c: &mut Criterion) {
+    let input = SNIPPET.repeat(1000);
+
+    let mut group = c.benchmark_group("parser");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.bench_function("program", |b| b.iter(|| Parser::new(Lexer::new(&input)).parse_program().unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, parser);
+criterion_main!(benches);
@@ -0,0 +1,173 @@
+use std::collections::VecDeque;
+use token::Span;
+use lexer::Lexer;
+use ast::{Program, Statement};
+use crate::{ParseError, Parser};
+
+// A change to a source: the text from start up to end replaced with text.
+// Positions count lines and columns from 1 like spans, in the source before
+// the change.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TextEdit {
+    pub start: Span,
+    pub end: Span,
+    pub text: String,
+}
+
+impl TextEdit {
+    // The source after the change
+    pub fn apply(&self, source: &str) -> String {
+        let (start, end) = (offset(source, self.start), offset(source, self.end));
+        format!("{}{}{}", &source[..start], self.text, &source[end.max(start)..])
+    }
+
+    fn lines_unchanged(&self) -> bool {
+        self.start.line == self.end.line && !self.text.contains('\n')
+    }
+}
+
+impl Parser<'_> {
+    // Parses source, which is the source old was parsed from with edit
+    // applied, reusing the statements of old the edit cannot have changed:
+    // those before it, and when it adds or removes no lines, those starting
+    // on a later line but the first, which could take doc comments from the
+    // edit. Only the text in between is lexed and parsed again.
+    pub fn reparse(old: Program, source: &str, edit: &TextEdit) -> Result<Program, Vec<ParseError>> {
+        let mut statements = old.statements;
+        // A statement is only known to end where the next one starts, and
+        // how it parsed depends on the token there, so that token has to
+        // end before the edit too, with a character to spare
+        let mut keep = 0;
+        while keep + 1 < statements.len() && ends_before(&statements[keep + 1], edit.start) {
+            keep += 1;
+        }
+
+        let later = match edit.lines_unchanged() {
+            true => statements.iter().skip(keep).position(|statement| statement.span().line > edit.end.line).map_or(statements.len(), |i| statements.len().min(keep + i + 1)),
+            false => statements.len(),
+        };
+        let reusable: VecDeque<Statement> = statements.split_off(later).into();
+        let resume = match statements.get(keep) {
+            Some(statement) if keep > 0 => statement.span(),
+            _ => Span { line: 1, column: 1 },
+        };
+        statements.truncate(keep);
+
+        let lexer = Lexer::new(&source[offset(source, resume)..]).starting_at(resume.line, resume.column);
+        Parser::new(lexer).parse_statements(statements, reusable)
+    }
+}
+
+// Whether the first token of the statement, and the character after it, come
+// before the position
+fn ends_before(statement: &Statement, position: Span) -> bool {
+    let start = statement.span();
+    let literal = &statement.token().literal;
+    !literal.contains('\n') && (start.line, start.column + literal.chars().count()) < (position.line, position.column)
+}
+
+// The byte offset of a position, clamped to the end of its line or of the
+// source
+fn offset(source: &str, position: Span) -> usize {
+    let mut line_start = 0;
+    for _ in 1..position.line {
+        match source[line_start..].find('\n') {
+            Some(newline) => line_start += newline + 1,
+            None => return source.len(),
+        }
+    }
+    let line = &source[line_start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+    line_start + line.char_indices().nth(position.column.saturating_sub(1)).map_or(line.len(), |(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Program {
+        Parser::new(Lexer::new(source)).parse_program().unwrap()
+    }
+
+    fn edit(start: (usize, usize), end: (usize, usize), text: &str) -> TextEdit {
+        TextEdit {
+            start: Span { line: start.0, column: start.1 },
+            end: Span { line: end.0, column: end.1 },
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let source = "let a = 1;\nlet bé = 2;\n";
+        assert_eq!(edit((2, 6), (2, 7), "x").apply(source), "let a = 1;\nlet bx = 2;\n");
+        assert_eq!(edit((1, 11), (2, 1), "").apply(source), "let a = 1;let bé = 2;\n");
+        assert_eq!(edit((3, 1), (3, 1), "x").apply(source), "let a = 1;\nlet bé = 2;\nx");
+        assert_eq!(edit((1, 50), (9, 1), "!").apply(source), "let a = 1;!");
+    }
+
+    #[test]
+    fn test_reparse() {
+        let source = "let a = 1;\nlet b = a + 2;\nputs(b)\nfn(x) {\n  x * 2\n}(3);\nlet c = 4;";
+        let tests = vec![
+            // Within a line, before and after the middle
+            edit((2, 13), (2, 14), "20"),
+            edit((5, 7), (5, 8), "3"),
+            // Joining and splitting lines
+            edit((3, 8), (4, 1), " + "),
+            edit((5, 3), (5, 3), "x +\n  "),
+            // Into what was the first token of a statement
+            edit((3, 1), (3, 5), "print"),
+            edit((7, 5), (7, 5), "x"),
+            edit((1, 1), (1, 1), "\n"),
+            edit((7, 11), (7, 11), " c"),
+            edit((1, 1), (8, 1), ""),
+        ];
+
+        for edit in tests {
+            let edited = edit.apply(source);
+            let expected = Parser::new(Lexer::new(&edited)).parse_program();
+            let reparsed = Parser::reparse(parse(source), &edited, &edit);
+            // Spans are left out of equality, so compare where statements
+            // start too
+            let spans = |program: &Result<Program, Vec<ParseError>>| match program {
+                Ok(program) => program.statements.iter().map(|s| s.span()).collect(),
+                Err(_) => Vec::new(),
+            };
+            assert_eq!(spans(&reparsed), spans(&expected), "{}", edited);
+            assert_eq!(reparsed, expected, "{}", edited);
+        }
+    }
+
+    #[test]
+    fn test_reparse_reuses_statements() {
+        // Reused statements come from the old tree as they are, which shows
+        // when it was parsed from something else
+        let old = parse("let x = 1;\nlet b = 2;\nlet z = 3;\nlet w = 4;");
+        let edit = edit((2, 9), (2, 10), "5");
+        let program = Parser::reparse(old, "let a = 1;\nlet b = 5;\nlet c = 3;\nlet d = 4;", &edit).unwrap();
+        assert_eq!(program.to_string(), "let x = 1;let b = 5;let c = 3;let w = 4;");
+    }
+
+    #[test]
+    fn test_reparse_doc_comments() {
+        // The statement after an edit to its doc comment is parsed again
+        let source = "let a = 1;\n/// Two\nlet b = 2;";
+        let edit = edit((2, 5), (2, 8), "Deux");
+        let program = Parser::reparse(parse(source), &edit.apply(source), &edit).unwrap();
+        match &program.statements[1] {
+            Statement::LetStatement(statement) => assert_eq!(statement.docs.as_deref(), Some("Deux")),
+            other => panic!("expected a let statement, got {}", other),
+        }
+    }
+
+    #[test]
+    fn test_reparse_errors() {
+        let source = "let a = 1;\nlet b = 2;\nlet c = 3;";
+        let edit = edit((2, 5), (2, 6), "");
+        let errors = Parser::reparse(parse(source), &edit.apply(source), &edit).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span(), Span { line: 2, column: 6 });
+        assert_eq!(errors[0].message(), "expected next token to be Identifier, got Assign");
+    }
+}
@@ -1,20 +1,33 @@
-use token::{Token, TokenType};
+mod incremental;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use token::{Span, Symbol, Token, TokenRef, TokenType};
 use lexer::Lexer;
 #[allow(unused_imports)]
 use ast::{
     Program, Statement,
     LetStatement, ReturnStatement, ExpressionStatement,
+    BlockStatement, ForStatement, ForInStatement, ImportStatement,
     Identifier, Expression,
-    IntegerLiteral, Prefix, Infix, Postfix, Ternary,
+    IntegerLiteral, FloatLiteral, CharLiteral, Prefix, Infix, Postfix, Propagate, Ternary, Assignment,
+    ArrayLiteral, HashLiteral, FunctionLiteral, RangeLiteral, Call, Member, MethodCall, Index, Import,
+    MatchExpression, MatchArm, Pattern, StructStatement, StructLiteral, InterpolatedString, StringPart,
+    ThrowStatement, TryExpression,
 };
 
-#[allow(dead_code)]
-#[derive(Debug, PartialEq, Clone, PartialOrd, Eq, Ord)]
-enum Precedence {
+pub use incremental::TextEdit;
+
+#[derive(Debug, PartialEq, Clone, Copy, PartialOrd, Eq, Ord)]
+pub enum Precedence {
     Lowest,
+    Assign,         // =
     Ternary,        // ?
+    Pipe,           // |>
+    Coalesce,       // ??
     Equals,         // ==
     LessGreater,    // > or <
+    Range,          // ..
     Sum,            // +
     Product,        // *
     Prefix,         // -X or !X
@@ -22,111 +35,480 @@ enum Precedence {
     Index,          // array[index]
 }
 
-fn precedence_for_op(op: TokenType) -> Precedence {
-    match op {
-        TokenType::Question => Precedence::Ternary,
-        TokenType::Equal | TokenType::NotEqual => Precedence::Equals,
-        TokenType::LessThan | TokenType::GreaterThan | TokenType::LessThanOrEqual | TokenType::GreaterThanOrEqual => Precedence::LessGreater,
-        TokenType::Plus | TokenType::Minus => Precedence::Sum,
-        TokenType::Asterisk | TokenType::Slash | TokenType::Percent => Precedence::Product,
-        TokenType::LeftParen => Precedence::Call,
-        TokenType::LeftSquareBracket => Precedence::Index,
-        _ => Precedence::Lowest,
+impl Precedence {
+    // The next level down, which lets an operator of this level continue
+    // the operand on its right
+    fn below(self) -> Precedence {
+        match self {
+            Precedence::Lowest | Precedence::Assign => Precedence::Lowest,
+            Precedence::Ternary => Precedence::Assign,
+            Precedence::Pipe => Precedence::Ternary,
+            Precedence::Coalesce => Precedence::Pipe,
+            Precedence::Equals => Precedence::Coalesce,
+            Precedence::LessGreater => Precedence::Equals,
+            Precedence::Range => Precedence::LessGreater,
+            Precedence::Sum => Precedence::Range,
+            Precedence::Product => Precedence::Sum,
+            Precedence::Prefix => Precedence::Product,
+            Precedence::Call => Precedence::Prefix,
+            Precedence::Index => Precedence::Call,
+        }
     }
 }
 
-fn is_infix_op(op: TokenType) -> bool {
-    match op {
-        TokenType::Plus | TokenType::Minus | TokenType::Asterisk | TokenType::Slash | TokenType::Percent | TokenType::Equal | TokenType::NotEqual | TokenType::LessThan | TokenType::GreaterThan | TokenType::LessThanOrEqual | TokenType::GreaterThanOrEqual => true,
-        _ => false,
-    }
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+// Called with the current token on the operator or the operand's first token
+pub type PrefixParseFn<'a> = fn(&mut Parser<'a>) -> Result<Expression, ParseError>;
+pub type InfixParseFn<'a> = fn(&mut Parser<'a>, Expression) -> Result<Expression, ParseError>;
+
+#[derive(Clone, Copy)]
+struct InfixRule<'a> {
+    precedence: Precedence,
+    assoc: Assoc,
+    parse: InfixParseFn<'a>,
 }
 
 fn is_postfix_op(op: TokenType) -> bool {
-    match op {
-        TokenType::Increment | TokenType::Decrement => true,
-        _ => false,
+    matches!(op, TokenType::Increment | TokenType::Decrement)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ParseError {
+    UnexpectedToken { expected: TokenType, token: Token },
+    UnexpectedEof { expected: Option<TokenType>, token: Token },
+    NoPrefixParse(Token),
+    IntegerOutOfRange { literal: String, token: Token },
+    InvalidPrefixOperand { operator: Token, operand: Token },
+    InvalidAssignmentTarget { target: String, token: Token },
+    InvalidIncrementTarget { target: String, token: Token },
+    InvalidModuleName(Token),
+    InvalidPattern(Token),
+    Illegal { error: lexer::LexError, token: Token },
+    TooDeep { limit: usize, token: Token },
+}
+
+impl ParseError {
+    pub fn token(&self) -> &Token {
+        match self {
+            ParseError::UnexpectedToken { token, .. } => token,
+            ParseError::UnexpectedEof { token, .. } => token,
+            ParseError::NoPrefixParse(token) => token,
+            ParseError::IntegerOutOfRange { token, .. } => token,
+            ParseError::InvalidPrefixOperand { operand, .. } => operand,
+            ParseError::InvalidAssignmentTarget { token, .. } => token,
+            ParseError::InvalidIncrementTarget { token, .. } => token,
+            ParseError::InvalidModuleName(token) => token,
+            ParseError::InvalidPattern(token) => token,
+            ParseError::Illegal { token, .. } => token,
+            ParseError::TooDeep { token, .. } => token,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        self.token().span()
+    }
+
+    // The description without the position, for renderers that show it separately
+    pub fn message(&self) -> String {
+        match self {
+            ParseError::UnexpectedToken { expected, token } => {
+                format!("expected next token to be {:?}, got {:?}", expected, token.ttype)
+            },
+            ParseError::UnexpectedEof { expected: Some(expected), .. } => {
+                format!("expected next token to be {:?}, got end of input", expected)
+            },
+            ParseError::UnexpectedEof { expected: None, .. } => "unexpected end of input".to_string(),
+            ParseError::NoPrefixParse(token) => format!("no prefix parse function for {:?}", token.ttype),
+            ParseError::IntegerOutOfRange { literal, .. } => format!("integer literal {} is out of range", literal),
+            ParseError::InvalidPrefixOperand { operator, operand } => {
+                format!("cannot apply prefix operator {} to {:?}", operator.literal, operand.ttype)
+            },
+            ParseError::InvalidAssignmentTarget { target, .. } => format!("invalid assignment target {}", target),
+            ParseError::InvalidIncrementTarget { target, token } => {
+                format!("cannot apply {} to {}, only to a variable", token.literal, target)
+            },
+            ParseError::InvalidModuleName(token) => {
+                format!("cannot name module {} after its path, use `let name = import({})`", token.literal, token.literal)
+            },
+            ParseError::InvalidPattern(token) => format!("invalid pattern {}", token),
+            ParseError::Illegal { error, .. } => error.to_string(),
+            ParseError::TooDeep { limit, .. } => format!("expression nested more than {} levels deep", limit),
+        }
     }
 }
 
-pub struct Parser {
-    l: Lexer,
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message(), self.span())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Parsing recurses once per level of nesting, so this bounds the stack it
+// can use: under a megabyte in release builds and a few in debug ones,
+// inside the main thread's 8 MiB. Operators chained in one expression count
+// too, since every pass over the tree recurses once for each of them. Far
+// beyond anything written by hand.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+pub struct Parser<'a> {
+    l: Lexer<'a>,
+
+    // The current token first, then however far ahead has been looked.
+    // Always holds at least the current and the peek token.
+    tokens: VecDeque<TokenRef<'a>>,
+
+    errors: Vec<ParseError>,
+
+    // Expressions currently being parsed, innermost included
+    depth: usize,
+    max_depth: usize,
 
-    cur_token: Option<Token>,
-    peek_token: Option<Token>,
+    // Off while parsing what a match is on, so that in `match x { ... }`
+    // the braces are taken as the arms, not as fields of a struct named x
+    struct_literals: bool,
+
+    // Indexed by token type
+    prefix_parse_fns: Vec<Option<PrefixParseFn<'a>>>,
+    infix_rules: Vec<Option<InfixRule<'a>>>,
+
+    // Doc comments by the line and column of the token after them, and
+    // the lines read since the last token
+    docs: HashMap<(usize, usize), String>,
+    doc_lines: Vec<&'a str>,
 }
 
-impl Parser {
-    pub fn new(l: Lexer) -> Parser {
+impl<'a> Parser<'a> {
+    pub fn new(l: Lexer<'a>) -> Parser<'a> {
+        Parser::with_max_depth(l, DEFAULT_MAX_DEPTH)
+    }
+
+    pub fn with_max_depth(l: Lexer<'a>, max_depth: usize) -> Parser<'a> {
         let mut p = Parser {
-            l,
-            cur_token: Some(Token::new(TokenType::Illegal, "".to_string())),
-            peek_token: Some(Token::new(TokenType::Illegal, "".to_string())),
+            l: l.with_docs(),
+            tokens: VecDeque::new(),
+            errors: Vec::new(),
+            depth: 0,
+            max_depth,
+            struct_literals: true,
+            prefix_parse_fns: Vec::new(),
+            infix_rules: Vec::new(),
+            docs: HashMap::new(),
+            doc_lines: Vec::new(),
         };
-        p.next_token();
-        p.next_token();
-        return p;
+        p.register_operators();
+        p.fill(1);
+        p
+    }
+
+    fn register_operators(&mut self) {
+        self.register_prefix(TokenType::Identifier, Parser::parse_identifier);
+        self.register_prefix(TokenType::Integer, Parser::parse_integer_literal);
+        self.register_prefix(TokenType::Float, Parser::parse_float_literal);
+        self.register_prefix(TokenType::String, Parser::parse_string_literal);
+        self.register_prefix(TokenType::InterpolatedString, Parser::parse_interpolated_string);
+        self.register_prefix(TokenType::RawString, Parser::parse_string_literal);
+        self.register_prefix(TokenType::Char, Parser::parse_char_literal);
+        self.register_prefix(TokenType::True, Parser::parse_boolean_literal);
+        self.register_prefix(TokenType::False, Parser::parse_boolean_literal);
+        self.register_prefix(TokenType::Null, Parser::parse_null_literal);
+        for ttype in [TokenType::Bang, TokenType::Minus, TokenType::Increment, TokenType::Decrement] {
+            self.register_prefix(ttype, Parser::parse_prefix_expression);
+        }
+        self.register_prefix(TokenType::Fn, Parser::parse_function_literal);
+        self.register_prefix(TokenType::Import, Parser::parse_import_expression);
+        self.register_prefix(TokenType::Match, Parser::parse_match_expression);
+        self.register_prefix(TokenType::Try, Parser::parse_try_expression);
+        self.register_prefix(TokenType::LeftSquareBracket, Parser::parse_array_literal);
+        self.register_prefix(TokenType::LeftCurlyBracket, Parser::parse_brace_expression);
+        self.register_prefix(TokenType::LeftParen, Parser::parse_grouped_expression);
+
+        for ttype in [TokenType::Assign, TokenType::PlusAssign, TokenType::MinusAssign, TokenType::AsteriskAssign, TokenType::SlashAssign, TokenType::PercentAssign] {
+            self.register_infix(ttype, Precedence::Assign, Assoc::Right, Parser::parse_assignment_expression);
+        }
+        self.register_infix(TokenType::Question, Precedence::Ternary, Assoc::Right, Parser::parse_ternary_expression);
+        self.register_infix(TokenType::Pipe, Precedence::Pipe, Assoc::Left, Parser::parse_pipe_expression);
+        let binary = [
+            (Precedence::Coalesce, &[TokenType::NullCoalesce][..]),
+            (Precedence::Equals, &[TokenType::Equal, TokenType::NotEqual]),
+            (Precedence::LessGreater, &[TokenType::LessThan, TokenType::GreaterThan, TokenType::LessThanOrEqual, TokenType::GreaterThanOrEqual]),
+            (Precedence::Range, &[TokenType::DotDot, TokenType::DotDotEq]),
+            (Precedence::Sum, &[TokenType::Plus, TokenType::Minus]),
+            (Precedence::Product, &[TokenType::Asterisk, TokenType::Slash, TokenType::Percent]),
+        ];
+        for (precedence, ttypes) in binary {
+            for &ttype in ttypes {
+                self.register_infix(ttype, precedence, Assoc::Left, Parser::parse_infix_expression);
+            }
+        }
+        self.register_infix(TokenType::LeftParen, Precedence::Call, Assoc::Left, Parser::parse_call_expression);
+        self.register_infix(TokenType::Dot, Precedence::Call, Assoc::Left, Parser::parse_member_expression);
+        self.register_infix(TokenType::LeftSquareBracket, Precedence::Index, Assoc::Left, Parser::parse_index_expression);
+    }
+
+    // Replaces whatever the token started before
+    pub fn register_prefix(&mut self, ttype: TokenType, parse: PrefixParseFn<'a>) {
+        let index = ttype as usize;
+        if self.prefix_parse_fns.len() <= index {
+            self.prefix_parse_fns.resize(index + 1, None);
+        }
+        self.prefix_parse_fns[index] = Some(parse);
+    }
+
+    // Replaces whatever the token did between two operands before. Handlers
+    // see the operator as the current token; `parse_infix_expression` is the
+    // one for plain binary operators.
+    pub fn register_infix(&mut self, ttype: TokenType, precedence: Precedence, assoc: Assoc, parse: InfixParseFn<'a>) {
+        let index = ttype as usize;
+        if self.infix_rules.len() <= index {
+            self.infix_rules.resize(index + 1, None);
+        }
+        self.infix_rules[index] = Some(InfixRule { precedence, assoc, parse });
+    }
+
+    fn infix_rule(&self, ttype: TokenType) -> Option<InfixRule<'a>> {
+        self.infix_rules.get(ttype as usize).copied().flatten()
     }
 
     pub fn next_token(&mut self) {
-        self.cur_token = self.peek_token.take();
-        self.peek_token = self.l.next();
+        self.tokens.pop_front();
+        self.fill(1);
     }
 
-    fn current_token_is(&self, t: TokenType) -> bool {
-        if self.cur_token == None {
-            return false;
+    // Lexes until the token n past the current one is buffered
+    fn fill(&mut self, n: usize) {
+        while self.tokens.len() <= n {
+            // The lexer stops after its Eof token, so keep reporting end of input
+            let next = match self.l.next() {
+                Some(token) => token,
+                None => match self.tokens.back() {
+                    Some(last) => TokenRef { ttype: TokenType::Eof, literal: "", error: None, ..*last },
+                    None => TokenRef::new(TokenType::Eof, ""),
+                },
+            };
+            if next.ttype == TokenType::DocComment {
+                self.doc_lines.push(next.literal);
+                continue;
+            }
+            if !self.doc_lines.is_empty() {
+                let lines: Vec<&str> = self.doc_lines.drain(..).map(|line| {
+                    let text = &line[3..];
+                    text.strip_prefix(' ').unwrap_or(text).trim_end()
+                }).collect();
+                self.docs.insert((next.line, next.column), lines.join("\n"));
+            }
+            self.tokens.push_back(next);
         }
-        return self.cur_token.clone().unwrap().ttype == t;
+    }
+
+    fn cur(&self) -> &TokenRef<'a> {
+        &self.tokens[0]
+    }
+
+    fn peek(&self) -> &TokenRef<'a> {
+        &self.tokens[1]
+    }
+
+    // The token n past the current one, so that peek_nth(1) is peek()
+    pub fn peek_nth(&mut self, n: usize) -> &TokenRef<'a> {
+        self.fill(n);
+        &self.tokens[n]
+    }
+
+    fn current_token_is(&self, t: TokenType) -> bool {
+        self.cur().ttype == t
     }
 
     fn peek_token_is(&self, t: TokenType) -> bool {
-        if self.peek_token == None {
-            return false;
-        }
-        return self.peek_token.clone().unwrap().ttype == t;
+        self.peek().ttype == t
     }
 
-    fn expect_peek(&mut self, t: TokenType) -> Result<(), String> {
-        return if self.peek_token_is(t.clone()) {
+    fn expect_peek(&mut self, t: TokenType) -> Result<(), ParseError> {
+        let peek = *self.peek_nth(1);
+        if peek.ttype == t {
             self.next_token();
-            Ok(())
-        } else {
-            Err(format!("expected next token to be {:?}, got {:?} instead", t, self.peek_token))
+            return Ok(());
         }
+        Err(match peek.ttype {
+            TokenType::Illegal => self.illegal(&peek),
+            TokenType::Eof => ParseError::UnexpectedEof { expected: Some(t), token: peek.to_token() },
+            _ => ParseError::UnexpectedToken { expected: t, token: peek.to_token() },
+        })
+    }
+
+    // Whatever the lexer could not read is reported as such, not as a
+    // token the parser did not expect
+    fn illegal(&self, token: &TokenRef) -> ParseError {
+        let error = token.error.expect("the lexer gives every Illegal token its error");
+        ParseError::Illegal { error, token: token.to_token() }
+    }
+
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    pub fn parse_program(&mut self) -> Result<Program, Vec<ParseError>> {
+        self.parse_statements(Vec::new(), VecDeque::new())
     }
 
-    pub fn parse_program(&mut self) -> Result<Program, String> {
-        let mut statements: Vec<Statement> = Vec::new();
+    // Parses statements after those already parsed until the input ends or
+    // the parser reaches where one of the reusable statements starts. From
+    // there on the input is what they were parsed from, so they are taken
+    // as they are instead.
+    fn parse_statements(&mut self, mut statements: Vec<Statement>, mut reusable: VecDeque<Statement>) -> Result<Program, Vec<ParseError>> {
+        while !self.current_token_is(TokenType::Eof) {
+            let at = self.cur().span();
+            while reusable.front().is_some_and(|statement| (statement.span().line, statement.span().column) < (at.line, at.column)) {
+                reusable.pop_front();
+            }
+            if reusable.front().is_some_and(|statement| statement.span() == at) {
+                statements.extend(reusable);
+                break;
+            }
+            match self.parse_statement() {
+                Ok(stmt) => statements.push(stmt),
+                Err(err) => {
+                    self.errors.push(err);
+                    self.synchronize();
+                },
+            }
+            self.next_token();
+        }
+
+        if !self.errors.is_empty() {
+            return Err(self.errors.clone());
+        }
+        Ok(Program{statements})
+    }
 
-        while self.cur_token != None && !self.current_token_is(TokenType::Eof) {
-            let stmt = self.parse_statement()?;
-            statements.push(stmt);
+    // Skip ahead after an error so parsing can resume at the next statement:
+    // either just past the next semicolon or right before a statement keyword.
+    fn synchronize(&mut self) {
+        while !self.current_token_is(TokenType::Semicolon)
+            && !self.current_token_is(TokenType::Eof)
+            && !self.peek_token_is(TokenType::Let)
+            && !self.peek_token_is(TokenType::Return)
+            && !self.peek_token_is(TokenType::For)
+            && !self.peek_token_is(TokenType::Import)
+            && !self.peek_token_is(TokenType::Struct)
+            && !self.peek_token_is(TokenType::Throw)
+        {
             self.next_token();
         }
+    }
 
-        return Ok(Program{statements});
+    fn parse_statement(&mut self) -> Result<Statement, ParseError> {
+        match self.cur().ttype {
+            TokenType::Let => self.parse_let_statement(),
+            TokenType::Return => self.parse_return_statement(),
+            TokenType::For => self.parse_for_statement(),
+            TokenType::Import if self.peek_token_is(TokenType::String) => self.parse_import_statement(),
+            TokenType::Struct => self.parse_struct_statement(),
+            TokenType::Throw => self.parse_throw_statement(),
+            TokenType::LeftCurlyBracket => self.parse_brace_statement(),
+            _ => self.parse_expression_statement(),
+        }
     }
 
-    fn parse_statement(&mut self) -> Result<Statement, String> {
-        match self.cur_token {
-            Some(ref token) => match token.ttype {
-                TokenType::Let => self.parse_let_statement(),
-                TokenType::Return => self.parse_return_statement(),
-                _ => self.parse_expression_statement(),
+    // Unlike an expression, a block statement ends at its closing brace. A
+    // hash in its place goes on like any expression statement.
+    fn parse_brace_statement(&mut self) -> Result<Statement, ParseError> {
+        let token = self.cur().to_token();
+        let expression = match self.nested(|p| p.parse_brace_expression())? {
+            Expression::Block(block) => {
+                if self.peek_token_is(TokenType::Semicolon) {
+                    self.next_token();
+                }
+                return Ok(Statement::BlockStatement(block));
             },
-            _ => Err(format!("parse_statement() not implemented for {:?}", self.cur_token)),
+            hash => self.nested(|p| p.parse_infix_chain(hash, Precedence::Lowest))?,
+        };
+
+        let semicolon = self.peek_token_is(TokenType::Semicolon);
+        if semicolon {
+            self.next_token();
+        }
+        Ok(Statement::ExpressionStatement(ExpressionStatement{token, expression, semicolon}))
+    }
+
+    // struct Name { field, ... }, the last comma optional
+    fn parse_struct_statement(&mut self) -> Result<Statement, ParseError> {
+        let token = self.cur().to_token();
+        self.expect_peek(TokenType::Identifier)?;
+        let name = Identifier{
+            token: self.cur().to_token(),
+            value: Symbol::intern(self.cur().literal),
+        };
+        self.expect_peek(TokenType::LeftCurlyBracket)?;
+
+        let mut fields = Vec::new();
+        while !self.peek_token_is(TokenType::RightCurlyBracket) {
+            self.expect_peek(TokenType::Identifier)?;
+            fields.push(Identifier{
+                token: self.cur().to_token(),
+                value: Symbol::intern(self.cur().literal),
+            });
+            if !self.peek_token_is(TokenType::RightCurlyBracket) {
+                self.expect_peek(TokenType::Comma)?;
+            }
+        }
+        self.expect_peek(TokenType::RightCurlyBracket)?;
+
+        if self.peek_token_is(TokenType::Semicolon) {
+            self.next_token();
+        }
+
+        Ok(Statement::StructStatement(StructStatement{
+            token,
+            name,
+            fields,
+        }))
+    }
+
+    fn parse_import_statement(&mut self) -> Result<Statement, ParseError> {
+        let token = self.cur().to_token();
+        self.next_token();
+        let path = self.cur().to_token();
+
+        // "lib/math" and "lib/math.mky" are both bound to math
+        let quoted = token::string_contents(&path.literal);
+        let stem = quoted.rsplit('/').next().unwrap_or_default();
+        let stem = stem.strip_suffix(".mky").unwrap_or(stem);
+        if stem.is_empty() || !stem.chars().all(|c| c.is_alphabetic() || c == '_') {
+            return Err(ParseError::InvalidModuleName(path));
+        }
+        let name = Identifier{
+            token: Token::with_position(TokenType::Identifier, stem.to_string(), path.line, path.column),
+            value: Symbol::intern(stem),
+        };
+
+        if self.peek_token_is(TokenType::Semicolon) {
+            self.next_token();
         }
+
+        Ok(Statement::ImportStatement(ImportStatement{
+            token,
+            path,
+            name,
+        }))
     }
 
-    fn parse_let_statement(&mut self) -> Result<Statement, String> {
-        let token = self.cur_token.clone().unwrap();
+    fn parse_let_statement(&mut self) -> Result<Statement, ParseError> {
+        let token = self.cur().to_token();
+        let docs = self.docs.remove(&(token.line, token.column)).map(String::into_boxed_str);
 
         self.expect_peek(TokenType::Identifier)?;
         let name = Identifier{
-            token: self.cur_token.clone().unwrap(),
-            value: self.cur_token.clone().unwrap().literal.clone()
+            token: self.cur().to_token(),
+            value: Symbol::intern(self.cur().literal)
         };
+        let annotation = self.parse_type_annotation(TokenType::Colon)?.map(Box::new);
         self.expect_peek(TokenType::Assign)?;
         self.next_token();
 
@@ -136,15 +518,17 @@ impl Parser {
             self.next_token();
         }
 
-        return Ok(Statement::LetStatement(LetStatement{
+        Ok(Statement::LetStatement(LetStatement{
             token,
             name,
+            annotation,
             value,
-        }));
+            docs,
+        }))
     }
 
-    fn parse_return_statement(&mut self) -> Result<Statement, String> {
-        let token = self.cur_token.clone().unwrap();
+    fn parse_return_statement(&mut self) -> Result<Statement, ParseError> {
+        let token = self.cur().to_token();
         self.next_token();
 
         let return_value = self.parse_expression(Precedence::Lowest)?;
@@ -153,205 +537,885 @@ impl Parser {
             self.next_token();
         }
 
-        return Ok(Statement::ReturnStatement(ReturnStatement{
+        Ok(Statement::ReturnStatement(ReturnStatement{
             token,
             return_value,
-        }));
+        }))
     }
 
-    fn parse_expression_statement(&mut self) -> Result<Statement, String> {
-        let token = self.cur_token.clone().unwrap();
-        let expression = self.parse_expression(Precedence::Lowest)?;
+    fn parse_throw_statement(&mut self) -> Result<Statement, ParseError> {
+        let token = self.cur().to_token();
+        self.next_token();
+
+        let value = self.parse_expression(Precedence::Lowest)?;
 
         if self.peek_token_is(TokenType::Semicolon) {
             self.next_token();
         }
 
-        return Ok(Statement::ExpressionStatement(ExpressionStatement{
+        Ok(Statement::ThrowStatement(ThrowStatement{
+            token,
+            value,
+        }))
+    }
+
+    fn parse_expression_statement(&mut self) -> Result<Statement, ParseError> {
+        let token = self.cur().to_token();
+        let expression = self.parse_expression(Precedence::Lowest)?;
+
+        let semicolon = self.peek_token_is(TokenType::Semicolon);
+        if semicolon {
+            self.next_token();
+        }
+
+        Ok(Statement::ExpressionStatement(ExpressionStatement{
             token,
             expression,
-        }));
+            semicolon,
+        }))
     }
 
-    fn parse_expression(&mut self, precedence: Precedence) -> Result<Expression, String> {
-        let current = self.cur_token.clone().unwrap();
-        let mut left: Expression = match current.ttype {
-            TokenType::Identifier => {
-                let mut left = Expression::Identifier(Identifier{
-                    token: current.clone(),
-                    value: current.literal.clone(),
-                });
-                if is_postfix_op(self.peek_token.clone().unwrap().ttype) {
-                    self.next_token();
-                    left = self.parse_postfix_expression(left)?;
-                }
-                left
-            },
-            TokenType::Integer => {
-                let mut left = Expression::IntegerLiteral(IntegerLiteral{token: current.clone(), value: current.literal.parse::<i64>().unwrap()});
-                if is_postfix_op(self.peek_token.clone().unwrap().ttype) {
-                    self.next_token();
-                    left = self.parse_postfix_expression(left)?;
-                }
-                left
-            },
-            TokenType::String => {
-                Expression::StringLiteral(current.clone())
-            },
-            TokenType::True | TokenType::False => {
-                Expression::BooleanLiteral(current.clone())
-            },
-            TokenType::Bang | TokenType::Minus | TokenType::Increment | TokenType::Decrement => {
-                let mut left = self.parse_prefix_expression()?;
-                match current.ttype {
-                    TokenType::Minus | TokenType::Increment | TokenType::Decrement => {
-                        if is_postfix_op(self.peek_token.clone().unwrap().ttype) {
-                            self.next_token();
-                            left = self.parse_postfix_expression(left)?;
-                        }
-                        left
-                    },
-                    _ => left,
-                }
-            },
-            _ => {return Err(format!("parse_expression() not implemented for {:?}", current));},
-        };
+    fn parse_block_statement(&mut self) -> Result<BlockStatement, ParseError> {
+        let token = self.cur().to_token();
+        self.parse_block_rest(token, Vec::new())
+    }
 
-        while !self.peek_token_is(TokenType::Semicolon) && precedence < self.peek_precedence() {
-            // println!("peek_precedence: {:?}", self.peek_precedence());
-            if is_infix_op(self.peek_token.clone().unwrap().ttype) {
-                self.next_token();
-                left = self.parse_infix_expression(left)?;
+    // The statements after those already parsed, up to the closing brace
+    fn parse_block_rest(&mut self, token: Token, mut statements: Vec<Statement>) -> Result<BlockStatement, ParseError> {
+        self.next_token();
+
+        while !self.current_token_is(TokenType::RightCurlyBracket) {
+            if self.current_token_is(TokenType::Eof) {
+                return Err(ParseError::UnexpectedEof {
+                    expected: Some(TokenType::RightCurlyBracket),
+                    token: self.cur().to_token(),
+                });
             }
+            statements.push(self.parse_statement()?);
+            self.next_token();
         }
 
-        return Ok(left);
+        Ok(BlockStatement{
+            token,
+            statements,
+        })
     }
 
-    fn parse_prefix_expression(&mut self) -> Result<Expression, String> {
-        // Cannot perform prefix operations on a string
-        if self.peek_token.clone().unwrap().ttype == TokenType::String {
-            return Err(format!("parse_prefix_expression() not implemented for {:?}", self.cur_token));
+    fn parse_for_statement(&mut self) -> Result<Statement, ParseError> {
+        let token = self.cur().to_token();
+        self.expect_peek(TokenType::LeftParen)?;
+        self.next_token();
+
+        if self.current_token_is(TokenType::Identifier) && self.peek_token_is(TokenType::In) {
+            let variable = Identifier{
+                token: self.cur().to_token(),
+                value: Symbol::intern(self.cur().literal),
+            };
+            self.next_token();
+            self.next_token();
+
+            let iterable = self.parse_expression(Precedence::Lowest)?;
+            self.expect_peek(TokenType::RightParen)?;
+            self.expect_peek(TokenType::LeftCurlyBracket)?;
+            let body = self.parse_block_statement()?;
+
+            return Ok(Statement::ForInStatement(ForInStatement{
+                token,
+                variable,
+                iterable,
+                body,
+            }));
         }
 
-        let token = self.cur_token.clone().unwrap();
+        let init = match self.cur().ttype {
+            TokenType::Let => self.parse_let_statement()?,
+            _ => self.parse_expression_statement()?,
+        };
+        if !self.current_token_is(TokenType::Semicolon) {
+            self.expect_peek(TokenType::Semicolon)?;
+        }
         self.next_token();
-        let right = self.parse_expression(Precedence::Prefix)?;
-        return Ok(Expression::Prefix(Prefix{
-            operator: token,
-            right: Box::new(right),
-        }));
-    }
 
-    fn parse_infix_expression(&mut self, left: Expression) -> Result<Expression, String> {
-        let token = self.cur_token.clone().unwrap();
-        let precedence = self.current_precedence();
+        let condition = self.parse_expression(Precedence::Lowest)?;
+        self.expect_peek(TokenType::Semicolon)?;
         self.next_token();
-        let right = self.parse_expression(precedence)?;
-        return Ok(Expression::Infix(Infix{
-            left: Box::new(left),
-            operator: token,
-            right: Box::new(right),
-        }));
-    }
 
-    fn parse_postfix_expression(&mut self, left: Expression) -> Result<Expression, String> {
-        let token = self.cur_token.clone().unwrap();
-        return Ok(Expression::Postfix(Postfix{
-            left: Box::new(left),
-            operator: token,
-        }));
-    }
+        let update = self.parse_expression(Precedence::Lowest)?;
+        self.expect_peek(TokenType::RightParen)?;
+        self.expect_peek(TokenType::LeftCurlyBracket)?;
+        let body = self.parse_block_statement()?;
 
-    fn peek_precedence(&self) -> Precedence {
-        return precedence_for_op(self.peek_token.clone().unwrap().ttype);
+        Ok(Statement::ForStatement(ForStatement{
+            token,
+            init: Box::new(init),
+            condition,
+            update,
+            body,
+        }))
     }
 
-    fn current_precedence(&self) -> Precedence {
-        return precedence_for_op(self.cur_token.clone().unwrap().ttype);
+    // Every kind of nesting, from parentheses to function bodies, goes
+    // through here, so this is where the depth is checked
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        self.deeper()?;
+        let result = parse(self);
+        self.depth -= 1;
+        result
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn deeper(&mut self) -> Result<(), ParseError> {
+        if self.depth >= self.max_depth {
+            return Err(ParseError::TooDeep { limit: self.max_depth, token: self.cur().to_token() });
+        }
+        self.depth += 1;
+        Ok(())
+    }
 
-    #[test]
-    fn test_let_statements() {
-        let input = String::from(r#"
-        let x = 5;
-        let y = 10;
-        let foobar = 838383;
-        "#);
+    fn parse_expression(&mut self, precedence: Precedence) -> Result<Expression, ParseError> {
+        self.nested(|p| p.parse_expression_inner(precedence))
+    }
 
-        let l = Lexer::new(input);
-        let mut p = Parser::new(l);
+    fn parse_expression_inner(&mut self, precedence: Precedence) -> Result<Expression, ParseError> {
+        let left = self.parse_operand()?;
+        self.parse_infix_chain(left, precedence)
+    }
 
-        let program = p.parse_program();
-        assert!(program.is_ok(), "parse_program() returned an error: {:?}", program.err().unwrap());
+    // The operators binding tighter than precedence that follow left. Each
+    // one puts what came before a level further down the tree, which the
+    // passes over it recurse into as the parser does into nesting, so a
+    // long chain counts towards the depth as much as nesting would.
+    fn parse_infix_chain(&mut self, left: Expression, precedence: Precedence) -> Result<Expression, ParseError> {
+        let depth = self.depth;
+        let result = self.parse_infix_operators(left, precedence);
+        self.depth = depth;
+        result
+    }
 
-        let program = program.unwrap();
-        assert_eq!(program.statements.len(), 3, "program.Statements does not contain 3 statements. got={}", program.statements.len());
+    fn parse_infix_operators(&mut self, mut left: Expression, precedence: Precedence) -> Result<Expression, ParseError> {
+        loop {
+            // A postfix `?` binds like a call, so `-f()?` negates f()?
+            if self.peek_token_is(TokenType::Question) && precedence < Precedence::Call && self.question_is_postfix() {
+                self.next_token();
+                self.deeper()?;
+                left = Expression::Propagate(Propagate{
+                    value: Box::new(left),
+                    operator: self.cur().to_token(),
+                });
+                continue;
+            }
+            if self.peek_token_is(TokenType::Semicolon) || precedence >= self.peek_precedence() {
+                break;
+            }
+            let Some(rule) = self.infix_rule(self.peek().ttype) else { break };
+            self.next_token();
+            self.deeper()?;
+            left = (rule.parse)(self, left)?;
+        }
 
-        assert_eq!(program.statements[0].string(), "let x = 5;".to_string());
-        assert_eq!(program.statements[1].string(), "let y = 10;".to_string());
-        assert_eq!(program.statements[2].string(), "let foobar = 838383;".to_string());
+        Ok(left)
     }
 
-    #[test]
-    fn test_return_statements() {
-        let input = String::from(r#"
-        return 5;
-        return 10;
-        return 993322;
-        "#);
+    // Whether the peeked `?` ends an operand rather than starting a
+    // conditional. What follows decides when it can only start an
+    // expression or only continue one. Tokens that can do both, like `-` or
+    // `[`, start a conditional only if a `:` comes before the expression ends.
+    fn question_is_postfix(&mut self) -> bool {
+        let next = self.peek_nth(2).ttype;
+        if self.prefix_parse_fns.get(next as usize).copied().flatten().is_none() {
+            return true;
+        }
+        if self.infix_rule(next).is_none() {
+            return false;
+        }
+        let mut depth = 0usize;
+        let mut n = 2;
+        loop {
+            match self.peek_nth(n).ttype {
+                TokenType::LeftParen | TokenType::LeftSquareBracket | TokenType::LeftCurlyBracket => depth += 1,
+                TokenType::RightParen | TokenType::RightSquareBracket | TokenType::RightCurlyBracket if depth == 0 => return true,
+                TokenType::RightParen | TokenType::RightSquareBracket | TokenType::RightCurlyBracket => depth -= 1,
+                TokenType::Colon if depth == 0 => return false,
+                TokenType::Semicolon | TokenType::Comma | TokenType::FatArrow if depth == 0 => return true,
+                TokenType::Let | TokenType::Return | TokenType::For | TokenType::Struct | TokenType::Import | TokenType::Throw
+                    if depth == 0 => return true,
+                TokenType::Eof => return true,
+                _ => {},
+            }
+            n += 1;
+        }
+    }
 
-        let l = Lexer::new(input);
-        let mut p = Parser::new(l);
+    // The expression an operator chain starts from. Kept apart from the
+    // operators so each recursive call holds a smaller stack frame.
+    fn parse_operand(&mut self) -> Result<Expression, ParseError> {
+        let parse = match self.prefix_parse_fns.get(self.cur().ttype as usize).copied().flatten() {
+            Some(parse) => parse,
+            None => return Err(match self.cur().ttype {
+                TokenType::Eof => ParseError::UnexpectedEof { expected: None, token: self.cur().to_token() },
+                TokenType::Illegal => self.illegal(self.cur()),
+                _ => ParseError::NoPrefixParse(self.cur().to_token()),
+            }),
+        };
+        let mut left = parse(self)?;
+        if is_postfix_op(self.peek().ttype) {
+            self.next_token();
+            left = self.parse_postfix_expression(left)?;
+        }
+        Ok(left)
+    }
 
-        let program = p.parse_program();
-        assert!(program.is_ok(), "parse_program() returned an error: {:?}", program.err().unwrap());
+    fn parse_identifier(&mut self) -> Result<Expression, ParseError> {
+        if self.struct_literals && self.peek_token_is(TokenType::LeftCurlyBracket) {
+            return self.parse_struct_literal();
+        }
+        Ok(Expression::Identifier(Identifier{
+            token: self.cur().to_token(),
+            value: Symbol::intern(self.cur().literal),
+        }))
+    }
 
-        let program = program.unwrap();
-        assert_eq!(program.statements.len(), 3, "program.Statements does not contain 3 statements. got={}", program.statements.len());
+    fn parse_string_literal(&mut self) -> Result<Expression, ParseError> {
+        Ok(Expression::StringLiteral(self.cur().to_token()))
+    }
 
-        assert_eq!(program.statements[0].string(), "return 5;");
-        assert_eq!(program.statements[1].string(), "return 10;".to_string());
-        assert_eq!(program.statements[2].string(), "return 993322;".to_string());
+    // Each `${...}` is parsed by a parser of its own, placed where the code
+    // sits in the source and sharing what is left of the nesting limit
+    fn parse_interpolated_string(&mut self) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        let pieces = lexer::string_parts(&token.literal).ok_or_else(|| ParseError::Illegal { error: lexer::LexError::UnterminatedString, token: token.clone() })?;
+        let mut parts = Vec::new();
+        for piece in pieces {
+            match piece {
+                lexer::StringPart::Text(text) => parts.push(StringPart::Text(text.to_string())),
+                lexer::StringPart::Code { source, offset } => {
+                    // Strings may span lines, so count from the last newline before the code
+                    let before = &token.literal[..offset];
+                    let (line, column) = match before.rfind('\n') {
+                        Some(newline) => (token.line + before.matches('\n').count(), before[newline + 1..].chars().count() + 1),
+                        None => (token.line, token.column + before.chars().count()),
+                    };
+                    let l = Lexer::new(source).starting_at(line, column);
+                    let mut parser = Parser::with_max_depth(l, self.max_depth - self.depth);
+                    let expression = parser.parse_expression(Precedence::Lowest)?;
+                    parser.expect_peek(TokenType::Eof)?;
+                    parts.push(StringPart::Expression(expression));
+                },
+            }
+        }
+        Ok(Expression::InterpolatedString(InterpolatedString{token, parts}))
     }
 
-    #[test]
-    fn test_identifier_expression() {
-        let input = String::from("foobar;");
+    // The lexer only emits well-formed character literals
+    fn parse_char_literal(&mut self) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        let value = lexer::char_literal(&token.literal).map_err(|error| ParseError::Illegal { error, token: token.clone() })?;
+        Ok(Expression::CharLiteral(CharLiteral{token, value}))
+    }
 
-        let l = Lexer::new(input);
-        let mut p = Parser::new(l);
+    fn parse_boolean_literal(&mut self) -> Result<Expression, ParseError> {
+        Ok(Expression::BooleanLiteral(self.cur().to_token()))
+    }
 
-        let program = p.parse_program();
-        assert!(program.is_ok(), "parse_program() returned an error: {:?}", program.err().unwrap());
+    fn parse_null_literal(&mut self) -> Result<Expression, ParseError> {
+        Ok(Expression::NullLiteral(self.cur().to_token()))
+    }
 
-        let program = program.unwrap();
-        assert_eq!(program.statements.len(), 1, "program.Statements does not contain 1 statements. got={}", program.statements.len());
+    fn parse_array_literal(&mut self) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        let elements = self.parse_expression_list(TokenType::RightSquareBracket)?;
+        Ok(Expression::ArrayLiteral(ArrayLiteral{token, elements}))
+    }
 
-        if let Statement::ExpressionStatement(expr) = &program.statements[0] {
-            assert_eq!(expr.string(), "foobar;");
-        } else {
-            panic!("program.statements[0] is not ast.ExpressionStatement. got={:?}", program.statements[0]);
+    // Calling a member is a method call, looked up on the object when it runs
+    fn parse_call_expression(&mut self, function: Expression) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        let arguments = self.parse_expression_list(TokenType::RightParen)?;
+        if let Expression::Member(member) = function {
+            return Ok(Expression::MethodCall(MethodCall{
+                token,
+                object: member.object,
+                method: member.property,
+                arguments,
+            }));
         }
+        Ok(Expression::Call(Call{
+            token,
+            function: Box::new(function),
+            arguments,
+        }))
     }
 
-    #[test]
-    fn test_literal_expression() {
-        let input = String::from("5; \"test\";");
+    fn parse_member_expression(&mut self, object: Expression) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        self.expect_peek(TokenType::Identifier)?;
+        Ok(Expression::Member(Member{
+            object: Box::new(object),
+            token,
+            property: Identifier{
+                token: self.cur().to_token(),
+                value: Symbol::intern(self.cur().literal),
+            },
+        }))
+    }
 
-        let l = Lexer::new(input);
-        let mut p = Parser::new(l);
+    fn parse_index_expression(&mut self, left: Expression) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        self.next_token();
+        let index = self.parse_expression(Precedence::Lowest)?;
+        self.expect_peek(TokenType::RightSquareBracket)?;
+        Ok(Expression::Index(Index{
+            token,
+            left: Box::new(left),
+            index: Box::new(index),
+        }))
+    }
 
-        let program = p.parse_program();
-        assert!(program.is_ok(), "parse_program() returned an error: {:?}", program.err().unwrap());
+    // Parentheses only steer precedence and leave no node behind
+    fn parse_grouped_expression(&mut self) -> Result<Expression, ParseError> {
+        self.next_token();
+        let struct_literals = std::mem::replace(&mut self.struct_literals, true);
+        let expression = self.parse_expression(Precedence::Lowest);
+        self.struct_literals = struct_literals;
+        let expression = expression?;
+        self.expect_peek(TokenType::RightParen)?;
+        Ok(expression)
+    }
 
-        let program = program.unwrap();
-        assert_eq!(program.statements.len(), 2, "program.Statements does not contain 1 statements. got={}", program.statements.len());
+    // Name { field: value, ... }, the last comma optional
+    fn parse_struct_literal(&mut self) -> Result<Expression, ParseError> {
+        let name = Identifier{
+            token: self.cur().to_token(),
+            value: Symbol::intern(self.cur().literal),
+        };
+        self.next_token();
+
+        let mut fields = Vec::new();
+        while !self.peek_token_is(TokenType::RightCurlyBracket) {
+            self.expect_peek(TokenType::Identifier)?;
+            let field = Identifier{
+                token: self.cur().to_token(),
+                value: Symbol::intern(self.cur().literal),
+            };
+            self.expect_peek(TokenType::Colon)?;
+            self.next_token();
+            fields.push((field, self.parse_expression(Precedence::Lowest)?));
+            if !self.peek_token_is(TokenType::RightCurlyBracket) {
+                self.expect_peek(TokenType::Comma)?;
+            }
+        }
+        self.expect_peek(TokenType::RightCurlyBracket)?;
+
+        Ok(Expression::StructLiteral(StructLiteral{name, fields}))
+    }
+
+    fn parse_integer_literal(&mut self) -> Result<Expression, ParseError> {
+        let current = self.cur().to_token();
+        match current.literal.parse::<i64>() {
+            Ok(value) => Ok(Expression::IntegerLiteral(IntegerLiteral{token: current, value})),
+            #[cfg(feature = "bigint")]
+            Err(_) => Ok(Expression::BigIntegerLiteral(current)),
+            #[cfg(not(feature = "bigint"))]
+            Err(_) => Err(ParseError::IntegerOutOfRange { literal: current.literal.clone(), token: current }),
+        }
+    }
+
+    // The lexer only makes digits with a fraction, which always parse, too
+    // many of them as infinity
+    fn parse_float_literal(&mut self) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        let value = token.literal.parse::<f64>().unwrap_or(f64::INFINITY);
+        Ok(Expression::FloatLiteral(FloatLiteral{token, value}))
+    }
+
+    fn parse_prefix_expression(&mut self) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        self.next_token();
+        // The smallest i64 has no positive counterpart to negate, so it is
+        // read as one literal, sign and all
+        if token.ttype == TokenType::Minus && self.cur().ttype == TokenType::Integer && self.cur().literal.parse() == Ok(i64::MIN.unsigned_abs()) {
+            let literal = Token::with_position(TokenType::Integer, format!("-{}", self.cur().literal), token.line, token.column);
+            return self.parse_infix_chain(Expression::IntegerLiteral(IntegerLiteral{token: literal, value: i64::MIN}), Precedence::Prefix);
+        }
+        let right = self.parse_expression(Precedence::Prefix)?;
+        // Cannot perform prefix operations on a string, even a parenthesised
+        // one, which would print without its parens
+        if let Expression::StringLiteral(operand) = right {
+            return Err(ParseError::InvalidPrefixOperand { operator: token, operand });
+        }
+        if is_postfix_op(token.ttype) && !matches!(right, Expression::Identifier(_)) {
+            return Err(ParseError::InvalidIncrementTarget { target: right.to_string(), token });
+        }
+        Ok(Expression::Prefix(Prefix{
+            operator: token,
+            right: Box::new(right),
+        }))
+    }
+
+    pub fn parse_infix_expression(&mut self, left: Expression) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        let precedence = self.right_precedence();
+        self.next_token();
+        let right = self.parse_expression(precedence)?;
+        // Ranges parse like any other operator but get a node of their own
+        if matches!(token.ttype, TokenType::DotDot | TokenType::DotDotEq) {
+            return Ok(Expression::RangeLiteral(RangeLiteral{
+                token,
+                start: Box::new(left),
+                end: Box::new(right),
+            }));
+        }
+        Ok(Expression::Infix(Infix{
+            left: Box::new(left),
+            operator: token,
+            right: Box::new(right),
+        }))
+    }
+
+    // `x |> f` is `f(x)` and `x |> f(y)` is `f(x, y)`, so pipes leave only
+    // calls behind
+    fn parse_pipe_expression(&mut self, left: Expression) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        let precedence = self.right_precedence();
+        self.next_token();
+        Ok(match self.parse_expression(precedence)? {
+            Expression::Call(mut call) => {
+                call.arguments.insert(0, left);
+                Expression::Call(call)
+            },
+            Expression::MethodCall(mut call) => {
+                call.arguments.insert(0, left);
+                Expression::MethodCall(call)
+            },
+            function => Expression::Call(Call{
+                token,
+                function: Box::new(function),
+                arguments: vec![left],
+            }),
+        })
+    }
+
+    // Right-associative, so `a ? b : c ? d : e` picks between b and the
+    // second conditional
+    fn parse_ternary_expression(&mut self, condition: Expression) -> Result<Expression, ParseError> {
+        self.next_token();
+        let if_true = self.parse_expression(Precedence::Lowest)?;
+        self.expect_peek(TokenType::Colon)?;
+        self.next_token();
+        let if_false = self.parse_expression(Precedence::Assign)?;
+        Ok(Expression::Ternary(Ternary{
+            condition: Box::new(condition),
+            if_true: Box::new(if_true),
+            if_false: Box::new(if_false),
+        }))
+    }
+
+    // `{` opens a hash when it is empty or its first expression is followed
+    // by a colon, and a block when it starts with a statement keyword or its
+    // first expression is followed by a semicolon or the closing brace
+    fn parse_brace_expression(&mut self) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        let keyword = match self.peek_nth(1).ttype {
+            TokenType::Let | TokenType::Return | TokenType::For | TokenType::Struct | TokenType::Throw => true,
+            TokenType::Import => self.peek_nth(2).ttype == TokenType::String,
+            TokenType::RightCurlyBracket => {
+                self.next_token();
+                return Ok(Expression::HashLiteral(HashLiteral{token, pairs: Vec::new()}));
+            },
+            _ => false,
+        };
+        if keyword {
+            return Ok(Expression::Block(self.parse_block_rest(token, Vec::new())?));
+        }
+
+        self.next_token();
+        let mut first = ExpressionStatement{token: self.cur().to_token(), expression: self.parse_expression(Precedence::Lowest)?, semicolon: false};
+        match self.peek().ttype {
+            TokenType::Semicolon => {
+                self.next_token();
+                first.semicolon = true;
+            },
+            TokenType::RightCurlyBracket => {},
+            _ => return self.parse_hash_literal(token, first.expression),
+        }
+        Ok(Expression::Block(self.parse_block_rest(token, vec![Statement::ExpressionStatement(first)])?))
+    }
+
+    // The pairs after the first key, the last comma optional
+    fn parse_hash_literal(&mut self, token: Token, first: Expression) -> Result<Expression, ParseError> {
+        let mut pairs = Vec::new();
+        let mut key = first;
+        loop {
+            self.expect_peek(TokenType::Colon)?;
+            self.next_token();
+            let value = self.parse_expression(Precedence::Lowest)?;
+            pairs.push((key, value));
+            if self.peek_token_is(TokenType::RightCurlyBracket) {
+                break;
+            }
+            self.expect_peek(TokenType::Comma)?;
+            if self.peek_token_is(TokenType::RightCurlyBracket) {
+                break;
+            }
+            self.next_token();
+            key = self.parse_expression(Precedence::Lowest)?;
+        }
+        self.expect_peek(TokenType::RightCurlyBracket)?;
+        Ok(Expression::HashLiteral(HashLiteral{token, pairs}))
+    }
+
+    // match subject { pattern => value, ... }, the last comma optional
+    fn parse_match_expression(&mut self) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        self.next_token();
+        let struct_literals = std::mem::replace(&mut self.struct_literals, false);
+        let subject = self.parse_expression(Precedence::Lowest);
+        self.struct_literals = struct_literals;
+        let subject = subject?;
+        self.expect_peek(TokenType::LeftCurlyBracket)?;
+
+        let mut arms = Vec::new();
+        while !self.peek_token_is(TokenType::RightCurlyBracket) {
+            self.next_token();
+            let pattern = self.parse_pattern()?;
+            self.expect_peek(TokenType::FatArrow)?;
+            self.next_token();
+            let body = self.parse_expression(Precedence::Lowest)?;
+            arms.push(MatchArm{pattern, body});
+            if !self.peek_token_is(TokenType::RightCurlyBracket) {
+                self.expect_peek(TokenType::Comma)?;
+            }
+        }
+        self.expect_peek(TokenType::RightCurlyBracket)?;
+
+        Ok(Expression::Match(MatchExpression{
+            token,
+            subject: Box::new(subject),
+            arms,
+        }))
+    }
+
+    // try { body } catch (name) { handler }
+    fn parse_try_expression(&mut self) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        self.expect_peek(TokenType::LeftCurlyBracket)?;
+        let body = self.parse_block_statement()?;
+        self.expect_peek(TokenType::Catch)?;
+        self.expect_peek(TokenType::LeftParen)?;
+        self.expect_peek(TokenType::Identifier)?;
+        let parameter = Identifier{
+            token: self.cur().to_token(),
+            value: Symbol::intern(self.cur().literal),
+        };
+        self.expect_peek(TokenType::RightParen)?;
+        self.expect_peek(TokenType::LeftCurlyBracket)?;
+        let handler = self.parse_block_statement()?;
+
+        Ok(Expression::Try(Box::new(TryExpression{
+            token,
+            body,
+            parameter,
+            handler,
+        })))
+    }
+
+    fn parse_pattern(&mut self) -> Result<Pattern, ParseError> {
+        if self.current_token_is(TokenType::Identifier) && self.cur().literal == "_" {
+            return Ok(Pattern::Wildcard(self.cur().to_token()));
+        }
+        let start = self.parse_pattern_literal()?;
+        if !self.peek_token_is(TokenType::DotDot) {
+            return Ok(Pattern::Literal(start));
+        }
+
+        // Only integers and characters have an order to range over
+        let ordered = |literal: &Expression| match literal {
+            Expression::Prefix(prefix) => !matches!(*prefix.right, Expression::FloatLiteral(_)),
+            _ => matches!(literal, Expression::IntegerLiteral(_) | Expression::BigIntegerLiteral(_) | Expression::CharLiteral(_)),
+        };
+        if !ordered(&start) {
+            return Err(ParseError::InvalidPattern(self.peek().to_token()));
+        }
+        self.next_token();
+        self.next_token();
+        let end = self.parse_pattern_literal()?;
+        if !ordered(&end) {
+            return Err(ParseError::InvalidPattern(self.cur().to_token()));
+        }
+        Ok(Pattern::Range{start, end})
+    }
+
+    fn parse_pattern_literal(&mut self) -> Result<Expression, ParseError> {
+        match self.cur().ttype {
+            TokenType::Integer | TokenType::Float | TokenType::String | TokenType::RawString | TokenType::Char | TokenType::True | TokenType::False | TokenType::Null => {
+                self.parse_operand()
+            },
+            TokenType::Minus if self.peek_token_is(TokenType::Integer) || self.peek_token_is(TokenType::Float) => self.parse_prefix_expression(),
+            TokenType::Eof => Err(ParseError::UnexpectedEof { expected: None, token: self.cur().to_token() }),
+            _ => Err(ParseError::InvalidPattern(self.cur().to_token())),
+        }
+    }
+
+    fn parse_function_literal(&mut self) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        self.expect_peek(TokenType::LeftParen)?;
+        let (parameters, parameter_types) = self.parse_function_parameters()?;
+        let return_type = self.parse_type_annotation(TokenType::Arrow)?.map(Box::new);
+        self.expect_peek(TokenType::LeftCurlyBracket)?;
+        let struct_literals = std::mem::replace(&mut self.struct_literals, true);
+        let body = self.parse_block_statement();
+        self.struct_literals = struct_literals;
+        let body = body?;
+
+        Ok(Expression::FunctionLiteral(FunctionLiteral{
+            token,
+            parameters,
+            parameter_types,
+            return_type,
+            body,
+        }))
+    }
+
+    // An optional type name after the given token, as in `x: int` or
+    // `-> bool`. Types are names, or `fn` for any function; which ones
+    // exist is up to the checker.
+    fn parse_type_annotation(&mut self, marker: TokenType) -> Result<Option<Identifier>, ParseError> {
+        if !self.peek_token_is(marker) {
+            return Ok(None);
+        }
+        self.next_token();
+        if self.peek_token_is(TokenType::Fn) {
+            self.next_token();
+        } else {
+            self.expect_peek(TokenType::Identifier)?;
+        }
+        let token = self.cur().to_token();
+        Ok(Some(Identifier{
+            value: Symbol::intern(&token.literal),
+            token,
+        }))
+    }
+
+    // import("path") or import "path" where an expression is expected
+    fn parse_import_expression(&mut self) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        let parenthesized = self.peek_token_is(TokenType::LeftParen);
+        if parenthesized {
+            self.next_token();
+        }
+        self.expect_peek(TokenType::String)?;
+        let path = self.cur().to_token();
+        if parenthesized {
+            self.expect_peek(TokenType::RightParen)?;
+        }
+
+        Ok(Expression::Import(Import{
+            token,
+            path,
+        }))
+    }
+
+    // The parameters and, alongside, the type of each if it has one
+    fn parse_function_parameters(&mut self) -> Result<(Vec<Identifier>, Vec<Option<Identifier>>), ParseError> {
+        let mut parameters: Vec<Identifier> = Vec::new();
+        let mut types = Vec::new();
+
+        if self.peek_token_is(TokenType::RightParen) {
+            self.next_token();
+            return Ok((parameters, types));
+        }
+
+        loop {
+            self.expect_peek(TokenType::Identifier)?;
+            let token = self.cur().to_token();
+            parameters.push(Identifier{
+                value: Symbol::intern(&token.literal),
+                token,
+            });
+            types.push(self.parse_type_annotation(TokenType::Colon)?);
+            if !self.peek_token_is(TokenType::Comma) {
+                break;
+            }
+            self.next_token();
+        }
+
+        self.expect_peek(TokenType::RightParen)?;
+        Ok((parameters, types))
+    }
+
+    // Parses comma-separated expressions up to and including the closing token,
+    // starting with the opening token as the current token
+    fn parse_expression_list(&mut self, end: TokenType) -> Result<Vec<Expression>, ParseError> {
+        let mut list: Vec<Expression> = Vec::new();
+
+        if self.peek_token_is(end) {
+            self.next_token();
+            return Ok(list);
+        }
+
+        self.next_token();
+        list.push(self.parse_expression(Precedence::Lowest)?);
+
+        while self.peek_token_is(TokenType::Comma) {
+            self.next_token();
+            self.next_token();
+            list.push(self.parse_expression(Precedence::Lowest)?);
+        }
+
+        self.expect_peek(end)?;
+        Ok(list)
+    }
+
+    // Assignment is right-associative, so `a = b = 5` assigns 5 to both.
+    // Compound forms like `a += 1` share the node and keep their operator token.
+    // Besides a name, the target can be an element or member of what a name
+    // holds, as in `a[0] = 1` or `p.x += 1`.
+    fn parse_assignment_expression(&mut self, target: Expression) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        if !assignable(&target) {
+            return Err(ParseError::InvalidAssignmentTarget { target: target.to_string(), token });
+        }
+
+        let precedence = self.right_precedence();
+        self.next_token();
+        let value = self.parse_expression(precedence)?;
+        Ok(Expression::Assignment(Assignment{
+            target: Box::new(target),
+            token,
+            value: Box::new(value),
+        }))
+    }
+
+    // Like assignment, stepping only makes sense on a name, though here it has
+    // to be the name itself
+    fn parse_postfix_expression(&mut self, left: Expression) -> Result<Expression, ParseError> {
+        let token = self.cur().to_token();
+        if !matches!(left, Expression::Identifier(_)) {
+            return Err(ParseError::InvalidIncrementTarget { target: left.to_string(), token });
+        }
+        Ok(Expression::Postfix(Postfix{
+            left: Box::new(left),
+            operator: token,
+        }))
+    }
+
+    fn peek_precedence(&self) -> Precedence {
+        self.infix_rule(self.peek().ttype).map_or(Precedence::Lowest, |rule| rule.precedence)
+    }
+
+    // What the operand to the right of the current operator is parsed at, so
+    // that operators of the same level group to the left or to the right
+    fn right_precedence(&self) -> Precedence {
+        match self.infix_rule(self.cur().ttype) {
+            Some(InfixRule { precedence, assoc: Assoc::Left, .. }) => precedence,
+            Some(InfixRule { precedence, assoc: Assoc::Right, .. }) => precedence.below(),
+            None => Precedence::Lowest,
+        }
+    }
+}
+
+// A name, or indexes and members reached from one
+fn assignable(target: &Expression) -> bool {
+    match target {
+        Expression::Identifier(_) => true,
+        Expression::Index(index) => assignable(&index.left),
+        Expression::Member(member) => assignable(&member.object),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_let_statements() {
+        let input = String::from(r#"
+        let x = 5;
+        let y = 10;
+        let foobar = 838383;
+        "#);
+
+        let l = Lexer::new(&input);
+        let mut p = Parser::new(l);
+
+        let program = p.parse_program();
+        assert!(program.is_ok(), "parse_program() returned an error: {:?}", program.err().unwrap());
+
+        let program = program.unwrap();
+        assert_eq!(program.statements.len(), 3, "program.Statements does not contain 3 statements. got={}", program.statements.len());
+
+        assert_eq!(program.statements[0].to_string(), "let x = 5;".to_string());
+        assert_eq!(program.statements[1].to_string(), "let y = 10;".to_string());
+        assert_eq!(program.statements[2].to_string(), "let foobar = 838383;".to_string());
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let input = "/// Adds one.\n///\n///   Indented stays indented.  \nlet inc = fn(x) { x + 1 };\n// plain\nlet y = 2;\n/// Orphaned\nputs(y);\n/// Last\nlet z = 3;";
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let docs: Vec<Option<&str>> = program.statements.iter().filter_map(|statement| match statement {
+            Statement::LetStatement(statement) => Some(statement.docs.as_deref()),
+            _ => None,
+        }).collect();
+        assert_eq!(docs, vec![Some("Adds one.\n\n  Indented stays indented."), None, Some("Last")]);
+        // and doc comments are dropped from the output like others
+        assert_eq!(program.to_string(), "let inc = fn(x) { (x + 1) };let y = 2;puts(y);let z = 3;");
+    }
+
+    #[test]
+    fn test_return_statements() {
+        let input = String::from(r#"
+        return 5;
+        return 10;
+        return 993322;
+        "#);
+
+        let l = Lexer::new(&input);
+        let mut p = Parser::new(l);
+
+        let program = p.parse_program();
+        assert!(program.is_ok(), "parse_program() returned an error: {:?}", program.err().unwrap());
+
+        let program = program.unwrap();
+        assert_eq!(program.statements.len(), 3, "program.Statements does not contain 3 statements. got={}", program.statements.len());
+
+        assert_eq!(program.statements[0].to_string(), "return 5;");
+        assert_eq!(program.statements[1].to_string(), "return 10;".to_string());
+        assert_eq!(program.statements[2].to_string(), "return 993322;".to_string());
+    }
+
+    #[test]
+    fn test_identifier_expression() {
+        let input = String::from("foobar;");
+
+        let l = Lexer::new(&input);
+        let mut p = Parser::new(l);
+
+        let program = p.parse_program();
+        assert!(program.is_ok(), "parse_program() returned an error: {:?}", program.err().unwrap());
+
+        let program = program.unwrap();
+        assert_eq!(program.statements.len(), 1, "program.Statements does not contain 1 statements. got={}", program.statements.len());
+
+        if let Statement::ExpressionStatement(expr) = &program.statements[0] {
+            assert_eq!(expr.to_string(), "foobar;");
+        } else {
+            panic!("program.statements[0] is not ast.ExpressionStatement. got={:?}", program.statements[0]);
+        }
+    }
+
+    #[test]
+    fn test_literal_expression() {
+        let input = String::from("5; \"test\"; '\\n';");
+
+        let l = Lexer::new(&input);
+        let mut p = Parser::new(l);
+
+        let program = p.parse_program();
+        assert!(program.is_ok(), "parse_program() returned an error: {:?}", program.err().unwrap());
+
+        let program = program.unwrap();
+        assert_eq!(program.statements.len(), 3, "program.Statements does not contain 3 statements. got={}", program.statements.len());
 
         if let Statement::ExpressionStatement(expr) = &program.statements[0] {
             if let Expression::IntegerLiteral(int) = &expr.expression {
@@ -359,7 +1423,7 @@ mod tests {
             } else {
                 panic!("expr.expression is not ast.IntegerLiteral. got={:?}", expr.expression);
             }
-            assert_eq!(expr.string(), "5;");
+            assert_eq!(expr.to_string(), "5;");
         } else {
             panic!("program.statements[0] is not ast.ExpressionStatement. got={:?}", program.statements[0]);
         }
@@ -370,10 +1434,21 @@ mod tests {
             } else {
                 panic!("expr.expression is not ast.StringLiteral. got={:?}", expr.expression);
             }
-            assert_eq!(expr.string(), "\"test\";");
+            assert_eq!(expr.to_string(), "\"test\";");
         } else {
             panic!("program.statements[1] is not ast.ExpressionStatement. got={:?}", program.statements[1]);
         }
+
+        if let Statement::ExpressionStatement(expr) = &program.statements[2] {
+            if let Expression::CharLiteral(c) = &expr.expression {
+                assert_eq!(c.value, '\n');
+            } else {
+                panic!("expr.expression is not ast.CharLiteral. got={:?}", expr.expression);
+            }
+            assert_eq!(expr.to_string(), "'\\n';");
+        } else {
+            panic!("program.statements[2] is not ast.ExpressionStatement. got={:?}", program.statements[2]);
+        }
     }
 
     struct PrefixTest {
@@ -383,9 +1458,9 @@ mod tests {
     }
     #[test]
     fn test_prefix_expression() {
-        let input = String::from("!5; -15; ++5; --5; !test");
+        let input = String::from("!5; -15; ++a; --b; !test");
 
-        let l = Lexer::new(input);
+        let l = Lexer::new(&input);
         let mut p = Parser::new(l);
 
         let program = p.parse_program();
@@ -394,12 +1469,8 @@ mod tests {
         let program = program.unwrap();
         assert_eq!(program.statements.len(), 5, "program.Statements does not contain 5 statements. got={}", program.statements.len());
 
-        let tests = vec![
-            PrefixTest { str: "(!5);".to_string(), operator: TokenType::Bang, value: 5},
-            PrefixTest { str: "(-15);".to_string(), operator: TokenType::Minus, value: 15},
-            PrefixTest { str: "(++5);".to_string(), operator: TokenType::Increment, value: 5},
-            PrefixTest { str: "(--5);".to_string(), operator: TokenType:: Decrement, value: 5},
-        ];
+        let tests = [PrefixTest { str: "(!5);".to_string(), operator: TokenType::Bang, value: 5},
+            PrefixTest { str: "(-15);".to_string(), operator: TokenType::Minus, value: 15}];
 
         for (i, test) in tests.iter().enumerate() {
             if let Statement::ExpressionStatement(expr) = &program.statements[i] {
@@ -413,12 +1484,22 @@ mod tests {
                 } else {
                     panic!("expr.expression is not ast.Prefix. got={:?}", expr.expression);
                 }
-                assert_eq!(expr.string(), test.str);
+                assert_eq!(expr.to_string(), test.str);
             } else {
                 panic!("program.statements[{}] is not ast.ExpressionStatement. got={:?}", i, program.statements[i]);
             }
         }
 
+        for (i, operator, name) in [(2, TokenType::Increment, "a"), (3, TokenType::Decrement, "b")] {
+            match &program.statements[i] {
+                Statement::ExpressionStatement(ExpressionStatement { expression: Expression::Prefix(pre), .. }) => {
+                    assert_eq!(pre.operator.ttype, operator);
+                    assert!(matches!(&*pre.right, Expression::Identifier(ident) if ident.value == name), "got={:?}", pre.right);
+                },
+                other => panic!("program.statements[{}] is not a prefix expression. got={:?}", i, other),
+            }
+        }
+
         if let Statement::ExpressionStatement(expr) = &program.statements[4] {
             if let Expression::Prefix(pre) = &expr.expression {
                 assert_eq!(pre.operator.ttype, TokenType::Bang);
@@ -430,7 +1511,7 @@ mod tests {
             } else {
                 panic!("expr.expression is not ast.Prefix. got={:?}", expr.expression);
             }
-            assert_eq!(expr.string(), "(!test);");
+            assert_eq!(expr.to_string(), "(!test);");
         } else {
             panic!("program.statements[3] is not ast.ExpressionStatement. got={:?}", program.statements[4]);
         }
@@ -458,7 +1539,7 @@ mod tests {
         5 >= 5;
         "#);
 
-        let l = Lexer::new(input);
+        let l = Lexer::new(&input);
         let mut p = Parser::new(l);
 
         let program = p.parse_program();
@@ -498,7 +1579,7 @@ mod tests {
                 } else {
                     panic!("expr.expression is not ast.Infix. got={:?}", expr.expression);
                 }
-                assert_eq!(expr.string(), test.str);
+                assert_eq!(expr.to_string(), test.str);
             } else {
                 panic!("program.statements[{}] is not ast.ExpressionStatement. got={:?}", i, program.statements[i]);
             }
@@ -524,18 +1605,72 @@ mod tests {
             PrecedenceTest{str: "5 > 4 == 3 < 4".to_string(), expected: "((5 > 4) == (3 < 4));".to_string()},
             PrecedenceTest{str: "5 < 4 != 3 > 4".to_string(), expected: "((5 < 4) != (3 > 4));".to_string()},
             PrecedenceTest{str: "3 + 4 * 5 == 3 * 1 + 4 * 5".to_string(), expected: "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)));".to_string()},
+            PrecedenceTest{str: "a ?? b == null".to_string(), expected: "(a ?? (b == null));".to_string()},
+            PrecedenceTest{str: "a ?? b ?? c + 1".to_string(), expected: "((a ?? b) ?? (c + 1));".to_string()},
+            PrecedenceTest{str: "a * [1, 2, 3, 4][b * c] * d".to_string(), expected: "((a * ([1, 2, 3, 4][(b * c)])) * d);".to_string()},
+            PrecedenceTest{str: "add(a * b[2], b[1], 2 * [1, 2][1])".to_string(), expected: "add((a * (b[2])), (b[1]), (2 * ([1, 2][1])));".to_string()},
+            PrecedenceTest{str: "s[0][1] + m.items[i]".to_string(), expected: "(((s[0])[1]) + (m.items[i]));".to_string()},
+            PrecedenceTest{str: "(a + b) * c".to_string(), expected: "((a + b) * c);".to_string()},
+            PrecedenceTest{str: "a - (b - c)".to_string(), expected: "(a - (b - c));".to_string()},
+            PrecedenceTest{str: "-(5 + 5)".to_string(), expected: "(-(5 + 5));".to_string()},
+            PrecedenceTest{str: "!(true == true)".to_string(), expected: "(!(true == true));".to_string()},
+            PrecedenceTest{str: "((a))".to_string(), expected: "a;".to_string()},
+            PrecedenceTest{str: "(fn(x) { x })(1)".to_string(), expected: "fn(x) { x }(1);".to_string()},
+            PrecedenceTest{str: "(a ?? b)[0]".to_string(), expected: "((a ?? b)[0]);".to_string()},
         ];
 
         for test in tests {
-            let l = Lexer::new(test.str);
+            let l = Lexer::new(&test.str);
             let mut p = Parser::new(l);
             let program = p.parse_program().unwrap();
 
-            let actual = program.string();
+            let actual = program.to_string();
             assert_eq!(test.expected, actual)
         }
     }
 
+    #[test]
+    fn test_registered_operators() {
+        let parse = |input: &str, register: fn(&mut Parser)| {
+            let mut p = Parser::new(Lexer::new(input));
+            register(&mut p);
+            p.parse_program().unwrap().to_string()
+        };
+
+        assert_eq!(parse("1 + 2 % 3", |_| {}), "(1 + (2 % 3));");
+        assert_eq!(parse("1 + 2 % 3", |p| {
+            p.register_infix(TokenType::Percent, Precedence::Sum, Assoc::Left, Parser::parse_infix_expression);
+        }), "((1 + 2) % 3);");
+        assert_eq!(parse("1 - 2 - 3", |p| {
+            p.register_infix(TokenType::Minus, Precedence::Sum, Assoc::Right, Parser::parse_infix_expression);
+        }), "(1 - (2 - 3));");
+        assert_eq!(parse("a : b", |p| {
+            p.register_infix(TokenType::Colon, Precedence::Sum, Assoc::Left, Parser::parse_infix_expression);
+        }), "(a : b);");
+        assert_eq!(parse("?", |p| {
+            p.register_prefix(TokenType::Question, |p| Ok(Expression::NullLiteral(p.cur().to_token())));
+        }), "?;");
+    }
+
+    #[test]
+    fn test_peek_nth() {
+        let mut p = Parser::new(Lexer::new("let x = 1;"));
+        assert_eq!(p.peek_nth(3).literal, "1");
+        assert_eq!(p.peek_nth(0).literal, "let");
+        let peek = p.peek().literal;
+        assert_eq!(p.peek_nth(1).literal, peek);
+
+        // Looking ahead leaves the tokens to come where they were
+        p.next_token();
+        assert_eq!(p.cur().literal, "x");
+        assert_eq!(p.peek().literal, "=");
+
+        // and past the end it is all end of input, at the end of the input
+        let eof = *p.peek_nth(10);
+        assert_eq!(eof.ttype, TokenType::Eof);
+        assert_eq!(eof.column, p.peek_nth(5).column);
+    }
+
     struct PostfixTest {
         str: String,
         expected: String,
@@ -550,21 +1685,1116 @@ mod tests {
         ];
 
         for test in tests {
-            let l = Lexer::new(test.str);
+            let l = Lexer::new(&test.str);
             let mut p = Parser::new(l);
             let program = p.parse_program().unwrap();
 
-            let actual = program.string();
+            let actual = program.to_string();
             assert_eq!(test.expected, actual)
         }
     }
 
     #[test]
     fn test_expression() {
-        let l = Lexer::new("a b c".to_string());
+        let l = Lexer::new("a b c");
         let mut p = Parser::new(l);
         let program = p.parse_program().unwrap();
 
         assert_eq!(program.statements.len(), 3);
     }
+
+    #[test]
+    fn test_error_positions() {
+        let tests = vec![
+            ("let x = 5;\nlet = 10;", "expected next token to be Identifier, got Assign at line 2, col 5"),
+            ("let x 5;", "expected next token to be Assign, got Integer at line 1, col 7"),
+            ("5;\n  ;", "no prefix parse function for Semicolon at line 2, col 3"),
+            ("let x", "expected next token to be Assign, got end of input at line 1, col 6"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            assert_eq!(p.parse_program().unwrap_err()[0].to_string(), expected.to_string());
+        }
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let parse = |input: &str| Parser::new(Lexer::new(input)).parse_program().unwrap_err().remove(0);
+
+        match parse("let 5 = x;") {
+            ParseError::UnexpectedToken { expected, token } => {
+                assert_eq!(expected, TokenType::Identifier);
+                assert_eq!(token, Token::new(TokenType::Integer, "5".to_string()));
+            },
+            err => panic!("expected UnexpectedToken, got {:?}", err),
+        }
+
+        match parse("let x = ") {
+            ParseError::UnexpectedEof { expected: None, token } => assert_eq!(token.ttype, TokenType::Eof),
+            err => panic!("expected UnexpectedEof, got {:?}", err),
+        }
+
+        match parse("x + \"oops") {
+            ParseError::Illegal { error, token } => {
+                assert_eq!(error, lexer::LexError::UnterminatedString);
+                assert_eq!(token.literal, "\"oops");
+            },
+            err => panic!("expected Illegal, got {:?}", err),
+        }
+
+        for (input, expected) in [
+            ("let # = 1;", "unknown character '#' at line 1, col 5"),
+            ("f(1, 2 @)", "unknown character '@' at line 1, col 8"),
+            ("let s = \"a\n\nb;", "unterminated string at line 1, col 9"),
+            ("x + '\\z'", "unknown escape \\z in character literal at line 1, col 5"),
+            ("1 + 'ab'", "character literal must hold exactly one character at line 1, col 5"),
+            ("let x = 1; /* and", "unterminated block comment at line 1, col 12"),
+        ] {
+            assert_eq!(parse(input).to_string(), expected, "{}", input);
+        }
+
+        for input in ["-\"str\"", "-(\"str\")"] {
+            match parse(input) {
+                ParseError::InvalidPrefixOperand { operator, operand } => {
+                    assert_eq!(operator.ttype, TokenType::Minus);
+                    assert_eq!(operand.ttype, TokenType::String);
+                },
+                err => panic!("expected InvalidPrefixOperand for {}, got {:?}", input, err),
+            }
+        }
+
+        for (input, operator, target) in [
+            ("++5", TokenType::Increment, "5"),
+            ("5--", TokenType::Decrement, "5"),
+            ("++null", TokenType::Increment, "null"),
+            ("--f()", TokenType::Decrement, "f()"),
+            ("++a++", TokenType::Increment, "(a++)"),
+            ("-f()++", TokenType::Increment, "(-f())"),
+        ] {
+            match parse(input) {
+                ParseError::InvalidIncrementTarget { target: found, token } => {
+                    assert_eq!(token.ttype, operator, "{}", input);
+                    assert_eq!(found, target, "{}", input);
+                },
+                err => panic!("expected InvalidIncrementTarget for {}, got {:?}", input, err),
+            }
+        }
+        assert_eq!(parse("5++").to_string(), "cannot apply ++ to 5, only to a variable at line 1, col 2");
+
+        let err = parse("1 + }");
+        assert_eq!(err, ParseError::NoPrefixParse(Token::new(TokenType::RightCurlyBracket, "}".to_string())));
+        assert_eq!(err.span(), Span { line: 1, column: 5 });
+    }
+
+    #[test]
+    #[cfg(not(feature = "bigint"))]
+    fn test_integer_out_of_range() {
+        let tests = vec![
+            ("9223372036854775808", Span { line: 1, column: 1 }),
+            ("let x = 99999999999999999999;", Span { line: 1, column: 9 }),
+            ("1 +\n  -18446744073709551616", Span { line: 2, column: 4 }),
+        ];
+
+        for (input, span) in tests {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            let literal = input.split(|c: char| !c.is_ascii_digit()).max_by_key(|s| s.len()).unwrap();
+            assert_eq!(errors, vec![ParseError::IntegerOutOfRange {
+                literal: literal.to_string(),
+                token: Token::new(TokenType::Integer, literal.to_string()),
+            }], "{}", input);
+            assert_eq!(errors[0].span(), span, "{}", input);
+            assert_eq!(errors[0].message(), format!("integer literal {} is out of range", literal));
+        }
+
+        // The largest i64 still fits, as does the smallest
+        let program = Parser::new(Lexer::new("9223372036854775807")).parse_program().unwrap();
+        assert_eq!(program.to_string(), "9223372036854775807;");
+        let errors = Parser::new(Lexer::new("-9223372036854775809")).parse_program().unwrap_err();
+        assert_eq!(errors[0].message(), "integer literal 9223372036854775809 is out of range");
+    }
+
+    #[test]
+    fn test_smallest_integer() {
+        let tests = vec![
+            ("-9223372036854775808", "-9223372036854775808;"),
+            ("x - -9223372036854775808 * 2", "(x - (-9223372036854775808 * 2));"),
+            ("- 9223372036854775808", "-9223372036854775808;"),
+            ("- -9223372036854775808", "(- -9223372036854775808);"),
+            ("-9223372036854775807", "(-9223372036854775807);"),
+        ];
+
+        for (input, expected) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            assert_eq!(program.to_string(), expected, "{}", input);
+        }
+        let program = Parser::new(Lexer::new("\n  -9223372036854775808")).parse_program().unwrap();
+        let Statement::ExpressionStatement(es) = &program.statements[0] else { panic!("not an expression") };
+        assert_eq!(es.expression.span(), Span { line: 2, column: 3 });
+        assert!(matches!(es.expression, Expression::IntegerLiteral(IntegerLiteral { value: i64::MIN, .. })));
+    }
+
+    #[test]
+    #[cfg(feature = "bigint")]
+    fn test_big_integer_literals() {
+        let program = Parser::new(Lexer::new("99999999999999999999 + 1")).parse_program().unwrap();
+        let Statement::ExpressionStatement(es) = &program.statements[0] else { panic!("expected an expression statement") };
+        let Expression::Infix(infix) = &es.expression else { panic!("expected an infix expression") };
+        assert_eq!(*infix.left, Expression::BigIntegerLiteral(Token::new(TokenType::Integer, "99999999999999999999".to_string())));
+        assert_eq!(program.to_string(), "(99999999999999999999 + 1);");
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let input = String::from(r#"
+        let = 5;
+        let y = 10;
+        let z 15;
+        return y;
+        1 + ;
+        let ok = 1
+        "#);
+
+        let l = Lexer::new(&input);
+        let mut p = Parser::new(l);
+
+        let errors = p.parse_program().unwrap_err();
+        assert_eq!(errors.len(), 3, "expected 3 errors, got {:?}", errors);
+        assert_eq!(p.errors(), &errors[..]);
+
+        assert!(matches!(&errors[0], ParseError::UnexpectedToken { expected: TokenType::Identifier, .. }));
+        assert_eq!(errors[0].span(), Span { line: 2, column: 13 });
+        assert!(matches!(&errors[1], ParseError::UnexpectedToken { expected: TokenType::Assign, .. }));
+        assert_eq!(errors[1].span(), Span { line: 4, column: 15 });
+        assert!(matches!(&errors[2], ParseError::NoPrefixParse(_)));
+        assert_eq!(errors[2].span(), Span { line: 6, column: 13 });
+    }
+
+    #[test]
+    fn test_for_statements() {
+        let tests = vec![
+            ("for (let i = 0; i < 10; i++) { x + i; }", "for (let i = 0; (i < 10); (i++)) { (x + i); }"),
+            ("for (i; i < 10; i++) { }", "for (i; (i < 10); (i++)) { }"),
+            ("for (c in \"abc\") { let d = c; return d; }", "for (c in \"abc\") { let d = c; return d; }"),
+            ("for (x in xs) { for (y in x) { y; } }", "for (x in xs) { for (y in x) { y; } }"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            let program = p.parse_program().unwrap();
+
+            assert_eq!(program.statements.len(), 1);
+            assert_eq!(program.to_string(), expected);
+        }
+
+        let l = Lexer::new("for (let i = 0; i < 3; i++) { i; }");
+        let program = Parser::new(l).parse_program().unwrap();
+        if let Statement::ForStatement(stmt) = &program.statements[0] {
+            assert!(matches!(&*stmt.init, Statement::LetStatement(_)));
+            assert!(matches!(&stmt.condition, Expression::Infix(_)));
+            assert!(matches!(&stmt.update, Expression::Postfix(_)));
+            assert_eq!(stmt.body.statements.len(), 1);
+        } else {
+            panic!("program.statements[0] is not ast.ForStatement. got={:?}", program.statements[0]);
+        }
+    }
+
+    #[test]
+    fn test_for_statement_errors() {
+        let tests = vec![
+            ("for i < 10 { }", "expected next token to be LeftParen, got Identifier at line 1, col 5"),
+            ("for (let i = 0 i < 10; i++) { }", "expected next token to be Semicolon, got Identifier at line 1, col 16"),
+            ("for (x in xs) x;", "expected next token to be LeftCurlyBracket, got Identifier at line 1, col 15"),
+            ("for (x in xs) { x;", "expected next token to be RightCurlyBracket, got end of input at line 1, col 19"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            assert_eq!(p.parse_program().unwrap_err()[0].to_string(), expected.to_string());
+        }
+    }
+
+    #[test]
+    fn test_assignment_expressions() {
+        let tests = vec![
+            ("x = 5", "(x = 5);"),
+            ("x = y = 5;", "(x = (y = 5));"),
+            ("x = y + 2 * z", "(x = (y + (2 * z)));"),
+            ("let x = y = 1;", "let x = (y = 1);"),
+            ("for (let i = 0; i < 3; i = i + 1) { }", "for (let i = 0; (i < 3); (i = (i + 1))) { }"),
+            ("x += 1", "(x += 1);"),
+            ("x -= y * 2", "(x -= (y * 2));"),
+            ("x *= y /= 2", "(x *= (y /= 2));"),
+            ("x %= 3; y = x", "(x %= 3);(y = x);"),
+            ("a[0] = 9", "((a[0]) = 9);"),
+            ("h[\"k\"][i + 1] += 2", "(((h[\"k\"])[(i + 1)]) += 2);"),
+            ("p.x = q.y = 5", "(p.x = (q.y = 5));"),
+            ("a[0].x -= 1", "((a[0]).x -= 1);"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            assert_eq!(p.parse_program().unwrap().to_string(), expected);
+        }
+
+        let tests = vec![
+            ("5 = x;", "invalid assignment target 5 at line 1, col 3"),
+            ("a + b = c;", "invalid assignment target (a + b) at line 1, col 7"),
+            ("x = ;", "no prefix parse function for Semicolon at line 1, col 5"),
+            ("1 += 2;", "invalid assignment target 1 at line 1, col 3"),
+            ("f()[0] = 1;", "invalid assignment target (f()[0]) at line 1, col 8"),
+            ("[1][0] = 2;", "invalid assignment target ([1][0]) at line 1, col 8"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            assert_eq!(p.parse_program().unwrap_err()[0].to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_array_literals() {
+        let tests = vec![
+            ("[]", "[];"),
+            ("[1, 2 * 2, 3 + 3]", "[1, (2 * 2), (3 + 3)];"),
+            ("[\"a\", [true, x], -y]", "[\"a\", [true, x], (-y)];"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            assert_eq!(p.parse_program().unwrap().to_string(), expected);
+        }
+
+        let l = Lexer::new("[1, 2");
+        let mut p = Parser::new(l);
+        assert!(matches!(
+            &p.parse_program().unwrap_err()[0],
+            ParseError::UnexpectedEof { expected: Some(TokenType::RightSquareBracket), .. }
+        ));
+    }
+
+    #[test]
+    fn test_call_expressions() {
+        let tests = vec![
+            ("add(1, 2 * 3, 4 + 5);", "add(1, (2 * 3), (4 + 5));"),
+            ("len()", "len();"),
+            ("a + add(b * c) + d", "((a + add((b * c))) + d);"),
+            ("add(a, b, 1, 2 * 3, 4 + 5, add(6, 7 * 8))", "add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)));"),
+            ("f(x)(y)", "f(x)(y);"),
+            ("x = push(xs, 1)", "(x = push(xs, 1));"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            assert_eq!(p.parse_program().unwrap().to_string(), expected);
+        }
+
+        let l = Lexer::new("add(1, 2;");
+        let mut p = Parser::new(l);
+        assert_eq!(
+            p.parse_program().unwrap_err()[0].to_string(),
+            "expected next token to be RightParen, got Semicolon at line 1, col 9"
+        );
+    }
+
+    #[test]
+    fn test_pipe_expressions() {
+        let tests = vec![
+            ("x |> f", "f(x);"),
+            ("x |> f |> g(2)", "g(f(x), 2);"),
+            ("1 + 2 |> f", "f((1 + 2));"),
+            ("xs |> map(fn(x) { x * 2 }) |> sum", "sum(map(xs, fn(x) { (x * 2) }));"),
+            ("s |> strings.upper()", "strings.upper(s);"),
+            ("x |> f(1)(2)", "f(1)(x, 2);"),
+            ("y = x |> f", "(y = f(x));"),
+            ("a ?? b |> f", "f((a ?? b));"),
+            ("x |> f ? 1 : 2", "(f(x) ? 1 : 2);"),
+        ];
+
+        for (input, expected) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            assert_eq!(program.to_string(), expected, "{}", input);
+        }
+
+        let err = Parser::new(Lexer::new("x |> ")).parse_program().unwrap_err();
+        assert_eq!(err[0].to_string(), "unexpected end of input at line 1, col 6");
+    }
+
+    #[test]
+    fn test_interpolated_strings() {
+        let tests = vec![
+            (r#""sum is ${a + b}""#, r#""sum is ${(a + b)}";"#, "(program (interpolate \"sum is \" (infix + a b)))\n"),
+            (r#""${x}${y}""#, r#""${x}${y}";"#, "(program (interpolate x y))\n"),
+            (r#""${ "inner ${n}" } and ${ {"k": 1}["k"] }""#, r#""${"inner ${n}"} and ${({"k": 1}["k"])}";"#,
+                "(program\n  (interpolate (interpolate \"inner \" n) \" and \" (index (hash (pair \"k\" 1)) \"k\")))\n"),
+        ];
+
+        for (input, expected, sexpr) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            assert_eq!(program.to_string(), expected, "{}", input);
+            assert_eq!(program.to_sexpr(), sexpr, "{}", input);
+        }
+
+        // Embedded code is positioned where it is in the source
+        let program = Parser::new(Lexer::new("let s = \"é ${ total }\";")).parse_program().unwrap();
+        match &program.statements[0] {
+            Statement::LetStatement(LetStatement { value: Expression::InterpolatedString(s), .. }) => match &s.parts[1] {
+                StringPart::Expression(expression) => assert_eq!(expression.span(), Span { line: 1, column: 15 }),
+                other => panic!("expected an expression part, got {:?}", other),
+            },
+            other => panic!("expected an interpolated string, got {:?}", other),
+        }
+
+        for (input, expected) in [
+            ("\"a ${}\"", "unexpected end of input at line 1, col 6"),
+            ("x = \"${1 +}\"", "unexpected end of input at line 1, col 11"),
+            ("\"${a b}\"", "expected next token to be Eof, got Identifier at line 1, col 6"),
+        ] {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            assert_eq!(errors[0].to_string(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_raw_and_multiline_strings() {
+        // Strings print exactly as written, so each kind survives formatting
+        for input in ["r\"C:\\dir ${x}\";", "`say \"hi\"`;", "\"two\nlines\";", "`a\n${b}`;"] {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            assert_eq!(program.to_string(), input);
+            assert_eq!(ast::pretty::pretty(&program, 4), format!("{}\n", input));
+        }
+
+        let program = Parser::new(Lexer::new("match s { r\"${\" => 1, _ => 2 }")).parse_program().unwrap();
+        assert_eq!(program.to_string(), "match s { r\"${\" => 1, _ => 2 };");
+
+        let err = Parser::new(Lexer::new("\"first\n  and ${x +}\"")).parse_program().unwrap_err();
+        assert_eq!(err[0].to_string(), "unexpected end of input at line 2, col 12");
+    }
+
+    #[test]
+    fn test_index_expressions() {
+        let program = Parser::new(Lexer::new("items[1 + 1]")).parse_program().unwrap();
+        match &program.statements[0] {
+            Statement::ExpressionStatement(stmt) => match &stmt.expression {
+                Expression::Index(index) => {
+                    assert_eq!(index.left.to_string(), "items");
+                    assert_eq!(index.index.to_string(), "(1 + 1)");
+                    assert_eq!(index.token.span(), Span { line: 1, column: 6 });
+                },
+                other => panic!("not an index expression: {:?}", other),
+            },
+            other => panic!("not an expression statement: {:?}", other),
+        }
+
+        let errors = vec![
+            ("items[1;", "expected next token to be RightSquareBracket, got Semicolon at line 1, col 8"),
+            ("items[]", "no prefix parse function for RightSquareBracket at line 1, col 7"),
+            ("items()[0] = 1", "invalid assignment target (items()[0]) at line 1, col 12"),
+        ];
+
+        for (input, expected) in errors {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            assert_eq!(errors[0].to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_hash_literals() {
+        let tests = vec![
+            ("{}", "{};"),
+            ("{\"one\": 1, \"two\": 2}", "{\"one\": 1, \"two\": 2};"),
+            ("{1 + 1: a * b, true: [1]}[2]", "({(1 + 1): (a * b), true: [1]}[2]);"),
+            ("let h = {\"f\": fn(x) { x }, \"h\": {}};", "let h = {\"f\": fn(x) { x }, \"h\": {}};"),
+        ];
+
+        for (input, expected) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program();
+            assert_eq!(program.map(|p| p.to_string()), Ok(expected.to_string()), "input: {}", input);
+        }
+
+        let errors = vec![
+            ("{\"a\" 1}", "expected next token to be Colon, got Integer at line 1, col 6"),
+            ("{\"a\": 1 \"b\": 2}", "expected next token to be Comma, got String at line 1, col 9"),
+            ("{\"a\": 1", "expected next token to be Comma, got end of input at line 1, col 8"),
+        ];
+
+        for (input, expected) in errors {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            assert_eq!(errors[0].to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_blocks() {
+        let tests = vec![
+            ("{ let x = 1; x + 1 }", "{ let x = 1; (x + 1) }"),
+            ("{ x }; { x; y }", "{ x }{ x; y }"),
+            ("{ f(x); let y = 2; } - 1", "{ f(x); let y = 2; }(-1);"),
+            ("let y = { let x = 1; x } * 2;", "let y = ({ let x = 1; x } * 2);"),
+            ("{ {1: 2} }", "{ {1: 2} }"),
+            ("{ return 1; }", "{ return 1; }"),
+            ("fn() { x; }; fn() { x }", "fn() { x; };fn() { x };"),
+            ("{ import \"lib/m\"; m }", "{ import \"lib/m\"; m }"),
+            ("{ { 1 } }", "{ { 1 } }"),
+        ];
+
+        for (input, expected) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program();
+            assert_eq!(program.map(|p| p.to_string()), Ok(expected.to_string()), "input: {}", input);
+        }
+
+        let program = Parser::new(Lexer::new("{ let x = 1; }; {\"k\": 1}[\"k\"]; let v = { 2 };")).parse_program().unwrap();
+        assert_eq!(program.to_sexpr(), "(program (block (let x 1)) (index (hash (pair \"k\" 1)) \"k\") (let v (block 2)))\n");
+
+        let errors = Parser::new(Lexer::new("{ x y }")).parse_program().unwrap_err();
+        assert_eq!(errors[0].to_string(), "expected next token to be Colon, got Identifier at line 1, col 5");
+        let errors = Parser::new(Lexer::new("{ let x = 1;")).parse_program().unwrap_err();
+        assert_eq!(errors[0].to_string(), "expected next token to be RightCurlyBracket, got end of input at line 1, col 13");
+    }
+
+    #[test]
+    fn test_ternary_expressions() {
+        let tests = vec![
+            ("a ? b : c", "(a ? b : c);"),
+            ("x < 0 ? -x : x", "((x < 0) ? (-x) : x);"),
+            ("a ? b : c ? d : e", "(a ? b : (c ? d : e));"),
+            ("a ? b ? c : d : e", "(a ? (b ? c : d) : e);"),
+            ("x = a ?? b ? 1 + 2 : f(3)", "(x = ((a ?? b) ? (1 + 2) : f(3)));"),
+            ("let y = ok ? \"yes\" : \"no\";", "let y = (ok ? \"yes\" : \"no\");"),
+        ];
+
+        for (input, expected) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program();
+            assert_eq!(program.map(|p| p.to_string()), Ok(expected.to_string()), "input: {}", input);
+        }
+
+        let errors = Parser::new(Lexer::new("a ? b;")).parse_program().unwrap_err();
+        assert_eq!(errors[0].to_string(), "expected next token to be Colon, got Semicolon at line 1, col 6");
+    }
+
+    #[test]
+    fn test_float_literals() {
+        let tests = vec![
+            ("1.5", "1.5;", "(program 1.5)\n"),
+            ("-0.25 * 2", "((-0.25) * 2);", "(program (infix * (prefix - 0.25) 2))\n"),
+            ("1.0..2", "(1.0..2);", "(program (range .. 1.0 2))\n"),
+            ("match x { 0.5 => 1, -1.5 => 2, _ => 3 }", "match x { 0.5 => 1, -1.5 => 2, _ => 3 };", "(program (match x (arm 0.5 1) (arm (prefix - 1.5) 2) (arm _ 3)))\n"),
+        ];
+
+        for (input, expected, sexpr) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            assert_eq!(program.to_string(), expected, "{}", input);
+            assert_eq!(program.to_sexpr(), sexpr, "{}", input);
+            assert_round_trips(input, &program);
+        }
+
+        let program = Parser::new(Lexer::new("3.25")).parse_program().unwrap();
+        let Statement::ExpressionStatement(es) = &program.statements[0] else { panic!("not an expression") };
+        assert!(matches!(es.expression, Expression::FloatLiteral(FloatLiteral { value: 3.25, .. })));
+
+        let errors = Parser::new(Lexer::new("match x { 0.5..1.5 => 1 }")).parse_program().unwrap_err();
+        assert_eq!(errors[0].to_string(), "invalid pattern .. at line 1, col 14");
+    }
+
+    #[test]
+    fn test_propagate_expressions() {
+        let tests = vec![
+            ("f()?", "(f()?);", "(program (propagate (call f)))\n"),
+            ("let x = a + f(1)? * 2;", "let x = (a + ((f(1)?) * 2));", "(program (let x (infix + a (infix * (propagate (call f 1)) 2))))\n"),
+            ("-f()?", "(-(f()?));", "(program (prefix - (propagate (call f))))\n"),
+            ("f()?.x", "(f()?).x;", "(program (member (propagate (call f)) x))\n"),
+            ("g(f()?, x)?", "(g((f()?), x)?);", "(program (propagate (call g (propagate (call f)) x)))\n"),
+            ("f()? - 1", "((f()?) - 1);", "(program (infix - (propagate (call f)) 1))\n"),
+            ("f()?[0]", "((f()?)[0]);", "(program (index (propagate (call f)) 0))\n"),
+            ("f()? ?? 0", "((f()?) ?? 0);", "(program (infix ?? (propagate (call f)) 0))\n"),
+            // Followed by a `:` it is still a conditional
+            ("x ? -1 : 1", "(x ? (-1) : 1);", "(program (ternary x (prefix - 1) 1))\n"),
+            ("c ? f()? : [0]", "(c ? (f()?) : [0]);", "(program (ternary c (propagate (call f)) (array 0)))\n"),
+            ("x ? [f(a ? 1 : 2)] : y", "(x ? [f((a ? 1 : 2))] : y);", "(program (ternary x (array (call f (ternary a 1 2))) y))\n"),
+            ("f()? ?", "((f()?)?);", "(program (propagate (propagate (call f))))\n"),
+            ("fn() { let v = f()?; v }", "fn() { let v = (f()?); v };", "(program (fn (params) (block (let v (propagate (call f))) v)))\n"),
+        ];
+
+        for (input, expected, sexpr) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            assert_eq!(program.to_string(), expected, "{}", input);
+            assert_eq!(program.to_sexpr(), sexpr, "{}", input);
+            assert_round_trips(input, &program);
+        }
+    }
+
+    #[test]
+    fn test_imports() {
+        let tests = vec![
+            ("import \"lib/math\"; math.sqrt(16)", "import \"lib/math\";math.sqrt(16);"),
+            ("import \"util.mky\"", "import \"util.mky\";"),
+            ("let m = import(\"math\"); m.pi * 2", "let m = import(\"math\");(m.pi * 2);"),
+            ("let b = import \"a\".b;", "let b = import(\"a\").b;"),
+            ("-m.x + f(1).y", "((-m.x) + f(1).y);"),
+        ];
+
+        for (input, expected) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program();
+            assert_eq!(program.map(|p| p.to_string()), Ok(expected.to_string()), "input: {}", input);
+        }
+
+        let program = Parser::new(Lexer::new("import \"lib/math\";")).parse_program().unwrap();
+        match &program.statements[0] {
+            Statement::ImportStatement(is) => {
+                assert_eq!(is.name.value.as_str(), "math");
+                assert_eq!(is.name.token.span(), Span { line: 1, column: 8 });
+            },
+            other => panic!("not an import statement: {:?}", other),
+        }
+
+        let errors = vec![
+            ("import \"lib/\";", "cannot name module \"lib/\" after its path, use `let name = import(\"lib/\")` at line 1, col 8"),
+            ("import \"my-lib\";", "cannot name module \"my-lib\" after its path, use `let name = import(\"my-lib\")` at line 1, col 8"),
+            ("import(math)", "expected next token to be String, got Identifier at line 1, col 8"),
+            ("m.1", "expected next token to be Identifier, got Integer at line 1, col 3"),
+            ("import(\"m\").x = 1", "invalid assignment target import(\"m\").x at line 1, col 15"),
+        ];
+
+        for (input, expected) in errors {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            assert_eq!(errors[0].to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_function_literals() {
+        let tests = vec![
+            ("fn() { }", "fn() { };"),
+            ("fn(x) { x; }", "fn(x) { x; };"),
+            ("fn(x, y, z) { x + y; return z; }", "fn(x, y, z) { (x + y); return z; };"),
+            ("let adder = fn(x) { fn(y) { x + y } };", "let adder = fn(x) { fn(y) { (x + y) } };"),
+            ("fn(x) { x }(5)", "fn(x) { x }(5);"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            assert_eq!(p.parse_program().unwrap().to_string(), expected);
+        }
+
+        let l = Lexer::new("fn(x, y) { x + y; }");
+        let program = Parser::new(l).parse_program().unwrap();
+        if let Statement::ExpressionStatement(stmt) = &program.statements[0] {
+            if let Expression::FunctionLiteral(func) = &stmt.expression {
+                let names: Vec<&str> = func.parameters.iter().map(|p| p.value.as_str()).collect();
+                assert_eq!(names, vec!["x", "y"]);
+                assert_eq!(func.body.statements.len(), 1);
+            } else {
+                panic!("stmt.expression is not ast.FunctionLiteral. got={:?}", stmt.expression);
+            }
+        } else {
+            panic!("program.statements[0] is not ast.ExpressionStatement. got={:?}", program.statements[0]);
+        }
+
+        let tests = vec![
+            ("fn(1) { }", "expected next token to be Identifier, got Integer at line 1, col 4"),
+            ("fn(x y) { }", "expected next token to be RightParen, got Identifier at line 1, col 6"),
+            ("fn(x) x", "expected next token to be LeftCurlyBracket, got Identifier at line 1, col 7"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            assert_eq!(p.parse_program().unwrap_err()[0].to_string(), expected);
+        }
+    }
+    #[test]
+    fn test_match_expressions() {
+        let tests = vec![
+            (
+                "match x { 1 => \"one\", 2..5 => \"few\", _ => \"many\" }",
+                "match x { 1 => \"one\", 2..5 => \"few\", _ => \"many\" };",
+                "(program (match x (arm 1 \"one\") (arm (range 2 5) \"few\") (arm _ \"many\")))\n",
+            ),
+            (
+                "let y = match a + 1 { -1 => b = 2, 'a'..'z' => f(1), null => true, } * 2;",
+                "let y = (match (a + 1) { -1 => (b = 2), 'a'..'z' => f(1), null => true } * 2);",
+                "(program\n  (let y\n    (infix *\n      (match\n        (infix + a 1)\n        (arm (prefix - 1) (assign = b 2))\n        (arm (range 'a' 'z') (call f 1))\n        (arm null true))\n      2)))\n",
+            ),
+            ("match {1: 2} {}", "match {1: 2} {};", "(program (match (hash (pair 1 2))))\n"),
+        ];
+
+        for (input, expected, sexpr) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            assert_eq!(program.to_string(), expected, "{}", input);
+            assert_eq!(program.to_sexpr(), sexpr, "{}", input);
+            assert_round_trips(input, &program);
+        }
+
+        let tests = vec![
+            ("match x { y => 1 }", "invalid pattern y at line 1, col 11"),
+            ("match x { 1 + 2 => 1 }", "expected next token to be FatArrow, got Plus at line 1, col 13"),
+            ("match x { \"a\"..\"b\" => 1 }", "invalid pattern .. at line 1, col 14"),
+            ("match x { 1..true => 1 }", "invalid pattern true at line 1, col 14"),
+            ("match x { 1 => 1 2 => 2 }", "expected next token to be Comma, got Integer at line 1, col 18"),
+            ("match x { 1 => 1", "expected next token to be Comma, got end of input at line 1, col 17"),
+            ("match x 1", "expected next token to be LeftCurlyBracket, got Integer at line 1, col 9"),
+        ];
+
+        for (input, expected) in tests {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            assert_eq!(errors[0].to_string(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_try_expressions() {
+        let tests = vec![
+            (
+                "try { xs[1] } catch (e) { 0 }",
+                "try { (xs[1]) } catch (e) { 0 };",
+                "(program (try (block (index xs 1)) (catch e (block 0))))\n",
+            ),
+            (
+                "let y = try { throw \"no\"; } catch (err) { len(err) } + 1;",
+                "let y = (try { throw \"no\"; } catch (err) { len(err) } + 1);",
+                "(program\n  (let y\n    (infix + (try (block (throw \"no\")) (catch err (block (call len err)))) 1)))\n",
+            ),
+            ("throw {\"code\": 1}", "throw {\"code\": 1};", "(program (throw (hash (pair \"code\" 1))))\n"),
+            ("fn() { throw x }", "fn() { throw x; };", "(program (fn (params) (block (throw x))))\n"),
+        ];
+
+        for (input, expected, sexpr) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            assert_eq!(program.to_string(), expected, "{}", input);
+            assert_eq!(program.to_sexpr(), sexpr, "{}", input);
+            assert_round_trips(input, &program);
+        }
+
+        let tests = vec![
+            ("try { 1 }", "expected next token to be Catch, got end of input at line 1, col 10"),
+            ("try { 1 } catch { 2 }", "expected next token to be LeftParen, got LeftCurlyBracket at line 1, col 17"),
+            ("try { 1 } catch (1) { 2 }", "expected next token to be Identifier, got Integer at line 1, col 18"),
+            ("try 1 catch (e) { 2 }", "expected next token to be LeftCurlyBracket, got Integer at line 1, col 5"),
+        ];
+
+        for (input, expected) in tests {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            assert_eq!(errors[0].to_string(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_ranges() {
+        let tests = vec![
+            ("1..10", "(1..10);", "(program (range .. 1 10))\n"),
+            ("0..=n - 1", "(0..=(n - 1));", "(program (range ..= 0 (infix - n 1)))\n"),
+            ("a * 2..b < c", "(((a * 2)..b) < c);", "(program (infix < (range .. (infix * a 2) b) c))\n"),
+            ("-5..f(x)", "((-5)..f(x));", "(program (range .. (prefix - 5) (call f x)))\n"),
+            ("(1..3).map(f)", "(1..3).map(f);", "(program (method (range .. 1 3) map f))\n"),
+            ("for (i in 0..len(xs)) { i }", "for (i in (0..len(xs))) { i }", "(program (for-in i (range .. 0 (call len xs)) (block i)))\n"),
+        ];
+
+        for (input, expected, sexpr) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            assert_eq!(program.to_string(), expected, "{}", input);
+            assert_eq!(program.to_sexpr(), sexpr, "{}", input);
+            assert_round_trips(input, &program);
+        }
+
+        let errors = Parser::new(Lexer::new("1..")).parse_program().unwrap_err();
+        assert_eq!(errors[0].to_string(), "unexpected end of input at line 1, col 4");
+    }
+
+    #[test]
+    fn test_method_calls() {
+        let tests = vec![
+            ("xs.len()", "xs.len();", "(program (method xs len))\n"),
+            ("s.split(\",\").join(\"-\")", "s.split(\",\").join(\"-\");", "(program (method (method s split \",\") join \"-\"))\n"),
+            ("-a.b(1, c.d)", "(-a.b(1, c.d));", "(program (prefix - (method a b 1 (member c d))))\n"),
+            ("f(x).y()[0]", "(f(x).y()[0]);", "(program (index (method (call f x) y) 0))\n"),
+            ("(a + b).c()", "(a + b).c();", "(program (method (infix + a b) c))\n"),
+        ];
+
+        for (input, expected, sexpr) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            assert_eq!(program.to_string(), expected, "{}", input);
+            assert_eq!(program.to_sexpr(), sexpr, "{}", input);
+            assert_round_trips(input, &program);
+        }
+    }
+
+    #[test]
+    fn test_structs() {
+        let tests = vec![
+            ("struct Point { x, y, }", "struct Point { x, y }", "(program (struct Point (fields x y)))\n"),
+            ("struct Unit {}", "struct Unit {}", "(program (struct Unit (fields)))\n"),
+            (
+                "let p = Point { x: 1, y: a + 2 }; p.x",
+                "let p = Point { x: 1, y: (a + 2) };p.x;",
+                "(program\n  (let p (instance Point (field x 1) (field y (infix + a 2))))\n  (member p x))\n",
+            ),
+            ("Unit {} == Unit {}", "(Unit {} == Unit {});", "(program (infix == (instance Unit) (instance Unit)))\n"),
+            // The braces after a match subject hold the arms
+            ("match p { _ => 1 }", "match p { _ => 1 };", "(program (match p (arm _ 1)))\n"),
+            ("match (P {}) { _ => 1 }", "match (P {}) { _ => 1 };", "(program (match (instance P) (arm _ 1)))\n"),
+            ("match (f(P {}.x)) {}", "match (f(P {}.x)) {};", "(program (match (call f (member (instance P) x))))\n"),
+        ];
+
+        for (input, expected, sexpr) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            assert_eq!(program.to_string(), expected, "{}", input);
+            assert_eq!(program.to_sexpr(), sexpr, "{}", input);
+            assert_round_trips(input, &program);
+        }
+
+        let tests = vec![
+            ("struct { x }", "expected next token to be Identifier, got LeftCurlyBracket at line 1, col 8"),
+            ("struct P { x y }", "expected next token to be Comma, got Identifier at line 1, col 14"),
+            ("struct P { 1 }", "expected next token to be Identifier, got Integer at line 1, col 12"),
+            ("P { x }", "expected next token to be Colon, got RightCurlyBracket at line 1, col 7"),
+            ("P { x: 1", "expected next token to be Comma, got end of input at line 1, col 9"),
+        ];
+
+        for (input, expected) in tests {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            assert_eq!(errors[0].to_string(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_type_annotations() {
+        let tests = vec![
+            ("let x: int = 5;", "let x: int = 5;", "(program (let x (type int) 5))\n"),
+            ("let f: fn = fn(x) { x };", "let f: fn = fn(x) { x };", "(program (let f (type fn) (fn (params x) (block x))))\n"),
+            (
+                "fn(x: int, y) -> bool { x == y }",
+                "fn(x: int, y) -> bool { (x == y) };",
+                "(program (fn (params (x int) y) (returns bool) (block (infix == x y))))\n",
+            ),
+        ];
+
+        for (input, expected, sexpr) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            assert_eq!(program.to_string(), expected, "{}", input);
+            assert_eq!(program.to_sexpr(), sexpr, "{}", input);
+            assert_round_trips(input, &program);
+        }
+
+        let tests = vec![
+            ("let x: = 5;", "expected next token to be Identifier, got Assign at line 1, col 8"),
+            ("let x: int 5;", "expected next token to be Assign, got Integer at line 1, col 12"),
+            ("fn(x: 1) { }", "expected next token to be Identifier, got Integer at line 1, col 7"),
+            ("fn() -> { }", "expected next token to be Identifier, got LeftCurlyBracket at line 1, col 9"),
+        ];
+
+        for (input, expected) in tests {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            assert_eq!(errors[0].to_string(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_grouping_errors() {
+        let tests = vec![
+            ("(1 + 2", "expected next token to be RightParen, got end of input at line 1, col 7"),
+            ("(1 2)", "expected next token to be RightParen, got Integer at line 1, col 4"),
+            ("()", "no prefix parse function for RightParen at line 1, col 2"),
+        ];
+
+        for (input, expected) in tests {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            assert_eq!(errors[0].to_string(), expected, "{}", input);
+        }
+    }
+
+    // Debug builds need more stack per level than a test thread has, so deep
+    // inputs are parsed on a thread the size of the main one
+    fn parse_on_main_sized_stack(input: String) -> Result<(), Vec<ParseError>> {
+        std::thread::Builder::new().stack_size(8 << 20)
+            .spawn(move || Parser::new(Lexer::new(&input)).parse_program().map(|_| ()))
+            .unwrap().join().unwrap()
+    }
+
+    #[test]
+    fn test_nesting_depth() {
+        let nested = |open: &str, close: &str, count: usize| format!("{}1{}", open.repeat(count), close.repeat(count));
+        let tests = vec![
+            nested("(", ")", 10_000),
+            nested("!", "", 10_000),
+            nested("-", "", 10_000),
+            nested("[", "]", 10_000),
+            nested("{1: ", "}", 10_000),
+            nested("{ ", " }", 10_000),
+            nested("{ 1; ", " }", 10_000),
+            nested("fn() { ", " }", 10_000),
+            nested("f(", ")", 10_000),
+            nested("a = ", "", 10_000),
+            nested("a ? b : ", "", 10_000),
+            nested("x[", "]", 10_000),
+        ];
+        for input in tests {
+            let start = input[..20].to_string();
+            let errors = parse_on_main_sized_stack(input).unwrap_err();
+            assert!(matches!(errors[0], ParseError::TooDeep { limit: DEFAULT_MAX_DEPTH, .. }), "{}: {:?}", start, errors[0]);
+        }
+
+        let errors = Parser::with_max_depth(Lexer::new("((1))"), 2).parse_program().unwrap_err();
+        assert_eq!(errors[0].to_string(), "expression nested more than 2 levels deep at line 1, col 3");
+        assert!(Parser::with_max_depth(Lexer::new("((1)); ((2))"), 3).parse_program().is_ok());
+        assert!(Parser::new(Lexer::new(&nested("(", ")", 100))).parse_program().is_ok());
+
+        // Operators chained one after another leave as deep a tree
+        let chain = |operator: &str, count: usize| format!("x{}", operator.repeat(count));
+        for operator in [" + x", " == x", " |> f", "()?", ".y", "[0]"] {
+            let errors = parse_on_main_sized_stack(chain(operator, 100_000)).unwrap_err();
+            assert!(matches!(errors[0], ParseError::TooDeep { limit: DEFAULT_MAX_DEPTH, .. }), "{}: {:?}", operator, errors[0]);
+            assert!(Parser::new(Lexer::new(&chain(operator, 100))).parse_program().is_ok(), "{}", operator);
+        }
+        let errors = Parser::with_max_depth(Lexer::new("1 + 2 + 3 + 4"), 4).parse_program().unwrap_err();
+        assert_eq!(errors[0].to_string(), "expression nested more than 4 levels deep at line 1, col 13");
+        assert!(Parser::with_max_depth(Lexer::new("1 + 2 + 3; 4 + 5 + 6"), 4).parse_program().is_ok());
+    }
+
+    // Deterministic xorshift, enough to vary generated inputs without a
+    // dependency
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+    }
+
+    // Random mixes of everything that nests, to check that none of them
+    // can get past the limit
+    #[test]
+    fn test_nesting_depth_mixed() {
+        let openers = ["(", "!", "- ", "[", "{1: ", "fn() { ", "f(", "a ? b : ", "x[", "1 + ", "[0, "];
+        let closers = [")", "", "", "]", "}", " }", ")", "", "]", "", "]"];
+        let mut rng = Rng(0x2545F4914F6CDD1D);
+        for _ in 0..50 {
+            let mut input = String::new();
+            let mut closing = Vec::new();
+            for _ in 0..2_000 {
+                let choice = rng.below(openers.len());
+                input.push_str(openers[choice]);
+                closing.push(closers[choice]);
+            }
+            input.push('1');
+            closing.reverse();
+            input.push_str(&closing.concat());
+
+            let errors = parse_on_main_sized_stack(input).unwrap_err();
+            assert!(errors.iter().any(|err| matches!(err, ParseError::TooDeep { .. })), "{:?}", errors);
+        }
+    }
+
+    // Parsing the printed form of a program gives the same program back,
+    // whether printed fully parenthesised or formatted
+    fn assert_round_trips(input: &str, program: &Program) {
+        let printed = program.to_string();
+        let reparsed = Parser::new(Lexer::new(&printed)).parse_program();
+        assert_eq!(reparsed.map(|p| p.to_string()), Ok(printed.clone()), "{} printed as {}", input, printed);
+
+        let pretty = ast::pretty(program, 4);
+        let reparsed = Parser::new(Lexer::new(&pretty)).parse_program();
+        assert_eq!(reparsed.map(|p| p.to_string()), Ok(printed), "{} formatted as {}", input, pretty);
+    }
+
+    // Sequences of real tokens get much further into the parser than random
+    // characters do. Whatever they are, parsing must not panic.
+    #[test]
+    fn test_arbitrary_tokens() {
+        let vocabulary = [
+            "let", "x", "y", "f", "fn", "return", "for", "in", "import", "\"s\"", "\"m\"", "1", "0",
+            "99999999999999999999", "'c'", "true", "null", "=", "+=", "+", "-", "*", "/", "%", "!",
+            "==", "<", "<=", "??", "?", ":", "++", "--", ",", ";", ".", "(", ")", "[", "]", "{", "}",
+            "|>", "\"${x}\"", "\"${", "try", "catch", "throw", "1.5",
+        ];
+        let mut rng = Rng(0x9E3779B97F4A7C15);
+        let mut parsed = 0;
+        for _ in 0..20_000 {
+            let len = 1 + rng.below(12);
+            let input = (0..len).map(|_| rng.pick(&vocabulary)).collect::<Vec<_>>().join(" ");
+            if let Ok(program) = Parser::new(Lexer::new(&input)).parse_program() {
+                assert_round_trips(&input, &program);
+                parsed += 1;
+            }
+        }
+        // Make sure the round trip was exercised at all
+        assert!(parsed > 100, "only {} inputs parsed", parsed);
+    }
+
+    fn generate_expression(rng: &mut Rng, depth: usize) -> String {
+        let leaves = ["x", "y", "1", "42", "\"s\"", "'c'", "true", "null", "[]", "{}"];
+        if depth == 0 {
+            return rng.pick(&leaves).to_string();
+        }
+        let mut sub = || generate_expression(rng, depth - 1);
+        let (a, b, c) = (sub(), sub(), sub());
+        match rng.below(15) {
+            0 => rng.pick(&leaves).to_string(),
+            1 => {
+                // Strings are refused as prefix operands, however grouped
+                let string = a.trim_matches(|c| c == '(' || c == ')').ends_with('"');
+                format!("{}({})", rng.pick(&["-", "!"]), if string { "x" } else { &a })
+            },
+            2 => format!("{} {} {}", a, rng.pick(&["+", "-", "*", "/", "%", "==", "!=", "<", ">=", "??", "|>"]), b),
+            3 => format!("({})", a),
+            4 => format!("f({}, {})", a, b),
+            5 => format!("{}[{}]", a, b),
+            6 => format!("{}.member", a),
+            7 => format!("[{}, {}]", a, b),
+            8 => format!("{{{}: {}, {}: {}}}", a, b, c, a),
+            9 => format!("fn(p, q) {{ return {}; }}", a),
+            10 => format!("{} ? {} : {}", a, b, c),
+            11 => format!("x {} {}", rng.pick(&["=", "+=", "*="]), a),
+            12 => format!("{}{}", rng.pick(&["x", "y"]), rng.pick(&["++", "--"])),
+            13 => format!("\"a ${{{}}} b\"", a),
+            _ => format!("import(\"m\").{}", rng.pick(&["a", "b"])),
+        }
+    }
+
+    fn generate_statement(rng: &mut Rng) -> String {
+        let depth = 1 + rng.below(4);
+        let expression = generate_expression(rng, depth);
+        match rng.below(6) {
+            0 => format!("let v = {};", expression),
+            1 => format!("return {};", expression),
+            2 => format!("for (let i = 0; i < {}; i++) {{ {}; }}", expression, generate_expression(rng, 2)),
+            3 => format!("for (item in {}) {{ {}; }}", expression, generate_expression(rng, 2)),
+            4 => "import \"lib/m\";".to_string(),
+            _ => format!("{};", expression),
+        }
+    }
+
+    // Generated from the grammar, so almost all of these are valid
+    #[test]
+    fn test_generated_programs_round_trip() {
+        let mut rng = Rng(0xD1B54A32D192ED03);
+        for _ in 0..2_000 {
+            let input = (0..1 + rng.below(4)).map(|_| generate_statement(&mut rng)).collect::<Vec<_>>().join("\n");
+            match Parser::new(Lexer::new(&input)).parse_program() {
+                Ok(program) => assert_round_trips(&input, &program),
+                // Only assignment to something other than a name is refused,
+                // though recovery from it can report more
+                Err(errors) => assert!(
+                    matches!(errors[0], ParseError::InvalidAssignmentTarget { .. }),
+                    "{}: {:?}", input, errors,
+                ),
+            }
+        }
+    }
+
+    // Random characters, including ones the language has no use for
+    #[test]
+    fn test_arbitrary_input() {
+        let alphabet: Vec<char> = "abfnlet_01 \n+-*/%=!<>?:;,.()[]{}\"'\\\0é😀".chars().collect();
+        let mut rng = Rng(0x94D049BB133111EB);
+        for _ in 0..5_000 {
+            let len = rng.below(30);
+            let input: String = (0..len).map(|_| alphabet[rng.below(alphabet.len())]).collect();
+            if let Ok(program) = Parser::new(Lexer::new(&input)).parse_program() {
+                assert_round_trips(&input, &program);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pretty_round_trip() {
+        let input = "import \"lib/io\";let add=fn(a,b){return (a+b)*2;};for(let i=0;i<3;i++){x+=add(i,-1);}for(c in \"ab\"){io.puts([c,!true]);}let y=x??import(\"m\").null_value;let z=-y[0][i+1];let h={\"a\":1,2:[z]};";
+        let expected = "import \"lib/io\";
+let add = fn(a, b) {
+    return (a + b) * 2;
+};
+for (let i = 0; i < 3; i++) {
+    x += add(i, -1);
+}
+for (c in \"ab\") {
+    io.puts([c, !true]);
+}
+let y = x ?? import(\"m\").null_value;
+let z = -y[0][i + 1];
+let h = {\"a\": 1, 2: [z]};
+";
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let printed = ast::pretty(&program, 4);
+        assert_eq!(printed, expected);
+
+        // Printing is stable and keeps the meaning of the program
+        let reparsed = Parser::new(Lexer::new(&printed)).parse_program().unwrap();
+        assert_eq!(reparsed.to_string(), program.to_string());
+        assert_eq!(ast::pretty(&reparsed, 4), printed);
+    }
+
+    // Compares against src/snapshots/<name>.snap. Run with
+    // UPDATE_SNAPSHOTS=1 to write the current output there instead, then
+    // review the change in the diff.
+    fn assert_snapshot(name: &str, actual: &str) {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/snapshots").join(format!("{}.snap", name));
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, actual).unwrap();
+            return;
+        }
+        let expected = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(actual == expected, "snapshot {} changed, rerun with UPDATE_SNAPSHOTS=1 to accept\n--- expected\n{}--- actual\n{}", name, expected, actual);
+    }
+
+    // Each input followed by its tree
+    fn snapshot_trees(inputs: &[&str]) -> String {
+        inputs.iter().map(|input| {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            format!("> {}\n{}\n", input, program.to_sexpr())
+        }).collect()
+    }
+
+    #[test]
+    fn test_precedence_snapshot() {
+        let inputs = [
+            "-a * b",
+            "!-a",
+            "a + b + c",
+            "a + b * c + d / e - f",
+            "a * (b + c)",
+            "-(a + b)",
+            "a < b == c > d",
+            "a ?? b == c",
+            "a ?? b ?? c",
+            "a ? b : c ? d : e",
+            "a ? b ? c : d : e",
+            "x = y = a + 1",
+            "x += a ? b : c",
+            "a++ * -b--",
+            "f(a + b)[c * d].e",
+            "-a[0]",
+            "!f(x)",
+            "(a ?? b)[0]",
+            "import(\"m\").f(1)",
+        ];
+        assert_snapshot("precedence", &snapshot_trees(&inputs));
+    }
+
+    #[test]
+    fn test_statements_snapshot() {
+        let inputs = [
+            "let x = 1; return x;",
+            "import \"lib/io\"; io.puts(\"hi\");",
+            "for (let i = 0; i < 3; i++) { puts(i); }",
+            "for (c in \"ab\") { puts(c); }",
+            "let h = {\"a\": [1, 'b'], 2: fn(x, y) { x + y }};",
+            "let count = fn(items) { let n = 0; for (item in items) { n += 1; } return n; };",
+        ];
+        assert_snapshot("statements", &snapshot_trees(&inputs));
+    }
 }
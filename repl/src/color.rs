@@ -0,0 +1,143 @@
+use lexer::HighlightKind;
+use std::io::IsTerminal;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const BLUE: &str = "\x1b[34m";
+const MAGENTA: &str = "\x1b[35m";
+const CYAN: &str = "\x1b[36m";
+
+// Whether output gets ANSI colors. Everything is passed through unchanged
+// when they are off.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Colors {
+    pub enabled: bool,
+}
+
+impl Colors {
+    pub const PLAIN: Colors = Colors { enabled: false };
+
+    // On for terminals, unless turned off with the NO_COLOR convention
+    pub fn detect(stream: &impl IsTerminal) -> Colors {
+        Colors { enabled: stream.is_terminal() && std::env::var_os("NO_COLOR").is_none() }
+    }
+
+    fn paint(&self, text: &str, style: &str) -> String {
+        match self.enabled && !text.is_empty() {
+            true => format!("{}{}{}", style, text, RESET),
+            false => text.to_string(),
+        }
+    }
+
+    pub fn result(&self, text: &str) -> String {
+        self.paint(text, GREEN)
+    }
+
+    // For debug dumps, which are there to be read past
+    pub fn dim(&self, text: &str) -> String {
+        self.paint(text, DIM)
+    }
+
+    // Colors diagnostics as rendered by Diagnostic::render, line by line
+    pub fn diagnostic(&self, text: &str) -> String {
+        if !self.enabled {
+            return text.to_string();
+        }
+        let lines: Vec<String> = text.split('\n').map(|line| self.diagnostic_line(line)).collect();
+        lines.join("\n")
+    }
+
+    fn diagnostic_line(&self, line: &str) -> String {
+        for (label, color) in [("error", RED), ("warning", YELLOW)] {
+            if let Some(message) = line.strip_prefix(label).and_then(|rest| rest.strip_prefix(':')) {
+                return format!("{}{}", self.paint(label, &format!("{}{}", BOLD, color)), self.paint(&format!(":{}", message), BOLD));
+            }
+        }
+        let gutter = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit() || c == ' ').len();
+        let (left, rest) = line.split_at(gutter);
+        if let Some(arrow) = rest.strip_prefix("-->") {
+            return format!("{}{}", self.paint(&format!("{}-->", left), BLUE), arrow);
+        }
+        if let Some(note) = rest.strip_prefix('=') {
+            return format!("{}{}", self.paint(&format!("{}=", left), BLUE), note);
+        }
+        match rest.strip_prefix('|') {
+            Some(code) if !code.is_empty() && code.trim_start().chars().all(|c| c == '^') => {
+                format!("{}{}", self.paint(&format!("{}|", left), BLUE), self.paint(code, &format!("{}{}", BOLD, RED)))
+            },
+            Some(code) => format!("{}{}", self.paint(&format!("{}|", left), BLUE), code),
+            None => line.to_string(),
+        }
+    }
+
+    // Source with its tokens colored by kind
+    pub fn highlight(&self, source: &str) -> String {
+        if !self.enabled {
+            return source.to_string();
+        }
+        let mut out = String::new();
+        let mut end = 0;
+        for span in lexer::highlight(source) {
+            out.push_str(&source[end..span.range.start]);
+            let text = &source[span.range.clone()];
+            end = span.range.end;
+            let style = match span.kind {
+                HighlightKind::Keyword => MAGENTA,
+                HighlightKind::Number => CYAN,
+                HighlightKind::String => YELLOW,
+                HighlightKind::Comment => DIM,
+                HighlightKind::Error => RED,
+                HighlightKind::Identifier | HighlightKind::Operator => {
+                    out.push_str(text);
+                    continue;
+                },
+            };
+            out.push_str(&self.paint(text, style));
+        }
+        out.push_str(&source[end..]);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLORS: Colors = Colors { enabled: true };
+
+    #[test]
+    fn test_plain() {
+        let text = "error: oops\n --> line 1, col 1";
+        assert_eq!(Colors::PLAIN.diagnostic(text), text);
+        assert_eq!(Colors::PLAIN.highlight("let x = 1;"), "let x = 1;");
+        assert_eq!(Colors::PLAIN.result("1"), "1");
+    }
+
+    #[test]
+    fn test_diagnostic() {
+        let text = "error: type mismatch\n --> line 1, col 3\n  |\n1 | 1 + true\n  |   ^\n  = note: in f";
+        let expected = [
+            "\x1b[1m\x1b[31merror\x1b[0m\x1b[1m: type mismatch\x1b[0m",
+            "\x1b[34m -->\x1b[0m line 1, col 3",
+            "\x1b[34m  |\x1b[0m",
+            "\x1b[34m1 |\x1b[0m 1 + true",
+            "\x1b[34m  |\x1b[0m\x1b[1m\x1b[31m   ^\x1b[0m",
+            "\x1b[34m  =\x1b[0m note: in f",
+        ];
+        assert_eq!(COLORS.diagnostic(text), expected.join("\n"));
+        assert!(COLORS.diagnostic("warning: unused").starts_with("\x1b[1m\x1b[33mwarning\x1b[0m"));
+    }
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(
+            COLORS.highlight("let s = \"a\"; // note\nf(12)"),
+            "\x1b[35mlet\x1b[0m s = \x1b[33m\"a\"\x1b[0m; \x1b[2m// note\x1b[0m\nf(\x1b[36m12\x1b[0m)",
+        );
+        assert_eq!(COLORS.highlight("  x  "), "  x  ");
+    }
+}
@@ -0,0 +1,80 @@
+use lexer::KEYWORDS;
+use object::{Environment, Object};
+
+// Candidates for the word the cursor is at the end of, with the position the
+// word starts at. After a dot they are the members of the value before it,
+// otherwise the names in scope, builtins and keywords.
+pub fn complete(line: &str, pos: usize, env: &Environment) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = word_start(before);
+    let prefix = &before[start..];
+
+    let mut candidates = match before[..start].strip_suffix('.') {
+        Some(receiver) => {
+            let receiver = &receiver[word_start(receiver)..];
+            match env.get(receiver) {
+                Some(value) if !receiver.is_empty() => members(&value),
+                _ => Vec::new(),
+            }
+        },
+        None => {
+            let mut names = env.names();
+            names.extend(eval::builtins::BUILTINS.iter().map(|(name, _)| name.to_string()));
+            names.extend(KEYWORDS.iter().map(|(keyword, _)| keyword.to_string()));
+            names
+        },
+    };
+    candidates.retain(|candidate| candidate.starts_with(prefix));
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
+
+fn word_start(text: &str) -> usize {
+    text.rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .map_or(0, |i| i + text[i..].chars().next().unwrap().len_utf8())
+}
+
+fn members(value: &Object) -> Vec<String> {
+    match value {
+        Object::Module(module) => module.env.borrow().bindings().into_iter().map(|(name, _)| name).collect(),
+        Object::Struct(s) => s.definition.fields.iter().map(|field| field.to_string()).collect(),
+        other => eval::builtins::methods(other.type_name()).iter().map(|name| name.to_string()).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object::shared::{Rc, RefCell};
+
+    fn env(input: &str) -> Rc<RefCell<Environment>> {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        crate::eval_line(input, &env);
+        env
+    }
+
+    fn complete_end(line: &str, env: &Rc<RefCell<Environment>>) -> (usize, Vec<String>) {
+        complete(line, line.len(), &env.borrow())
+    }
+
+    #[test]
+    fn test_complete_names() {
+        let env = env("let total = 1; let tally = 2;");
+        assert_eq!(complete_end("1 + t", &env), (4, ["tally", "throw", "total", "trim", "true", "try", "type"].map(String::from).to_vec()));
+        assert_eq!(complete_end("le", &env), (0, vec!["len".to_string(), "let".to_string()]));
+        assert_eq!(complete_end("zzz", &env), (0, vec![]));
+    }
+
+    #[test]
+    fn test_complete_members() {
+        let env = env("let xs = [1, 2]; struct Point { x, y }; let p = Point { x: 1, y: 2 };");
+        let (start, candidates) = complete_end("xs.p", &env);
+        assert_eq!(start, 3);
+        assert!(candidates.contains(&"push".to_string()));
+        assert!(candidates.iter().all(|name| name.starts_with('p')));
+        assert_eq!(complete_end("p.", &env), (2, vec!["x".to_string(), "y".to_string()]));
+        assert_eq!(complete_end("missing.", &env), (8, vec![]));
+        assert_eq!(complete_end("1.", &env), (2, vec![]));
+    }
+}
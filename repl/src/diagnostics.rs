@@ -0,0 +1,290 @@
+use lexer::LexError;
+use object::RuntimeError;
+use parser::ParseError;
+use token::{Span, Symbol, TokenType};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// An error or warning ready to be shown to the user, optionally pointing
+// into the source it came from
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    // The module the span points into, when it is not the source rendered
+    pub module: Option<Symbol>,
+    // Number of columns to underline, at least one
+    pub width: usize,
+    pub help: Option<String>,
+    // Extra lines after the snippet, such as the frames of a stack trace
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: String) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, message, span: None, module: None, width: 1, help: None, notes: Vec::new() }
+    }
+
+    pub fn warning(message: String) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, ..Diagnostic::error(message) }
+    }
+
+    pub fn with_span(mut self, span: Span, width: usize) -> Diagnostic {
+        self.span = Some(span);
+        self.width = width.max(1);
+        self
+    }
+
+    pub fn in_module(mut self, module: Symbol) -> Diagnostic {
+        self.module = Some(module);
+        self
+    }
+
+    pub fn with_help(mut self, help: &str) -> Diagnostic {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Diagnostic {
+        self.notes.push(note);
+        self
+    }
+
+    // Renders in the style of rustc:
+    //
+    // error: expected next token to be Identifier, got Assign
+    //  --> line 1, col 5
+    //   |
+    // 1 | let = 1;
+    //   |     ^
+    pub fn render(&self, source: &str) -> String {
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let mut out = format!("{}: {}", label, self.message);
+        let span = match self.span {
+            Some(span) => span,
+            None => {
+                if let Some(help) = &self.help {
+                    out.push_str(&format!("\n  = help: {}", help));
+                }
+                for note in &self.notes {
+                    out.push_str(&format!("\n  = note: {}", note));
+                }
+                return out;
+            },
+        };
+
+        // Only the standard library's modules have their source at hand
+        let source = match &self.module {
+            Some(module) => eval::stdlib::source(module.as_str()).unwrap_or_default(),
+            None => source,
+        };
+        let gutter = " ".repeat(span.line.to_string().len());
+        out.push_str(&format!("\n{}--> {}", gutter, object::location(span, self.module)));
+        if let Some(line) = source.lines().nth(span.line.saturating_sub(1)) {
+            out.push_str(&format!("\n{} |", gutter));
+            out.push_str(&format!("\n{} | {}", span.line, line));
+            out.push_str(&format!("\n{} | {}{}", gutter, " ".repeat(span.column.saturating_sub(1)), "^".repeat(self.width)));
+        }
+        if let Some(help) = &self.help {
+            out.push_str(&format!("\n{} = help: {}", gutter, help));
+        }
+        for note in &self.notes {
+            out.push_str(&format!("\n{} = note: {}", gutter, note));
+        }
+        out
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(err: &ParseError) -> Diagnostic {
+        let token = err.token();
+        // Only the first line of a token that spans several is underlined
+        let width = match token.ttype {
+            TokenType::Eof => 1,
+            _ => token.literal.lines().next().map_or(1, |line| line.chars().count().max(1)),
+        };
+        let diagnostic = Diagnostic::error(err.message()).with_span(err.span(), width);
+
+        match err {
+            ParseError::UnexpectedEof { .. } => diagnostic.with_help("the input ended before the statement was complete"),
+            ParseError::InvalidAssignmentTarget { .. } => diagnostic.with_help("only variables and the elements or members of what they hold can be assigned to"),
+            ParseError::InvalidIncrementTarget { .. } => diagnostic.with_help("only variables can be incremented or decremented"),
+            ParseError::InvalidPrefixOperand { .. } => diagnostic.with_help("prefix operators cannot be applied to string literals"),
+            ParseError::InvalidPattern(_) => diagnostic.with_help("patterns are literals, ranges of integers or characters, or _"),
+            ParseError::IntegerOutOfRange { .. } => diagnostic.with_help(&format!("integers must fit in 64 bits, at most {}", i64::MAX)),
+            ParseError::Illegal { error: LexError::UnterminatedString, token } => {
+                let quote = if token.literal.starts_with('`') { '`' } else { '"' };
+                diagnostic.with_help(&format!("the string runs to the end of the input, close it with {}", quote))
+            },
+            ParseError::Illegal { error: LexError::UnterminatedComment, .. } => diagnostic.with_help("block comments are closed with */"),
+            ParseError::Illegal { error: LexError::BadEscape(_), .. } => diagnostic.with_help(r#"the escapes are \n, \t, \r, \0, \\, \' and \""#),
+            ParseError::Illegal { error: LexError::BadCharLiteral, .. } => diagnostic.with_help("strings of any length are written in double quotes"),
+            _ => diagnostic,
+        }
+    }
+}
+
+// Points at the failing expression and lists the enclosing calls,
+// innermost first
+impl From<&RuntimeError> for Diagnostic {
+    fn from(err: &RuntimeError) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(err.error.to_string());
+        if let Some(span) = err.span {
+            diagnostic = diagnostic.with_span(span, 1);
+        }
+        if let Some(module) = err.module {
+            diagnostic = diagnostic.in_module(module);
+        }
+        for (frame, count) in err.frames() {
+            diagnostic = diagnostic.with_note(frame.describe(count));
+        }
+        diagnostic
+    }
+}
+
+pub fn render_parse_errors(source: &str, errors: &[ParseError]) -> String {
+    let rendered: Vec<String> = errors.iter().map(|err| Diagnostic::from(err).render(source)).collect();
+    rendered.join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn parse_errors(input: &str) -> String {
+        let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+        render_parse_errors(input, &errors)
+    }
+
+    #[test]
+    fn test_render() {
+        let tests = vec![
+            ("let = 1;", "\
+error: expected next token to be Identifier, got Assign
+ --> line 1, col 5
+  |
+1 | let = 1;
+  |     ^"),
+            ("let x = 1;\nlet y 2;", "\
+error: expected next token to be Assign, got Integer
+ --> line 2, col 7
+  |
+2 | let y 2;
+  |       ^"),
+            ("[1, 2", "\
+error: expected next token to be RightSquareBracket, got end of input
+ --> line 1, col 6
+  |
+1 | [1, 2
+  |      ^
+  = help: the input ended before the statement was complete"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(parse_errors(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_render_lex_errors() {
+        let tests = vec![
+            ("let s = \"open\nmore;", "\
+error: unterminated string
+ --> line 1, col 9
+  |
+1 | let s = \"open
+  |         ^^^^^
+  = help: the string runs to the end of the input, close it with \""),
+            ("let c = '\\q';", "\
+error: unknown escape \\q in character literal
+ --> line 1, col 9
+  |
+1 | let c = '\\q';
+  |         ^^^^
+  = help: the escapes are \\n, \\t, \\r, \\0, \\\\, \\' and \\\""),
+            ("1 ~ 2", "\
+error: unknown character '~'
+ --> line 1, col 3
+  |
+1 | 1 ~ 2
+  |   ^"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(parse_errors(input), expected, "{}", input);
+        }
+    }
+
+    // With big integers the literal is valid
+    #[test]
+    #[cfg(not(feature = "bigint"))]
+    fn test_render_invalid_integer() {
+        assert_eq!(parse_errors("let x = 99999999999999999999;"), "\
+error: integer literal 99999999999999999999 is out of range
+ --> line 1, col 9
+  |
+1 | let x = 99999999999999999999;
+  |         ^^^^^^^^^^^^^^^^^^^^
+  = help: integers must fit in 64 bits, at most 9223372036854775807");
+    }
+
+    #[test]
+    fn test_render_without_span() {
+        let diagnostic = Diagnostic::error("division by zero".to_string());
+        assert_eq!(diagnostic.render("1 / 0"), "error: division by zero");
+        let diagnostic = diagnostic.with_help("check the divisor first");
+        assert_eq!(diagnostic.render("1 / 0"), "error: division by zero\n  = help: check the divisor first");
+    }
+
+    #[test]
+    fn test_render_warning() {
+        let diagnostic = Diagnostic::warning("unused binding x".to_string())
+            .with_span(Span { line: 1, column: 5 }, 1)
+            .with_note("lint: unused_binding".to_string());
+        assert_eq!(diagnostic.render("let x = 1;"), "warning: unused binding x\n --> line 1, col 5\n  |\n1 | let x = 1;\n  |     ^\n  = note: lint: unused_binding");
+    }
+
+    #[test]
+    fn test_gutter_width() {
+        let source = format!("{}let = 1;", "\n".repeat(11));
+        let diagnostic = Diagnostic::error("oops".to_string()).with_span(Span { line: 12, column: 5 }, 1);
+        assert_eq!(diagnostic.render(&source), "error: oops\n  --> line 12, col 5\n   |\n12 | let = 1;\n   |     ^");
+    }
+
+    #[test]
+    fn test_render_runtime_error() {
+        let source = "let f = fn(x) { x + true };\nf(1);";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let env = object::shared::Rc::new(object::shared::RefCell::new(object::Environment::new()));
+        let err = eval::eval_program(&program, &env).unwrap_err();
+        assert_eq!(Diagnostic::from(&err).render(source), "\
+error: type mismatch: INTEGER + BOOLEAN
+ --> line 1, col 19
+  |
+1 | let f = fn(x) { x + true };
+  |                   ^
+  = note: in f called at line 2, col 2");
+
+        // Errors in the standard library show its source
+        let source = "import \"std/functional\";\nfunctional.map(1, fn(x) { x });";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let err = eval::eval_program(&program, &env).unwrap_err();
+        assert_eq!(Diagnostic::from(&err).render(source), "\
+error: cannot iterate over INTEGER
+  --> line 10, col 18 of std/functional
+   |
+10 |     for (item in items) {
+   |                  ^
+   = note: in functional.map called at line 2, col 15");
+    }
+}
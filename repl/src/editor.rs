@@ -0,0 +1,104 @@
+use object::Environment;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use std::borrow::Cow;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use object::shared::{Rc, RefCell};
+use crate::color::Colors;
+use crate::{completion, Line, LineSource};
+
+const HISTORY_FILE: &str = ".interpreter_history";
+
+// Line editing and history for a terminal session
+pub struct Editor {
+    editor: Box<rustyline::Editor<Helper, DefaultHistory>>,
+    history: Option<PathBuf>,
+    colors: Colors,
+}
+
+impl Editor {
+    // None when stdin is piped in rather than a terminal
+    pub fn new(colors: Colors) -> Option<Editor> {
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+        let mut editor = rustyline::Editor::new().ok()?;
+        let history = history_path();
+        if let Some(path) = &history {
+            // A missing history file just means a fresh start
+            let _ = editor.load_history(path);
+        }
+        Some(Editor { editor: Box::new(editor), history, colors })
+    }
+}
+
+impl LineSource for Editor {
+    fn set_environment(&mut self, env: &Rc<RefCell<Environment>>) {
+        self.editor.set_helper(Some(Helper { env: Rc::clone(env), colors: self.colors }));
+    }
+
+    // The editor draws its own prompt on the terminal
+    fn read_line(&mut self, prompt: &str, _output: &mut dyn Write) -> Line {
+        match self.editor.readline(prompt) {
+            Ok(line) => Line::Text(line + "\n"),
+            Err(ReadlineError::Interrupted) => Line::Interrupted,
+            Err(_) => Line::Eof,
+        }
+    }
+
+    fn add_history(&mut self, entry: &str) {
+        let _ = self.editor.add_history_entry(entry.trim_end());
+    }
+
+    fn save_history(&mut self) {
+        if let Some(path) = &self.history {
+            let _ = self.editor.save_history(path);
+        }
+    }
+}
+
+// Completes on Tab from the session's environment and highlights the line
+// being typed
+pub struct Helper {
+    env: Rc<RefCell<Environment>>,
+    colors: Colors,
+}
+
+impl Completer for Helper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(completion::complete(line, pos, &self.env.borrow()))
+    }
+}
+
+impl Hinter for Helper {
+    type Hint = String;
+}
+
+impl Highlighter for Helper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        match self.colors.enabled {
+            true => Cow::Owned(self.colors.highlight(line)),
+            false => Cow::Borrowed(line),
+        }
+    }
+
+    // Every edit can change how the rest of the line lexes
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        self.colors.enabled && kind != CmdKind::MoveCursor
+    }
+}
+
+impl Validator for Helper {}
+
+impl rustyline::Helper for Helper {}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
@@ -1,20 +1,432 @@
-use lexer::Lexer;
-use std::io::{Stdin, Stdout, Write, BufRead, BufReader};
+pub mod color;
+pub mod completion;
+pub mod diagnostics;
+#[cfg(feature = "terminal")]
+mod editor;
+
+use lexer::{LexError, Lexer};
+use token::TokenType;
+use parser::{Parser, ParseError};
+use object::{Environment, InspectOptions, Input, Io, Object, Output};
+use std::io::Write;
+use object::shared::{Rc, RefCell};
+use color::Colors;
+use diagnostics::Diagnostic;
 
 const PROMPT: &str = ">> ";
+const CONTINUATION_PROMPT: &str = ".. ";
 
-pub fn start(input: Stdin, mut output: Stdout) {
-    let mut scanner = BufReader::new(input);
+pub enum Line {
+    Text(String),
+    // Ctrl-C discards whatever has been typed so far
+    Interrupted,
+    Eof,
+}
 
-    loop {
-        print!("{}", PROMPT);
+// Where the loop gets its input lines from
+trait LineSource {
+    // Lines are returned with their trailing newline
+    fn read_line(&mut self, prompt: &str, output: &mut dyn Write) -> Line;
+    // The environment the session evaluates in, for completion
+    fn set_environment(&mut self, _env: &Rc<RefCell<Environment>>) {}
+    fn add_history(&mut self, _entry: &str) {}
+    fn save_history(&mut self) {}
+}
+
+// Prompts on the output and reads whole lines, with no editing
+struct PlainLines(Rc<RefCell<dyn Input>>);
+
+impl LineSource for PlainLines {
+    fn read_line(&mut self, prompt: &str, output: &mut dyn Write) -> Line {
+        write!(output, "{}", prompt).unwrap();
         output.flush().unwrap();
         let mut line = String::new();
-        scanner.read_line(&mut line).unwrap();
-        let lexer = Lexer::new(line);
+        match self.0.borrow_mut().read_line(&mut line) {
+            Ok(0) | Err(_) => Line::Eof,
+            Ok(_) => Line::Text(line),
+        }
+    }
+}
+
+// Runs the loop over any pair of streams. Scripts share them, so what they
+// print lands in the same output as the results.
+pub fn start(input: impl Input + 'static, output: impl Output + 'static) {
+    start_with_io(Io::new(input, output));
+}
+
+// For hosts that keep a handle on the streams, such as a shared buffer
+pub fn start_with_io(io: Io) {
+    run(&mut PlainLines(Rc::clone(&io.input)), io, Colors::PLAIN);
+}
+
+// The interactive loop on stdin and stdout, with line editing and history
+// when attached to a terminal
+#[cfg(feature = "terminal")]
+pub fn start_terminal(colors: Colors) {
+    match editor::Editor::new(colors) {
+        Some(mut editor) => run(&mut editor, Io::stdio(), colors),
+        None => {
+            let io = Io::stdio();
+            run(&mut PlainLines(Rc::clone(&io.input)), io, colors)
+        },
+    }
+}
+
+fn run(lines: &mut dyn LineSource, io: Io, colors: Colors) {
+    let mut session = Session::new();
+    session.colors = colors;
+    session.env.borrow_mut().set_io(io.clone());
+    lines.set_environment(&session.env);
+    let output = io.output;
+
+    let mut buffer = String::new();
+
+    loop {
+        let prompt = if buffer.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+        let line = match lines.read_line(prompt, &mut *output.borrow_mut()) {
+            Line::Text(line) => line,
+            Line::Interrupted => {
+                buffer.clear();
+                continue;
+            },
+            Line::Eof => break,
+        };
+
+        if buffer.is_empty() && line.trim_start().starts_with(':') {
+            lines.add_history(&line);
+            match run_command(line.trim(), &mut session) {
+                Action::Print(text) => writeln!(output.borrow_mut(), "{}", text).unwrap(),
+                Action::Quit => break,
+            }
+            continue;
+        }
+
+        // A blank continuation line gives up waiting and reports the errors
+        let blank = line.trim().is_empty();
+        buffer.push_str(&line);
+        if !blank && is_incomplete(&buffer) {
+            continue;
+        }
+
+        if !buffer.trim().is_empty() {
+            lines.add_history(&buffer);
+            // Evaluated before borrowing the output, which scripts write to
+            let result = session.eval(&buffer);
+            writeln!(output.borrow_mut(), "{}", result).unwrap();
+        }
+        buffer.clear();
+    }
+    lines.save_history();
+}
+
+// What the loop keeps between inputs
+pub struct Session {
+    // Shared by every line so bindings survive between inputs
+    pub env: Rc<RefCell<Environment>>,
+    // Inputs that evaluated without errors, in order, for :save
+    inputs: Vec<String>,
+    // For what the session prints, off unless set
+    pub colors: Colors,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session { env: Rc::new(RefCell::new(Environment::new())), inputs: Vec::new(), colors: Colors::PLAIN }
+    }
+
+    // Evaluates one complete input, as eval_line, keeping it if it succeeds
+    // along with its value unless that is null
+    pub fn eval(&mut self, input: &str) -> String {
+        match eval_input(input, &self.env) {
+            Ok(value) => {
+                self.inputs.push(input.to_string());
+                let text = value.pretty(&InspectOptions::default());
+                if value != Object::Null {
+                    self.env.borrow_mut().push_result(value);
+                }
+                self.colors.result(&text)
+            },
+            Err(error) => self.colors.diagnostic(&error),
+        }
+    }
+
+    // Writes the inputs kept so far, so that loading the file rebuilds the
+    // session
+    pub fn save(&self, path: &str) -> std::io::Result<usize> {
+        let mut source = String::new();
+        for input in &self.inputs {
+            source.push_str(input);
+            if !input.ends_with('\n') {
+                source.push('\n');
+            }
+        }
+        std::fs::write(path, source)?;
+        Ok(self.inputs.len())
+    }
+
+    // Evaluates a file into the session's environment
+    pub fn load(&mut self, path: &str) -> Result<(), String> {
+        let source = std::fs::read_to_string(path).map_err(|err| format!("error: cannot read {}: {}", path, err))?;
+        eval_input(&source, &self.env)?;
+        self.inputs.push(source);
+        Ok(())
+    }
+}
+
+pub enum Action {
+    Print(String),
+    Quit,
+}
+
+// Dispatches a `:name argument` meta-command
+pub fn run_command(line: &str, session: &mut Session) -> Action {
+    let env = &session.env;
+    let colors = session.colors;
+    let (name, argument) = match line.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, argument.trim()),
+        None => (line, ""),
+    };
+
+    let text = match name {
+        ":tokens" => colors.dim(lexer::token_table(argument).trim_end()),
+        ":ast" => {
+            let mut parser = Parser::new(Lexer::new(argument));
+            match parser.parse_program() {
+                Ok(program) => {
+                    let statements: Vec<String> = program.statements.iter().map(|s| s.to_string()).collect();
+                    colors.dim(&statements.join("\n"))
+                },
+                Err(errors) => colors.diagnostic(&diagnostics::render_parse_errors(argument, &errors)),
+            }
+        },
+        ":env" => {
+            let bindings: Vec<String> = env.borrow().bindings().iter()
+                .map(|(name, value)| format!("{} = {}", name, value.inspect()))
+                .collect();
+            bindings.join("\n")
+        },
+        ":history" => {
+            let results: Vec<String> = env.borrow().results().iter().enumerate()
+                .map(|(i, value)| format!("_{} = {}", i + 1, value.inspect()))
+                .collect();
+            results.join("\n")
+        },
+        ":reset" => {
+            // Output keeps going to the same place
+            let io = env.borrow().io();
+            *env.borrow_mut() = Environment::new();
+            env.borrow_mut().set_io(io);
+            session.inputs.clear();
+            "environment cleared".to_string()
+        },
+        ":save" | ":load" if argument.is_empty() => format!("usage: {} <file>", name),
+        ":save" => match session.save(argument) {
+            Ok(count) => format!("saved {} inputs to {}", count, argument),
+            Err(err) => format!("error: cannot write {}: {}", argument, err),
+        },
+        ":load" => match session.load(argument) {
+            Ok(()) => format!("loaded {}", argument),
+            Err(error) => colors.diagnostic(&error),
+        },
+        ":quit" => return Action::Quit,
+        _ => format!("unknown command {} (available: :tokens, :ast, :env, :history, :reset, :save, :load, :quit)", name),
+    };
+    Action::Print(text)
+}
+
+// Input is incomplete while it has unclosed brackets or strings, or the
+// parser ran out of tokens before finishing a statement
+pub fn is_incomplete(input: &str) -> bool {
+    let mut depth = 0;
+    for token in Lexer::new(input) {
+        match token.ttype {
+            TokenType::LeftParen | TokenType::LeftSquareBracket | TokenType::LeftCurlyBracket => depth += 1,
+            TokenType::RightParen | TokenType::RightSquareBracket | TokenType::RightCurlyBracket => depth -= 1,
+            // Strings run on to the end of the input until they are closed
+            TokenType::Illegal if token.error == Some(LexError::UnterminatedString) => return true,
+            _ => {},
+        }
+    }
+    if depth > 0 {
+        return true;
+    }
+
+    let mut parser = Parser::new(Lexer::new(input));
+    match parser.parse_program() {
+        Ok(_) => false,
+        Err(errors) => errors.iter().any(|err| matches!(err, ParseError::UnexpectedEof { .. })),
+    }
+}
+
+// What the REPL prints for one complete input: the value, or the errors
+// rendered against the source
+pub fn eval_line(line: &str, env: &Rc<RefCell<Environment>>) -> String {
+    match eval_input(line, env) {
+        Ok(value) => value.pretty(&InspectOptions::default()),
+        Err(error) => error,
+    }
+}
+
+fn eval_input(line: &str, env: &Rc<RefCell<Environment>>) -> Result<Object, String> {
+    let mut parser = Parser::new(Lexer::new(line));
+    let program = parser.parse_program().map_err(|errors| diagnostics::render_parse_errors(line, &errors))?;
+
+    match eval::eval_program(&program, env) {
+        Ok(value) => Ok(value),
+        Err(err) => Err(Diagnostic::from(&err).render(line)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_line() {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        let tests = vec![
+            ("let x = 5;", "null"),
+            ("x * 2", "10"),
+            ("let f = fn(y) { x + y };", "null"),
+            ("f(1)", "6"),
+            ("\"a\" + 1", "error: type mismatch: STRING + INTEGER\n --> line 1, col 5\n  |\n1 | \"a\" + 1\n  |     ^"),
+            ("let = 1;", "error: expected next token to be Identifier, got Assign\n --> line 1, col 5\n  |\n1 | let = 1;\n  |     ^"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval_line(input, &env), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut session = Session::new();
+        session.eval("let b = [1, 2]; let a = 1;");
+
+        let tests = vec![
+            (":env", "a = 1\nb = [1, 2]"),
+            (":ast 1 + 2 * 3", "(1 + (2 * 3));"),
+            (":ast let x = -a; x", "let x = (-a);\nx;"),
+            (":tokens x;", "type        literal  position  bytes\nIdentifier  \"x\"      1:1       0..1\nSemicolon   \";\"      1:2       1..2\nEof         \"\\0\"     1:3       2..2"),
+            (":nope", "unknown command :nope (available: :tokens, :ast, :env, :history, :reset, :save, :load, :quit)"),
+            (":save", "usage: :save <file>"),
+            (":reset", "environment cleared"),
+            (":env", ""),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(command(input, &mut session), expected, "{}", input);
+        }
+        assert!(matches!(run_command(":quit", &mut session), Action::Quit));
+    }
+
+    fn command(line: &str, session: &mut Session) -> String {
+        match run_command(line, session) {
+            Action::Print(text) => text,
+            Action::Quit => panic!("unexpected quit for {}", line),
+        }
+    }
+
+    #[test]
+    fn test_result_history() {
+        let mut session = Session::new();
+        let tests = vec![
+            ("1 + 2", "3"),
+            ("let x = 10;", "null"),
+            ("_ * 2", "6"),
+            ("_1 + _2 + _", "15"),
+            ("\"a\" + 1", "error: type mismatch: STRING + INTEGER\n --> line 1, col 5\n  |\n1 | \"a\" + 1\n  |     ^"),
+            ("_", "15"),
+            ("let _1 = \"mine\"; _1", "\"mine\""),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(session.eval(input), expected, "{}", input);
+        }
+        assert!(session.eval("_9").starts_with("error: identifier not found: _9"));
+        assert!(session.eval("_0").starts_with("error: identifier not found: _0"));
+        assert_eq!(command(":history", &mut session), "_1 = 3\n_2 = 6\n_3 = 15\n_4 = 15\n_5 = mine");
+        command(":reset", &mut session);
+        assert_eq!(command(":history", &mut session), "");
+    }
+
+    #[test]
+    fn test_colors() {
+        let mut session = Session::new();
+        session.colors = Colors { enabled: true };
+        assert_eq!(session.eval("1 + 1"), "\x1b[32m2\x1b[0m");
+        assert!(session.eval("1 + true").starts_with("\x1b[1m\x1b[31merror\x1b[0m"));
+        assert_eq!(command(":ast 1", &mut session), "\x1b[2m1;\x1b[0m");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("repl-session-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("session.mky").to_string_lossy().into_owned();
+
+        let mut session = Session::new();
+        session.eval("let a = 2;\n");
+        session.eval("let b = a +;\n");
+        session.eval("missing\n");
+        session.eval("let double = fn(x) { x * a };");
+        assert_eq!(command(&format!(":save {}", path), &mut session), format!("saved 2 inputs to {}", path));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "let a = 2;\nlet double = fn(x) { x * a };\n");
+
+        let mut loaded = Session::new();
+        assert_eq!(command(&format!(":load {}", path), &mut loaded), format!("loaded {}", path));
+        assert_eq!(loaded.eval("double(5)"), "10");
+        // Loaded files are saved along with the session
+        assert_eq!(command(&format!(":save {}", path), &mut loaded), format!("saved 2 inputs to {}", path));
+
+        let missing = dir.join("missing.mky").to_string_lossy().into_owned();
+        assert!(command(&format!(":load {}", missing), &mut loaded).starts_with(&format!("error: cannot read {}: ", missing)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_start() {
+        let input = "let add = fn(a, b) {\n  a + b\n};\nputs(add(1, 2))\n:reset\nprint(\"x\"); add\n";
+        let output = Rc::new(RefCell::new(Vec::new()));
+        start_with_io(Io { input: Rc::new(RefCell::new(input.as_bytes())), output: output.clone() });
+
+        let output = String::from_utf8(output.take()).unwrap();
+        assert_eq!(output, ">> .. .. null\n>> 3\nnull\n>> environment cleared\n>> xerror: identifier not found: add
+ --> line 1, col 13
+  |
+1 | print(\"x\"); add
+  |             ^
+>> ");
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        let tests = vec![
+            ("fn(x) {", true),
+            ("fn(x) {\n x + 1", true),
+            ("fn(x) {\n x + 1\n}", false),
+            ("[1, 2,", true),
+            ("len(", true),
+            ("let x =", true),
+            ("1 +", true),
+            ("1 + 2", false),
+            ("let = 1", false),
+            ("\"{\"", false),
+            ("let s = \"first\nsecond", true),
+            ("`raw", true),
+            ("r\"raw\n", true),
+            ("\"a ${f(\"b\"", true),
+            ("let s = `a\nb`", false),
+            ("}", false),
+        ];
 
-        for token in lexer {
-            println!("{:?}", token);
+        for (input, expected) in tests {
+            assert_eq!(is_incomplete(input), expected, "{}", input);
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,139 @@
+// Documentation for a script, from the `///` comments on the top-level
+// bindings it makes with let. Every function is listed, other values only
+// when they have docs.
+
+use std::fmt::Write;
+use ast::{Expression, Program, Statement};
+
+#[derive(Debug, PartialEq)]
+pub struct Item {
+    pub name: String,
+    // The parameters and return type of a function, None for other values
+    pub signature: Option<String>,
+    // The type a value is annotated with
+    pub annotation: Option<String>,
+    pub docs: Option<String>,
+}
+
+impl Item {
+    // How the item is written in headings: `add(a, b) -> int`, `limit: int`
+    fn heading(&self) -> String {
+        match (&self.signature, &self.annotation) {
+            (Some(signature), _) => format!("{}{}", self.name, signature),
+            (None, Some(annotation)) => format!("{}: {}", self.name, annotation),
+            (None, None) => self.name.clone(),
+        }
+    }
+}
+
+// In the order the script binds them
+pub fn items(program: &Program) -> Vec<Item> {
+    program.statements.iter()
+        .filter_map(|statement| match statement {
+            Statement::LetStatement(statement) => Some(statement),
+            _ => None,
+        })
+        .filter_map(|statement| {
+            let signature = match &statement.value {
+                Expression::FunctionLiteral(function) => {
+                    let returns = function.return_type.as_ref().map(|returns| format!(" -> {}", returns)).unwrap_or_default();
+                    Some(format!("({}){}", function.signature(), returns))
+                },
+                _ => None,
+            };
+            if signature.is_none() && statement.docs.is_none() {
+                return None;
+            }
+            Some(Item {
+                name: statement.name.to_string(),
+                signature,
+                annotation: statement.annotation.as_ref().map(|annotation| annotation.to_string()),
+                docs: statement.docs.as_deref().map(String::from),
+            })
+        })
+        .collect()
+}
+
+// The docs are Markdown already, so they go in as written
+pub fn markdown(title: &str, items: &[Item]) -> String {
+    let mut out = format!("# {}\n", title);
+    for item in items {
+        write!(out, "\n## `{}`\n", item.heading()).unwrap();
+        if let Some(docs) = &item.docs {
+            write!(out, "\n{}\n", docs).unwrap();
+        }
+    }
+    out
+}
+
+// A page of its own, with the docs as plain paragraphs split at blank lines
+pub fn html(title: &str, items: &[Item]) -> String {
+    let mut out = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    writeln!(out, "<title>{}</title>\n</head>\n<body>\n<h1>{}</h1>", escape(title), escape(title)).unwrap();
+    for item in items {
+        writeln!(out, "<h2 id=\"{}\"><code>{}</code></h2>", escape(&item.name), escape(&item.heading())).unwrap();
+        for paragraph in item.docs.iter().flat_map(|docs| docs.split("\n\n")) {
+            writeln!(out, "<p>{}</p>", escape(paragraph.trim())).unwrap();
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    const SOURCE: &str = "/// Adds the two.
+///
+/// Both have to be <ints>.
+let add = fn(a: int, b: int) -> int { a + b };
+let twice = fn(f, x) { f(f(x)) };
+/// How many there can be
+let limit: int = 10;
+let hidden = 1;
+/// Not bound
+puts(limit);
+";
+
+    #[test]
+    fn test_items() {
+        let program = Parser::new(Lexer::new(SOURCE)).parse_program().unwrap();
+        let names: Vec<String> = items(&program).iter().map(Item::heading).collect();
+        assert_eq!(names, vec!["add(a: int, b: int) -> int", "twice(f, x)", "limit: int"]);
+    }
+
+    #[test]
+    fn test_markdown() {
+        let program = Parser::new(Lexer::new(SOURCE)).parse_program().unwrap();
+        assert_eq!(markdown("math", &items(&program)), "# math
+
+## `add(a: int, b: int) -> int`
+
+Adds the two.
+
+Both have to be <ints>.
+
+## `twice(f, x)`
+
+## `limit: int`
+
+How many there can be
+");
+    }
+
+    #[test]
+    fn test_html() {
+        let program = Parser::new(Lexer::new(SOURCE)).parse_program().unwrap();
+        let page = html("math", &items(&program));
+        assert!(page.starts_with("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>math</title>"), "{}", page);
+        assert!(page.contains("<h2 id=\"add\"><code>add(a: int, b: int) -&gt; int</code></h2>\n<p>Adds the two.</p>\n<p>Both have to be &lt;ints&gt;.</p>\n<h2 id=\"twice\">"), "{}", page);
+        assert!(page.ends_with("<p>How many there can be</p>\n</body>\n</html>\n"), "{}", page);
+    }
+}
@@ -1,7 +1,641 @@
-use std::io::{stdin, stdout};
+use std::io::{stdin, Read};
+use std::path::Path;
+use std::process::ExitCode;
+use object::shared::{Rc, RefCell};
+use lexer::Lexer;
+use parser::Parser;
+use object::{Object, Environment, InspectOptions, Profiler, Trace, WriteTracer};
+use compiler::{Bytecode, Compiler};
+use repl::color::Colors;
+use repl::diagnostics::Diagnostic;
 
-fn main() {
-    println!("Monkey REPL");
-    println!("Feel free to type in commands.");
-    repl::start(stdin(), stdout());
+mod doc;
+mod testing;
+
+const USAGE: &str = "usage: interpreter [--trace | --profile] [--no-color] [run <script> | -e <source> | - | compile <script> [-o <output>] | fmt [--check] <script> | parse [--json] <script> | ast [--dot] <script> | tokens <script> | lint <script> | check <path>... | test <path> | doc [--html] <script>]";
+
+#[derive(Debug, PartialEq)]
+enum Mode {
+    Repl,
+    Run(String),
+    Eval(String),
+    Stdin,
+    Compile { path: String, output: String },
+    Fmt { path: String, check: bool },
+    Parse { path: String, json: bool },
+    Ast { path: String, dot: bool },
+    Tokens(String),
+    Lint(String),
+    Check(Vec<String>),
+    Test(String),
+    Doc { path: String, html: bool },
+}
+
+fn parse_args(args: &[String]) -> Result<Mode, String> {
+    match args {
+        [] => Ok(Mode::Repl),
+        [command, path] if command == "run" && path == "-" => Ok(Mode::Stdin),
+        [command, path] if command == "run" => Ok(Mode::Run(path.clone())),
+        [flag, source] if flag == "-e" => Ok(Mode::Eval(source.clone())),
+        [dash] if dash == "-" => Ok(Mode::Stdin),
+        [command, path] if command == "compile" => {
+            Ok(Mode::Compile { path: path.clone(), output: Path::new(path).with_extension("mkyc").display().to_string() })
+        },
+        [command, path, flag, output] | [command, flag, output, path] if command == "compile" && flag == "-o" => {
+            Ok(Mode::Compile { path: path.clone(), output: output.clone() })
+        },
+        [command, path] if command == "fmt" => Ok(Mode::Fmt { path: path.clone(), check: false }),
+        [command, flag, path] | [command, path, flag] if command == "fmt" && flag == "--check" => {
+            Ok(Mode::Fmt { path: path.clone(), check: true })
+        },
+        [command, path] if command == "parse" => Ok(Mode::Parse { path: path.clone(), json: false }),
+        [command, flag, path] | [command, path, flag] if command == "parse" && flag == "--json" => {
+            Ok(Mode::Parse { path: path.clone(), json: true })
+        },
+        [command, path] if command == "ast" => Ok(Mode::Ast { path: path.clone(), dot: false }),
+        [command, flag, path] | [command, path, flag] if command == "ast" && flag == "--dot" => {
+            Ok(Mode::Ast { path: path.clone(), dot: true })
+        },
+        [command, path] if command == "tokens" => Ok(Mode::Tokens(path.clone())),
+        [command, path] if command == "lint" => Ok(Mode::Lint(path.clone())),
+        [command, paths @ ..] if command == "check" && !paths.is_empty() => Ok(Mode::Check(paths.to_vec())),
+        [command, path] if command == "test" => Ok(Mode::Test(path.clone())),
+        [command, path] if command == "doc" => Ok(Mode::Doc { path: path.clone(), html: false }),
+        [command, flag, path] | [command, path, flag] if command == "doc" && flag == "--html" => {
+            Ok(Mode::Doc { path: path.clone(), html: true })
+        },
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+// Errors come back already formatted for the terminal. Imports are looked
+// up relative to modules.
+fn run(source: &str, modules: &Path, trace: Option<Trace>) -> Result<Object, String> {
+    let mut parser = Parser::new(Lexer::new(source));
+    let program = parser.parse_program().map_err(|errors| repl::diagnostics::render_parse_errors(source, &errors))?;
+    // Programs without annotations run as they always have
+    if typecheck::has_annotations(&program) {
+        typecheck::check(&program).map_err(|errors| render_type_errors(source, &errors))?;
+    }
+    let env = Rc::new(RefCell::new(Environment::new()));
+    env.borrow().modules().borrow_mut().set_root(modules);
+    if let Some(trace) = trace {
+        env.borrow_mut().set_trace(trace);
+    }
+    eval::eval_program(&program, &env).map_err(|err| Diagnostic::from(&err).render(source))
+}
+
+// Compiled programs run on the VM. Their source is not at hand, so errors
+// give the location without the line itself.
+fn run_bytecode(bytes: &[u8], trace: Option<Trace>) -> Result<Object, String> {
+    let bytecode = Bytecode::deserialize(bytes).map_err(|err| Diagnostic::error(err.to_string()).render(""))?;
+    let mut vm = vm::Vm::new();
+    if let Some(trace) = trace {
+        vm.set_trace(trace);
+    }
+    vm.run(bytecode).map_err(|err| Diagnostic::from(&err).render(""))
+}
+
+// Compiles the script for the VM, so it can be run without its source
+fn compile(path: &str, output: &str, colors: Colors) -> ExitCode {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("could not read {}: {}", path, err);
+            return ExitCode::FAILURE;
+        },
+    };
+    let bytes = match compile_source(&source) {
+        Ok(bytes) => bytes,
+        Err(message) => {
+            eprintln!("{}", colors.diagnostic(&message));
+            return ExitCode::FAILURE;
+        },
+    };
+    if let Err(err) = std::fs::write(output, bytes) {
+        eprintln!("could not write {}: {}", output, err);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn compile_source(source: &str) -> Result<Vec<u8>, String> {
+    let program = Parser::new(Lexer::new(source)).parse_program()
+        .map_err(|errors| repl::diagnostics::render_parse_errors(source, &errors))?;
+    if typecheck::has_annotations(&program) {
+        typecheck::check(&program).map_err(|errors| render_type_errors(source, &errors))?;
+    }
+    let bytecode = Compiler::new().compile(&program).map_err(|err| Diagnostic::error(err.to_string()).render(source))?;
+    Ok(bytecode.serialize())
+}
+
+fn render_type_errors(source: &str, errors: &[typecheck::TypeError]) -> String {
+    errors.iter()
+        .map(|error| Diagnostic::error(error.message.clone()).with_span(error.span, 1).render(source))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+// Canonical source for a program. Comments do not survive parsing, so
+// files containing them are left alone rather than silently stripped.
+fn format_source(source: &str) -> Result<String, String> {
+    if has_comments(source) {
+        return Err("cannot format source containing comments".to_string());
+    }
+    let mut parser = Parser::new(Lexer::new(source));
+    let program = parser.parse_program().map_err(|errors| repl::diagnostics::render_parse_errors(source, &errors))?;
+    Ok(ast::pretty(&program, 4))
+}
+
+// Asks the lexer, so comment markers inside strings of any kind are not
+// mistaken for comments
+fn has_comments(source: &str) -> bool {
+    lexer::highlight(source).iter().any(|span| span.kind == lexer::HighlightKind::Comment)
+}
+
+fn fmt(path: &str, check: bool, colors: Colors) -> ExitCode {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("could not read {}: {}", path, err);
+            return ExitCode::FAILURE;
+        },
+    };
+    let formatted = match format_source(&source) {
+        Ok(formatted) => formatted,
+        Err(message) => {
+            eprintln!("{}: {}", path, colors.diagnostic(&message));
+            return ExitCode::FAILURE;
+        },
+    };
+
+    if formatted == source {
+        return ExitCode::SUCCESS;
+    }
+    if check {
+        eprintln!("{} is not formatted", path);
+        return ExitCode::FAILURE;
+    }
+    if let Err(err) = std::fs::write(path, formatted) {
+        eprintln!("could not write {}: {}", path, err);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+// Prints the parse tree, as JSON for external tools or one statement per line
+fn parse(path: &str, json: bool, colors: Colors) -> ExitCode {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("could not read {}: {}", path, err);
+            return ExitCode::FAILURE;
+        },
+    };
+    let program = match Parser::new(Lexer::new(&source)).parse_program() {
+        Ok(program) => program,
+        Err(errors) => {
+            eprintln!("{}", colors.diagnostic(&repl::diagnostics::render_parse_errors(&source, &errors)));
+            return ExitCode::FAILURE;
+        },
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&program).unwrap());
+    } else {
+        for statement in &program.statements {
+            println!("{}", statement);
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+// Prints the tree the parser builds, as an S-expression or a Graphviz graph
+fn ast(path: &str, dot: bool, colors: Colors) -> ExitCode {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("could not read {}: {}", path, err);
+            return ExitCode::FAILURE;
+        },
+    };
+    match Parser::new(Lexer::new(&source)).parse_program() {
+        Ok(program) if dot => print!("{}", program.to_dot()),
+        Ok(program) => print!("{}", program.to_sexpr()),
+        Err(errors) => {
+            eprintln!("{}", colors.diagnostic(&repl::diagnostics::render_parse_errors(&source, &errors)));
+            return ExitCode::FAILURE;
+        },
+    }
+    ExitCode::SUCCESS
+}
+
+// Prints what the lexer makes of the script, errors included as Illegal
+// tokens
+fn tokens(path: &str) -> ExitCode {
+    match std::fs::read_to_string(path) {
+        Ok(source) => {
+            print!("{}", lexer::token_table(&source));
+            ExitCode::SUCCESS
+        },
+        Err(err) => {
+            eprintln!("could not read {}: {}", path, err);
+            ExitCode::FAILURE
+        },
+    }
+}
+
+// Runs the optimizer over the script and reports what it found, without
+// running it. Fails if there is anything to report.
+fn lint(path: &str, colors: Colors) -> ExitCode {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("could not read {}: {}", path, err);
+            return ExitCode::FAILURE;
+        },
+    };
+    match lint_source(&source) {
+        Ok(warnings) if warnings.is_empty() => ExitCode::SUCCESS,
+        Ok(warnings) | Err(warnings) => {
+            eprintln!("{}", colors.diagnostic(&warnings));
+            ExitCode::FAILURE
+        },
+    }
+}
+
+fn lint_source(source: &str) -> Result<String, String> {
+    let mut program = Parser::new(Lexer::new(source)).parse_program()
+        .map_err(|errors| repl::diagnostics::render_parse_errors(source, &errors))?;
+    let rendered: Vec<String> = optimizer::optimize(&mut program).iter()
+        .map(|warning| {
+            Diagnostic::warning(warning.message.clone())
+                .with_span(warning.span, 1)
+                .with_note(format!("lint: {}", warning.lint))
+                .render(source)
+        })
+        .collect();
+    Ok(rendered.join("\n\n"))
+}
+
+// Reports what parsing and static analysis find in the scripts, without
+// running them. Directories are searched for .mky files. Only errors fail
+// the check, after every file has been looked at.
+fn check(paths: &[String], colors: Colors) -> ExitCode {
+    let mut files = Vec::new();
+    for path in paths {
+        match testing::find(Path::new(path), ".mky") {
+            Ok(found) => files.extend(found),
+            Err(err) => {
+                eprintln!("could not read {}: {}", path, err);
+                return ExitCode::FAILURE;
+            },
+        }
+    }
+
+    let mut failed = 0;
+    for file in &files {
+        let (ok, report) = match std::fs::read_to_string(file) {
+            Ok(source) => match check_source(&source) {
+                Ok(warnings) => (true, warnings),
+                Err(errors) => (false, errors),
+            },
+            Err(err) => (false, format!("could not read {}: {}", file.display(), err)),
+        };
+        if !ok {
+            failed += 1;
+        }
+        // Findings are only told apart by file when there are several
+        match (report.is_empty(), files.len()) {
+            (true, _) => {},
+            (false, 1) => eprintln!("{}", colors.diagnostic(&report)),
+            (false, _) => eprintln!("{}:\n{}\n", file.display(), colors.diagnostic(&report)),
+        }
+    }
+    if files.len() > 1 {
+        eprintln!("checked {} files, {} with errors", files.len(), failed);
+    }
+    match failed {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
+}
+
+fn check_source(source: &str) -> Result<String, String> {
+    let program = Parser::new(Lexer::new(source)).parse_program()
+        .map_err(|errors| repl::diagnostics::render_parse_errors(source, &errors))?;
+    let findings = analysis::analyze(&program);
+    let rendered: Vec<String> = findings.iter()
+        .map(|finding| {
+            let diagnostic = match finding.level {
+                analysis::Level::Error => Diagnostic::error(finding.message.clone()),
+                analysis::Level::Warning => Diagnostic::warning(finding.message.clone()),
+            };
+            diagnostic.with_span(finding.span, 1).with_note(format!("lint: {}", finding.lint)).render(source)
+        })
+        .collect();
+    let mut rendered = rendered.join("\n\n");
+    let type_errors = typecheck::check(&program).err().unwrap_or_default();
+    if !type_errors.is_empty() {
+        if !rendered.is_empty() {
+            rendered.push_str("\n\n");
+        }
+        rendered.push_str(&render_type_errors(source, &type_errors));
+    }
+    match type_errors.is_empty() && findings.iter().all(|finding| finding.level != analysis::Level::Error) {
+        true => Ok(rendered),
+        false => Err(rendered),
+    }
+}
+
+fn test(path: &str) -> ExitCode {
+    match testing::run(Path::new(path)) {
+        Ok(summary) if summary.failed == 0 => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("could not read {}: {}", path, err);
+            ExitCode::FAILURE
+        },
+    }
+}
+
+// Prints the documentation for the script's functions and documented
+// values, as Markdown or as an HTML page titled after the file
+fn document(path: &str, html: bool, colors: Colors) -> ExitCode {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("could not read {}: {}", path, err);
+            return ExitCode::FAILURE;
+        },
+    };
+    let program = match Parser::new(Lexer::new(&source)).parse_program() {
+        Ok(program) => program,
+        Err(errors) => {
+            eprintln!("{}", colors.diagnostic(&repl::diagnostics::render_parse_errors(&source, &errors)));
+            return ExitCode::FAILURE;
+        },
+    };
+    let title = Path::new(path).file_stem().map_or_else(|| path.to_string(), |stem| stem.to_string_lossy().to_string());
+    let items = doc::items(&program);
+    match html {
+        true => print!("{}", doc::html(&title, &items)),
+        false => print!("{}", doc::markdown(&title, &items)),
+    }
+    ExitCode::SUCCESS
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // Allowed anywhere, and only do anything for commands that run a script.
+    // Both report through the one tracer a run has, so only one can be used.
+    let profiler = args.iter().any(|arg| arg == "--profile").then(|| Rc::new(RefCell::new(Profiler::new())));
+    let trace = match (&profiler, args.iter().any(|arg| arg == "--trace")) {
+        (Some(_), true) => {
+            eprintln!("--trace and --profile cannot be used together");
+            return ExitCode::from(2);
+        },
+        (Some(profiler), false) => Some(Trace::new(profiler.clone())),
+        (None, true) => Some(Trace::new(Rc::new(RefCell::new(WriteTracer(std::io::stderr()))))),
+        (None, false) => None,
+    };
+    // Colors are only used on terminals, and can be turned off there too
+    let (output, errors) = match args.iter().any(|arg| arg == "--no-color") {
+        true => (Colors::PLAIN, Colors::PLAIN),
+        false => (Colors::detect(&std::io::stdout()), Colors::detect(&std::io::stderr())),
+    };
+    args.retain(|arg| arg != "--trace" && arg != "--profile" && arg != "--no-color");
+    let mode = match parse_args(&args) {
+        Ok(mode) => mode,
+        Err(usage) => {
+            eprintln!("{}", usage);
+            return ExitCode::from(2);
+        },
+    };
+
+    // Scripts import from beside themselves, everything else from the
+    // working directory
+    let here = Path::new("");
+    let (result, print_result) = match mode {
+        Mode::Repl => {
+            println!("Monkey REPL");
+            println!("Feel free to type in commands.");
+            repl::start_terminal(output);
+            return ExitCode::SUCCESS;
+        },
+        Mode::Fmt { path, check } => return fmt(&path, check, errors),
+        Mode::Parse { path, json } => return parse(&path, json, errors),
+        Mode::Ast { path, dot } => return ast(&path, dot, errors),
+        Mode::Tokens(path) => return tokens(&path),
+        Mode::Lint(path) => return lint(&path, errors),
+        Mode::Check(paths) => return check(&paths, errors),
+        Mode::Test(path) => return test(&path),
+        Mode::Doc { path, html } => return document(&path, html, errors),
+        Mode::Compile { path, output: bytecode } => return compile(&path, &bytecode, errors),
+        Mode::Run(path) => {
+            let bytes = match std::fs::read(&path) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    eprintln!("could not read {}: {}", path, err);
+                    return ExitCode::FAILURE;
+                },
+            };
+            if Bytecode::is_serialized(&bytes) {
+                (run_bytecode(&bytes, trace), false)
+            } else {
+                match String::from_utf8(bytes) {
+                    Ok(source) => (run(&source, Path::new(&path).parent().unwrap_or(here), trace), false),
+                    Err(err) => {
+                        eprintln!("could not read {}: {}", path, err);
+                        return ExitCode::FAILURE;
+                    },
+                }
+            }
+        },
+        // One-liners show their value like the REPL would
+        Mode::Eval(source) => (run(&source, here, trace), true),
+        Mode::Stdin => {
+            let mut source = String::new();
+            if let Err(err) = stdin().read_to_string(&mut source) {
+                eprintln!("could not read stdin: {}", err);
+                return ExitCode::FAILURE;
+            }
+            (run(&source, here, trace), false)
+        },
+    };
+
+    // Reported for failed runs too, up to where they stopped
+    if let Some(profiler) = profiler {
+        eprint!("{}", profiler.borrow().report());
+    }
+    match result {
+        Ok(value) => {
+            if print_result {
+                println!("{}", output.result(&value.pretty(&InspectOptions::default())));
+            }
+            ExitCode::SUCCESS
+        },
+        Err(message) => {
+            eprintln!("{}", errors.diagnostic(&message));
+            ExitCode::FAILURE
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        let tests = vec![
+            (vec![], Ok(Mode::Repl)),
+            (vec!["run", "script.mky"], Ok(Mode::Run("script.mky".to_string()))),
+            (vec!["run", "-"], Ok(Mode::Stdin)),
+            (vec!["-e", "1 + 2"], Ok(Mode::Eval("1 + 2".to_string()))),
+            (vec!["-"], Ok(Mode::Stdin)),
+            (vec!["run"], Err(USAGE.to_string())),
+            (vec!["-e"], Err(USAGE.to_string())),
+            (vec!["script.mky"], Err(USAGE.to_string())),
+            (vec!["fmt", "a.mky"], Ok(Mode::Fmt { path: "a.mky".to_string(), check: false })),
+            (vec!["fmt", "--check", "a.mky"], Ok(Mode::Fmt { path: "a.mky".to_string(), check: true })),
+            (vec!["fmt", "a.mky", "--check"], Ok(Mode::Fmt { path: "a.mky".to_string(), check: true })),
+            (vec!["fmt"], Err(USAGE.to_string())),
+            (vec!["parse", "a.mky"], Ok(Mode::Parse { path: "a.mky".to_string(), json: false })),
+            (vec!["parse", "--json", "a.mky"], Ok(Mode::Parse { path: "a.mky".to_string(), json: true })),
+            (vec!["ast", "a.mky"], Ok(Mode::Ast { path: "a.mky".to_string(), dot: false })),
+            (vec!["ast", "--dot", "a.mky"], Ok(Mode::Ast { path: "a.mky".to_string(), dot: true })),
+            (vec!["tokens", "a.mky"], Ok(Mode::Tokens("a.mky".to_string()))),
+            (vec!["lint", "a.mky"], Ok(Mode::Lint("a.mky".to_string()))),
+            (vec!["check", "a.mky"], Ok(Mode::Check(vec!["a.mky".to_string()]))),
+            (vec!["check", "a.mky", "scripts"], Ok(Mode::Check(vec!["a.mky".to_string(), "scripts".to_string()]))),
+            (vec!["check"], Err(USAGE.to_string())),
+            (vec!["test", "tests"], Ok(Mode::Test("tests".to_string()))),
+            (vec!["test"], Err(USAGE.to_string())),
+            (vec!["doc", "a.mky"], Ok(Mode::Doc { path: "a.mky".to_string(), html: false })),
+            (vec!["doc", "--html", "a.mky"], Ok(Mode::Doc { path: "a.mky".to_string(), html: true })),
+            (vec!["doc"], Err(USAGE.to_string())),
+            (vec!["compile", "a.mky"], Ok(Mode::Compile { path: "a.mky".to_string(), output: "a.mkyc".to_string() })),
+            (vec!["compile", "a.mky", "-o", "b.out"], Ok(Mode::Compile { path: "a.mky".to_string(), output: "b.out".to_string() })),
+            (vec!["compile", "-o", "b.out", "a.mky"], Ok(Mode::Compile { path: "a.mky".to_string(), output: "b.out".to_string() })),
+            (vec!["compile", "a.mky", "-o"], Err(USAGE.to_string())),
+        ];
+
+        for (args, expected) in tests {
+            let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            assert_eq!(parse_args(&args), expected, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let here = Path::new("");
+        assert_eq!(run("let x = 2; x * 3", here, None), Ok(Object::Integer(6)));
+        assert_eq!(run("1 + true", here, None), Err("error: type mismatch: INTEGER + BOOLEAN\n --> line 1, col 3\n  |\n1 | 1 + true\n  |   ^".to_string()));
+        assert!(run("let = 1;", here, None).unwrap_err().starts_with("error: expected next token"));
+
+        // Annotated programs are type checked before they run, others only
+        // fail on what they actually do
+        assert_eq!(run("let x: int = 2; x * 3", here, None), Ok(Object::Integer(6)));
+        assert_eq!(run("let x: int = \"a\"; x", here, None), Err("error: expected int, found string\n --> line 1, col 14\n  |\n1 | let x: int = \"a\"; x\n  |              ^".to_string()));
+        assert_eq!(run("false ? 1 + \"a\" : 2", here, None), Ok(Object::Integer(2)));
+    }
+
+    #[test]
+    fn test_run_imports() {
+        let root = std::env::temp_dir().join(format!("interpreter-run-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("util.mky"), "let inc = fn(x) { x + 1 };").unwrap();
+        let result = run("import \"util\"; util.inc(1)", &root, None);
+        let missing = run("import \"util\"", Path::new(""), None);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(result, Ok(Object::Integer(2)));
+        assert!(missing.unwrap_err().starts_with("error: module not found: util"));
+    }
+
+    #[test]
+    fn test_run_bytecode() {
+        let bytes = compile_source("let add = fn(a, b) { a + b }; add(2, 3)").unwrap();
+        assert_eq!(run_bytecode(&bytes, None), Ok(Object::Integer(5)));
+        assert_eq!(run_bytecode(&compile_source("1 + true").unwrap(), None), Err("error: type mismatch: INTEGER + BOOLEAN\n --> line 1, col 3".to_string()));
+        assert_eq!(run_bytecode(&bytes[..bytes.len() - 1], None), Err("error: bytecode ends unexpectedly".to_string()));
+        assert_eq!(compile_source("struct P { x }"), Err("error: struct is not supported by the compiler".to_string()));
+    }
+
+    #[test]
+    fn test_ast_json_round_trip() {
+        let source = "let add = fn(a, b) { a + b }; for (x in [1, 2]) { add(x, -1); }";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let json = serde_json::to_string(&program).unwrap();
+        let decoded: ast::Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, program);
+        assert!(json.contains("\"LetStatement\""), "{}", json);
+    }
+
+    #[test]
+    fn test_lint_source() {
+        assert_eq!(lint_source("let f = fn(x) { x };"), Ok(String::new()));
+        assert_eq!(lint_source("let f = fn() {\n    let x = 1;\n    return 2;\n    x\n};"), Ok("\
+warning: unreachable statement
+ --> line 4, col 5
+  |
+4 |     x
+  |     ^
+  = note: lint: unreachable_code
+
+warning: unused binding x
+ --> line 2, col 9
+  |
+2 |     let x = 1;
+  |         ^
+  = note: lint: unused_binding".to_string()));
+        assert!(lint_source("let = 1;").unwrap_err().starts_with("error: expected next token"));
+    }
+
+    #[test]
+    fn test_check_source() {
+        assert_eq!(check_source("let f = fn(x) { x };"), Ok(String::new()));
+        assert_eq!(check_source("let x = 1;\nfalse ? x : 2"), Ok("\
+warning: condition is always false
+ --> line 2, col 1
+  |
+2 | false ? x : 2
+  | ^
+  = note: lint: constant_condition".to_string()));
+        assert_eq!(check_source("puts(y);"), Err("\
+error: identifier not found: y
+ --> line 1, col 6
+  |
+1 | puts(y);
+  |      ^
+  = note: lint: undefined_identifier".to_string()));
+        assert_eq!(check_source("let f = fn(x: int) { x };\nf(\"a\")"), Err("\
+error: expected int, found string
+ --> line 2, col 3
+  |
+2 | f(\"a\")
+  |   ^".to_string()));
+    }
+
+    #[test]
+    fn test_format_source() {
+        let tests = vec![
+            ("let x=1;x+2", Ok("let x = 1;\nx + 2;\n".to_string())),
+            ("let x = 1;\nx + 2;\n", Ok("let x = 1;\nx + 2;\n".to_string())),
+            ("let s = \"a // b\"", Ok("let s = \"a // b\";\n".to_string())),
+            ("let x = 1; // one", Err("cannot format source containing comments".to_string())),
+            ("/* header */ 1", Err("cannot format source containing comments".to_string())),
+            ("let q = '\"'; // quote", Err("cannot format source containing comments".to_string())),
+            ("let c = '/';c", Ok("let c = '/';\nc;\n".to_string())),
+            ("let s = \"a\nb\"; // keep me", Err("cannot format source containing comments".to_string())),
+            ("let s = `\"`; /* keep */", Err("cannot format source containing comments".to_string())),
+            ("let s = r\"\\\"; // keep", Err("cannot format source containing comments".to_string())),
+            ("let s = `// not a comment`", Ok("let s = `// not a comment`;\n".to_string())),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(format_source(input), expected, "{}", input);
+        }
+    }
 }
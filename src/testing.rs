@@ -0,0 +1,196 @@
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+use object::shared::{Rc, RefCell};
+use ast::{Expression, Program, Statement};
+use lexer::Lexer;
+use parser::Parser;
+use object::{Environment, Object};
+use repl::diagnostics::Diagnostic;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+// Every `*_test.mky` under path, in a stable order. A file is taken as is.
+pub fn discover(path: &Path) -> io::Result<Vec<PathBuf>> {
+    find(path, "_test.mky")
+}
+
+// Every file under path whose name ends with suffix, in a stable order
+pub fn find(path: &Path, suffix: &str) -> io::Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            files.extend(find(&entry, suffix)?);
+        } else if entry.to_string_lossy().ends_with(suffix) {
+            files.push(entry);
+        }
+    }
+    Ok(files)
+}
+
+// Top-level functions named test_*, in the order they are defined
+fn test_names(program: &Program) -> Vec<String> {
+    program.statements.iter()
+        .filter_map(|statement| match statement {
+            Statement::LetStatement(ls) if matches!(ls.value, Expression::FunctionLiteral(_)) => Some(ls.name.value.to_string()),
+            _ => None,
+        })
+        .filter(|name| name.starts_with("test_"))
+        .collect()
+}
+
+// Runs one file's tests, writing a line per test and the diagnostic for each
+// failure. A file that does not load counts as one failure.
+pub fn run_file(path: &Path, source: &str, out: &mut String) -> Summary {
+    let mut summary = Summary::default();
+    let name = path.display();
+    let program = match Parser::new(Lexer::new(source)).parse_program() {
+        Ok(program) => program,
+        Err(errors) => {
+            writeln!(out, "{} ... FAILED\n{}", name, repl::diagnostics::render_parse_errors(source, &errors)).unwrap();
+            summary.failed += 1;
+            return summary;
+        },
+    };
+
+    let env = Rc::new(RefCell::new(Environment::new()));
+    let root = path.parent().unwrap_or(Path::new(""));
+    env.borrow().modules().borrow_mut().set_root(root);
+    if let Err(err) = eval::eval_program(&program, &env) {
+        writeln!(out, "{} ... FAILED\n{}", name, Diagnostic::from(&err).render(source)).unwrap();
+        summary.failed += 1;
+        return summary;
+    }
+
+    for test in test_names(&program) {
+        let function = env.borrow().get(test.as_str()).unwrap_or(Object::Null);
+        match eval::call_function(function, Vec::new(), &env) {
+            Ok(_) => {
+                writeln!(out, "test {}::{} ... ok", name, test).unwrap();
+                summary.passed += 1;
+            },
+            Err(err) => {
+                writeln!(out, "test {}::{} ... FAILED\n{}", name, test, Diagnostic::from(&err).render(source)).unwrap();
+                summary.failed += 1;
+            },
+        }
+    }
+    summary
+}
+
+pub fn run(path: &Path) -> io::Result<Summary> {
+    let mut summary = Summary::default();
+    for file in discover(path)? {
+        let source = std::fs::read_to_string(&file)?;
+        let mut out = String::new();
+        let result = run_file(&file, &source, &mut out);
+        print!("{}", out);
+        summary.passed += result.passed;
+        summary.failed += result.failed;
+    }
+    println!("\n{} passed; {} failed", summary.passed, summary.failed);
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_file() {
+        let source = "let double = fn(x) { x * 2 };
+let test_double = fn() { assert_eq(double(2), 4); };
+let helper = fn() { assert(false); };
+let test_broken = fn() {
+    assert_eq(double(3), 5);
+};
+let test_message = fn() { assert(double(0), \"zero is falsy?\"); };";
+        let mut out = String::new();
+        let summary = run_file(Path::new("math_test.mky"), source, &mut out);
+
+        assert_eq!(summary, Summary { passed: 2, failed: 1 });
+        assert_eq!(out, "test math_test.mky::test_double ... ok
+test math_test.mky::test_broken ... FAILED
+error: assertion failed: 6 != 5
+ --> line 5, col 14
+  |
+5 |     assert_eq(double(3), 5);
+  |              ^
+  = note: in assert_eq called at line 5, col 14
+test math_test.mky::test_message ... ok
+");
+    }
+
+    #[test]
+    fn test_failure_messages() {
+        let source = "let test_words = fn() {
+    assert_eq(split(\"one two four\", \" \"), [\"one\", \"two\", \"three\"]);
+};
+let test_panic = fn() { try { panic(\"unreachable\") } catch (e) { e } };";
+        let mut out = String::new();
+        assert_eq!(run_file(Path::new("words_test.mky"), source, &mut out), Summary { passed: 0, failed: 2 });
+        assert_eq!(out, "test words_test.mky::test_words ... FAILED
+error: assertion failed: values differ (- left, + right)
+  [
+      \"one\",
+      \"two\",
+-     \"four\",
++     \"three\",
+  ]
+ --> line 2, col 14
+  |
+2 |     assert_eq(split(\"one two four\", \" \"), [\"one\", \"two\", \"three\"]);
+  |              ^
+  = note: in assert_eq called at line 2, col 14
+test words_test.mky::test_panic ... FAILED
+error: panicked: unreachable
+ --> line 4, col 36
+  |
+4 | let test_panic = fn() { try { panic(\"unreachable\") } catch (e) { e } };
+  |                                    ^
+  = note: in panic called at line 4, col 36
+");
+    }
+
+    #[test]
+    fn test_load_failures() {
+        let mut out = String::new();
+        assert_eq!(run_file(Path::new("a_test.mky"), "let = 1;", &mut out), Summary { passed: 0, failed: 1 });
+        assert!(out.starts_with("a_test.mky ... FAILED\nerror: expected next token"), "{}", out);
+
+        let mut out = String::new();
+        assert_eq!(run_file(Path::new("b_test.mky"), "let test_a = fn() {}; 1 + true", &mut out), Summary { passed: 0, failed: 1 });
+        assert!(out.starts_with("b_test.mky ... FAILED\nerror: type mismatch"), "{}", out);
+    }
+
+    #[test]
+    fn test_discover() {
+        let root = std::env::temp_dir().join(format!("interpreter-test-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("nested")).unwrap();
+        for file in ["b_test.mky", "a_test.mky", "helper.mky", "nested/c_test.mky"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        let found = discover(&root);
+        let single = discover(&root.join("helper.mky"));
+        let scripts = find(&root, ".mky");
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let names = |files: Vec<PathBuf>| -> Vec<String> {
+            files.iter().map(|f| f.strip_prefix(&root).unwrap().to_string_lossy().into_owned()).collect()
+        };
+        assert_eq!(names(found.unwrap()), vec!["a_test.mky", "b_test.mky", "nested/c_test.mky"]);
+        assert_eq!(names(single.unwrap()), vec!["helper.mky"]);
+        assert_eq!(names(scripts.unwrap()), vec!["a_test.mky", "b_test.mky", "helper.mky", "nested/c_test.mky"]);
+    }
+}
@@ -0,0 +1,135 @@
+// Runs every program in tests/golden through both backends and compares
+// what it prints with the .out file beside it, apart from the few listed as
+// only running on the evaluator. A program that fails ends its output with
+// the error. Programs run deterministically, so random numbers come out the
+// same without seeding. Run with UPDATE_GOLDEN=1 to rewrite the .out files
+// from the evaluator instead.
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use object::shared::{Rc, RefCell};
+use ast::Program;
+use compiler::{CompileError, Compiler};
+use lexer::Lexer;
+use object::{Environment, EvalConfig, Io};
+use parser::Parser;
+use vm::Vm;
+
+const CORPUS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+
+// Programs only the evaluator runs, with the feature the compiler lacks.
+// Any other program the compiler refuses fails the suite, as does one here
+// that compiles, so the list has to change along with the compiler.
+const EVAL_ONLY: &[(&str, &str)] = &[
+    ("exceptions.mky", "try"),
+    ("matching.mky", "match"),
+    ("modules.mky", "import"),
+    ("results.mky", "?"),
+    ("structs.mky", "struct"),
+];
+
+fn config() -> EvalConfig {
+    EvalConfig { deterministic: true, ..EvalConfig::default() }
+}
+
+// Modules the programs import live in a subdirectory, so only the top level
+// is the corpus
+fn programs() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(CORPUS).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "mky"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn capture() -> (Io, Rc<RefCell<Vec<u8>>>) {
+    let buffer = Rc::new(RefCell::new(Vec::new()));
+    let io = Io { input: Rc::new(RefCell::new(std::io::empty())), output: buffer.clone() };
+    (io, buffer)
+}
+
+fn finish(buffer: Rc<RefCell<Vec<u8>>>, error: Option<String>) -> String {
+    let mut output = String::from_utf8(buffer.take()).unwrap();
+    if let Some(error) = error {
+        writeln!(output, "error: {}", error).unwrap();
+    }
+    output
+}
+
+// Every parse error, one to a line
+fn parse(source: &str) -> Result<Program, String> {
+    Parser::new(Lexer::new(source)).parse_program().map_err(|errors| {
+        errors.iter().map(|err| err.to_string()).collect::<Vec<_>>().join("\nerror: ")
+    })
+}
+
+fn run_eval(source: &str) -> String {
+    let (io, buffer) = capture();
+    let program = match parse(source) {
+        Ok(program) => program,
+        Err(errors) => return finish(buffer, Some(errors)),
+    };
+    let env = Rc::new(RefCell::new(Environment::new()));
+    env.borrow_mut().set_config(config());
+    env.borrow_mut().set_io(io);
+    env.borrow().modules().borrow_mut().set_root(Path::new(CORPUS));
+    let error = eval::eval_program(&program, &env).err().map(|err| err.error.to_string());
+    finish(buffer, error)
+}
+
+// The feature the compiler lacks as the error when the program uses
+// something only the evaluator implements
+fn run_vm(source: &str) -> Result<String, &'static str> {
+    let (io, buffer) = capture();
+    let program = match parse(source) {
+        Ok(program) => program,
+        Err(errors) => return Ok(finish(buffer, Some(errors))),
+    };
+    let bytecode = match Compiler::new().compile(&program) {
+        Ok(bytecode) => bytecode,
+        Err(CompileError::Unsupported(feature)) => return Err(feature),
+        Err(err) => return Ok(finish(buffer, Some(err.to_string()))),
+    };
+    let mut vm = Vm::with_config(config());
+    vm.set_io(io);
+    let error = vm.run(bytecode).err().map(|err| err.error.to_string());
+    Ok(finish(buffer, error))
+}
+
+#[test]
+fn golden() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+    for path in programs() {
+        let source = std::fs::read_to_string(&path).unwrap();
+        let expected_path = path.with_extension("out");
+        let actual = run_eval(&source);
+        if update {
+            std::fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let expected = std::fs::read_to_string(&expected_path).unwrap_or_default();
+        if actual != expected {
+            failures.push(format!("{} (eval)\n--- expected\n{}--- actual\n{}", name, expected, actual));
+        }
+        let skipped = EVAL_ONLY.iter().find(|(program, _)| *program == name).map(|&(_, feature)| feature);
+        match (run_vm(&source), skipped) {
+            (Ok(actual), None) if actual != expected => {
+                failures.push(format!("{} (vm)\n--- expected\n{}--- actual\n{}", name, expected, actual));
+            },
+            (Ok(_), None) => {},
+            (Ok(_), Some(_)) => failures.push(format!("{} (vm)\ncompiles now, take it off EVAL_ONLY", name)),
+            (Err(feature), Some(skipped)) if feature == skipped => {},
+            (Err(feature), _) => failures.push(format!("{} (vm)\n{} is not supported by the compiler", name, feature)),
+        }
+    }
+    for (program, _) in EVAL_ONLY {
+        if !update && !Path::new(CORPUS).join(program).exists() {
+            failures.push(format!("{} (vm)\nno such program, take it off EVAL_ONLY", program));
+        }
+    }
+    assert!(failures.is_empty(), "{} golden mismatches\n\n{}", failures.len(), failures.join("\n"));
+}
@@ -1,18 +1,147 @@
 #![allow(dead_code)]
 
-#[derive(Debug, PartialEq, Clone)]
+pub mod symbol;
+
+use std::fmt;
+
+pub use symbol::Symbol;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, col {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Token {
     pub ttype: TokenType,
     pub literal: String,
+    pub line: usize,
+    pub column: usize,
 }
 
 impl Token {
     pub fn new(ttype: TokenType, literal: String) -> Token {
-        Token { ttype, literal }
+        Token { ttype, literal, line: 0, column: 0 }
+    }
+
+    pub fn with_position(ttype: TokenType, literal: String, line: usize, column: usize) -> Token {
+        Token { ttype, literal, line, column }
+    }
+
+    pub fn span(&self) -> Span {
+        Span { line: self.line, column: self.column }
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+// The text of a string literal inside its quotes, for any kind of string:
+// "...", r"..." or `...`
+pub fn string_contents(literal: &str) -> &str {
+    let quoted = literal.strip_prefix('r').unwrap_or(literal);
+    &quoted[1..quoted.len() - 1]
+}
+
+// Positions are deliberately left out of equality so tokens (and the AST nodes
+// holding them) compare by content regardless of where they appeared.
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.ttype == other.ttype && self.literal == other.literal
+    }
+}
+
+// Why the text of an Illegal token is not a token
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LexError {
+    UnknownCharacter(char),
+    UnterminatedString,
+    UnterminatedChar,
+    UnterminatedComment,
+    // Only character literals have escapes, strings are taken as written
+    BadEscape(char),
+    // Quoted, but empty or more than one character
+    BadCharLiteral,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexError::UnknownCharacter(c) => write!(f, "unknown character {:?}", c),
+            LexError::UnterminatedString => write!(f, "unterminated string"),
+            LexError::UnterminatedChar => write!(f, "unterminated character literal"),
+            LexError::UnterminatedComment => write!(f, "unterminated block comment"),
+            LexError::BadEscape(c) => write!(f, "unknown escape \\{} in character literal", c),
+            LexError::BadCharLiteral => write!(f, "character literal must hold exactly one character"),
+        }
+    }
+}
+
+// A token whose literal borrows from the source text, as produced by the lexer.
+// Convert it with to_token where the token has to outlive the source.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenRef<'a> {
+    pub ttype: TokenType,
+    pub literal: &'a str,
+    pub line: usize,
+    pub column: usize,
+    // Why the lexer could not read an Illegal token
+    pub error: Option<LexError>,
+}
+
+impl<'a> TokenRef<'a> {
+    pub fn new(ttype: TokenType, literal: &'a str) -> TokenRef<'a> {
+        TokenRef { ttype, literal, line: 0, column: 0, error: None }
+    }
+
+    pub fn illegal(literal: &'a str, error: LexError) -> TokenRef<'a> {
+        TokenRef { error: Some(error), ..TokenRef::new(TokenType::Illegal, literal) }
+    }
+
+    pub fn span(&self) -> Span {
+        Span { line: self.line, column: self.column }
+    }
+
+    pub fn to_token(&self) -> Token {
+        Token::with_position(self.ttype, self.literal.to_string(), self.line, self.column)
+    }
+}
+
+impl From<TokenRef<'_>> for Token {
+    fn from(token: TokenRef<'_>) -> Token {
+        token.to_token()
+    }
+}
+
+impl PartialEq for TokenRef<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ttype == other.ttype && self.literal == other.literal
+    }
+}
+
+impl PartialEq<Token> for TokenRef<'_> {
+    fn eq(&self, other: &Token) -> bool {
+        self.ttype == other.ttype && self.literal == other.literal
+    }
+}
+
+impl fmt::Display for TokenRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.ttype {
+            TokenType::Eof => write!(f, "{}", self.ttype),
+            _ => write!(f, "{}", self.literal),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TokenType {
     // Keywords
     Let,
@@ -22,11 +151,27 @@ pub enum TokenType {
     If,
     Else,
     Return,
+    For,
+    In,
+    Null,
+    Import,
+    Match,
+    Struct,
+    Try,
+    Catch,
+    Throw,
 
     // Identifiers and literals
     Identifier,
     Integer,
+    // Digits with a fractional part, as in 1.5
+    Float,
     String,
+    // A string with `${...}` in it, split into parts by the parser
+    InterpolatedString,
+    // r"..." or `...`, which leave `${` alone
+    RawString,
+    Char,
 
     // Operators
     Plus,
@@ -36,8 +181,15 @@ pub enum TokenType {
     Asterisk,
     Slash,
     Question,
+    NullCoalesce,
+    Pipe,
     Percent,
     Assign,
+    PlusAssign,
+    MinusAssign,
+    AsteriskAssign,
+    SlashAssign,
+    PercentAssign,
     Bang,
     Equal,
     NotEqual,
@@ -50,6 +202,13 @@ pub enum TokenType {
     Comma,
     Semicolon,
     Colon,
+    // Before a function's return type
+    Arrow,
+    // Between a pattern and its result
+    FatArrow,
+    Dot,
+    DotDot,
+    DotDotEq,
     LeftParen,
     RightParen,
     LeftSquareBracket,
@@ -60,4 +219,111 @@ pub enum TokenType {
     // End of file
     Eof,
     Illegal,
-}
\ No newline at end of file
+
+    // Only produced when the lexer is asked to keep comments
+    Comment,
+    // A `///` line, produced when the lexer is asked to keep comments or
+    // doc comments
+    DocComment,
+}
+
+impl TokenType {
+    // The member a struct defines to give an infix operator its own meaning
+    // when the struct is on the left. `!=` is the opposite of `__eq`.
+    pub fn overload(&self) -> Option<&'static str> {
+        match self {
+            TokenType::Plus => Some("__add"),
+            TokenType::Minus => Some("__sub"),
+            TokenType::Asterisk => Some("__mul"),
+            TokenType::Slash => Some("__div"),
+            TokenType::Percent => Some("__mod"),
+            TokenType::Equal | TokenType::NotEqual => Some("__eq"),
+            TokenType::LessThan => Some("__lt"),
+            TokenType::GreaterThan => Some("__gt"),
+            TokenType::LessThanOrEqual => Some("__le"),
+            TokenType::GreaterThanOrEqual => Some("__ge"),
+            _ => None,
+        }
+    }
+}
+
+// Fixed tokens print as they are written in source, the rest by category
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            TokenType::Let => "let",
+            TokenType::Fn => "fn",
+            TokenType::True => "true",
+            TokenType::False => "false",
+            TokenType::If => "if",
+            TokenType::Else => "else",
+            TokenType::Return => "return",
+            TokenType::For => "for",
+            TokenType::In => "in",
+            TokenType::Null => "null",
+            TokenType::Import => "import",
+            TokenType::Match => "match",
+            TokenType::Struct => "struct",
+            TokenType::Try => "try",
+            TokenType::Catch => "catch",
+            TokenType::Throw => "throw",
+            TokenType::Identifier => "identifier",
+            TokenType::Integer => "integer",
+            TokenType::Float => "float",
+            TokenType::String | TokenType::InterpolatedString | TokenType::RawString => "string",
+            TokenType::Char => "character",
+            TokenType::Plus => "+",
+            TokenType::Increment => "++",
+            TokenType::Minus => "-",
+            TokenType::Decrement => "--",
+            TokenType::Asterisk => "*",
+            TokenType::Slash => "/",
+            TokenType::Question => "?",
+            TokenType::NullCoalesce => "??",
+            TokenType::Pipe => "|>",
+            TokenType::Percent => "%",
+            TokenType::Assign => "=",
+            TokenType::PlusAssign => "+=",
+            TokenType::MinusAssign => "-=",
+            TokenType::AsteriskAssign => "*=",
+            TokenType::SlashAssign => "/=",
+            TokenType::PercentAssign => "%=",
+            TokenType::Bang => "!",
+            TokenType::Equal => "==",
+            TokenType::NotEqual => "!=",
+            TokenType::LessThan => "<",
+            TokenType::GreaterThan => ">",
+            TokenType::LessThanOrEqual => "<=",
+            TokenType::GreaterThanOrEqual => ">=",
+            TokenType::Comma => ",",
+            TokenType::Semicolon => ";",
+            TokenType::Colon => ":",
+            TokenType::Arrow => "->",
+            TokenType::FatArrow => "=>",
+            TokenType::Dot => ".",
+            TokenType::DotDot => "..",
+            TokenType::DotDotEq => "..=",
+            TokenType::LeftParen => "(",
+            TokenType::RightParen => ")",
+            TokenType::LeftSquareBracket => "[",
+            TokenType::RightSquareBracket => "]",
+            TokenType::LeftCurlyBracket => "{",
+            TokenType::RightCurlyBracket => "}",
+            TokenType::Eof => "end of input",
+            TokenType::Illegal => "illegal",
+            TokenType::Comment => "comment",
+            TokenType::DocComment => "doc comment",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// A token prints as its source text
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.ttype {
+            TokenType::Eof => write!(f, "{}", self.ttype),
+            _ => write!(f, "{}", self.literal),
+        }
+    }
+}
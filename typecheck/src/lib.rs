@@ -0,0 +1,749 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem::discriminant;
+use std::rc::Rc;
+use ast::visit::{self, Visitor};
+use ast::{Expression, FunctionLiteral, Identifier, Pattern, Program, Statement, StringPart};
+use token::{Span, Symbol, TokenType};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Type {
+    // Unknown until the program runs, so fits anywhere
+    Any,
+    Int,
+    Float,
+    Bool,
+    String,
+    Char,
+    Null,
+    Array,
+    Hash,
+    Range,
+    Module,
+    // An instance of the named struct
+    Struct(Symbol),
+    // The signature is known for function literals, not for builtins or
+    // values annotated as plain `fn`
+    Function(Option<Rc<Signature>>),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::String => write!(f, "string"),
+            Type::Char => write!(f, "char"),
+            Type::Null => write!(f, "null"),
+            Type::Array => write!(f, "array"),
+            Type::Hash => write!(f, "hash"),
+            Type::Range => write!(f, "range"),
+            Type::Module => write!(f, "module"),
+            Type::Struct(name) => write!(f, "{}", name),
+            Type::Function(_) => write!(f, "fn"),
+        }
+    }
+}
+
+impl Type {
+    // The type an annotation names
+    pub fn from_name(name: &str) -> Option<Type> {
+        let ty = match name {
+            "any" => Type::Any,
+            "int" => Type::Int,
+            "float" => Type::Float,
+            "bool" => Type::Bool,
+            "string" => Type::String,
+            "char" => Type::Char,
+            "null" => Type::Null,
+            "array" => Type::Array,
+            "hash" => Type::Hash,
+            "range" => Type::Range,
+            "module" => Type::Module,
+            "fn" => Type::Function(None),
+            _ => return None,
+        };
+        Some(ty)
+    }
+
+    // Whether a value of type found may be used where self is expected
+    fn accepts(&self, found: &Type) -> bool {
+        match (self, found) {
+            (Type::Struct(expected), Type::Struct(found)) => expected == found,
+            _ => *self == Type::Any || *found == Type::Any || discriminant(self) == discriminant(found),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Signature {
+    pub parameters: Vec<Type>,
+    pub returns: Type,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TypeError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.span)
+    }
+}
+
+// Checks the types of a program without running it. Only annotated names
+// and operations on values whose types are known are checked, so programs
+// without annotations only fail on operations that would always fail.
+pub fn check(program: &Program) -> Result<(), Vec<TypeError>> {
+    let mut reassigned = Reassigned::default();
+    reassigned.visit_program(program);
+    let mut checker = Checker {
+        scopes: vec![HashMap::new()],
+        returns: Vec::new(),
+        reassigned: reassigned.0,
+        structs: HashMap::new(),
+        errors: Vec::new(),
+    };
+
+    // Functions may call any global function and name any global struct,
+    // even one defined further down
+    for statement in &program.statements {
+        if let Statement::StructStatement(ss) = statement {
+            checker.define(ss);
+        }
+    }
+    for statement in &program.statements {
+        if let Statement::LetStatement(ls) = statement {
+            if let (None, Expression::FunctionLiteral(func)) = (&ls.annotation, &ls.value) {
+                let signature = checker.signature(func);
+                let ty = checker.inferred(ls.name.value, Type::Function(Some(signature)));
+                checker.declare(ls.name.value, ty, false);
+            }
+        }
+    }
+    for statement in &program.statements {
+        checker.statement(statement);
+    }
+
+    // Signatures of global functions are read twice
+    let mut errors = checker.errors;
+    errors.sort_by_key(|error| (error.span.line, error.span.column));
+    errors.dedup();
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors),
+    }
+}
+
+// Whether anything in the program is annotated, which is when running it
+// checks its types first
+pub fn has_annotations(program: &Program) -> bool {
+    let mut annotations = Annotations(false);
+    annotations.visit_program(program);
+    annotations.0
+}
+
+#[derive(Clone)]
+struct Binding {
+    ty: Type,
+    // Annotated bindings keep their type when assigned to
+    annotated: bool,
+}
+
+struct Checker {
+    scopes: Vec<HashMap<Symbol, Binding>>,
+    // The annotated return type of each function being checked
+    returns: Vec<Option<Type>>,
+    reassigned: HashSet<Symbol>,
+    // The fields of each struct declared so far
+    structs: HashMap<Symbol, Vec<Symbol>>,
+    errors: Vec<TypeError>,
+}
+
+impl Checker {
+    fn error(&mut self, message: String, span: Span) {
+        self.errors.push(TypeError { message, span });
+    }
+
+    fn expect(&mut self, expected: &Type, found: &Type, span: Span) {
+        if !expected.accepts(found) {
+            self.error(format!("expected {}, found {}", expected, found), span);
+        }
+    }
+
+    fn resolve(&mut self, annotation: &Identifier) -> Type {
+        if self.structs.contains_key(&annotation.value) {
+            return Type::Struct(annotation.value);
+        }
+        Type::from_name(annotation.value.as_str()).unwrap_or_else(|| {
+            self.error(format!("unknown type {}", annotation.value), annotation.token.span());
+            Type::Any
+        })
+    }
+
+    fn signature(&mut self, func: &FunctionLiteral) -> Rc<Signature> {
+        let parameters = (0..func.parameters.len())
+            .map(|i| match func.parameter_types.get(i) {
+                Some(Some(annotation)) => self.resolve(annotation),
+                _ => Type::Any,
+            })
+            .collect();
+        let returns = match &func.return_type {
+            Some(annotation) => self.resolve(annotation),
+            None => Type::Any,
+        };
+        Rc::new(Signature { parameters, returns })
+    }
+
+    // An unannotated name assigned a new value somewhere may hold anything
+    fn inferred(&self, name: Symbol, ty: Type) -> Type {
+        match self.reassigned.contains(&name) {
+            true => Type::Any,
+            false => ty,
+        }
+    }
+
+    fn define(&mut self, ss: &ast::StructStatement) {
+        self.structs.insert(ss.name.value, ss.fields.iter().map(|field| field.value).collect());
+    }
+
+    fn declare(&mut self, name: Symbol, ty: Type, annotated: bool) {
+        self.scopes.last_mut().unwrap().insert(name, Binding { ty, annotated });
+    }
+
+    // Names bound nowhere the checker can see, such as builtins, are Any
+    fn lookup(&self, name: Symbol) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(&name))
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::LetStatement(ls) => {
+                let declared = ls.annotation.as_ref().map(|annotation| self.resolve(annotation));
+                // A function can call itself by name
+                if let (None, Expression::FunctionLiteral(func)) = (&declared, &ls.value) {
+                    let signature = self.signature(func);
+                    let ty = self.inferred(ls.name.value, Type::Function(Some(signature)));
+                    self.declare(ls.name.value, ty, false);
+                }
+                let found = self.expression(&ls.value);
+                match declared {
+                    Some(expected) => {
+                        self.expect(&expected, &found, ls.value.span());
+                        self.declare(ls.name.value, expected, true);
+                    },
+                    None => {
+                        let ty = self.inferred(ls.name.value, found);
+                        self.declare(ls.name.value, ty, false);
+                    },
+                }
+            },
+            Statement::ReturnStatement(rs) => {
+                let found = self.expression(&rs.return_value);
+                if let Some(Some(expected)) = self.returns.last().cloned() {
+                    self.expect(&expected, &found, rs.return_value.span());
+                }
+            },
+            Statement::ExpressionStatement(es) => {
+                self.expression(&es.expression);
+            },
+            Statement::ThrowStatement(ts) => {
+                self.expression(&ts.value);
+            },
+            // Loop bodies share the enclosing scope
+            Statement::ForStatement(fs) => {
+                self.statement(&fs.init);
+                self.expression(&fs.condition);
+                self.expression(&fs.update);
+                fs.body.statements.iter().for_each(|statement| self.statement(statement));
+            },
+            Statement::ForInStatement(fs) => {
+                self.expression(&fs.iterable);
+                self.declare(fs.variable.value, Type::Any, false);
+                fs.body.statements.iter().for_each(|statement| self.statement(statement));
+            },
+            Statement::ImportStatement(is) => self.declare(is.name.value, Type::Module, false),
+            Statement::StructStatement(ss) => {
+                self.define(ss);
+                self.declare(ss.name.value, Type::Any, false);
+            },
+            Statement::BlockStatement(bs) => {
+                self.block(bs);
+            },
+        }
+    }
+
+    // A block is worth its last expression, or null when it ends in
+    // anything else, a semicolon included
+    fn block(&mut self, block: &ast::BlockStatement) -> Type {
+        self.scopes.push(HashMap::new());
+        let mut ty = Type::Null;
+        for statement in &block.statements {
+            ty = match statement {
+                Statement::ExpressionStatement(es) => {
+                    let ty = self.expression(&es.expression);
+                    if es.semicolon { Type::Null } else { ty }
+                },
+                Statement::BlockStatement(bs) => self.block(bs),
+                Statement::ReturnStatement(_) | Statement::ThrowStatement(_) => {
+                    self.statement(statement);
+                    Type::Any
+                },
+                statement => {
+                    self.statement(statement);
+                    Type::Null
+                },
+            };
+        }
+        self.scopes.pop();
+        ty
+    }
+
+    fn function(&mut self, func: &FunctionLiteral) -> Type {
+        let signature = self.signature(func);
+        let returns = func.return_type.as_ref().map(|_| signature.returns.clone());
+
+        self.scopes.push(HashMap::new());
+        self.returns.push(returns.clone());
+        for (parameter, ty) in func.parameters.iter().zip(&signature.parameters) {
+            self.declare(parameter.value, ty.clone(), *ty != Type::Any);
+        }
+        for (i, statement) in func.body.statements.iter().enumerate() {
+            match statement {
+                // The last expression is what the function returns
+                Statement::ExpressionStatement(es) if i + 1 == func.body.statements.len() && !es.semicolon => {
+                    let found = self.expression(&es.expression);
+                    if let Some(expected) = &returns {
+                        self.expect(expected, &found, es.expression.span());
+                    }
+                },
+                statement => self.statement(statement),
+            }
+        }
+        self.returns.pop();
+        self.scopes.pop();
+
+        Type::Function(Some(signature))
+    }
+
+    // Returns Any for expressions with errors, so one mistake is only
+    // reported once
+    fn expression(&mut self, expression: &Expression) -> Type {
+        match expression {
+            Expression::IntegerLiteral(_) | Expression::BigIntegerLiteral(_) => Type::Int,
+            Expression::FloatLiteral(_) => Type::Float,
+            Expression::StringLiteral(_) => Type::String,
+            // Any value can be shown in a string
+            Expression::InterpolatedString(string) => {
+                for part in &string.parts {
+                    if let StringPart::Expression(expression) = part {
+                        self.expression(expression);
+                    }
+                }
+                Type::String
+            },
+            Expression::CharLiteral(_) => Type::Char,
+            Expression::BooleanLiteral(_) => Type::Bool,
+            Expression::NullLiteral(_) => Type::Null,
+            Expression::Identifier(ident) => self.lookup(ident.value).map_or(Type::Any, |binding| binding.ty.clone()),
+            Expression::Prefix(prefix) => {
+                let right = self.expression(&prefix.right);
+                match (prefix.operator.ttype, right) {
+                    (TokenType::Bang, _) => Type::Bool,
+                    (TokenType::Minus | TokenType::Increment | TokenType::Decrement, Type::Int) => Type::Int,
+                    (TokenType::Minus, Type::Float) => Type::Float,
+                    (_, Type::Any) => Type::Any,
+                    (_, right) => {
+                        self.error(format!("unknown operator: {}{}", prefix.operator.literal, right), prefix.operator.span());
+                        Type::Any
+                    },
+                }
+            },
+            Expression::Infix(infix) => {
+                let left = self.expression(&infix.left);
+                let right = self.expression(&infix.right);
+                self.infix(infix.operator.ttype, &infix.operator.literal, left, right, infix.operator.span())
+            },
+            Expression::Postfix(postfix) => {
+                let left = self.expression(&postfix.left);
+                match left {
+                    Type::Int | Type::Any => left,
+                    left => {
+                        self.error(format!("unknown operator: {}{}", left, postfix.operator.literal), postfix.operator.span());
+                        Type::Any
+                    },
+                }
+            },
+            // Results hold values of any type
+            Expression::Propagate(propagate) => {
+                self.expression(&propagate.value);
+                Type::Any
+            },
+            Expression::Ternary(ternary) => {
+                self.expression(&ternary.condition);
+                let if_true = self.expression(&ternary.if_true);
+                let if_false = self.expression(&ternary.if_false);
+                match if_true == if_false {
+                    true => if_true,
+                    false => Type::Any,
+                }
+            },
+            Expression::Assignment(assignment) => self.assignment(assignment),
+            Expression::ArrayLiteral(array) => {
+                array.elements.iter().for_each(|element| { self.expression(element); });
+                Type::Array
+            },
+            Expression::RangeLiteral(range) => {
+                let start = self.expression(&range.start);
+                let end = self.expression(&range.end);
+                if !Type::Int.accepts(&start) || !Type::Int.accepts(&end) {
+                    self.error(format!("range bounds must be integers, got {}..{}", start, end), range.token.span());
+                }
+                Type::Range
+            },
+            Expression::HashLiteral(hash) => {
+                for (key, value) in &hash.pairs {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                Type::Hash
+            },
+            Expression::FunctionLiteral(func) => self.function(func),
+            Expression::Call(call) => {
+                let callee = self.expression(&call.function);
+                let arguments: Vec<Type> = call.arguments.iter().map(|argument| self.expression(argument)).collect();
+                match callee {
+                    Type::Function(Some(signature)) if arguments.len() != signature.parameters.len() => {
+                        let message = format!("wrong number of arguments. got={}, want={}", arguments.len(), signature.parameters.len());
+                        self.error(message, call.token.span());
+                        Type::Any
+                    },
+                    Type::Function(Some(signature)) => {
+                        for ((argument, found), expected) in call.arguments.iter().zip(&arguments).zip(&signature.parameters) {
+                            self.expect(expected, found, argument.span());
+                        }
+                        signature.returns.clone()
+                    },
+                    Type::Function(None) | Type::Any => Type::Any,
+                    callee => {
+                        self.error(format!("not a function: {}", callee), call.token.span());
+                        Type::Any
+                    },
+                }
+            },
+            Expression::Member(member) => {
+                let object = self.expression(&member.object);
+                self.member(&object, &member.property);
+                Type::Any
+            },
+            Expression::MethodCall(call) => {
+                let object = self.expression(&call.object);
+                self.member(&object, &call.method);
+                call.arguments.iter().for_each(|argument| { self.expression(argument); });
+                Type::Any
+            },
+            Expression::Index(index) => {
+                let left = self.expression(&index.left);
+                let position = self.expression(&index.index);
+                match (&left, &position) {
+                    (Type::Any | Type::Hash, _) | (Type::Array | Type::String, Type::Int | Type::Any) => {},
+                    (Type::Struct(name), _) if self.has_member(*name, "__index") => {},
+                    _ => self.error(format!("index operator not supported: {}[{}]", left, position), index.token.span()),
+                }
+                Type::Any
+            },
+            Expression::Import(_) => Type::Module,
+            Expression::StructLiteral(literal) => {
+                literal.fields.iter().for_each(|(_, value)| { self.expression(value); });
+                match self.structs.contains_key(&literal.name.value) {
+                    true => Type::Struct(literal.name.value),
+                    false => Type::Any,
+                }
+            },
+            // Without a wildcard nothing may match, making the value null
+            Expression::Match(m) => {
+                self.expression(&m.subject);
+                let arms: Vec<Type> = m.arms.iter().map(|arm| self.expression(&arm.body)).collect();
+                let exhaustive = m.arms.iter().any(|arm| matches!(arm.pattern, Pattern::Wildcard(_)));
+                match arms.split_first() {
+                    Some((first, rest)) if exhaustive && rest.iter().all(|ty| ty == first) => first.clone(),
+                    _ => Type::Any,
+                }
+            },
+            // The handler may run instead of any part of the body, so the
+            // value has a type only when both agree
+            Expression::Try(t) => {
+                let body = self.block(&t.body);
+                self.scopes.push(HashMap::new());
+                self.declare(t.parameter.value, Type::Any, false);
+                let handler = self.block(&t.handler);
+                self.scopes.pop();
+                if body == handler { body } else { Type::Any }
+            },
+            Expression::Block(block) => self.block(block),
+        }
+    }
+
+    fn has_member(&self, name: Symbol, member: &str) -> bool {
+        self.structs[&name].contains(&Symbol::intern(member))
+    }
+
+    fn member(&mut self, object: &Type, property: &Identifier) {
+        if let Type::Struct(name) = object {
+            if !self.structs[name].contains(&property.value) {
+                self.error(format!("struct {} has no member {}", name, property.value), property.token.span());
+            }
+        }
+    }
+
+    fn assignment(&mut self, assignment: &ast::Assignment) -> Type {
+        let value = self.expression(&assignment.value);
+        let Expression::Identifier(ident) = &*assignment.target else {
+            self.expression(&assignment.target);
+            return match assignment.token.ttype {
+                TokenType::Assign => value,
+                _ => Type::Any,
+            };
+        };
+
+        let Some(binding) = self.lookup(ident.value).cloned() else {
+            return Type::Any;
+        };
+        let result = match assignment.token.ttype {
+            TokenType::Assign => value,
+            compound => {
+                let operator = match compound {
+                    TokenType::PlusAssign => TokenType::Plus,
+                    TokenType::MinusAssign => TokenType::Minus,
+                    TokenType::AsteriskAssign => TokenType::Asterisk,
+                    TokenType::SlashAssign => TokenType::Slash,
+                    _ => TokenType::Percent,
+                };
+                let literal = assignment.token.literal.trim_end_matches('=');
+                self.infix(operator, literal, binding.ty.clone(), value, assignment.token.span())
+            },
+        };
+        if binding.annotated {
+            self.expect(&binding.ty, &result, assignment.value.span());
+        }
+        result
+    }
+
+    // Mirrors the evaluator's rules for which operands an operator takes
+    fn infix(&mut self, operator: TokenType, literal: &str, left: Type, right: Type, span: Span) -> Type {
+        let comparison = matches!(operator,
+            TokenType::Equal | TokenType::NotEqual
+            | TokenType::LessThan | TokenType::GreaterThan
+            | TokenType::LessThanOrEqual | TokenType::GreaterThanOrEqual);
+        let equality = matches!(operator, TokenType::Equal | TokenType::NotEqual);
+        let arithmetic = matches!(operator,
+            TokenType::Plus | TokenType::Minus | TokenType::Asterisk | TokenType::Slash | TokenType::Percent);
+
+        match (&left, &right) {
+            // The fallback is only used when the left side is null
+            _ if operator == TokenType::NullCoalesce => match left {
+                Type::Null => right,
+                Type::Any => Type::Any,
+                left => left,
+            },
+            // Structs can define their own meaning for an operator
+            (Type::Struct(name), _) if operator.overload().is_some_and(|method| self.has_member(*name, method)) => Type::Any,
+            (Type::Any, _) | (_, Type::Any) if comparison => Type::Bool,
+            (Type::Any, _) | (_, Type::Any) => Type::Any,
+            (Type::Int, Type::Int) if arithmetic => Type::Int,
+            // Mixed with an int, a float makes the result a float
+            (Type::Int | Type::Float, Type::Int | Type::Float) if arithmetic => Type::Float,
+            (Type::Int | Type::Float, Type::Int | Type::Float) | (Type::String, Type::String) | (Type::Char, Type::Char) if comparison => Type::Bool,
+            (Type::String, Type::String) if operator == TokenType::Plus => Type::String,
+            (Type::String, Type::Int) | (Type::Int, Type::String) if operator == TokenType::Asterisk => Type::String,
+            (Type::Bool, Type::Bool) | (Type::Array, Type::Array) | (Type::Hash, Type::Hash) | (Type::Struct(_), Type::Struct(_)) | (Type::Null, _) | (_, Type::Null) if equality => Type::Bool,
+            _ => {
+                let problem = match discriminant(&left) == discriminant(&right) {
+                    true => "unknown operator",
+                    false => "type mismatch",
+                };
+                self.error(format!("{}: {} {} {}", problem, left, literal, right), span);
+                Type::Any
+            },
+        }
+    }
+}
+
+// Names given a new value with a plain `=` anywhere in the program
+#[derive(Default)]
+struct Reassigned(HashSet<Symbol>);
+
+impl Visitor for Reassigned {
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let Expression::Assignment(assignment) = expression {
+            if let (TokenType::Assign, Expression::Identifier(ident)) = (assignment.token.ttype, &*assignment.target) {
+                self.0.insert(ident.value);
+            }
+        }
+        visit::walk_expression(self, expression);
+    }
+}
+
+struct Annotations(bool);
+
+impl Visitor for Annotations {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if let Statement::LetStatement(ls) = statement {
+            self.0 |= ls.annotation.is_some();
+        }
+        visit::walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let Expression::FunctionLiteral(func) = expression {
+            self.0 |= func.return_type.is_some() || func.parameter_types.iter().any(Option::is_some);
+        }
+        visit::walk_expression(self, expression);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lexer::Lexer;
+    use parser::Parser;
+    use super::*;
+
+    fn errors(input: &str) -> Vec<String> {
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        match check(&program) {
+            Ok(()) => vec![],
+            Err(errors) => errors.iter().map(|error| error.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_annotations() {
+        let tests = vec![
+            ("let x: int = 5;", vec![]),
+            ("let x: int = \"five\";", vec!["expected int, found string at line 1, col 14"]),
+            ("let x: any = \"five\";", vec![]),
+            ("let x: decimal = 5;", vec!["unknown type decimal at line 1, col 8"]),
+            ("let x: int = 5; x = \"five\";", vec!["expected int, found string at line 1, col 21"]),
+            ("let x: int = 5; x += \"five\";", vec!["type mismatch: int + string at line 1, col 19"]),
+            ("let x: string = \"a\"; x *= 3;", vec![]),
+            ("let f: fn = fn(x) { x };", vec![]),
+            ("let s: string = len(\"abc\");", vec![]),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(errors(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_functions() {
+        let tests = vec![
+            ("let f = fn(x: int, y: string) -> bool { true }; f(1, \"a\");", vec![]),
+            ("let f = fn(x: int) { x }; f(\"a\");", vec!["expected int, found string at line 1, col 29"]),
+            ("let f = fn(x: int) { x }; f(1, 2);", vec!["wrong number of arguments. got=2, want=1 at line 1, col 28"]),
+            ("let f = fn() -> int { \"a\" };", vec!["expected int, found string at line 1, col 23"]),
+            ("let f = fn() -> int { return true; };", vec!["expected int, found bool at line 1, col 30"]),
+            ("let f = fn(x: int) { x + \"a\" };", vec!["type mismatch: int + string at line 1, col 24"]),
+            ("let f = fn() -> string { 1 }; let n: int = f();", vec![
+                "expected string, found int at line 1, col 26",
+                "expected int, found string at line 1, col 45",
+            ]),
+            // Recursion and calls to functions defined further down
+            ("let f = fn(n: int) -> int { n == 0 ? 0 : f(n - 1) }; f(\"a\");", vec!["expected int, found string at line 1, col 56"]),
+            ("let f = fn() { g(\"a\") }; let g = fn(x: int) { x };", vec!["expected int, found string at line 1, col 18"]),
+            ("let x = 1; x(2);", vec!["not a function: int at line 1, col 13"]),
+            ("let f = fn(x: strng) { x };", vec!["unknown type strng at line 1, col 15"]),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(errors(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_inference() {
+        let tests = vec![
+            ("let x = 1; let y = \"a\"; x + y;", vec!["type mismatch: int + string at line 1, col 27"]),
+            ("let x = 1; let y: string = x;", vec!["expected string, found int at line 1, col 28"]),
+            ("let x = true; -x;", vec!["unknown operator: -bool at line 1, col 15"]),
+            ("let x = \"a\"; x++;", vec!["unknown operator: string++ at line 1, col 15"]),
+            ("let x = \"a\"; ++x;", vec!["unknown operator: ++string at line 1, col 14"]),
+            ("true + false;", vec!["unknown operator: bool + bool at line 1, col 6"]),
+            ("let s: string = \"${1 + true}\";", vec!["type mismatch: int + bool at line 1, col 22"]),
+            ("1 == \"a\";", vec!["type mismatch: int == string at line 1, col 3"]),
+            ("[1] < [2];", vec!["unknown operator: array < array at line 1, col 5"]),
+            ("let x: float = 1 + 0.5; let n: int = x * 2;", vec!["expected int, found float at line 1, col 40"]),
+            ("let x: float = 1;", vec!["expected float, found int at line 1, col 16"]),
+            ("let b: bool = 1 < 1.5; let x = -2.5 % 2; x++;", vec!["unknown operator: float++ at line 1, col 43"]),
+            ("5[0];", vec!["index operator not supported: int[int] at line 1, col 2"]),
+            ("[1][\"a\"];", vec!["index operator not supported: array[string] at line 1, col 4"]),
+            ("let n: int = match 1 { 1 => 2, _ => 3 };", vec![]),
+            ("let n: int = match 1 { 1 => 2, _ => \"many\" };", vec![]),
+            ("let s: string = match 1 { _ => 2 };", vec!["expected string, found int at line 1, col 17"]),
+            ("struct P { x } let p: P = P { x: 1 }; p.x; p == P { x: 2 };", vec![]),
+            ("let f = fn(p: P) { p.x }; struct P { x } f(P { x: 1 });", vec![]),
+            ("struct P { x } struct Q { x } let p: P = Q { x: 1 };", vec!["expected P, found Q at line 1, col 42"]),
+            ("let r: range = 1..=3; r = 0..\"a\";", vec!["range bounds must be integers, got int..string at line 1, col 28"]),
+            ("struct P { x } let p: P = 1;", vec!["expected P, found int at line 1, col 27"]),
+            ("struct P { x } P { x: 1 }.y;", vec!["struct P has no member y at line 1, col 27"]),
+            ("struct P { x } let p = P { x: 1 }; p.y(); \"a\".upper();", vec!["struct P has no member y at line 1, col 38"]),
+            ("struct V { x, __add, __index } let v = V { x: 1, __add: fn(o) { o }, __index: fn(i) { i } }; v + 1; v[\"a\"];", vec![]),
+            ("struct P { x } let p = P { x: 1 }; p + 1; p[0];", vec!["type mismatch: P + int at line 1, col 38", "index operator not supported: P[int] at line 1, col 44"]),
+            ("let n: int = { let s = \"a\"; len(s) };", vec![]),
+            ("let n: int = { let x = 1; \"a\" };", vec!["expected int, found string at line 1, col 14"]),
+            ("let x = 1; { let x = \"a\"; } let y: int = x;", vec![]),
+            ("let n: int = try { 1 } catch (e) { 0 };", vec![]),
+            ("let n: int = try { 1 } catch (e) { e };", vec![]),
+            ("let s: string = try { 1 } catch (e) { 0 };", vec!["expected string, found int at line 1, col 17"]),
+            ("let f = fn(x: int) -> int { x < 0 ? 0 : x }; try { throw 1; } catch (e) { f(\"a\") };", vec!["expected int, found string at line 1, col 77"]),
+            // Mistakes inside a bad expression are only reported once
+            ("(1 + \"a\") + \"b\";", vec!["type mismatch: int + string at line 1, col 4"]),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(errors(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_untyped_programs() {
+        // Anything the evaluator accepts, as far as the checker can tell
+        let tests = vec![
+            "let x = 1; x = \"a\"; x + \"b\";",
+            "let s = \"ab\" * 2; s + \"c\"; 2 * s;",
+            "let x = null; x == 1; 1 != null; x ?? 2;",
+            "let h = {\"a\": 1}; h[\"a\"] + 1; [1, 2][0] * 3; \"ab\"[1] + \"c\";",
+            "let c = 'a'; c < 'b';",
+            "let a: bool = [1] == [1]; let b: bool = {\"a\": 1} != {};",
+            "let f = fn(x) { x + 1 }; f(\"a\");",
+            "let xs = [1, 2]; for (x in xs) { x + \"a\"; }",
+            "let n = 0; for (let i = 0; i < 3; i++) { n += i; }",
+            "let m = import(\"math\"); m.sqrt(4) + 1;",
+            "puts(len(\"abc\") + 1, first([1]));",
+            "!5; true ? 1 : \"a\";",
+        ];
+
+        for input in tests {
+            assert_eq!(errors(input), Vec::<String>::new(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_has_annotations() {
+        let tests = vec![
+            ("let x = fn(y) { y };", false),
+            ("let x: int = 1;", true),
+            ("[fn(y: int) { y }];", true),
+            ("puts(fn() -> int { 1 });", true),
+        ];
+
+        for (input, expected) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            assert_eq!(has_annotations(&program), expected, "{}", input);
+        }
+    }
+}
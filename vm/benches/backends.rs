@@ -0,0 +1,69 @@
+use object::shared::{Rc, RefCell};
+use criterion::{criterion_group, criterion_main, Criterion};
+use compiler::Compiler;
+use lexer::Lexer;
+use object::Environment;
+use parser::Parser;
+use vm::Vm;
+
+// Workloads run on both backends. There are no conditionals or hashes yet,
+// so fib is iterative and the collection workload builds arrays.
+const PROGRAMS: &[(&str, &str)] = &[
+    ("fib", "
+        let fib = fn(n) {
+            let a = 0;
+            let b = 1;
+            for (let i = 0; i < n; i++) {
+                let t = a + b;
+                a = b;
+                b = t;
+            }
+            a;
+        };
+        let total = 0;
+        for (let k = 0; k < 200; k++) {
+            total += fib(50);
+        }
+        total;
+    "),
+    ("strings", r#"
+        let out = "";
+        for (let i = 0; i < 500; i++) {
+            let pad = i % 4;
+            out = out + "ab" + "-" * pad;
+        }
+        len(out);
+    "#),
+    ("arrays", "
+        let xs = [];
+        for (let i = 0; i < 300; i++) {
+            xs = push(xs, i * i);
+        }
+        let sum = 0;
+        for (x in xs) {
+            sum += x;
+        }
+        sum;
+    "),
+];
+
+fn backends(c: &mut Criterion) {
+    for (name, source) in PROGRAMS {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let bytecode = Compiler::new().compile(&program).unwrap();
+
+        let evaluated = eval::eval_program(&program, &Rc::new(RefCell::new(Environment::new()))).unwrap();
+        assert_eq!(Vm::new().run(bytecode.clone()).unwrap(), evaluated, "backends disagree on {}", name);
+
+        let mut group = c.benchmark_group(*name);
+        group.bench_function("eval", |b| b.iter(|| {
+            let env = Rc::new(RefCell::new(Environment::new()));
+            eval::eval_program(&program, &env).unwrap()
+        }));
+        group.bench_function("vm", |b| b.iter(|| Vm::new().run(bytecode.clone()).unwrap()));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, backends);
+criterion_main!(benches);
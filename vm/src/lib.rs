@@ -0,0 +1,818 @@
+use std::collections::BTreeMap;
+use object::shared::{Rc, RefCell};
+use token::TokenType;
+use code::{Op, Capture, Step, Variable};
+use eval::place::{self, get_in, set_in};
+use token::{Span, Symbol};
+use object::{Object, EvalError, RuntimeError, EvalConfig, CompiledFunction, Closure, Io, Runtime, Budget, Trace, TraceEvent, Usage, Random};
+use compiler::Bytecode;
+
+struct Frame {
+    closure: Closure,
+    ip: usize,
+    // Stack height when the frame was entered, restored on return
+    base: usize,
+    // Locals live in cells so closures can capture them by reference
+    locals: Vec<Rc<RefCell<Object>>>,
+}
+
+impl Frame {
+    fn new(closure: Closure, base: usize, arguments: Vec<Object>) -> Frame {
+        let mut locals: Vec<Rc<RefCell<Object>>> = arguments.into_iter().map(|arg| Rc::new(RefCell::new(arg))).collect();
+        while locals.len() < closure.function.num_locals {
+            locals.push(Rc::new(RefCell::new(Object::Null)));
+        }
+        Frame { closure, ip: 0, base, locals }
+    }
+}
+
+// Globals persist between runs so a REPL can feed one program at a time
+// through the same compiler and VM
+pub struct Vm {
+    constants: Vec<Object>,
+    globals: Vec<Object>,
+    stack: Vec<Object>,
+    frames: Vec<Frame>,
+    last_popped: Object,
+    config: EvalConfig,
+    io: Io,
+    trace: Option<Trace>,
+    budget: Budget,
+    random: Random,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        Vm {
+            constants: Vec::new(),
+            globals: Vec::new(),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            last_popped: Object::Null,
+            config: EvalConfig::default(),
+            io: Io::stdio(),
+            trace: None,
+            budget: Budget::default(),
+            random: Random::default(),
+        }
+    }
+
+    pub fn with_config(config: EvalConfig) -> Vm {
+        let random = match config.deterministic {
+            true => Random::new(Random::FIXED_SEED),
+            false => Random::default(),
+        };
+        Vm { config, random, ..Vm::new() }
+    }
+
+    // Where puts and print write, stdout unless set
+    pub fn set_io(&mut self, io: Io) {
+        self.io = io;
+    }
+
+    // Makes the random builtins give the same numbers on every run
+    pub fn seed(&mut self, seed: u64) {
+        self.random.seed(seed);
+    }
+
+    // Has every instruction executed reported, see Tracer
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = Some(trace);
+    }
+
+    // Returns the value of the last expression statement, like eval_program
+    pub fn run(&mut self, bytecode: Bytecode) -> Result<Object, RuntimeError> {
+        self.constants = bytecode.constants;
+        self.stack.clear();
+        self.last_popped = Object::Null;
+
+        let main = CompiledFunction { instructions: bytecode.instructions, spans: bytecode.spans, num_locals: 0, num_parameters: 0 };
+        self.frames = vec![Frame::new(Closure { function: Rc::new(main), free: Vec::new() }, 0, Vec::new())];
+        // A failed run can leave frames counted, so each run starts afresh
+        self.budget = Budget::default();
+        self.budget.enter(&self.config)?;
+
+        let result = self.execute(1).map_err(|err| self.locate(err));
+        self.frames.clear();
+        result
+    }
+
+    // Frames are left in place when an error unwinds, so the innermost one
+    // is still at the instruction that failed
+    fn locate(&self, error: EvalError) -> RuntimeError {
+        let error = RuntimeError::from(error);
+        let frame = self.frames.last().unwrap();
+        match frame.closure.function.spans.span(frame.ip - 1) {
+            Some(span) => error.with_span(span),
+            None => error,
+        }
+    }
+
+    // Runs until the frame at the given depth returns, so a builtin calling
+    // back into a closure gets control back once that closure is done
+    fn execute(&mut self, depth: usize) -> Result<Object, EvalError> {
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let function = Rc::clone(&frame.closure.function);
+            let op = match function.instructions.get(frame.ip) {
+                Some(op) => op,
+                None => return Ok(self.last_popped.clone()),
+            };
+            // What to report once the instruction has run, taken now as it
+            // may leave the frame
+            let traced = self.trace.is_some()
+                .then(|| (format!("{:?}", op), function.spans.span(frame.ip), self.budget.depth() - 1));
+            frame.ip += 1;
+            self.budget.step()?;
+
+            match op {
+                Op::Constant(index) => self.allocate(self.constants[*index].clone())?,
+                Op::Pop => self.last_popped = self.pop(),
+                Op::Dup => {
+                    let top = self.stack.last().unwrap().clone();
+                    self.push(top);
+                },
+                Op::True => self.push(Object::Boolean(true)),
+                Op::False => self.push(Object::Boolean(false)),
+                Op::Null => self.push(Object::Null),
+
+                Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod
+                | Op::Equal | Op::NotEqual
+                | Op::LessThan | Op::GreaterThan | Op::LessThanOrEqual | Op::GreaterThanOrEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let (operator, literal) = infix_operator(op);
+                    self.allocate(eval::apply_infix_operator(&operator, literal, left, right, &self.config)?)?;
+                },
+                Op::Minus => {
+                    let right = self.pop();
+                    self.allocate(eval::apply_prefix_operator(&TokenType::Minus, "-", right, &self.config)?)?;
+                },
+                Op::Bang => {
+                    let right = self.pop();
+                    self.allocate(eval::apply_prefix_operator(&TokenType::Bang, "!", right, &self.config)?)?;
+                },
+                Op::Increment | Op::Decrement => {
+                    let increment = *op == Op::Increment;
+                    let value = self.pop();
+                    match eval::step_integer(&value, increment, &self.config) {
+                        Some(result) => self.push(result?),
+                        None => return Err(EvalError::UnknownPostfixOperator {
+                            left: value.type_name(),
+                            operator: if increment { "++" } else { "--" }.to_string(),
+                        }),
+                    }
+                },
+
+                Op::Jump(target) => self.jump(*target),
+                Op::JumpIfFalse(target) => {
+                    if !self.pop().is_truthy() {
+                        self.jump(*target);
+                    }
+                },
+                Op::JumpIfNotNull(target) => {
+                    if self.stack.last() == Some(&Object::Null) {
+                        self.pop();
+                    } else {
+                        self.jump(*target);
+                    }
+                },
+                // Leaves the items and the next index to visit on the stack
+                Op::IterStart => {
+                    let items = eval::iterate(self.pop())?;
+                    self.allocate(Object::Array(Rc::new(items)))?;
+                    self.push(Object::Integer(0));
+                },
+                Op::IterNext(end) => {
+                    let len = self.stack.len();
+                    let item = match (&self.stack[len - 2], &self.stack[len - 1]) {
+                        (Object::Array(items), Object::Integer(index)) => items.get(*index as usize).cloned(),
+                        _ => unreachable!("IterNext without IterStart"),
+                    };
+                    match item {
+                        Some(item) => {
+                            if let Object::Integer(index) = &mut self.stack[len - 1] {
+                                *index += 1;
+                            }
+                            self.push(item);
+                        },
+                        None => {
+                            self.stack.truncate(len - 2);
+                            self.jump(*end);
+                        },
+                    }
+                },
+
+                Op::GetGlobal(index) => self.push(self.globals.get(*index).cloned().unwrap_or(Object::Null)),
+                Op::SetGlobal(index) => {
+                    let value = self.pop();
+                    if *index >= self.globals.len() {
+                        self.globals.resize(*index + 1, Object::Null);
+                    }
+                    self.globals[*index] = value;
+                },
+                Op::GetLocal(index) => {
+                    let value = self.frames.last().unwrap().locals[*index].borrow().clone();
+                    self.push(value);
+                },
+                Op::SetLocal(index) => {
+                    let value = self.pop();
+                    *self.frames.last().unwrap().locals[*index].borrow_mut() = value;
+                },
+                Op::GetFree(index) => {
+                    let value = self.frames.last().unwrap().closure.free[*index].borrow().clone();
+                    self.push(value);
+                },
+                Op::SetFree(index) => {
+                    let value = self.pop();
+                    *self.frames.last().unwrap().closure.free[*index].borrow_mut() = value;
+                },
+                Op::GetBuiltin(index) => self.push(Object::Builtin(eval::builtins::BUILTINS[*index].1)),
+                Op::GetPath { variable, steps } => {
+                    let count = steps.iter().filter(|step| **step == Step::Index).count();
+                    let indexes = self.stack[self.stack.len() - count..].to_vec();
+                    let path = self.path(steps, indexes);
+                    let current = self.with_variable(*variable, |value| get_in(value, &path))?;
+                    self.push(current);
+                },
+                Op::SetPath { variable, steps } => {
+                    let value = self.pop();
+                    let count = steps.iter().filter(|step| **step == Step::Index).count();
+                    let indexes = self.stack.split_off(self.stack.len() - count);
+                    let path = self.path(steps, indexes);
+                    // Read first so errors match the evaluator's
+                    self.with_variable(*variable, |current| {
+                        get_in(current, &path)?;
+                        set_in(current, &path, value.clone())
+                    })?;
+                    self.push(value);
+                },
+
+                Op::Array(count) => {
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    self.allocate(Object::Array(Rc::new(elements)))?;
+                },
+                Op::Hash(count) => {
+                    let items = self.stack.split_off(self.stack.len() - count * 2);
+                    let mut pairs = BTreeMap::new();
+                    for pair in items.chunks(2) {
+                        pairs.insert(pair[0].hash_key()?, pair[1].clone());
+                    }
+                    self.allocate(Object::Hash(Rc::new(pairs)))?;
+                },
+                Op::Interpolate(count) => {
+                    let parts = self.stack.split_off(self.stack.len() - count);
+                    let value: String = parts.iter().map(Object::inspect).collect();
+                    self.allocate(Object::String(value))?;
+                },
+                Op::Range { inclusive } => {
+                    let end = self.pop();
+                    let start = self.pop();
+                    self.push(eval::make_range(start, end, *inclusive)?);
+                },
+                Op::Index => {
+                    let index = self.pop();
+                    let left = self.pop();
+                    self.allocate(eval::apply_index_operator(left, index)?)?;
+                },
+                Op::Member(constant) => {
+                    let object = self.pop();
+                    let path = [place::Step::Member(self.name(*constant))];
+                    self.push(get_in(&object, &path)?);
+                },
+                Op::Call(count) => self.call(*count)?,
+                Op::ReturnValue | Op::Return => {
+                    let value = if *op == Op::ReturnValue { self.pop() } else { Object::Null };
+                    let frame = self.frames.pop().unwrap();
+                    self.budget.exit();
+                    self.stack.truncate(frame.base);
+                    if self.frames.len() < depth {
+                        self.record(traced, Some(&value));
+                        return Ok(value);
+                    }
+                    self.push(value);
+                },
+                Op::Closure { constant, captures } => {
+                    let function = match &self.constants[*constant] {
+                        Object::CompiledFunction(function) => Rc::clone(function),
+                        other => unreachable!("closure over {:?}", other),
+                    };
+                    let frame = self.frames.last().unwrap();
+                    let free = captures.iter().map(|capture| match capture {
+                        Capture::Local(index) => Rc::clone(&frame.locals[*index]),
+                        Capture::Free(index) => Rc::clone(&frame.closure.free[*index]),
+                    }).collect();
+                    self.allocate(Object::Closure(Closure { function, free }))?;
+                },
+            }
+            self.record(traced, self.stack.last());
+        }
+    }
+
+    fn record(&self, traced: Option<(String, Option<Span>, usize)>, value: Option<&Object>) {
+        if let (Some(trace), Some((node, span, depth))) = (&self.trace, traced) {
+            trace.record(&TraceEvent { node, span, value, depth });
+        }
+    }
+
+    fn call(&mut self, count: usize) -> Result<(), EvalError> {
+        let arguments = self.stack.split_off(self.stack.len() - count);
+        match self.pop() {
+            Object::Closure(closure) => {
+                if count != closure.function.num_parameters {
+                    return Err(EvalError::WrongArgumentCount { got: count, want: closure.function.num_parameters });
+                }
+                self.budget.enter(&self.config)?;
+                let frame = Frame::new(closure, self.stack.len(), arguments);
+                self.frames.push(frame);
+            },
+            Object::Builtin(func) => {
+                let result = func(self, arguments)?;
+                self.allocate(result)?;
+            },
+            Object::Native(func) => {
+                let result = func.call(&arguments)?;
+                self.allocate(result)?;
+            },
+            other => return Err(EvalError::NotCallable(other.type_name())),
+        }
+        Ok(())
+    }
+
+    // Runs f on the variable where it is, so what it changes is not copied
+    fn with_variable<T>(&mut self, variable: Variable, f: impl FnOnce(&mut Object) -> T) -> T {
+        match variable {
+            Variable::Global(index) => {
+                if index >= self.globals.len() {
+                    self.globals.resize(index + 1, Object::Null);
+                }
+                f(&mut self.globals[index])
+            },
+            Variable::Local(index) => f(&mut self.frames.last().unwrap().locals[index].borrow_mut()),
+            Variable::Free(index) => f(&mut self.frames.last().unwrap().closure.free[index].borrow_mut()),
+        }
+    }
+
+    // The steps of an assignment with the indexes they take, in order
+    fn path(&self, steps: &[Step], indexes: Vec<Object>) -> Vec<place::Step> {
+        let mut indexes = indexes.into_iter();
+        steps.iter().map(|step| match step {
+            Step::Index => place::Step::Index(indexes.next().unwrap()),
+            Step::Member(constant) => place::Step::Member(self.name(*constant)),
+        }).collect()
+    }
+
+    fn name(&self, constant: usize) -> Symbol {
+        match &self.constants[constant] {
+            Object::String(name) => Symbol::intern(name),
+            other => unreachable!("member named by {:?}", other),
+        }
+    }
+
+    fn jump(&mut self, target: usize) {
+        self.frames.last_mut().unwrap().ip = target;
+    }
+
+    fn push(&mut self, value: Object) {
+        self.stack.push(value);
+    }
+
+    // Pushes a value the instruction created, rather than one it read
+    fn allocate(&mut self, value: Object) -> Result<(), EvalError> {
+        self.budget.allocate(&value)?;
+        self.push(value);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Object {
+        self.stack.pop().expect("stack underflow")
+    }
+}
+
+impl Runtime for Vm {
+    fn call(&mut self, function: Object, arguments: Vec<Object>) -> Result<Object, EvalError> {
+        match function {
+            Object::Closure(closure) => {
+                if arguments.len() != closure.function.num_parameters {
+                    return Err(EvalError::WrongArgumentCount { got: arguments.len(), want: closure.function.num_parameters });
+                }
+                self.budget.enter(&self.config)?;
+                self.budget.nest()?;
+                self.frames.push(Frame::new(closure, self.stack.len(), arguments));
+                let result = self.execute(self.frames.len());
+                self.budget.unnest();
+                result
+            },
+            Object::Builtin(func) => func(self, arguments),
+            Object::Native(func) => func.call(&arguments),
+            other => Err(EvalError::NotCallable(other.type_name())),
+        }
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), EvalError> {
+        self.io.write(text)
+    }
+
+    fn usage(&mut self) -> Usage {
+        Usage { scopes: self.frames.len(), ..self.budget.usage() }
+    }
+
+    fn random(&mut self) -> u64 {
+        self.random.next_u64()
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.random.seed(seed);
+    }
+}
+
+// Maps an operator instruction back onto the token the evaluator expects
+fn infix_operator(op: &Op) -> (TokenType, &'static str) {
+    match op {
+        Op::Add => (TokenType::Plus, "+"),
+        Op::Sub => (TokenType::Minus, "-"),
+        Op::Mul => (TokenType::Asterisk, "*"),
+        Op::Div => (TokenType::Slash, "/"),
+        Op::Mod => (TokenType::Percent, "%"),
+        Op::Equal => (TokenType::Equal, "=="),
+        Op::NotEqual => (TokenType::NotEqual, "!="),
+        Op::LessThan => (TokenType::LessThan, "<"),
+        Op::GreaterThan => (TokenType::GreaterThan, ">"),
+        Op::LessThanOrEqual => (TokenType::LessThanOrEqual, "<="),
+        Op::GreaterThanOrEqual => (TokenType::GreaterThanOrEqual, ">="),
+        other => unreachable!("{:?} is not an infix operator", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+    use compiler::Compiler;
+    use object::{Environment, Limits, Resource};
+    use std::time::Duration;
+
+    fn parse(input: &str) -> ast::Program {
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        p.parse_program().unwrap()
+    }
+
+    fn run(input: &str) -> Result<Object, EvalError> {
+        let bytecode = Compiler::new().compile(&parse(input)).unwrap();
+        Vm::new().run(bytecode).map_err(|err| err.error)
+    }
+
+    // Both backends must agree on every input
+    fn assert_backends_agree(input: &str, expected: Result<Object, EvalError>) {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        assert_eq!(eval::eval_program(&parse(input), &env).map_err(|err| err.error), expected, "eval: {}", input);
+        assert_eq!(run(input), expected, "vm: {}", input);
+    }
+
+    #[test]
+    fn test_expressions() {
+        let tests = vec![
+            ("5", Object::Integer(5)),
+            ("-10 + 3 * 4 - 6 / 2", Object::Integer(-1)),
+            ("17 % 5", Object::Integer(2)),
+            ("-9223372036854775808", Object::Integer(i64::MIN)),
+            ("-9223372036854775808 + 9223372036854775807", Object::Integer(-1)),
+            ("(5 + 10 * 2 + 15 / 3) * 2 + -10", Object::Integer(50)),
+            ("1 < 2", Object::Boolean(true)),
+            ("1 >= 2", Object::Boolean(false)),
+            ("true == !false", Object::Boolean(true)),
+            ("!5", Object::Boolean(false)),
+            (r#""foo""#, Object::String("foo".to_string())),
+            (r#""foo" + "bar""#, Object::String("foobar".to_string())),
+            (r#""ab" * 2"#, Object::String("abab".to_string())),
+            (r#""a" < "b""#, Object::Boolean(true)),
+            ("null", Object::Null),
+            ("'a' < 'b'", Object::Boolean(true)),
+            ("chr(ord('a') + 2)", Object::Char('c')),
+            ("null == null", Object::Boolean(true)),
+            ("[1, [2, \"a\"]] == [1, [2, \"a\"]]", Object::Boolean(true)),
+            ("[1, 2] != [2, 1]", Object::Boolean(true)),
+            ("{\"a\": [1], 2: null} == {2: null, \"a\": [1]}", Object::Boolean(true)),
+            ("{\"a\": 1} == {\"a\": 2}", Object::Boolean(false)),
+            ("let a = [1]; let b = a; b[0] = 2; a == b", Object::Boolean(false)),
+            ("null ?? 1 + 2", Object::Integer(3)),
+            ("let x = 4; x ?? 5", Object::Integer(4)),
+            ("is_null(first([]))", Object::Boolean(true)),
+            ("1 > 2 ? \"a\" : \"b\"", Object::String("b".to_string())),
+            ("let x = -3; x < 0 ? -x : x", Object::Integer(3)),
+            ("let s = 0; s > 0 ? 1 : s < 0 ? -1 : 0", Object::Integer(0)),
+            // Only the chosen branch runs
+            ("true ? 1 : 1 / 0", Object::Integer(1)),
+            ("[1, 2 + 3]", Object::Array(Rc::new(vec![Object::Integer(1), Object::Integer(5)]))),
+            ("[1, 2, 3][1 + 1]", Object::Integer(3)),
+            ("let s = \"héllo\"; s[1] + s[9 - 5]", Object::from("éo")),
+            ("[\"ab\"][0][5]", Object::Null),
+            ("upper(slice(\"héllo\", 0, 2))", Object::from("HÉ")),
+            (r#"let a = 2; "a=${a}, ${[a, "b"]} ${null}${'c'}""#, Object::from("a=2, [2, b] nullc")),
+            (r#"let n = "x"; "${"<${n}>" * 2}""#, Object::from("<x><x>")),
+            ("r\"${n}\" + `\"\\`", Object::from("${n}\"\\")),
+            ("len(\"a\nb\")", Object::Integer(3)),
+            ("1.5 * 2 + -0.5", Object::Float(2.5)),
+            ("1 == 1.0 ? 7 / 2.0 : 0", Object::Float(3.5)),
+            ("", Object::Null),
+            ("let x = 5;", Object::Null),
+        ];
+
+        for (input, expected) in tests {
+            assert_backends_agree(input, Ok(expected));
+        }
+    }
+
+    #[test]
+    fn test_bindings() {
+        let tests = vec![
+            ("let x = 5; x", 5),
+            ("let x = 5; let y = x * 2; y + x", 15),
+            ("let x = 5; x = 7; x", 7),
+            ("let x = 5; x += 3", 8),
+            ("let x = 5; x++", 5),
+            ("let x = 5; x--; x", 4),
+            ("let x = 5; ++x", 6),
+            ("let x = 5; --x + x", 8),
+            ("let f = fn() { let n = 1; let g = fn() { ++n }; g(); n }; f()", 2),
+            ("let x = 5; let y = x = 9; x + y", 18),
+        ];
+
+        for (input, expected) in tests {
+            assert_backends_agree(input, Ok(Object::Integer(expected)));
+        }
+    }
+
+    #[test]
+    fn test_loops() {
+        let tests = vec![
+            ("let sum = 0; for (let i = 0; i < 5; i++) { sum += i; } sum", 10),
+            ("let sum = 0; for (x in [1, 2, 3]) { sum += x; } sum", 6),
+            ("let n = 0; for (c in \"abc\") { n++; } n", 3),
+            ("let sum = 0; for (i in 1..=4) { sum += i; } sum", 10),
+            ("let n = 3; reduce(map(0..n, fn(x) { x * x }), 0, fn(a, b) { a + b })", 5),
+            ("let f = fn() { for (x in [7, 8]) { return x; } 0 }; f()", 7),
+            ("let f = fn() { let n = 0; for (let i = 0; i < 3; i++) { n += 2; } n }; f()", 6),
+        ];
+
+        for (input, expected) in tests {
+            assert_backends_agree(input, Ok(Object::Integer(expected)));
+        }
+    }
+
+    #[test]
+    fn test_functions() {
+        let tests = vec![
+            ("let add = fn(a, b) { a + b }; add(2, 3)", Object::Integer(5)),
+            ("let f = fn() { return 1; 2 }; f()", Object::Integer(1)),
+            ("let f = fn() { let x = 1; }; f()", Object::Null),
+            ("let f = fn() { }; f()", Object::Null),
+            ("let f = fn(x) { x * 2; }; f(3)", Object::Null),
+            ("return 3; 4", Object::Integer(3)),
+            ("let fact = fn(n) { let r = 1; for (let i = 2; i <= n; i++) { r *= i; } r }; fact(5)", Object::Integer(120)),
+            ("let apply = fn(f, x) { f(x) }; apply(fn(x) { x * 2 }, 4)", Object::Integer(8)),
+            ("len([1, 2, 3]) + len(\"ab\")", Object::Integer(5)),
+            ("push([1], 2)", Object::Array(Rc::new(vec![Object::Integer(1), Object::Integer(2)]))),
+            ("let xs = [3, 1, 2]; join(concat(sort(xs), xs), \"\")", Object::from("123312")),
+            ("map(filter([1, 2, 3, 4], fn(x) { x > 2 }), fn(x) { x * 10 })", Object::Array(Rc::new(vec![Object::Integer(30), Object::Integer(40)]))),
+            ("let total = 0; each([1, 2], fn(x) { total += x; }); total", Object::Integer(3)),
+            ("let add = fn(a, b) { return a + b; }; reduce([1, 2, 3], 0, add) + 1", Object::Integer(7)),
+            ("find(map([[1], [2, 3]], len), fn(n) { n > 1 })", Object::Integer(2)),
+            ("let h = {\"a\": 1, \"b\": 1 + 1}; h[\"b\"] + len(keys(h))", Object::Integer(4)),
+            ("let n = 0; for (k in {3: 0, 4: 0}) { n += k; } n", Object::Integer(7)),
+            ("[3, 1, 2] |> sort |> push(4) |> reduce(0, fn(a, b) { a * 10 + b })", Object::Integer(1234)),
+        ];
+
+        for (input, expected) in tests {
+            assert_backends_agree(input, Ok(expected));
+        }
+    }
+
+    #[test]
+    fn test_closures() {
+        let tests = vec![
+            ("let adder = fn(x) { fn(y) { x + y } }; let add_two = adder(2); add_two(3)", 5),
+            ("let f = fn(a) { fn(b) { fn(c) { a + b + c } } }; f(1)(2)(3)", 6),
+            ("let counter = fn() { let n = 0; fn() { n++; n } }; let c = counter(); c(); c(); c()", 3),
+            ("let make = fn() { let n = 0; let inc = fn() { n += 1 }; inc(); inc(); n }; make()", 2),
+            ("let x = 1; let f = fn() { x = 10 }; f(); x", 10),
+            ("let outer = fn() { let count = fn(n) { for (x in [n]) { return n; } }; count(4) }; outer()", 4),
+        ];
+
+        for (input, expected) in tests {
+            assert_backends_agree(input, Ok(Object::Integer(expected)));
+        }
+    }
+
+    #[test]
+    fn test_recursion() {
+        let input = "
+            let sum_to = fn(n) {
+                for (x in [n]) {
+                    for (let done = n == 0; done; done = false) { return 0; }
+                }
+                n + sum_to(n - 1)
+            };
+            sum_to(10)";
+        assert_backends_agree(input, Ok(Object::Integer(55)));
+    }
+
+    #[test]
+    fn test_index_assignment() {
+        let tests = vec![
+            ("let a = [1, 2, 3]; a[0] = 9; a", "[9, 2, 3]"),
+            ("let a = [1, 2, 3]; a[0] = 9", "9"),
+            ("let h = {\"k\": 1}; h[\"k\"] = 2; h[\"new\"] = 3; h", "{k: 2, new: 3}"),
+            ("let a = [1, 2]; a[0] += 5; a[1] *= 10; a", "[6, 20]"),
+            ("let m = {\"xs\": [[1], [2]]}; m[\"xs\"][1][0] = 7; m", "{xs: [[1], [7]]}"),
+            ("let i = 0; let a = [0, 0]; a[i++] = 5; [a, i]", "[[5, 0], 1]"),
+            ("let a = [1, 2]; let b = a; a[0] = 9; [a, b]", "[[9, 2], [1, 2]]"),
+            ("let f = fn() { let a = [1]; let g = fn() { a[0] = 2 }; g(); a }; f()", "[2]"),
+            ("let f = fn(a) { a[0] = 2; a }; let a = [1]; [f(a), a]", "[[2], [1]]"),
+        ];
+
+        for (input, expected) in tests {
+            let env = Rc::new(RefCell::new(Environment::new()));
+            assert_eq!(eval::eval_program(&parse(input), &env).map(|value| value.inspect()), Ok(expected.to_string()), "eval: {}", input);
+            assert_eq!(run(input).map(|value| value.inspect()), Ok(expected.to_string()), "vm: {}", input);
+        }
+
+        let tests = vec![
+            ("let a = [1]; a[1] = 2", EvalError::IndexOutOfRange { index: 1, len: 1 }),
+            ("let a = [1]; a[\"k\"] = 2", EvalError::IndexNotSupported { left: "ARRAY", index: "STRING" }),
+            ("let s = \"ab\"; s[0] = \"c\"", EvalError::IndexNotSupported { left: "STRING", index: "INTEGER" }),
+            ("let h = {}; h[[1]] = 2", EvalError::UnhashableKey("ARRAY")),
+            ("let h = {}; h[\"a\"][\"b\"] = 1", EvalError::IndexNotSupported { left: "NULL", index: "STRING" }),
+            ("let a = [\"x\"]; a[0] += 1", EvalError::TypeMismatch { left: "STRING", operator: "+".to_string(), right: "INTEGER" }),
+            ("let a = [1]; a.x = 2", EvalError::NoSuchMember { object: "ARRAY".to_string(), member: "x".to_string() }),
+        ];
+
+        for (input, expected) in tests {
+            assert_backends_agree(input, Err(expected));
+        }
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let tests = vec![
+            ("5 + true", EvalError::TypeMismatch { left: "INTEGER", operator: "+".to_string(), right: "BOOLEAN" }),
+            ("-true", EvalError::UnknownPrefixOperator { operator: "-".to_string(), right: "BOOLEAN" }),
+            ("1 / 0", EvalError::DivisionByZero),
+            ("\"ab\" * -2", EvalError::NegativeRepeatCount(-2)),
+            ("\"abc\" * 9223372036854775807", EvalError::IntegerOverflow("3 * 9223372036854775807".to_string())),
+            ("let s = \"a\"; s++", EvalError::UnknownPostfixOperator { left: "STRING", operator: "++".to_string() }),
+            ("for (x in 5) { }", EvalError::NotIterable("INTEGER")),
+            ("5()", EvalError::NotCallable("INTEGER")),
+            ("let f = fn(a) { a }; f()", EvalError::WrongArgumentCount { got: 0, want: 1 }),
+            ("len(1)", EvalError::UnsupportedArgument { function: "len", got: "INTEGER" }),
+            ("[1][\"0\"]", EvalError::IndexNotSupported { left: "ARRAY", index: "STRING" }),
+            ("map([1, 0], fn(x) { 1 / x })", EvalError::DivisionByZero),
+            ("{[]: 1}", EvalError::UnhashableKey("ARRAY")),
+            ("\"${1 / 0}\"", EvalError::DivisionByZero),
+        ];
+
+        for (input, expected) in tests {
+            assert_backends_agree(input, Err(expected));
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        #[derive(Default)]
+        struct Collect(Vec<(String, Option<Span>, Option<Object>, usize)>);
+        impl object::Tracer for Collect {
+            fn trace(&mut self, event: &TraceEvent) {
+                self.0.push((event.node.clone(), event.span, event.value.cloned(), event.depth));
+            }
+        }
+
+        let tracer = Rc::new(RefCell::new(Collect::default()));
+        let mut vm = Vm::new();
+        vm.set_trace(Trace::new(tracer.clone()));
+        vm.run(Compiler::new().compile(&parse("let f = fn(x) { x };\nf(2)")).unwrap()).unwrap();
+
+        let span = |line, column| Some(Span { line, column });
+        let int = |i| Some(Object::Integer(i));
+        // Closures are left out, as they only compare equal to themselves
+        let tracer = tracer.borrow();
+        let events: Vec<_> = tracer.0.iter()
+            .map(|(node, span, value, depth)| (node.as_str(), *span, value.clone().filter(|value| !matches!(value, Object::Closure(_))), *depth))
+            .collect();
+        assert_eq!(events, [
+            ("Closure { constant: 0, captures: [] }", span(1, 9), None, 0),
+            ("SetGlobal(0)", span(1, 1), None, 0),
+            ("GetGlobal(0)", span(2, 1), None, 0),
+            ("Constant(1)", span(2, 3), int(2), 0),
+            ("Call(1)", span(2, 2), None, 0),
+            ("GetLocal(0)", span(1, 17), int(2), 1),
+            ("ReturnValue", span(1, 17), int(2), 1),
+            ("Pop", span(2, 1), None, 0),
+        ]);
+    }
+
+    #[test]
+    fn test_globals_persist_between_runs() {
+        let mut compiler = Compiler::new();
+        let mut vm = Vm::new();
+        vm.run(compiler.compile(&parse("let x = 40;")).unwrap()).unwrap();
+        let result = vm.run(compiler.compile(&parse("x + 2")).unwrap());
+        assert_eq!(result, Ok(Object::Integer(42)));
+    }
+
+    #[test]
+    fn test_error_spans() {
+        // Reported where the evaluator reports them
+        let tests = vec![
+            "1 + true",
+            "let x = 1;\nlet y = [x, -true];",
+            "let f = fn(a) {\n    a / 0\n};\nf(1)",
+            "let f = fn(a) { a };\nf()",
+            "map([1, 0], fn(x) {\n    1 / x\n})",
+            "\"${1}${null + 1}\"",
+        ];
+
+        for input in tests {
+            let env = Rc::new(RefCell::new(Environment::new()));
+            let expected = eval::eval_program(&parse(input), &env).unwrap_err().span;
+            let bytecode = Compiler::new().compile(&parse(input)).unwrap();
+            assert_eq!(Vm::new().run(bytecode).unwrap_err().span, expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let limits = |limits: Limits| EvalConfig { limits, ..EvalConfig::default() };
+        let steps = limits(Limits { max_steps: Some(1000), ..Limits::default() });
+        let objects = limits(Limits { max_objects: Some(50), ..Limits::default() });
+        let depth = limits(Limits { max_depth: Some(10), ..Limits::default() });
+        let time = limits(Limits { timeout: Some(Duration::from_millis(10)), ..Limits::default() });
+        let tests = vec![
+            ("for (let i = 0; true; i++) {}", steps, Err(Resource::Steps(1000))),
+            ("let total = 0; for (let i = 0; i < 10; i++) { total += i; } total", steps, Ok(Object::Integer(45))),
+            ("let a = []; for (let i = 0; i < 100; i++) { a = push(a, \"x\"); }", objects, Err(Resource::Objects(50))),
+            ("let f = fn(n) { n == 0 ? 0 : f(n - 1) }; f(20)", depth, Err(Resource::Depth(10))),
+            ("let f = fn(n) { n == 0 ? 0 : f(n - 1) }; f(5)", depth, Ok(Object::Integer(0))),
+            // Calls made back from builtins count too
+            ("let f = fn(n) { map([n], fn(x) { f(x + 1) }) }; f(0)", depth, Err(Resource::Depth(10))),
+            ("for (let i = 0; true; i++) {}", time, Err(Resource::Time(Duration::from_millis(10)))),
+            ("let f = fn() { f() }; f()", EvalConfig::default(), Err(Resource::Depth(object::DEFAULT_MAX_DEPTH))),
+            ("let f = fn(n) { n == 0 ? 0 : 1 + f(n - 1) }; f(1000)", EvalConfig::default(), Ok(Object::Integer(1000))),
+        ];
+
+        for (input, config, expected) in tests {
+            let mut compiler = Compiler::new();
+            let mut vm = Vm::with_config(config);
+            let result = vm.run(compiler.compile(&parse(input)).unwrap()).map_err(|err| err.error);
+            assert_eq!(result, expected.map_err(EvalError::ResourceExceeded), "{} with {:?}", input, config.limits);
+            // Each run starts with a fresh budget
+            let result = vm.run(compiler.compile(&parse("let g = fn(n) { n }; g(1)")).unwrap());
+            assert_eq!(result, Ok(Object::Integer(1)), "after {}", input);
+        }
+    }
+
+    #[test]
+    fn test_default_nesting_limit() {
+        // Callbacks from builtins recurse on the host stack, and stop before
+        // they overflow one the size of the main thread's
+        let result = std::thread::Builder::new().stack_size(8 << 20).spawn(|| {
+            let bytecode = Compiler::new().compile(&parse("let f = fn() { map([1], fn(x) { f() }) }; f()")).unwrap();
+            Vm::new().run(bytecode).map(|value| value.inspect()).map_err(|err| err.error.to_string())
+        }).unwrap().join().unwrap();
+        assert_eq!(result, Err(EvalError::ResourceExceeded(Resource::Nesting(object::DEFAULT_MAX_NESTING)).to_string()));
+    }
+
+    #[test]
+    fn test_deterministic() {
+        // Both backends draw the same numbers from the fixed seed
+        let input = "[rand_int(0, 1000), shuffle([1, 2, 3, 4, 5]), rand()]";
+        let config = EvalConfig { deterministic: true, ..EvalConfig::default() };
+        let env = Rc::new(RefCell::new(Environment::new()));
+        env.borrow_mut().set_config(config);
+        let expected = eval::eval_program(&parse(input), &env).unwrap();
+        let bytecode = Compiler::new().compile(&parse(input)).unwrap();
+        assert_eq!(Vm::with_config(config).run(bytecode), Ok(expected));
+    }
+
+    #[test]
+    fn test_overflow_config() {
+        let run_with = |input: &str, overflow| {
+            let bytecode = Compiler::new().compile(&parse(input)).unwrap();
+            Vm::with_config(EvalConfig { overflow, ..EvalConfig::default() }).run(bytecode).map_err(|err| err.error)
+        };
+
+        let result = run_with("let x = 9223372036854775807; x++; [x, x * 2]", eval::Overflow::Saturate);
+        assert_eq!(result.unwrap().inspect(), format!("[{}, {}]", i64::MAX, i64::MAX));
+        let result = run_with("9223372036854775807 + 1", eval::Overflow::Error);
+        assert_eq!(result, Err(EvalError::IntegerOverflow("9223372036854775807 + 1".to_string())));
+        let result = run_with("let x = -9223372036854775807; x--; x--", eval::Overflow::Error);
+        assert_eq!(result, Err(EvalError::IntegerOverflow(format!("{} - 1", i64::MIN))));
+    }
+}
@@ -0,0 +1,78 @@
+use object::shared::{Rc, RefCell};
+use js_sys::{Array, Object as JsObject, Reflect};
+use wasm_bindgen::prelude::*;
+use lexer::Lexer;
+use object::{Environment, EvalConfig, Io};
+
+// Runs a whole program and returns what a terminal session would show:
+// the lines printed with puts, then the result or the rendered errors
+#[wasm_bindgen]
+pub fn run(source: &str) -> String {
+    // There is no stdout in the browser, so printed lines are collected here
+    let buffer = Rc::new(RefCell::new(Vec::new()));
+    let env = Rc::new(RefCell::new(Environment::new()));
+    env.borrow_mut().set_io(Io { input: Rc::new(RefCell::new(std::io::empty())), output: buffer.clone() });
+    // Nor a file system or sockets
+    env.borrow_mut().set_config(EvalConfig { filesystem: false, network: false, ..EvalConfig::default() });
+
+    let result = repl::eval_line(source, &env);
+    let mut output = String::from_utf8_lossy(&buffer.take()).into_owned();
+    output.push_str(&result);
+    output
+}
+
+// An array of { type, literal, line, column } objects, one per token
+#[wasm_bindgen]
+pub fn tokenize(source: &str) -> JsValue {
+    let tokens = Array::new();
+    for (ttype, literal, line, column) in token_list(source) {
+        let token = JsObject::new();
+        let fields = [
+            ("type", JsValue::from_str(&ttype)),
+            ("literal", JsValue::from_str(&literal)),
+            ("line", JsValue::from(line as u32)),
+            ("column", JsValue::from(column as u32)),
+        ];
+        for (name, value) in fields {
+            // Setting a property on a fresh object cannot fail
+            Reflect::set(&token, &JsValue::from_str(name), &value).unwrap();
+        }
+        tokens.push(&token);
+    }
+    tokens.into()
+}
+
+fn token_list(source: &str) -> Vec<(String, String, usize, usize)> {
+    Lexer::new(source)
+        .map(|token| (format!("{:?}", token.ttype), token.literal.to_string(), token.line, token.column))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let tests = vec![
+            ("1 + 2", "3"),
+            ("puts(\"hi\", 2); len(\"abc\")", "hi\n2\n3"),
+            ("print(\"a\"); println(\"{}\", 1); 0", "a1\n0"),
+            ("import \"std/file\"", "error: file system access is disabled for this interpreter\n --> line 1, col 8\n  |\n1 | import \"std/file\"\n  |        ^"),
+            ("let x = 1 +;", "error: no prefix parse function for Semicolon\n --> line 1, col 12\n  |\n1 | let x = 1 +;\n  |            ^"),
+        ];
+
+        for (source, expected) in tests {
+            assert_eq!(run(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_token_list() {
+        assert_eq!(token_list("let x"), vec![
+            ("Let".to_string(), "let".to_string(), 1, 1),
+            ("Identifier".to_string(), "x".to_string(), 1, 5),
+            ("Eof".to_string(), "\0".to_string(), 1, 6),
+        ]);
+    }
+}